@@ -1,4 +1,12 @@
 import App from './App.svelte'
+import { reportError } from './telemetry.js'
+
+window.addEventListener("error", (e) => {
+  reportError(e.message, { context: `${e.filename}:${e.lineno}` })
+})
+window.addEventListener("unhandledrejection", (e) => {
+  reportError(String(e.reason), { context: "unhandled rejection" })
+})
 
 const app = new App({
   target: document.getElementById('app')
@@ -0,0 +1,15 @@
+// the server puts question lists in order, but our own votes (see adjustQuestions in List.svelte)
+// only show up in it a little later. re-sorting by votes makes them count right away, but that's
+// only right for guests in an experiment (who are listed by votes), and only if the host hasn't
+// locked the list in an order of their own.
+export function resort(running, listed) {
+	return running && listed.order === "top" && !listed.locked;
+}
+
+// how a question list response says it's arranged
+export function arrangement(headers) {
+	return {
+		"order": headers.get("x-order"),
+		"locked": headers.get("x-locked") === "true",
+	};
+}
@@ -0,0 +1,24 @@
+import { test } from 'node:test';
+import assert from 'node:assert';
+import { resort, arrangement } from './order.js';
+
+const listed = (order, locked) => arrangement(new Headers(
+	locked ? { "x-order": order, "x-locked": "true" } : { "x-order": order }
+));
+
+test("keeps the order the server listed in", () => {
+	// nobody is in an experiment, so the host's pick (or top) stands
+	assert.equal(resort(false, listed("top", false)), false);
+	assert.equal(resort(false, listed("fair", false)), false);
+	// guests in an experiment only re-sort lists that are by votes anyway
+	assert.equal(resort(true, listed("trending", false)), false);
+	assert.equal(resort(true, listed("new", false)), false);
+	// and never ones the host locked
+	assert.equal(resort(true, listed("top", true)), false);
+	// or from servers that don't say
+	assert.equal(resort(true, arrangement(new Headers())), false);
+});
+
+test("re-sorts by votes during an experiment", () => {
+	assert.equal(resort(true, listed("top", false)), true);
+});
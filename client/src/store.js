@@ -1,5 +1,14 @@
 import { writable } from "svelte/store";
 
+// identifies this browser (but not who's using it) in pings and votes
+export const token = localStorage.getItem("token") || crypto.randomUUID();
+localStorage.setItem("token", token);
+
+// identifies this browser as the asker of its questions, kept apart from the token above so that
+// what someone asked can't be tied to what they voted for
+export const author = localStorage.getItem("author") || crypto.randomUUID();
+localStorage.setItem("author", author);
+
 const storedVotedFor = JSON.parse(localStorage.getItem("votedFor"));
 export const votedFor = writable(!storedVotedFor ? {} : storedVotedFor);
 votedFor.subscribe(value => {
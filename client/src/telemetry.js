@@ -0,0 +1,34 @@
+import { token } from './store.js';
+
+// the server only takes a few reports a minute from each browser anyway
+let budget = 10;
+
+// let the operators know something went wrong here (see server/src/telemetry.rs)
+export function reportError(message, { context, eid, response } = {}) {
+	if (budget <= 0) {
+		return;
+	}
+	budget -= 1;
+	let report = { "token": token, "message": String(message).slice(0, 500) };
+	if (context) {
+		report.context = String(context).slice(-100);
+	}
+	if (eid) {
+		report.eid = eid;
+	}
+	if (response) {
+		report.status = response.status;
+		let id = response.headers.get("x-request-id");
+		if (id) {
+			report.request_ids = [id];
+		}
+	}
+	// failing to report shouldn't lead to more reports
+	fetch("/api/telemetry/client-error", {
+		"method": "POST",
+		"headers": {
+			'Content-Type': 'application/json',
+		},
+		"body": JSON.stringify(report),
+	}).catch(() => {});
+}
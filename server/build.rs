@@ -0,0 +1,30 @@
+//! Find the client to build into the binary with the `embed` feature.
+//!
+//! That's `client/dist`, which only exists once the client has been built. If it hasn't been, an
+//! empty directory is built in instead (with a warning), so that the server still compiles and
+//! only the client is missing.
+
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if std::env::var_os("CARGO_FEATURE_EMBED").is_none() {
+        return;
+    }
+
+    let dist =
+        PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("../client/dist");
+    println!("cargo:rerun-if-changed={}", dist.display());
+    let dist = if dist.is_dir() {
+        dist
+    } else {
+        println!(
+            "cargo:warning=client/dist doesn't exist, so no client is built into the server; \
+             run `npm ci && npm run build` in client/ first"
+        );
+        let empty = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("no-client");
+        std::fs::create_dir_all(&empty).expect("OUT_DIR is writable");
+        empty
+    };
+    println!("cargo:rustc-env=CLIENT_DIST={}", dist.display());
+}
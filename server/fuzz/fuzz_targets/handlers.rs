@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wewerewondering_api::fuzz::Op;
+
+fuzz_target!(|ops: Vec<Op>| {
+    wewerewondering_api::fuzz::handlers(ops);
+});
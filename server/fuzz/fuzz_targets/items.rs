@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wewerewondering_api::fuzz::{Attr, Field};
+
+fuzz_target!(|attrs: Vec<(Field, Attr)>| {
+    wewerewondering_api::fuzz::items(attrs);
+});
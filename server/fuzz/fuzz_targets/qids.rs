@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|qids: &str| {
+    wewerewondering_api::fuzz::qids(qids);
+});
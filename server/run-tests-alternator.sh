@@ -0,0 +1,27 @@
+#!/usr/bin/env bash
+# Runs the DynamoDB tests against ScyllaDB's DynamoDB-compatible API (Alternator) in a container.
+set -euo pipefail
+port=8000
+docker run --rm -d --name wewerewondering-scylla -p "$port:8000" scylladb/scylla \
+	--smp 1 --memory 750M --overprovisioned 1 \
+	--alternator-port 8000 --alternator-write-isolation always >/dev/null
+trap 'docker stop wewerewondering-scylla >/dev/null' EXIT
+
+export DYNAMODB_ENDPOINT="http://localhost:$port"
+export ALTERNATOR=1
+# alternator doesn't check credentials, but the SDK insists on having some
+export AWS_REGION=us-east-1
+export AWS_ACCESS_KEY_ID=alternator
+export AWS_SECRET_ACCESS_KEY=alternator
+
+until aws dynamodb list-tables --endpoint-url "$DYNAMODB_ENDPOINT" >/dev/null 2>&1; do
+	sleep 1
+done
+# the same tables we deploy to AWS, minus TTL which alternator only has experimental support for
+cargo run -q -- print-infra |
+	jq -c '.Resources[] | select(.Type == "AWS::DynamoDB::Table") | .Properties | del(.TimeToLiveSpecification)' |
+	while read -r table; do
+		aws dynamodb create-table --endpoint-url "$DYNAMODB_ENDPOINT" --cli-input-json "$table" >/dev/null
+	done
+
+cargo t -- --ignored
@@ -0,0 +1,193 @@
+//! Doing several things to a question at once, for hosts moderating with keyboard shortcuts.
+//!
+//! `POST /api/event/:eid/questions/:secret/:qid/actions` takes a list of actions, like
+//! `["answer", "unhide"]`, and does what they add up to, so that a host's single keystroke
+//! doesn't take a round trip per step. The actions are `answer`, `unanswer`, `hide`, and
+//! `unhide`, which is all there is to do to a question. Questions don't know who asked them, and
+//! there's no spotlight to move on, so there's no banning askers or moving to the next question
+//! here.
+//!
+//! The whole list is checked before anything is done, so a list with anything unknown in it does
+//! nothing at all. After that, each property is only set once, to where the list leaves it (so
+//! later actions win). The backends can't set both in one write, so if setting one fails the
+//! other may already be set, but every action just sets a property rather than flipping it, so
+//! sending the same list again is always safe.
+
+use super::{toggle::Property, Backend};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most actions a single request can do.
+const MAX_ACTIONS: usize = 16;
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Action {
+    Answer,
+    Unanswer,
+    Hide,
+    Unhide,
+}
+
+impl Action {
+    fn sets(self) -> (Property, bool) {
+        match self {
+            Action::Answer => (Property::Answered, true),
+            Action::Unanswer => (Property::Answered, false),
+            Action::Hide => (Property::Hidden, true),
+            Action::Unhide => (Property::Hidden, false),
+        }
+    }
+}
+
+/// Where `actions` leave the question's answered and hidden properties, if they touch them.
+fn outcome(actions: &[Action]) -> (Option<bool>, Option<bool>) {
+    let (mut answered, mut hidden) = (None, None);
+    for action in actions {
+        match action.sets() {
+            (Property::Answered, set) => answered = Some(set),
+            (Property::Hidden, set) => hidden = Some(set),
+        }
+    }
+    (answered, hidden)
+}
+
+pub(super) async fn actions(
+    Path((eid, secret, qid)): Path<(Uuid, String, Uuid)>,
+    State(dynamo): State<Backend>,
+    Json(actions): Json<Vec<Action>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if actions.is_empty() || actions.len() > MAX_ACTIONS {
+        warn!(%eid, %qid, n = actions.len(), "bad number of question actions");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    super::check_secret(&dynamo, &eid, &secret).await?;
+
+    let (answered, hidden) = outcome(&actions);
+    let mut done = serde_json::Map::new();
+    for (property, set, name) in [
+        (Property::Answered, answered, "answered"),
+        (Property::Hidden, hidden, "hidden"),
+    ] {
+        if let Some(set) = set {
+            super::toggle::apply(&dynamo, &eid, qid, property, set, None).await?;
+            done.insert(name.into(), set.into());
+        }
+    }
+    debug!(%eid, %qid, ?actions, "did question actions");
+    Ok(Json(done.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use http::HeaderMap;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "hello world".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let act = |actions: Vec<Action>| {
+            super::actions(
+                Path((eid, secret.to_string(), qid)),
+                State(backend.clone()),
+                Json(actions),
+            )
+        };
+        let state = || async {
+            let Json(qs) = crate::list::list_all(
+                Path((eid, secret.to_string())),
+                State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap();
+            let q = &qs[0];
+            (q["answered"].clone(), q["hidden"].clone())
+        };
+
+        let Json(done) = act(vec![Action::Hide, Action::Answer]).await.unwrap();
+        assert_eq!(
+            done,
+            serde_json::json!({ "answered": true, "hidden": true })
+        );
+        assert_eq!(state().await, (true.into(), true.into()));
+
+        // later actions win, and only what's touched changes
+        let Json(done) = act(vec![Action::Hide, Action::Unhide]).await.unwrap();
+        assert_eq!(done, serde_json::json!({ "hidden": false }));
+        assert_eq!(state().await, (true.into(), false.into()));
+
+        assert_eq!(act(vec![]).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        let wrong = super::actions(
+            Path((eid, String::from("wrong"), qid)),
+            State(backend.clone()),
+            Json(vec![Action::Unanswer]),
+        )
+        .await;
+        assert_eq!(wrong.unwrap_err(), StatusCode::UNAUTHORIZED);
+        assert_eq!(state().await, (true.into(), false.into()));
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[test]
+    fn parses() {
+        let actions: Vec<Action> = serde_json::from_str(r#"["answer", "unhide"]"#).unwrap();
+        assert_eq!(outcome(&actions), (Some(true), Some(false)));
+        // nothing that isn't there is done
+        assert!(serde_json::from_str::<Vec<Action>>(r#"["answer", "ban_author"]"#).is_err());
+    }
+}
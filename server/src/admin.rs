@@ -0,0 +1,42 @@
+use axum::{async_trait, extract::FromRequestParts};
+use http::{header, request::Parts, StatusCode};
+use subtle::ConstantTimeEq;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Proof that the request came from an operator.
+///
+/// Extracting this checks the `Authorization: Bearer` header against `ADMIN_TOKEN`. If no admin
+/// token is configured, admin routes pretend not to exist.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Admin;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let Some(ref token) = super::config::config().admin_token else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+
+        let given = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match given {
+            // in constant time, so that how long it takes doesn't tell how much of it was right
+            Some(given) if bool::from(given.as_bytes().ct_eq(token.as_bytes())) => Ok(Admin),
+            Some(_) => {
+                warn!(path = %parts.uri.path(), "attempted admin access with incorrect token");
+                Err(StatusCode::UNAUTHORIZED)
+            }
+            None => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Putting away questions that have waited too long, for events that never end.
+//!
+//! Some events are left open for good, like a team's standing "office hours", and questions nobody
+//! got to months ago just push the live ones down the list. So the host can say that unanswered
+//! questions go away once they're old enough, with `POST /api/event/:eid/aging/:secret` and
+//! `{"after_days": 14}` (anywhere from a day to a year), or `{"after_days": null}` to keep them.
+//! Questions that have aged out are hidden with the reason `expired` (see [`super::mine`]), so
+//! the host still has them, and can unhide any that turn out to matter after all. Answered
+//! questions are left alone however old they are.
+//!
+//! Nothing runs in the background (the API runs as a Lambda), so questions are put away by the
+//! `aging` command, which is meant to run on a schedule (daily, say). Like [digest](super::digest)
+//! subscriptions, what each event's host asked for is kept in the [blob store](super::blobs),
+//! which therefore has to be one the command can see too. Settings of events that are gone are
+//! removed, and frozen events are left alone until they're unfrozen.
+
+use super::{blobs::BlobStore, mine::Reason, toggle::Property, Backend};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use bytes::Bytes;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Questions can't be put away sooner than this many days after they were asked.
+const MIN_DAYS: u64 = 1;
+/// Nor later than this.
+const MAX_DAYS: u64 = 365;
+
+/// How old an event's unanswered questions can get.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Setting {
+    after_days: u64,
+}
+
+fn key(eid: &Uuid) -> String {
+    format!("aging/{eid}.json")
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+async fn setting(store: &BlobStore, eid: &Uuid) -> Option<Setting> {
+    match store.get(&key(eid)).await {
+        Ok(blob) => blob.and_then(|blob| {
+            serde_json::from_slice(&blob.data)
+                .map_err(|e| error!(%eid, error = %e, "found malformed aging setting"))
+                .ok()
+        }),
+        Err(e) => {
+            error!(%eid, error = %e, "failed to read aging setting");
+            None
+        }
+    }
+}
+
+/// How old the host lets unanswered questions get, if they don't want to keep them all.
+#[derive(Debug, Deserialize)]
+pub(super) struct Age {
+    after_days: Option<u64>,
+}
+
+/// Set how old the event's unanswered questions can get, or keep them all.
+pub(super) async fn aging(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Age>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    set(super::blobs::store().await, &dynamo, &eid, &secret, req).await
+}
+
+async fn set(
+    store: &BlobStore,
+    dynamo: &Backend,
+    eid: &Uuid,
+    secret: &str,
+    req: Age,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(dynamo, eid, secret).await?;
+    let Some(after_days) = req.after_days else {
+        if let Err(e) = store.delete(&key(eid)).await {
+            error!(%eid, error = %e, "failed to remove aging setting");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        info!(%eid, "host keeps questions however old");
+        return Ok(Json(serde_json::json!({ "after_days": null })));
+    };
+    if !(MIN_DAYS..=MAX_DAYS).contains(&after_days) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let data = serde_json::to_vec(&Setting { after_days }).expect("settings always serialize");
+    if let Err(e) = store
+        .put_bytes(&key(eid), "application/json", Bytes::from(data))
+        .await
+    {
+        error!(%eid, error = %e, "failed to store aging setting");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    info!(%eid, after_days, "host set how old questions can get");
+    Ok(Json(serde_json::json!({ "after_days": after_days })))
+}
+
+/// What the `aging` command did (or would have done).
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub(super) struct Report {
+    /// Events whose host set how old questions can get.
+    pub(super) events: usize,
+    /// Questions that were (or would be) hidden, by event.
+    pub(super) expired: Vec<(Uuid, Vec<Uuid>)>,
+    /// Events whose questions couldn't all be looked at or put away, and will be tried again.
+    pub(super) failed: Vec<Uuid>,
+    /// Settings that were removed because their event is gone.
+    pub(super) removed: Vec<Uuid>,
+}
+
+/// Hide every unanswered question that's older than its event allows, if `apply`.
+pub(super) async fn run(
+    dynamo: &Backend,
+    store: &BlobStore,
+    apply: bool,
+) -> Result<Report, StatusCode> {
+    sweep(dynamo, store, now(), apply).await
+}
+
+/// Hide the questions that are too old as of `now`, in seconds since the epoch.
+async fn sweep(
+    dynamo: &Backend,
+    store: &BlobStore,
+    now: u64,
+    apply: bool,
+) -> Result<Report, StatusCode> {
+    let keys = store.list("aging").await.map_err(|e| {
+        error!(error = %e, "failed to list aging settings");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut report = Report::default();
+    for key in keys {
+        let Some(eid) = key
+            .strip_prefix("aging/")
+            .and_then(|k| k.strip_suffix(".json"))
+            .and_then(|eid| Uuid::parse_str(eid).ok())
+        else {
+            continue;
+        };
+        let Some(setting) = setting(store, &eid).await else {
+            continue;
+        };
+        report.events += 1;
+        match super::get_meta(dynamo, &eid).await {
+            Ok(meta) if meta.frozen() => continue,
+            Ok(_) => {}
+            Err(StatusCode::NOT_FOUND) => {
+                if !apply {
+                    report.removed.push(eid);
+                } else if let Err(e) = store.delete(&key).await {
+                    error!(%eid, error = %e, "failed to remove aging setting");
+                } else {
+                    report.removed.push(eid);
+                }
+                continue;
+            }
+            Err(status) => {
+                error!(%eid, %status, "skipping aging for event that couldn't be checked");
+                report.failed.push(eid);
+                continue;
+            }
+        }
+
+        let questions = match dynamo.list(&eid, false).await {
+            Ok(qs) => qs,
+            Err(e) => {
+                error!(%eid, error = %e, "dynamodb request for questions to age failed");
+                report.failed.push(eid);
+                continue;
+            }
+        };
+        let oldest = now.saturating_sub(setting.after_days * 24 * 60 * 60);
+        let expired: Vec<_> = questions
+            .items()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|doc| super::list::parse(&eid, doc))
+            // questions from before the index said when they were asked can't be told apart
+            .filter(|(_, q)| !q.answered && q.asked != 0 && q.asked < oldest)
+            .map(|(qid, _)| qid)
+            .collect();
+        if expired.is_empty() {
+            continue;
+        }
+        if apply {
+            let mut ok = true;
+            for qid in &expired {
+                let hidden = super::toggle::apply(
+                    dynamo,
+                    &eid,
+                    *qid,
+                    Property::Hidden,
+                    true,
+                    Some(Reason::Expired),
+                )
+                .await;
+                ok &= hidden.is_ok();
+            }
+            if !ok {
+                report.failed.push(eid);
+            }
+            info!(%eid, n = expired.len(), "put away questions that were too old");
+        }
+        report.expired.push((eid, expired));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blobs::Location;
+
+    async fn inner(backend: Backend) {
+        let store = BlobStore::open(&Location::Memory).await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = |body: &str| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+        };
+        let waiting = ask("will anyone get to this one?").await.unwrap();
+        let waiting = Uuid::parse_str(waiting["id"].as_str().unwrap()).unwrap();
+        let answered = ask("and this one was answered").await.unwrap();
+        let answered = Uuid::parse_str(answered["id"].as_str().unwrap()).unwrap();
+        crate::toggle::apply(&backend, &eid, answered, Property::Answered, true, None)
+            .await
+            .unwrap();
+
+        let age = |after_days| {
+            set(
+                &store,
+                &backend,
+                &eid,
+                secret,
+                Age {
+                    after_days: Some(after_days),
+                },
+            )
+        };
+        assert_eq!(age(0).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            age(MAX_DAYS + 1).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            set(
+                &store,
+                &backend,
+                &eid,
+                "wrong",
+                Age {
+                    after_days: Some(7)
+                }
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        let Json(set) = age(7).await.unwrap();
+        assert_eq!(set["after_days"], 7);
+
+        // nothing is old enough yet
+        let report = sweep(&backend, &store, now(), true).await.unwrap();
+        assert_eq!(report.events, 1);
+        assert!(report.expired.is_empty());
+
+        // a week and a day later, the unanswered one is
+        let later = now() + 8 * 24 * 60 * 60;
+        let report = sweep(&backend, &store, later, false).await.unwrap();
+        assert_eq!(report.expired, [(eid, vec![waiting])]);
+        let qs = backend.list(&eid, false).await.unwrap();
+        assert_eq!(qs.items().unwrap().len(), 2, "only reported, not hidden");
+        let report = sweep(&backend, &store, later, true).await.unwrap();
+        assert_eq!(report.expired, [(eid, vec![waiting])]);
+        assert!(report.failed.is_empty());
+        let qs = backend.list(&eid, false).await.unwrap();
+        let left: Vec<_> = qs
+            .items()
+            .unwrap()
+            .iter()
+            .filter_map(|doc| crate::list::parse(&eid, doc))
+            .map(|(qid, _)| qid)
+            .collect();
+        assert_eq!(left, [answered]);
+        let q = backend.questions(&[waiting]).await.unwrap();
+        let q = &q.responses().unwrap()["questions"][0];
+        assert_eq!(
+            q["reason"],
+            aws_sdk_dynamodb::model::AttributeValue::S("expired".into())
+        );
+
+        // settings of events that are gone go too
+        backend.delete(&eid).await;
+        let report = sweep(&backend, &store, later, true).await.unwrap();
+        assert_eq!(report.removed, [eid]);
+        assert!(store.list("aging").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,555 @@
+//! Announcements that hosts broadcast to everyone following an event.
+//!
+//! A host can post a short message ("We'll start in 5 minutes", "Please keep questions on-topic")
+//! that's shown above the question list until it expires or the host replaces or clears it. It's
+//! stored on the event's item, so it comes along with the read that list requests make anyway.
+//! Since list responses are a bare array, they carry it in the `X-Announcement` header
+//! (percent-encoded like `encodeURIComponent` does, as it can be any text) along with
+//! `X-Announcement-Until`; the change feed has it in its body.
+//!
+//! Posting an announcement is recorded as a change, which bumps the event's version, so clients
+//! never hold on to an out-of-date one because their list is "not modified".
+//!
+//! Hosts can also queue announcements up ahead of time ("Doors open", "The break is over") by
+//! saying when they should start. There's nothing running in the background to post them when
+//! the time comes (the API runs as a Lambda), so instead the queue is stored next to the current
+//! announcement, and whoever reads the event works out what should be showing right then. Queued
+//! announcements that have ended are dropped as they're read, and from the queue the next time
+//! it's written. An announcement the host posts right away takes precedence over queued ones.
+//! Queued announcements starting doesn't change the event's version, but lists that are "not
+//! modified" still carry the announcement headers as of when they were checked.
+
+use super::{changes::Change, Backend, Local};
+use aws_sdk_dynamodb::model::AttributeValue;
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::{header::HeaderName, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Announcements are meant to be glanced at, so they're kept short.
+pub(super) const MAX_LENGTH: usize = 280;
+/// How long announcements show for if the host doesn't say.
+const DEFAULT_MINUTES: u64 = 15;
+/// Nothing is announced for longer than this.
+pub(super) const MAX_MINUTES: u64 = 24 * 60;
+/// Events can't have more than this many announcements queued up.
+pub(super) const MAX_SCHEDULED: usize = 20;
+
+pub(super) const ANNOUNCEMENT: HeaderName = HeaderName::from_static("x-announcement");
+pub(super) const ANNOUNCEMENT_UNTIL: HeaderName = HeaderName::from_static("x-announcement-until");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Announcement {
+    pub(super) text: String,
+    /// When the announcement stops showing, in seconds since the epoch.
+    pub(super) until: u64,
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Announcement {
+    /// The announcement stored as `text` and `until`, if there is one and it's still showing.
+    pub(super) fn stored(text: Option<String>, until: Option<u64>) -> Option<Self> {
+        Some(Announcement {
+            text: text?,
+            until: until?,
+        })
+        .filter(Self::live)
+    }
+
+    pub(super) fn live(&self) -> bool {
+        self.until > now()
+    }
+
+    pub(super) fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "text": self.text,
+            "until": self.until,
+        })
+    }
+
+    /// The headers list responses carry the announcement in.
+    pub(super) fn headers(&self) -> [(HeaderName, String); 2] {
+        let mut text = String::with_capacity(self.text.len());
+        for b in self.text.bytes() {
+            if b.is_ascii_alphanumeric() || b"-_.!~*'()".contains(&b) {
+                text.push(b as char);
+            } else {
+                text.push_str(&format!("%{b:02X}"));
+            }
+        }
+        [
+            (ANNOUNCEMENT, text),
+            (ANNOUNCEMENT_UNTIL, self.until.to_string()),
+        ]
+    }
+}
+
+/// An announcement queued up to show from `at` (in seconds since the epoch) until `until`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Scheduled {
+    pub(super) at: u64,
+    pub(super) until: u64,
+    pub(super) text: String,
+}
+
+impl Scheduled {
+    /// The queue stored as `scheduled`, without the announcements that have ended.
+    pub(super) fn stored(scheduled: Option<&str>) -> Vec<Self> {
+        let Some(scheduled) = scheduled else {
+            return Vec::new();
+        };
+        let scheduled: Vec<Self> = serde_json::from_str(scheduled).unwrap_or_else(|e| {
+            error!(error = %e, "found malformed announcement queue");
+            Vec::new()
+        });
+        scheduled.into_iter().filter(|s| !s.ended()).collect()
+    }
+
+    pub(super) fn ended(&self) -> bool {
+        self.until <= now()
+    }
+
+    /// How a queue is stored, or `None` if it's empty.
+    pub(super) fn store(scheduled: &[Self]) -> Option<String> {
+        (!scheduled.is_empty())
+            .then(|| serde_json::to_string(scheduled).expect("queues always serialize"))
+    }
+
+    pub(super) fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "at": self.at,
+            "until": self.until,
+            "text": self.text,
+        })
+    }
+}
+
+/// What should be showing right now, given the `posted` announcement and the `scheduled` ones.
+pub(super) fn showing(
+    posted: Option<&Announcement>,
+    scheduled: &[Scheduled],
+) -> Option<Announcement> {
+    if let Some(a) = posted.filter(|a| a.live()) {
+        return Some(a.clone());
+    }
+    let now = now();
+    // if queued announcements overlap, the one that started last is the more relevant one
+    scheduled
+        .iter()
+        .filter(|s| s.at <= now && now < s.until)
+        .max_by_key(|s| s.at)
+        .map(|s| Announcement {
+            text: s.text.clone(),
+            until: s.until,
+        })
+}
+
+impl Backend {
+    /// Make `announcement` the event's announcement, or clear it if it's `None`.
+    pub(super) async fn announce(
+        &self,
+        eid: &Uuid,
+        announcement: Option<&Announcement>,
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()));
+                let upd = if let Some(a) = announcement {
+                    upd.update_expression("SET announcement = :text, announced_until = :until")
+                        .expression_attribute_values(":text", AttributeValue::S(a.text.clone()))
+                        .expression_attribute_values(
+                            ":until",
+                            AttributeValue::N(a.until.to_string()),
+                        )
+                } else {
+                    upd.update_expression("REMOVE announcement, announced_until")
+                };
+                // see Backend::record
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.send().await?;
+                Ok(())
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    events,
+                    announcements,
+                    journal,
+                    ..
+                } = &mut *local;
+
+                if !events.contains_key(eid) {
+                    return Err(super::mint_service_error(
+                        aws_sdk_dynamodb::error::UpdateItemError::generic(
+                            Error::builder()
+                                .code("ValidationException")
+                                .message("announcing in event that doesn't exist")
+                                .build(),
+                        ),
+                    )
+                    .into());
+                }
+                journal.announcement(eid, announcement);
+                match announcement {
+                    Some(a) => announcements.insert(*eid, a.clone()),
+                    None => announcements.remove(eid),
+                };
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .announce(eid, announcement)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .announce(eid, announcement)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .announce(eid, announcement)
+                .map_err(super::mint_unhandled),
+        }
+    }
+
+    /// Make `scheduled` the event's queue of announcements.
+    pub(super) async fn schedule(
+        &self,
+        eid: &Uuid,
+        scheduled: &[Scheduled],
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        let stored = Scheduled::store(scheduled);
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()));
+                let upd = if let Some(stored) = stored {
+                    upd.update_expression("SET scheduled = :scheduled")
+                        .expression_attribute_values(":scheduled", AttributeValue::S(stored))
+                } else {
+                    upd.update_expression("REMOVE scheduled")
+                };
+                // see Backend::record
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.send().await?;
+                Ok(())
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    events,
+                    scheduled: queues,
+                    journal,
+                    ..
+                } = &mut *local;
+
+                if !events.contains_key(eid) {
+                    return Err(super::mint_service_error(
+                        aws_sdk_dynamodb::error::UpdateItemError::generic(
+                            Error::builder()
+                                .code("ValidationException")
+                                .message("scheduling in event that doesn't exist")
+                                .build(),
+                        ),
+                    )
+                    .into());
+                }
+                journal.scheduled(eid, scheduled);
+                if scheduled.is_empty() {
+                    queues.remove(eid);
+                } else {
+                    queues.insert(*eid, scheduled.to_vec());
+                }
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .schedule(eid, stored)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .schedule(eid, stored)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.schedule(eid, stored).map_err(super::mint_unhandled),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Announce {
+    /// What to announce. Empty to clear the current announcement.
+    text: String,
+    /// How long to show it for.
+    minutes: Option<u64>,
+    /// When to start showing it, in seconds since the epoch, if not right away.
+    ///
+    /// With an empty `text`, this removes the announcement queued up to start at `at`.
+    at: Option<u64>,
+}
+
+pub(super) async fn announce(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Announce>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+
+    let text = req.text.trim();
+    if text.chars().count() > MAX_LENGTH {
+        warn!(%eid, "announcement is too long");
+        super::rejections::reject(&eid, "announcement_too_long");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let minutes = req.minutes.unwrap_or(DEFAULT_MINUTES).clamp(1, MAX_MINUTES);
+    if let Some(at) = req.at.filter(|&at| at > now()) {
+        let mut scheduled = meta.scheduled;
+        scheduled.retain(|s| s.at != at);
+        if !text.is_empty() {
+            if scheduled.len() >= MAX_SCHEDULED {
+                warn!(%eid, "too many announcements queued up");
+                super::rejections::reject(&eid, "too_many_scheduled");
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            scheduled.push(Scheduled {
+                at,
+                until: at + minutes * 60,
+                text: text.to_string(),
+            });
+            scheduled.sort_by_key(|s| s.at);
+        }
+        return match dynamo.schedule(&eid, &scheduled).await {
+            Ok(()) => {
+                debug!(%eid, at, n = scheduled.len(), "scheduled announcement");
+                dynamo.try_record(&eid, Change::Announced).await;
+                Ok(Json(serde_json::json!({
+                    "scheduled": scheduled.iter().map(Scheduled::to_json).collect::<Vec<_>>(),
+                })))
+            }
+            Err(e) => {
+                error!(%eid, error = %e, "dynamodb request to schedule announcement failed");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    }
+
+    let announcement = (!text.is_empty()).then(|| Announcement {
+        text: text.to_string(),
+        until: now() + minutes * 60,
+    });
+
+    match dynamo.announce(&eid, announcement.as_ref()).await {
+        Ok(()) => {
+            debug!(%eid, ?announcement, "announced");
+            dynamo.try_record(&eid, Change::Announced).await;
+            Ok(Json(serde_json::json!({
+                "announcement": announcement.as_ref().map(Announcement::to_json),
+            })))
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "dynamodb request to announce failed");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// What's showing now, and what's queued up, for the host.
+pub(super) async fn announcements(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    Ok(Json(serde_json::json!({
+        "announcement": meta.showing().as_ref().map(Announcement::to_json),
+        "scheduled": meta.scheduled.iter().map(Scheduled::to_json).collect::<Vec<_>>(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let schedule = |secret: &str, text: &str, at: Option<u64>| {
+            announce(
+                Path((eid, secret.to_string())),
+                State(backend.clone()),
+                Json(Announce {
+                    text: text.to_string(),
+                    minutes: Some(5),
+                    at,
+                }),
+            )
+        };
+        let announce = |secret: &str, text: &str| schedule(secret, text, None);
+        let meta = || crate::get_meta(&backend, &eid);
+
+        assert_eq!(meta().await.unwrap().announcement, None);
+        assert_eq!(
+            announce("wrong", "hi").await.unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            announce(secret, &"x".repeat(MAX_LENGTH + 1))
+                .await
+                .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let before = meta().await.unwrap().version;
+        let a = announce(secret, "  we'll start in 5 minutes ")
+            .await
+            .unwrap();
+        assert_eq!(a["announcement"]["text"], "we'll start in 5 minutes");
+        let meta_now = meta().await.unwrap();
+        let announcement = meta_now.announcement.unwrap();
+        assert_eq!(announcement.text, "we'll start in 5 minutes");
+        assert!((now() + 4 * 60..=now() + 5 * 60).contains(&announcement.until));
+        assert!(
+            meta_now.version > before,
+            "announcing should change the event's version"
+        );
+
+        let a = announce(secret, "").await.unwrap();
+        assert_eq!(a["announcement"], serde_json::Value::Null);
+        assert_eq!(meta().await.unwrap().announcement, None);
+
+        // queued announcements don't show until they start
+        let at = now() + 600;
+        let a = schedule(secret, "doors open", Some(at)).await.unwrap();
+        assert_eq!(a["scheduled"][0]["text"], "doors open");
+        let a = schedule(secret, "break is over", Some(at + 60))
+            .await
+            .unwrap();
+        assert_eq!(a["scheduled"].as_array().unwrap().len(), 2);
+        let meta_now = meta().await.unwrap();
+        assert_eq!(meta_now.showing(), None);
+        assert_eq!(meta_now.scheduled.len(), 2);
+        assert_eq!(meta_now.scheduled[1].until, at + 60 + 5 * 60);
+        let a = announcements(Path((eid, secret.to_string())), State(backend.clone()))
+            .await
+            .unwrap();
+        assert_eq!(a["scheduled"][1]["text"], "break is over");
+        assert_eq!(
+            announcements(Path((eid, String::from("wrong"))), State(backend.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let a = schedule(secret, "", Some(at)).await.unwrap();
+        assert_eq!(a["scheduled"].as_array().unwrap().len(), 1);
+        assert_eq!(meta().await.unwrap().scheduled[0].text, "break is over");
+
+        backend.delete(&eid).await;
+    }
+
+    #[test]
+    fn expires() {
+        let text = || Some(String::from("hi"));
+        assert!(Announcement::stored(text(), Some(now() + 1)).is_some());
+        assert_eq!(Announcement::stored(text(), Some(now())), None);
+        assert_eq!(Announcement::stored(None, Some(now() + 1)), None);
+    }
+
+    #[test]
+    fn shows_latest_started() {
+        let now = now();
+        let scheduled = |at, until, text: &str| Scheduled {
+            at,
+            until,
+            text: text.to_string(),
+        };
+        let queue = [
+            scheduled(now - 600, now + 600, "welcome"),
+            scheduled(now - 60, now + 60, "break"),
+            scheduled(now + 60, now + 600, "later"),
+            scheduled(now - 600, now - 60, "over"),
+        ];
+        assert_eq!(showing(None, &queue).unwrap().text, "break");
+        assert_eq!(showing(None, &queue[2..]), None);
+
+        let posted = Announcement {
+            text: String::from("now"),
+            until: now + 60,
+        };
+        assert_eq!(showing(Some(&posted), &queue), Some(posted));
+
+        let stored = Scheduled::store(&queue).unwrap();
+        assert_eq!(Scheduled::stored(Some(&stored)), queue[..3]);
+        assert_eq!(Scheduled::store(&[]), None);
+    }
+
+    #[test]
+    fn headers() {
+        let a = Announcement {
+            text: String::from("Pause: 5 min — back at 3pm!"),
+            until: 42,
+        };
+        let [(_, text), (_, until)] = a.headers();
+        assert_eq!(text, "Pause%3A%205%20min%20%E2%80%94%20back%20at%203pm!");
+        assert_eq!(until, "42");
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
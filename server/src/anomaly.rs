@@ -0,0 +1,730 @@
+//! Spotting votes that look like someone pushing a question up.
+//!
+//! A question that suddenly gets a lot of votes (see [`super::surge`]) is usually just popular,
+//! but now and then it's one person with a script, or a few friends on the same network clearing
+//! their cookies. So every vote's change (see [`super::changes`]) also keeps a short hash of the
+//! network it came from (the /24 for IPv4, the /48 for IPv6) and of the voter token it was cast
+//! with, both salted with the event id. They're never shown to anyone, and can't be told apart
+//! from any other network or token without trying them all.
+//!
+//! The `anomalies` command, meant to run on a schedule (every few minutes, say), goes through the
+//! logs of every event that's changed since it last looked, finds each question's busiest
+//! `WINDOW` of upvotes, and if that's `ANOMALY_BURST` votes or more, scores it from 0 to 100 on how
+//! few networks they came from and how many came from voter tokens that hadn't voted for anything
+//! until just before (or didn't say). A busy question that came from everywhere and from people
+//! who've been voting all along scores low. Everyone on an office network shares one address,
+//! so a burst from one network alone only gets a question halfway. Hosts can see the scores with
+//! `GET /api/event/:eid/anomalies/:secret`.
+//!
+//! With `POST /api/event/:eid/anomalies/:secret` and `{"quarantine": true}`, questions that score
+//! `ANOMALY_THRESHOLD` or more also have their suspicious votes (those from fresh tokens on
+//! networks that voted more than once in the burst) held back from their count until the host
+//! reviews them, with `POST /api/event/:eid/anomalies/:secret/:qid/release` to give them back or
+//! `.../discard` to leave them out for good. Either way, the question isn't held again. Votes
+//! being held and released are in the change log, as `votes_held` and `votes_released`.
+//!
+//! Like [aging](super::aging), the analyses and the host's settings are kept in the
+//! [blob store](super::blobs), which has to be one the command can see too.
+
+use super::{blobs::BlobStore, changes::Change, vote::UpDown, Backend, Local};
+use aws_sdk_dynamodb::{error::UpdateItemError, model::AttributeValue, types::SdkError};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use bytes::Bytes;
+use http::StatusCode;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+    time::SystemTime,
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How long a burst of votes can take, in seconds.
+const WINDOW: u64 = 120;
+/// How long before a burst a voter token has to have first voted to not be fresh, in seconds.
+const FRESH: u64 = 10 * 60;
+
+/// The hidden attributes of a vote that say where it came from.
+pub(super) fn marks(
+    eid: &Uuid,
+    client: Option<IpAddr>,
+    voter: Option<&str>,
+) -> Vec<(&'static str, AttributeValue)> {
+    let hash = |what: &str| {
+        let mut sha1 = sha1_smol::Sha1::new();
+        sha1.update(eid.as_bytes());
+        sha1.update(b":");
+        sha1.update(what.as_bytes());
+        AttributeValue::S(sha1.digest().to_string()[..12].to_string())
+    };
+    let net = client.map(|ip| {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        let prefix = if ip.is_ipv4() { 24 } else { 48 };
+        IpNet::new(ip, prefix)
+            .expect("prefix fits the address")
+            .trunc()
+    });
+    net.map(|net| ("net", hash(&net.to_string())))
+        .into_iter()
+        .chain(voter.map(|voter| ("voter", hash(voter))))
+        .collect()
+}
+
+/// An upvote, as the log has it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Upvote {
+    qid: Uuid,
+    at: u64,
+    net: Option<String>,
+    voter: Option<String>,
+}
+
+/// How a question's votes look.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Score {
+    /// How likely it is that the votes were manipulated, from 0 to 100.
+    pub(super) score: u32,
+    /// How many upvotes the question got in its busiest `WINDOW`.
+    pub(super) burst: usize,
+    /// How many networks they came from.
+    pub(super) networks: usize,
+    /// How many came from fresh voter tokens.
+    pub(super) fresh: usize,
+    /// How many came from fresh voter tokens on networks that voted more than once.
+    pub(super) suspicious: usize,
+    /// How many votes are being held back from the question's count.
+    pub(super) held: u64,
+}
+
+/// Score the questions with a burst of at least `burst` upvotes among `votes`, in log order.
+fn analyze(votes: &[Upvote], burst: usize) -> BTreeMap<Uuid, Score> {
+    let mut first = HashMap::new();
+    let mut by_question: HashMap<Uuid, Vec<&Upvote>> = HashMap::new();
+    for v in votes {
+        if let Some(voter) = &v.voter {
+            first.entry(voter.as_str()).or_insert(v.at);
+        }
+        by_question.entry(v.qid).or_default().push(v);
+    }
+
+    let mut scores = BTreeMap::new();
+    for (qid, votes) in by_question {
+        // the busiest window, by sliding one along
+        let (mut start, mut best) = (0, 0..0);
+        for end in 0..votes.len() {
+            while votes[end].at >= votes[start].at + WINDOW {
+                start += 1;
+            }
+            if end + 1 - start > best.len() {
+                best = start..end + 1;
+            }
+        }
+        let busiest = &votes[best];
+        if busiest.len() < burst.max(2) {
+            continue;
+        }
+
+        let began = busiest[0].at;
+        let mut nets: HashMap<&str, usize> = HashMap::new();
+        let mut unknown = 0;
+        for v in busiest {
+            match &v.net {
+                Some(net) => *nets.entry(net.as_str()).or_default() += 1,
+                None => unknown += 1,
+            }
+        }
+        let is_fresh = |v: &Upvote| {
+            v.voter
+                .as_deref()
+                .is_none_or(|voter| first[voter] + FRESH > began)
+        };
+        let fresh = busiest.iter().filter(|v| is_fresh(v)).count();
+        let suspicious = busiest
+            .iter()
+            .filter(|v| is_fresh(v))
+            .filter(|v| v.net.as_deref().is_some_and(|net| nets[net] > 1))
+            .count();
+
+        let n = busiest.len() as f64;
+        let networks = nets.len() + unknown;
+        let narrow = 1.0 - (networks - 1) as f64 / (n - 1.0);
+        let score = (50.0 * (narrow + fresh as f64 / n)).round() as u32;
+        scores.insert(
+            qid,
+            Score {
+                score,
+                burst: busiest.len(),
+                networks,
+                fresh,
+                suspicious,
+                held: 0,
+            },
+        );
+    }
+    scores
+}
+
+impl Backend {
+    /// Change the votes of `qid` by `by`, whichever way.
+    pub(super) async fn shift_votes(
+        &self,
+        qid: &Uuid,
+        by: i64,
+    ) -> Result<(), SdkError<UpdateItemError>> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression("SET votes = votes + :by")
+                    .expression_attribute_values(":by", AttributeValue::N(by.to_string()))
+                    .send()
+                    .await?;
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                let Some(q) = questions.get_mut(qid) else {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("shifting votes of unknown question")
+                            .build(),
+                    )));
+                };
+                if let Some(AttributeValue::N(n)) = q.get_mut("votes") {
+                    let votes = n.parse::<i64>().expect("votes values are numbers");
+                    *n = (votes + by).max(0).to_string();
+                }
+                journal.question(qid, q);
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                mongo
+                    .update::<UpdateItemError>(qid, mongodb::bson::doc! { "$inc": { "votes": by } })
+                    .await?;
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                redis.vote::<UpdateItemError>(qid, by).await?;
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                sled.vote::<UpdateItemError>(qid, by).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the `anomalies` command found for an event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Analysis {
+    /// The sequence number of the last change it included.
+    seq: u64,
+    /// When it was made, in seconds since the epoch.
+    at: u64,
+    questions: BTreeMap<Uuid, Score>,
+}
+
+/// What the host said about holding back suspicious votes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Quarantine {
+    /// Questions whose held votes the host has decided on, which aren't held again.
+    reviewed: BTreeSet<Uuid>,
+}
+
+fn analysis_key(eid: &Uuid) -> String {
+    format!("anomalies/{eid}.json")
+}
+
+fn quarantine_key(eid: &Uuid) -> String {
+    format!("quarantine/{eid}.json")
+}
+
+async fn load<T: serde::de::DeserializeOwned>(
+    store: &BlobStore,
+    key: &str,
+) -> Result<Option<T>, StatusCode> {
+    match store.get(key).await {
+        Ok(blob) => Ok(blob.and_then(|blob| {
+            serde_json::from_slice(&blob.data)
+                .map_err(|e| error!(key, error = %e, "found malformed anomaly state"))
+                .ok()
+        })),
+        Err(e) => {
+            error!(key, error = %e, "failed to read anomaly state");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn save<T: Serialize>(store: &BlobStore, key: &str, value: &T) -> Result<(), StatusCode> {
+    let data = serde_json::to_vec(value).expect("anomaly state always serializes");
+    store
+        .put_bytes(key, "application/json", Bytes::from(data))
+        .await
+        .map_err(|e| {
+            error!(key, error = %e, "failed to store anomaly state");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// The upvotes in `eid`'s log, how many votes each question has held back, and the last `seq`.
+async fn read_log(
+    dynamo: &Backend,
+    eid: &Uuid,
+) -> Result<(Vec<Upvote>, HashMap<Uuid, u64>, u64), aws_sdk_dynamodb::Error> {
+    let mut upvotes = Vec::new();
+    let mut held: HashMap<Uuid, u64> = HashMap::new();
+    let mut seq = 0;
+    loop {
+        let page = dynamo.changes(eid, seq).await?;
+        let items = page.items().unwrap_or_default();
+        if items.is_empty() {
+            break;
+        }
+        for item in items {
+            let number = |k| item.get(k)?.as_n().ok()?.parse::<u64>().ok();
+            let text = |k| Some(item.get(k)?.as_s().ok()?.clone());
+            seq = number("seq").expect("seq is always a number");
+            match Change::from_item(item) {
+                Some(Change::VoteCast {
+                    qid,
+                    direction: UpDown::Up,
+                }) => upvotes.push(Upvote {
+                    qid,
+                    at: number("at").unwrap_or(0),
+                    net: text("net"),
+                    voter: text("voter"),
+                }),
+                Some(Change::VotesHeld { qid, votes }) => *held.entry(qid).or_default() += votes,
+                Some(Change::VotesReleased { qid, votes }) => {
+                    let held = held.entry(qid).or_default();
+                    *held = held.saturating_sub(votes);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok((upvotes, held, seq))
+}
+
+/// What the `anomalies` command did.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub(super) struct Report {
+    /// Events whose votes were looked at again.
+    pub(super) analyzed: usize,
+    /// Questions that scored `ANOMALY_THRESHOLD` or more, by event.
+    pub(super) flagged: Vec<(Uuid, Vec<Uuid>)>,
+    /// How many more votes were held back, by event and question.
+    pub(super) held: Vec<(Uuid, Uuid, u64)>,
+    /// Events whose votes couldn't be looked at, and will be tried again.
+    pub(super) failed: Vec<Uuid>,
+}
+
+/// Score the votes of every event that's changed since it was last looked at.
+pub(super) async fn run(dynamo: &Backend, store: &BlobStore) -> Result<Report, StatusCode> {
+    let events = dynamo.all_events().await.map_err(|e| {
+        error!(error = %e, "failed to list events to look for anomalies in");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut report = Report::default();
+    for (eid, _) in events {
+        if let Err(status) = check(dynamo, store, &eid, &mut report).await {
+            error!(%eid, %status, "failed to look for anomalies");
+            report.failed.push(eid);
+        }
+    }
+    Ok(report)
+}
+
+async fn check(
+    dynamo: &Backend,
+    store: &BlobStore,
+    eid: &Uuid,
+    report: &mut Report,
+) -> Result<(), StatusCode> {
+    let config = super::config::config();
+    let meta = super::get_meta(dynamo, eid).await?;
+    let last: Option<Analysis> = load(store, &analysis_key(eid)).await?;
+    if meta.frozen() || last.is_some_and(|a| a.seq >= meta.version) {
+        return Ok(());
+    }
+    let quarantine: Option<Quarantine> = load(store, &quarantine_key(eid)).await?;
+
+    let (upvotes, held, mut seq) = read_log(dynamo, eid).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for votes to look at failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    report.analyzed += 1;
+    let mut questions = analyze(&upvotes, config.anomaly_burst);
+    let mut flagged = Vec::new();
+    let mut held_more = false;
+    for (qid, score) in &mut questions {
+        score.held = held.get(qid).copied().unwrap_or(0);
+        if score.score < config.anomaly_threshold {
+            continue;
+        }
+        flagged.push(*qid);
+        let Some(quarantine) = &quarantine else {
+            continue;
+        };
+        let more = (score.suspicious as u64).saturating_sub(score.held);
+        if more == 0 || quarantine.reviewed.contains(qid) {
+            continue;
+        }
+        if let Err(e) = dynamo.shift_votes(qid, -(more as i64)).await {
+            error!(%eid, %qid, error = %e, "dynamodb request to hold votes failed");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        info!(%eid, %qid, votes = more, "holding back suspicious votes");
+        super::metrics::add("anomaly.held", more);
+        dynamo
+            .try_record(
+                eid,
+                Change::VotesHeld {
+                    qid: *qid,
+                    votes: more,
+                },
+            )
+            .await;
+        score.held += more;
+        held_more = true;
+        report.held.push((*eid, *qid, more));
+    }
+    if held_more {
+        // the votes being held are in the log too, and don't need looking at again (any vote that
+        // slipped in meanwhile is looked at along with the next one)
+        seq = super::get_meta(dynamo, eid).await?.version;
+    }
+    if !flagged.is_empty() {
+        report.flagged.push((*eid, flagged));
+    }
+
+    let at = super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    save(store, &analysis_key(eid), &Analysis { seq, at, questions }).await
+}
+
+/// The scores of the event's questions, as of the last time the `anomalies` command looked.
+pub(super) async fn anomalies(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    scores(super::blobs::store().await, &dynamo, &eid, &secret).await
+}
+
+async fn scores(
+    store: &BlobStore,
+    dynamo: &Backend,
+    eid: &Uuid,
+    secret: &str,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(dynamo, eid, secret).await?;
+    let analysis: Option<Analysis> = load(store, &analysis_key(eid)).await?;
+    let quarantine: Option<Quarantine> = load(store, &quarantine_key(eid)).await?;
+    let threshold = super::config::config().anomaly_threshold;
+    let analysis = analysis.unwrap_or_default();
+    Ok(Json(serde_json::json!({
+        "analyzed": (analysis.at != 0).then_some(analysis.at),
+        "threshold": threshold,
+        "quarantine": quarantine.is_some(),
+        "reviewed": quarantine.map(|q| q.reviewed).unwrap_or_default(),
+        "questions": analysis.questions,
+    })))
+}
+
+/// Whether to hold back suspicious votes.
+#[derive(Debug, Deserialize)]
+pub(super) struct Hold {
+    quarantine: bool,
+}
+
+pub(super) async fn quarantine(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Hold>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    set(super::blobs::store().await, &dynamo, &eid, &secret, req).await
+}
+
+async fn set(
+    store: &BlobStore,
+    dynamo: &Backend,
+    eid: &Uuid,
+    secret: &str,
+    req: Hold,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(dynamo, eid, secret).await?;
+    let key = quarantine_key(eid);
+    if !req.quarantine {
+        // votes that are held stay held until the host decides on them
+        store.delete(&key).await.map_err(|e| {
+            error!(%eid, error = %e, "failed to remove quarantine setting");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    } else if load::<Quarantine>(store, &key).await?.is_none() {
+        save(store, &key, &Quarantine::default()).await?;
+    }
+    info!(%eid, quarantine = req.quarantine, "host set whether to hold suspicious votes");
+    Ok(Json(serde_json::json!({ "quarantine": req.quarantine })))
+}
+
+/// What the host decided about a question's held votes.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Decision {
+    Release,
+    Discard,
+}
+
+pub(super) async fn review(
+    Path((eid, secret, qid, decision)): Path<(Uuid, String, Uuid, Decision)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    decide(
+        super::blobs::store().await,
+        &dynamo,
+        &eid,
+        &secret,
+        qid,
+        decision,
+    )
+    .await
+}
+
+async fn decide(
+    store: &BlobStore,
+    dynamo: &Backend,
+    eid: &Uuid,
+    secret: &str,
+    qid: Uuid,
+    decision: Decision,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if super::check_secret(dynamo, eid, secret).await?.frozen() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let (_, held, _) = read_log(dynamo, eid).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for held votes failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let held = held.get(&qid).copied().unwrap_or(0);
+    let key = quarantine_key(eid);
+    if let Some(mut quarantine) = load::<Quarantine>(store, &key).await? {
+        quarantine.reviewed.insert(qid);
+        save(store, &key, &quarantine).await?;
+    }
+    if decision == Decision::Release && held != 0 {
+        if let Err(e) = dynamo.shift_votes(&qid, held as i64).await {
+            error!(%eid, %qid, error = %e, "dynamodb request to release votes failed");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        dynamo
+            .try_record(eid, Change::VotesReleased { qid, votes: held })
+            .await;
+    }
+    info!(%eid, %qid, ?decision, held, "host reviewed held votes");
+    Ok(Json(serde_json::json!({
+        "released": if decision == Decision::Release { held } else { 0 },
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blobs::Location;
+    use crate::networks::Client;
+
+    fn up(qid: Uuid, at: u64, net: &str, voter: &str) -> Upvote {
+        Upvote {
+            qid,
+            at,
+            net: Some(net.to_string()),
+            voter: Some(voter.to_string()),
+        }
+    }
+
+    #[test]
+    fn analyzes() {
+        let (popular, pushed, quiet) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut votes = Vec::new();
+        // people who've been around for a while, from all over
+        for i in 0..10 {
+            votes.push(up(quiet, i, &format!("net{i}"), &format!("old{i}")));
+        }
+        for i in 0..10 {
+            votes.push(up(
+                popular,
+                1000 + i,
+                &format!("net{i}"),
+                &format!("old{i}"),
+            ));
+        }
+        // and a bunch of new tokens from two networks
+        for i in 0..10 {
+            votes.push(up(
+                pushed,
+                1000 + i,
+                &format!("bad{}", i % 2),
+                &format!("new{i}"),
+            ));
+        }
+        let scores = analyze(&votes, 10);
+        assert_eq!(scores[&popular].score, 0);
+        assert_eq!(scores[&popular].fresh, 0);
+        assert_eq!(scores[&pushed].networks, 2);
+        assert_eq!(scores[&pushed].fresh, 10);
+        assert_eq!(scores[&pushed].suspicious, 10);
+        assert_eq!(scores[&pushed].score, 94);
+        // which is fresh there, but its votes come from everywhere
+        assert_eq!(scores[&quiet].score, 50);
+        assert_eq!(scores[&quiet].suspicious, 0);
+
+        // spread out over longer, it's not a burst
+        let slow: Vec<_> = (0..10).map(|i| up(quiet, i * 60, "net", "v")).collect();
+        assert!(analyze(&slow, 10).is_empty());
+    }
+
+    #[test]
+    fn marks_networks() {
+        let eid = Uuid::new_v4();
+        let net = |ip: &str| {
+            marks(&eid, Some(ip.parse().unwrap()), None)
+                .into_iter()
+                .find(|(k, _)| *k == "net")
+                .unwrap()
+                .1
+        };
+        assert_eq!(net("192.0.2.1"), net("192.0.2.200"));
+        assert_eq!(net("192.0.2.1"), net("::ffff:192.0.2.9"));
+        assert_ne!(net("192.0.2.1"), net("192.0.3.1"));
+        assert_eq!(net("2001:db8:1::1"), net("2001:db8:1:ff::1"));
+        assert_ne!(net("2001:db8:1::1"), net("2001:db8:2::1"));
+        assert!(marks(&eid, None, None).is_empty());
+        assert_eq!(marks(&eid, None, Some("voter")).len(), 1);
+    }
+
+    async fn inner(backend: Backend) {
+        let store = BlobStore::open(&Location::Memory).await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "why not give everyone a raise?".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let votes = || async {
+            let list = backend.list(&eid, true).await.unwrap();
+            crate::list::parse(&eid, &list.items().unwrap()[0])
+                .unwrap()
+                .1
+                .votes
+        };
+
+        assert_eq!(
+            set(&store, &backend, &eid, secret, Hold { quarantine: true })
+                .await
+                .unwrap()["quarantine"],
+            true
+        );
+        let burst = crate::config::config().anomaly_burst;
+        for i in 0..burst {
+            crate::vote::vote(
+                Path((qid, UpDown::Up)),
+                State(backend.clone()),
+                http::HeaderMap::from_iter([(
+                    crate::vote::VOTER,
+                    format!("token{i}").parse().unwrap(),
+                )]),
+                Client(Some("192.0.2.7".parse().unwrap())),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(votes().await, 1 + burst);
+
+        let report = run(&backend, &store).await.unwrap();
+        assert_eq!(report.flagged, [(eid, vec![qid])]);
+        assert_eq!(report.held, [(eid, qid, burst as u64)]);
+        assert_eq!(votes().await, 1);
+        let Json(s) = scores(&store, &backend, &eid, secret).await.unwrap();
+        assert_eq!(s["questions"][qid.to_string()]["score"], 100);
+        assert_eq!(s["questions"][qid.to_string()]["held"], burst);
+
+        // nothing's changed, so there's nothing to look at
+        let report = run(&backend, &store).await.unwrap();
+        assert_eq!(report.analyzed, 0);
+
+        let Json(r) = decide(&store, &backend, &eid, secret, qid, Decision::Release)
+            .await
+            .unwrap();
+        assert_eq!(r["released"], burst);
+        assert_eq!(votes().await, 1 + burst);
+        // and having been reviewed, they aren't held again
+        let report = run(&backend, &store).await.unwrap();
+        assert_eq!(report.analyzed, 1);
+        assert!(report.held.is_empty());
+        assert_eq!(votes().await, 1 + burst);
+
+        // which the log agrees with
+        let replayed = crate::rebuild::replay(backend.all_changes(&eid).await.unwrap());
+        assert_eq!(replayed.questions[0].1.votes, 1 + burst as i64);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,396 @@
+//! Closing events, and keeping the ones hosts want public readable after they're over.
+//!
+//! When the talk is over, the host can close the event with `POST /api/event/:eid/close/:secret`
+//! (and `{"state": "closed"}`), after which nobody can ask new questions. With `"archived"`
+//! instead, the event is also published as a public archive at `GET /archive/:eid`: a plain HTML
+//! page with the questions, their votes, and who asked them, rendered by the server so that it can
+//! be linked to, and found by search engines, without the client or any polling. `"open"` undoes
+//! either. Like holds, this goes in the event's moderation log (see [`super::moderation`]).
+//!
+//! An archive shows the event as it was when it was archived: the questions are kept as a snapshot
+//! in the [blob store](super::blobs), and if that's missing (say, because another instance kept it
+//! in memory), it's taken again the first time the page is asked for. Pages are cached for an
+//! hour.
+//!
+//! Archives of frozen events are withheld like everything else about them, and archives of events
+//! that have expired are gone with them, unless the event is on hold.
+
+use super::{
+    moderation::{self, Action, Kind, Role},
+    text::{Line, Transcript},
+    Backend,
+};
+use axum::extract::{Path, State};
+use axum::response::{AppendHeaders, Html, Json};
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use serde::Deserialize;
+use std::{fmt::Write, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const ROBOTS: HeaderName = HeaderName::from_static("x-robots-tag");
+
+/// Whether an event is taking questions, and who gets to read it once it isn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Stage {
+    Open,
+    Closed,
+    Archived,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Close {
+    pub(super) state: Stage,
+}
+
+/// Open, close, or archive the event, for its host.
+pub(super) async fn close(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Close>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    let kind = match req.state {
+        Stage::Open => Kind::Reopened,
+        Stage::Closed => Kind::Closed,
+        Stage::Archived => Kind::Archived,
+    };
+    let mut stage = Stage::Open;
+    let (log, changed) = moderation::amend(&dynamo, &eid, meta, "close", |log| {
+        stage = match moderation::closed(log).map(|a| a.kind) {
+            None => Stage::Open,
+            Some(Kind::Archived) => Stage::Archived,
+            Some(_) => Stage::Closed,
+        };
+        if stage == req.state {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: super::clock::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            kind,
+            note: String::new(),
+            by: Some(Role::Host),
+        });
+        Ok(true)
+    })
+    .await?;
+    if !changed {
+        return Ok(Json(moderation::status(&log)));
+    }
+    if kind == Kind::Archived {
+        match super::text::transcript(&dynamo, &eid).await {
+            Ok((_, transcript)) => keep(&eid, &transcript).await,
+            Err((_, status)) => warn!(%eid, %status, "failed to take snapshot of archive"),
+        }
+    } else if stage == Stage::Archived {
+        if let Err(e) = super::blobs::store().await.delete(&snapshot(&eid)).await {
+            warn!(%eid, error = %e, "failed to remove snapshot of archive");
+        }
+    }
+    info!(%eid, ?kind, "host changed whether event is open");
+    super::metrics::incr(format!("close.{kind:?}").to_lowercase());
+    Ok(Json(moderation::status(&log)))
+}
+
+/// Where the snapshot of an archived event's questions is stored.
+fn snapshot(eid: &Uuid) -> String {
+    format!("archives/{eid}.json")
+}
+
+/// Store `transcript` as the snapshot of the archived event `eid`.
+async fn keep(eid: &Uuid, transcript: &Transcript) {
+    let json = serde_json::to_vec(transcript).expect("transcripts always serialize");
+    let stored = super::blobs::store()
+        .await
+        .put_bytes(&snapshot(eid), "application/json", json.into())
+        .await;
+    if let Err(e) = stored {
+        warn!(%eid, error = %e, "failed to store snapshot of archive");
+    }
+}
+
+/// The snapshot of the archived event `eid`, if there is one.
+async fn kept(eid: &Uuid) -> Option<Transcript> {
+    match super::blobs::store().await.get(&snapshot(eid)).await {
+        Ok(blob) => serde_json::from_slice(&blob?.data)
+            .map_err(|e| warn!(%eid, error = %e, "found malformed snapshot of archive"))
+            .ok(),
+        Err(e) => {
+            warn!(%eid, error = %e, "failed to read snapshot of archive");
+            None
+        }
+    }
+}
+
+/// Make `s` safe to put in HTML text and attribute values.
+pub(super) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// How the archive page is to be presented, besides what's in it.
+struct Page<'a> {
+    title: &'a str,
+    /// Where the page is for good, if the instance knows its own URL.
+    canonical: Option<String>,
+    /// Whether search engines may index the page.
+    index: bool,
+}
+
+fn render(page: &Page<'_>, transcript: &Transcript) -> String {
+    let title = escape(page.title);
+    let mut out = String::from(
+        "<!DOCTYPE html>\n\
+         <html lang=\"en\">\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+    );
+    if !page.index {
+        out.push_str("<meta name=\"robots\" content=\"noindex\">\n");
+    }
+    if let Some(canonical) = &page.canonical {
+        let _ = writeln!(
+            out,
+            "<link rel=\"canonical\" href=\"{}\">",
+            escape(canonical)
+        );
+    }
+    let _ = write!(
+        out,
+        "<title>{title}</title>\n\
+         </head>\n\
+         <body>\n\
+         <main>\n\
+         <h1>{title}</h1>\n"
+    );
+    let Transcript {
+        unanswered,
+        answered,
+    } = transcript;
+    if unanswered.is_empty() && answered.is_empty() {
+        out.push_str("<p>No questions were asked.</p>\n");
+    }
+    for (heading, lines) in [("Answered", answered), ("Unanswered", unanswered)] {
+        if lines.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "<section>\n<h2>{heading}</h2>\n<ol>\n");
+        for Line { text, who, votes } in lines {
+            let plural = if *votes == 1 { "" } else { "s" };
+            // keep the asker's line breaks
+            let text = escape(text).replace('\n', "<br>\n");
+            let _ = write!(out, "<li>\n<p>{text}</p>\n<p>{votes} vote{plural}");
+            if let Some(who) = who {
+                let _ = write!(out, ", asked by {}", escape(who));
+            }
+            out.push_str("</p>\n</li>\n");
+        }
+        out.push_str("</ol>\n</section>\n");
+    }
+    out.push_str("</main>\n</body>\n</html>\n");
+    out
+}
+
+pub(super) async fn archive(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+) -> (
+    AppendHeaders<Vec<(HeaderName, &'static str)>>,
+    Result<Html<String>, StatusCode>,
+) {
+    let cache = |cache| AppendHeaders(vec![(header::CACHE_CONTROL, cache)]);
+    let meta = match super::get_meta(&dynamo, &eid).await {
+        Ok(meta) => meta,
+        Err(StatusCode::NOT_FOUND) => {
+            // events are unlikely to re-appear with the same uuid
+            return (cache("max-age=86400"), Err(StatusCode::NOT_FOUND));
+        }
+        Err(status) => return (cache("no-cache"), Err(status)),
+    };
+    if meta.frozen() {
+        // the event may well be unfrozen after review
+        return (cache("max-age=60"), Err(StatusCode::FORBIDDEN));
+    }
+    if !meta.archived() {
+        // but the host may be about to archive it
+        debug!(%eid, "request for archive of event that isn't archived");
+        return (cache("max-age=60"), Err(StatusCode::NOT_FOUND));
+    }
+
+    // the snapshot may not have been taken (or kept) when the event was archived
+    let transcript = match kept(&eid).await {
+        Some(transcript) => transcript,
+        None => match super::text::transcript(&dynamo, &eid).await {
+            Ok((_, transcript)) => {
+                keep(&eid, &transcript).await;
+                transcript
+            }
+            Err((cache_for, status)) => return (cache(cache_for), Err(status)),
+        },
+    };
+
+    let config = super::config::config();
+    let title = match &config.instance_name {
+        Some(name) => format!("Questions asked at {name}"),
+        None => String::from("Questions asked"),
+    };
+    let page = Page {
+        title: &title,
+        canonical: config
+            .public_url
+            .as_ref()
+            .map(|base| format!("{base}/archive/{eid}")),
+        index: !meta.unlisted(),
+    };
+    let mut headers = cache("max-age=3600");
+    if !page.index {
+        // for crawlers that don't read the page itself
+        headers.0.push((ROBOTS, "noindex"));
+    }
+    (headers, Ok(Html(render(&page, &transcript))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = |body: &'static str| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: Some(String::from("<script>")),
+                }),
+            )
+        };
+        let close = |state| {
+            super::close(
+                Path((eid, secret.to_string())),
+                State(backend.clone()),
+                Json(Close { state }),
+            )
+        };
+        let archive = || super::archive(Path(eid), State(backend.clone()));
+
+        let q = ask("is this <b>bold</b>?").await.unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        assert_eq!(archive().await.1.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let Json(s) = close(Stage::Closed).await.unwrap();
+        assert_eq!(s["closed"], true);
+        assert_eq!(s["archived"], false);
+        assert_eq!(
+            ask("can I still ask?").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        // closed isn't public
+        assert_eq!(archive().await.1.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let Json(s) = close(Stage::Archived).await.unwrap();
+        assert_eq!(s["archived"], true);
+        let (AppendHeaders(headers), page) = archive().await;
+        assert_eq!(headers, [(header::CACHE_CONTROL, "max-age=3600")]);
+        let Html(page) = page.unwrap();
+        assert!(page.contains("is this &lt;b&gt;bold&lt;/b&gt;?"), "{page}");
+        assert!(page.contains("asked by &lt;script&gt;"), "{page}");
+        assert!(!page.contains("can I still ask?"), "{page}");
+
+        // the archive is what the event was like when it was archived
+        crate::toggle::toggle(
+            Path((
+                eid,
+                secret.to_string(),
+                qid,
+                crate::toggle::Property::Answered,
+            )),
+            State(backend.clone()),
+            axum::extract::Query(Default::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        let Html(page) = archive().await.1.unwrap();
+        assert!(!page.contains("Answered"), "{page}");
+
+        // doing it again doesn't add to the log
+        let Json(s) = close(Stage::Archived).await.unwrap();
+        assert_eq!(s["log"].as_array().unwrap().len(), 2);
+
+        let Json(s) = close(Stage::Open).await.unwrap();
+        assert_eq!(s["closed"], false);
+        ask("can I ask again?").await.unwrap();
+        assert_eq!(archive().await.1.unwrap_err(), StatusCode::NOT_FOUND);
+
+        assert_eq!(
+            super::close(
+                Path((eid, String::from("wrong"))),
+                State(backend.clone()),
+                Json(Close {
+                    state: Stage::Archived
+                }),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
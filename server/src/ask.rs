@@ -1,10 +1,11 @@
-use super::{Backend, Local};
+use super::{changes::Change, Backend, Local};
 use aws_sdk_dynamodb::{
     error::PutItemError, model::AttributeValue, output::PutItemOutput, types::SdkError,
 };
+use aws_smithy_types::Error;
 use axum::extract::{Path, State};
 use axum::response::Json;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode};
 use serde::Deserialize;
 use std::{
     collections::HashMap,
@@ -15,44 +16,64 @@ use uuid::Uuid;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
-const QUESTIONS_EXPIRE_AFTER_DAYS: u64 = 30;
+pub(super) const QUESTIONS_EXPIRE_AFTER_DAYS: u64 = 30;
 
 impl Backend {
-    pub(super) async fn ask(
+    /// Store a question as if it had been asked at `when`, never to expire if the event is `held`.
+    pub(super) async fn ask_at(
         &self,
         eid: &Uuid,
         qid: &Uuid,
         q: Question,
+        when: SystemTime,
+        held: bool,
     ) -> Result<PutItemOutput, SdkError<PutItemError>> {
-        let attrs = [
+        self.ask_with(eid, qid, q, when, held, Vec::new()).await
+    }
+
+    /// Store a question like [`Backend::ask_at`], with `extra` attributes (which win over the
+    /// usual ones).
+    pub(super) async fn ask_with(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        q: Question,
+        when: SystemTime,
+        held: bool,
+        extra: Vec<(&'static str, AttributeValue)>,
+    ) -> Result<PutItemOutput, SdkError<PutItemError>> {
+        let mut attrs = vec![
             ("id", AttributeValue::S(qid.to_string())),
             ("eid", AttributeValue::S(eid.to_string())),
             ("votes", AttributeValue::N(1.to_string())),
-            ("text", AttributeValue::S(q.body.into())),
+            ("text", AttributeValue::S(q.body)),
             (
                 "when",
                 AttributeValue::N(
-                    SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
+                    when.duration_since(SystemTime::UNIX_EPOCH)
                         .unwrap()
                         .as_secs()
                         .to_string(),
                 ),
             ),
-            (
+            ("hidden", AttributeValue::Bool(false)),
+            ("answered", AttributeValue::Bool(false)),
+        ];
+        if !held {
+            attrs.push((
                 "expire",
                 AttributeValue::N(
-                    (SystemTime::now()
+                    (super::clock::now()
                         + Duration::from_secs(QUESTIONS_EXPIRE_AFTER_DAYS * 24 * 60 * 60))
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .unwrap()
                     .as_secs()
                     .to_string(),
                 ),
-            ),
-            ("hidden", AttributeValue::Bool(false)),
-            ("answered", AttributeValue::Bool(false)),
-        ];
+            ));
+        }
+        attrs.retain(|(k, _)| !extra.iter().any(|(e, _)| e == k));
+        attrs.extend(extra);
         match self {
             Self::Dynamo(dynamo) => {
                 let mut r = dynamo.put_item().table_name("questions");
@@ -65,24 +86,46 @@ impl Backend {
                 r.send().await
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
+                let mut local = local.write().unwrap();
                 let Local {
                     questions,
                     questions_by_eid,
+                    journal,
                     ..
                 } = &mut *local;
 
+                let Some(qids) = questions_by_eid.get_mut(eid) else {
+                    return Err(super::mint_service_error(PutItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("adding question to event that doesn't exist")
+                            .build(),
+                    )));
+                };
                 let mut question = HashMap::from_iter(attrs);
                 if let Some(asker) = q.asker {
                     question.insert("who", AttributeValue::S(asker));
                 }
-                questions.insert(qid.clone(), question);
-                questions_by_eid
-                    .get_mut(eid)
-                    .expect("adding question to event that doesn't exist")
-                    .push(qid.clone());
+                journal.question(qid, &question);
+                questions.insert(*qid, question);
+                qids.push(*qid);
                 Ok(PutItemOutput::builder().build())
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let asker = q.asker.map(|asker| ("who", AttributeValue::S(asker)));
+                mongo.put("questions", attrs.into_iter().chain(asker)).await
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                let asker = q.asker.map(|asker| ("who", AttributeValue::S(asker)));
+                redis.ask(eid, qid, attrs.into_iter().chain(asker)).await
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let asker = q.asker.map(|asker| ("who", AttributeValue::S(asker)));
+                sled.ask(eid, qid, attrs.into_iter().chain(asker)).await
+            }
         }
     }
 }
@@ -93,26 +136,63 @@ pub(super) struct Question {
     pub(super) asker: Option<String>,
 }
 
+/// Turn away questions that are empty or only a single word.
+pub(super) fn screen(eid: &Uuid, body: &str) -> Result<(), StatusCode> {
+    if body.trim().is_empty() {
+        warn!(%eid, "ignoring empty question");
+        super::rejections::reject(eid, "empty");
+        return Err(http::StatusCode::BAD_REQUEST);
+    } else if !body.trim().contains(' ') {
+        warn!(%eid, body, "rejecting single-word question");
+        super::rejections::reject(eid, "single_word");
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+/// Turn away questions for events that aren't taking any.
+pub(super) fn taking(eid: &Uuid, meta: &super::Meta) -> Result<(), StatusCode> {
+    if meta.frozen() {
+        warn!(%eid, "question for frozen event");
+        super::rejections::reject(eid, "frozen");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if meta.closed() {
+        debug!(%eid, "question for closed event");
+        super::rejections::reject(eid, "closed");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
 pub(super) async fn ask(
     Path(eid): Path<Uuid>,
     State(dynamo): State<Backend>,
+    headers: HeaderMap,
     q: Json<Question>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    if q.body.trim().is_empty() {
-        warn!(%eid, "ignoring empty question");
-        return Err(http::StatusCode::BAD_REQUEST);
-    } else if !q.body.trim().contains(' ') {
-        warn!(%eid, body = q.body, "rejecting single-word question");
-        return Err(http::StatusCode::BAD_REQUEST);
-    }
+    screen(&eid, &q.body)?;
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    taking(&eid, &meta)?;
 
-    // TODO: check that eid actually exists
+    // so that the asker can find their questions again (see super::mine)
+    let extra = super::mine::author(&headers, &meta.secret)
+        .map(|author| ("author", AttributeValue::S(author)))
+        .into_iter()
+        .collect();
     // TODO: UUIDv7
     let qid = uuid::Uuid::new_v4();
-    match dynamo.ask(&eid, &qid, q.0).await {
+    match dynamo
+        .ask_with(&eid, &qid, q.0, super::clock::now(), meta.held(), extra)
+        .await
+    {
         Ok(_) => {
             debug!(%eid, %qid, "created question");
-            Ok(Json(serde_json::json!({ "id": qid.to_string() })))
+            dynamo.try_record(&eid, Change::QuestionAsked { qid }).await;
+            Ok(Json(serde_json::json!({
+                "id": qid.to_string(),
+                "receipt": super::mine::receipt(&meta.secret, &qid),
+            })))
         }
         Err(e) => {
             error!(%eid, %qid, error = %e, "dynamodb request to create question failed");
@@ -126,12 +206,13 @@ mod tests {
     use super::*;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
         let _secret = e["secret"].as_str().unwrap();
         let q = super::ask(
-            Path(eid.clone()),
+            Path(eid),
             State(backend.clone()),
+            http::HeaderMap::new(),
             Json(Question {
                 body: "hello world".into(),
                 asker: Some("person".into()),
@@ -154,4 +235,24 @@ mod tests {
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
 }
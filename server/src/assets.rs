@@ -0,0 +1,327 @@
+//! Serving the built client, from `--static-dir` or from the binary itself.
+//!
+//! This does what CloudFront and its viewer-request function do in the AWS deployment:
+//!
+//! - `/event/…` paths are the client's own routes, so they get `index.html`. Other unknown paths
+//!   are still 404s.
+//! - If the client was built with `.br` or `.gz` files next to the originals, those are sent to
+//!   clients whose `Accept-Encoding` allows it.
+//! - Vite gives everything under `assets/` a content hash in its file name, so those can be cached
+//!   forever. Everything else (notably `index.html`, which refers to the current hashes) has to be
+//!   revalidated on every use.
+//!
+//! With the `embed` feature, `client/dist` is compiled into the binary and served the same way
+//! when there's no `--static-dir`, so that deploying is copying a single file. Build the client
+//! (and compress it, if you like) before building the server. If it hasn't been built, the server
+//! still compiles (see `build.rs`), but has no client in it, and says so when it starts.
+
+use axum::{
+    body::boxed,
+    http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode, Uri},
+    response::IntoResponse,
+    routing::{get, MethodRouter},
+};
+use std::{path::Path, sync::Arc};
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const IMMUTABLE: HeaderValue = HeaderValue::from_static("public, max-age=31536000, immutable");
+const REVALIDATE: HeaderValue = HeaderValue::from_static("no-cache");
+
+/// Serve the client built into `dir`.
+pub(super) fn dir<B>(dir: &Path) -> MethodRouter<(), B>
+where
+    B: axum::body::HttpBody + Send + 'static,
+{
+    let dir: Arc<Path> = Arc::from(dir);
+    get(move |req: Request<B>| serve(dir, req))
+}
+
+/// The client's build output, as it was when the server was compiled.
+#[cfg(feature = "embed")]
+static CLIENT: include_dir::Dir<'static> = include_dir::include_dir!("$CLIENT_DIST");
+
+/// Serve the client that was built into the binary.
+#[cfg(feature = "embed")]
+pub(super) fn embedded<B>() -> MethodRouter<(), B>
+where
+    B: axum::body::HttpBody + Send + 'static,
+{
+    if CLIENT.entries().is_empty() {
+        warn!("no client was built into the server, so only the API is served (build the client before the server, or pass --static-dir)");
+    }
+    get(|req: Request<B>| async move { serve_embedded(&CLIENT, &req) })
+}
+
+/// Which file to serve for a request path.
+fn entry(path: &str) -> Option<Uri> {
+    path.starts_with("/event/")
+        .then(|| Uri::from_static("/index.html"))
+}
+
+/// Whether the file at `path` may be cached without revalidation.
+fn immutable(path: &str) -> bool {
+    path.starts_with("/assets/")
+}
+
+async fn serve<B>(dir: Arc<Path>, mut req: Request<B>) -> axum::response::Response
+where
+    B: Send + 'static,
+{
+    if let Some(uri) = entry(req.uri().path()) {
+        *req.uri_mut() = uri;
+    }
+    prefer_brotli(req.headers_mut());
+    let path = req.uri().path().to_owned();
+    let files = ServeDir::new(&*dir).precompressed_br().precompressed_gzip();
+    match files.oneshot(req).await {
+        Ok(res) => cache(&path, res.map(boxed)),
+        Err(e) => {
+            error!(%path, error = %e, "failed to serve static file");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(feature = "embed")]
+fn serve_embedded<B>(
+    files: &'static include_dir::Dir<'static>,
+    req: &Request<B>,
+) -> axum::response::Response {
+    let path = entry(req.uri().path())
+        .map_or_else(|| req.uri().path().to_owned(), |uri| uri.path().to_owned());
+    let mut name = path.trim_start_matches('/').to_owned();
+    if name.is_empty() || name.ends_with('/') {
+        name.push_str("index.html");
+    }
+    let Some(mut file) = files.get_file(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut encoding = None;
+    for (accept, extension) in [("br", "br"), ("gzip", "gz")] {
+        if !accepts(req.headers(), accept) {
+            continue;
+        }
+        if let Some(compressed) = files.get_file(format!("{name}.{extension}")) {
+            file = compressed;
+            encoding = Some(accept);
+            break;
+        }
+    }
+
+    // there's no modification time to go by, so use the contents instead
+    let etag = {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        file.contents().hash(&mut hasher);
+        HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish())).unwrap()
+    };
+    let mut res = if req.headers().get(header::IF_NONE_MATCH) == Some(&etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mime = mime_guess::from_path(&name).first_or_octet_stream();
+        let mut res = (
+            [(header::CONTENT_TYPE, mime.as_ref())],
+            axum::body::Full::from(axum::body::Bytes::from_static(file.contents())),
+        )
+            .into_response();
+        if let Some(encoding) = encoding {
+            res.headers_mut()
+                .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        res
+    };
+    res.headers_mut().insert(header::ETAG, etag);
+    cache(&path, res)
+}
+
+/// Whether `Accept-Encoding` allows `encoding`.
+#[cfg(feature = "embed")]
+fn accepts(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|e| {
+            let mut e = e.split(';');
+            let name = e.next().unwrap_or_default().trim();
+            let q = e
+                .next()
+                .and_then(|q| q.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok());
+            name == encoding && q.is_some_and(|q| q > 0.0)
+        })
+}
+
+/// Move `br` to the front of `Accept-Encoding`.
+///
+/// Browsers list it after `gzip`, and `ServeDir` picks the first of the equally acceptable
+/// encodings, even though Brotli files are smaller.
+fn prefer_brotli(headers: &mut HeaderMap) {
+    let Some(accept) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return;
+    };
+    let (br, rest): (Vec<_>, Vec<_>) = accept
+        .split(',')
+        .map(str::trim)
+        .partition(|e| e.split(';').next().map(str::trim) == Some("br"));
+    let accept = br.into_iter().chain(rest).collect::<Vec<_>>().join(", ");
+    if let Ok(accept) = HeaderValue::from_str(&accept) {
+        headers.insert(header::ACCEPT_ENCODING, accept);
+    }
+}
+
+/// Add the caching headers for the file at `path`.
+fn cache<B>(path: &str, mut res: Response<B>) -> Response<B> {
+    if res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED {
+        let headers = res.headers_mut();
+        headers.insert(
+            header::CACHE_CONTROL,
+            if immutable(path) {
+                IMMUTABLE
+            } else {
+                REVALIDATE
+            },
+        );
+        // which file is sent depends on the encodings the client accepts
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::fs;
+    use uuid::Uuid;
+
+    async fn get(dir: &Path, path: &str, encoding: Option<&str>) -> Response<axum::body::BoxBody> {
+        let mut req = Request::get(path);
+        if let Some(encoding) = encoding {
+            req = req.header(header::ACCEPT_ENCODING, encoding);
+        }
+        serve(Arc::from(dir), req.body(Body::empty()).unwrap()).await
+    }
+
+    async fn body(res: Response<axum::body::BoxBody>) -> String {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves() {
+        let dir = std::env::temp_dir().join(format!("wewerewondering-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("assets")).unwrap();
+        fs::write(dir.join("index.html"), "index").unwrap();
+        fs::write(dir.join("favicon.png"), "icon").unwrap();
+        fs::write(dir.join("assets/index-4ed993c7.js"), "js").unwrap();
+        fs::write(dir.join("assets/index-4ed993c7.js.br"), "js.br").unwrap();
+        fs::write(dir.join("assets/index-4ed993c7.js.gz"), "js.gz").unwrap();
+
+        // client routes get the entry point, which must always be revalidated
+        for path in ["/", "/event/abc/secret"] {
+            let res = get(&dir, path, None).await;
+            assert_eq!(res.status(), StatusCode::OK, "{path}");
+            assert_eq!(res.headers()[header::CACHE_CONTROL], "no-cache", "{path}");
+            assert_eq!(body(res).await, "index", "{path}");
+        }
+        let res = get(&dir, "/favicon.png", None).await;
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "no-cache");
+
+        // but other unknown paths don't
+        let res = get(&dir, "/nope", None).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = get(&dir, "/assets/index-00000000.js", None).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(!res.headers().contains_key(header::CACHE_CONTROL));
+
+        // hashed assets are immutable, and come in the best encoding the client accepts
+        for (encoding, expected) in [
+            (None, "js"),
+            (Some("gzip"), "js.gz"),
+            (Some("gzip, deflate, br"), "js.br"),
+            (Some("gzip, br;q=0.5"), "js.gz"),
+        ] {
+            let res = get(&dir, "/assets/index-4ed993c7.js", encoding).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers()[header::CACHE_CONTROL],
+                "public, max-age=31536000, immutable"
+            );
+            assert_eq!(res.headers()[header::VARY], "accept-encoding");
+            assert_eq!(res.headers()[header::CONTENT_TYPE], "text/javascript");
+            assert_eq!(body(res).await, expected, "{encoding:?}");
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "embed")]
+    #[tokio::test]
+    async fn embedded() {
+        use include_dir::{Dir, DirEntry, File};
+        static ASSETS: [DirEntry<'static>; 2] = [
+            DirEntry::File(File::new("assets/index-4ed993c7.js", b"js")),
+            DirEntry::File(File::new("assets/index-4ed993c7.js.br", b"js.br")),
+        ];
+        static CLIENT: [DirEntry<'static>; 2] = [
+            DirEntry::File(File::new("index.html", b"index")),
+            DirEntry::Dir(Dir::new("assets", &ASSETS)),
+        ];
+        static FILES: Dir<'static> = Dir::new("", &CLIENT);
+        let get = |path: &str, encoding: Option<&str>| {
+            let mut req = Request::get(path);
+            if let Some(encoding) = encoding {
+                req = req.header(header::ACCEPT_ENCODING, encoding);
+            }
+            serve_embedded(&FILES, &req.body(()).unwrap())
+        };
+
+        let res = get("/event/abc/secret", None);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "no-cache");
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/html");
+        let etag = res.headers()[header::ETAG].clone();
+        assert_eq!(body(res).await, "index");
+        let res = serve_embedded(
+            &FILES,
+            &Request::get("/")
+                .header(header::IF_NONE_MATCH, etag)
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        assert_eq!(get("/nope", None).status(), StatusCode::NOT_FOUND);
+
+        for (encoding, expected) in [
+            (None, "js"),
+            (Some("gzip"), "js"),
+            (Some("gzip, deflate, br"), "js.br"),
+            (Some("br;q=0"), "js"),
+        ] {
+            let res = get("/assets/index-4ed993c7.js", encoding);
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers()[header::CACHE_CONTROL],
+                "public, max-age=31536000, immutable"
+            );
+            assert_eq!(res.headers()[header::CONTENT_TYPE], "text/javascript");
+            assert_eq!(
+                res.headers().get(header::CONTENT_ENCODING).is_some(),
+                expected == "js.br",
+                "{encoding:?}"
+            );
+            assert_eq!(body(res).await, expected, "{encoding:?}");
+        }
+    }
+}
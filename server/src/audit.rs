@@ -0,0 +1,291 @@
+//! Keeping a record of who changed what, for deployments that have to be able to show it.
+//!
+//! Regulated customers sometimes need to demonstrate who moderated what, and when. With `AUDIT`
+//! set to a percentage, that share of the requests that change something through host and
+//! operator routes (toggling questions, announcing, closing, freezing, holds, and so on) is
+//! recorded in the [blob store](super::blobs): when, which route and event, how it went and how
+//! long it took, whether it was the host or an operator, the request id, and where it came from.
+//! Bodies aren't recorded, and neither is the path as it was sent, since host paths carry the
+//! event's secret.
+//!
+//! Operators can export the records with `GET /api/admin/audit?since=<s>&until=<s>` (in seconds
+//! since the epoch, both optional). Records are kept for `AUDIT_DAYS`, after which the `retention`
+//! command (with `--apply`) deletes them, and exports leave them out.
+//!
+//! A request is recorded before its response goes out, so a record that can't be stored doesn't
+//! get lost with a Lambda that's frozen right after, but it doesn't fail the request either. Such
+//! failures are logged and counted as `audit.failed`.
+
+use super::{admin::Admin, blobs::BlobStore};
+use axum::{
+    extract::{MatchedPath, Path, Query, State},
+    http::Request,
+    middleware::Next,
+    response::{Json, Response},
+};
+use bytes::Bytes;
+use http::{header, Method, StatusCode};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Instant, SystemTime},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The operator routes that change things, on top of all the host routes.
+const OPERATOR_ROUTES: &[&str] = &["moderate", "set_hold", "retention", "warm"];
+
+/// A request, as it's recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    /// When the request came in, in milliseconds since the epoch.
+    at: u64,
+    route: String,
+    method: String,
+    /// The route's path, like `/api/event/:eid/close/:secret`.
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eid: Option<Uuid>,
+    /// `operator` or `host`.
+    by: String,
+    status: u16,
+    ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user_agent: Option<String>,
+}
+
+fn now_ms() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Where the record of a request that came in `at` is kept.
+///
+/// Keys start with the time, so that what's too old can be told from the key alone.
+fn key(at: u64) -> String {
+    format!("audit/{at:015}-{}.json", Uuid::new_v4().simple())
+}
+
+/// When the record kept at `key` was made, in milliseconds since the epoch.
+fn made(key: &str) -> Option<u64> {
+    key.strip_prefix("audit/")?.split_once('-')?.0.parse().ok()
+}
+
+/// The oldest a record can be, in milliseconds since the epoch, and still be kept.
+fn cutoff(now: u64) -> u64 {
+    now.saturating_sub(super::config::config().audit_days * 24 * 60 * 60 * 1000)
+}
+
+/// Which route a request is for, so [`record`] knows whether to record it.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Audit {
+    route: &'static str,
+    operator: bool,
+}
+
+pub(super) fn route(route: &'static str) -> Audit {
+    Audit {
+        route,
+        operator: OPERATOR_ROUTES.contains(&route),
+    }
+}
+
+impl Audit {
+    fn audited(&self, method: &Method) -> bool {
+        // reads don't change anything
+        let changes = !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+        changes && (self.operator || super::shed::Class::of(self.route) == super::shed::Class::Host)
+    }
+}
+
+/// Record the request, if it's one that's audited and it's sampled.
+pub(super) async fn record<B>(
+    State(audit): State<Audit>,
+    path: Option<MatchedPath>,
+    params: Option<Path<HashMap<String, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let share = super::config::config().audit;
+    let sampled = share != 0 && rand::thread_rng().gen_range(0..100) < share;
+    if !sampled || !audit.audited(req.method()) {
+        return next.run(req).await;
+    }
+
+    let headers = req.headers();
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let mut record = Record {
+        at: now_ms(),
+        route: audit.route.to_string(),
+        method: req.method().to_string(),
+        path: path.map(|p| p.as_str().to_string()).unwrap_or_default(),
+        eid: params.and_then(|Path(params)| Uuid::parse_str(params.get("eid")?).ok()),
+        by: String::from(if audit.operator { "operator" } else { "host" }),
+        status: 0,
+        ms: 0,
+        request_id: header("x-request-id"),
+        client: super::networks::client(&req).map(|ip| ip.to_string()),
+        user_agent: header(header::USER_AGENT.as_str()),
+    };
+    let start = Instant::now();
+    let res = next.run(req).await;
+    record.status = res.status().as_u16();
+    record.ms = start.elapsed().as_millis() as u64;
+
+    let data = Bytes::from(serde_json::to_vec(&record).expect("records always serialize"));
+    let store = super::blobs::store().await;
+    if let Err(e) = store
+        .put_bytes(&key(record.at), "application/json", data)
+        .await
+    {
+        error!(route = audit.route, error = %e, "failed to store audit record");
+        super::metrics::incr("audit.failed");
+    }
+    res
+}
+
+/// Delete the records that are older than `AUDIT_DAYS`, or only count them unless `apply`.
+pub(super) async fn prune(store: &BlobStore, apply: bool) -> Result<usize, String> {
+    let keys = store.list("audit").await.map_err(|e| e.to_string())?;
+    let cutoff = cutoff(now_ms());
+    let mut expired = 0;
+    for key in keys {
+        if made(&key).is_none_or(|at| at >= cutoff) {
+            continue;
+        }
+        expired += 1;
+        if apply {
+            store.delete(&key).await.map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(expired)
+}
+
+/// Which records to export, in seconds since the epoch.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Span {
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+/// The records of requests made between `since` and `until`, oldest first.
+async fn records(store: &BlobStore, span: &Span) -> Result<Vec<Record>, String> {
+    let since = span
+        .since
+        .map_or(0, |s| s.saturating_mul(1000))
+        .max(cutoff(now_ms()));
+    let until = span.until.map_or(u64::MAX, |s| s.saturating_mul(1000));
+    let mut keys: Vec<_> = store
+        .list("audit")
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|key| made(key).is_some_and(|at| at >= since && at < until))
+        .collect();
+    keys.sort_unstable();
+    let mut records = Vec::with_capacity(keys.len());
+    for key in keys {
+        let Some(blob) = store.get(&key).await.map_err(|e| e.to_string())? else {
+            // pruned since it was listed
+            continue;
+        };
+        match serde_json::from_slice(&blob.data) {
+            Ok(record) => records.push(record),
+            Err(e) => error!(key, error = %e, "found malformed audit record"),
+        }
+    }
+    Ok(records)
+}
+
+/// The audit records, for operators.
+pub(super) async fn export(
+    _: Admin,
+    Query(span): Query<Span>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let records = records(super::blobs::store().await, &span)
+        .await
+        .map_err(|e| {
+            error!(error = e, "failed to export audit records");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(serde_json::json!({ "records": records })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blobs::Location;
+
+    fn at(at: u64) -> Record {
+        Record {
+            at,
+            route: String::from("close"),
+            method: String::from("POST"),
+            path: String::from("/api/event/:eid/close/:secret"),
+            eid: Some(Uuid::new_v4()),
+            by: String::from("host"),
+            status: 200,
+            ms: 3,
+            request_id: None,
+            client: Some(String::from("192.0.2.7")),
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn what_is_audited() {
+        assert!(route("close").audited(&Method::POST));
+        assert!(route("moderate").audited(&Method::POST));
+        // reads, and what attendees do, aren't
+        assert!(!route("moderate").audited(&Method::GET));
+        assert!(!route("ask").audited(&Method::POST));
+        assert_eq!(made(&key(1234)), Some(1234));
+    }
+
+    #[tokio::test]
+    async fn exports_and_prunes() {
+        let store = BlobStore::open(&Location::Memory).await;
+        let now = now_ms();
+        let old = cutoff(now) - 1;
+        for record in [at(now - 2000), at(now - 1000), at(old)] {
+            let data = Bytes::from(serde_json::to_vec(&record).unwrap());
+            store
+                .put_bytes(&key(record.at), "application/json", data)
+                .await
+                .unwrap();
+        }
+
+        let all = records(&store, &Span::default()).await.unwrap();
+        assert_eq!(
+            all.iter().map(|r| r.at).collect::<Vec<_>>(),
+            [now - 2000, now - 1000],
+            "too old to keep, so left out"
+        );
+        let span = Span {
+            since: Some((now - 1500) / 1000),
+            until: None,
+        };
+        let recent = records(&store, &span).await.unwrap();
+        assert!(recent.iter().all(|r| r.at >= (now - 1500) / 1000 * 1000));
+
+        assert_eq!(prune(&store, false).await.unwrap(), 1);
+        assert_eq!(store.list("audit").await.unwrap().len(), 3);
+        assert_eq!(prune(&store, true).await.unwrap(), 1);
+        assert_eq!(store.list("audit").await.unwrap().len(), 2);
+    }
+}
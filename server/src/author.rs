@@ -0,0 +1,650 @@
+//! Letting askers fix or take back their own questions.
+//!
+//! Questions don't know who asked them, but askers have the question's `receipt` (see
+//! [`super::mine`]), which only the event's secret can make, and may have asked with an author
+//! token (`X-Author`). So with either, an asker can fix a typo with
+//! `POST /api/event/:eid/edit/:qid` and `{"receipt": "<receipt>", "body": ".."}` (or just
+//! `{"body": ".."}` and the token) within `EDIT_WITHIN` of asking, and take a question back at any
+//! time with `POST /api/event/:eid/retract/:qid` and `{"receipt": "<receipt>"}` (or `{}` and the
+//! token). Both are a 401 if neither checks out.
+//!
+//! Edits go into the change log as `question_edited`, so clients that already have the old text
+//! know to fetch it again. Until the window closes, a question's text isn't cached for long (see
+//! [`super::questions`] and [`super::texts`]), so what they fetch is the edit. Questions that have
+//! been answered or hidden can't be edited (hidden ones can be [revised](super::revise) instead),
+//! and neither can questions of events that aren't taking questions. DynamoDB and the in-memory
+//! backend also check all of that as they make the edit, along with the text still being what the
+//! asker edited, so a question that's answered, hidden, or edited again in the meantime isn't
+//! edited after all.
+//!
+//! The texts a question had before are kept on it as its `history`, oldest first, and only the
+//! original and the latest `MAX_HISTORY` after it are kept. Attendees just see that a question was
+//! `edited`, and hosts can see what it used to say with
+//! `GET /api/event/:eid/questions/:secret/:qid/history`.
+//!
+//! Retracting a question hides it with the reason `retracted`, so it's still in the host's list
+//! (as a hidden question), and the host can unhide it if it should stay after all. Questions that
+//! are already hidden, or answered, are left as they are.
+
+use super::{changes::Change, mine::Reason, toggle::Property, Backend, Local};
+use aws_sdk_dynamodb::{
+    error::{ConditionalCheckFailedException, UpdateItemError, UpdateItemErrorKind},
+    model::AttributeValue,
+    types::SdkError,
+};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// For how many seconds after asking a question its asker can still edit it.
+pub(super) const EDIT_WITHIN: u64 = 5 * 60;
+
+/// How many of a question's earlier texts are kept besides the original.
+const MAX_HISTORY: usize = 10;
+
+/// A text a question had before it was edited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Earlier {
+    text: String,
+    /// When it was replaced, in seconds since the epoch.
+    until: u64,
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Whether the question `q` may still be edited by its asker (as far as its age goes).
+pub(super) fn editable(q: &HashMap<String, AttributeValue>) -> bool {
+    q.get("when")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .is_some_and(|when| when + EDIT_WITHIN > now())
+}
+
+/// The texts `q` had before, oldest first.
+pub(super) fn history(q: &HashMap<String, AttributeValue>) -> Vec<Earlier> {
+    q.get("history")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| serde_json::from_str(v).ok())
+        .unwrap_or_default()
+}
+
+/// `history` with `previous` added to the end, leaving out the oldest edits beyond `MAX_HISTORY`.
+fn amend(mut history: Vec<Earlier>, previous: &str, until: u64) -> Vec<Earlier> {
+    history.push(Earlier {
+        text: previous.to_string(),
+        until,
+    });
+    if history.len() > MAX_HISTORY + 1 {
+        // the original stays
+        history.remove(1);
+    }
+    history
+}
+
+impl Backend {
+    /// Replace the text of `qid`, which is `previous`, with `body`, and its history with
+    /// `history`, as long as it was asked no earlier than `since` (in seconds since the epoch) and
+    /// is neither answered nor hidden.
+    ///
+    /// DynamoDB and the in-memory backend refuse edits to questions that don't qualify (any more)
+    /// with a `ConditionalCheckFailedException`. The other backends leave that to the caller.
+    pub(super) async fn edit(
+        &self,
+        qid: &Uuid,
+        previous: &str,
+        body: String,
+        history: &[Earlier],
+        since: u64,
+    ) -> Result<(), SdkError<UpdateItemError>> {
+        let history = serde_json::to_string(history).expect("history serializes");
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression("SET #text = :text, #history = :history")
+                    .expression_attribute_names("#text", "text")
+                    .expression_attribute_names("#history", "history")
+                    .expression_attribute_values(":text", AttributeValue::S(body))
+                    .expression_attribute_values(":history", AttributeValue::S(history));
+                // see Backend::record (so with alternator, the caller's checks are all there is)
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression(
+                        "attribute_exists(id) AND #when >= :since \
+                         AND answered = :no AND hidden = :no AND #text = :previous",
+                    )
+                    .expression_attribute_names("#when", "when")
+                    .expression_attribute_values(":since", AttributeValue::N(since.to_string()))
+                    .expression_attribute_values(":no", AttributeValue::Bool(false))
+                    .expression_attribute_values(
+                        ":previous",
+                        AttributeValue::S(previous.to_string()),
+                    )
+                };
+                upd.send().await?;
+                Ok(())
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                let qualifies = |q: &HashMap<&'static str, AttributeValue>| {
+                    let when = q
+                        .get("when")
+                        .and_then(|v| v.as_n().ok())
+                        .and_then(|v| v.parse::<u64>().ok());
+                    let no = |k| q.get(k).and_then(|v| v.as_bool().ok()) == Some(&false);
+                    let text = q.get("text").and_then(|v| v.as_s().ok());
+                    when.is_some_and(|when| when >= since)
+                        && no("answered")
+                        && no("hidden")
+                        && text.is_some_and(|t| t == previous)
+                };
+                let Some(q) = questions.get_mut(qid).filter(|q| qualifies(q)) else {
+                    return Err(super::mint_service_error(UpdateItemError::new(
+                        UpdateItemErrorKind::ConditionalCheckFailedException(
+                            ConditionalCheckFailedException::builder()
+                                .message("editing question that can't be edited")
+                                .build(),
+                        ),
+                        Error::builder()
+                            .code("ConditionalCheckFailedException")
+                            .build(),
+                    )));
+                };
+                q.insert("text", AttributeValue::S(body));
+                q.insert("history", AttributeValue::S(history));
+                journal.question(qid, q);
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                mongo
+                    .update::<UpdateItemError>(
+                        qid,
+                        mongodb::bson::doc! { "$set": { "text": body, "history": history } },
+                    )
+                    .await?;
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                redis
+                    .set::<UpdateItemError>(
+                        qid,
+                        &[
+                            ("text", AttributeValue::S(body)),
+                            ("history", AttributeValue::S(history)),
+                        ],
+                    )
+                    .await?;
+                Ok(())
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                sled.set::<UpdateItemError>(
+                    qid,
+                    &[
+                        ("text", AttributeValue::S(body)),
+                        ("history", AttributeValue::S(history)),
+                    ],
+                )
+                .await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Edit {
+    #[serde(default)]
+    receipt: Option<String>,
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Retraction {
+    #[serde(default)]
+    receipt: Option<String>,
+}
+
+/// The question `qid` of `eid`, if the asker's `receipt`, or the author token in `headers`, checks
+/// out.
+async fn own(
+    dynamo: &Backend,
+    eid: &Uuid,
+    qid: &Uuid,
+    receipt: Option<&str>,
+    headers: &HeaderMap,
+) -> Result<(super::Meta, HashMap<String, AttributeValue>), StatusCode> {
+    let meta = super::get_meta(dynamo, eid).await?;
+    let by_receipt = receipt.is_some_and(|r| super::mine::checks_out(&meta.secret, qid, r));
+    let author = super::mine::author(headers, &meta.secret);
+    if !by_receipt && author.is_none() {
+        warn!(%eid, %qid, "attempted to change question with incorrect receipt");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let q = dynamo.questions(&[*qid]).await.map_err(|e| {
+        error!(%eid, %qid, error = %e, "dynamodb request for asker's question failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let q = q
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .and_then(|qs| qs.first())
+        .filter(|q| q.get("eid").and_then(|v| v.as_s().ok()) == Some(&eid.to_string()))
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let by_author = match (q.get("author").and_then(|v| v.as_s().ok()), &author) {
+        (Some(theirs), Some(author)) => super::mine::same(theirs, author),
+        _ => false,
+    };
+    if !by_receipt && !by_author {
+        warn!(%eid, %qid, "attempted to change question with incorrect author token");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok((meta, q))
+}
+
+fn is_set(q: &HashMap<String, AttributeValue>, property: &str) -> bool {
+    q.get(property).and_then(|v| v.as_bool().ok()) == Some(&true)
+}
+
+pub(super) async fn edit(
+    Path((eid, qid)): Path<(Uuid, Uuid)>,
+    State(dynamo): State<Backend>,
+    headers: HeaderMap,
+    Json(edit): Json<Edit>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::ask::screen(&eid, &edit.body)?;
+    let (meta, q) = own(&dynamo, &eid, &qid, edit.receipt.as_deref(), &headers).await?;
+    super::ask::taking(&eid, &meta)?;
+    if is_set(&q, "answered") || is_set(&q, "hidden") {
+        debug!(%eid, %qid, "attempted to edit question that's been dealt with");
+        return Err(StatusCode::CONFLICT);
+    }
+    if !editable(&q) {
+        debug!(%eid, %qid, "attempted to edit question too late");
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let previous = q.get("text").and_then(|v| v.as_s().ok()).ok_or_else(|| {
+        error!(%eid, %qid, "question has no text");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if *previous == edit.body {
+        return Ok(Json(serde_json::json!({ "id": qid.to_string() })));
+    }
+    let history = amend(history(&q), previous, now());
+    let since = now().saturating_sub(EDIT_WITHIN);
+    match dynamo
+        .edit(&qid, previous, edit.body, &history, since)
+        .await
+    {
+        Ok(()) => {}
+        Err(SdkError::ServiceError { ref err, .. })
+            if err.is_conditional_check_failed_exception() =>
+        {
+            debug!(%eid, %qid, "question was dealt with or edited while it was being edited");
+            return Err(StatusCode::CONFLICT);
+        }
+        Err(e) => {
+            error!(%eid, %qid, error = %e, "dynamodb request to edit question failed");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    super::texts::forget(&qid);
+    debug!(%eid, %qid, "edited question");
+    dynamo
+        .try_record(&eid, Change::QuestionEdited { qid })
+        .await;
+    Ok(Json(serde_json::json!({ "id": qid.to_string() })))
+}
+
+pub(super) async fn retract(
+    Path((eid, qid)): Path<(Uuid, Uuid)>,
+    State(dynamo): State<Backend>,
+    headers: HeaderMap,
+    Json(retraction): Json<Retraction>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let (meta, q) = own(&dynamo, &eid, &qid, retraction.receipt.as_deref(), &headers).await?;
+    if meta.frozen() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if is_set(&q, "answered") || is_set(&q, "hidden") {
+        debug!(%eid, %qid, "attempted to retract question that's been dealt with");
+        return Err(StatusCode::CONFLICT);
+    }
+    super::toggle::apply(
+        &dynamo,
+        &eid,
+        qid,
+        Property::Hidden,
+        true,
+        Some(Reason::Retracted),
+    )
+    .await?;
+    info!(%eid, %qid, "asker retracted question");
+    Ok(Json(serde_json::json!({ "id": qid.to_string() })))
+}
+
+/// What question `qid` of `eid` said before it was edited, for its hosts.
+pub(super) async fn earlier(
+    Path((eid, secret, qid)): Path<(Uuid, String, Uuid)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(&dynamo, &eid, &secret).await?;
+    let q = dynamo.questions(&[qid]).await.map_err(|e| {
+        error!(%eid, %qid, error = %e, "dynamodb request for question history failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let q = q
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .and_then(|qs| qs.first())
+        .filter(|q| q.get("eid").and_then(|v| v.as_s().ok()) == Some(&eid.to_string()))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(serde_json::json!({
+        "text": q.get("text").and_then(|v| v.as_s().ok()),
+        "history": history(q),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_original() {
+        let mut history = Vec::new();
+        for i in 0..(MAX_HISTORY + 5) {
+            history = amend(history, &format!("take {i}"), i as u64);
+        }
+        assert_eq!(history.len(), MAX_HISTORY + 1);
+        assert_eq!(history[0].text, "take 0");
+        assert_eq!(history[1].text, "take 5");
+        assert_eq!(
+            history.last().unwrap().text,
+            format!("take {}", MAX_HISTORY + 4)
+        );
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let token = |t: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(crate::mine::AUTHOR, t.parse().unwrap());
+            headers
+        };
+        let ask = |body: &str, headers: HeaderMap| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                headers,
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+        };
+        let q = ask("what abut the thing?", HeaderMap::new()).await.unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let receipt = q["receipt"].as_str().unwrap().to_string();
+        let item = || async {
+            let qs = backend.questions(&[qid]).await.unwrap();
+            qs.responses().unwrap()["questions"][0].clone()
+        };
+        let text = || async { item().await["text"].as_s().unwrap().clone() };
+        let edit = |qid: Uuid, receipt: Option<&str>, headers: HeaderMap, body: &str| {
+            super::edit(
+                Path((eid, qid)),
+                State(backend.clone()),
+                headers,
+                Json(Edit {
+                    receipt: receipt.map(String::from),
+                    body: body.to_string(),
+                }),
+            )
+        };
+
+        // only by the asker
+        assert_eq!(
+            edit(qid, Some("nope"), HeaderMap::new(), "what about the thing?")
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            edit(qid, None, token("someone"), "what about the thing?")
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            edit(qid, Some(&receipt), HeaderMap::new(), "")
+                .await
+                .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let q = item().await;
+        assert!(history(&q).is_empty());
+        assert!(crate::questions::to_json(&q)
+            .unwrap()
+            .1
+            .get("edited")
+            .is_none());
+        edit(
+            qid,
+            Some(&receipt),
+            HeaderMap::new(),
+            "what about the thing?",
+        )
+        .await
+        .unwrap();
+        assert_eq!(text().await, "what about the thing?");
+        let q = item().await;
+        assert!(editable(&q));
+        assert_eq!(crate::questions::to_json(&q).unwrap().1["edited"], true);
+        let changes = backend.changes(&eid, 0).await.unwrap();
+        let last = changes.items().unwrap().last().unwrap();
+        assert_eq!(
+            crate::changes::Change::from_item(last),
+            Some(Change::QuestionEdited { qid })
+        );
+
+        // hosts can see what it used to say
+        let earlier = |secret: &str| {
+            super::earlier(Path((eid, secret.to_string(), qid)), State(backend.clone()))
+        };
+        assert_eq!(earlier("nope").await.unwrap_err(), StatusCode::UNAUTHORIZED);
+        let Json(h) = earlier(&secret).await.unwrap();
+        assert_eq!(h["text"], "what about the thing?");
+        assert_eq!(h["history"].as_array().unwrap().len(), 1);
+        assert_eq!(h["history"][0]["text"], "what abut the thing?");
+
+        // some backends check too
+        if matches!(backend, Backend::Dynamo(_) | Backend::Local(_)) {
+            let err = backend
+                .edit(
+                    &qid,
+                    "what about the thing?",
+                    String::from("too late now"),
+                    &[],
+                    now() + 60,
+                )
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                SdkError::ServiceError { ref err, .. } if err.is_conditional_check_failed_exception()
+            ));
+            // including that nobody else edited it first
+            let err = backend
+                .edit(
+                    &qid,
+                    "what abut the thing?",
+                    String::from("what about it?"),
+                    &[],
+                    0,
+                )
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                SdkError::ServiceError { ref err, .. } if err.is_conditional_check_failed_exception()
+            ));
+        }
+
+        // an author token works just as well as a receipt, but only for its own questions
+        let q = ask("by who?", token("me")).await.unwrap();
+        let mine = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            edit(mine, None, token("you"), "by whom?")
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            edit(qid, None, token("me"), "mine now").await.unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        edit(mine, None, token("me"), "by whom?").await.unwrap();
+        edit(mine, None, token("me"), "by whom, then?")
+            .await
+            .unwrap();
+        let Json(h) = super::earlier(Path((eid, secret.clone(), mine)), State(backend.clone()))
+            .await
+            .unwrap();
+        assert_eq!(h["text"], "by whom, then?");
+        let texts: Vec<_> = h["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(texts, ["by who?", "by whom?"]);
+
+        // taking it back hides it, for good reason
+        let retract = |qid: Uuid, receipt: Option<&str>, headers: HeaderMap| {
+            super::retract(
+                Path((eid, qid)),
+                State(backend.clone()),
+                headers,
+                Json(Retraction {
+                    receipt: receipt.map(String::from),
+                }),
+            )
+        };
+        assert_eq!(
+            retract(qid, Some("nope"), HeaderMap::new())
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        retract(qid, Some(&receipt), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            retract(qid, Some(&receipt), HeaderMap::new())
+                .await
+                .unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        retract(mine, None, token("me")).await.unwrap();
+        let Json(mine) = crate::mine::mine(
+            Path(eid),
+            State(backend.clone()),
+            Json(HashMap::from_iter([(qid, receipt.clone())])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            mine,
+            serde_json::json!({ qid.to_string(): { "hidden": true, "reason": "retracted" } })
+        );
+        // and then it's too late to edit
+        assert_eq!(
+            edit(
+                qid,
+                Some(&receipt),
+                HeaderMap::new(),
+                "what about the other thing?"
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::CONFLICT
+        );
+
+        // questions from another event are nobody's
+        let other = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let other = Uuid::parse_str(other["id"].as_str().unwrap()).unwrap();
+        let meta = crate::get_meta(&backend, &other).await.unwrap();
+        assert_eq!(
+            super::retract(
+                Path((other, qid)),
+                State(backend.clone()),
+                HeaderMap::new(),
+                Json(Retraction {
+                    receipt: Some(crate::mine::receipt(&meta.secret, &qid)),
+                }),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        backend.delete(&eid).await;
+        backend.delete(&other).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,669 @@
+//! Storing files outside of the database, like snapshots of public archives.
+//!
+//! `BLOB_STORE` says where they go: an S3 bucket (`s3://<bucket>`, in the region and with the
+//! credentials the rest of the AWS configuration uses), a directory (`file://<dir>`), or, if it's
+//! unset, memory. Memory is what local development and tests get, and is fine for anything that
+//! can be made again, since each Lambda instance only has its own.
+//!
+//! Whatever the store, what goes in it is held to the same rules: keys are plain paths, files are
+//! at most `MAX_SIZE` bytes, and only a few content types are allowed, which the start of each
+//! file has to match. Uploads are streamed through to the store (S3 needs to know their length
+//! up front, so everyone does), and are checked before any of them is stored.
+
+use aws_sigv4::http_request::{
+    sign, PayloadChecksumKind, PercentEncodingMode, SignableBody, SignableRequest, SigningParams,
+    SigningSettings,
+};
+use aws_types::credentials::{ProvideCredentials, SharedCredentialsProvider};
+use axum::BoxError;
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use http::{header, Method, Request, StatusCode};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The largest file that can be stored, in bytes.
+pub(super) const MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The content types files can have.
+const ALLOWED: &[&str] = &[
+    "application/json",
+    "text/plain",
+    "text/html",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+];
+
+/// How much of the start of a file is checked against its content type.
+const SNIFF: usize = 16;
+
+/// Where files are stored, as configured by `BLOB_STORE`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) enum Location {
+    S3(String),
+    Dir(PathBuf),
+    #[default]
+    Memory,
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(bucket) = s.strip_prefix("s3://") {
+            let bucket = bucket.trim_end_matches('/');
+            if bucket.is_empty() || bucket.contains('/') {
+                return Err(format!("`{s}` isn't a bucket"));
+            }
+            Ok(Location::S3(bucket.to_string()))
+        } else if let Some(dir) = s.strip_prefix("file://") {
+            Ok(Location::Dir(PathBuf::from(dir)))
+        } else if s == "memory" {
+            Ok(Location::Memory)
+        } else {
+            Err(format!(
+                "`{s}` isn't s3://<bucket>, file://<dir>, or memory"
+            ))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum BlobError {
+    /// The key isn't a plain path.
+    Key,
+    /// Files of this content type can't be stored.
+    ContentType(String),
+    /// The file doesn't look like its content type says it is.
+    Mismatch,
+    /// The file is larger than `MAX_SIZE`, or than it said it would be.
+    TooLarge,
+    /// The file is smaller than it said it would be.
+    Truncated,
+    /// Reading the file that was being stored failed.
+    Body(String),
+    Io(io::Error),
+    S3(String),
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::Key => f.write_str("invalid key"),
+            BlobError::ContentType(t) => write!(f, "content type `{t}` isn't allowed"),
+            BlobError::Mismatch => f.write_str("contents don't match the content type"),
+            BlobError::TooLarge => f.write_str("too large"),
+            BlobError::Truncated => f.write_str("shorter than its length"),
+            BlobError::Body(e) => write!(f, "failed to read body: {e}"),
+            BlobError::Io(e) => e.fmt(f),
+            BlobError::S3(e) => write!(f, "s3: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+impl From<io::Error> for BlobError {
+    fn from(e: io::Error) -> Self {
+        BlobError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Blob {
+    pub(super) content_type: String,
+    pub(super) data: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct S3 {
+    client: hyper::Client<aws_smithy_client::conns::Https>,
+    bucket: String,
+    region: String,
+    credentials: SharedCredentialsProvider,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum BlobStore {
+    S3(Box<S3>),
+    Dir(Arc<Path>),
+    Memory(Arc<Mutex<HashMap<String, Blob>>>),
+}
+
+/// The configured store.
+pub(super) async fn store() -> &'static BlobStore {
+    // tokio's cell can't be made in a const without parking_lot, but it can be opened async
+    static STORE: OnceLock<OnceCell<BlobStore>> = OnceLock::new();
+    STORE
+        .get_or_init(OnceCell::new)
+        .get_or_init(|| BlobStore::open(&super::config::config().blob_store))
+        .await
+}
+
+/// Whether `key` is a plain path, like `archives/<id>.json`.
+fn plain(key: &str) -> bool {
+    key.len() <= 256
+        && key.split('/').all(|part| {
+            !part.is_empty()
+                && part != "."
+                && part != ".."
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        })
+}
+
+/// Whether a file that starts with `head` could be of `content_type`.
+fn looks_like(content_type: &str, head: &[u8]) -> bool {
+    match content_type {
+        "image/png" => head.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => head.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a"),
+        "image/webp" => head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP",
+        // text can be cut off in the middle of a character
+        _ => std::str::from_utf8(head).map_or_else(|e| e.error_len().is_none(), |_| true),
+    }
+}
+
+impl BlobStore {
+    /// Open the store at `location`.
+    pub(super) async fn open(location: &Location) -> Self {
+        match location {
+            Location::S3(bucket) => {
+                let aws = aws_config::load_from_env().await;
+                BlobStore::S3(Box::new(S3 {
+                    client: hyper::Client::builder().build(aws_smithy_client::conns::https()),
+                    bucket: bucket.clone(),
+                    region: aws
+                        .region()
+                        .map_or_else(|| String::from("us-east-1"), |r| r.to_string()),
+                    credentials: aws
+                        .credentials_provider()
+                        .expect("AWS credentials are configured")
+                        .clone(),
+                }))
+            }
+            Location::Dir(dir) => BlobStore::Dir(Arc::from(dir.as_path())),
+            Location::Memory => BlobStore::Memory(Default::default()),
+        }
+    }
+
+    /// Store `length` bytes of `content_type` read from `body` as `key`, replacing what's there.
+    pub(super) async fn put<S, E>(
+        &self,
+        key: &str,
+        content_type: &str,
+        length: u64,
+        body: S,
+    ) -> Result<(), BlobError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        if !plain(key) {
+            return Err(BlobError::Key);
+        }
+        if !ALLOWED.contains(&content_type) {
+            return Err(BlobError::ContentType(content_type.to_string()));
+        }
+        if length > MAX_SIZE {
+            return Err(BlobError::TooLarge);
+        }
+
+        // check what the file starts with before any of it goes anywhere
+        let mut body = Box::pin(body.map_err(|e| BlobError::Body(e.into().to_string())));
+        let mut head = Vec::new();
+        let mut read = Vec::new();
+        while head.len() < SNIFF {
+            let Some(chunk) = body.next().await else {
+                break;
+            };
+            let chunk = chunk?;
+            head.extend_from_slice(&chunk);
+            read.push(chunk);
+        }
+        if !looks_like(content_type, &head[..head.len().min(SNIFF)]) {
+            return Err(BlobError::Mismatch);
+        }
+        let mut seen = 0;
+        let body = stream::iter(read.into_iter().map(Ok))
+            .chain(body)
+            .map(move |chunk| {
+                let chunk = chunk?;
+                seen += chunk.len() as u64;
+                if seen > length {
+                    Err(BlobError::TooLarge)
+                } else {
+                    Ok(chunk)
+                }
+            });
+
+        match self {
+            BlobStore::S3(s3) => {
+                let req = Request::put(s3.url(key))
+                    .header(header::CONTENT_TYPE, content_type)
+                    .header(header::CONTENT_LENGTH, length)
+                    .body(hyper::Body::wrap_stream(body))
+                    .expect("keys are plain paths");
+                s3.send(req, SignableBody::UnsignedPayload).await?;
+                Ok(())
+            }
+            BlobStore::Dir(dir) => {
+                let path = dir.join(key);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                // so that nobody ever reads half a file
+                let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+                let written = async {
+                    let mut out = std::fs::File::create(&tmp)?;
+                    writeln!(out, "{content_type}")?;
+                    let mut body = std::pin::pin!(body);
+                    let mut written = 0;
+                    while let Some(chunk) = body.next().await {
+                        let chunk = chunk?;
+                        out.write_all(&chunk)?;
+                        written += chunk.len() as u64;
+                    }
+                    if written == length {
+                        std::fs::rename(&tmp, &path)?;
+                        Ok(())
+                    } else {
+                        Err(BlobError::Truncated)
+                    }
+                }
+                .await;
+                if written.is_err() {
+                    let _ = std::fs::remove_file(&tmp);
+                }
+                written
+            }
+            BlobStore::Memory(blobs) => {
+                let data: Vec<Bytes> = body.try_collect().await?;
+                let data = Bytes::from(data.concat());
+                if data.len() as u64 != length {
+                    return Err(BlobError::Truncated);
+                }
+                blobs.lock().unwrap().insert(
+                    key.to_string(),
+                    Blob {
+                        content_type: content_type.to_string(),
+                        data,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Store `data` of `content_type` as `key`, replacing what's there.
+    pub(super) async fn put_bytes(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+    ) -> Result<(), BlobError> {
+        let length = data.len() as u64;
+        let body = stream::once(async move { Ok::<_, BoxError>(data) });
+        self.put(key, content_type, length, body).await
+    }
+
+    /// The file stored as `key`, if there is one.
+    pub(super) async fn get(&self, key: &str) -> Result<Option<Blob>, BlobError> {
+        if !plain(key) {
+            return Err(BlobError::Key);
+        }
+        match self {
+            BlobStore::S3(s3) => {
+                let req = Request::get(s3.url(key))
+                    .body(hyper::Body::empty())
+                    .expect("keys are plain paths");
+                let Some(res) = s3.send(req, SignableBody::Bytes(&[])).await? else {
+                    return Ok(None);
+                };
+                let content_type = res
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|t| t.to_str().ok())
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+                let data = hyper::body::to_bytes(res.into_body())
+                    .await
+                    .map_err(|e| BlobError::S3(e.to_string()))?;
+                Ok(Some(Blob { content_type, data }))
+            }
+            BlobStore::Dir(dir) => {
+                let file = match std::fs::read(dir.join(key)) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let Some(newline) = file.iter().position(|&b| b == b'\n') else {
+                    return Err(
+                        io::Error::new(io::ErrorKind::InvalidData, "no content type").into(),
+                    );
+                };
+                Ok(Some(Blob {
+                    content_type: String::from_utf8_lossy(&file[..newline]).into_owned(),
+                    data: Bytes::copy_from_slice(&file[newline + 1..]),
+                }))
+            }
+            BlobStore::Memory(blobs) => Ok(blobs.lock().unwrap().get(key).cloned()),
+        }
+    }
+
+    /// Remove the file stored as `key`, if there is one.
+    pub(super) async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        if !plain(key) {
+            return Err(BlobError::Key);
+        }
+        match self {
+            BlobStore::S3(s3) => {
+                let req = Request::delete(s3.url(key))
+                    .body(hyper::Body::empty())
+                    .expect("keys are plain paths");
+                s3.send(req, SignableBody::Bytes(&[])).await?;
+                Ok(())
+            }
+            BlobStore::Dir(dir) => match std::fs::remove_file(dir.join(key)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            },
+            BlobStore::Memory(blobs) => {
+                blobs.lock().unwrap().remove(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// The keys of the files directly in `dir` (like `archives`), in no particular order.
+    pub(super) async fn list(&self, dir: &str) -> Result<Vec<String>, BlobError> {
+        if !plain(dir) {
+            return Err(BlobError::Key);
+        }
+        match self {
+            BlobStore::S3(s3) => {
+                let mut keys = Vec::new();
+                let mut token: Option<String> = None;
+                loop {
+                    let mut url = format!(
+                        "{}?delimiter=%2F&list-type=2&prefix={}",
+                        s3.url(""),
+                        encode(&format!("{dir}/"))
+                    );
+                    if let Some(token) = &token {
+                        url.push_str("&continuation-token=");
+                        url.push_str(&encode(token));
+                    }
+                    let req = Request::get(url)
+                        .body(hyper::Body::empty())
+                        .expect("queries are encoded");
+                    let Some(res) = s3.send(req, SignableBody::Bytes(&[])).await? else {
+                        return Err(BlobError::S3(format!("no bucket {}", s3.bucket)));
+                    };
+                    let body = hyper::body::to_bytes(res.into_body())
+                        .await
+                        .map_err(|e| BlobError::S3(e.to_string()))?;
+                    let body = String::from_utf8_lossy(&body);
+                    // keys are plain paths, so they don't need unescaping
+                    keys.extend(elements(&body, "Key").into_iter().map(String::from));
+                    token = elements(&body, "NextContinuationToken")
+                        .first()
+                        .map(|t| String::from(*t));
+                    if token.is_none() {
+                        return Ok(keys);
+                    }
+                }
+            }
+            BlobStore::Dir(root) => {
+                let entries = match std::fs::read_dir(root.join(dir)) {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut keys = Vec::new();
+                for entry in entries {
+                    let entry = entry?;
+                    if !entry.file_type()?.is_file() {
+                        continue;
+                    }
+                    // anything else, like uploads in progress, isn't stored yet
+                    if let Some(name) = entry.file_name().to_str().filter(|n| plain(n)) {
+                        keys.push(format!("{dir}/{name}"));
+                    }
+                }
+                Ok(keys)
+            }
+            BlobStore::Memory(blobs) => Ok(blobs
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|key| {
+                    key.strip_prefix(dir)
+                        .and_then(|k| k.strip_prefix('/'))
+                        .is_some_and(|name| !name.contains('/'))
+                })
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+/// Percent-encode `s` for a query string.
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+                char::from(b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+/// The contents of every `<tag>` element in `xml`.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let close = format!("</{tag}>");
+    xml.split(&format!("<{tag}>"))
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close).map(|(inner, _)| inner))
+        .collect()
+}
+
+impl S3 {
+    fn url(&self, key: &str) -> String {
+        format!(
+            "https://{}.s3.{}.amazonaws.com/{key}",
+            self.bucket, self.region
+        )
+    }
+
+    /// Sign and send `req`, and give back the response, or `None` if there's no such object.
+    async fn send(
+        &self,
+        mut req: Request<hyper::Body>,
+        payload: SignableBody<'_>,
+    ) -> Result<Option<hyper::Response<hyper::Body>>, BlobError> {
+        let credentials = self
+            .credentials
+            .provide_credentials()
+            .await
+            .map_err(|e| BlobError::S3(e.to_string()))?;
+        let mut settings = SigningSettings::default();
+        settings.percent_encoding_mode = PercentEncodingMode::Single;
+        settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+        let mut params = SigningParams::builder()
+            .access_key(credentials.access_key_id())
+            .secret_key(credentials.secret_access_key())
+            .region(&self.region)
+            .service_name("s3")
+            .time(SystemTime::now())
+            .settings(settings);
+        params.set_security_token(credentials.session_token());
+        let params = params.build().expect("all required parameters are set");
+        let signable = SignableRequest::new(req.method(), req.uri(), req.headers(), payload);
+        let (signing, _) = sign(signable, &params)
+            .map_err(|e| BlobError::S3(e.to_string()))?
+            .into_parts();
+        signing.apply_to_request(&mut req);
+
+        let method = req.method().clone();
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| BlobError::S3(e.to_string()))?;
+        match res.status() {
+            s if s.is_success() => Ok(Some(res)),
+            StatusCode::NOT_FOUND if method != Method::PUT => Ok(None),
+            s => {
+                let body = hyper::body::to_bytes(res.into_body())
+                    .await
+                    .unwrap_or_default();
+                Err(BlobError::S3(format!(
+                    "{method} failed with {s}: {}",
+                    String::from_utf8_lossy(&body)
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(store: BlobStore) {
+        let json = Bytes::from_static(br#"{"hello":"world"}"#);
+        store
+            .put_bytes("tests/hello.json", "application/json", json.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get("tests/hello.json").await.unwrap(),
+            Some(Blob {
+                content_type: String::from("application/json"),
+                data: json,
+            })
+        );
+
+        // streamed in pieces, and replacing what was there
+        let png = b"\x89PNG\r\n\x1a\nnot really a png";
+        let chunks: Vec<_> = png
+            .chunks(3)
+            .map(|c| Ok::<_, BoxError>(Bytes::copy_from_slice(c)))
+            .collect();
+        store
+            .put(
+                "tests/hello.json",
+                "image/png",
+                png.len() as u64,
+                stream::iter(chunks),
+            )
+            .await
+            .unwrap();
+        let blob = store.get("tests/hello.json").await.unwrap().unwrap();
+        assert_eq!(blob.content_type, "image/png");
+        assert_eq!(&blob.data[..], &png[..]);
+
+        let put = |key, content_type, data: &'static [u8]| {
+            store.put_bytes(key, content_type, Bytes::from_static(data))
+        };
+        assert!(matches!(
+            put("tests/hello.png", "image/png", b"GIF89a").await,
+            Err(BlobError::Mismatch)
+        ));
+        assert!(matches!(
+            put("tests/hello.exe", "application/x-msdownload", b"MZ").await,
+            Err(BlobError::ContentType(_))
+        ));
+        assert!(matches!(
+            put("../hello.txt", "text/plain", b"hi").await,
+            Err(BlobError::Key)
+        ));
+        // lying about the length doesn't get a file stored either
+        let body = stream::once(async { Ok::<_, BoxError>(Bytes::from_static(b"hello")) });
+        assert!(matches!(
+            store.put("tests/short.txt", "text/plain", 3, body).await,
+            Err(BlobError::TooLarge)
+        ));
+        let body = stream::once(async { Ok::<_, BoxError>(Bytes::from_static(b"hello")) });
+        assert!(matches!(
+            store.put("tests/long.txt", "text/plain", 10, body).await,
+            Err(BlobError::Truncated)
+        ));
+        assert_eq!(store.get("tests/short.txt").await.unwrap(), None);
+        assert_eq!(store.get("tests/long.txt").await.unwrap(), None);
+
+        store
+            .put_bytes(
+                "tests/more/hello.txt",
+                "text/plain",
+                Bytes::from_static(b"hi"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.list("tests").await.unwrap(), ["tests/hello.json"]);
+        assert_eq!(
+            store.list("tests/more").await.unwrap(),
+            ["tests/more/hello.txt"]
+        );
+        assert!(store.list("nothing").await.unwrap().is_empty());
+        store.delete("tests/more/hello.txt").await.unwrap();
+
+        store.delete("tests/hello.json").await.unwrap();
+        assert_eq!(store.get("tests/hello.json").await.unwrap(), None);
+        // which is fine to do again
+        store.delete("tests/hello.json").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory() {
+        inner(BlobStore::open(&Location::Memory).await).await;
+    }
+
+    #[tokio::test]
+    async fn dir() {
+        let dir = std::env::temp_dir().join(format!("wewerewondering-{}", Uuid::new_v4()));
+        inner(BlobStore::open(&Location::Dir(dir.clone())).await).await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn s3() {
+        let bucket = std::env::var("BLOB_TEST_BUCKET").expect("BLOB_TEST_BUCKET is set");
+        inner(BlobStore::open(&Location::S3(bucket)).await).await;
+    }
+
+    #[test]
+    fn locations() {
+        assert_eq!(
+            "s3://my-bucket".parse(),
+            Ok(Location::S3(String::from("my-bucket")))
+        );
+        assert_eq!(
+            "file:///var/lib/wewerewondering".parse(),
+            Ok(Location::Dir(PathBuf::from("/var/lib/wewerewondering")))
+        );
+        assert_eq!("memory".parse(), Ok(Location::Memory));
+        assert!("s3://".parse::<Location>().is_err());
+        assert!("s3://bucket/prefix".parse::<Location>().is_err());
+        assert!("ftp://example.com".parse::<Location>().is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Everything a wallboard shows about an event, in one request.
+//!
+//! Dashboards on screens around a venue want the top questions, what's trending, what was just
+//! asked, and what the host has announced, and polling the list once per view (and then working
+//! the rest out client-side) adds up. `GET /api/event/:eid/board?n=` puts together all of it,
+//! with the `n` (default `DEFAULT_N`, at most `MAX_N`) first questions of each:
+//!
+//! ```json
+//! {
+//!   "version": 17,
+//!   "announcement": {"text": "...", "until": 1700000000},
+//!   "counts": {"questions": 12, "answered": 3, "unanswered": 9, "votes": 48},
+//!   "spotlight": {"qid": "...", "votes": 9, "hidden": false, "answered": false},
+//!   "top": [...],
+//!   "trending": [...],
+//!   "newest": [...]
+//! }
+//! ```
+//!
+//! Only what guests can see is on the board. `top` and `trending` are in the order guests see them
+//! in, so they follow the order the host [locked](super::lock) the list in, if they did. The
+//! `spotlight` is the first unanswered question in `top`, which is the one the room is most likely
+//! to get to next, or `null` if every question has been answered. Like the question list, the
+//! board doesn't include question texts, which clients get from `/api/questions`.
+//!
+//! Events that are [hot](super::hot) have their board put together from their hot copy in a
+//! single pass while it's locked, so it costs no database requests at all. Other events go
+//! through the guest question list first, which checks that the event exists and isn't frozen,
+//! and makes it hot. Boards are cached for as long as guest lists are.
+
+use super::{
+    announce::Announcement,
+    experiment::Ordering,
+    hot::Question,
+    list::{self, Order},
+    Backend,
+};
+use axum::extract::{Path, Query, State};
+use axum::response::{AppendHeaders, Json};
+use http::{
+    header::{self, HeaderName},
+    HeaderMap, StatusCode,
+};
+use serde::Deserialize;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How many questions each part of the board has unless asked for something else.
+const DEFAULT_N: usize = 5;
+/// The most questions each part of the board can have.
+const MAX_N: usize = 25;
+
+/// How big a board to make.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Size {
+    n: Option<usize>,
+}
+
+/// The board of an event at version `seq`, with `n` questions in each part.
+///
+/// `questions` are all of the event's questions, hidden or not, and are only gone through once.
+pub(super) fn assemble(
+    seq: u64,
+    announcement: Option<Announcement>,
+    locked: Option<&[Uuid]>,
+    questions: impl IntoIterator<Item = (Uuid, Question)>,
+    n: usize,
+) -> serde_json::Value {
+    let (mut answered, mut votes) = (0, 0);
+    let mut visible = Vec::new();
+    for (qid, q) in questions {
+        if q.hidden {
+            continue;
+        }
+        answered += usize::from(q.answered);
+        votes += q.votes;
+        visible.push((qid, q));
+    }
+    let now = super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let in_order = |order: Ordering| {
+        let mut qs = visible.clone();
+        order.sort(&mut qs, now);
+        if let Some(locked) = locked {
+            super::lock::arrange(&mut qs, locked);
+        }
+        qs
+    };
+    let top = in_order(Ordering::Top);
+    let trending = in_order(Ordering::Trending);
+    let spotlight = top
+        .iter()
+        .find(|(_, q)| !q.answered)
+        .map(|(qid, q)| q.to_json(qid));
+    let mut newest = visible;
+    Ordering::New.sort(&mut newest, now);
+
+    let first = |qs: &[(Uuid, Question)]| -> Vec<_> {
+        qs.iter().take(n).map(|(qid, q)| q.to_json(qid)).collect()
+    };
+    serde_json::json!({
+        "version": seq,
+        "announcement": announcement.as_ref().map(Announcement::to_json),
+        "counts": {
+            "questions": newest.len(),
+            "answered": answered,
+            "unanswered": newest.len() - answered,
+            "votes": votes,
+        },
+        "spotlight": spotlight,
+        "top": first(&top),
+        "trending": first(&trending),
+        "newest": first(&newest),
+    })
+}
+
+pub(super) async fn board(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    Query(Size { n }): Query<Size>,
+) -> (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Result<Json<serde_json::Value>, StatusCode>,
+) {
+    let n = n.unwrap_or(DEFAULT_N).min(MAX_N);
+    super::hot::recheck(&dynamo, &eid).await;
+    if let Some(board) = super::hot::board(&eid, n) {
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=10")]),
+            Ok(Json(board)),
+        );
+    }
+
+    // the list guests see has already been checked for existence, freezing, and the like
+    let (AppendHeaders([(_, cache)]), _, listed) = list::list(
+        Path(eid),
+        State(dynamo.clone()),
+        Query(Order::default()),
+        HeaderMap::new(),
+    )
+    .await;
+    if let Err(status) = listed {
+        return (AppendHeaders([(header::CACHE_CONTROL, cache)]), Err(status));
+    }
+    if let Some(board) = super::hot::board(&eid, n) {
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, cache)]),
+            Ok(Json(board)),
+        );
+    }
+
+    // this instance doesn't keep the event hot (or the list was stale), so do it the slow way
+    let (meta, questions) = match tokio::try_join!(super::get_meta(&dynamo, &eid), async {
+        dynamo.list(&eid, false).await.map_err(|e| {
+            error!(%eid, error = %e, "dynamodb request for board questions failed");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+    }) {
+        Ok(v) => v,
+        Err(status) => {
+            return (
+                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                Err(status),
+            )
+        }
+    };
+    let questions = questions
+        .items()
+        .into_iter()
+        .flatten()
+        .filter_map(|doc| list::parse(&eid, doc));
+    let locked = super::lock::snapshot(&meta);
+    let board = assemble(
+        meta.version,
+        meta.showing(),
+        locked.as_deref(),
+        questions,
+        n,
+    );
+    (
+        AppendHeaders([(header::CACHE_CONTROL, cache)]),
+        Ok(Json(board)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::{Property, Why};
+
+    fn q(votes: usize, answered: bool, asked: u64) -> Question {
+        Question {
+            votes,
+            hidden: false,
+            answered,
+            voters: 0,
+            asked,
+            merged_into: None,
+            asker: None,
+            reactions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn assembles() {
+        let (a, b, c, d) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let mut hidden = q(50, false, 4);
+        hidden.hidden = true;
+        let qs = [
+            (a, q(9, true, 1)),
+            (b, q(5, false, 2)),
+            (c, q(1, false, 3)),
+            (d, hidden),
+        ];
+        let qids = |v: &serde_json::Value| -> Vec<String> {
+            v.as_array()
+                .unwrap()
+                .iter()
+                .map(|q| q["qid"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let board = assemble(3, None, None, qs, 2);
+        assert_eq!(board["version"], 3);
+        assert_eq!(board["announcement"], serde_json::Value::Null);
+        assert_eq!(
+            board["counts"],
+            serde_json::json!({"questions": 3, "answered": 1, "unanswered": 2, "votes": 15})
+        );
+        assert_eq!(qids(&board["top"]), [a.to_string(), b.to_string()]);
+        assert_eq!(qids(&board["newest"]), [c.to_string(), b.to_string()]);
+        assert_eq!(board["trending"].as_array().unwrap().len(), 2);
+        assert_eq!(board["spotlight"]["qid"], b.to_string());
+
+        // the locked order goes first
+        let board = assemble(3, None, Some(&[c]), qs, 1);
+        assert_eq!(qids(&board["top"]), [c.to_string()]);
+        assert_eq!(qids(&board["trending"]), [c.to_string()]);
+        assert_eq!(board["spotlight"]["qid"], c.to_string());
+
+        let board = assemble(3, None, None, [(a, q(1, true, 1))], 5);
+        assert_eq!(board["spotlight"], serde_json::Value::Null);
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let mut qids = Vec::new();
+        for body in ["what comes first?", "and second?", "is there a third?"] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap();
+            qids.push(q["id"].as_str().unwrap().to_string());
+        }
+        let hidden = Uuid::parse_str(&qids[2]).unwrap();
+        crate::toggle::toggle(
+            Path((eid, secret.clone(), hidden, Property::Hidden)),
+            State(backend.clone()),
+            Query(Why::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+
+        let (AppendHeaders(headers), res) = board(
+            Path(eid),
+            State(backend.clone()),
+            Query(Size { n: Some(1) }),
+        )
+        .await;
+        assert_eq!(headers[0].1, "max-age=10");
+        let Json(board) = res.unwrap();
+        assert_eq!(board["counts"]["questions"], 2);
+        assert_eq!(board["counts"]["votes"], 2);
+        assert_eq!(board["top"].as_array().unwrap().len(), 1);
+        assert_ne!(board["top"][0]["qid"], qids[2].as_str());
+        assert!(board["spotlight"].is_object());
+
+        let (_, res) = super::board(
+            Path(Uuid::new_v4()),
+            State(backend.clone()),
+            Query(Size::default()),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_FOUND);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,270 @@
+//! Capping how much any one event can cost to run.
+//!
+//! On a public instance, one event with a huge audience (or someone hammering it with a script)
+//! can run up the DynamoDB bill for everyone. With `EVENT_BUDGET` set, each event gets a bucket
+//! of `EVENT_BURST` cost units that refills at `EVENT_BUDGET` units a second, and every attendee
+//! request for the event takes what it roughly costs out of it (see [`cost`]): a unit for a read,
+//! which is usually a single small database read or none at all, more for asking (a write and a
+//! change log entry), and most for exports, which read the whole event.
+//!
+//! An event whose bucket runs dry is throttled: its attendee requests get a 429 with a
+//! `Retry-After` of how long until there's enough again, and `{"error": "event_over_budget"}`, so
+//! clients can back off and try again rather than show an error. Hosts aren't throttled, and are
+//! told (once per time it happens) over sockets and streams with the secret as `{"kind":
+//! "budget", "throttled": true, "retry_after": .., "text": ..}`, and at their digest webhook if
+//! they have one, so they know why attendees are seeing delays. Throttled requests are counted
+//! as `budget.throttled`.
+//!
+//! Votes only name their question, so they can't be turned away before they're made, but they're
+//! taken out of the bucket once they are, so an event that's being flooded with votes gets its
+//! polls throttled instead. Like the other process-wide state, buckets only exist on the instance
+//! that serves the requests, so as a Lambda each instance gives every event a budget of its own.
+
+use axum::{
+    extract::{Path, State},
+    http::Request,
+    middleware::Next,
+    response::{AppendHeaders, IntoResponse, Json, Response},
+};
+use http::{header, StatusCode};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Forget about events whose buckets are full once there are this many.
+const MAX_BUCKETS: usize = 4096;
+
+/// Roughly what a request for `route` costs, in units of a single small read.
+pub(super) fn cost(route: &str) -> f64 {
+    match route {
+        // the whole event, every time
+        "text" | "pdf" | "archive" => 20.0,
+        // a write, and a write to the change log
+        "ask" | "revise" => 5.0,
+        // a conditional update, and a write to the change log
+        "vote" | "react" => 2.0,
+        _ => 1.0,
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+    /// Whether requests were being turned away, so hosts are only told once.
+    throttled: bool,
+}
+
+impl Bucket {
+    fn full(burst: f64, now: Instant) -> Self {
+        Bucket {
+            tokens: burst,
+            at: now,
+            throttled: false,
+        }
+    }
+
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.at = now;
+    }
+
+    /// Take `cost` out of the bucket, or say how long until there's that much in it.
+    fn take(&mut self, cost: f64, now: Instant, rate: f64, burst: f64) -> Result<(), Duration> {
+        self.refill(now, rate, burst);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (cost.min(burst) - self.tokens) / rate,
+        ))
+    }
+}
+
+static BUCKETS: Mutex<BTreeMap<Uuid, Bucket>> = Mutex::new(BTreeMap::new());
+
+/// How an attempt to spend out of an event's budget went.
+#[derive(Debug, PartialEq)]
+enum Spent {
+    Ok,
+    /// Over budget, until the given time has passed. `first` if the event wasn't already.
+    Over {
+        wait: Duration,
+        first: bool,
+    },
+}
+
+fn spend_at(
+    buckets: &mut BTreeMap<Uuid, Bucket>,
+    eid: Uuid,
+    cost: f64,
+    now: Instant,
+    rate: f64,
+    burst: f64,
+) -> Spent {
+    if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&eid) {
+        buckets.retain(|_, b| {
+            b.refill(now, rate, burst);
+            b.tokens < burst
+        });
+    }
+    let bucket = buckets
+        .entry(eid)
+        .or_insert_with(|| Bucket::full(burst, now));
+    match bucket.take(cost, now, rate, burst) {
+        Ok(()) => {
+            bucket.throttled = false;
+            Spent::Ok
+        }
+        Err(wait) => {
+            let first = !bucket.throttled;
+            bucket.throttled = true;
+            Spent::Over { wait, first }
+        }
+    }
+}
+
+/// Take `cost` out of `eid`'s budget, and tell the host if that's put it over.
+///
+/// Returns how long to wait if the event is over budget.
+pub(super) fn spend(eid: &Uuid, cost: f64) -> Option<Duration> {
+    let config = super::config::config();
+    if config.event_budget == 0 {
+        return None;
+    }
+    let spent = spend_at(
+        &mut BUCKETS.lock().unwrap(),
+        *eid,
+        cost,
+        Instant::now(),
+        f64::from(config.event_budget),
+        f64::from(config.event_burst.max(1)),
+    );
+    let Spent::Over { wait, first } = spent else {
+        return None;
+    };
+    super::metrics::incr("budget.throttled");
+    if first {
+        let retry_after = wait.as_secs() + 1;
+        warn!(%eid, retry_after, "event is over budget");
+        let text = format!(
+            "Event {eid} is getting more requests than this instance allows a single event, so \
+             attendees are being asked to wait and retry. Things go back to normal by themselves \
+             once there's less going on."
+        );
+        let notice = serde_json::json!({
+            "kind": "budget",
+            "throttled": true,
+            "retry_after": retry_after,
+            "text": text,
+        });
+        super::bus::tell_hosts(eid, notice.clone());
+        let eid = *eid;
+        tokio::spawn(async move { super::digest::alert(&eid, notice, text).await });
+    }
+    Some(wait)
+}
+
+/// What a request for a route takes out of its event's budget.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Check {
+    route: &'static str,
+    /// Only attendee requests are throttled.
+    applies: bool,
+}
+
+pub(super) fn check(route: &'static str) -> Check {
+    use super::shed::Class;
+    Check {
+        route,
+        applies: matches!(Class::of(route), Class::Read | Class::Write),
+    }
+}
+
+/// Turn away attendee requests for events that are over budget.
+pub(super) async fn enforce<B>(
+    State(check): State<Check>,
+    params: Option<Path<HashMap<String, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let eid = params
+        .filter(|_| check.applies)
+        .and_then(|Path(params)| Uuid::parse_str(params.get("eid")?).ok());
+    let Some(eid) = eid else {
+        return next.run(req).await;
+    };
+    if let Some(wait) = spend(&eid, cost(check.route)) {
+        debug!(%eid, route = check.route, ?wait, "throttling event over budget");
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            AppendHeaders([(header::RETRY_AFTER, (wait.as_secs() + 1).to_string())]),
+            Json(serde_json::json!({ "error": "event_over_budget" })),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refills() {
+        let mut buckets = BTreeMap::new();
+        let (eid, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // a burst of 10, refilling at 2 a second
+        assert_eq!(
+            spend_at(&mut buckets, eid, 5.0, at(0), 2.0, 10.0),
+            Spent::Ok
+        );
+        assert_eq!(
+            spend_at(&mut buckets, eid, 5.0, at(0), 2.0, 10.0),
+            Spent::Ok
+        );
+        assert_eq!(
+            spend_at(&mut buckets, eid, 1.0, at(0), 2.0, 10.0),
+            Spent::Over {
+                wait: Duration::from_millis(500),
+                first: true
+            }
+        );
+        // the host's already been told
+        assert!(matches!(
+            spend_at(&mut buckets, eid, 1.0, at(100), 2.0, 10.0),
+            Spent::Over { first: false, .. }
+        ));
+        // other events have budgets of their own
+        assert_eq!(
+            spend_at(&mut buckets, other, 10.0, at(100), 2.0, 10.0),
+            Spent::Ok
+        );
+
+        assert_eq!(
+            spend_at(&mut buckets, eid, 1.0, at(500), 2.0, 10.0),
+            Spent::Ok
+        );
+        assert!(!buckets[&eid].throttled);
+        // and it never fills up past the burst
+        assert_eq!(
+            spend_at(&mut buckets, eid, 10.0, at(60_000), 2.0, 10.0),
+            Spent::Ok
+        );
+        assert!(matches!(
+            spend_at(&mut buckets, eid, 1.0, at(60_000), 2.0, 10.0),
+            Spent::Over { first: true, .. }
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+//! Passing changes on to whoever's following an event live, as they happen.
+//!
+//! Every change that's recorded through this instance is published to the event's channel, as the
+//! same JSON the change feed (`/api/event/:eid/changes`) has for it, and anything pushing changes
+//! to clients (see [`super::ws`] and [`super::sse`]) subscribes to it. Channels only exist while
+//! someone is listening. A change that couldn't be recorded has no sequence number, so listeners
+//! are told to `resync` from the change feed instead, and so are listeners that fall more than
+//! `LIVE_BACKLOG` changes behind. Each channel keeps only that many changes however many are
+//! listening, and a listener that falls behind just misses the oldest ones, so one slow listener
+//! doesn't make anyone else's changes pile up in memory. Some notices, like who's moderating which
+//! question (see [`super::lease`]) or which questions are surging (see [`super::surge`]), are only
+//! for hosts, and listeners without the event secret skip them.
+//!
+//! Like the hot set, this only knows about changes made through this instance, whatever the
+//! backend, so with several instances live listeners need to poll the change feed now and then
+//! too.
+
+use super::{changes::Change, Backend, Meta};
+use http::StatusCode;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Something to tell an event's listeners, as the JSON they're sent.
+#[derive(Debug, Clone)]
+pub(super) struct Notice {
+    pub(super) text: Arc<str>,
+    pub(super) hosts_only: bool,
+}
+
+static CHANNELS: Mutex<BTreeMap<Uuid, broadcast::Sender<Notice>>> = Mutex::new(BTreeMap::new());
+
+/// What listeners are told when they've missed changes.
+pub(super) const RESYNC: &str = r#"{"resync":true}"#;
+
+/// Tell `eid`'s listeners about `change`, numbered `seq` if it was recorded.
+pub(super) fn publish(eid: &Uuid, seq: Option<u64>, change: Change) {
+    if !CHANNELS.lock().unwrap().contains_key(eid) {
+        return;
+    }
+    let text: Arc<str> = match seq {
+        Some(seq) => {
+            let at = super::clock::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            super::changes::json(seq, at, change).to_string().into()
+        }
+        None => RESYNC.into(),
+    };
+    send(
+        eid,
+        Notice {
+            text,
+            hosts_only: false,
+        },
+    );
+}
+
+/// Tell `eid`'s listeners that have the event secret about `notice`.
+pub(super) fn tell_hosts(eid: &Uuid, notice: serde_json::Value) {
+    send(
+        eid,
+        Notice {
+            text: notice.to_string().into(),
+            hosts_only: true,
+        },
+    );
+}
+
+fn send(eid: &Uuid, notice: Notice) {
+    let mut channels = CHANNELS.lock().unwrap();
+    let Some(channel) = channels.get(eid) else {
+        return;
+    };
+    if channel.send(notice).is_err() {
+        // everyone has left
+        channels.remove(eid);
+    }
+}
+
+/// Who wants to listen to an event, as they say in the query string.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Listen {
+    /// The event secret, for hosts.
+    secret: Option<String>,
+}
+
+/// The event `listen` wants to listen to, and whether they're one of its hosts.
+///
+/// Frozen events can't be listened to.
+pub(super) async fn admit(
+    dynamo: &Backend,
+    eid: &Uuid,
+    listen: &Listen,
+) -> Result<(Meta, bool), StatusCode> {
+    let meta = match &listen.secret {
+        Some(secret) => super::check_secret(dynamo, eid, secret).await?,
+        None => super::get_meta(dynamo, eid).await?,
+    };
+    if meta.frozen() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok((meta, listen.secret.is_some()))
+}
+
+/// Start listening for changes to `eid`.
+pub(super) fn subscribe(eid: &Uuid) -> broadcast::Receiver<Notice> {
+    let mut channels = CHANNELS.lock().unwrap();
+    // drop the channels of events nobody listens to anymore while we're here
+    channels.retain(|_, c| c.receiver_count() != 0);
+    channels
+        .entry(*eid)
+        .or_insert_with(|| broadcast::channel(super::config::config().live_backlog).0)
+        .subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vote::UpDown;
+
+    #[test]
+    fn passes_changes_on() {
+        let (eid, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let qid = Uuid::new_v4();
+        // nobody is listening yet, so this goes nowhere
+        publish(&eid, Some(1), Change::QuestionAsked { qid });
+
+        let mut rx = subscribe(&eid);
+        publish(
+            &eid,
+            Some(2),
+            Change::VoteCast {
+                qid,
+                direction: UpDown::Up,
+            },
+        );
+        publish(&other, Some(1), Change::QuestionAsked { qid });
+        publish(&eid, None, Change::QuestionAsked { qid });
+
+        tell_hosts(&eid, serde_json::json!({ "kind": "lease" }));
+
+        let notice = rx.try_recv().unwrap();
+        assert!(!notice.hosts_only);
+        let notice: serde_json::Value = serde_json::from_str(&notice.text).unwrap();
+        assert_eq!(notice["seq"], 2);
+        assert_eq!(notice["kind"], "vote_cast");
+        assert_eq!(notice["direction"], "up");
+        assert_eq!(&*rx.try_recv().unwrap().text, RESYNC);
+        let notice = rx.try_recv().unwrap();
+        assert!(notice.hosts_only);
+        assert_eq!(&*notice.text, r#"{"kind":"lease"}"#);
+        assert!(rx.try_recv().is_err());
+
+        drop(rx);
+        publish(&eid, Some(3), Change::QuestionAsked { qid });
+        assert!(!CHANNELS.lock().unwrap().contains_key(&eid));
+    }
+}
@@ -0,0 +1,684 @@
+//! The append-only log of everything that happens to an event.
+//!
+//! Every mutation that goes through the API also appends a "domain event" to the `changes` table,
+//! numbered by a per-event sequence number. Clients can use it to fetch only what changed since
+//! they last looked, and operators can use it to see (or replay) exactly what happened.
+
+use super::{react::Reaction, vote::UpDown, Backend, Local};
+use aws_sdk_dynamodb::{
+    error::QueryError,
+    model::{AttributeValue, Put, ReturnValue, TransactWriteItem, Update},
+    output::QueryOutput,
+    types::SdkError,
+};
+use axum::extract::{Path, Query, State};
+use axum::response::{AppendHeaders, Json};
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use rand::Rng;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Don't return more than this many changes in one go.
+const MAX_CHANGES: usize = 500;
+
+/// How many times to retry a transaction that lost a race (like recording a change, for the next
+/// sequence number) before giving up.
+pub(super) const MAX_RETRIES: u32 = 5;
+
+/// How long to wait before the first such retry, at most. Later retries wait up to twice as long
+/// as the one before.
+pub(super) const BACKOFF: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Change {
+    EventCreated,
+    QuestionAsked {
+        qid: Uuid,
+    },
+    VoteCast {
+        qid: Uuid,
+        direction: UpDown,
+    },
+    QuestionAnswered {
+        qid: Uuid,
+        set: bool,
+    },
+    QuestionHidden {
+        qid: Uuid,
+        set: bool,
+    },
+    /// The asker changed the question's text (see [`super::author`]).
+    QuestionEdited {
+        qid: Uuid,
+    },
+    /// The host posted or cleared an announcement.
+    Announced,
+    /// The host merged a duplicate question into another one, which got its `votes` (see
+    /// [`super::merge`]). The duplicate is hidden.
+    QuestionMerged {
+        qid: Uuid,
+        into: Uuid,
+        votes: u64,
+    },
+    /// Suspicious `votes` were taken off the question until the host reviews them (see
+    /// [`super::anomaly`]).
+    VotesHeld {
+        qid: Uuid,
+        votes: u64,
+    },
+    /// The host gave held `votes` back to the question.
+    VotesReleased {
+        qid: Uuid,
+        votes: u64,
+    },
+    /// A guest reacted to the question with an emoji (see [`super::react`]).
+    Reacted {
+        qid: Uuid,
+        reaction: Reaction,
+    },
+}
+
+impl Change {
+    fn kind(&self) -> &'static str {
+        match self {
+            Change::EventCreated => "event_created",
+            Change::QuestionAsked { .. } => "question_asked",
+            Change::VoteCast { .. } => "vote_cast",
+            Change::QuestionAnswered { .. } => "question_answered",
+            Change::QuestionHidden { .. } => "question_hidden",
+            Change::QuestionEdited { .. } => "question_edited",
+            Change::Announced => "announced",
+            Change::QuestionMerged { .. } => "question_merged",
+            Change::VotesHeld { .. } => "votes_held",
+            Change::VotesReleased { .. } => "votes_released",
+            Change::Reacted { .. } => "reacted",
+        }
+    }
+
+    fn attributes(&self) -> Vec<(&'static str, AttributeValue)> {
+        let mut attrs = vec![("kind", AttributeValue::S(self.kind().to_string()))];
+        match *self {
+            Change::EventCreated | Change::Announced => {}
+            Change::QuestionAsked { qid } | Change::QuestionEdited { qid } => {
+                attrs.push(("qid", AttributeValue::S(qid.to_string())));
+            }
+            Change::VoteCast { qid, direction } => {
+                attrs.push(("qid", AttributeValue::S(qid.to_string())));
+                let direction = match direction {
+                    UpDown::Up => "up",
+                    UpDown::Down => "down",
+                };
+                attrs.push(("direction", AttributeValue::S(direction.to_string())));
+            }
+            Change::QuestionAnswered { qid, set } | Change::QuestionHidden { qid, set } => {
+                attrs.push(("qid", AttributeValue::S(qid.to_string())));
+                attrs.push(("set", AttributeValue::Bool(set)));
+            }
+            Change::QuestionMerged { qid, into, votes } => {
+                attrs.push(("qid", AttributeValue::S(qid.to_string())));
+                attrs.push(("into", AttributeValue::S(into.to_string())));
+                attrs.push(("votes", AttributeValue::N(votes.to_string())));
+            }
+            Change::VotesHeld { qid, votes } | Change::VotesReleased { qid, votes } => {
+                attrs.push(("qid", AttributeValue::S(qid.to_string())));
+                attrs.push(("votes", AttributeValue::N(votes.to_string())));
+            }
+            Change::Reacted { qid, reaction } => {
+                attrs.push(("qid", AttributeValue::S(qid.to_string())));
+                attrs.push(("emoji", AttributeValue::S(reaction.emoji().to_string())));
+            }
+        }
+        attrs
+    }
+
+    /// Parse a change back out of a stored log item.
+    pub(super) fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let qid = || {
+            item.get("qid")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|v| Uuid::parse_str(v).ok())
+        };
+        let set = || item.get("set").and_then(|v| v.as_bool().ok()).copied();
+        let votes = || item.get("votes")?.as_n().ok()?.parse().ok();
+        Some(match item.get("kind")?.as_s().ok()?.as_str() {
+            "event_created" => Change::EventCreated,
+            "question_asked" => Change::QuestionAsked { qid: qid()? },
+            "vote_cast" => Change::VoteCast {
+                qid: qid()?,
+                direction: match item.get("direction")?.as_s().ok()?.as_str() {
+                    "up" => UpDown::Up,
+                    "down" => UpDown::Down,
+                    _ => return None,
+                },
+            },
+            "question_answered" => Change::QuestionAnswered {
+                qid: qid()?,
+                set: set()?,
+            },
+            "question_hidden" => Change::QuestionHidden {
+                qid: qid()?,
+                set: set()?,
+            },
+            "question_edited" => Change::QuestionEdited { qid: qid()? },
+            "announced" => Change::Announced,
+            "question_merged" => Change::QuestionMerged {
+                qid: qid()?,
+                into: item
+                    .get("into")
+                    .and_then(|v| v.as_s().ok())
+                    .and_then(|v| Uuid::parse_str(v).ok())?,
+                votes: votes()?,
+            },
+            "votes_held" => Change::VotesHeld {
+                qid: qid()?,
+                votes: votes()?,
+            },
+            "votes_released" => Change::VotesReleased {
+                qid: qid()?,
+                votes: votes()?,
+            },
+            "reacted" => Change::Reacted {
+                qid: qid()?,
+                reaction: item.get("emoji")?.as_s().ok()?.parse().ok()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Append `change` to the log of `eid` without a transaction, and return its sequence number.
+///
+/// Transactions need lightweight transactions in alternator, which it may not be set up for, so
+/// there the sequence number is bumped first and the change written after. If that second write
+/// fails, the log has a gap where the change would have been.
+async fn record_untransacted(
+    dynamo: &aws_sdk_dynamodb::Client,
+    eid: &Uuid,
+    change: HashMap<String, AttributeValue>,
+) -> Result<u64, aws_sdk_dynamodb::Error> {
+    let seq = dynamo
+        .update_item()
+        .table_name("events")
+        .key("id", AttributeValue::S(eid.to_string()))
+        .update_expression("SET seq = if_not_exists(seq, :zero) + :one")
+        .expression_attribute_values(":zero", AttributeValue::N(0.to_string()))
+        .expression_attribute_values(":one", AttributeValue::N(1.to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await?
+        .attributes()
+        .and_then(|a| a.get("seq"))
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .expect("seq is always a number");
+
+    dynamo
+        .put_item()
+        .table_name("changes")
+        .set_item(Some(change))
+        .item("seq", AttributeValue::N(seq.to_string()))
+        .send()
+        .await?;
+    Ok(seq)
+}
+
+impl Backend {
+    /// Append a change to the event's log, and return its sequence number.
+    ///
+    /// The `extra` attributes are kept with the change, but never shown (see [`super::anomaly`]).
+    pub(super) async fn record(
+        &self,
+        eid: &Uuid,
+        change: Change,
+        extra: Vec<(&'static str, AttributeValue)>,
+    ) -> Result<u64, aws_sdk_dynamodb::Error> {
+        let now = super::clock::now();
+        let at = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // the log is only useful for as long as the event is around
+        let expire = (now
+            + Duration::from_secs(super::new::EVENTS_EXPIRE_AFTER_DAYS * 24 * 60 * 60))
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+        match self {
+            Self::Dynamo(dynamo) => {
+                let change = change.attributes().into_iter().chain(extra).chain([
+                    ("eid", AttributeValue::S(eid.to_string())),
+                    ("at", AttributeValue::N(at.to_string())),
+                    ("expire", AttributeValue::N(expire.to_string())),
+                ]);
+                let change: HashMap<_, _> = change.map(|(k, v)| (k.to_string(), v)).collect();
+                if super::config::config().alternator {
+                    return record_untransacted(dynamo, eid, change).await;
+                }
+
+                for retry in 0.. {
+                    // the sequence number has to be known up front to go in both writes, so the
+                    // bump is conditional on nobody else having bumped it in the meantime
+                    let seq = dynamo
+                        .get_item()
+                        .table_name("events")
+                        .key("id", AttributeValue::S(eid.to_string()))
+                        .projection_expression("seq")
+                        .consistent_read(true)
+                        .send()
+                        .await?;
+                    let Some(seq) = seq.item() else {
+                        return Err(aws_sdk_dynamodb::Error::Unhandled(
+                            format!("recording change for non-existing event {eid}").into(),
+                        ));
+                    };
+                    let last = seq
+                        .get("seq")
+                        .and_then(|v| v.as_n().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .unwrap_or(0);
+                    let seq = last + 1;
+
+                    let bump = Update::builder()
+                        .table_name("events")
+                        .key("id", AttributeValue::S(eid.to_string()))
+                        .update_expression("SET seq = :seq")
+                        .expression_attribute_values(":seq", AttributeValue::N(seq.to_string()));
+                    let bump = if last == 0 {
+                        bump.condition_expression(
+                            "attribute_exists(id) AND attribute_not_exists(seq)",
+                        )
+                    } else {
+                        bump.condition_expression("seq = :last")
+                            .expression_attribute_values(
+                                ":last",
+                                AttributeValue::N(last.to_string()),
+                            )
+                    };
+                    let mut item = change.clone();
+                    item.insert(String::from("seq"), AttributeValue::N(seq.to_string()));
+                    let put = Put::builder()
+                        .table_name("changes")
+                        .set_item(Some(item))
+                        .condition_expression("attribute_not_exists(seq)");
+
+                    // both or neither, so that a failed write never leaves a gap in the log
+                    match dynamo
+                        .transact_write_items()
+                        .transact_items(TransactWriteItem::builder().update(bump.build()).build())
+                        .transact_items(TransactWriteItem::builder().put(put.build()).build())
+                        .send()
+                        .await
+                    {
+                        Ok(_) => return Ok(seq),
+                        Err(SdkError::ServiceError { ref err, .. })
+                            if err.is_transaction_canceled_exception() && retry < MAX_RETRIES =>
+                        {
+                            let wait = rand::thread_rng()
+                                .gen_range(Duration::ZERO..=BACKOFF.saturating_mul(1 << retry));
+                            debug!(%eid, retry, ?wait, "lost race to record change");
+                            super::metrics::incr("changes.retry");
+                            tokio::time::sleep(wait).await;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                unreachable!("retries until it gives up");
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    changes, journal, ..
+                } = &mut *local;
+
+                let log = changes.entry(*eid).or_default();
+                let seq = log.len() as u64 + 1;
+                let mut item = HashMap::from_iter(change.attributes().into_iter().chain(extra));
+                item.insert("eid", AttributeValue::S(eid.to_string()));
+                item.insert("seq", AttributeValue::N(seq.to_string()));
+                item.insert("at", AttributeValue::N(at.to_string()));
+                item.insert("expire", AttributeValue::N(expire.to_string()));
+                journal.change(eid, &item);
+                log.push(item);
+                Ok(seq)
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let item = change.attributes().into_iter().chain(extra).chain([
+                    ("at", AttributeValue::N(at.to_string())),
+                    ("expire", AttributeValue::N(expire.to_string())),
+                ]);
+                mongo.record(eid, item).await.map_err(super::mint_unhandled)
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                let item = change.attributes().into_iter().chain(extra).chain([
+                    ("at", AttributeValue::N(at.to_string())),
+                    ("expire", AttributeValue::N(expire.to_string())),
+                ]);
+                redis.record(eid, item).await.map_err(super::mint_unhandled)
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let item = change.attributes().into_iter().chain(extra).chain([
+                    ("at", AttributeValue::N(at.to_string())),
+                    ("expire", AttributeValue::N(expire.to_string())),
+                ]);
+                sled.record(eid, item).map_err(super::mint_unhandled)
+            }
+        }
+    }
+
+    /// Record a change, but don't fail the operation that caused it if that doesn't work.
+    ///
+    /// The change is also applied to the event's in-memory question list, if it has one, and
+    /// passed on to anyone following the event live.
+    pub(super) async fn try_record(&self, eid: &Uuid, change: Change) {
+        self.try_record_with(eid, change, Vec::new()).await
+    }
+
+    /// Like [`Backend::try_record`], but with `extra` attributes (see [`Backend::record`]).
+    pub(super) async fn try_record_with(
+        &self,
+        eid: &Uuid,
+        change: Change,
+        extra: Vec<(&'static str, AttributeValue)>,
+    ) {
+        let seq = match self.record(eid, change, extra).await {
+            Ok(seq) => Some(seq),
+            Err(e) => {
+                error!(%eid, ?change, error = %e, "failed to record change");
+                None
+            }
+        };
+        if let Some(seq) = seq {
+            super::poll::observe(eid, seq);
+        }
+        super::hot::apply(eid, seq, change);
+        super::bus::publish(eid, seq, change);
+    }
+
+    /// All changes to an event with a sequence number greater than `since`, in order.
+    pub(super) async fn changes(
+        &self,
+        eid: &Uuid,
+        since: u64,
+    ) -> Result<QueryOutput, SdkError<QueryError>> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                dynamo
+                    .query()
+                    .table_name("changes")
+                    .key_condition_expression("eid = :eid AND seq > :since")
+                    .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()))
+                    .expression_attribute_values(":since", AttributeValue::N(since.to_string()))
+                    .limit(MAX_CHANGES as i32)
+                    .send()
+                    .await
+            }
+            Self::Local(local) => {
+                let local = local.read().unwrap();
+                let Local { changes, .. } = &*local;
+
+                let items: Vec<_> = changes
+                    .get(eid)
+                    .map(|log| {
+                        log.iter()
+                            .skip(since as usize)
+                            .take(MAX_CHANGES)
+                            .map(|c| c.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Ok(QueryOutput::builder()
+                    .set_count(Some(items.len() as i32))
+                    .set_items(Some(items))
+                    .build())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.changes(eid, since, MAX_CHANGES).await,
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.changes(eid, since, MAX_CHANGES).await,
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.changes(eid, since, MAX_CHANGES).await,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(super) struct Since {
+    #[serde(default)]
+    since: u64,
+}
+
+/// The API's view of a change log item.
+pub(super) fn to_json(item: &HashMap<String, AttributeValue>) -> Option<serde_json::Value> {
+    let seq = item
+        .get("seq")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<u64>().ok())?;
+    let at = item
+        .get("at")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<u64>().ok())?;
+    Some(json(seq, at, Change::from_item(item)?))
+}
+
+/// The API's view of `change`, numbered `seq` and made at `at`.
+pub(super) fn json(seq: u64, at: u64, change: Change) -> serde_json::Value {
+    let mut v = serde_json::json!({
+        "seq": seq,
+        "at": at,
+    });
+    for (k, attr) in change.attributes() {
+        v[k] = match attr {
+            AttributeValue::S(s) => s.into(),
+            AttributeValue::Bool(b) => b.into(),
+            AttributeValue::N(n) => n.parse::<u64>().expect("changes only count up").into(),
+            _ => unreachable!("changes only have strings, bools, and counts"),
+        };
+    }
+    v
+}
+
+pub(super) async fn changes(
+    Path(eid): Path<Uuid>,
+    Query(since): Query<Since>,
+    State(dynamo): State<Backend>,
+) -> (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Result<Json<serde_json::Value>, StatusCode>,
+) {
+    let announcement = match super::get_meta(&dynamo, &eid).await {
+        Ok(meta) if meta.frozen() => {
+            // the event may well be unfrozen after review
+            return (
+                AppendHeaders([(header::CACHE_CONTROL, "max-age=60")]),
+                Err(StatusCode::FORBIDDEN),
+            );
+        }
+        Ok(meta) => {
+            super::poll::observe(&eid, meta.version);
+            meta.showing()
+        }
+        Err(e) => {
+            // events are unlikely to re-appear with the same uuid
+            return (
+                AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
+                Err(e),
+            );
+        }
+    };
+
+    match dynamo.changes(&eid, since.since).await {
+        Ok(cs) => {
+            let changes: Vec<_> = cs
+                .items()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|item| {
+                    let change = to_json(item);
+                    if change.is_none() {
+                        error!(%eid, ?item, "found malformed change");
+                    }
+                    change
+                })
+                .collect();
+            let latest = changes
+                .last()
+                .and_then(|c| c["seq"].as_u64())
+                .unwrap_or(since.since);
+            (
+                // the log only grows, so it can be cached for about as long as the question list
+                AppendHeaders([(header::CACHE_CONTROL, "max-age=3")]),
+                Ok(Json(serde_json::json!({
+                    "seq": latest,
+                    "more": changes.len() == MAX_CHANGES,
+                    "changes": changes,
+                    "poll_after_ms": super::poll::hint(&eid, false).as_millis() as u64,
+                    "announcement": announcement.as_ref().map(super::announce::Announcement::to_json),
+                }))),
+            )
+        }
+        Err(e) => {
+            error!(%eid, error = %e, "dynamodb request for changes failed");
+            (
+                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                Err(http::StatusCode::INTERNAL_SERVER_ERROR),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderMap;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "hello world".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        crate::vote::vote(
+            Path((qid, UpDown::Up)),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
+        crate::toggle::toggle(
+            Path((
+                eid,
+                secret.to_string(),
+                qid,
+                crate::toggle::Property::Answered,
+            )),
+            State(backend.clone()),
+            axum::extract::Query(Default::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+
+        let all = super::changes(Path(eid), Query(Since::default()), State(backend.clone()))
+            .await
+            .1
+            .unwrap();
+        let kinds: Vec<_> = all["changes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "event_created",
+                "question_asked",
+                "vote_cast",
+                "question_answered"
+            ]
+        );
+        assert_eq!(all["seq"], 4);
+        assert_eq!(all["changes"][2]["qid"], qid.to_string());
+        assert_eq!(all["changes"][2]["direction"], "up");
+        assert_eq!(all["changes"][3]["set"], true);
+
+        // only what happened after what the client has already seen
+        let rest = super::changes(Path(eid), Query(Since { since: 2 }), State(backend.clone()))
+            .await
+            .1
+            .unwrap();
+        assert_eq!(rest["changes"].as_array().unwrap().len(), 2);
+        assert_eq!(rest["changes"][0]["seq"], 3);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[tokio::test]
+    async fn golden() {
+        let eid = Uuid::parse_str("4d6f8b0a-2c4e-4a7b-9d1e-6f8a0c2e4b75").unwrap();
+        let (backend, replay) = crate::golden::replay("changes");
+        replay.check(
+            super::changes(Path(eid), Query(Since::default()), State(backend))
+                .await
+                .1
+                .unwrap()
+                .0,
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! The time as far as stored data is concerned.
+//!
+//! This is the system clock, except that the dev dashboard can move it forward to see how things
+//! look once questions have aged (or events have expired).
+
+use std::time::SystemTime;
+
+#[cfg(all(feature = "dev", debug_assertions))]
+static OFFSET: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// The current time.
+pub(super) fn now() -> SystemTime {
+    #[cfg(all(feature = "dev", debug_assertions))]
+    {
+        use std::sync::atomic::Ordering;
+        SystemTime::now() + std::time::Duration::from_secs(OFFSET.load(Ordering::Relaxed))
+    }
+    #[cfg(not(all(feature = "dev", debug_assertions)))]
+    SystemTime::now()
+}
+
+/// Move the clock forward by `by`.
+#[cfg(all(feature = "dev", debug_assertions))]
+pub(super) fn advance(by: std::time::Duration) {
+    OFFSET.fetch_add(by.as_secs(), std::sync::atomic::Ordering::Relaxed);
+}
+
+/// How far ahead of the system clock the clock is.
+#[cfg(all(feature = "dev", debug_assertions))]
+pub(super) fn offset() -> std::time::Duration {
+    std::time::Duration::from_secs(OFFSET.load(std::sync::atomic::Ordering::Relaxed))
+}
@@ -0,0 +1,404 @@
+//! Deployment configuration.
+//!
+//! Everything is read from environment variables the first time it's needed, since that's what
+//! Lambda gives us. Unset variables fall back to defaults that match the hosted instance.
+
+use std::{collections::HashMap, str::FromStr, sync::OnceLock, time::Duration};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug)]
+pub(super) struct Config {
+    /// Bearer token for `/api/admin` endpoints (`ADMIN_TOKEN`). Admin access is disabled if unset.
+    pub(super) admin_token: Option<String>,
+    /// How long any request may take before it's cut off with a 504 (`TIMEOUT_MS`).
+    pub(super) timeout: Duration,
+    /// Overrides of `timeout` for individual routes (`ROUTE_TIMEOUTS`, like `list=2000,ask=8000`).
+    pub(super) route_timeouts: HashMap<String, Duration>,
+    /// How many attendee requests may be in flight at once (`MAX_CONCURRENCY`). Unlimited if unset.
+    pub(super) max_concurrency: Option<usize>,
+    /// Caps on in-flight requests for individual routes (`ROUTE_CONCURRENCY`, like `list=50`).
+    pub(super) route_concurrency: HashMap<String, usize>,
+    /// How many host requests may be in flight at once (`HOST_CONCURRENCY`). Unlimited if unset.
+    pub(super) host_concurrency: Option<usize>,
+    /// How many events to keep question lists in memory for (`HOT_EVENTS`). Zero disables it.
+    pub(super) hot_events: usize,
+    /// How old an in-memory question list may get before it's reloaded (`HOT_REFRESH_MS`).
+    pub(super) hot_refresh: Duration,
+    /// How old an in-memory question list may be and still be served when the database can't be
+    /// reached (`STALE_FOR_MS`). Zero disables it.
+    pub(super) stale_for: Duration,
+    /// How many question texts to keep in memory (`TEXT_CACHE`). Zero disables it.
+    pub(super) text_cache: usize,
+    /// How many upvotes a question has to get within `vote_surge_window` for hosts to be alerted
+    /// (`VOTE_SURGE`). Zero disables it.
+    pub(super) vote_surge: usize,
+    /// How quickly a question has to get `vote_surge` upvotes (`VOTE_SURGE_WINDOW_MS`).
+    pub(super) vote_surge_window: Duration,
+    /// How many cost units a second each event's attendee requests may use (`EVENT_BUDGET`). Zero
+    /// disables it.
+    pub(super) event_budget: u32,
+    /// How many cost units an event may use at once before it's held to `event_budget`
+    /// (`EVENT_BURST`).
+    pub(super) event_burst: u32,
+    /// Look questions up with this many concurrent `GetItem`s instead of a `BatchGetItem`
+    /// (`GET_ITEM_CONCURRENCY`), for stores that don't support the latter (well).
+    pub(super) get_item_concurrency: Option<usize>,
+    /// Talk to this DynamoDB-compatible endpoint instead of AWS's (`DYNAMODB_ENDPOINT`).
+    pub(super) dynamodb_endpoint: Option<String>,
+    /// Move to the replicas of the tables in this region while the primary one is down
+    /// (`DYNAMODB_FAILOVER_REGION`). See [`super::region`].
+    pub(super) dynamodb_failover_region: Option<String>,
+    /// After how many failed calls in a row the primary region is taken to be down
+    /// (`FAILOVER_AFTER`).
+    pub(super) failover_after: u32,
+    /// How long to stay in the failover region before trying the primary again
+    /// (`FAILOVER_FOR_MS`).
+    pub(super) failover_for: Duration,
+    /// Avoid the DynamoDB features that ScyllaDB's Alternator lacks (`ALTERNATOR=1`).
+    pub(super) alternator: bool,
+    /// Store everything in MongoDB at this URI instead of DynamoDB (`MONGODB_URI`). Only honored
+    /// when built with the `mongo` feature.
+    pub(super) mongodb_uri: Option<String>,
+    /// Store everything in Redis at this URL instead of DynamoDB (`REDIS_URL`). Only honored when
+    /// built with the `redis` feature.
+    pub(super) redis_url: Option<String>,
+    /// How long events (and everything in them) last in Redis (`REDIS_TTL_HOURS`).
+    pub(super) redis_ttl: Duration,
+    /// Share question lists between instances in the Redis at this URL (`LIST_CACHE_URL`). Only
+    /// honored when built with the `redis` feature.
+    pub(super) list_cache_url: Option<String>,
+    /// How long shared question lists are kept (`LIST_CACHE_TTL_MS`).
+    pub(super) list_cache_ttl: Duration,
+    /// How many changes each live listener can fall behind by (`LIVE_BACKLOG`).
+    pub(super) live_backlog: usize,
+    /// What to do with event streams that fall further behind than that (`STREAM_SLOW`, `resync`
+    /// or `disconnect`).
+    pub(super) stream_slow: super::sse::Slow,
+    /// How many streams each event may have open on an instance at once
+    /// (`STREAM_MAX_PER_EVENT`). Unlimited if unset.
+    pub(super) stream_max_per_event: Option<usize>,
+    /// After how many days questions are scrubbed or deleted by the `retention` command
+    /// (`RETENTION`, like `text=30,who=7,question=never`). Nothing is if unset.
+    pub(super) retention: super::retention::Policy,
+    /// The version of the terms of service hosts have to accept before they can create events
+    /// (`TOS_VERSION`). Hosts don't have to accept anything if unset.
+    pub(super) tos_version: Option<String>,
+    /// Where the terms of service are (`TOS_URL`).
+    pub(super) tos_url: Option<String>,
+    /// What the instance calls itself (`INSTANCE_NAME`).
+    pub(super) instance_name: Option<String>,
+    /// How to reach whoever runs the instance, like an email address (`INSTANCE_CONTACT`).
+    pub(super) instance_contact: Option<String>,
+    /// Where the privacy policy is (`PRIVACY_URL`).
+    pub(super) privacy_url: Option<String>,
+    /// Where the site is, like `https://wewerewondering.com` (`PUBLIC_URL`). There's no sitemap
+    /// if unset, since it has to have full URLs.
+    pub(super) public_url: Option<String>,
+    /// Where files like archive snapshots are stored (`BLOB_STORE`, like `s3://<bucket>` or
+    /// `file://<dir>`). They're kept in memory if unset.
+    pub(super) blob_store: super::blobs::Location,
+    /// How guests are split between question orderings (`ORDERING_EXPERIMENT`, like
+    /// `top=50,trending=50`). Everyone sees `top` if unset.
+    pub(super) ordering_experiment: super::experiment::Split,
+    /// How quickly votes stop counting towards a question's trending score (`TRENDING_GRAVITY`).
+    pub(super) trending_gravity: f64,
+    /// Routes that are turned off or only for operators (`POLICY`, like
+    /// `exports=off,new=operator`). Everything is open if unset, and off if it's malformed.
+    pub(super) policy: super::policy::Policy,
+    /// What percentage of the host and operator requests that change something to record
+    /// (`AUDIT`). None are if unset.
+    pub(super) audit: u32,
+    /// How many days audit records are kept (`AUDIT_DAYS`).
+    pub(super) audit_days: u64,
+    /// The domain whose email becomes questions, like `qa.example.com` (`INBOUND_EMAIL_DOMAIN`).
+    pub(super) inbound_email_domain: Option<String>,
+    /// What the email provider has to post inbound email to `/api/email/` with
+    /// (`INBOUND_EMAIL_TOKEN`). Email can't be used to ask questions if unset.
+    pub(super) inbound_email_token: Option<String>,
+    /// What survey tools have to post responses to `/api/event/:eid/survey` with (`SURVEY_KEY`).
+    /// Surveys can't be used to ask questions if unset.
+    pub(super) survey_key: Option<String>,
+    /// Where in survey responses the question and the asker's name are (`SURVEY_FIELDS`, like
+    /// `question=What would you like to ask?,name=/respondent/name`).
+    pub(super) survey_fields: super::survey::Fields,
+    /// What makes summaries of an event's questions for its host (`SUMMARIZER`, `bedrock` or
+    /// `openai`, each with the feature of the same name). There are no summaries if unset.
+    pub(super) summarizer: super::summary::Provider,
+    /// The model summaries are made with (`SUMMARIZER_MODEL`), if not the summarizer's default.
+    pub(super) summarizer_model: Option<String>,
+    /// The key for OpenAI's API (`OPENAI_API_KEY`), if `SUMMARIZER` is `openai`.
+    #[cfg_attr(not(feature = "openai"), allow(dead_code))]
+    pub(super) openai_api_key: Option<String>,
+    /// How many upvotes a question has to get within two minutes to be looked at for
+    /// manipulation (`ANOMALY_BURST`).
+    pub(super) anomaly_burst: usize,
+    /// How likely it has to be, from 0 to 100, that a question's votes were manipulated for them
+    /// to be held back in events that quarantine them (`ANOMALY_THRESHOLD`).
+    pub(super) anomaly_threshold: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            admin_token: None,
+            timeout: Duration::from_secs(10),
+            route_timeouts: HashMap::new(),
+            max_concurrency: None,
+            route_concurrency: HashMap::new(),
+            host_concurrency: None,
+            hot_events: 16,
+            hot_refresh: Duration::from_secs(3),
+            stale_for: Duration::from_secs(30),
+            text_cache: 4096,
+            vote_surge: 20,
+            vote_surge_window: Duration::from_secs(60),
+            event_budget: 0,
+            event_burst: 600,
+            get_item_concurrency: None,
+            dynamodb_endpoint: None,
+            dynamodb_failover_region: None,
+            failover_after: 5,
+            failover_for: Duration::from_secs(60),
+            alternator: false,
+            mongodb_uri: None,
+            redis_url: None,
+            redis_ttl: Duration::from_secs(24 * 60 * 60),
+            list_cache_url: None,
+            list_cache_ttl: Duration::from_secs(30),
+            live_backlog: 64,
+            stream_slow: Default::default(),
+            stream_max_per_event: None,
+            retention: Default::default(),
+            tos_version: None,
+            tos_url: None,
+            instance_name: None,
+            instance_contact: None,
+            privacy_url: None,
+            public_url: None,
+            blob_store: Default::default(),
+            ordering_experiment: Default::default(),
+            trending_gravity: 1.5,
+            policy: Default::default(),
+            audit: 0,
+            audit_days: 365,
+            inbound_email_domain: None,
+            inbound_email_token: None,
+            survey_key: None,
+            survey_fields: Default::default(),
+            summarizer: Default::default(),
+            summarizer_model: None,
+            openai_api_key: None,
+            anomaly_burst: 10,
+            anomaly_threshold: 75,
+        }
+    }
+}
+
+/// Parse a list of `name=value` pairs, skipping (and complaining about) malformed ones.
+fn parse_pairs<T: FromStr>(s: &str) -> HashMap<String, T> {
+    s.split(',')
+        .filter(|kv| !kv.trim().is_empty())
+        .filter_map(|kv| {
+            let parsed = kv
+                .split_once('=')
+                .and_then(|(k, v)| Some((k.trim().to_string(), v.trim().parse().ok()?)));
+            if parsed.is_none() {
+                warn!(entry = kv, "ignoring malformed setting");
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Parse a list of `name=milliseconds` pairs.
+fn parse_durations(s: &str) -> HashMap<String, Duration> {
+    parse_pairs(s)
+        .into_iter()
+        .map(|(k, ms)| (k, Duration::from_millis(ms)))
+        .collect()
+}
+
+impl Config {
+    fn from_env() -> Self {
+        let var = |k| std::env::var(k).ok().filter(|v: &String| !v.is_empty());
+        let default = Config::default();
+        Config {
+            admin_token: var("ADMIN_TOKEN"),
+            timeout: var("TIMEOUT_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(default.timeout),
+            route_timeouts: var("ROUTE_TIMEOUTS")
+                .map(|v| parse_durations(&v))
+                .unwrap_or_default(),
+            max_concurrency: var("MAX_CONCURRENCY").and_then(|v| v.parse().ok()),
+            route_concurrency: var("ROUTE_CONCURRENCY")
+                .map(|v| parse_pairs(&v))
+                .unwrap_or_default(),
+            host_concurrency: var("HOST_CONCURRENCY").and_then(|v| v.parse().ok()),
+            hot_events: var("HOT_EVENTS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.hot_events),
+            hot_refresh: var("HOT_REFRESH_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(default.hot_refresh),
+            stale_for: var("STALE_FOR_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(default.stale_for),
+            text_cache: var("TEXT_CACHE")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.text_cache),
+            vote_surge: var("VOTE_SURGE")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.vote_surge),
+            vote_surge_window: var("VOTE_SURGE_WINDOW_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(default.vote_surge_window),
+            event_budget: var("EVENT_BUDGET")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.event_budget),
+            event_burst: var("EVENT_BURST")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.event_burst),
+            get_item_concurrency: var("GET_ITEM_CONCURRENCY").and_then(|v| v.parse().ok()),
+            dynamodb_endpoint: var("DYNAMODB_ENDPOINT"),
+            dynamodb_failover_region: var("DYNAMODB_FAILOVER_REGION"),
+            failover_after: var("FAILOVER_AFTER")
+                .and_then(|v| v.parse().ok())
+                .filter(|&n: &u32| n > 0)
+                .unwrap_or(default.failover_after),
+            failover_for: var("FAILOVER_FOR_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(default.failover_for),
+            alternator: matches!(var("ALTERNATOR").as_deref(), Some("1" | "true")),
+            mongodb_uri: var("MONGODB_URI"),
+            redis_url: var("REDIS_URL"),
+            redis_ttl: var("REDIS_TTL_HOURS")
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(|h| Duration::from_secs(h * 60 * 60))
+                .unwrap_or(default.redis_ttl),
+            list_cache_url: var("LIST_CACHE_URL"),
+            list_cache_ttl: var("LIST_CACHE_TTL_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(default.list_cache_ttl),
+            live_backlog: var("LIVE_BACKLOG")
+                .and_then(|v| v.parse().ok())
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(default.live_backlog),
+            stream_slow: var("STREAM_SLOW")
+                .and_then(|v| {
+                    v.parse()
+                        .map_err(|e: String| warn!(error = e, "ignoring malformed STREAM_SLOW"))
+                        .ok()
+                })
+                .unwrap_or(default.stream_slow),
+            stream_max_per_event: var("STREAM_MAX_PER_EVENT").and_then(|v| v.parse().ok()),
+            retention: var("RETENTION")
+                .and_then(|v| {
+                    v.parse()
+                        .map_err(|e: String| warn!(error = e, "ignoring malformed RETENTION"))
+                        .ok()
+                })
+                .unwrap_or(default.retention),
+            tos_version: var("TOS_VERSION"),
+            tos_url: var("TOS_URL"),
+            instance_name: var("INSTANCE_NAME"),
+            instance_contact: var("INSTANCE_CONTACT"),
+            privacy_url: var("PRIVACY_URL"),
+            public_url: var("PUBLIC_URL").map(|u| u.trim_end_matches('/').to_string()),
+            blob_store: var("BLOB_STORE")
+                .and_then(|v| {
+                    v.parse()
+                        .map_err(|e: String| warn!(error = e, "ignoring malformed BLOB_STORE"))
+                        .ok()
+                })
+                .unwrap_or(default.blob_store),
+            ordering_experiment: var("ORDERING_EXPERIMENT")
+                .and_then(|v| {
+                    v.parse()
+                        .map_err(|e: String| {
+                            warn!(error = e, "ignoring malformed ORDERING_EXPERIMENT")
+                        })
+                        .ok()
+                })
+                .unwrap_or(default.ordering_experiment),
+            trending_gravity: var("TRENDING_GRAVITY")
+                .and_then(|v| v.parse().ok())
+                .filter(|&g: &f64| g.is_finite() && g >= 0.0)
+                .unwrap_or(default.trending_gravity),
+            // the server won't start with a malformed policy (see policy::verify), and anything
+            // else shouldn't take it to mean that everything is open
+            policy: var("POLICY")
+                .map(|v| v.parse().unwrap_or_else(super::policy::Policy::broken))
+                .unwrap_or(default.policy),
+            audit: var("AUDIT")
+                .and_then(|v| v.parse().ok())
+                .map(|p: u32| p.min(100))
+                .unwrap_or(default.audit),
+            audit_days: var("AUDIT_DAYS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.audit_days),
+            inbound_email_domain: var("INBOUND_EMAIL_DOMAIN"),
+            inbound_email_token: var("INBOUND_EMAIL_TOKEN"),
+            survey_key: var("SURVEY_KEY"),
+            survey_fields: var("SURVEY_FIELDS")
+                .and_then(|v| {
+                    v.parse()
+                        .map_err(|e: String| warn!(error = e, "ignoring malformed SURVEY_FIELDS"))
+                        .ok()
+                })
+                .unwrap_or(default.survey_fields),
+            summarizer: var("SUMMARIZER")
+                .and_then(|v| {
+                    v.parse()
+                        .map_err(|e: String| warn!(error = e, "ignoring malformed SUMMARIZER"))
+                        .ok()
+                })
+                .unwrap_or(default.summarizer),
+            summarizer_model: var("SUMMARIZER_MODEL"),
+            openai_api_key: var("OPENAI_API_KEY"),
+            anomaly_burst: var("ANOMALY_BURST")
+                .and_then(|v| v.parse().ok())
+                .filter(|&n: &usize| n >= 2)
+                .unwrap_or(default.anomaly_burst),
+            anomaly_threshold: var("ANOMALY_THRESHOLD")
+                .and_then(|v| v.parse().ok())
+                .map(|t: u32| t.min(100))
+                .unwrap_or(default.anomaly_threshold),
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub(super) fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let d = parse_durations("list=2000, ask = 8000,,bogus,vote=soon");
+        assert_eq!(d.len(), 2);
+        assert_eq!(d["list"], Duration::from_secs(2));
+        assert_eq!(d["ask"], Duration::from_secs(8));
+    }
+
+    #[test]
+    fn counts() {
+        let c: HashMap<String, usize> = parse_pairs("list=50,vote=-1,ask=10");
+        assert_eq!(c.len(), 2);
+        assert_eq!(c["list"], 50);
+        assert_eq!(c["ask"], 10);
+    }
+}
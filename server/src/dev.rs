@@ -0,0 +1,203 @@
+//! A dashboard for frontend development at `/dev`.
+//!
+//! It shows everything the in-memory backend holds, and has buttons to add a seeded event, move
+//! the [clock](super::clock) forward, and make the API slow or flaky, so that UI states that are
+//! hard to reach by hand can be looked at. Only debug builds with the `dev` feature have it.
+
+use super::{Backend, Local};
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Router,
+};
+use http::StatusCode;
+use rand::Rng;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Faults {
+    /// The percentage of API requests that fail with a 500.
+    fail: u8,
+    /// How long every API request is held up for, in milliseconds.
+    delay_ms: u64,
+}
+
+static FAULTS: Mutex<Faults> = Mutex::new(Faults {
+    fail: 0,
+    delay_ms: 0,
+});
+
+pub(super) fn routes<B>() -> Router<Backend, B>
+where
+    B: axum::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<axum::BoxError>,
+{
+    Router::new()
+        .route("/", get(dashboard))
+        .route("/seed", post(seed))
+        .route("/clock", post(clock))
+        .route("/faults", post(faults))
+}
+
+/// Delay and fail API requests as configured on the dashboard.
+pub(super) async fn inject<B>(req: Request<B>, next: Next<B>) -> Response {
+    let faults = *FAULTS.lock().unwrap();
+    if faults.delay_ms != 0 {
+        tokio::time::sleep(Duration::from_millis(faults.delay_ms)).await;
+    }
+    if rand::thread_rng().gen_range(0..100) < faults.fail {
+        debug!(path = %req.uri().path(), "injecting failure");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    next.run(req).await
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn number(q: &HashMap<&'static str, AttributeValue>, field: &str) -> u64 {
+    q.get(field)
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+fn render(local: &Local) -> String {
+    let mut events: Vec<_> = local.events.iter().collect();
+    events.sort();
+
+    let mut out = String::new();
+    for (eid, secret) in events {
+        let qids = local.questions_by_eid.get(eid).map_or(&[][..], |q| &q[..]);
+        let changes = local.changes.get(eid).map_or(0, |log| log.len());
+        let _ = write!(
+            out,
+            "<h2><a href=\"/event/{eid}/{secret}\">{eid}</a></h2>\
+             <p>{} questions, {changes} changes</p>\
+             <table><tr><th>votes</th><th>hidden</th><th>answered</th><th>asked</th><th>text</th></tr>",
+            qids.len(),
+            secret = escape(secret),
+        );
+        let mut questions: Vec<_> = qids
+            .iter()
+            .filter_map(|qid| local.questions.get(qid))
+            .collect();
+        questions.sort_by_key(|q| std::cmp::Reverse(number(q, "votes")));
+        for q in questions {
+            let b = |k| q.get(k).and_then(|v| v.as_bool().ok()).copied() == Some(true);
+            let text = q.get("text").and_then(|v| v.as_s().ok());
+            let _ = write!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                number(q, "votes"),
+                b("hidden"),
+                b("answered"),
+                number(q, "when"),
+                escape(text.map_or("", |t| t.as_str())),
+            );
+        }
+        out.push_str("</table>");
+    }
+    out
+}
+
+async fn dashboard(State(backend): State<Backend>) -> Html<String> {
+    let contents = match &backend {
+        Backend::Local(local) => render(&local.read().unwrap()),
+        _ => String::from("<p>The server isn't using the in-memory backend.</p>"),
+    };
+    let now = super::clock::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let offset = super::clock::offset().as_secs() / 60;
+    let Faults { fail, delay_ms } = *FAULTS.lock().unwrap();
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><title>wewerewondering dev</title>
+<style>body {{ font-family: sans-serif; }} td, th {{ padding: 0 .5em; text-align: left; }}</style>
+</head>
+<body>
+<h1>wewerewondering dev</h1>
+<form method="post" action="/dev/seed">
+  <input type="number" name="questions" value="20" min="0"> questions
+  <button>Add seeded event</button>
+</form>
+<form method="post" action="/dev/clock">
+  Clock is at {now} ({offset} minutes ahead).
+  <input type="number" name="minutes" value="60" min="0"> minutes
+  <button>Fast-forward</button>
+</form>
+<form method="post" action="/dev/faults">
+  Fail <input type="number" name="fail" value="{fail}" min="0" max="100">% of API requests,
+  and delay them all by <input type="number" name="delay_ms" value="{delay_ms}" min="0"> ms.
+  <button>Inject</button>
+</form>
+{contents}
+</body>
+</html>"#
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct Seed {
+    questions: usize,
+}
+
+async fn seed(
+    State(backend): State<Backend>,
+    Form(Seed { questions }): Form<Seed>,
+) -> Result<Redirect, StatusCode> {
+    let params = super::seed::Params {
+        questions,
+        votes: questions * 10,
+        ..Default::default()
+    };
+    match super::seed::event(&backend, &params).await {
+        Ok((eid, _)) => {
+            info!(%eid, "seeded event");
+            Ok(Redirect::to("/dev"))
+        }
+        Err(e) => {
+            error!(error = %e, "failed to seed event");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Clock {
+    minutes: u64,
+}
+
+async fn clock(Form(Clock { minutes }): Form<Clock>) -> Redirect {
+    super::clock::advance(Duration::from_secs(minutes * 60));
+    Redirect::to("/dev")
+}
+
+async fn faults(Form(faults): Form<Faults>) -> Redirect {
+    *FAULTS.lock().unwrap() = Faults {
+        fail: faults.fail.min(100),
+        ..faults
+    };
+    Redirect::to("/dev")
+}
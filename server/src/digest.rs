@@ -0,0 +1,582 @@
+//! Digests of what's new in an event, for hosts who'd rather not keep it open in a tab.
+//!
+//! Events that collect questions over days (like an AMA) don't need watching the whole time, but
+//! the host should still hear about new questions before they pile up. The host can subscribe with
+//! `POST /api/event/:eid/digest/:secret` and `{"webhook": "https://...", "every": 86400}`, after
+//! which the host gets a digest at most every `every` seconds (an hour at the least) of the
+//! questions asked since the last one that are still waiting to be answered, and how many were
+//! answered or hidden in the meantime. `{"webhook": null}` unsubscribes. Digests are worked out
+//! from the event's change log, so they pick up where the last one left off.
+//!
+//! Digests are posted to the webhook as JSON, including a plain-text summary as `text` so that
+//! chat webhooks (like Slack's) can show them as they are. Anything that takes a webhook can turn
+//! them into emails. The same webhook also gets [alerts](super::surge) about questions that are
+//! suddenly getting lots of votes.
+//!
+//! Nothing runs in the background (the API runs as a Lambda), so digests are sent by the `digest`
+//! command, which is meant to run on a schedule (every 15 minutes, say). Subscriptions, and where
+//! each one's digests are up to, are kept in the [blob store](super::blobs), which therefore has to
+//! be one the command can see too. Subscriptions of events that are gone are removed, and frozen
+//! events don't get digests until they're unfrozen.
+
+use super::{blobs::BlobStore, changes::Change, Backend};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use bytes::Bytes;
+use http::{header, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt::Write, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Digests can't come more often than this, in seconds.
+const MIN_EVERY: u64 = 60 * 60;
+/// Nor less often than this.
+const MAX_EVERY: u64 = 7 * 24 * 60 * 60;
+/// Webhook URLs can't be longer than this.
+const MAX_WEBHOOK: usize = 512;
+/// A digest doesn't list more questions than this, and says how many more there are instead.
+const MAX_LISTED: usize = 50;
+
+/// Where an event's digests go, how often, and what's been covered so far.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Subscription {
+    webhook: String,
+    every: u64,
+    /// The sequence number of the last change the previous digest covered.
+    seq: u64,
+    /// When the previous digest was sent (or the host subscribed), in seconds since the epoch.
+    sent: u64,
+}
+
+fn key(eid: &Uuid) -> String {
+    format!("digests/{eid}.json")
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+async fn subscription(store: &BlobStore, eid: &Uuid) -> Option<Subscription> {
+    match store.get(&key(eid)).await {
+        Ok(blob) => blob.and_then(|blob| {
+            serde_json::from_slice(&blob.data)
+                .map_err(|e| error!(%eid, error = %e, "found malformed digest subscription"))
+                .ok()
+        }),
+        Err(e) => {
+            error!(%eid, error = %e, "failed to read digest subscription");
+            None
+        }
+    }
+}
+
+async fn save(store: &BlobStore, eid: &Uuid, sub: &Subscription) -> Result<(), StatusCode> {
+    let data = Bytes::from(serde_json::to_vec(sub).expect("subscriptions always serialize"));
+    store
+        .put_bytes(&key(eid), "application/json", data)
+        .await
+        .map_err(|e| {
+            error!(%eid, error = %e, "failed to store digest subscription");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Post `alert` to the webhook `eid`'s host gets digests at, if they subscribed to them.
+///
+/// `text` is the plain-text version, for chat webhooks. Alerts that don't get through aren't
+/// tried again, since they'd be old news by then.
+pub(super) async fn alert(eid: &Uuid, mut alert: serde_json::Value, text: String) {
+    let store = super::blobs::store().await;
+    let Some(sub) = subscription(store, eid).await else {
+        return;
+    };
+    alert["event"] = eid.to_string().into();
+    alert["text"] = text.into();
+    let client =
+        hyper::Client::builder().build::<_, hyper::Body>(aws_smithy_client::conns::https());
+    let req = Request::post(&sub.webhook)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(alert.to_string()))
+        .expect("webhooks are checked when subscribing");
+    match client.request(req).await {
+        Ok(res) if res.status().is_success() => debug!(%eid, "sent alert"),
+        Ok(res) => warn!(%eid, status = %res.status(), "digest webhook refused alert"),
+        Err(e) => warn!(%eid, error = %e, "failed to send alert"),
+    }
+}
+
+/// Where and how often the host wants digests, if at all.
+#[derive(Debug, Deserialize)]
+pub(super) struct Subscribe {
+    webhook: Option<String>,
+    #[serde(default = "daily")]
+    every: u64,
+}
+
+fn daily() -> u64 {
+    24 * 60 * 60
+}
+
+/// Subscribe the event's host to digests, or unsubscribe them.
+pub(super) async fn digest(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Subscribe>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    subscribe(super::blobs::store().await, &dynamo, &eid, &secret, req).await
+}
+
+async fn subscribe(
+    store: &BlobStore,
+    dynamo: &Backend,
+    eid: &Uuid,
+    secret: &str,
+    req: Subscribe,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(dynamo, eid, secret).await?;
+    let Some(webhook) = req.webhook else {
+        if let Err(e) = store.delete(&key(eid)).await {
+            error!(%eid, error = %e, "failed to remove digest subscription");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        info!(%eid, "host unsubscribed from digests");
+        return Ok(Json(serde_json::json!({ "subscribed": false })));
+    };
+    // digests are for the host, so they shouldn't be sent anywhere in the clear
+    if !webhook.starts_with("https://")
+        || webhook.len() > MAX_WEBHOOK
+        || webhook.parse::<http::Uri>().is_err()
+    {
+        warn!(%eid, webhook, "refused digest webhook");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !(MIN_EVERY..=MAX_EVERY).contains(&req.every) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let sub = match subscription(store, eid).await {
+        // changing where or how often digests go doesn't skip anything
+        Some(sub) => Subscription {
+            webhook,
+            every: req.every,
+            ..sub
+        },
+        None => Subscription {
+            webhook,
+            every: req.every,
+            seq: meta.version,
+            sent: now(),
+        },
+    };
+    save(store, eid, &sub).await?;
+    info!(%eid, every = sub.every, "host subscribed to digests");
+    Ok(Json(serde_json::json!({
+        "subscribed": true,
+        "every": sub.every,
+        "next": sub.sent + sub.every,
+    })))
+}
+
+/// A question that's waiting to be answered, as it's listed in a digest.
+#[derive(Debug, Serialize)]
+struct Waiting {
+    qid: Uuid,
+    text: String,
+    who: Option<String>,
+}
+
+/// What happened in an event since its last digest.
+#[derive(Debug, Default, Serialize)]
+struct Summary {
+    /// The sequence number of the last change this covers.
+    #[serde(skip)]
+    seq: u64,
+    waiting: Vec<Waiting>,
+    /// How many more questions are waiting than are listed.
+    more: usize,
+    answered: usize,
+    hidden: usize,
+}
+
+impl Summary {
+    fn is_empty(&self) -> bool {
+        self.waiting.is_empty() && self.more == 0 && self.answered == 0 && self.hidden == 0
+    }
+
+    fn text(&self, eid: &Uuid) -> String {
+        let mut out = format!("Since the last digest for event {eid}:\n");
+        let n = self.waiting.len() + self.more;
+        let plural = |n| if n == 1 { "" } else { "s" };
+        let _ = writeln!(out, "{n} new question{} waiting", plural(n));
+        for q in &self.waiting {
+            let text = q.text.replace('\n', "\n  ");
+            let _ = match &q.who {
+                Some(who) => writeln!(out, "* {text} (asked by {who})"),
+                None => writeln!(out, "* {text}"),
+            };
+        }
+        if self.more != 0 {
+            let _ = writeln!(out, "* and {} more", self.more);
+        }
+        let _ = writeln!(
+            out,
+            "{} question{} answered, {} hidden",
+            self.answered,
+            plural(self.answered),
+            self.hidden
+        );
+        out
+    }
+}
+
+/// Work out what happened in `eid` after the change numbered `since`.
+async fn summarize(
+    dynamo: &Backend,
+    eid: &Uuid,
+    since: u64,
+) -> Result<Summary, aws_sdk_dynamodb::Error> {
+    let mut asked = Vec::new();
+    let mut answered = HashSet::new();
+    let mut hidden = HashSet::new();
+    let mut seq = since;
+    loop {
+        let page = dynamo
+            .changes(eid, seq)
+            .await
+            .map_err(aws_sdk_dynamodb::Error::from)?;
+        let items = page.items().unwrap_or_default();
+        for item in items {
+            let Some(change) = super::changes::to_json(item) else {
+                error!(%eid, ?item, "found malformed change");
+                continue;
+            };
+            seq = change["seq"].as_u64().unwrap_or(seq);
+            match Change::from_item(item) {
+                Some(Change::QuestionAsked { qid }) => asked.push(qid),
+                Some(Change::QuestionAnswered { qid, set }) => {
+                    if set {
+                        answered.insert(qid)
+                    } else {
+                        answered.remove(&qid)
+                    };
+                }
+                Some(Change::QuestionHidden { qid, set }) => {
+                    if set {
+                        hidden.insert(qid)
+                    } else {
+                        hidden.remove(&qid)
+                    };
+                }
+                _ => {}
+            }
+        }
+        if items.is_empty() {
+            break;
+        }
+    }
+
+    let waiting: Vec<_> = asked
+        .into_iter()
+        .filter(|qid| !answered.contains(qid) && !hidden.contains(qid))
+        .collect();
+    let (listed, rest) = waiting.split_at(waiting.len().min(MAX_LISTED));
+    let mut summary = Summary {
+        seq,
+        more: rest.len(),
+        answered: answered.len(),
+        hidden: hidden.len(),
+        ..Default::default()
+    };
+    if !listed.is_empty() {
+        let texts: std::collections::HashMap<_, _> = dynamo
+            .questions(listed)
+            .await?
+            .responses()
+            .and_then(|r| r.get("questions"))
+            .into_iter()
+            .flatten()
+            .filter_map(super::questions::to_json)
+            .collect();
+        summary.waiting = listed
+            .iter()
+            .filter_map(|qid| {
+                // gone already, say because it expired
+                let q = texts.get(&qid.to_string())?;
+                Some(Waiting {
+                    qid: *qid,
+                    text: q["text"].as_str().unwrap_or_default().trim().to_string(),
+                    who: q["who"].as_str().map(String::from),
+                })
+            })
+            .collect();
+    }
+    Ok(summary)
+}
+
+/// What the `digest` command did.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub(super) struct Report {
+    pub(super) subscriptions: usize,
+    /// Events that were sent a digest.
+    pub(super) sent: Vec<Uuid>,
+    /// Events whose digest couldn't be sent, and will be tried again next time.
+    pub(super) failed: Vec<Uuid>,
+    /// Subscriptions that were removed because their event is gone.
+    pub(super) removed: Vec<Uuid>,
+}
+
+/// Send every digest that's due.
+pub(super) async fn run(dynamo: &Backend, store: &BlobStore) -> Result<Report, StatusCode> {
+    let client =
+        hyper::Client::builder().build::<_, hyper::Body>(aws_smithy_client::conns::https());
+    let keys = store.list("digests").await.map_err(|e| {
+        error!(error = %e, "failed to list digest subscriptions");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut report = Report::default();
+    for key in keys {
+        let Some(eid) = key
+            .strip_prefix("digests/")
+            .and_then(|k| k.strip_suffix(".json"))
+            .and_then(|eid| Uuid::parse_str(eid).ok())
+        else {
+            continue;
+        };
+        let Some(mut sub) = subscription(store, &eid).await else {
+            continue;
+        };
+        report.subscriptions += 1;
+        let now = now();
+        if now < sub.sent + sub.every {
+            continue;
+        }
+        match super::get_meta(dynamo, &eid).await {
+            Ok(meta) if meta.frozen() => continue,
+            Ok(_) => {}
+            Err(StatusCode::NOT_FOUND) => {
+                if let Err(e) = store.delete(&key).await {
+                    error!(%eid, error = %e, "failed to remove digest subscription");
+                } else {
+                    report.removed.push(eid);
+                }
+                continue;
+            }
+            Err(status) => {
+                error!(%eid, %status, "skipping digest for event that couldn't be checked");
+                report.failed.push(eid);
+                continue;
+            }
+        }
+
+        let summary = match summarize(dynamo, &eid, sub.seq).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                error!(%eid, error = %e, "failed to summarize event for digest");
+                report.failed.push(eid);
+                continue;
+            }
+        };
+        if summary.is_empty() {
+            // nothing to say, so the next digest can go as soon as there is
+            continue;
+        }
+        let mut body = serde_json::to_value(&summary).expect("summaries always serialize");
+        body["event"] = eid.to_string().into();
+        body["text"] = summary.text(&eid).into();
+        let req = Request::post(&sub.webhook)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(body.to_string()))
+            .expect("webhooks are checked when subscribing");
+        match client.request(req).await {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => {
+                warn!(%eid, status = %res.status(), "digest webhook refused digest");
+                report.failed.push(eid);
+                continue;
+            }
+            Err(e) => {
+                warn!(%eid, error = %e, "failed to send digest");
+                report.failed.push(eid);
+                continue;
+            }
+        }
+        sub.seq = summary.seq;
+        sub.sent = now;
+        // if this doesn't stick, the next digest repeats this one, which beats skipping it
+        if save(store, &eid, &sub).await.is_err() {
+            report.failed.push(eid);
+        } else {
+            info!(%eid, waiting = summary.waiting.len() + summary.more, "sent digest");
+            report.sent.push(eid);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blobs::Location;
+    use std::sync::{Arc, Mutex};
+
+    /// A webhook that keeps what it's sent.
+    fn webhook() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let got = Arc::new(Mutex::new(Vec::new()));
+        let sink = got.clone();
+        let app = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move |Json(v): Json<serde_json::Value>| {
+                let sink = sink.clone();
+                async move { sink.lock().unwrap().push(v) }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        (url, got)
+    }
+
+    async fn inner(backend: Backend) {
+        let store = BlobStore::open(&Location::Memory).await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = |body: &str| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: Some(String::from("Alice")),
+                }),
+            )
+        };
+        ask("asked before subscribing").await.unwrap();
+
+        let sub = |webhook: Option<&str>, every| {
+            subscribe(
+                &store,
+                &backend,
+                &eid,
+                secret,
+                Subscribe {
+                    webhook: webhook.map(String::from),
+                    every,
+                },
+            )
+        };
+        assert_eq!(
+            sub(Some("http://example.com/hook"), daily())
+                .await
+                .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            sub(Some("https://example.com/hook"), 60).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let Json(s) = sub(Some("https://example.com/hook"), daily())
+            .await
+            .unwrap();
+        assert_eq!(s["subscribed"], true);
+
+        let q = ask("is anyone reading these?").await.unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        ask("this one stays").await.unwrap();
+        let hide = Uuid::parse_str(ask("hide me").await.unwrap()["id"].as_str().unwrap()).unwrap();
+        crate::toggle::toggle(
+            Path((
+                eid,
+                secret.to_string(),
+                hide,
+                crate::toggle::Property::Hidden,
+            )),
+            State(backend.clone()),
+            axum::extract::Query(Default::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+
+        // not due yet
+        let report = run(&backend, &store).await.unwrap();
+        assert_eq!(report.subscriptions, 1);
+        assert!(report.sent.is_empty());
+
+        // the test webhook isn't https, so it's put in place behind the host's back
+        let (url, got) = webhook();
+        let mut s = subscription(&store, &eid).await.unwrap();
+        s.webhook = url;
+        s.sent -= s.every;
+        save(&store, &eid, &s).await.unwrap();
+        let report = run(&backend, &store).await.unwrap();
+        assert_eq!(report.sent, [eid]);
+        let digest = got.lock().unwrap().pop().unwrap();
+        assert_eq!(digest["event"], eid.to_string());
+        assert_eq!(digest["hidden"], 1);
+        let waiting = digest["waiting"].as_array().unwrap();
+        assert_eq!(waiting.len(), 2, "{digest}");
+        assert_eq!(waiting[0]["qid"], qid.to_string());
+        assert_eq!(waiting[0]["who"], "Alice");
+        let text = digest["text"].as_str().unwrap();
+        assert!(text.contains("2 new questions waiting"), "{text}");
+        assert!(
+            text.contains("* is anyone reading these? (asked by Alice)"),
+            "{text}"
+        );
+        assert!(!text.contains("asked before subscribing"), "{text}");
+
+        // the next one only has what's new since
+        let mut s = subscription(&store, &eid).await.unwrap();
+        s.sent -= s.every;
+        save(&store, &eid, &s).await.unwrap();
+        let report = run(&backend, &store).await.unwrap();
+        assert!(report.sent.is_empty());
+        assert!(got.lock().unwrap().is_empty());
+
+        backend.delete(&eid).await;
+        let report = run(&backend, &store).await.unwrap();
+        assert_eq!(report.removed, [eid]);
+        assert_eq!(subscription(&store, &eid).await, None);
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,325 @@
+//! Asking questions by email, for audiences who won't open a web page.
+//!
+//! With `INBOUND_EMAIL_DOMAIN` (like `qa.example.com`) pointed at an email provider's inbound
+//! processing, and the provider told to post what it receives to
+//! `POST /api/email/<INBOUND_EMAIL_TOKEN>`, emails sent to `<event id>@qa.example.com` become
+//! questions in that event (events don't have any other name to send email to). The posts are
+//! expected in the JSON Postmark sends for inbound email, which other providers can be made to
+//! send too: the question is the reply text without quotes or signature, or the subject if
+//! there's nothing else.
+//!
+//! Questions from email go through the same checks as the ones asked on the site, and are
+//! anonymous: senders are only ever known by a hash of their address, which is what each gets
+//! `PER_SENDER` questions an hour by. Emails that can't become questions are refused with a 403,
+//! which is what tells providers not to try again; anything else that goes wrong is worth another
+//! try, and gets a 5xx. Emails are bigger than anything else sent to the API, so this route takes
+//! bodies up to `MAX_BODY` bytes.
+
+use super::Backend;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::SystemTime,
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The largest inbound email post that's read, in bytes.
+pub(super) const MAX_BODY: usize = 256 * 1024;
+/// Questions from email can't be longer than this, in bytes.
+const MAX_QUESTION: usize = 1000;
+/// How many questions a single sender may ask by email in an hour.
+const PER_SENDER: usize = 10;
+/// Keep track of this many senders at most.
+const MAX_SENDERS: usize = 4096;
+
+/// An inbound email, as Postmark posts it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct Inbound {
+    from: String,
+    #[serde(default)]
+    to: String,
+    /// Who the email was actually delivered to, which `To` may not say if it was Bcc'd.
+    #[serde(default)]
+    original_recipient: String,
+    #[serde(default)]
+    subject: String,
+    #[serde(default)]
+    text_body: String,
+    /// The reply without the email it's replying to, if the provider could tell.
+    #[serde(default)]
+    stripped_text_reply: String,
+}
+
+/// The event the email was sent to, if it was sent to one.
+fn event(inbound: &Inbound) -> Option<Uuid> {
+    [&inbound.original_recipient, &inbound.to]
+        .into_iter()
+        .flat_map(|to| to.split([',', ' ', '<', '>']))
+        .filter_map(|address| address.trim().split_once('@'))
+        .find_map(|(local, _)| Uuid::parse_str(local).ok())
+}
+
+/// The question in the email, without quoted text or a signature.
+fn question(inbound: &Inbound) -> String {
+    let text = if inbound.stripped_text_reply.trim().is_empty() {
+        &inbound.text_body
+    } else {
+        &inbound.stripped_text_reply
+    };
+    let text = text.replace("\r\n", "\n");
+    let text = text.split("\n-- \n").next().unwrap_or_default();
+    let text: Vec<_> = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('>'))
+        .collect();
+    let text = text.join("\n").trim().to_string();
+    if text.is_empty() {
+        inbound.subject.trim().to_string()
+    } else {
+        text
+    }
+}
+
+/// Who sent the email, without keeping their address around.
+fn sender(from: &str) -> u64 {
+    let address = from
+        .rsplit_once('<')
+        .map_or(from, |(_, address)| address.trim_end_matches('>'));
+    let mut hasher = DefaultHasher::new();
+    address.trim().to_lowercase().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// How many questions each sender asked in the current hour.
+static ASKED: Mutex<(u64, BTreeMap<u64, usize>)> = Mutex::new((0, BTreeMap::new()));
+
+/// Whether `sender` may ask another question in `hour`.
+fn admit(asked: &mut (u64, BTreeMap<u64, usize>), sender: u64, hour: u64) -> bool {
+    let (current, by_sender) = asked;
+    if *current != hour {
+        *current = hour;
+        by_sender.clear();
+    }
+    if by_sender.len() >= MAX_SENDERS && !by_sender.contains_key(&sender) {
+        // make room by forgetting whoever has asked the least, so that sending from ever new
+        // addresses only ever forgets about those, and never about who's close to their limit
+        let least = by_sender
+            .iter()
+            .min_by_key(|(_, n)| **n)
+            .map(|(sender, _)| *sender)
+            .expect("there are senders when there are too many");
+        by_sender.remove(&least);
+    }
+    let n = by_sender.entry(sender).or_default();
+    if *n >= PER_SENDER {
+        return false;
+    }
+    *n += 1;
+    true
+}
+
+fn hour() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / (60 * 60)
+}
+
+pub(super) async fn email(
+    Path(token): Path<String>,
+    State(dynamo): State<Backend>,
+    Json(inbound): Json<Inbound>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let Some(ref expected) = super::config::config().inbound_email_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if token != *expected {
+        warn!("inbound email with incorrect token");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    receive(&dynamo, inbound).await
+}
+
+async fn receive(
+    dynamo: &Backend,
+    inbound: Inbound,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let sender = sender(&inbound.from);
+    let Some(eid) = event(&inbound) else {
+        debug!(sender, "inbound email wasn't for an event");
+        super::metrics::incr("email.unaddressed");
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let body = question(&inbound);
+    if body.len() > MAX_QUESTION {
+        debug!(%eid, sender, "inbound email too long to be a question");
+        super::rejections::reject(&eid, "too_long");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !admit(&mut ASKED.lock().unwrap(), sender, hour()) {
+        warn!(%eid, sender, "too many questions by email");
+        super::rejections::reject(&eid, "email_rate");
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let asked = super::ask::ask(
+        Path(eid),
+        State(dynamo.clone()),
+        http::HeaderMap::new(),
+        Json(super::ask::Question { body, asker: None }),
+    )
+    .await;
+    match asked {
+        Ok(q) => {
+            info!(%eid, sender, qid = %q["id"], "asked question by email");
+            super::metrics::incr("email.asked");
+            Ok(q)
+        }
+        // the same email isn't going to do any better next time
+        Err(status) if status.is_client_error() => Err(StatusCode::FORBIDDEN),
+        Err(status) => Err(status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+
+        let q = receive(
+            &backend,
+            Inbound {
+                from: String::from("Alice <alice@example.com>"),
+                to: format!("\"Q&A\" <{eid}@qa.example.com>"),
+                subject: String::from("Question"),
+                text_body: String::from("what happens to my address?\r\n\r\n-- \r\nAlice\r\n"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let qid = q["id"].as_str().unwrap();
+        let Json(texts) = crate::questions::questions(
+            Path(qid.to_string()),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .2
+        .unwrap();
+        assert_eq!(texts[qid]["text"], "what happens to my address?");
+        assert!(texts[qid].get("who").is_none(), "{texts}");
+
+        // not for an event, or not a question
+        for inbound in [
+            Inbound {
+                from: String::from("bob@example.com"),
+                to: String::from("hello@qa.example.com"),
+                subject: String::from("is anyone there?"),
+                ..Default::default()
+            },
+            Inbound {
+                from: String::from("bob@example.com"),
+                original_recipient: format!("{eid}@qa.example.com"),
+                subject: String::from("hello"),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(
+                receive(&backend, inbound).await.unwrap_err(),
+                StatusCode::FORBIDDEN
+            );
+        }
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[test]
+    fn reads_emails() {
+        let eid = Uuid::new_v4();
+        let inbound = Inbound {
+            from: String::from("Alice <Alice@Example.com>"),
+            to: format!("hello@example.com, Q&A <{eid}@qa.example.com>"),
+            subject: String::from("Re: the talk"),
+            text_body: String::from("whatever"),
+            stripped_text_reply: String::from(
+                "what about\nthe second slide?\n\n> On Monday, someone wrote:\n> hi\n",
+            ),
+            ..Default::default()
+        };
+        assert_eq!(event(&inbound), Some(eid));
+        assert_eq!(question(&inbound), "what about\nthe second slide?");
+        assert_eq!(sender(&inbound.from), sender("alice@example.com"));
+
+        let only_subject = Inbound {
+            subject: String::from(" what time is it? "),
+            ..Default::default()
+        };
+        assert_eq!(question(&only_subject), "what time is it?");
+    }
+
+    #[test]
+    fn limits_senders() {
+        let mut asked = (0, BTreeMap::new());
+        for _ in 0..PER_SENDER {
+            assert!(admit(&mut asked, 1, 7));
+        }
+        assert!(!admit(&mut asked, 1, 7));
+        assert!(admit(&mut asked, 2, 7));
+
+        // senders that only come around once don't make room for those at their limit
+        for sender in 3..3 + MAX_SENDERS as u64 + 100 {
+            assert!(admit(&mut asked, sender, 7));
+        }
+        assert_eq!(asked.1.len(), MAX_SENDERS);
+        assert!(!admit(&mut asked, 1, 7));
+
+        // a new hour starts over
+        assert!(admit(&mut asked, 1, 8));
+    }
+}
@@ -32,8 +32,8 @@ impl Backend {
                     .await
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { events, .. } = &mut *local;
+                let local = local.read().unwrap();
+                let Local { events, .. } = &*local;
 
                 Ok(GetItemOutput::builder()
                     .set_item(events.get(eid).map(|_| {
@@ -44,6 +44,12 @@ impl Backend {
                     }))
                     .build())
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.event(eid).await,
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.event(eid).await,
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.event(eid).await,
         }
     }
 }
@@ -57,19 +63,22 @@ pub(super) async fn event(
 ) {
     match dynamo.event(&eid).await {
         Ok(v) => {
-            if let Some(_) = v.item() {
+            if v.item().is_some() {
                 (
-                    AppendHeaders([(header::CACHE_CONTROL, "max-age=864001")]),
-                    Ok(Json(serde_json::json!({}))),
+                    // the event itself never changes, but how many are following it does
+                    AppendHeaders([(header::CACHE_CONTROL, "max-age=60")]),
+                    Ok(Json(serde_json::json!({
+                        "attendees": super::presence::count(&eid),
+                    }))),
                 )
             } else {
                 warn!(%eid, "non-existing event");
-                return (
+                (
                     // it's relatively unlikely that an event uuid that didn't exist will start
                     // existing. but just in case, don't make it _too_ long.
                     AppendHeaders([(header::CACHE_CONTROL, "max-age=3600")]),
                     Err(http::StatusCode::NOT_FOUND),
-                );
+                )
             }
         }
         Err(e) => {
@@ -81,3 +90,15 @@ pub(super) async fn event(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn golden() {
+        let eid = Uuid::parse_str("0b8f6a52-3c1d-4e7a-9f25-6d4c8b1e7a30").unwrap();
+        let (backend, replay) = crate::golden::replay("event");
+        replay.check(super::event(Path(eid), State(backend)).await.1.unwrap().0);
+    }
+}
@@ -0,0 +1,373 @@
+//! Experiments with the order guests see questions in.
+//!
+//! Questions are normally listed by votes (`top`), which buries new questions under the ones that
+//! have been collecting votes since the start. Listing them by votes per hour of age (`trending`)
+//! might get more people voting, and `ORDERING_EXPERIMENT` (like `top=50,trending=50`) is how to
+//! find out. It gives each ordering a share of guests in percent, and any guests left over see
+//! `top`. Guests are put in one of a hundred buckets by hashing the token their browser pings and
+//! votes with, so they see the same ordering on every poll, from every instance.
+//!
+//! Clients find out which ordering they're in from `POST /api/experiment` (with `{"token": ...}`),
+//! which counts as the guest being exposed to it (`ordering.<ordering>.exposed`), and then list
+//! questions with `?order=<ordering>`. Lists don't know who's asking, so they're cached once per
+//! ordering like before. The outcomes compared are votes and pings from each ordering's guests
+//! (`ordering.<ordering>.votes` and `ordering.<ordering>.pings`), which are only counted while an
+//! experiment is running.
+//!
+//! A question's trending score is worked out whenever a list is, from its votes and how many hours
+//! ago it was asked, as `votes / (hours + 2) ^ gravity`. The gravity is 1.5 unless
+//! `TRENDING_GRAVITY` says otherwise. The lower it is, the longer votes keep counting, which suits
+//! events that go on for days better than the default, which is about right for an afternoon.
+//!
+//! Lists can also be put in `new` order (most recently asked first), which isn't much of an
+//! experiment but is a view hosts want. Hosts aren't in experiments, and pick the order of their
+//! list with `?sort=top|new|trending|fair` (which guests' lists take as well).
+//!
+//! In `fair` order, the list takes turns between the question with the most votes, the unanswered
+//! question that has waited longest, and the question with the most votes among those that are
+//! their asker's first, skipping questions that are already listed. That way, the people who
+//! don't have a crowd voting for them still get heard now and then. Only questions asked with an
+//! `X-Author` token (see [`super::mine`]), which the bundled client sends with every question, can
+//! be told to be someone's first, and questions asked
+//! since the list was last loaded into memory (see [`super::hot`]) count as not yet. Hosts can
+//! make any order their event's default (see [`super::order`]), which is mostly what `fair` is
+//! for.
+
+use super::hot::Question;
+use axum::response::Json;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// An order to list questions in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Ordering {
+    /// Most votes first.
+    #[default]
+    Top,
+    /// Most votes for their age first, so that new questions get a look in.
+    Trending,
+    /// Most recently asked first.
+    New,
+    /// Taking turns between most votes, longest waiting, and first questions.
+    Fair,
+}
+
+impl Ordering {
+    fn as_str(self) -> &'static str {
+        match self {
+            Ordering::Top => "top",
+            Ordering::Trending => "trending",
+            Ordering::New => "new",
+            Ordering::Fair => "fair",
+        }
+    }
+
+    /// Put `questions` in this order, given that it's `now` (in seconds since the epoch).
+    pub(super) fn sort(self, questions: &mut [(Uuid, Question)], now: u64) {
+        match self {
+            Ordering::Top => questions
+                .sort_unstable_by(|(aid, a), (bid, b)| b.votes.cmp(&a.votes).then(aid.cmp(bid))),
+            Ordering::Trending => {
+                let gravity = super::config::config().trending_gravity;
+                let score = |q: &Question| score(q, now, gravity);
+                questions.sort_unstable_by(|(aid, a), (bid, b)| {
+                    score(b).total_cmp(&score(a)).then(aid.cmp(bid))
+                })
+            }
+            Ordering::New => questions
+                .sort_unstable_by(|(aid, a), (bid, b)| b.asked.cmp(&a.asked).then(aid.cmp(bid))),
+            Ordering::Fair => fair(questions),
+        }
+    }
+}
+
+/// Put `questions` in `fair` order.
+fn fair(questions: &mut [(Uuid, Question)]) {
+    let mut top = questions.to_vec();
+    Ordering::Top.sort(&mut top, 0);
+
+    let mut waiting: Vec<_> = top.iter().filter(|(_, q)| !q.answered).copied().collect();
+    waiting.sort_by_key(|(_, q)| q.asked);
+
+    // each asker's first question is the one they asked earliest
+    let mut firsts: HashMap<u64, (u64, Uuid)> = HashMap::new();
+    for (qid, q) in &top {
+        if let Some(asker) = q.asker {
+            let first = firsts.entry(asker).or_insert((q.asked, *qid));
+            *first = (*first).min((q.asked, *qid));
+        }
+    }
+    let firsts: HashSet<_> = firsts.into_values().map(|(_, qid)| qid).collect();
+    let newcomers: Vec<_> = top
+        .iter()
+        .filter(|(qid, _)| firsts.contains(qid))
+        .copied()
+        .collect();
+
+    let lanes = [&top, &waiting, &newcomers];
+    let mut next = [0; 3];
+    let mut listed = HashSet::new();
+    let mut i = 0;
+    // every question is in the top lane, so this runs out when it does
+    while listed.len() < top.len() {
+        for (lane, at) in lanes.iter().zip(&mut next) {
+            while lane.get(*at).is_some_and(|(qid, _)| listed.contains(qid)) {
+                *at += 1;
+            }
+            if let Some(&q) = lane.get(*at) {
+                listed.insert(q.0);
+                questions[i] = q;
+                i += 1;
+            }
+        }
+    }
+}
+
+/// How much `q` is trending at `now`, when votes decay with age at the rate of `gravity`.
+fn score(q: &Question, now: u64, gravity: f64) -> f64 {
+    // like Hacker News: votes decay with age, and nothing starts out at infinity
+    let hours = now.saturating_sub(q.asked) as f64 / 3600.0;
+    q.votes as f64 / (hours + 2.0).powf(gravity)
+}
+
+impl fmt::Display for Ordering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Ordering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "top" => Ok(Ordering::Top),
+            "trending" => Ok(Ordering::Trending),
+            "new" => Ok(Ordering::New),
+            "fair" => Ok(Ordering::Fair),
+            o => Err(format!("unknown ordering `{o}`")),
+        }
+    }
+}
+
+/// How guests are split between orderings, as the percentage of buckets each one gets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Split {
+    shares: Vec<(Ordering, u8)>,
+}
+
+impl FromStr for Split {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut shares: Vec<(Ordering, u8)> = Vec::new();
+        for share in s.split(',').filter(|s| !s.trim().is_empty()) {
+            let Some((ordering, percent)) = share.split_once('=') else {
+                return Err(format!("`{share}` isn't of the form ordering=percent"));
+            };
+            let ordering: Ordering = ordering.parse()?;
+            let percent = percent
+                .trim()
+                .parse()
+                .map_err(|_| format!("`{percent}` isn't a percentage"))?;
+            if shares.iter().any(|&(o, _)| o == ordering) {
+                return Err(format!("`{ordering}` is in the split twice"));
+            }
+            shares.push((ordering, percent));
+        }
+        if shares.iter().map(|&(_, p)| u32::from(p)).sum::<u32>() > 100 {
+            return Err(String::from("the shares add up to more than 100%"));
+        }
+        Ok(Split { shares })
+    }
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (ordering, percent)) in self.shares.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{ordering}={percent}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Split {
+    /// Whether there's an experiment running at all.
+    pub(super) fn running(&self) -> bool {
+        !self.shares.is_empty()
+    }
+
+    fn ordering(&self, bucket: u8) -> Ordering {
+        let mut upto = 0;
+        for &(ordering, percent) in &self.shares {
+            upto += percent;
+            if bucket < upto {
+                return ordering;
+            }
+        }
+        Ordering::Top
+    }
+}
+
+/// Which of the hundred buckets the guest with `token` is in.
+fn bucket(token: &str) -> u8 {
+    // a fresh default hasher always uses the same keys, so every instance agrees
+    let mut hasher = DefaultHasher::new();
+    ("ordering", token).hash(&mut hasher);
+    (hasher.finish() % 100) as u8
+}
+
+/// Count `outcome` for the ordering the guest with `token` sees, if an experiment is running.
+pub(super) fn outcome(token: &str, outcome: &str) {
+    let split = &super::config::config().ordering_experiment;
+    if split.running() {
+        let ordering = split.ordering(bucket(token));
+        super::metrics::incr(format!("ordering.{ordering}.{outcome}"));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Enroll {
+    /// The same token the guest pings and votes with.
+    token: String,
+}
+
+pub(super) async fn experiment(
+    Json(enroll): Json<Enroll>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if enroll.token.is_empty() || enroll.token.len() > 64 {
+        warn!("experiment enrollment with bad token");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let split = &super::config::config().ordering_experiment;
+    let bucket = bucket(&enroll.token);
+    let ordering = split.ordering(bucket);
+    if split.running() {
+        super::metrics::incr(format!("ordering.{ordering}.exposed"));
+    }
+    Ok(Json(serde_json::json!({
+        "running": split.running(),
+        "bucket": bucket,
+        "ordering": ordering,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(votes: usize, asked: u64) -> Question {
+        Question {
+            votes,
+            hidden: false,
+            answered: false,
+            voters: 0,
+            asked,
+            merged_into: None,
+            asker: None,
+            reactions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn orders() {
+        let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+        let now = 10 * 3600;
+        let mut qs = [(new, q(3, now - 600)), (old, q(10, 0))];
+        Ordering::Top.sort(&mut qs, now);
+        assert_eq!(qs[0].0, old);
+        Ordering::Trending.sort(&mut qs, now);
+        assert_eq!(qs[0].0, new, "a few recent votes beat many old ones");
+        Ordering::Top.sort(&mut qs, now);
+        Ordering::New.sort(&mut qs, now);
+        assert_eq!(qs[0].0, new);
+        assert_eq!("new".parse(), Ok(Ordering::New));
+    }
+
+    #[test]
+    fn takes_turns() {
+        let asked = |by: u64, votes, asked| Question {
+            asker: Some(by),
+            ..q(votes, asked)
+        };
+        let qids: Vec<_> = (0..6).map(|_| Uuid::new_v4()).collect();
+        let mut qs = [
+            // a regular, who asked first, asks the popular questions
+            (qids[0], asked(1, 50, 10)),
+            (qids[1], asked(1, 40, 20)),
+            (qids[2], asked(1, 30, 30)),
+            // an old one that was already answered
+            (
+                qids[3],
+                Question {
+                    answered: true,
+                    ..q(20, 0)
+                },
+            ),
+            // someone new asks late, and has few votes
+            (qids[4], asked(2, 2, 50)),
+            (qids[5], q(1, 40)),
+        ];
+        Ordering::Fair.sort(&mut qs, 0);
+        let order: Vec<_> = qs.iter().map(|(qid, _)| *qid).collect();
+        // most votes, longest waiting (and unanswered), and first questions (the regular's first
+        // is already in), then around again
+        assert_eq!(
+            order,
+            [qids[0], qids[1], qids[4], qids[2], qids[5], qids[3]]
+        );
+        assert_eq!("fair".parse(), Ok(Ordering::Fair));
+    }
+
+    #[test]
+    fn decays() {
+        let now = 48 * 3600;
+        let (old, new) = (q(40, 0), q(3, now - 600));
+        assert!(score(&new, now, 1.5) > score(&old, now, 1.5));
+        // with less gravity, a day's worth of votes still counts for more
+        assert!(score(&new, now, 0.5) < score(&old, now, 0.5));
+        assert_eq!(score(&old, now, 0.0), 40.0);
+    }
+
+    #[test]
+    fn splits() {
+        let split: Split = "top=50, trending=25".parse().unwrap();
+        assert_eq!(split.to_string(), "top=50,trending=25");
+        assert!(split.running());
+        assert_eq!(split.ordering(0), Ordering::Top);
+        assert_eq!(split.ordering(49), Ordering::Top);
+        assert_eq!(split.ordering(50), Ordering::Trending);
+        assert_eq!(split.ordering(74), Ordering::Trending);
+        // the rest get the usual ordering
+        assert_eq!(split.ordering(75), Ordering::Top);
+
+        assert!(!"".parse::<Split>().unwrap().running());
+        for bad in [
+            "top",
+            "best=10",
+            "top=lots",
+            "top=60,trending=60",
+            "top=1,top=2",
+        ] {
+            assert!(bad.parse::<Split>().is_err(), "{bad}");
+        }
+
+        // guests stay where they are
+        assert_eq!(bucket("guest"), bucket("guest"));
+        assert!((0..1000).all(|i| bucket(&i.to_string()) < 100));
+    }
+}
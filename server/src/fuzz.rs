@@ -0,0 +1,443 @@
+//! Entry points for the fuzz targets in `fuzz/`, which can't reach into the crate otherwise.
+//!
+//! Everything runs against the in-memory backend. Beyond not panicking, each entry point checks a
+//! few things that should hold no matter how odd the input is.
+
+use super::{Backend, Local};
+use arbitrary::Arbitrary;
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    Router,
+};
+use http::{header, HeaderMap, Method, Request, StatusCode};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, OnceLock, RwLock},
+};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        })
+        .block_on(f)
+}
+
+fn local() -> Backend {
+    Backend::Local(Arc::new(RwLock::new(Local::default())))
+}
+
+/// Send a request through the API, and return its status and body.
+///
+/// Returns `None` if the request can't be made at all, like when `uri` isn't a URI.
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: impl Into<Body>,
+) -> Option<(StatusCode, serde_json::Value)> {
+    let req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .ok()?;
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    Some((status, serde_json::from_slice(&body).unwrap_or_default()))
+}
+
+/// The comma-separated list of question ids in `/api/questions/:qids`.
+pub fn qids(qids: &str) {
+    let valid = qids.split(',').all(|qid| Uuid::parse_str(qid).is_ok());
+    let (_, _, res) = block_on(super::questions::questions(
+        Path(qids.to_string()),
+        State(local()),
+        HeaderMap::new(),
+        crate::networks::Client(None),
+    ));
+    // none of the questions exist, so the only question is whether the list parsed
+    let expected = if valid {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    assert_eq!(res.map(|_| StatusCode::OK), Err(expected), "{qids:?}");
+}
+
+/// The body of a request to ask a question.
+pub fn question(body: &[u8]) {
+    block_on(async {
+        let app = super::api().with_state(local());
+        let (_, event) = send(&app, Method::POST, "/api/event", Body::empty())
+            .await
+            .unwrap();
+        let eid = event["id"].as_str().unwrap();
+        let (status, asked) = send(
+            &app,
+            Method::POST,
+            &format!("/api/event/{eid}"),
+            body.to_vec(),
+        )
+        .await
+        .unwrap();
+        if status != StatusCode::OK {
+            assert!(status.is_client_error(), "{status} for {body:?}");
+            return;
+        }
+
+        // whatever was accepted should come back out the same
+        let sent: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let qid = asked["id"].as_str().unwrap();
+        let (status, got) = send(
+            &app,
+            Method::GET,
+            &format!("/api/questions/{qid}"),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(got[qid]["text"], sent["body"]);
+        if sent["asker"].is_string() {
+            assert_eq!(got[qid]["who"], sent["asker"]);
+        }
+    });
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Field {
+    Id,
+    Eid,
+    Text,
+    When,
+    Who,
+    Votes,
+    Hidden,
+    Answered,
+    Kind,
+    Qid,
+    Direction,
+    Set,
+    Seq,
+    At,
+}
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::Id => "id",
+            Field::Eid => "eid",
+            Field::Text => "text",
+            Field::When => "when",
+            Field::Who => "who",
+            Field::Votes => "votes",
+            Field::Hidden => "hidden",
+            Field::Answered => "answered",
+            Field::Kind => "kind",
+            Field::Qid => "qid",
+            Field::Direction => "direction",
+            Field::Set => "set",
+            Field::Seq => "seq",
+            Field::At => "at",
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug, Clone)]
+pub enum Attr {
+    S(String),
+    /// A string that's a valid uuid.
+    Uuid(u128),
+    /// A string that's a valid change kind.
+    Kind(u8),
+    N(String),
+    /// A number that's a valid count or timestamp.
+    Count(u64),
+    Bool(bool),
+    Null,
+    Ss(Vec<String>),
+}
+
+impl From<Attr> for AttributeValue {
+    fn from(attr: Attr) -> Self {
+        match attr {
+            Attr::S(s) => AttributeValue::S(s),
+            Attr::Uuid(u) => AttributeValue::S(Uuid::from_u128(u).to_string()),
+            Attr::Kind(k) => {
+                let kinds = [
+                    "event_created",
+                    "question_asked",
+                    "vote_cast",
+                    "question_answered",
+                    "question_hidden",
+                ];
+                AttributeValue::S(kinds[usize::from(k) % kinds.len()].to_string())
+            }
+            Attr::N(n) => AttributeValue::N(n),
+            Attr::Count(n) => AttributeValue::N(n.to_string()),
+            Attr::Bool(b) => AttributeValue::Bool(b),
+            Attr::Null => AttributeValue::Null(true),
+            Attr::Ss(ss) => AttributeValue::Ss(ss),
+        }
+    }
+}
+
+/// Turning items as DynamoDB (or any other backend) returns them into what the API returns.
+pub fn items(attrs: Vec<(Field, Attr)>) {
+    let item: HashMap<&'static str, AttributeValue> = attrs
+        .into_iter()
+        .map(|(f, a)| (f.name(), a.into()))
+        .collect();
+    let owned: HashMap<String, AttributeValue> = item
+        .iter()
+        .map(|(&k, v)| (k.to_string(), v.clone()))
+        .collect();
+
+    if let Some((qid, _)) = super::list::parse(&Uuid::nil(), &owned) {
+        assert_eq!(Some(qid.to_string()), owned["id"].as_s().ok().cloned());
+    }
+    if let Some((qid, q)) = super::questions::to_json(&owned) {
+        assert_eq!(Some(&qid), owned["id"].as_s().ok());
+        assert_eq!(
+            Some(&q["text"]),
+            owned["text"].as_s().ok().map(|t| t.clone().into()).as_ref()
+        );
+    }
+    if let Some(c) = super::changes::to_json(&owned) {
+        assert_eq!(
+            Some(&c["kind"]),
+            owned["kind"].as_s().ok().map(|k| k.clone().into()).as_ref()
+        );
+    }
+
+    // the journal keeps only the types the backends write
+    if item.values().all(|v| {
+        matches!(
+            v,
+            AttributeValue::S(_) | AttributeValue::N(_) | AttributeValue::Bool(_)
+        )
+    }) {
+        let json = super::journal::to_json(&item);
+        assert_eq!(super::journal::from_json(json), Some(item));
+    }
+}
+
+/// Pick one of `known`, or a uuid that (most likely) doesn't exist.
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Id {
+    Known(u8),
+    Unknown(u128),
+}
+
+impl Id {
+    fn pick<T: Copy>(self, known: &[T], id: impl Fn(T) -> Uuid) -> Uuid {
+        match self {
+            Id::Known(i) if !known.is_empty() => id(known[usize::from(i) % known.len()]),
+            Id::Known(_) => Uuid::nil(),
+            Id::Unknown(u) => Uuid::from_u128(u),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+pub enum Op {
+    New,
+    Ask {
+        event: Id,
+        body: String,
+        asker: Option<String>,
+    },
+    Vote {
+        question: Id,
+        up: bool,
+    },
+    Toggle {
+        event: Id,
+        question: Id,
+        right_secret: bool,
+        hidden: bool,
+        body: Option<bool>,
+    },
+    Event {
+        event: Id,
+    },
+    List {
+        event: Id,
+        host: bool,
+    },
+    Changes {
+        event: Id,
+        since: u64,
+    },
+    Questions {
+        questions: Vec<Id>,
+    },
+    /// Anything at all, to exercise routing and extraction.
+    Raw {
+        post: bool,
+        path: String,
+        body: Vec<u8>,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct Asked {
+    eid: Uuid,
+    qid: Uuid,
+}
+
+/// A sequence of API requests.
+pub fn handlers(ops: Vec<Op>) {
+    block_on(async {
+        let app = super::api().with_state(local());
+        let mut events: Vec<Uuid> = Vec::new();
+        let mut secrets: HashMap<Uuid, String> = HashMap::new();
+        let mut asked: Vec<Asked> = Vec::new();
+        let secret_of = |eid: Uuid, secrets: &HashMap<Uuid, String>| {
+            secrets
+                .get(&eid)
+                .map_or("wrong", String::as_str)
+                .to_string()
+        };
+
+        for op in ops {
+            match op {
+                Op::New => {
+                    let (status, e) = send(&app, Method::POST, "/api/event", Body::empty())
+                        .await
+                        .unwrap();
+                    assert_eq!(status, StatusCode::OK);
+                    let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+                    events.push(eid);
+                    secrets.insert(eid, e["secret"].as_str().unwrap().to_string());
+                }
+                Op::Ask { event, body, asker } => {
+                    let eid = event.pick(&events, |e| e);
+                    let body = serde_json::json!({ "body": body, "asker": asker }).to_string();
+                    let uri = format!("/api/event/{eid}");
+                    let (status, q) = send(&app, Method::POST, &uri, body).await.unwrap();
+                    if status == StatusCode::OK {
+                        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+                        asked.push(Asked { eid, qid });
+                    }
+                }
+                Op::Vote { question, up } => {
+                    let qid = question.pick(&asked, |a| a.qid);
+                    let dir = if up { "up" } else { "down" };
+                    send(&app, Method::POST, &format!("/api/vote/{qid}/{dir}"), "").await;
+                }
+                Op::Toggle {
+                    event,
+                    question,
+                    right_secret,
+                    hidden,
+                    body,
+                } => {
+                    let eid = event.pick(&events, |e| e);
+                    let qid = question.pick(&asked, |a| a.qid);
+                    let secret = if right_secret {
+                        secret_of(eid, &secrets)
+                    } else {
+                        String::from("wrong")
+                    };
+                    let property = if hidden { "hidden" } else { "answered" };
+                    let set = match body {
+                        Some(true) => "on",
+                        Some(false) => "off",
+                        None => "maybe",
+                    };
+                    let uri =
+                        format!("/api/event/{eid}/questions/{secret}/{qid}/toggle/{property}");
+                    let (status, _) = send(&app, Method::POST, &uri, set).await.unwrap();
+                    let (Some(set), StatusCode::OK) = (body, status) else {
+                        continue;
+                    };
+                    // the property is now what was asked for, however many times it was toggled
+                    let Some(a) = asked.iter().find(|a| a.qid == qid) else {
+                        continue;
+                    };
+                    let uri = format!(
+                        "/api/event/{}/questions/{}",
+                        a.eid,
+                        secret_of(a.eid, &secrets)
+                    );
+                    let (status, qs) = send(&app, Method::GET, &uri, Body::empty()).await.unwrap();
+                    if status != StatusCode::OK {
+                        continue;
+                    }
+                    let q = qs
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .find(|q| q["qid"] == qid.to_string())
+                        .unwrap();
+                    assert_eq!(q[property], set, "{property} after toggling it {set}");
+                }
+                Op::Event { event } => {
+                    let eid = event.pick(&events, |e| e);
+                    send(
+                        &app,
+                        Method::GET,
+                        &format!("/api/event/{eid}"),
+                        Body::empty(),
+                    )
+                    .await;
+                }
+                Op::List { event, host } => {
+                    let eid = event.pick(&events, |e| e);
+                    let uri = if host {
+                        format!("/api/event/{eid}/questions/{}", secret_of(eid, &secrets))
+                    } else {
+                        format!("/api/event/{eid}/questions")
+                    };
+                    send(&app, Method::GET, &uri, Body::empty()).await;
+                }
+                Op::Changes { event, since } => {
+                    let eid = event.pick(&events, |e| e);
+                    let uri = format!("/api/event/{eid}/changes?since={since}");
+                    send(&app, Method::GET, &uri, Body::empty()).await;
+                }
+                Op::Questions { questions } => {
+                    let qids: Vec<_> = questions
+                        .into_iter()
+                        .map(|q| q.pick(&asked, |a| a.qid).to_string())
+                        .collect();
+                    let uri = format!("/api/questions/{}", qids.join(","));
+                    send(&app, Method::GET, &uri, Body::empty()).await;
+                }
+                Op::Raw { post, path, body } => {
+                    let method = if post { Method::POST } else { Method::GET };
+                    send(&app, method, &path, body).await;
+                }
+            }
+        }
+
+        // every question that was asked is listed for its event, and the log has no gaps
+        for &eid in &events {
+            let secret = secret_of(eid, &secrets);
+            let uri = format!("/api/event/{eid}/questions/{secret}");
+            let (status, qs) = send(&app, Method::GET, &uri, Body::empty()).await.unwrap();
+            assert_eq!(status, StatusCode::OK);
+            let n = asked.iter().filter(|a| a.eid == eid).count();
+            assert_eq!(qs.as_array().unwrap().len(), n);
+
+            let uri = format!("/api/event/{eid}/changes");
+            let (status, log) = send(&app, Method::GET, &uri, Body::empty()).await.unwrap();
+            assert_eq!(status, StatusCode::OK);
+            for (i, c) in log["changes"].as_array().unwrap().iter().enumerate() {
+                assert_eq!(c["seq"], i + 1);
+            }
+        }
+    });
+}
@@ -0,0 +1,155 @@
+//! Golden-file tests against recorded DynamoDB traffic.
+//!
+//! Each case in `fixtures/dynamodb/` lists the requests a handler makes to DynamoDB along with the
+//! (sanitized) responses DynamoDB gave to them. [`replay`] gives a backend that answers with those
+//! responses, and [`Replay::check`] asserts that the handler made exactly those requests and
+//! produced the output in `fixtures/golden/`. That way, SDK upgrades and refactors that change how
+//! attributes are sent or read fail `cargo test` without needing AWS.
+//!
+//! After an intended change to the output, run the tests with `UPDATE_GOLDEN=1` to rewrite the
+//! golden files, and check the diff.
+
+use super::Backend;
+use aws_smithy_client::test_connection::TestConnection;
+use aws_smithy_http::body::SdkBody;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Deserialize)]
+struct Exchange {
+    /// The `X-Amz-Target` operation, like `GetItem`.
+    operation: String,
+    request: serde_json::Value,
+    response: serde_json::Value,
+}
+
+pub(super) struct Replay {
+    case: &'static str,
+    exchanges: Vec<Exchange>,
+    conn: TestConnection<String>,
+}
+
+fn fixture(dir: &str, case: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(dir)
+        .join(format!("{case}.json"))
+}
+
+/// A DynamoDB client that sends its requests to `conn`.
+pub(super) fn client<C>(conn: C) -> aws_sdk_dynamodb::Client
+where
+    C: aws_smithy_client::bounds::SmithyConnector,
+{
+    let config = aws_sdk_dynamodb::Config::builder()
+        .region(aws_sdk_dynamodb::Region::new("us-east-1"))
+        .credentials_provider(aws_sdk_dynamodb::Credentials::new(
+            "AKIDEXAMPLE",
+            "secret",
+            None,
+            None,
+            "golden",
+        ))
+        .retry_config(aws_smithy_types::retry::RetryConfig::disabled())
+        .build();
+    aws_sdk_dynamodb::Client::from_conf_conn(config, conn)
+}
+
+/// A DynamoDB backend that can't be reached, so every request to it fails.
+pub(super) fn unreachable() -> Backend {
+    Backend::Dynamo(client(TestConnection::<String>::new(Vec::new())).into())
+}
+
+/// A DynamoDB backend that answers its first `n` requests with empty responses, and the connection
+/// to it, to see which requests were made.
+pub(super) fn answering(n: usize) -> (Backend, TestConnection<String>) {
+    let conn = TestConnection::new(
+        (0..n)
+            .map(|_| {
+                (
+                    http::Request::new(SdkBody::from("{}")),
+                    http::Response::builder()
+                        .status(200)
+                        .header("content-type", "application/x-amz-json-1.0")
+                        .body(String::from("{}"))
+                        .unwrap(),
+                )
+            })
+            .collect(),
+    );
+    (Backend::Dynamo(client(conn.clone()).into()), conn)
+}
+
+/// A DynamoDB backend that plays back the responses recorded for `case`.
+pub(super) fn replay(case: &'static str) -> (Backend, Replay) {
+    let recorded = std::fs::read_to_string(fixture("dynamodb", case)).unwrap();
+    let exchanges: Vec<Exchange> = serde_json::from_str(&recorded).unwrap();
+    let conn = TestConnection::new(
+        exchanges
+            .iter()
+            .map(|e| {
+                (
+                    http::Request::new(SdkBody::from(e.request.to_string())),
+                    http::Response::builder()
+                        .status(200)
+                        .header("content-type", "application/x-amz-json-1.0")
+                        .body(e.response.to_string())
+                        .unwrap(),
+                )
+            })
+            .collect(),
+    );
+    let client = client(conn.clone());
+    let replay = Replay {
+        case,
+        exchanges,
+        conn,
+    };
+    (Backend::Dynamo(client.into()), replay)
+}
+
+impl Replay {
+    /// Check that the recorded requests were made, and that `output` matches the golden file.
+    pub(super) fn check(self, output: serde_json::Value) {
+        let requests = self.conn.requests();
+        assert_eq!(
+            requests.len(),
+            self.exchanges.len(),
+            "{}: wrong number of requests to DynamoDB",
+            self.case
+        );
+        for (req, exchange) in requests.iter().zip(&self.exchanges) {
+            let target = req.actual.headers()["x-amz-target"].to_str().unwrap();
+            assert_eq!(
+                target,
+                format!("DynamoDB_20120810.{}", exchange.operation),
+                "{}: unexpected operation",
+                self.case
+            );
+            let body: serde_json::Value =
+                serde_json::from_slice(req.actual.body().bytes().unwrap()).unwrap();
+            assert_eq!(
+                body, exchange.request,
+                "{}: {} request differs from the recording",
+                self.case, exchange.operation
+            );
+        }
+
+        let golden = fixture("golden", self.case);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let mut out = serde_json::to_string_pretty(&output).unwrap();
+            out.push('\n');
+            std::fs::write(&golden, out).unwrap();
+            return;
+        }
+        let expected: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&golden).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            expected,
+            "{}: output differs from {} (run with UPDATE_GOLDEN=1 if that's intended)",
+            self.case,
+            golden.display()
+        );
+    }
+}
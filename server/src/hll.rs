@@ -0,0 +1,152 @@
+//! A [HyperLogLog] sketch, for counting roughly how many distinct things there are in a fixed
+//! amount of memory.
+//!
+//! With `PRECISION` 10 a sketch is 1KiB, and its estimates are usually within a few percent of the
+//! truth, no matter how many things it has seen. Sketches of different sets can be merged into a
+//! sketch of their union without losing anything.
+//!
+//! Sketches that are stored only keep the registers that have been set, two bytes each, so the
+//! sketch of a handful of things takes a handful of bytes.
+//!
+//! [HyperLogLog]: https://en.wikipedia.org/wiki/HyperLogLog
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+const PRECISION: u32 = 10;
+const REGISTERS: usize = 1 << PRECISION;
+
+#[derive(Clone, PartialEq, Eq)]
+pub(super) struct Sketch {
+    registers: Box<[u8; REGISTERS]>,
+}
+
+impl std::fmt::Debug for Sketch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sketch")
+            .field("estimate", &self.estimate())
+            .finish()
+    }
+}
+
+impl Default for Sketch {
+    fn default() -> Self {
+        Sketch {
+            registers: Box::new([0; REGISTERS]),
+        }
+    }
+}
+
+impl Sketch {
+    /// Note that `item` has been seen, and return whether that changed the sketch.
+    pub(super) fn insert(&mut self, item: impl Hash) -> bool {
+        // a fresh default hasher always uses the same keys, so the same item lands in the same
+        // place on every instance
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // the position of the first one in what's left, with a one at the end so it's never zero
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[register] {
+            self.registers[register] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The sketch as it's stored.
+    pub(super) fn to_stored(&self) -> String {
+        let mut bytes = Vec::new();
+        for (i, &r) in self.registers.iter().enumerate() {
+            if r != 0 {
+                // ranks never go above 64 - PRECISION + 1, so they fit in the low six bits
+                bytes.extend_from_slice(&((i as u16) << 6 | u16::from(r)).to_be_bytes());
+            }
+        }
+        STANDARD_NO_PAD.encode(bytes)
+    }
+
+    /// The sketch stored as `stored`, if it's valid.
+    pub(super) fn stored(stored: &str) -> Option<Self> {
+        let bytes = STANDARD_NO_PAD.decode(stored).ok()?;
+        if bytes.len() % 2 != 0 {
+            return None;
+        }
+        let mut sketch = Sketch::default();
+        for pair in bytes.chunks_exact(2) {
+            let v = u16::from_be_bytes([pair[0], pair[1]]);
+            sketch.registers[usize::from(v >> 6)] = (v & 0x3f) as u8;
+        }
+        Some(sketch)
+    }
+
+    /// Add everything `other` has seen to this sketch.
+    pub(super) fn merge(&mut self, other: &Sketch) {
+        for (r, &o) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *r = (*r).max(o);
+        }
+    }
+
+    /// Roughly how many distinct items the sketch has seen.
+    pub(super) fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        let estimate = if raw <= 2.5 * m && zeros != 0 {
+            // small cardinalities are estimated better by how many registers are still empty
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates() {
+        let mut sketch = Sketch::default();
+        assert_eq!(sketch.estimate(), 0);
+        for i in 0..10 {
+            // seeing things again doesn't count
+            assert!(sketch.insert(i));
+            assert!(!sketch.insert(i));
+        }
+        assert_eq!(sketch.estimate(), 10);
+        let stored = sketch.to_stored();
+        assert!(stored.len() <= 27, "{stored}");
+        assert_eq!(Sketch::stored(&stored), Some(sketch));
+        assert_eq!(Sketch::stored("not a sketch"), None);
+
+        let mut big = Sketch::default();
+        for i in 0..100_000u32 {
+            big.insert(i);
+        }
+        let n = big.estimate();
+        assert!((90_000..110_000).contains(&n), "{n}");
+
+        let mut other = Sketch::default();
+        for i in 50_000..150_000u32 {
+            other.insert(i);
+        }
+        big.merge(&other);
+        let n = big.estimate();
+        assert!((135_000..165_000).contains(&n), "{n}");
+    }
+}
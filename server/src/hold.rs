@@ -0,0 +1,425 @@
+//! Legal holds, which keep an event and everything in it around for as long as they last.
+//!
+//! Organisations sometimes need an event preserved while it's being investigated. The host (with
+//! the event secret) or an operator can put the event on hold, after which it doesn't expire, its
+//! questions and change log don't either, and the retention policy leaves it alone. Only an
+//! operator can release a hold, since the point is that nobody involved can make it go away. Holds
+//! and releases go in the event's moderation log (see [`super::moderation`]), which is its audit
+//! trail, along with who asked for them.
+//!
+//! Holding removes `expire` from the event, its questions, and its change log, and questions asked
+//! while the event is held are stored without one. Changes recorded while the event is held still
+//! get one, and the `retention` command (which is meant to run daily) clears those again long
+//! before they're due. Releasing a hold starts the clock over, as if the event and its questions
+//! had just been created.
+
+use super::{
+    admin::Admin,
+    moderation::{self, Action, Kind, Role},
+    Backend, Local,
+};
+use aws_sdk_dynamodb::{
+    client::fluent_builders::UpdateItem, error::UpdateItemError, model::AttributeValue,
+};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// When an event and what's in it expire if their clocks start now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Expiry {
+    /// For the event and its change log.
+    pub(super) event: u64,
+    pub(super) questions: u64,
+}
+
+impl Expiry {
+    fn from_now() -> Self {
+        let after = |days| {
+            (super::clock::now() + Duration::from_secs(days * 24 * 60 * 60))
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        Expiry {
+            event: after(super::new::EVENTS_EXPIRE_AFTER_DAYS),
+            questions: after(super::ask::QUESTIONS_EXPIRE_AFTER_DAYS),
+        }
+    }
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Backend {
+    /// Stop the event and everything in it from expiring if it's `held`, or start their clocks
+    /// over if it isn't.
+    pub(super) async fn keep(&self, eid: &Uuid, held: bool) -> Result<(), aws_sdk_dynamodb::Error> {
+        let expire = (!held).then(Expiry::from_now);
+        match self {
+            Self::Dynamo(dynamo) => {
+                let set = |upd: UpdateItem, at: Option<u64>| match at {
+                    Some(at) => upd
+                        .update_expression("SET expire = :expire")
+                        .expression_attribute_values(":expire", AttributeValue::N(at.to_string())),
+                    None => upd.update_expression("REMOVE expire"),
+                };
+                let upd = dynamo
+                    .update_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()));
+                let upd = set(upd, expire.map(|e| e.event));
+                // see Backend::record
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.send().await?;
+
+                for qid in self.question_ids(eid).await? {
+                    let upd = dynamo
+                        .update_item()
+                        .table_name("questions")
+                        .key("id", AttributeValue::S(qid.to_string()));
+                    set(upd, expire.map(|e| e.questions)).send().await?;
+                }
+                for seq in self.change_seqs(eid).await? {
+                    let upd = dynamo
+                        .update_item()
+                        .table_name("changes")
+                        .key("eid", AttributeValue::S(eid.to_string()))
+                        .key("seq", AttributeValue::N(seq.to_string()));
+                    set(upd, expire.map(|e| e.event)).send().await?;
+                }
+                Ok(())
+            }
+            Self::Local(local) => {
+                // nothing expires in memory, but the items should look like they would in DynamoDB
+                let mut local = local.write().unwrap();
+                let Local {
+                    events,
+                    questions,
+                    questions_by_eid,
+                    changes,
+                    journal,
+                    ..
+                } = &mut *local;
+
+                if !events.contains_key(eid) {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("holding event that doesn't exist")
+                            .build(),
+                    ))
+                    .into());
+                }
+                let set = |item: &mut std::collections::HashMap<&'static str, AttributeValue>,
+                           at: Option<u64>| {
+                    match at {
+                        Some(at) => item.insert("expire", AttributeValue::N(at.to_string())),
+                        None => item.remove("expire"),
+                    };
+                };
+                for qid in questions_by_eid.get(eid).into_iter().flatten() {
+                    if let Some(q) = questions.get_mut(qid) {
+                        set(q, expire.map(|e| e.questions));
+                        journal.question(qid, q);
+                    }
+                }
+                // the journal only ever appends changes, so this is only persisted by snapshots
+                for c in changes.get_mut(eid).into_iter().flatten() {
+                    set(c, expire.map(|e| e.event));
+                }
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .keep(eid, expire.as_ref())
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                // every key of an event expires along with the event in redis
+                let expire = expire.map(|_| {
+                    (SystemTime::now() + super::config::config().redis_ttl)
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs() as usize
+                });
+                redis.keep(eid, expire).await.map_err(super::mint_unhandled)
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .keep(eid, expire.map(|e| e.event))
+                .map_err(super::mint_unhandled),
+        }
+    }
+
+    async fn question_ids(&self, eid: &Uuid) -> Result<Vec<Uuid>, aws_sdk_dynamodb::Error> {
+        Ok(self
+            .list(eid, true)
+            .await?
+            .items()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|q| q.get("id")?.as_s().ok())
+            .filter_map(|qid| Uuid::parse_str(qid).ok())
+            .collect())
+    }
+
+    async fn change_seqs(&self, eid: &Uuid) -> Result<Vec<u64>, aws_sdk_dynamodb::Error> {
+        let mut seqs = Vec::new();
+        loop {
+            let since = seqs.last().copied().unwrap_or(0);
+            let page = self.changes(eid, since).await?;
+            let items = page.items().unwrap_or_default();
+            if items.is_empty() {
+                return Ok(seqs);
+            }
+            seqs.extend(
+                items
+                    .iter()
+                    .filter_map(|c| c.get("seq")?.as_n().ok()?.parse::<u64>().ok()),
+            );
+        }
+    }
+}
+
+/// Put the event on hold, or release it, as `by`.
+///
+/// Doing what's already been done doesn't add to the log, but does go over the event's items
+/// again, in case that didn't finish the first time.
+async fn set(
+    dynamo: &Backend,
+    eid: &Uuid,
+    meta: super::Meta,
+    held: bool,
+    note: &str,
+    by: Role,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let note = note.trim();
+    if note.chars().count() > moderation::MAX_NOTE {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if held && note.is_empty() {
+        // whoever comes across the hold later needs to know what it's for
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let kind = if held { Kind::Held } else { Kind::Released };
+    // a hold has to be on record before any question asked from then on is stored, and a release
+    // shouldn't be until everything has been set to expire again, but it's better to keep an
+    // event that's been released than to lose one that's been held
+    let (log, changed) = moderation::amend(dynamo, eid, meta, "hold", |log| {
+        if moderation::held(log).is_some() == held {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: now(),
+            kind,
+            note: note.to_string(),
+            by: Some(by),
+        });
+        Ok(true)
+    })
+    .await?;
+    if let Err(e) = dynamo.keep(eid, held).await {
+        error!(%eid, held, error = %e, "failed to update expiry of held event");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    if changed {
+        info!(%eid, ?kind, ?by, note, "changed legal hold");
+        super::metrics::incr(format!("hold.{kind:?}").to_lowercase());
+    } else {
+        info!(%eid, ?kind, "re-applied legal hold");
+    }
+    Ok(Json(moderation::status(&log)))
+}
+
+/// Why the host wants their event kept.
+#[derive(Debug, Deserialize)]
+pub(super) struct Hold {
+    note: String,
+}
+
+/// Put the event on hold, for its host.
+pub(super) async fn hold(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Hold>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    set(&dynamo, &eid, meta, true, &req.note, Role::Host).await
+}
+
+/// What an operator has decided about keeping an event.
+#[derive(Debug, Deserialize)]
+pub(super) struct SetHold {
+    held: bool,
+    /// What the hold is for, like a case number, or why it was released.
+    #[serde(default)]
+    note: String,
+}
+
+/// Put the event on hold or release it, for operators.
+pub(super) async fn set_hold(
+    _: Admin,
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<SetHold>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    set(&dynamo, &eid, meta, req.held, &req.note, Role::Operator).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = |body: &str| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+        };
+        let hold = |note: &str| {
+            super::hold(
+                Path((eid, secret.to_string())),
+                State(backend.clone()),
+                Json(Hold {
+                    note: note.to_string(),
+                }),
+            )
+        };
+        let set_hold = |held, note: &str| {
+            super::set_hold(
+                Admin,
+                Path(eid),
+                State(backend.clone()),
+                Json(SetHold {
+                    held,
+                    note: note.to_string(),
+                }),
+            )
+        };
+        let texts = |qid: Uuid| {
+            crate::questions::questions(
+                Path(qid.to_string()),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                crate::networks::Client(None),
+            )
+        };
+        let expires = |qid: Uuid| {
+            let Backend::Local(local) = &backend else {
+                return None;
+            };
+            let local = local.read().unwrap();
+            Some(local.questions[&qid].contains_key("expire"))
+        };
+
+        let q = ask("asked before the hold").await.unwrap();
+        let before = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        assert_ne!(expires(before), Some(false));
+
+        assert_eq!(hold("  ").await.unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            super::hold(
+                Path((eid, String::from("wrong"))),
+                State(backend.clone()),
+                Json(Hold {
+                    note: String::from("case 42"),
+                }),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        let s = hold("case 42").await.unwrap();
+        assert_eq!(s["held"], true);
+        assert_eq!(s["frozen"], false);
+        assert_eq!(s["log"][0]["kind"], "held");
+        assert_eq!(s["log"][0]["by"], "host");
+        assert_ne!(expires(before), Some(true));
+
+        // questions asked while the event is held don't expire either
+        let q = ask("asked during the hold").await.unwrap();
+        let during = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        assert_ne!(expires(during), Some(true));
+
+        // and retention leaves them alone
+        let all = "text=0,who=0,question=0".parse().unwrap();
+        let report = backend.retention(&all, true).await.unwrap();
+        assert!(report.held >= 1);
+        let qs = texts(before).await.2.unwrap();
+        assert_eq!(qs[before.to_string()]["text"], "asked before the hold");
+
+        // holding again changes nothing, and operators can hold too
+        hold("case 43").await.unwrap();
+        let s = set_hold(true, "case 42").await.unwrap();
+        assert_eq!(s["log"].as_array().unwrap().len(), 1);
+
+        let s = set_hold(false, "case closed").await.unwrap();
+        assert_eq!(s["held"], false);
+        assert_eq!(s["log"][1]["kind"], "released");
+        assert_eq!(s["log"][1]["by"], "operator");
+        assert_ne!(expires(before), Some(false));
+        assert_ne!(expires(during), Some(false));
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,661 @@
+//! In-memory question lists for the events that are live right now.
+//!
+//! While an event is running, its audience polls the question list constantly, and almost all of
+//! those polls would otherwise end up as DynamoDB queries. Instead, the events that were listed
+//! most recently (up to `HOT_EVENTS` of them) keep a copy of their entire question list here, and
+//! attendee list reads are answered straight from it.
+//!
+//! Mutations made through this instance are applied to the copy as they're recorded in the change
+//! log. If the log's sequence numbers skip ahead of the event's version when it was loaded, some
+//! other instance changed the event in the meantime, so the copy is dropped and reloaded on the
+//! next read. Host list reads always go to
+//! the database, and refresh the copy while they're at it. Nothing tells an instance that's only
+//! serving reads about changes made elsewhere, so copies are also reloaded once they're
+//! `HOT_REFRESH_MS` old. Reloads may come from a list another instance [shared](super::shared)
+//! rather than from the database.
+//!
+//! Freezes are the exception: a [frozen](super::moderation) event's list mustn't be served for
+//! however long it takes its copy to get old, so every so often (at most once a second) a read
+//! checks the event in the database first, and drops the copy if the event has been frozen since.
+//!
+//! Copies also hold the event's announcements, and work out which one is showing whenever they're
+//! read. Announcement changes don't say what was announced, so they drop the copy instead. The
+//! order the host [locked](super::lock) the list in, if they did, comes along too.
+//!
+//! A copy that's too old to be served is kept around for up to `STALE_FOR_MS` after it was loaded
+//! anyway. If the database can't be reached when the list is reloaded, attendees would much rather
+//! see a list that's half a minute old than an error in the middle of a talk, so they get the
+//! [`stale`] copy instead, marked with an `x-stale` header.
+//!
+//! A huge event can have thousands of attendees polling and voting at once, so each copy has a
+//! lock of its own, and the lock on the set as a whole is only held long enough to find (or swap
+//! out) a copy. Lists are put together under a read lock, so polls of the same event don't wait for
+//! each other, only for the changes being applied, and polls of other events wait for neither. To
+//! keep the two from deadlocking, a copy is never locked while the set is.
+
+use super::{
+    announce::{self, Announcement, Scheduled},
+    changes::Change,
+    experiment::Ordering,
+    list::Arrangement,
+    react::Reactions,
+    vote::UpDown,
+    Backend,
+};
+use http::{header::HeaderName, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The mutable state of a question, which is all that the question list has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Question {
+    pub(super) votes: usize,
+    pub(super) hidden: bool,
+    pub(super) answered: bool,
+    /// Roughly how many different people voted for the question, which only hosts get to see.
+    pub(super) voters: u64,
+    /// When the question was asked, in seconds since the epoch.
+    pub(super) asked: u64,
+    /// The question this one was merged into, if it was (see [`super::merge`]).
+    #[serde(default)]
+    pub(super) merged_into: Option<Uuid>,
+    /// A short hash of who asked the question, if they said who they were (see [`super::mine`]).
+    #[serde(default)]
+    pub(super) asker: Option<u64>,
+    /// How many times the question was reacted to with each emoji (see [`super::react`]).
+    #[serde(default)]
+    pub(super) reactions: Reactions,
+}
+
+impl Question {
+    /// The question as it appears in list responses.
+    pub(super) fn to_json(self, qid: &Uuid) -> serde_json::Value {
+        let mut v = serde_json::json!({
+            "qid": qid.to_string(),
+            "votes": self.votes,
+            "hidden": self.hidden,
+            "answered": self.answered,
+        });
+        if let Some(into) = self.merged_into {
+            v["merged_into"] = into.to_string().into();
+        }
+        if let Some(reactions) = self.reactions.to_json() {
+            v["reactions"] = reactions;
+        }
+        v
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The sequence number of the last change the copy includes.
+    seq: u64,
+    announcement: Option<Announcement>,
+    scheduled: Vec<Scheduled>,
+    /// The order the host locked the list in, if they did.
+    locked: Option<Vec<Uuid>>,
+    /// The order the host picked for lists that don't ask for one (see [`super::order`]).
+    picked: Ordering,
+    questions: HashMap<Uuid, Question>,
+}
+
+impl Entry {
+    fn visible(&self) -> Vec<(Uuid, Question)> {
+        self.questions
+            .iter()
+            .filter(|(_, q)| !q.hidden)
+            .map(|(&qid, &q)| (qid, q))
+            .collect()
+    }
+
+    fn listing(&self, order: Option<Ordering>) -> Listing {
+        let arranged = Arrangement {
+            order: order.unwrap_or(self.picked),
+            locked: self.locked.is_some(),
+        };
+        let order = arranged.order;
+        let mut qs = self.visible();
+        order.sort(&mut qs, now());
+        if let Some(locked) = &self.locked {
+            super::lock::arrange(&mut qs, locked);
+        }
+        (
+            self.seq,
+            announce::showing(self.announcement.as_ref(), &self.scheduled),
+            arranged,
+            qs.iter().map(|(qid, q)| q.to_json(qid)).collect(),
+        )
+    }
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// An event's copy in the hot set.
+#[derive(Debug)]
+struct Slot {
+    loaded: Instant,
+    used: Instant,
+    /// When the event was last checked for having been frozen.
+    checked: Instant,
+    entry: Arc<RwLock<Entry>>,
+}
+
+static HOT: Mutex<BTreeMap<Uuid, Slot>> = Mutex::new(BTreeMap::new());
+
+/// How often a copy that's being read checks whether its event has been frozen.
+const RECHECK: Duration = Duration::from_secs(1);
+
+/// Marks question lists that were served from a [`stale`] copy.
+pub(super) const STALE: HeaderName = HeaderName::from_static("x-stale");
+
+/// An event's version, its announcement, the order it's in, and its visible questions in that order.
+type Listing = (
+    u64,
+    Option<Announcement>,
+    Arrangement,
+    Vec<serde_json::Value>,
+);
+
+/// An event's version, its announcement, and its visible questions.
+type Ranked = (u64, Option<Announcement>, Vec<(Uuid, Question)>);
+
+/// `eid`'s hot copy, if it's recent enough to be served.
+fn fresh(eid: &Uuid) -> Option<Arc<RwLock<Entry>>> {
+    let config = super::config::config();
+    let mut hot = HOT.lock().unwrap();
+    let Some(age) = hot.get(eid).map(|s| s.loaded.elapsed()) else {
+        super::metrics::incr("hot.miss");
+        return None;
+    };
+    if age > config.hot_refresh {
+        // keep it to fall back on in case the reload fails
+        if age > config.stale_for {
+            hot.remove(eid);
+        }
+        super::metrics::incr("hot.miss");
+        return None;
+    }
+    let slot = hot.get_mut(eid).expect("just looked");
+    slot.used = Instant::now();
+    super::metrics::incr("hot.hit");
+    Some(Arc::clone(&slot.entry))
+}
+
+/// Whether it's time `eid`'s hot copy checked whether its event has been frozen, in which case
+/// it's taken to have been.
+fn due(eid: &Uuid) -> bool {
+    let mut hot = HOT.lock().unwrap();
+    let Some(slot) = hot.get_mut(eid).filter(|s| s.checked.elapsed() >= RECHECK) else {
+        return false;
+    };
+    slot.checked = Instant::now();
+    true
+}
+
+/// Drop `eid`'s hot copy if the event has been frozen (or deleted) since it was loaded.
+///
+/// Only actually looks every [`RECHECK`], so that reads of a hot event stay all but free. Freezes
+/// made through this instance drop the copy straight away, but ones made through other instances
+/// are only in the database. If the database can't say, the copy is kept.
+pub(super) async fn recheck(dynamo: &Backend, eid: &Uuid) {
+    if !due(eid) {
+        return;
+    }
+    match super::get_meta(dynamo, eid).await {
+        Ok(meta) if !meta.frozen() => {}
+        Err(StatusCode::INTERNAL_SERVER_ERROR) => {}
+        _ => {
+            debug!(%eid, "dropping hot copy of event frozen elsewhere");
+            forget(eid);
+        }
+    }
+}
+
+/// `eid`'s question list in `order` (or the one its host picked), if the event is hot.
+pub(super) fn get(eid: &Uuid, order: Option<Ordering>) -> Option<Listing> {
+    let entry = fresh(eid)?;
+    let entry = entry.read().unwrap();
+    Some(entry.listing(order))
+}
+
+/// `eid`'s version, its announcement, and its visible questions by votes, the way guests
+/// [page](super::list) through them (which is never in a locked order), if the event is hot.
+pub(super) fn top(eid: &Uuid) -> Option<Ranked> {
+    let entry = fresh(eid)?;
+    let entry = entry.read().unwrap();
+    let mut qs = entry.visible();
+    Ordering::Top.sort(&mut qs, now());
+    Some((
+        entry.seq,
+        announce::showing(entry.announcement.as_ref(), &entry.scheduled),
+        qs,
+    ))
+}
+
+/// `eid`'s [board](super::board) with `n` questions in each part, if the event is hot.
+pub(super) fn board(eid: &Uuid, n: usize) -> Option<serde_json::Value> {
+    let entry = fresh(eid)?;
+    let entry = entry.read().unwrap();
+    Some(super::board::assemble(
+        entry.seq,
+        announce::showing(entry.announcement.as_ref(), &entry.scheduled),
+        entry.locked.as_deref(),
+        entry.questions.iter().map(|(&qid, &q)| (qid, q)),
+        n,
+    ))
+}
+
+/// `eid`'s question list as of at most `STALE_FOR_MS` ago, however out of date that is.
+///
+/// Only for when the current list can't be had.
+pub(super) fn stale(eid: &Uuid, order: Option<Ordering>) -> Option<Listing> {
+    let Some((age, entry)) = HOT
+        .lock()
+        .unwrap()
+        .get(eid)
+        .map(|s| (s.loaded.elapsed(), Arc::clone(&s.entry)))
+        .filter(|(age, _)| *age <= super::config::config().stale_for)
+    else {
+        super::metrics::incr("stale.miss");
+        return None;
+    };
+    super::metrics::incr("stale.hit");
+    warn!(%eid, ?age, "serving stale question list");
+    let entry = entry.read().unwrap();
+    Some(entry.listing(order))
+}
+
+/// Make `questions` the hot copy of `eid`'s (entire) question list as of version `seq`.
+pub(super) fn load(
+    eid: &Uuid,
+    seq: u64,
+    announcement: Option<Announcement>,
+    scheduled: Vec<Scheduled>,
+    locked: Option<Vec<Uuid>>,
+    picked: Ordering,
+    questions: impl IntoIterator<Item = (Uuid, Question)>,
+) {
+    let limit = super::config::config().hot_events;
+    if limit == 0 {
+        return;
+    }
+
+    // the copy is put together before the set is locked, since that can take a while
+    let entry = Arc::new(RwLock::new(Entry {
+        seq,
+        announcement,
+        scheduled,
+        locked,
+        picked,
+        questions: questions.into_iter().collect(),
+    }));
+    let now = Instant::now();
+    let mut hot = HOT.lock().unwrap();
+    hot.insert(
+        *eid,
+        Slot {
+            loaded: now,
+            used: now,
+            checked: now,
+            entry,
+        },
+    );
+    // only the events that are actually being polled get to stay
+    while hot.len() > limit {
+        let coldest = hot
+            .iter()
+            .min_by_key(|(_, s)| s.used)
+            .map(|(eid, _)| *eid)
+            .expect("more than zero events");
+        trace!(eid = %coldest, "evicting event from hot set");
+        hot.remove(&coldest);
+    }
+}
+
+/// Apply a change made through this instance to `eid`'s hot copy, if there is one.
+///
+/// `seq` is the change's sequence number in the event's log, or `None` if it couldn't be recorded.
+pub(super) fn apply(eid: &Uuid, seq: Option<u64>, change: Change) {
+    let Some(shared) = HOT.lock().unwrap().get(eid).map(|s| Arc::clone(&s.entry)) else {
+        return;
+    };
+    let mut entry = shared.write().unwrap();
+    // only drops this very copy, in case it's been replaced since
+    let evict = || {
+        let mut hot = HOT.lock().unwrap();
+        if hot.get(eid).is_some_and(|s| Arc::ptr_eq(&s.entry, &shared)) {
+            hot.remove(eid);
+        }
+    };
+
+    let Some(seq) = seq.filter(|&seq| seq == entry.seq + 1) else {
+        debug!(%eid, last = entry.seq, ?seq, "hot copy may have missed a change");
+        evict();
+        return;
+    };
+    entry.seq = seq;
+
+    let known = match change {
+        Change::EventCreated => true,
+        Change::Announced => {
+            trace!(%eid, "dropping hot copy with outdated announcement");
+            evict();
+            return;
+        }
+        Change::QuestionAsked { qid } => {
+            entry.questions.insert(
+                qid,
+                Question {
+                    votes: 1,
+                    hidden: false,
+                    answered: false,
+                    voters: 0,
+                    asked: now(),
+                    merged_into: None,
+                    asker: None,
+                    reactions: Default::default(),
+                },
+            );
+            true
+        }
+        Change::VoteCast { qid, direction } => entry
+            .questions
+            .get_mut(&qid)
+            .map(|q| match direction {
+                UpDown::Up => q.votes += 1,
+                UpDown::Down => q.votes = q.votes.saturating_sub(1),
+            })
+            .is_some(),
+        Change::QuestionAnswered { qid, set } => entry
+            .questions
+            .get_mut(&qid)
+            .map(|q| q.answered = set)
+            .is_some(),
+        Change::QuestionHidden { qid, set } => entry
+            .questions
+            .get_mut(&qid)
+            .map(|q| q.hidden = set)
+            .is_some(),
+        // copies don't have texts
+        Change::QuestionEdited { qid } => entry.questions.contains_key(&qid),
+        Change::QuestionMerged { qid, into, votes } => {
+            if entry.questions.contains_key(&qid) && entry.questions.contains_key(&into) {
+                let q = entry.questions.get_mut(&qid).expect("checked above");
+                q.hidden = true;
+                q.merged_into = Some(into);
+                entry.questions.get_mut(&into).expect("checked above").votes += votes as usize;
+                true
+            } else {
+                false
+            }
+        }
+        Change::VotesHeld { qid, votes } => entry
+            .questions
+            .get_mut(&qid)
+            .map(|q| q.votes = q.votes.saturating_sub(votes as usize))
+            .is_some(),
+        Change::VotesReleased { qid, votes } => entry
+            .questions
+            .get_mut(&qid)
+            .map(|q| q.votes += votes as usize)
+            .is_some(),
+        Change::Reacted { qid, reaction } => entry
+            .questions
+            .get_mut(&qid)
+            .map(|q| q.reactions.add(reaction, 1))
+            .is_some(),
+    };
+    if !known {
+        warn!(%eid, ?change, "change to question missing from hot copy");
+        evict();
+    }
+}
+
+/// Whether `eid` is hot.
+pub(super) fn contains(eid: &Uuid) -> bool {
+    HOT.lock().unwrap().contains_key(eid)
+}
+
+/// How many events are hot, and how many questions their copies hold between them.
+pub(super) fn size() -> (usize, usize) {
+    let entries: Vec<_> = HOT
+        .lock()
+        .unwrap()
+        .values()
+        .map(|s| Arc::clone(&s.entry))
+        .collect();
+    let questions = entries
+        .iter()
+        .map(|e| e.read().unwrap().questions.len())
+        .sum();
+    (entries.len(), questions)
+}
+
+/// Drop `eid`'s hot copy, if there is one.
+pub(super) fn forget(eid: &Uuid) {
+    HOT.lock().unwrap().remove(eid);
+}
+
+/// Make `eid`'s hot copy check whether its event has been frozen the next time it's read.
+#[cfg(test)]
+pub(super) fn overdue(eid: &Uuid) {
+    if let Some(slot) = HOT.lock().unwrap().get_mut(eid) {
+        slot.checked = slot
+            .checked
+            .checked_sub(RECHECK)
+            .expect("machine has been up for long enough");
+    }
+}
+
+/// Make `eid`'s hot copy look like it was loaded `by` earlier than it was.
+#[cfg(test)]
+pub(super) fn age(eid: &Uuid, by: std::time::Duration) {
+    if let Some(slot) = HOT.lock().unwrap().get_mut(eid) {
+        slot.loaded = slot
+            .loaded
+            .checked_sub(by)
+            .expect("machine has been up for long enough");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(votes: usize, hidden: bool) -> Question {
+        Question {
+            votes,
+            hidden,
+            answered: false,
+            voters: 0,
+            asked: 0,
+            merged_into: None,
+            asker: None,
+            reactions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn applies_changes() {
+        let eid = Uuid::new_v4();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(get(&eid, None), None);
+
+        load(
+            &eid,
+            7,
+            None,
+            Vec::new(),
+            None,
+            Ordering::Top,
+            [(a, q(3, false)), (b, q(4, false)), (c, q(9, true))],
+        );
+        let qids = |qs: Vec<serde_json::Value>| -> Vec<String> {
+            qs.iter()
+                .map(|q| q["qid"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            qids(get(&eid, None).unwrap().3),
+            [b.to_string(), a.to_string()],
+            "hidden questions are left out, the rest ordered by votes"
+        );
+
+        apply(
+            &eid,
+            Some(8),
+            Change::VoteCast {
+                qid: a,
+                direction: UpDown::Up,
+            },
+        );
+        apply(
+            &eid,
+            Some(9),
+            Change::VoteCast {
+                qid: a,
+                direction: UpDown::Up,
+            },
+        );
+        apply(
+            &eid,
+            Some(10),
+            Change::QuestionHidden { qid: c, set: false },
+        );
+        let d = Uuid::new_v4();
+        apply(&eid, Some(11), Change::QuestionAsked { qid: d });
+        let (seq, _, _, qs) = get(&eid, None).unwrap();
+        assert_eq!(seq, 11);
+        assert_eq!(
+            qids(qs.clone()),
+            [c.to_string(), a.to_string(), b.to_string(), d.to_string()]
+        );
+        assert_eq!(qs[1]["votes"], 5);
+
+        // a gap in the log means another instance changed the event
+        apply(
+            &eid,
+            Some(13),
+            Change::QuestionAnswered { qid: a, set: true },
+        );
+        assert_eq!(get(&eid, None), None);
+    }
+
+    #[test]
+    fn unrecorded_changes_invalidate() {
+        let eid = Uuid::new_v4();
+        let a = Uuid::new_v4();
+        load(
+            &eid,
+            0,
+            None,
+            Vec::new(),
+            None,
+            Ordering::Top,
+            [(a, q(1, false))],
+        );
+        apply(&eid, None, Change::QuestionAnswered { qid: a, set: true });
+        assert_eq!(get(&eid, None), None);
+    }
+
+    #[test]
+    fn concurrent() {
+        let eid = Uuid::new_v4();
+        let a = Uuid::new_v4();
+        load(
+            &eid,
+            0,
+            None,
+            Vec::new(),
+            None,
+            Ordering::Top,
+            [(a, q(1, false))],
+        );
+        let seq = Mutex::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        // changes reach the copy in the order they were recorded
+                        let mut seq = seq.lock().unwrap();
+                        *seq += 1;
+                        apply(
+                            &eid,
+                            Some(*seq),
+                            Change::VoteCast {
+                                qid: a,
+                                direction: UpDown::Up,
+                            },
+                        );
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        assert!(get(&eid, None).is_some());
+                        assert!(board(&eid, 1).is_some());
+                    }
+                });
+            }
+        });
+        let (seq, _, _, qs) = get(&eid, None).unwrap();
+        assert_eq!(seq, 200);
+        assert_eq!(qs[0]["votes"], 201);
+        forget(&eid);
+    }
+
+    #[test]
+    fn announcements() {
+        let eid = Uuid::new_v4();
+        let announcement = Announcement {
+            text: String::from("hi"),
+            until: u64::MAX,
+        };
+        load(
+            &eid,
+            3,
+            Some(announcement.clone()),
+            Vec::new(),
+            None,
+            Ordering::Top,
+            [],
+        );
+        assert_eq!(get(&eid, None).unwrap().1, Some(announcement));
+        apply(&eid, Some(4), Change::Announced);
+        assert_eq!(get(&eid, None), None);
+
+        load(
+            &eid,
+            4,
+            Some(Announcement {
+                text: String::from("old"),
+                until: 1,
+            }),
+            vec![Scheduled {
+                at: 2,
+                until: u64::MAX,
+                text: String::from("queued"),
+            }],
+            None,
+            Ordering::Top,
+            [],
+        );
+        assert_eq!(
+            get(&eid, None).unwrap().1.unwrap().text,
+            "queued",
+            "expired announcements don't show, but queued ones that have started do"
+        );
+    }
+}
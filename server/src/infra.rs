@@ -0,0 +1,304 @@
+//! The AWS infrastructure this server expects to exist.
+//!
+//! Everything the code reads or writes in DynamoDB is described here once, and `print-infra`
+//! turns that into a CloudFormation template. If you add a table, an index, or a new kind of
+//! DynamoDB call, update this file too, otherwise the deployed stack and the code drift apart.
+
+use serde_json::{json, Value};
+
+/// A key attribute: its name and DynamoDB scalar type (`S` or `N`).
+pub(super) struct Key {
+    pub(super) name: &'static str,
+    pub(super) ty: &'static str,
+}
+
+pub(super) struct Index {
+    pub(super) name: &'static str,
+    pub(super) hash: Key,
+    pub(super) range: Option<Key>,
+    /// Non-key attributes projected into the index.
+    pub(super) include: &'static [&'static str],
+    /// The DynamoDB actions the server issues against this index.
+    pub(super) actions: &'static [&'static str],
+}
+
+pub(super) struct Table {
+    pub(super) name: &'static str,
+    pub(super) hash: Key,
+    pub(super) range: Option<Key>,
+    pub(super) indices: &'static [Index],
+    pub(super) ttl: Option<&'static str>,
+    /// The DynamoDB actions the server issues against this table.
+    pub(super) actions: &'static [&'static str],
+}
+
+pub(super) const TABLES: &[Table] = &[
+    Table {
+        name: "events",
+        hash: Key {
+            name: "id",
+            ty: "S",
+        },
+        range: None,
+        indices: &[],
+        ttl: Some("expire"),
+        // updates are for the change sequence number, scans for applying the retention policy
+        actions: &[
+            "dynamodb:GetItem",
+            "dynamodb:PutItem",
+            "dynamodb:UpdateItem",
+            "dynamodb:Scan",
+        ],
+    },
+    Table {
+        name: "questions",
+        hash: Key {
+            name: "id",
+            ty: "S",
+        },
+        range: None,
+        indices: &[Index {
+            name: "top",
+            hash: Key {
+                name: "eid",
+                ty: "S",
+            },
+            range: Some(Key {
+                name: "votes",
+                ty: "N",
+            }),
+            include: &[
+                "answered",
+                "hidden",
+                "voters",
+                "when",
+                "author",
+                "merged_into",
+                "reactions",
+            ],
+            actions: &["dynamodb:Query"],
+        }],
+        ttl: Some("expire"),
+        // gets are for fetching texts one at a time (`GET_ITEM_CONCURRENCY`), deletes are for
+        // applying the retention policy
+        actions: &[
+            "dynamodb:GetItem",
+            "dynamodb:BatchGetItem",
+            "dynamodb:PutItem",
+            "dynamodb:UpdateItem",
+            "dynamodb:DeleteItem",
+        ],
+    },
+    Table {
+        name: "changes",
+        hash: Key {
+            name: "eid",
+            ty: "S",
+        },
+        range: Some(Key {
+            name: "seq",
+            ty: "N",
+        }),
+        indices: &[],
+        ttl: Some("expire"),
+        // updates are for clearing (and restoring) the expiry of events on legal hold
+        actions: &["dynamodb:PutItem", "dynamodb:Query", "dynamodb:UpdateItem"],
+    },
+];
+
+impl Table {
+    fn logical_id(&self) -> String {
+        let mut cs = self.name.chars();
+        let first = cs.next().expect("table names are non-empty");
+        format!("{}{}Table", first.to_ascii_uppercase(), cs.as_str())
+    }
+
+    fn resource(&self) -> Value {
+        let mut attrs = vec![&self.hash];
+        let mut schema = vec![json!({ "AttributeName": self.hash.name, "KeyType": "HASH" })];
+        if let Some(ref range) = self.range {
+            attrs.push(range);
+            schema.push(json!({ "AttributeName": range.name, "KeyType": "RANGE" }));
+        }
+
+        let mut gsis = Vec::new();
+        for index in self.indices {
+            let mut schema = vec![json!({ "AttributeName": index.hash.name, "KeyType": "HASH" })];
+            attrs.push(&index.hash);
+            if let Some(ref range) = index.range {
+                attrs.push(range);
+                schema.push(json!({ "AttributeName": range.name, "KeyType": "RANGE" }));
+            }
+            gsis.push(json!({
+                "IndexName": index.name,
+                "KeySchema": schema,
+                "Projection": {
+                    "ProjectionType": "INCLUDE",
+                    "NonKeyAttributes": index.include,
+                },
+            }));
+        }
+
+        // every attribute may only be declared once, even if several indices use it
+        let mut definitions: Vec<Value> = Vec::new();
+        for attr in attrs {
+            if !definitions.iter().any(|d| d["AttributeName"] == attr.name) {
+                definitions.push(json!({ "AttributeName": attr.name, "AttributeType": attr.ty }));
+            }
+        }
+
+        let mut properties = json!({
+            "TableName": self.name,
+            "BillingMode": "PAY_PER_REQUEST",
+            "AttributeDefinitions": definitions,
+            "KeySchema": schema,
+        });
+        if !gsis.is_empty() {
+            properties["GlobalSecondaryIndexes"] = gsis.into();
+        }
+        if let Some(ttl) = self.ttl {
+            properties["TimeToLiveSpecification"] = json!({
+                "AttributeName": ttl,
+                "Enabled": true,
+            });
+        }
+
+        json!({
+            "Type": "AWS::DynamoDB::Table",
+            // the tables hold all the user data, so don't take it with us if the stack goes
+            "DeletionPolicy": "Retain",
+            "UpdateReplacePolicy": "Retain",
+            "Properties": properties,
+        })
+    }
+}
+
+/// The least-privilege policy document for the role the server runs as.
+///
+/// The caller decides how table and index ARNs are spelled, since they differ between a
+/// CloudFormation template (`Fn::GetAtt`) and a standalone policy document.
+fn policy_document(
+    arn: impl Fn(&Table) -> Value,
+    index_arn: impl Fn(&Table, &Index) -> Value,
+) -> Value {
+    let mut statements = Vec::new();
+    for table in TABLES {
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": table.actions,
+            "Resource": arn(table),
+        }));
+        for index in table.indices {
+            statements.push(json!({
+                "Effect": "Allow",
+                "Action": index.actions,
+                "Resource": index_arn(table, index),
+            }));
+        }
+    }
+    json!({
+        "Version": "2012-10-17",
+        "Statement": statements,
+    })
+}
+
+/// The policy document with concrete ARNs, as you would paste it into the IAM console.
+pub(super) fn policy_for_account(region: &str, account: &str) -> Value {
+    policy_document(
+        |t| format!("arn:aws:dynamodb:{region}:{account}:table/{}", t.name).into(),
+        |t, i| {
+            format!(
+                "arn:aws:dynamodb:{region}:{account}:table/{}/index/{}",
+                t.name, i.name
+            )
+            .into()
+        },
+    )
+}
+
+/// A CloudFormation template for all the tables and a managed policy granting access to them.
+pub(super) fn template() -> Value {
+    let mut resources = serde_json::Map::new();
+    for table in TABLES {
+        resources.insert(table.logical_id(), table.resource());
+    }
+    resources.insert(
+        String::from("ApiPolicy"),
+        json!({
+            "Type": "AWS::IAM::ManagedPolicy",
+            "Properties": {
+                "Description": "Access to the wewerewondering tables for the API Lambda",
+                "PolicyDocument": policy_document(
+                    |t| json!({ "Fn::GetAtt": [t.logical_id(), "Arn"] }),
+                    |t, i| json!({ "Fn::Join": ["", [
+                        { "Fn::GetAtt": [t.logical_id(), "Arn"] },
+                        format!("/index/{}", i.name),
+                    ]] }),
+                ),
+            },
+        }),
+    );
+
+    json!({
+        "AWSTemplateFormatVersion": "2010-09-09",
+        "Description": "DynamoDB tables and IAM policy for wewerewondering",
+        "Resources": resources,
+        "Outputs": {
+            "ApiPolicyArn": {
+                "Description": "Attach this to the Lambda execution role",
+                "Value": { "Ref": "ApiPolicy" },
+            },
+        },
+    })
+}
+
+/// Whether the policy for the server's role lets it make `action` calls against `table`.
+#[cfg(test)]
+pub(super) fn grants(table: &str, action: &str) -> bool {
+    let arn = format!("arn:aws:dynamodb:us-east-1:123456789012:table/{table}");
+    policy_for_account("us-east-1", "123456789012")["Statement"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|s| s["Resource"] == arn.as_str())
+        .any(|s| s["Action"].as_array().unwrap().iter().any(|a| a == action))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn template() {
+        let t = super::template();
+        let resources = t["Resources"].as_object().unwrap();
+        let questions = &resources["QuestionsTable"]["Properties"];
+        assert_eq!(questions["TableName"], "questions");
+        assert_eq!(questions["GlobalSecondaryIndexes"][0]["IndexName"], "top");
+        assert_eq!(
+            questions["TimeToLiveSpecification"]["AttributeName"],
+            "expire"
+        );
+        // eid and votes are only used by the index, but still need declaring
+        assert_eq!(
+            questions["AttributeDefinitions"].as_array().unwrap().len(),
+            3
+        );
+
+        let policy = &resources["ApiPolicy"]["Properties"]["PolicyDocument"];
+        let granted = policy["Statement"].as_array().unwrap();
+        assert_eq!(granted.len(), 4, "three tables and one index");
+    }
+
+    #[test]
+    fn policy() {
+        let p = super::policy_for_account("us-east-1", "123456789012");
+        let query = p["Statement"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|s| {
+                s["Resource"] == "arn:aws:dynamodb:us-east-1:123456789012:table/questions/index/top"
+            })
+            .expect("index is in the policy");
+        assert_eq!(query["Action"], serde_json::json!(["dynamodb:Query"]));
+    }
+}
@@ -0,0 +1,159 @@
+//! What generic clients and directories of instances need to know about this one.
+//!
+//! Self-hosted instances can run a client other than the bundled one, and may be listed in
+//! directories that link to many instances. `GET /api/instance` tells them what the operator
+//! calls the instance and how to reach them (`INSTANCE_NAME` and `INSTANCE_CONTACT`), where the
+//! terms of service and privacy policy are, which optional features are turned on, and the limits
+//! that submissions are held to, so they can present the instance (and check input) correctly.
+
+use super::config::Config;
+use axum::response::{AppendHeaders, Json};
+use http::header::{self, HeaderName};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+fn describe(config: &Config) -> serde_json::Value {
+    let mut features = vec![
+        "announcements",
+        "scheduled_announcements",
+        "attendee_counts",
+        "public_archives",
+    ];
+    if config.admin_token.is_some() {
+        // without an operator, holds could be placed but never released
+        features.extend(["moderation", "legal_hold"]);
+    }
+    if config.retention != Default::default() {
+        features.push("retention");
+    }
+    if config.tos_version.is_some() {
+        features.push("tos_required");
+    }
+    if config.ordering_experiment.running() {
+        features.push("ordering_experiment");
+    }
+    if config.public_url.is_some() {
+        features.push("sitemap");
+    }
+    if config.blob_store != Default::default() {
+        // the digest command can't see what's kept in the server's memory
+        features.push("digests");
+        features.push("question_aging");
+    }
+    if config.inbound_email_domain.is_some() && config.inbound_email_token.is_some() {
+        features.push("email_questions");
+    }
+    // what the policy turns off doesn't work, so it's not much of a feature
+    let off = |route| config.policy.rule(route) == Some(super::policy::Rule::Off);
+    features.retain(|f| match *f {
+        "announcements" | "scheduled_announcements" => !off("announce"),
+        "attendee_counts" => !off("ping"),
+        "public_archives" => !off("close") && !off("archive"),
+        "sitemap" => !off("sitemap"),
+        "digests" => !off("digest"),
+        "question_aging" => !off("aging"),
+        "email_questions" => !off("email"),
+        "moderation" => !off("appeal"),
+        "legal_hold" => !off("hold"),
+        _ => true,
+    });
+    serde_json::json!({
+        "name": config.instance_name,
+        "contact": config.instance_contact,
+        "tos_url": config.tos_url,
+        "tos_version": config.tos_version,
+        "privacy_url": config.privacy_url,
+        "email_domain": config.inbound_email_domain,
+        "features": features,
+        "policy": config.policy.to_json(),
+        "limits": {
+            "events_expire_after_days": super::new::EVENTS_EXPIRE_AFTER_DAYS,
+            "questions_expire_after_days": super::ask::QUESTIONS_EXPIRE_AFTER_DAYS,
+            "retention": config.retention.to_string(),
+            "announcement_length": super::announce::MAX_LENGTH,
+            "announcement_minutes": super::announce::MAX_MINUTES,
+            "scheduled_announcements": super::announce::MAX_SCHEDULED,
+            "note_length": super::moderation::MAX_NOTE,
+            "appeals": super::moderation::MAX_APPEALS,
+            "ping_every_secs": super::presence::PING,
+            "edit_within_secs": super::author::EDIT_WITHIN,
+        },
+    })
+}
+
+pub(super) async fn instance() -> (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Json<serde_json::Value>,
+) {
+    (
+        // this only changes with a deploy
+        AppendHeaders([(header::CACHE_CONTROL, "max-age=3600")]),
+        Json(describe(super::config::config())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes() {
+        let i = describe(&Config::default());
+        assert_eq!(i["name"], serde_json::Value::Null);
+        assert_eq!(i["tos_version"], serde_json::Value::Null);
+        assert_eq!(
+            i["features"],
+            serde_json::json!([
+                "announcements",
+                "scheduled_announcements",
+                "attendee_counts",
+                "public_archives"
+            ])
+        );
+        assert_eq!(i["limits"]["events_expire_after_days"], 60);
+        assert_eq!(i["limits"]["retention"], "");
+
+        let i = describe(&Config {
+            admin_token: Some(String::from("secret")),
+            instance_name: Some(String::from("Q&A at Example Corp")),
+            instance_contact: Some(String::from("qa@example.com")),
+            tos_version: Some(String::from("2")),
+            retention: "text=30".parse().unwrap(),
+            ..Default::default()
+        });
+        assert_eq!(i["name"], "Q&A at Example Corp");
+        assert_eq!(i["contact"], "qa@example.com");
+        assert_eq!(i["tos_version"], "2");
+        assert_eq!(i["limits"]["retention"], "text=30");
+        let features = i["features"].as_array().unwrap();
+        for f in ["moderation", "legal_hold", "retention", "tos_required"] {
+            assert!(features.contains(&f.into()), "{f}");
+        }
+        let i = describe(&Config {
+            policy: "exports=off,new=operator".parse().unwrap(),
+            ..Default::default()
+        });
+        assert!(!i["features"]
+            .as_array()
+            .unwrap()
+            .contains(&"public_archives".into()));
+        assert_eq!(i["policy"]["text"], "off");
+        assert_eq!(i["policy"]["new"], "operator");
+
+        let i = describe(&Config {
+            inbound_email_domain: Some(String::from("qa.example.com")),
+            inbound_email_token: Some(String::from("hunter2")),
+            ..Default::default()
+        });
+        assert_eq!(i["email_domain"], "qa.example.com");
+        assert!(i["features"]
+            .as_array()
+            .unwrap()
+            .contains(&"email_questions".into()));
+        assert!(!i.to_string().contains("hunter2"));
+
+        // the admin token is nobody's business
+        assert!(!i.to_string().contains("secret"));
+    }
+}
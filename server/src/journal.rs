@@ -0,0 +1,508 @@
+//! Persistence for the in-memory backend, so that dev and demo instances survive restarts.
+//!
+//! Every change to the state is appended to `journal.jsonl` in the data directory as the new
+//! version of whatever it changed (an event, a question, a change log entry, an announcement, a
+//! queue of announcements, a moderation log, or a retention override), or as the id of a question
+//! that was deleted. Every
+//! `SNAPSHOT_EVERY`, the whole state is written out to `snapshot.jsonl` in the same format, and
+//! the journal starts over. On startup, the snapshot and then the journal are replayed.
+//!
+//! Replaying an entry that's already reflected in the state is harmless, so a crash between
+//! writing a snapshot and truncating the journal doesn't corrupt anything.
+
+// only debug builds ever use the in-memory backend
+#![cfg_attr(not(debug_assertions), allow(dead_code))]
+
+use super::{
+    announce::{Announcement, Scheduled},
+    moderation::Action,
+    Local,
+};
+use aws_sdk_dynamodb::model::AttributeValue;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const SNAPSHOT_EVERY: Duration = Duration::from_secs(60);
+
+type Item = HashMap<&'static str, AttributeValue>;
+
+/// Where changes to a [`Local`] go. The default journal doesn't go anywhere.
+#[derive(Debug, Default)]
+pub(super) struct Journal {
+    dir: Option<PathBuf>,
+    file: Option<File>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Event {
+        eid: Uuid,
+        secret: String,
+        /// The terms of service acceptance the event was created with, as stored.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tos: Option<String>,
+    },
+    Question {
+        qid: Uuid,
+        item: serde_json::Value,
+    },
+    Change {
+        eid: Uuid,
+        item: serde_json::Value,
+    },
+    /// An announcement without `text` is one that was cleared.
+    Announcement {
+        eid: Uuid,
+        text: Option<String>,
+        until: Option<u64>,
+    },
+    Scheduled {
+        eid: Uuid,
+        scheduled: Vec<Scheduled>,
+    },
+    Moderation {
+        eid: Uuid,
+        log: Vec<Action>,
+    },
+    Locked {
+        eid: Uuid,
+        order: String,
+    },
+    /// A retention override without `policy` is one that was cleared.
+    Retention {
+        eid: Uuid,
+        policy: Option<String>,
+    },
+    Deleted {
+        eid: Uuid,
+        qid: Uuid,
+    },
+}
+
+impl Entry {
+    fn announcement(eid: &Uuid, announcement: Option<&Announcement>) -> Self {
+        Entry::Announcement {
+            eid: *eid,
+            text: announcement.map(|a| a.text.clone()),
+            until: announcement.map(|a| a.until),
+        }
+    }
+}
+
+/// The `&'static str` for the field `name`, which item keys have to be.
+fn field(name: &str) -> &'static str {
+    static FIELDS: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let mut fields = FIELDS.lock().unwrap();
+    if let Some(f) = fields.get(name) {
+        return f;
+    }
+    // there's only a handful of distinct fields, so this leaks very little
+    let f: &'static str = Box::leak(name.to_string().into_boxed_str());
+    fields.insert(f);
+    f
+}
+
+pub(super) fn to_json(item: &Item) -> serde_json::Value {
+    item.iter()
+        .map(|(&k, v)| (k.to_string(), value_to_json(v)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn value_to_json(v: &AttributeValue) -> serde_json::Value {
+    match v {
+        AttributeValue::S(s) => serde_json::json!({ "S": s }),
+        AttributeValue::N(n) => serde_json::json!({ "N": n }),
+        AttributeValue::Bool(b) => serde_json::json!({ "BOOL": b }),
+        AttributeValue::M(m) => serde_json::json!({
+            "M": m
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_json(v)))
+                .collect::<serde_json::Map<_, _>>(),
+        }),
+        v => unreachable!("no attributes are of type {v:?}"),
+    }
+}
+
+pub(super) fn from_json(item: serde_json::Value) -> Option<Item> {
+    let serde_json::Value::Object(item) = item else {
+        return None;
+    };
+    item.into_iter()
+        .map(|(k, v)| Some((field(&k), value_from_json(&v)?)))
+        .collect()
+}
+
+fn value_from_json(v: &serde_json::Value) -> Option<AttributeValue> {
+    Some(if let Some(s) = v.get("S") {
+        AttributeValue::S(s.as_str()?.to_string())
+    } else if let Some(n) = v.get("N") {
+        AttributeValue::N(n.as_str()?.to_string())
+    } else if let Some(m) = v.get("M") {
+        AttributeValue::M(
+            m.as_object()?
+                .iter()
+                .map(|(k, v)| Some((k.clone(), value_from_json(v)?)))
+                .collect::<Option<_>>()?,
+        )
+    } else {
+        AttributeValue::Bool(v.get("BOOL")?.as_bool()?)
+    })
+}
+
+impl Journal {
+    fn append(&mut self, entry: Entry) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let mut line = serde_json::to_vec(&entry).expect("entries always serialize");
+        line.push(b'\n');
+        if let Err(e) = file.write_all(&line) {
+            error!(error = %e, "failed to append to journal");
+        }
+    }
+
+    pub(super) fn event(&mut self, eid: &Uuid, secret: &str, tos: Option<&str>) {
+        self.append(Entry::Event {
+            eid: *eid,
+            secret: secret.to_string(),
+            tos: tos.map(String::from),
+        });
+    }
+
+    pub(super) fn question(&mut self, qid: &Uuid, item: &Item) {
+        self.append(Entry::Question {
+            qid: *qid,
+            item: to_json(item),
+        });
+    }
+
+    pub(super) fn change(&mut self, eid: &Uuid, item: &Item) {
+        self.append(Entry::Change {
+            eid: *eid,
+            item: to_json(item),
+        });
+    }
+
+    pub(super) fn announcement(&mut self, eid: &Uuid, announcement: Option<&Announcement>) {
+        self.append(Entry::announcement(eid, announcement));
+    }
+
+    pub(super) fn scheduled(&mut self, eid: &Uuid, scheduled: &[Scheduled]) {
+        self.append(Entry::Scheduled {
+            eid: *eid,
+            scheduled: scheduled.to_vec(),
+        });
+    }
+
+    pub(super) fn moderation(&mut self, eid: &Uuid, log: &[Action]) {
+        self.append(Entry::Moderation {
+            eid: *eid,
+            log: log.to_vec(),
+        });
+    }
+
+    pub(super) fn locked(&mut self, eid: &Uuid, order: &str) {
+        self.append(Entry::Locked {
+            eid: *eid,
+            order: order.to_string(),
+        });
+    }
+
+    pub(super) fn retention(&mut self, eid: &Uuid, policy: Option<&str>) {
+        self.append(Entry::Retention {
+            eid: *eid,
+            policy: policy.map(String::from),
+        });
+    }
+
+    pub(super) fn deleted(&mut self, eid: &Uuid, qid: &Uuid) {
+        self.append(Entry::Deleted {
+            eid: *eid,
+            qid: *qid,
+        });
+    }
+}
+
+impl Local {
+    fn replay(&mut self, entry: Entry) {
+        match entry {
+            Entry::Event { eid, secret, tos } => {
+                self.questions_by_eid.entry(eid).or_default();
+                self.events.insert(eid, secret);
+                if let Some(tos) = tos {
+                    self.tos.insert(eid, tos);
+                }
+            }
+            Entry::Question { qid, item } => {
+                let Some(item) = from_json(item) else {
+                    warn!(%qid, "skipping malformed question in journal");
+                    return;
+                };
+                let eid = item
+                    .get("eid")
+                    .and_then(|eid| eid.as_s().ok())
+                    .and_then(|eid| Uuid::parse_str(eid).ok());
+                if self.questions.insert(qid, item).is_none() {
+                    if let Some(eid) = eid {
+                        self.questions_by_eid.entry(eid).or_default().push(qid);
+                    }
+                }
+            }
+            Entry::Change { eid, item } => {
+                let Some(item) = from_json(item) else {
+                    warn!(%eid, "skipping malformed change in journal");
+                    return;
+                };
+                let log = self.changes.entry(eid).or_default();
+                let seq = item
+                    .get("seq")
+                    .and_then(|seq| seq.as_n().ok())
+                    .and_then(|seq| seq.parse::<usize>().ok());
+                // changes that are already in the log were covered by the snapshot
+                if seq == Some(log.len() + 1) {
+                    log.push(item);
+                }
+            }
+            Entry::Announcement { eid, text, until } => {
+                // expired announcements are as good as cleared
+                match Announcement::stored(text, until) {
+                    Some(a) => self.announcements.insert(eid, a),
+                    None => self.announcements.remove(&eid),
+                };
+            }
+            Entry::Scheduled { eid, mut scheduled } => {
+                scheduled.retain(|s| !s.ended());
+                if scheduled.is_empty() {
+                    self.scheduled.remove(&eid);
+                } else {
+                    self.scheduled.insert(eid, scheduled);
+                }
+            }
+            Entry::Moderation { eid, log } => {
+                self.moderation.insert(eid, log);
+            }
+            Entry::Locked { eid, order } => {
+                self.locked.insert(eid, order);
+            }
+            Entry::Retention { eid, policy } => {
+                match policy {
+                    Some(p) => self.retention.insert(eid, p),
+                    None => self.retention.remove(&eid),
+                };
+            }
+            Entry::Deleted { eid, qid } => {
+                self.questions.remove(&qid);
+                if let Some(qids) = self.questions_by_eid.get_mut(&eid) {
+                    qids.retain(|q| *q != qid);
+                }
+            }
+        }
+    }
+
+    fn replay_file(&mut self, path: &Path) -> io::Result<usize> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut n = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => self.replay(entry),
+                // most likely the last line, cut short by a crash
+                Err(e) => warn!(path = %path.display(), error = %e, "skipping unreadable entry"),
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Write the entire state to the snapshot, and start the journal over.
+    pub(super) fn snapshot(&mut self) -> io::Result<()> {
+        let Some(dir) = &self.journal.dir else {
+            return Ok(());
+        };
+        let tmp = dir.join("snapshot.jsonl.tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let mut write = |entry: Entry| -> io::Result<()> {
+            serde_json::to_writer(&mut out, &entry)?;
+            out.write_all(b"\n")
+        };
+        for (eid, secret) in &self.events {
+            write(Entry::Event {
+                eid: *eid,
+                secret: secret.clone(),
+                tos: self.tos.get(eid).cloned(),
+            })?;
+        }
+        for (qid, item) in &self.questions {
+            write(Entry::Question {
+                qid: *qid,
+                item: to_json(item),
+            })?;
+        }
+        for (eid, log) in &self.changes {
+            for item in log {
+                write(Entry::Change {
+                    eid: *eid,
+                    item: to_json(item),
+                })?;
+            }
+        }
+        for (eid, announcement) in &self.announcements {
+            write(Entry::announcement(eid, Some(announcement)))?;
+        }
+        for (eid, scheduled) in &self.scheduled {
+            write(Entry::Scheduled {
+                eid: *eid,
+                scheduled: scheduled.clone(),
+            })?;
+        }
+        for (eid, log) in &self.moderation {
+            write(Entry::Moderation {
+                eid: *eid,
+                log: log.clone(),
+            })?;
+        }
+        for (eid, order) in &self.locked {
+            write(Entry::Locked {
+                eid: *eid,
+                order: order.clone(),
+            })?;
+        }
+        for (eid, policy) in &self.retention {
+            write(Entry::Retention {
+                eid: *eid,
+                policy: Some(policy.clone()),
+            })?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(&tmp, dir.join("snapshot.jsonl"))?;
+
+        if let Some(file) = &self.journal.file {
+            file.set_len(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Restore the state kept in `dir` (if any), and keep it there from now on.
+pub(super) fn open(dir: &Path) -> io::Result<Local> {
+    fs::create_dir_all(dir)?;
+    let mut local = Local::default();
+    let snapshot = local.replay_file(&dir.join("snapshot.jsonl"))?;
+    let journal = local.replay_file(&dir.join("journal.jsonl"))?;
+    info!(
+        dir = %dir.display(),
+        snapshot, journal,
+        events = local.events.len(),
+        "restored local state"
+    );
+    local.journal = Journal {
+        dir: Some(dir.to_path_buf()),
+        file: Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join("journal.jsonl"))?,
+        ),
+    };
+    Ok(local)
+}
+
+/// Snapshot `local` every `SNAPSHOT_EVERY`, forever.
+pub(super) async fn snapshots(local: Arc<RwLock<Local>>) {
+    loop {
+        tokio::time::sleep(SNAPSHOT_EVERY).await;
+        if let Err(e) = local.write().unwrap().snapshot() {
+            error!(error = %e, "failed to snapshot local state");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores() {
+        let dir = std::env::temp_dir().join(format!("wewerewondering-{}", Uuid::new_v4()));
+        let (eid, qid) = (Uuid::new_v4(), Uuid::new_v4());
+        let question = |votes: usize| -> Item {
+            HashMap::from_iter([
+                ("id", AttributeValue::S(qid.to_string())),
+                ("eid", AttributeValue::S(eid.to_string())),
+                ("votes", AttributeValue::N(votes.to_string())),
+                ("hidden", AttributeValue::Bool(false)),
+                (
+                    "reactions",
+                    AttributeValue::M(HashMap::from_iter([(
+                        String::from("🎉"),
+                        AttributeValue::N(votes.to_string()),
+                    )])),
+                ),
+            ])
+        };
+        let change = |seq: usize| -> Item {
+            HashMap::from_iter([
+                ("kind", AttributeValue::S(String::from("vote_cast"))),
+                ("seq", AttributeValue::N(seq.to_string())),
+            ])
+        };
+
+        let mut local = open(&dir).unwrap();
+        local.journal.event(&eid, "secret", Some("{}"));
+        local.journal.question(&qid, &question(1));
+        local.journal.change(&eid, &change(1));
+        let announcement = Announcement {
+            text: String::from("hi"),
+            until: u64::MAX,
+        };
+        local.journal.announcement(&eid, Some(&announcement));
+        let scheduled = Scheduled {
+            at: u64::MAX - 1,
+            until: u64::MAX,
+            text: String::from("later"),
+        };
+        local
+            .journal
+            .scheduled(&eid, std::slice::from_ref(&scheduled));
+        drop(local);
+
+        // everything so far ends up in the snapshot, the rest only in the journal
+        let mut local = open(&dir).unwrap();
+        assert_eq!(local.events[&eid], "secret");
+        assert_eq!(local.tos[&eid], "{}");
+        assert_eq!(local.questions_by_eid[&eid], [qid]);
+        assert_eq!(local.announcements[&eid], announcement);
+        assert_eq!(local.scheduled[&eid], std::slice::from_ref(&scheduled));
+        local.snapshot().unwrap();
+        local.journal.question(&qid, &question(2));
+        local.journal.change(&eid, &change(2));
+        local.journal.announcement(&eid, None);
+        drop(local);
+
+        let local = open(&dir).unwrap();
+        assert_eq!(local.tos[&eid], "{}");
+        assert_eq!(local.questions[&qid], question(2));
+        assert_eq!(local.questions_by_eid[&eid], [qid]);
+        assert_eq!(local.changes[&eid], [change(1), change(2)]);
+        assert!(local.announcements.is_empty());
+        assert_eq!(local.scheduled[&eid], [scheduled]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Letting co-hosts see who's dealing with which question, so they don't both do it.
+//!
+//! Before acting on a question, a host's page can take a lease on it with
+//! `POST /api/event/:eid/questions/:secret/:qid/lease`, saying who they are (a random `holder`
+//! token of the page's own, and a `name` to show the others). The lease lasts `LEASE` seconds, and
+//! taking it again before then renews it. While it lasts, other holders get a 409 with the name of
+//! whoever has it instead, and `DELETE` on the same path gives it back early. Leases don't stop
+//! anyone from toggling the question; they're there so hosts can see what the others are doing.
+//!
+//! Hosts listening to the event with its secret (see [`super::ws`] and [`super::sse`]) are told
+//! whenever a lease is taken or given back, as `{"kind": "lease", "qid": .., "name": ..,
+//! "expires_in": ..}` (with no name once it's been given back), and pages that just opened can get
+//! all current leases from `GET /api/event/:eid/leases/:secret`.
+//!
+//! Like the other process-wide state, leases only exist on the instance that handed them out, so
+//! they're only reliable where the server runs on its own, which is also the only place hosts can
+//! be told about them as they happen.
+
+use super::Backend;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json, Response};
+use http::StatusCode;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Mutex, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How long a lease lasts, in seconds.
+const LEASE: u64 = 30;
+/// Forget about expired leases once there are this many.
+const MAX_LEASES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Lease {
+    holder: String,
+    name: String,
+    /// When the lease runs out, in seconds since the epoch.
+    until: u64,
+}
+
+impl Lease {
+    fn to_json(&self, qid: &Uuid, now: u64) -> serde_json::Value {
+        serde_json::json!({
+            "kind": "lease",
+            "qid": qid,
+            "name": self.name,
+            "expires_in": self.until.saturating_sub(now),
+        })
+    }
+}
+
+type Leases = BTreeMap<(Uuid, Uuid), Lease>;
+
+static LEASES: Mutex<Leases> = Mutex::new(BTreeMap::new());
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Give `qid` in `eid` to `holder` (known as `name`) for `LEASE` seconds from `now`, unless it's
+/// someone else's.
+///
+/// Either way, this gives back whoever has the lease afterwards.
+fn take_at(
+    leases: &mut Leases,
+    eid: Uuid,
+    qid: Uuid,
+    holder: &str,
+    name: &str,
+    now: u64,
+) -> Result<Lease, Lease> {
+    if leases.len() >= MAX_LEASES && !leases.contains_key(&(eid, qid)) {
+        leases.retain(|_, l| l.until > now);
+    }
+    match leases.get(&(eid, qid)) {
+        Some(l) if l.until > now && l.holder != holder => Err(l.clone()),
+        _ => {
+            let lease = Lease {
+                holder: holder.to_string(),
+                name: name.to_string(),
+                until: now + LEASE,
+            };
+            leases.insert((eid, qid), lease.clone());
+            Ok(lease)
+        }
+    }
+}
+
+/// Give back `holder`'s lease on `qid` in `eid`, unless someone else has it.
+///
+/// Returns whether there was a lease to give back.
+fn release_at(
+    leases: &mut Leases,
+    eid: Uuid,
+    qid: Uuid,
+    holder: &str,
+    now: u64,
+) -> Result<bool, Lease> {
+    match leases.get(&(eid, qid)) {
+        Some(l) if l.until > now && l.holder != holder => Err(l.clone()),
+        Some(l) => {
+            let held = l.until > now;
+            leases.remove(&(eid, qid));
+            Ok(held)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The leases on questions in `eid` that haven't run out at `now`.
+fn held_at(leases: &Leases, eid: Uuid, now: u64) -> Vec<(Uuid, Lease)> {
+    leases
+        .range((eid, Uuid::nil())..=(eid, Uuid::from_u128(u128::MAX)))
+        .filter(|(_, l)| l.until > now)
+        .map(|((_, qid), l)| (*qid, l.clone()))
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Holder {
+    /// Random, and the same for as long as the host has the event open.
+    holder: String,
+    /// What to show the other hosts.
+    #[serde(default)]
+    name: String,
+}
+
+impl Holder {
+    fn valid(&self) -> bool {
+        !self.holder.is_empty() && self.holder.len() <= 64 && self.name.len() <= 64
+    }
+}
+
+fn conflict(qid: &Uuid, lease: &Lease, now: u64) -> Response {
+    (StatusCode::CONFLICT, Json(lease.to_json(qid, now))).into_response()
+}
+
+pub(super) async fn take(
+    Path((eid, secret, qid)): Path<(Uuid, String, Uuid)>,
+    State(dynamo): State<Backend>,
+    Json(holder): Json<Holder>,
+) -> Response {
+    if !holder.valid() {
+        warn!(%eid, %qid, "lease with bad holder");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    if let Err(status) = super::check_secret(&dynamo, &eid, &secret).await {
+        return status.into_response();
+    }
+    let now = now();
+    let taken = take_at(
+        &mut LEASES.lock().unwrap(),
+        eid,
+        qid,
+        &holder.holder,
+        holder.name.trim(),
+        now,
+    );
+    match taken {
+        Ok(lease) => {
+            debug!(%eid, %qid, name = lease.name, "took lease");
+            let lease = lease.to_json(&qid, now);
+            super::bus::tell_hosts(&eid, lease.clone());
+            Json(lease).into_response()
+        }
+        Err(lease) => conflict(&qid, &lease, now),
+    }
+}
+
+pub(super) async fn release(
+    Path((eid, secret, qid)): Path<(Uuid, String, Uuid)>,
+    State(dynamo): State<Backend>,
+    Json(holder): Json<Holder>,
+) -> Response {
+    if !holder.valid() {
+        warn!(%eid, %qid, "lease release with bad holder");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    if let Err(status) = super::check_secret(&dynamo, &eid, &secret).await {
+        return status.into_response();
+    }
+    let now = now();
+    match release_at(&mut LEASES.lock().unwrap(), eid, qid, &holder.holder, now) {
+        Ok(released) => {
+            if released {
+                debug!(%eid, %qid, "released lease");
+                super::bus::tell_hosts(
+                    &eid,
+                    serde_json::json!({
+                        "kind": "lease",
+                        "qid": qid,
+                        "name": null,
+                        "expires_in": 0,
+                    }),
+                );
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(lease) => conflict(&qid, &lease, now),
+    }
+}
+
+pub(super) async fn leases(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(&dynamo, &eid, &secret).await?;
+    let now = now();
+    let held = held_at(&LEASES.lock().unwrap(), eid, now);
+    Ok(Json(
+        held.into_iter()
+            .map(|(qid, l)| (qid.to_string(), l.to_json(&qid, now)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases_run_out() {
+        let mut leases = BTreeMap::new();
+        let (eid, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (q1, q2) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let alice = take_at(&mut leases, eid, q1, "a", "Alice", 100).unwrap();
+        assert_eq!(alice.until, 100 + LEASE);
+        // someone else can't have it, but the holder can renew it
+        let taken = take_at(&mut leases, eid, q1, "b", "Bob", 110).unwrap_err();
+        assert_eq!(taken.name, "Alice");
+        assert_eq!(
+            take_at(&mut leases, eid, q1, "a", "Alice", 110)
+                .unwrap()
+                .until,
+            110 + LEASE
+        );
+        take_at(&mut leases, eid, q2, "b", "Bob", 110).unwrap();
+        take_at(&mut leases, other, q1, "c", "Carol", 110).unwrap();
+        let held = held_at(&leases, eid, 120);
+        assert_eq!(held.len(), 2);
+        assert_eq!(held_at(&leases, other, 120).len(), 1);
+
+        // once it runs out, it's anyone's
+        assert!(held_at(&leases, eid, 110 + LEASE).is_empty());
+        take_at(&mut leases, eid, q1, "b", "Bob", 110 + LEASE).unwrap();
+
+        // only the holder can give it back
+        assert_eq!(
+            release_at(&mut leases, eid, q1, "a", 150).unwrap_err().name,
+            "Bob"
+        );
+        assert_eq!(release_at(&mut leases, eid, q1, "b", 150), Ok(true));
+        assert_eq!(release_at(&mut leases, eid, q1, "b", 150), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn tells_hosts() {
+        let backend = Backend::local().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let qid = Uuid::new_v4();
+        let holder = |holder: &str, name: &str| {
+            Json(Holder {
+                holder: holder.to_string(),
+                name: name.to_string(),
+            })
+        };
+        let path = || Path((eid, secret.clone(), qid));
+
+        let mut rx = crate::bus::subscribe(&eid);
+        let res = take(path(), State(backend.clone()), holder("a", "Alice")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let notice = rx.try_recv().unwrap();
+        assert!(notice.hosts_only);
+        let notice: serde_json::Value = serde_json::from_str(&notice.text).unwrap();
+        assert_eq!(notice["name"], "Alice");
+        assert_eq!(notice["expires_in"], LEASE);
+
+        let res = take(path(), State(backend.clone()), holder("b", "Bob")).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let Json(held) = leases(Path((eid, secret.clone())), State(backend.clone()))
+            .await
+            .unwrap();
+        assert_eq!(held[qid.to_string()]["name"], "Alice");
+
+        let res = release(path(), State(backend.clone()), holder("a", "")).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let notice: serde_json::Value = serde_json::from_str(&rx.try_recv().unwrap().text).unwrap();
+        assert_eq!(notice["name"], serde_json::Value::Null);
+
+        let wrong = take(
+            Path((eid, String::from("wrong"), qid)),
+            State(backend.clone()),
+            holder("b", "Bob"),
+        )
+        .await;
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        let bad = take(path(), State(backend.clone()), holder("", "Bob")).await;
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+
+        backend.delete(&eid).await;
+    }
+}
@@ -0,0 +1,1138 @@
+use aws_sdk_dynamodb::{model::AttributeValue, types::SdkError};
+use aws_smithy_http::body::SdkBody;
+use axum::response::IntoResponse;
+use axum::routing::{get, post, put, MethodRouter};
+use axum::Router;
+use clap::{Parser, Subcommand};
+use http::StatusCode;
+use lambda_http::Error;
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+use tower::Layer;
+use tower_http::{
+    limit::RequestBodyLimitLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tower_service::Service;
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const SEED: &str = include_str!("test.json");
+
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+enum Backend {
+    Dynamo(region::Regional),
+    Local(Arc<RwLock<Local>>),
+    #[cfg(feature = "mongo")]
+    Mongo(mongo::Mongo),
+    #[cfg(feature = "redis")]
+    Redis(redis::Redis),
+    #[cfg(feature = "sled")]
+    Sled(sled::Sled),
+}
+
+#[cfg(test)]
+impl Backend {
+    async fn local() -> Self {
+        Backend::Local(Arc::new(RwLock::new(Local::default())))
+    }
+
+    async fn dynamo() -> Self {
+        Backend::Dynamo(dynamo().await)
+    }
+
+    #[cfg(feature = "mongo")]
+    async fn mongo() -> Self {
+        let uri = config::config()
+            .mongodb_uri
+            .as_deref()
+            .unwrap_or("mongodb://localhost:27017");
+        Backend::Mongo(mongo::Mongo::connect(uri).await.unwrap())
+    }
+
+    #[cfg(feature = "redis")]
+    async fn redis() -> Self {
+        let url = config::config()
+            .redis_url
+            .as_deref()
+            .unwrap_or("redis://localhost");
+        Backend::Redis(redis::Redis::connect(url).await.unwrap())
+    }
+
+    #[cfg(feature = "sled")]
+    async fn sled() -> Self {
+        Backend::Sled(sled::Sled::temporary().unwrap())
+    }
+}
+
+/// The backend configured by the command line and environment: sled if there's a `data_dir`,
+/// MongoDB if `MONGODB_URI` is set, Redis if `REDIS_URL` is, and DynamoDB otherwise.
+async fn backend(data_dir: Option<&Path>) -> Backend {
+    if let Some(dir) = data_dir {
+        #[cfg(feature = "sled")]
+        return Backend::Sled(sled::Sled::open(dir).expect("failed to open the data directory"));
+        #[cfg(not(feature = "sled"))]
+        {
+            error!(dir = %dir.display(), "--data-dir needs the server to be built with the `sled` feature");
+            std::process::exit(1);
+        }
+    }
+    #[cfg(feature = "mongo")]
+    if let Some(uri) = &config::config().mongodb_uri {
+        return Backend::Mongo(
+            mongo::Mongo::connect(uri)
+                .await
+                .expect("failed to connect to MongoDB"),
+        );
+    }
+    #[cfg(not(feature = "mongo"))]
+    if config::config().mongodb_uri.is_some() {
+        warn!("ignoring MONGODB_URI since the server was built without the `mongo` feature");
+    }
+    #[cfg(feature = "redis")]
+    if let Some(url) = &config::config().redis_url {
+        return Backend::Redis(
+            redis::Redis::connect(url)
+                .await
+                .expect("failed to connect to Redis"),
+        );
+    }
+    #[cfg(not(feature = "redis"))]
+    if config::config().redis_url.is_some() {
+        warn!("ignoring REDIS_URL since the server was built without the `redis` feature");
+    }
+    #[cfg(not(feature = "redis"))]
+    if config::config().list_cache_url.is_some() {
+        warn!("ignoring LIST_CACHE_URL since the server was built without the `redis` feature");
+    }
+    Backend::Dynamo(dynamo().await)
+}
+
+/// A DynamoDB client configured from the environment.
+async fn dynamo() -> region::Regional {
+    let aws = aws_config::load_from_env().await;
+    let mut builder = aws_sdk_dynamodb::config::Builder::from(&aws);
+    let conn =
+        aws_smithy_client::hyper_ext::Adapter::builder().build(aws_smithy_client::conns::https());
+    if let Some(endpoint) = &config::config().dynamodb_endpoint {
+        let uri = endpoint
+            .parse()
+            .expect("DYNAMODB_ENDPOINT is not a valid URI");
+        builder = builder.endpoint_resolver(aws_sdk_dynamodb::Endpoint::immutable(uri));
+        if config::config().dynamodb_failover_region.is_some() {
+            warn!("ignoring DYNAMODB_FAILOVER_REGION since DYNAMODB_ENDPOINT is set");
+        }
+    } else if let Some(failover) = &config::config().dynamodb_failover_region {
+        let health = region::Health::from_config();
+        let primary = aws_sdk_dynamodb::Client::from_conf_conn(
+            builder.build(),
+            xray::Traced::new(region::Watched::new(conn.clone(), Arc::clone(&health))),
+        );
+        let secondary = aws_sdk_dynamodb::Client::from_conf_conn(
+            aws_sdk_dynamodb::config::Builder::from(&aws)
+                .region(aws_sdk_dynamodb::Region::new(failover.clone()))
+                .build(),
+            xray::Traced::new(conn),
+        );
+        return region::Regional::new(primary, secondary, health);
+    }
+    aws_sdk_dynamodb::Client::from_conf_conn(builder.build(), xray::Traced::new(conn)).into()
+}
+
+/// The in-memory backend's tables.
+///
+/// The whole thing sits behind one `RwLock`: reads (listing, fetching texts, reading events) share
+/// it, so they run on as many cores as there are, while writes take it to themselves. Writes have
+/// to go one at a time anyway, since they all append to the same journal, and many of them touch
+/// several tables that have to agree with each other.
+///
+/// It's deliberately not split into a lock per table (or per event). The writes that come in the
+/// most, votes and questions, all go to `questions`, so they'd still queue up behind one lock,
+/// and questions are looked up by id alone, so there's no event to shard them by. What splitting
+/// would buy is reads of one table not waiting for writes to another, at the price of every
+/// multi-table write (asking, deleting, holds) taking its locks in the same order and journaling
+/// in the order the tables changed in, which is easy to get wrong and hard to test.
+#[derive(Debug, Default)]
+struct Local {
+    events: HashMap<Uuid, String>,
+    questions: HashMap<Uuid, HashMap<&'static str, AttributeValue>>,
+    questions_by_eid: HashMap<Uuid, Vec<Uuid>>,
+    changes: HashMap<Uuid, Vec<HashMap<&'static str, AttributeValue>>>,
+    announcements: HashMap<Uuid, announce::Announcement>,
+    scheduled: HashMap<Uuid, Vec<announce::Scheduled>>,
+    moderation: HashMap<Uuid, Vec<moderation::Action>>,
+    /// How many times each event's moderation log has been written. Not journaled, since it only
+    /// has to agree with itself for as long as the server runs.
+    moderated: HashMap<Uuid, u64>,
+    /// The order each event's question list was last locked in, as stored.
+    locked: HashMap<Uuid, String>,
+    /// Each event's retention override, as stored.
+    retention: HashMap<Uuid, String>,
+    /// The terms of service acceptance each event was created with, as stored.
+    tos: HashMap<Uuid, String>,
+    /// When each event was last warmed up. Not journaled, since it's only there to be read back.
+    warmed: HashMap<Uuid, u64>,
+    journal: journal::Journal,
+}
+
+mod actions;
+mod admin;
+mod aging;
+mod announce;
+mod anomaly;
+mod archive;
+mod ask;
+mod assets;
+mod audit;
+mod author;
+mod blobs;
+mod board;
+mod budget;
+mod bus;
+mod changes;
+mod clock;
+mod config;
+#[cfg(all(feature = "dev", debug_assertions))]
+mod dev;
+mod digest;
+mod email;
+mod event;
+mod experiment;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(test)]
+mod golden;
+mod hll;
+mod hold;
+mod hot;
+mod infra;
+mod instance;
+mod journal;
+mod lease;
+mod list;
+mod lock;
+mod merge;
+mod metrics;
+mod mine;
+mod moderation;
+#[cfg(feature = "mongo")]
+mod mongo;
+mod networks;
+mod new;
+mod order;
+#[cfg(test)]
+mod pact;
+mod pdf;
+mod permalink;
+mod permissions;
+mod policy;
+mod poll;
+mod presence;
+mod questions;
+mod react;
+mod rebuild;
+#[cfg(feature = "redis")]
+mod redis;
+mod region;
+mod rejections;
+mod retention;
+mod revise;
+mod seed;
+mod shared;
+mod shed;
+mod sitemap;
+#[cfg(feature = "sled")]
+mod sled;
+mod snapshot;
+mod soak;
+mod sse;
+mod summary;
+mod surge;
+mod survey;
+mod telemetry;
+mod templates;
+mod text;
+mod texts;
+mod timeout;
+mod toggle;
+mod tos;
+mod unfurl;
+mod vote;
+mod warm;
+mod ws;
+mod xray;
+
+/// What most requests need to know about an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Meta {
+    secret: String,
+    /// The sequence number of the event's latest change.
+    version: u64,
+    /// What the host has announced, if it's still showing.
+    announcement: Option<announce::Announcement>,
+    /// What the host has queued up to announce later, if it hasn't ended.
+    scheduled: Vec<announce::Scheduled>,
+    /// Everything operators and the host have done about the event being frozen or held.
+    moderation: Vec<moderation::Action>,
+    /// How many times the moderation log has been written, so that a write can tell whether the
+    /// log changed since it was read (see [`moderation::amend`]).
+    moderated: u64,
+    /// The order the question list was last locked in, as stored (see [`lock::snapshot`]).
+    locked: Option<String>,
+}
+
+impl Meta {
+    /// The announcement that should be showing right now.
+    fn showing(&self) -> Option<announce::Announcement> {
+        announce::showing(self.announcement.as_ref(), &self.scheduled)
+    }
+
+    /// Whether an operator has frozen the event (and not unfrozen it since).
+    fn frozen(&self) -> bool {
+        moderation::frozen(&self.moderation).is_some()
+    }
+
+    /// Whether the event is on legal hold (and hasn't been released since).
+    fn held(&self) -> bool {
+        moderation::held(&self.moderation).is_some()
+    }
+
+    /// Whether the host has closed the event (and not reopened it since).
+    fn closed(&self) -> bool {
+        moderation::closed(&self.moderation).is_some()
+    }
+
+    /// Whether the host has closed the event and published it as a public archive.
+    fn archived(&self) -> bool {
+        moderation::archived(&self.moderation).is_some()
+    }
+
+    /// Whether the host has asked search engines to leave the event out.
+    fn unlisted(&self) -> bool {
+        moderation::unlisted(&self.moderation).is_some()
+    }
+}
+
+async fn get_meta(dynamo: &Backend, eid: &Uuid) -> Result<Meta, StatusCode> {
+    match dynamo {
+        Backend::Dynamo(dynamo) => {
+            match dynamo
+                .get_item()
+                .table_name("events")
+                .key("id", AttributeValue::S(eid.to_string()))
+                .projection_expression(
+                    "secret, seq, announcement, announced_until, scheduled, moderation, moderated, locked",
+                )
+                .send()
+                .await
+            {
+                Ok(v) => {
+                    if let Some(s) = v
+                        .item()
+                        .and_then(|e| e.get("secret"))
+                        .and_then(|s| s.as_s().ok())
+                    {
+                        let n = |field| {
+                            v.item()
+                                .and_then(|e| e.get(field))
+                                .and_then(|n| n.as_n().ok())
+                                .and_then(|n| n.parse().ok())
+                        };
+                        let string = |field| {
+                            v.item()
+                                .and_then(|e| e.get(field))
+                                .and_then(|a| a.as_s().ok())
+                        };
+                        Ok(Meta {
+                            secret: s.clone(),
+                            version: n("seq").unwrap_or(0),
+                            announcement: announce::Announcement::stored(
+                                string("announcement").cloned(),
+                                n("announced_until"),
+                            ),
+                            scheduled: announce::Scheduled::stored(
+                                string("scheduled").map(String::as_str),
+                            ),
+                            moderation: moderation::stored(
+                                string("moderation").map(String::as_str),
+                            ),
+                            moderated: n("moderated").unwrap_or(0),
+                            locked: string("locked").cloned(),
+                        })
+                    } else {
+                        warn!(%eid, "attempted to access non-existing event");
+                        Err(StatusCode::NOT_FOUND)
+                    }
+                }
+                Err(e) => {
+                    error!(%eid, error = %e, "dynamodb event request for secret verificaton failed");
+                    Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+        Backend::Local(local) => {
+            let local = local.read().unwrap();
+            let Local {
+                events,
+                changes,
+                announcements,
+                scheduled,
+                moderation,
+                moderated,
+                locked,
+                ..
+            } = &*local;
+            match events.get(eid) {
+                Some(s) => Ok(Meta {
+                    secret: s.clone(),
+                    version: changes.get(eid).map_or(0, |log| log.len() as u64),
+                    announcement: announcements.get(eid).filter(|a| a.live()).cloned(),
+                    scheduled: scheduled
+                        .get(eid)
+                        .map(|s| s.iter().filter(|s| !s.ended()).cloned().collect())
+                        .unwrap_or_default(),
+                    moderation: moderation.get(eid).cloned().unwrap_or_default(),
+                    moderated: moderated.get(eid).copied().unwrap_or(0),
+                    locked: locked.get(eid).cloned(),
+                }),
+                None => Err(StatusCode::NOT_FOUND),
+            }
+        }
+        #[cfg(feature = "mongo")]
+        Backend::Mongo(mongo) => match mongo.meta(eid).await {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => {
+                warn!(%eid, "attempted to access non-existing event");
+                Err(StatusCode::NOT_FOUND)
+            }
+            Err(e) => {
+                error!(%eid, error = %e, "mongodb event request for secret verificaton failed");
+                Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        #[cfg(feature = "redis")]
+        Backend::Redis(redis) => match redis.meta(eid).await {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => {
+                warn!(%eid, "attempted to access non-existing event");
+                Err(StatusCode::NOT_FOUND)
+            }
+            Err(e) => {
+                error!(%eid, error = %e, "redis event request for secret verificaton failed");
+                Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        #[cfg(feature = "sled")]
+        Backend::Sled(sled) => match sled.meta(eid) {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => {
+                warn!(%eid, "attempted to access non-existing event");
+                Err(StatusCode::NOT_FOUND)
+            }
+            Err(e) => {
+                error!(%eid, error = %e, "sled event request for secret verificaton failed");
+                Err(http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+    }
+}
+
+/// Check that `secret` is the event's secret, and return what else there is to know if it is.
+async fn check_secret(dynamo: &Backend, eid: &Uuid, secret: &str) -> Result<Meta, StatusCode> {
+    let meta = get_meta(dynamo, eid).await?;
+    if meta.secret == secret {
+        Ok(meta)
+    } else {
+        warn!(%eid, secret, "attempted to access event with incorrect secret");
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Apply the configured concurrency limits and timeout for `route` to its handler.
+///
+/// The timeout wraps the concurrency limits, so that it also bounds how long writes queue.
+fn timed<B>(route: &'static str, handler: MethodRouter<Backend, B>) -> MethodRouter<Backend, B>
+where
+    B: axum::body::HttpBody + Send + 'static,
+{
+    handler
+        .layer(axum::middleware::from_fn_with_state(
+            shed::gate(route),
+            shed::admit,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            timeout::limit(route),
+            timeout::enforce,
+        ))
+        // events that are over budget shouldn't take up any permits either
+        .layer(axum::middleware::from_fn_with_state(
+            budget::check(route),
+            budget::enforce,
+        ))
+        // outermost, so that requests the policy refuses don't take up any permits
+        .layer(axum::middleware::from_fn_with_state(
+            policy::check(route),
+            policy::enforce,
+        ))
+        // but what the policy refused is on record too
+        .layer(axum::middleware::from_fn_with_state(
+            audit::route(route),
+            audit::record,
+        ))
+}
+
+/// Report a failure of a non-DynamoDB backend the way the DynamoDB client reports failing to reach
+/// the service.
+#[cfg(any(feature = "mongo", feature = "redis", feature = "sled"))]
+fn mint_dispatch_failure<E>(e: impl std::error::Error + Send + Sync + 'static) -> SdkError<E> {
+    SdkError::DispatchFailure(aws_smithy_http::result::ConnectorError::other(
+        Box::new(e),
+        None,
+    ))
+}
+
+/// Report a failure of a non-DynamoDB backend as an error the DynamoDB client doesn't know about.
+#[cfg(any(feature = "mongo", feature = "redis", feature = "sled"))]
+fn mint_unhandled(e: impl std::error::Error + Send + Sync + 'static) -> aws_sdk_dynamodb::Error {
+    aws_sdk_dynamodb::Error::Unhandled(Box::new(e))
+}
+
+fn mint_service_error<E>(e: E) -> SdkError<E> {
+    SdkError::ServiceError {
+        err: e,
+        raw: aws_smithy_http::operation::Response::new(
+            http::Response::builder().body(SdkBody::empty()).unwrap(),
+        ),
+    }
+}
+
+/// The wewerewondering API server.
+///
+/// Without a subcommand, it serves the API (locally in debug builds, as a Lambda otherwise).
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    /// Keep all data in DIR rather than in DynamoDB (or another database).
+    ///
+    /// The API is then served over HTTP directly, also in release builds, so together with
+    /// `--static-dir` this is a complete deployment without any external services. Needs the
+    /// `sled` feature.
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+    /// Also serve the built client from DIR.
+    ///
+    /// With the `embed` feature, the client that was built into the binary is served otherwise.
+    #[arg(long, value_name = "DIR")]
+    static_dir: Option<PathBuf>,
+    /// Keep the in-memory backend's state in DIR, so that it survives restarts.
+    ///
+    /// Only debug builds use the in-memory backend. It starts out with the seed data if DIR
+    /// doesn't hold any events yet.
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+    /// The address to serve HTTP on when not running as a Lambda.
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    listen: SocketAddr,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a CloudFormation template with the tables and IAM policy the server needs.
+    PrintInfra,
+    /// Print the least-privilege IAM policy for the role the server runs as.
+    PrintPolicy {
+        /// The AWS account that holds the tables.
+        #[arg(long)]
+        account: String,
+        /// The region that holds the tables.
+        #[arg(long, default_value = "*")]
+        region: String,
+    },
+    /// Check which of the DynamoDB permissions the server needs are missing.
+    ///
+    /// Uses the credentials from the environment, and never modifies any data.
+    CheckPermissions,
+    /// Replay an event's change log to check (and optionally repair) vote counts and flags.
+    Rebuild {
+        /// The event to rebuild.
+        #[arg(long)]
+        event: Uuid,
+        /// Write the replayed state back where it differs from what's stored, unless the log looks
+        /// like it's missing changes.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Scrub and delete questions the retention policy (`RETENTION`) says are due, and print what
+    /// was (or would be) done.
+    Retention {
+        /// Actually change things, rather than only reporting what would change.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Send hosts the digests of their events that are due, and print what was sent.
+    Digest,
+    /// Hide the unanswered questions that are older than their event's host lets them get, and
+    /// print what was (or would be) hidden.
+    Aging {
+        /// Actually hide questions, rather than only reporting what would be hidden.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Score the votes of events that have changed since they were last looked at, hold back
+    /// suspicious ones where hosts asked for that, and print what was found.
+    Anomalies,
+    /// Fill the backend with realistic-looking generated events, and print their ids and secrets.
+    Seed(seed::Params),
+    /// Export every DynamoDB table into a directory, or finish an export that was cut off, and
+    /// print how many items each table had.
+    Snapshot {
+        /// The directory to export into.
+        #[arg(long, value_name = "DIR")]
+        out: PathBuf,
+        /// How many segments to scan each table in at once.
+        #[arg(long, default_value_t = 4)]
+        segments: u32,
+    },
+    /// Load a snapshot into the DynamoDB tables.
+    Restore {
+        /// The directory `snapshot` exported into.
+        #[arg(long, value_name = "DIR")]
+        from: PathBuf,
+        /// Afterwards, check that every question and change log belongs to an event that's
+        /// there, and fail if not.
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Send traffic to a running instance for a long time, and fail if it seems to leak memory
+    /// or file descriptors.
+    Soak(soak::Params),
+}
+
+/// An in-memory backend starting from `state` plus a single event
+/// (`00000000-0000-0000-0000-000000000000`) full of questions, some of which keep getting upvoted.
+#[cfg(debug_assertions)]
+async fn seeded(mut state: Local) -> Backend {
+    use rand::prelude::SliceRandom;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    struct LiveAskQuestion {
+        likes: usize,
+        text: String,
+        hidden: bool,
+        answered: bool,
+        #[serde(rename = "createTimeUnix")]
+        created: usize,
+    }
+
+    let seed: Vec<LiveAskQuestion> = serde_json::from_str(SEED).unwrap();
+    let seed_e = "00000000-0000-0000-0000-000000000000";
+    let seed_e = Uuid::parse_str(seed_e).unwrap();
+    state.events.insert(seed_e, String::from("secret"));
+    state.questions_by_eid.insert(seed_e, Vec::new());
+    let mut state = Backend::Local(Arc::new(RwLock::new(state)));
+    let mut qs = Vec::new();
+    for q in seed {
+        let qid = uuid::Uuid::new_v4();
+        state
+            .ask_at(
+                &seed_e,
+                &qid,
+                ask::Question {
+                    body: q.text,
+                    asker: None,
+                },
+                clock::now(),
+                false,
+            )
+            .await
+            .unwrap();
+        qs.push((qid, q.created, q.likes, q.hidden, q.answered));
+    }
+    let mut qids = Vec::new();
+    {
+        let Backend::Local(ref mut state): Backend = state else {
+            unreachable!();
+        };
+        let state = Arc::get_mut(state).unwrap();
+        let state = RwLock::get_mut(state).unwrap();
+        for (qid, created, votes, hidden, answered) in qs {
+            let q = state.questions.get_mut(&qid).unwrap();
+            q.insert("votes", AttributeValue::N(votes.to_string()));
+            q.insert("answered", AttributeValue::Bool(answered));
+            q.insert("hidden", AttributeValue::Bool(hidden));
+            q.insert("when", AttributeValue::N(created.to_string()));
+            qids.push(qid);
+        }
+    }
+    let cheat = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let qid = qids.choose(&mut rand::thread_rng()).unwrap();
+            let _ = cheat.vote(qid, vote::UpDown::Up).await;
+        }
+    });
+    state
+}
+
+/// The API's routes.
+fn api<B>() -> Router<Backend, B>
+where
+    B: axum::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<axum::BoxError>,
+{
+    Router::new()
+        .route("/api/event", timed("new", post(new::new)))
+        .route("/api/tos", timed("tos", get(tos::tos)))
+        .route("/api/instance", timed("instance", get(instance::instance)))
+        .route("/api/event/:eid", timed("ask", post(ask::ask)))
+        .route("/api/event/:eid", timed("event", get(event::event)))
+        .route("/api/event/:eid/questions", timed("list", get(list::list)))
+        .route("/api/event/:eid/ping", timed("ping", post(presence::ping)))
+        .route(
+            "/api/event/:eid/mine",
+            timed("mine", post(mine::mine).get(mine::authored)),
+        )
+        .route(
+            "/api/event/:eid/revise/:qid",
+            timed("revise", post(revise::revise)),
+        )
+        .route(
+            "/api/event/:eid/edit/:qid",
+            timed("edit", post(author::edit)),
+        )
+        .route(
+            "/api/event/:eid/survey",
+            timed("survey", post(survey::survey)),
+        )
+        .route(
+            "/api/event/:eid/retract/:qid",
+            timed("retract", post(author::retract)),
+        )
+        .route("/api/event/:eid/board", timed("board", get(board::board)))
+        .route("/api/event/:eid/text", timed("text", get(text::text)))
+        .route("/api/event/:eid/export.pdf", timed("pdf", get(pdf::export)))
+        .route("/api/event/:eid/ws", timed("ws", get(ws::ws)))
+        .route("/api/event/:eid/stream", timed("stream", get(sse::stream)))
+        .route(
+            "/api/experiment",
+            timed("experiment", post(experiment::experiment)),
+        )
+        .route(
+            "/api/telemetry/client-error",
+            timed("client_error", post(telemetry::client_error)),
+        )
+        .route(
+            "/api/event/:eid/changes",
+            timed("changes", get(changes::changes)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret",
+            timed("list_all", get(list::list_all)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret/:qid/toggle/:property",
+            timed("toggle", post(toggle::toggle)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret/:qid/actions",
+            timed("actions", post(actions::actions)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret/:qid/answer",
+            timed("answer", post(templates::answer)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret/:qid/merge/:into",
+            timed("merge", post(merge::merge)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret/:qid/history",
+            timed("history", get(author::earlier)),
+        )
+        .route(
+            "/api/event/:eid/summary/:secret",
+            timed("summary", get(summary::summary)),
+        )
+        .route(
+            "/api/event/:eid/templates/:secret",
+            timed("templates", get(templates::templates)),
+        )
+        .route(
+            "/api/event/:eid/templates/:secret/:name",
+            timed("templates", put(templates::put).delete(templates::delete)),
+        )
+        .route(
+            "/api/event/:eid/questions/:secret/:qid/lease",
+            timed("lease", post(lease::take).delete(lease::release)),
+        )
+        .route(
+            "/api/event/:eid/leases/:secret",
+            timed("leases", get(lease::leases)),
+        )
+        .route(
+            "/api/event/:eid/announce/:secret",
+            timed(
+                "announce",
+                get(announce::announcements).post(announce::announce),
+            ),
+        )
+        .route(
+            "/api/event/:eid/appeal/:secret",
+            timed(
+                "appeal",
+                get(moderation::status_for_host).post(moderation::appeal),
+            ),
+        )
+        .route(
+            "/api/event/:eid/hold/:secret",
+            timed("hold", post(hold::hold)),
+        )
+        .route(
+            "/api/event/:eid/close/:secret",
+            timed("close", post(archive::close)),
+        )
+        .route(
+            "/api/event/:eid/robots/:secret",
+            timed("robots", post(sitemap::robots)),
+        )
+        .route(
+            "/api/event/:eid/networks/:secret",
+            timed("networks", post(networks::restrict)),
+        )
+        .route(
+            "/api/event/:eid/lock/:secret",
+            timed("lock", post(lock::lock)),
+        )
+        .route(
+            "/api/event/:eid/digest/:secret",
+            timed("digest", post(digest::digest)),
+        )
+        .route(
+            "/api/event/:eid/aging/:secret",
+            timed("aging", post(aging::aging)),
+        )
+        .route(
+            "/api/event/:eid/order/:secret",
+            timed("order", post(order::order)),
+        )
+        .route(
+            "/api/event/:eid/anomalies/:secret",
+            timed(
+                "anomalies",
+                get(anomaly::anomalies).post(anomaly::quarantine),
+            ),
+        )
+        .route(
+            "/api/event/:eid/anomalies/:secret/:qid/:decision",
+            timed("anomalies", post(anomaly::review)),
+        )
+        .route("/archive/:eid", timed("archive", get(archive::archive)))
+        .route("/share/event/:eid", timed("unfurl", get(unfurl::event)))
+        .route("/share/q/:qid", timed("unfurl", get(unfurl::question)))
+        .route("/sitemap.xml", timed("sitemap", get(sitemap::sitemap)))
+        .route("/api/vote/:qid/:updown", timed("vote", post(vote::vote)))
+        // really `/api/vote/:eid/:qid/react/:emoji`, but the router wants parameters in the same
+        // place to have the same name (and they're extracted in order anyway)
+        .route(
+            "/api/vote/:qid/:updown/react/:emoji",
+            timed("react", post(react::react)),
+        )
+        .route(
+            "/api/questions/:qids",
+            timed("questions", get(questions::questions)),
+        )
+        .route("/api/q/:qid", timed("permalink", get(permalink::permalink)))
+        .route(
+            "/api/admin/permissions",
+            timed("permissions", get(permissions::permissions)),
+        )
+        .route("/api/admin/metrics", get(metrics::metrics))
+        .route("/api/admin/audit", timed("audit", get(audit::export)))
+        .route(
+            "/api/admin/event/:eid/moderation",
+            timed(
+                "moderate",
+                get(moderation::review).post(moderation::moderate),
+            ),
+        )
+        .route(
+            "/api/admin/event/:eid/hold",
+            timed("set_hold", post(hold::set_hold)),
+        )
+        .route(
+            "/api/admin/event/:eid/retention",
+            timed("retention", post(retention::retention)),
+        )
+        .route("/api/admin/warm/:eid", timed("warm", post(warm::warm)))
+        .route("/api/admin/client-errors", get(telemetry::client_errors))
+}
+
+/// Run the server, or whatever other command was given on the command line.
+pub async fn run() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .without_time(/* cloudwatch does that */).init();
+
+    let args = Args::parse();
+    match args.command {
+        Some(Command::PrintInfra) => {
+            println!("{}", serde_json::to_string_pretty(&infra::template())?);
+            return Ok(());
+        }
+        Some(Command::PrintPolicy { account, region }) => {
+            let policy = infra::policy_for_account(&region, &account);
+            println!("{}", serde_json::to_string_pretty(&policy)?);
+            return Ok(());
+        }
+        Some(Command::CheckPermissions) => {
+            let backend = Backend::Dynamo(dynamo().await);
+            let report = permissions::report(&backend).await;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if report["ok"] != true {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Rebuild { event, apply }) => {
+            let backend = backend(args.data_dir.as_deref()).await;
+            let report = backend.rebuild(&event, apply).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if apply && report["refused"].is_string() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Retention { apply }) => {
+            let backend = backend(args.data_dir.as_deref()).await;
+            let mut report = backend
+                .retention(&config::config().retention, apply)
+                .await?;
+            report.audit_expired = audit::prune(blobs::store().await, apply).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+        Some(Command::Digest) => {
+            let backend = backend(args.data_dir.as_deref()).await;
+            let report = digest::run(&backend, blobs::store().await)
+                .await
+                .map_err(|status| format!("failed to send digests: {status}"))?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Aging { apply }) => {
+            let backend = backend(args.data_dir.as_deref()).await;
+            let report = aging::run(&backend, blobs::store().await, apply)
+                .await
+                .map_err(|status| format!("failed to age questions: {status}"))?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Anomalies) => {
+            let backend = backend(args.data_dir.as_deref()).await;
+            let report = anomaly::run(&backend, blobs::store().await)
+                .await
+                .map_err(|status| format!("failed to look for anomalies: {status}"))?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Soak(params)) => {
+            let report = soak::run(&params).await;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if report["ok"] != true {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Seed(params)) => {
+            let backend = backend(args.data_dir.as_deref()).await;
+            let events = seed::run(&backend, &params).await?;
+            println!("{}", serde_json::to_string_pretty(&events)?);
+            return Ok(());
+        }
+        Some(Command::Snapshot { out, segments }) => {
+            let manifest = snapshot::snapshot(&*dynamo().await, &out, segments).await?;
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+            return Ok(());
+        }
+        Some(Command::Restore { from, verify }) => {
+            let report = snapshot::restore(&*dynamo().await, &from, verify).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if report.orphans.as_ref().is_some_and(|o| !o.is_empty()) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    #[cfg(debug_assertions)]
+    let backend = if args.data_dir.is_some() {
+        backend(args.data_dir.as_deref()).await
+    } else if let Some(dir) = &args.state_dir {
+        let state = journal::open(dir)?;
+        let backend = if state.events.is_empty() {
+            seeded(state).await
+        } else {
+            Backend::Local(Arc::new(RwLock::new(state)))
+        };
+        let Backend::Local(state) = &backend else {
+            unreachable!();
+        };
+        // the seed data doesn't go through the journal
+        state.write().unwrap().snapshot()?;
+        tokio::spawn(journal::snapshots(state.clone()));
+        backend
+    } else {
+        seeded(Local::default()).await
+    };
+    #[cfg(not(debug_assertions))]
+    let backend = backend(args.data_dir.as_deref()).await;
+
+    // checked after routing, since it's only for routes that name an event
+    let mut app = api().route_layer(axum::middleware::from_fn_with_state(
+        backend.clone(),
+        networks::enforce,
+    ));
+    #[cfg(all(feature = "dev", debug_assertions))]
+    {
+        app = app
+            .layer(axum::middleware::from_fn(dev::inject))
+            .nest("/dev", dev::routes());
+    }
+    if let Some(dir) = &args.static_dir {
+        app = app.fallback_service(assets::dir(dir));
+    }
+    #[cfg(feature = "embed")]
+    if args.static_dir.is_none() {
+        app = app.fallback_service(assets::embedded());
+    }
+    // emails are much bigger than anything else that's sent to the API
+    let email = Router::new()
+        .route("/api/email/:token", timed("email", post(email::email)))
+        .layer(RequestBodyLimitLayer::new(email::MAX_BODY));
+    // and so are long lists of question ids
+    let texts = Router::new()
+        .route(
+            "/api/questions",
+            timed("questions", post(questions::questions_in_body)),
+        )
+        .layer(RequestBodyLimitLayer::new(questions::MAX_BODY));
+    let app = app
+        .layer(RequestBodyLimitLayer::new(1024))
+        .merge(email)
+        .merge(texts)
+        .layer(axum::middleware::from_fn(xray::trace))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(backend);
+    // now that every route is set up, a policy that names one that isn't is a mistake
+    policy::verify()?;
+    // this has to happen before routing, so it can't be a layer on the router
+    let app = tower::ServiceBuilder::new()
+        .map_request(text::rewrite)
+        .service(app);
+
+    if cfg!(debug_assertions) || args.data_dir.is_some() {
+        Ok(axum::Server::bind(&args.listen)
+            .serve(axum::ServiceExt::<http::Request<hyper::Body>>::into_make_service_with_connect_info::<
+                std::net::SocketAddr,
+            >(app))
+            .await?)
+    } else {
+        // If we compile in release mode, use the Lambda Runtime
+        // To run with AWS Lambda runtime, wrap in our `LambdaLayer`
+        let app = tower::ServiceBuilder::new()
+            .layer(TraceLayer::new_for_http())
+            .layer(LambdaLayer)
+            .service(app);
+
+        Ok(lambda_http::run(app).await?)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct LambdaLayer;
+
+impl<S> Layer<S> for LambdaLayer {
+    type Service = LambdaService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LambdaService { inner }
+    }
+}
+
+pub struct LambdaService<S> {
+    inner: S,
+}
+
+impl<S> Service<lambda_http::Request> for LambdaService<S>
+where
+    S: Service<axum::http::Request<axum::body::Body>>,
+    S::Response: axum::response::IntoResponse + Send + 'static,
+    S::Error: std::error::Error + Send + Sync + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = lambda_http::Response<lambda_http::Body>;
+    type Error = lambda_http::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: lambda_http::Request) -> Self::Future {
+        let (parts, body) = req.into_parts();
+        let body = match body {
+            lambda_http::Body::Empty => axum::body::Body::default(),
+            lambda_http::Body::Text(t) => t.into(),
+            lambda_http::Body::Binary(v) => v.into(),
+        };
+
+        let request = axum::http::Request::from_parts(parts, body);
+
+        let fut = self.inner.call(request);
+        let fut = async move {
+            let resp = fut.await?;
+            let (parts, body) = resp.into_response().into_parts();
+            let bytes = hyper::body::to_bytes(body).await?;
+            let bytes: &[u8] = &bytes;
+            let resp: hyper::Response<lambda_http::Body> = match std::str::from_utf8(bytes) {
+                Ok(s) => hyper::Response::from_parts(parts, s.into()),
+                Err(_) => hyper::Response::from_parts(parts, bytes.into()),
+            };
+            Ok(resp)
+        };
+
+        Box::pin(fut)
+    }
+}
@@ -1,4 +1,7 @@
-use super::{Backend, Local};
+use super::{
+    announce::Announcement, experiment::Ordering, hll::Sketch, hot::Question, react::Reactions,
+    Backend, Local,
+};
 use aws_sdk_dynamodb::{
     error::{QueryError, QueryErrorKind, ResourceNotFoundException},
     model::AttributeValue,
@@ -8,25 +11,60 @@ use aws_sdk_dynamodb::{
 use aws_smithy_types::Error;
 use axum::response::Json;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::AppendHeaders,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use http::{
     header::{self, HeaderName},
-    StatusCode,
+    HeaderMap, StatusCode,
 };
+use serde::Deserialize;
+use std::{collections::HashMap, time::SystemTime};
 use uuid::Uuid;
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
 impl Backend {
+    /// All of `eid`'s questions, by votes, including hidden ones if `has_secret`.
     pub(super) async fn list(
         &self,
         eid: &Uuid,
         has_secret: bool,
     ) -> Result<QueryOutput, SdkError<QueryError>> {
-        match self {
+        let mut items = Vec::new();
+        let mut start = None;
+        loop {
+            let page = self.list_page(eid, has_secret, start, None).await?;
+            start = page.last_evaluated_key().cloned();
+            if start.is_none() && items.is_empty() {
+                return Ok(page);
+            }
+            items.extend(page.items().unwrap_or_default().iter().cloned());
+            if start.is_none() {
+                return Ok(QueryOutput::builder()
+                    .set_count(Some(items.len() as i32))
+                    .set_items(Some(items))
+                    .build());
+            }
+        }
+    }
+
+    /// The page of `eid`'s question list that starts after `start`, which is the
+    /// `LastEvaluatedKey` of the page before it.
+    ///
+    /// DynamoDB ends pages after `limit` questions (counting hidden ones, even when they're left
+    /// out), or at a megabyte. The other backends have every question in one page unless there's
+    /// a limit, and their keys are offsets into the whole list.
+    pub(super) async fn list_page(
+        &self,
+        eid: &Uuid,
+        has_secret: bool,
+        start: Option<HashMap<String, AttributeValue>>,
+        limit: Option<i32>,
+    ) -> Result<QueryOutput, SdkError<QueryError>> {
+        let all = match self {
             Self::Dynamo(dynamo) => {
                 let query = dynamo.query();
                 let query = query
@@ -45,18 +83,22 @@ impl Backend {
                         .expression_attribute_values(":false", AttributeValue::Bool(false))
                 };
 
-                query.send().await
+                return query
+                    .set_exclusive_start_key(start)
+                    .set_limit(limit)
+                    .send()
+                    .await;
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
+                let local = local.read().unwrap();
                 let Local {
                     questions,
                     questions_by_eid,
                     events,
                     ..
-                } = &mut *local;
+                } = &*local;
 
-                if !events.contains_key(&eid) {
+                if !events.contains_key(eid) {
                     return Err(super::mint_service_error(QueryError::new(
                         QueryErrorKind::ResourceNotFoundException(
                             ResourceNotFoundException::builder().build(),
@@ -65,9 +107,11 @@ impl Backend {
                     )));
                 }
 
-                let qs = questions_by_eid
-                    .get_mut(eid)
-                    .expect("list for non-existing event");
+                // sorted on a copy, so that listing only needs to read
+                let mut qs = questions_by_eid
+                    .get(eid)
+                    .expect("list for non-existing event")
+                    .clone();
                 qs.sort_unstable_by_key(|qid| {
                     std::cmp::Reverse(
                         questions[qid]["votes"]
@@ -78,7 +122,7 @@ impl Backend {
                     )
                 });
 
-                Ok(QueryOutput::builder()
+                QueryOutput::builder()
                     .set_count(Some(qs.len() as i32))
                     .set_items(Some(
                         qs.iter()
@@ -98,113 +142,741 @@ impl Backend {
                             })
                             .collect(),
                     ))
-                    .build())
+                    .build()
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.list(eid, has_secret).await?,
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.list(eid, has_secret).await?,
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.list(eid, has_secret).await?,
+        };
+        Ok(by_offset(all, start.as_ref(), limit))
+    }
+}
+
+/// The part of the whole list `all` that starts at the offset in `start`, with at most `limit`
+/// questions, and the offset the next page starts at, if there is one.
+fn by_offset(
+    all: QueryOutput,
+    start: Option<&HashMap<String, AttributeValue>>,
+    limit: Option<i32>,
+) -> QueryOutput {
+    let offset = start
+        .and_then(|k| k.get("offset"))
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut items = all.items.unwrap_or_default();
+    let end = limit.map_or(items.len(), |n| {
+        offset.saturating_add(n.max(1) as usize).min(items.len())
+    });
+    let next = (end < items.len())
+        .then(|| HashMap::from([(String::from("offset"), AttributeValue::N(end.to_string()))]));
+    let items: Vec<_> = items.drain(offset.min(end)..end).collect();
+    QueryOutput::builder()
+        .set_count(Some(items.len() as i32))
+        .set_items(Some(items))
+        .set_last_evaluated_key(next)
+        .build()
+}
+
+/// Read a question out of an item of the question list.
+pub(super) fn parse(eid: &Uuid, doc: &HashMap<String, AttributeValue>) -> Option<(Uuid, Question)> {
+    let qid = doc
+        .get("id")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok());
+    let votes = doc
+        .get("votes")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    let hidden = doc.get("hidden").and_then(|v| v.as_bool().ok());
+    let answered = doc.get("answered").and_then(|v| v.as_bool().ok());
+    // questions nobody has voted for with a voter token yet have no sketch
+    let voters = doc
+        .get("voters")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Sketch::stored(v))
+        .map_or(0, |s| s.estimate());
+    let merged_into = doc
+        .get("merged_into")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok());
+    // the author is a hash already, so a part of it is plenty to tell askers apart
+    let asker = doc
+        .get("author")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| u64::from_str_radix(v.get(..16)?, 16).ok());
+    let reactions = Reactions::from_item(doc.get("reactions"));
+    // only orderings care, so questions from before the index included it are just old
+    let asked = doc
+        .get("when")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    match (qid, votes, hidden, answered) {
+        (Some(qid), Some(votes), Some(&hidden), Some(&answered)) => Some((
+            qid,
+            Question {
+                votes,
+                hidden,
+                answered,
+                voters,
+                asked,
+                merged_into,
+                asker,
+                reactions,
+            },
+        )),
+        (Some(qid), _, _, _) => {
+            error!(%eid, %qid, ?doc, "found malformed question");
+            None
+        }
+        _ => {
+            error!(%eid, ?doc, "found malformed question id");
+            None
+        }
+    }
+}
+
+/// The weak ETag of an event's question list at `version`.
+fn etag(version: u64) -> String {
+    format!("W/\"{version}\"")
+}
+
+/// Whether the client already has the list at `version`, according to `If-None-Match`.
+fn fresh(headers: &HeaderMap, version: u64) -> bool {
+    let etag = etag(version);
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        // list ETags are weak, so the comparison is too
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == &etag[2..])
+}
+
+type Listing = (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Option<AppendHeaders<Vec<(HeaderName, String)>>>,
+    Result<Json<serde_json::Value>, StatusCode>,
+);
+
+/// Says which order a question list is in.
+pub(super) const ORDER: HeaderName = HeaderName::from_static("x-order");
+
+/// Marks question lists that are in the order the host [locked](super::lock) them in, which clients
+/// should keep rather than re-sort.
+pub(super) const LOCKED: HeaderName = HeaderName::from_static("x-locked");
+
+/// The order a question list was put in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Arrangement {
+    pub(super) order: Ordering,
+    /// Whether the host's lock took over from `order`.
+    pub(super) locked: bool,
+}
+
+impl Arrangement {
+    /// The headers list responses say how they're arranged in.
+    fn headers(self) -> impl Iterator<Item = (HeaderName, String)> {
+        let locked = self.locked.then(|| (LOCKED, String::from("true")));
+        std::iter::once((ORDER, self.order.to_string())).chain(locked)
+    }
+}
+
+/// The value of the `name` header among the `tags` of a list response, if it has one.
+#[cfg(test)]
+pub(super) fn tag(
+    tags: &Option<AppendHeaders<Vec<(HeaderName, String)>>>,
+    name: &HeaderName,
+) -> Option<String> {
+    let AppendHeaders(tags) = tags.as_ref()?;
+    tags.iter().find(|(h, _)| h == name).map(|(_, v)| v.clone())
+}
+
+/// Where the next page of a question list starts.
+pub(super) const NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// The most questions a page of the question list can have.
+const MAX_LIMIT: usize = 1000;
+
+/// Which of an event's questions to list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Filter {
+    /// All of them (that the asker can see).
+    #[default]
+    All,
+    /// Only those that aren't hidden.
+    Visible,
+    /// Only hidden ones, which only hosts ever get.
+    Hidden,
+    /// Only answered ones.
+    Answered,
+    /// Only those still waiting for an answer.
+    Unanswered,
+}
+
+impl Filter {
+    /// Whether `q`, as it's listed, is one of the questions to list.
+    fn keeps(self, q: &serde_json::Value) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Visible => q["hidden"] == false,
+            Filter::Hidden => q["hidden"] == true,
+            Filter::Answered => q["answered"] == true,
+            Filter::Unanswered => q["answered"] == false,
         }
     }
 }
 
+/// How guests want their question list (see [`super::experiment`]), and which part of it.
+///
+/// The order can also be given as `?sort=`, which is what hosts switching views tend to use. Lists
+/// that don't say are in the order the host picked (see [`super::order`]).
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Order {
+    #[serde(default, alias = "sort")]
+    pub(super) order: Option<Ordering>,
+    /// Which questions to list, so clients don't have to download all of them to show some.
+    #[serde(default)]
+    pub(super) filter: Filter,
+    /// Where to pick up, from the `x-next-cursor` of the page before.
+    pub(super) cursor: Option<String>,
+    /// How many questions to list at most, if not all of them.
+    pub(super) limit: Option<usize>,
+}
+
+/// The part of a question list a guest asked for.
+#[derive(Debug, PartialEq)]
+struct Page {
+    /// The `LastEvaluatedKey` of the page before, if this isn't the first.
+    start: Option<HashMap<String, AttributeValue>>,
+    limit: Option<usize>,
+}
+
+/// The cursor for the page of a question list that starts after `key`.
+///
+/// It's the key itself, so that the page can be read straight from the database, but clients are
+/// to treat it as opaque.
+fn cursor(key: &HashMap<String, AttributeValue>) -> String {
+    let key: serde_json::Map<_, _> = key
+        .iter()
+        .filter_map(|(k, v)| {
+            let v = match v {
+                AttributeValue::S(s) => serde_json::json!({ "S": s }),
+                AttributeValue::N(n) => serde_json::json!({ "N": n }),
+                _ => return None,
+            };
+            Some((k.clone(), v))
+        })
+        .collect();
+    URL_SAFE_NO_PAD.encode(serde_json::Value::from(key).to_string())
+}
+
+/// The key a `cursor` of `eid`'s question list stands for, if it's one.
+fn key(eid: &Uuid, cursor: &str) -> Option<HashMap<String, AttributeValue>> {
+    let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let key: HashMap<String, HashMap<String, String>> = serde_json::from_slice(&json).ok()?;
+    let key = key
+        .into_iter()
+        .map(|(k, mut v)| {
+            let v = match (v.remove("S"), v.remove("N")) {
+                (Some(s), None) => AttributeValue::S(s),
+                (None, Some(n)) if n.parse::<u64>().is_ok() => AttributeValue::N(n),
+                _ => return None,
+            };
+            Some((k, v))
+        })
+        .collect::<Option<HashMap<_, _>>>()?;
+    // the database won't start one event's list in another's
+    match key.get("eid") {
+        Some(AttributeValue::S(of)) if *of != eid.to_string() => None,
+        _ => Some(key),
+    }
+}
+
+impl Page {
+    /// The page of `eid`'s list that starts at `cursor` and has at most `limit` questions, if
+    /// those make sense.
+    fn new(eid: &Uuid, cursor: Option<&str>, limit: Option<usize>) -> Option<Self> {
+        let start = match cursor {
+            Some(cursor) => Some(key(eid, cursor)?),
+            None => None,
+        };
+        let limit = match limit {
+            Some(0) => return None,
+            limit => limit.map(|n| n.min(MAX_LIMIT)),
+        };
+        Some(Page { start, limit })
+    }
+
+    /// Whether this is the whole list rather than a page of it.
+    fn whole(&self) -> bool {
+        self.start.is_none() && self.limit.is_none()
+    }
+}
+
+/// The response to a request for a page that can't be had.
+fn bad_page() -> Listing {
+    // a bad cursor will not turn good
+    (
+        AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
+        None,
+        Err(StatusCode::BAD_REQUEST),
+    )
+}
+
+/// Just the questions in `listing` that pass `filter`.
+fn filtered(filter: Filter, (cache, tags, listed): Listing) -> Listing {
+    let listed = listed.map(|Json(questions)| {
+        let mut questions = match questions {
+            serde_json::Value::Array(questions) => questions,
+            other => return Json(other),
+        };
+        questions.retain(|q| filter.keeps(q));
+        Json(serde_json::Value::from(questions))
+    });
+    (cache, tags, listed)
+}
+
 pub(super) async fn list(
     Path(eid): Path<Uuid>,
     State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<serde_json::Value>, StatusCode>,
-) {
-    list_inner(Path((eid, None)), State(dynamo)).await
+    Query(Order {
+        order,
+        filter,
+        cursor,
+        limit,
+    }): Query<Order>,
+    headers: HeaderMap,
+) -> Listing {
+    let Some(page) = Page::new(&eid, cursor.as_deref(), limit) else {
+        return bad_page();
+    };
+    if !page.whole() {
+        return list_page(eid, None, &dynamo, order, filter, page, &headers).await;
+    }
+    let listing = list_inner(Path((eid, None)), State(dynamo), order, headers).await;
+    filtered(filter, listing)
 }
 
 pub(super) async fn list_all(
     Path((eid, secret)): Path<(Uuid, String)>,
     State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<serde_json::Value>, StatusCode>,
-) {
-    list_inner(Path((eid, Some(secret))), State(dynamo)).await
+    Query(Order {
+        order,
+        filter,
+        cursor,
+        limit,
+    }): Query<Order>,
+    headers: HeaderMap,
+) -> Listing {
+    let Some(page) = Page::new(&eid, cursor.as_deref(), limit) else {
+        return bad_page();
+    };
+    if !page.whole() {
+        return list_page(eid, Some(secret), &dynamo, order, filter, page, &headers).await;
+    }
+    // hosts aren't part of any experiment, but can pick whichever order they like
+    let listing = list_inner(Path((eid, Some(secret))), State(dynamo), order, headers).await;
+    filtered(filter, listing)
 }
 
-async fn list_inner(
-    Path((eid, secret)): Path<(Uuid, Option<String>)>,
-    State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<serde_json::Value>, StatusCode>,
-) {
-    let has_secret = if let Some(secret) = secret {
-        debug!("list questions with admin access");
-        if let Err(e) = super::check_secret(&dynamo, &eid, &secret).await {
-            // a bad secret will not turn good and
-            // events are unlikely to re-appear with the same uuid
+/// The headers of `eid`'s list at `version`, for hosts if `host`, which say which version it is,
+/// what order it's in, when to come back, and what the host has announced, if anything (and hosts
+/// how many are following along).
+fn tagged(
+    eid: &Uuid,
+    version: u64,
+    host: bool,
+    announcement: Option<Announcement>,
+    arranged: Arrangement,
+) -> Option<AppendHeaders<Vec<(HeaderName, String)>>> {
+    let poll_after = super::poll::hint(eid, host).as_millis().to_string();
+    let mut headers = vec![
+        (header::ETAG, etag(version)),
+        (super::poll::POLL_AFTER, poll_after),
+    ];
+    headers.extend(arranged.headers());
+    headers.extend(announcement.iter().flat_map(Announcement::headers));
+    if host {
+        let attendees = super::presence::count(eid).to_string();
+        headers.push((super::presence::ATTENDEES, attendees));
+    }
+    Some(AppendHeaders(headers))
+}
+
+/// A `page` of `eid`'s list, with the questions that pass `filter`.
+///
+/// Pages are always in the order of the `top` index, by votes, since other orders (and locked
+/// lists) need the whole list to put together, so asking for a page in another order is a bad
+/// request.
+///
+/// Guests' pages of a [hot](super::hot) event come out of memory, like its whole list does, and
+/// only pages of other events (and hosts' pages) are read from the database. That costs a read of
+/// the event and a query for each page, which is cheaper than reading the whole list for one page
+/// of a long one, but adds up quickly if the audience of a live event pages through it on every
+/// poll without this instance keeping it hot. Cursors from either source work for the other,
+/// though questions with the same number of votes may not be in quite the same order in both.
+async fn list_page(
+    eid: Uuid,
+    secret: Option<String>,
+    dynamo: &Backend,
+    order: Option<Ordering>,
+    filter: Filter,
+    page: Page,
+    headers: &HeaderMap,
+) -> Listing {
+    if order.is_some_and(|o| o != Ordering::Top) {
+        return bad_page();
+    }
+    let has_secret = secret.is_some();
+    if !has_secret {
+        super::hot::recheck(dynamo, &eid).await;
+        if let Some(listing) = hot_page(&eid, dynamo, filter, &page, headers) {
+            return listing;
+        }
+    }
+    let meta = match secret {
+        Some(secret) => super::check_secret(dynamo, &eid, &secret).await,
+        None => super::get_meta(dynamo, &eid).await,
+    };
+    let meta = match meta {
+        Ok(meta) => meta,
+        Err(StatusCode::INTERNAL_SERVER_ERROR) => {
+            return (
+                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                None,
+                Err(StatusCode::INTERNAL_SERVER_ERROR),
+            );
+        }
+        // bad secrets don't turn good, and events are unlikely to re-appear with the same uuid
+        Err(e) => {
             return (
                 AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
+                None,
                 Err(e),
             );
         }
-        true
+    };
+    if meta.frozen() {
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=60")]),
+            None,
+            Err(StatusCode::FORBIDDEN),
+        );
+    }
+    let version = meta.version;
+    let max_age = if has_secret {
+        "max-age=3"
+    } else {
+        "max-age=10"
+    };
+    let arranged = Arrangement {
+        order: Ordering::Top,
+        locked: false,
+    };
+    let mut tags = tagged(&eid, version, has_secret, meta.showing(), arranged);
+    if fresh(headers, version) {
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, max_age)]),
+            tags,
+            Err(StatusCode::NOT_MODIFIED),
+        );
+    }
+
+    let limit = page.limit.map(|n| n as i32);
+    let qs = match dynamo.list_page(&eid, has_secret, page.start, limit).await {
+        Ok(qs) => qs,
+        Err(e) => {
+            error!(%eid, error = %e, "dynamodb request for page of question list failed");
+            return (
+                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                None,
+                Err(StatusCode::INTERNAL_SERVER_ERROR),
+            );
+        }
+    };
+    if let (Some(next), Some(AppendHeaders(tags))) = (qs.last_evaluated_key(), &mut tags) {
+        tags.push((NEXT_CURSOR, cursor(next)));
+    }
+    // the database counts questions that are filtered out towards the limit, so pages can come up
+    // short, but there's a next page for as long as there's a cursor
+    let questions: Vec<_> = qs
+        .items()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|doc| parse(&eid, doc))
+        .filter(|(_, q)| has_secret || !q.hidden)
+        .map(|(qid, q)| {
+            let mut json = q.to_json(&qid);
+            if has_secret {
+                json["voters"] = q.voters.into();
+            }
+            json
+        })
+        .filter(|q| filter.keeps(q))
+        .collect();
+    (
+        AppendHeaders([(header::CACHE_CONTROL, max_age)]),
+        tags,
+        Ok(Json(serde_json::Value::from(questions))),
+    )
+}
+
+/// A guest's `page` of `eid`'s list out of the event's hot copy, if it has one, and the page
+/// starts at a question that's (still) in it.
+///
+/// Next cursors are what the database would have given for the same page, so that instances that
+/// don't keep the event hot can pick up where this one left off.
+fn hot_page(
+    eid: &Uuid,
+    dynamo: &Backend,
+    filter: Filter,
+    page: &Page,
+    headers: &HeaderMap,
+) -> Option<Listing> {
+    let (version, announcement, questions) = super::hot::top(eid)?;
+    let start = match &page.start {
+        None => 0,
+        Some(key) => match (key.get("id"), key.get("offset")) {
+            (Some(AttributeValue::S(after)), _) => {
+                questions
+                    .iter()
+                    .position(|(qid, _)| qid.to_string() == *after)?
+                    + 1
+            }
+            (_, Some(AttributeValue::N(offset))) => offset.parse().ok()?,
+            _ => return None,
+        },
+    };
+    let arranged = Arrangement {
+        order: Ordering::Top,
+        locked: false,
+    };
+    let mut tags = tagged(eid, version, false, announcement, arranged);
+    if fresh(headers, version) {
+        return Some((
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=10")]),
+            tags,
+            Err(StatusCode::NOT_MODIFIED),
+        ));
+    }
+
+    let end = page.limit.map_or(questions.len(), |n| {
+        start.saturating_add(n).min(questions.len())
+    });
+    let start = start.min(end);
+    let last = questions[..end].last().filter(|_| end < questions.len());
+    if let (Some((qid, q)), Some(AppendHeaders(tags))) = (last, &mut tags) {
+        let next = match dynamo {
+            Backend::Dynamo(_) => HashMap::from([
+                (String::from("id"), AttributeValue::S(qid.to_string())),
+                (String::from("eid"), AttributeValue::S(eid.to_string())),
+                (
+                    String::from("votes"),
+                    AttributeValue::N(q.votes.to_string()),
+                ),
+            ]),
+            _ => HashMap::from([(String::from("offset"), AttributeValue::N(end.to_string()))]),
+        };
+        tags.push((NEXT_CURSOR, cursor(&next)));
+    }
+    let questions: Vec<_> = questions[start..end]
+        .iter()
+        .map(|(qid, q)| q.to_json(qid))
+        .filter(|q| filter.keeps(q))
+        .collect();
+    Some((
+        AppendHeaders([(header::CACHE_CONTROL, "max-age=10")]),
+        tags,
+        Ok(Json(serde_json::Value::from(questions))),
+    ))
+}
+
+async fn list_inner(
+    Path((eid, secret)): Path<(Uuid, Option<String>)>,
+    State(dynamo): State<Backend>,
+    order: Option<Ordering>,
+    headers: HeaderMap,
+) -> Listing {
+    // lists of events that exist say which version they are, and when to come back
+    let tagged =
+        |version, host, announcement, arranged| tagged(&eid, version, host, announcement, arranged);
+    // when the database is having trouble, guests are better off with a slightly old list
+    let stale = || {
+        let (version, announcement, arranged, questions) = super::hot::stale(&eid, order)?;
+        let mut tags = tagged(version, false, announcement, arranged);
+        if let Some(AppendHeaders(tags)) = &mut tags {
+            tags.push((super::hot::STALE, String::from("true")));
+        }
+        let questions = if fresh(&headers, version) {
+            Err(StatusCode::NOT_MODIFIED)
+        } else {
+            Ok(Json(serde_json::Value::from(questions)))
+        };
+        Some((
+            AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+            tags,
+            questions,
+        ))
+    };
+    let (has_secret, meta) = if let Some(secret) = secret {
+        debug!("list questions with admin access");
+        match super::check_secret(&dynamo, &eid, &secret).await {
+            Ok(meta) => (true, meta),
+            Err(e) => {
+                // a bad secret will not turn good and
+                // events are unlikely to re-appear with the same uuid
+                return (
+                    AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
+                    None,
+                    Err(e),
+                );
+            }
+        }
     } else {
         trace!("list questions with guest access");
-        // ensure that the event exists:
-        // this is _just_ so give 404s for old events so clients stop polling
-        if let Err(e) = super::get_secret(&dynamo, &eid).await {
-            // events are unlikely to re-appear with the same uuid
+        // live events are served from memory, and only exist if the event does
+        super::hot::recheck(&dynamo, &eid).await;
+        if let Some((version, announcement, arranged, questions)) = super::hot::get(&eid, order) {
+            let questions = if fresh(&headers, version) {
+                Err(StatusCode::NOT_MODIFIED)
+            } else {
+                Ok(Json(serde_json::Value::from(questions)))
+            };
             return (
-                AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
-                Err(e),
+                AppendHeaders([(header::CACHE_CONTROL, "max-age=10")]),
+                tagged(version, false, announcement, arranged),
+                questions,
             );
         }
-        false
+        // ensure that the event exists:
+        // this is _just_ so give 404s for old events so clients stop polling
+        match super::get_meta(&dynamo, &eid).await {
+            Ok(meta) => (false, meta),
+            Err(StatusCode::INTERNAL_SERVER_ERROR) => {
+                return stale().unwrap_or((
+                    AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                    None,
+                    Err(StatusCode::INTERNAL_SERVER_ERROR),
+                ));
+            }
+            Err(e) => {
+                // events are unlikely to re-appear with the same uuid
+                return (
+                    AppendHeaders([(header::CACHE_CONTROL, "max-age=86400")]),
+                    None,
+                    Err(e),
+                );
+            }
+        }
+    };
+
+    if meta.frozen() {
+        // the event may well be unfrozen after review
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=60")]),
+            None,
+            Err(StatusCode::FORBIDDEN),
+        );
+    }
+
+    let announcement = meta.showing();
+    let locked = super::lock::snapshot(&meta);
+    let picked = super::order::picked(&meta.moderation);
+    let order = order.unwrap_or(picked);
+    // hosts always see the list as it stands
+    let arranged = Arrangement {
+        order,
+        locked: locked.is_some() && !has_secret,
     };
+    let super::Meta {
+        version,
+        announcement: posted,
+        scheduled,
+        ..
+    } = meta;
+    super::poll::observe(&eid, version);
 
-    match dynamo.list(&eid, has_secret).await {
-        Ok(qs) => {
+    let max_age = if has_secret {
+        // hosts should be allowed to see more up-to-date views
+        "max-age=3"
+    } else {
+        // guests don't need super up-to-date, so cache for longer
+        "max-age=10"
+    };
+    // nothing has changed since the client last looked, so there's no need to query
+    if fresh(&headers, version) {
+        trace!(%eid, version, "question list not modified");
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, max_age)]),
+            tagged(version, has_secret, announcement, arranged),
+            Err(StatusCode::NOT_MODIFIED),
+        );
+    }
+
+    // another instance may well have listed this very version already
+    let shared = super::shared::get(&eid, version).await;
+    let listed = match shared {
+        Some(questions) => Ok((questions, true)),
+        // always fetch hidden questions too so that the whole list can be kept in memory
+        None => dynamo.list(&eid, true).await.map(|qs| {
             trace!(%eid, n = %qs.count(), "listed questions");
             let questions: Vec<_> = qs
                 .items()
-                .map(|qs| {
-                    qs.iter()
-                        .filter_map(|doc| {
-                            let qid = doc["id"].as_s().ok();
-                            let votes = doc["votes"]
-                                .as_n()
-                                .ok()
-                                .and_then(|v| v.parse::<usize>().ok());
-                            let hidden = doc["hidden"]
-                                .as_bool()
-                                .ok();
-                            let answered = doc["answered"]
-                                .as_bool()
-                                .ok();
-                            match (qid, votes, hidden, answered) {
-                                (Some(qid), Some(votes), Some(hidden), Some(answered)) => Some(serde_json::json!({
-                                    "qid": qid,
-                                    "votes": votes,
-                                    "hidden": hidden,
-                                    "answered": answered
-                                })),
-                                (Some(qid), _, _, _) => {
-                                    error!(%eid, %qid, votes = ?doc.get("votes"), "found non-numeric vote count");
-                                    None
-                                },
-                                _ => {
-                                    error!(%eid, ?doc, "found non-string question id");
-                                    None
-                                }
-                            }
-                        })
-                        .collect()
-                })
+                .map(|qs| qs.iter().filter_map(|doc| parse(&eid, doc)).collect())
                 .unwrap_or_default();
+            (questions, false)
+        }),
+    };
+    match listed {
+        Ok((questions, shared)) => {
+            if !shared {
+                super::shared::put(&eid, version, &questions).await;
+            }
+            super::hot::load(
+                &eid,
+                version,
+                posted,
+                scheduled,
+                locked.clone(),
+                picked,
+                questions.iter().copied(),
+            );
+            let mut questions: Vec<_> = questions
+                .into_iter()
+                .filter(|(_, q)| has_secret || !q.hidden)
+                .collect();
+            // the index already has them by votes
+            if order != Ordering::Top {
+                let now = super::clock::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                order.sort(&mut questions, now);
+            }
+            if let Some(locked) = locked.filter(|_| arranged.locked) {
+                super::lock::arrange(&mut questions, &locked);
+            }
+            let questions: Vec<_> = questions
+                .into_iter()
+                .map(|(qid, q)| {
+                    let mut json = q.to_json(&qid);
+                    if has_secret {
+                        json["voters"] = q.voters.into();
+                    }
+                    json
+                })
+                .collect();
 
-            let max_age = if has_secret {
-                // hosts should be allowed to see more up-to-date views
-                "max-age=3"
-            } else {
-                // guests don't need super up-to-date, so cache for longer
-                "max-age=10"
-            };
             (
                 AppendHeaders([(header::CACHE_CONTROL, max_age)]),
+                tagged(version, has_secret, announcement, arranged),
                 Ok(Json(serde_json::Value::from(questions))),
             )
         }
@@ -216,13 +888,18 @@ async fn list_inner(
                         // it's relatively unlikely that an event uuid that didn't exist will start
                         // existing. but just in case, don't make it _too_ long.
                         AppendHeaders([(header::CACHE_CONTROL, "max-age=3600")]),
+                        None,
                         Err(http::StatusCode::NOT_FOUND),
                     );
                 }
             }
             error!(%eid, error = %e, "dynamodb request for question list failed");
+            if let Some(stale) = stale().filter(|_| !has_secret) {
+                return stale;
+            }
             (
                 AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                None,
                 Err(http::StatusCode::INTERNAL_SERVER_ERROR),
             )
         }
@@ -232,14 +909,69 @@ async fn list_inner(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn pages() {
+        let eid = Uuid::new_v4();
+        let last = HashMap::from([
+            (
+                String::from("id"),
+                AttributeValue::S(Uuid::new_v4().to_string()),
+            ),
+            (String::from("eid"), AttributeValue::S(eid.to_string())),
+            (String::from("votes"), AttributeValue::N(String::from("7"))),
+        ]);
+        let next = cursor(&last);
+        assert_eq!(
+            Page::new(&eid, Some(&next), Some(2)),
+            Some(Page {
+                start: Some(last),
+                limit: Some(2)
+            })
+        );
+        assert!(Page::new(&eid, None, None).unwrap().whole());
+        // cursors are only good for the list they came from
+        assert_eq!(Page::new(&Uuid::new_v4(), Some(&next), None), None);
+        assert_eq!(Page::new(&eid, Some("nope"), None), None);
+        let offset =
+            HashMap::from([(String::from("offset"), AttributeValue::N(String::from("2")))]);
+        assert_eq!(key(&eid, &cursor(&offset)), Some(offset));
+        assert_eq!(Page::new(&eid, None, Some(0)), None);
+        assert_eq!(
+            Page::new(&eid, None, Some(usize::MAX)).unwrap().limit,
+            Some(MAX_LIMIT)
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let items: Vec<_> = (0..5)
+            .map(|i| HashMap::from([(String::from("id"), AttributeValue::N(i.to_string()))]))
+            .collect();
+        let all = || {
+            QueryOutput::builder()
+                .set_count(Some(items.len() as i32))
+                .set_items(Some(items.clone()))
+                .build()
+        };
+        let page = by_offset(all(), None, Some(3));
+        assert_eq!(page.items().unwrap(), &items[..3]);
+        let next = page.last_evaluated_key().cloned().unwrap();
+        let page = by_offset(all(), Some(&next), Some(3));
+        assert_eq!(page.items().unwrap(), &items[3..]);
+        assert_eq!(page.last_evaluated_key(), None);
+        assert_eq!(by_offset(all(), None, None).items().unwrap(), &items[..]);
+    }
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
         let secret = e["secret"].as_str().unwrap();
         let q = crate::ask::ask(
-            Path(eid.clone()),
+            Path(eid),
             State(backend.clone()),
+            http::HeaderMap::new(),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
@@ -265,30 +997,127 @@ mod tests {
 
         check(
             super::list_all(
-                Path((eid.clone(), secret.to_string())),
+                Path((eid, secret.to_string())),
                 State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
             )
             .await
-            .1
+            .2
             .unwrap()
             .0,
         );
         check(
-            super::list(Path(eid.clone()), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            super::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
         );
 
+        // other orderings list the same questions, whether the event is hot or not
+        let trending = || {
+            super::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(Order {
+                    order: Some(Ordering::Trending),
+                    ..Default::default()
+                }),
+                HeaderMap::new(),
+            )
+        };
+        check(trending().await.2.unwrap().0);
+        crate::hot::forget(&eid);
+        check(trending().await.2.unwrap().0);
+        // which needs to know when questions were asked
+        let qs = backend.list(&eid, false).await.unwrap();
+        let (_, q) = parse(&eid, &qs.items().unwrap()[0]).unwrap();
+        assert_ne!(q.asked, 0);
+
+        // clients that already have the current version get a 304, whether it's hot or not
+        let if_none_match =
+            |etag: &str| HeaderMap::from_iter([(header::IF_NONE_MATCH, etag.parse().unwrap())]);
+        let (_, etag, _) = super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        let AppendHeaders(tags) = etag.unwrap();
+        let [(_, etag), (poll, poll_after), (order, top)] = <[_; 3]>::try_from(tags).unwrap();
+        assert_eq!(poll, crate::poll::POLL_AFTER);
+        assert_eq!((order, top.as_str()), (ORDER, "top"));
+        assert!(poll_after.parse::<u64>().unwrap() >= 1000);
+        let (_, _, res) = super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            if_none_match(&etag),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_MODIFIED);
+        crate::hot::forget(&eid);
+        let (_, _, res) = super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            if_none_match(&etag),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_MODIFIED);
+        let (_, _, res) = super::list_all(
+            Path((eid, secret.to_string())),
+            State(backend.clone()),
+            Query(Default::default()),
+            if_none_match(&etag),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_MODIFIED);
+
+        // but not once something has changed
+        crate::vote::vote(
+            Path((Uuid::parse_str(qid).unwrap(), crate::vote::UpDown::Up)),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
+        let (_, new, res) = super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            if_none_match(&etag),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert_ne!(new.unwrap().0[0].1, etag);
+        let (_, _, res) = super::list_all(
+            Path((eid, secret.to_string())),
+            State(backend.clone()),
+            Query(Default::default()),
+            if_none_match(&etag),
+        )
+        .await;
+        assert!(res.is_ok());
+
         // lookup with wrong secret gives 401
         assert_eq!(
             super::list_all(
-                Path((eid.clone(), "wrong".to_string())),
+                Path((eid, "wrong".to_string())),
                 State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new()
             )
             .await
-            .1
+            .2
             .unwrap_err(),
             StatusCode::UNAUTHORIZED
         );
@@ -301,22 +1130,198 @@ mod tests {
                     secret.to_string()
                 )),
                 State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new()
             )
             .await
-            .1
+            .2
             .unwrap_err(),
             StatusCode::NOT_FOUND
         );
 
+        // long lists come in pages, both from the database and to guests
+        let other = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "and another thing".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let page = backend.list_page(&eid, true, None, Some(1)).await.unwrap();
+        assert_eq!(page.items().unwrap().len(), 1);
+        let start = page.last_evaluated_key().cloned();
+        assert!(start.is_some());
+        let rest = backend.list_page(&eid, true, start, None).await.unwrap();
+        assert_eq!(rest.items().unwrap().len(), 1);
+        assert_ne!(
+            page.items().unwrap()[0]["id"],
+            rest.items().unwrap()[0]["id"]
+        );
+
+        let paged = |cursor: Option<String>| {
+            super::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(Order {
+                    cursor,
+                    limit: Some(1),
+                    ..Default::default()
+                }),
+                HeaderMap::new(),
+            )
+        };
+        let (_, tags, res) = paged(None).await;
+        assert_eq!(res.unwrap().0.as_array().unwrap().len(), 1);
+        let AppendHeaders(tags) = tags.unwrap();
+        let (_, next) = tags.iter().find(|(h, _)| h == NEXT_CURSOR).unwrap();
+        let (_, tags, res) = paged(Some(next.clone())).await;
+        assert_eq!(res.unwrap().0[0]["qid"], other["id"]);
+        assert!(!tags.unwrap().0.iter().any(|(h, _)| h == NEXT_CURSOR));
+        let (_, _, res) = paged(Some(String::from("next"))).await;
+        assert_eq!(res.unwrap_err(), StatusCode::BAD_REQUEST);
+        // live events are paged out of memory, and cursors work whether the event is hot or not
+        let next = |tags: Option<AppendHeaders<Vec<(HeaderName, String)>>>| {
+            let (_, next) = tags
+                .unwrap()
+                .0
+                .into_iter()
+                .find(|(h, _)| h == NEXT_CURSOR)?;
+            Some(next)
+        };
+        crate::hot::forget(&eid);
+        let (_, tags, cold) = paged(None).await;
+        let cold = cold.unwrap().0;
+        let from_cold = next(tags).unwrap();
+        super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap();
+        assert!(crate::hot::contains(&eid));
+        let (_, tags, hot) = paged(None).await;
+        assert_eq!(hot.unwrap().0, cold);
+        let from_hot = next(tags).unwrap();
+        assert_eq!(from_hot, from_cold);
+        let (_, tags, res) = paged(Some(from_cold)).await;
+        assert_eq!(res.unwrap().0[0]["qid"], other["id"]);
+        assert_eq!(next(tags), None);
+        crate::hot::forget(&eid);
+        let (_, _, res) = paged(Some(from_hot)).await;
+        assert_eq!(res.unwrap().0[0]["qid"], other["id"]);
+        // pages are by votes, since other orders need the whole list
+        let newest: Order =
+            serde_json::from_value(serde_json::json!({"sort": "new", "limit": 1})).unwrap();
+        let (_, _, res) = super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(newest),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        // hosts can see the newest first
+        let newest: Order = serde_json::from_value(serde_json::json!({"sort": "new"})).unwrap();
+        let (_, _, res) = super::list_all(
+            Path((eid, secret.to_string())),
+            State(backend.clone()),
+            Query(newest),
+            HeaderMap::new(),
+        )
+        .await;
+        let qs = backend.list(&eid, true).await.unwrap();
+        let mut expected: Vec<_> = qs
+            .items()
+            .unwrap()
+            .iter()
+            .filter_map(|doc| parse(&eid, doc))
+            .collect();
+        Ordering::New.sort(&mut expected, 0);
+        let listed: Vec<_> = res
+            .unwrap()
+            .0
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|q| q["qid"].as_str().unwrap().to_string())
+            .collect();
+        let expected: Vec<_> = expected.iter().map(|(qid, _)| qid.to_string()).collect();
+        assert_eq!(listed, expected);
+
+        // and just some of the questions
+        let other_qid = Uuid::parse_str(other["id"].as_str().unwrap()).unwrap();
+        crate::toggle::apply(
+            &backend,
+            &eid,
+            other_qid,
+            crate::toggle::Property::Hidden,
+            true,
+            None,
+        )
+        .await
+        .unwrap();
+        let filtered = |secret: Option<&str>, filter: &str| {
+            let order: Order =
+                serde_json::from_value(serde_json::json!({ "filter": filter })).unwrap();
+            let backend = backend.clone();
+            let secret = secret.map(String::from);
+            async move {
+                let (_, _, res) = match secret {
+                    Some(secret) => {
+                        super::list_all(
+                            Path((eid, secret)),
+                            State(backend),
+                            Query(order),
+                            HeaderMap::new(),
+                        )
+                        .await
+                    }
+                    None => {
+                        super::list(Path(eid), State(backend), Query(order), HeaderMap::new()).await
+                    }
+                };
+                res.unwrap()
+                    .0
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|q| q["qid"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            filtered(Some(secret), "hidden").await,
+            [other_qid.to_string()]
+        );
+        assert_eq!(filtered(Some(secret), "visible").await, [qid.to_string()]);
+        assert_eq!(filtered(Some(secret), "all").await.len(), 2);
+        assert_eq!(filtered(Some(secret), "answered").await.len(), 0);
+        assert_eq!(filtered(None, "unanswered").await, [qid.to_string()]);
+        assert!(filtered(None, "hidden").await.is_empty());
+        assert!(serde_json::from_value::<Order>(serde_json::json!({"filter": "mine"})).is_err());
+
         backend.delete(&eid).await;
 
         // lookup for empty but existing event gives 200
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
-        super::list(Path(eid), State(backend.clone()))
-            .await
-            .1
-            .unwrap();
+        super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap();
         backend.delete(&eid).await;
 
         // lookup for non-existing event without secret gives 404
@@ -324,9 +1329,11 @@ mod tests {
             super::list(
                 Path(Uuid::parse_str("00000000-0000-0000-0000-000000000001").unwrap()),
                 State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new()
             )
             .await
-            .1
+            .2
             .unwrap_err(),
             StatusCode::NOT_FOUND
         );
@@ -342,4 +1349,140 @@ mod tests {
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[tokio::test]
+    async fn stale() {
+        let backend = crate::golden::unreachable();
+        let eid = Uuid::new_v4();
+        let qid = Uuid::new_v4();
+        let question = Question {
+            votes: 2,
+            hidden: false,
+            answered: false,
+            voters: 0,
+            asked: 0,
+            merged_into: None,
+            asker: None,
+            reactions: Default::default(),
+        };
+        crate::hot::load(
+            &eid,
+            4,
+            None,
+            Vec::new(),
+            None,
+            Ordering::Top,
+            [(qid, question)],
+        );
+        crate::hot::age(&eid, Duration::from_secs(10));
+
+        // too old to be served normally, but better than an error
+        let hits = crate::metrics::get("stale.hit");
+        let (cache, tags, res) = super::list(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(cache.0[0].1, "no-cache");
+        let tags = tags.unwrap().0;
+        assert!(tags.contains(&(crate::hot::STALE, String::from("true"))));
+        assert!(tags.contains(&(header::ETAG, etag(4))));
+        assert_eq!(res.unwrap().0, serde_json::json!([question.to_json(&qid)]));
+        assert!(crate::metrics::get("stale.hit") > hits);
+
+        // hosts can't be told apart from guests without the database
+        let (_, _, res) = super::list_all(
+            Path((eid, String::from("secret"))),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // and a list that's too out of date is no better than an error
+        crate::hot::age(&eid, Duration::from_secs(30));
+        let (cache, tags, res) = super::list(
+            Path(eid),
+            State(backend),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(cache.0[0].1, "no-cache");
+        assert!(tags.is_none());
+        assert_eq!(res.unwrap_err(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn golden() {
+        let eid = Uuid::parse_str("9e7c5a31-4f2d-4b8e-a6c0-3d1f9b7e5c42").unwrap();
+        let (backend, replay) = crate::golden::replay("list");
+        replay.check(
+            super::list(
+                Path(eid),
+                State(backend),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
+        );
+
+        let eid = Uuid::parse_str("2f4a6c8e-0b1d-4e3f-8a5c-7e9b1d3f5a64").unwrap();
+        let secret = String::from("wQ3vXh8kPz2LmN9aRt5Y");
+        let (backend, replay) = crate::golden::replay("list_all");
+        replay.check(
+            super::list_all(
+                Path((eid, secret)),
+                State(backend),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
+        );
+
+        // an unchanged list isn't queried at all
+        let eid = Uuid::parse_str("6b8d0f2a-4c6e-4f8a-9b1d-3e5f7a9c1e86").unwrap();
+        let (backend, replay) = crate::golden::replay("list_unchanged");
+        let headers = HeaderMap::from_iter([(header::IF_NONE_MATCH, "W/\"57\"".parse().unwrap())]);
+        let (_, etag, res) = super::list(
+            Path(eid),
+            State(backend),
+            Query(Default::default()),
+            headers,
+        )
+        .await;
+        replay.check(serde_json::json!({
+            "status": res.unwrap_err().as_u16(),
+            "etag": etag.unwrap().0[0].1,
+        }));
+    }
 }
@@ -0,0 +1,317 @@
+//! Keeping the question list still while the host answers from it.
+//!
+//! Once the presenter starts answering, questions jumping around as the audience keeps voting make
+//! it hard to follow along. So the host can lock the order of the list with
+//! `POST /api/event/:eid/lock/:secret` and `{"locked": true}`, and let it move again with
+//! `{"locked": false}`. Locking takes a snapshot of the visible questions by votes (up to
+//! `MAX_LOCKED` of them), and keeps it on the event in an attribute of its own that the next lock
+//! overwrites. That it was locked (and by how many questions) goes in the event's
+//! [moderation log](super::moderation), where the change is on record, and which is written along
+//! with the snapshot. Hosts listening with the secret are told with `{"kind": "locked",
+//! "locked": ..}`.
+//!
+//! While the list is locked, votes are still taken and counted, and attendees still see the
+//! counts change, but the questions in the snapshot stay where they were. Questions asked (or
+//! unhidden) since come after them, in the usual order. Hosts always see the list as it stands.
+//!
+//! Locking doesn't change the event's version, so attendees who already have the list at that
+//! version keep the order they have until the next vote or question comes in. Other instances
+//! that have the event [hot](super::hot) keep serving the order they had for up to
+//! `HOT_REFRESH_MS`.
+
+use super::{
+    experiment::Ordering,
+    hot::Question,
+    moderation::{self, Action, Kind, Role},
+    Backend,
+};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use std::{collections::HashMap, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Lock the order of at most this many questions.
+const MAX_LOCKED: usize = 200;
+
+/// The order the event's question list is locked in, if it is.
+pub(super) fn snapshot(meta: &super::Meta) -> Option<Vec<Uuid>> {
+    let action = moderation::locked(&meta.moderation)?;
+    // lists locked before the order had an attribute of its own kept it in the note
+    let order = meta.locked.as_deref().unwrap_or(&action.note);
+    Some(
+        order
+            .split(',')
+            .filter(|qid| !qid.is_empty())
+            .filter_map(|qid| {
+                Uuid::parse_str(qid)
+                    .map_err(|_| error!(qid, "found malformed question in locked order"))
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Put the questions in `locked` first, in that order, and leave the rest in the order they're in.
+pub(super) fn arrange(questions: &mut [(Uuid, Question)], locked: &[Uuid]) {
+    let at: HashMap<_, _> = locked.iter().enumerate().map(|(i, qid)| (qid, i)).collect();
+    // the sort is stable, so everything that's not locked stays in order
+    questions.sort_by_key(|(qid, _)| at.get(qid).copied().unwrap_or(usize::MAX));
+}
+
+/// Whether the host wants the order of the question list locked.
+#[derive(Debug, Deserialize)]
+pub(super) struct Lock {
+    locked: bool,
+}
+
+/// Lock the order of the question list, or let it move again, for the event's host.
+pub(super) async fn lock(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Lock>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    if moderation::locked(&meta.moderation).is_some() == req.locked {
+        return Ok(Json(moderation::status(&meta.moderation)));
+    }
+
+    let (kind, note, order) = if req.locked {
+        let qs = dynamo.list(&eid, false).await.map_err(|e| {
+            error!(%eid, error = %e, "dynamodb request for question list to lock failed");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let mut questions: Vec<_> = qs
+            .items()
+            .map(|qs| {
+                qs.iter()
+                    .filter_map(|doc| super::list::parse(&eid, doc))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ordering::Top.sort(&mut questions, 0);
+        let order: Vec<_> = questions
+            .iter()
+            .take(MAX_LOCKED)
+            .map(|(qid, _)| qid.to_string())
+            .collect();
+        let note = format!("{} questions", order.len());
+        (Kind::Locked, note, Some(order.join(",")))
+    } else {
+        (Kind::Unlocked, String::new(), None)
+    };
+    let order = order.as_deref();
+    let (log, changed) = moderation::amend_locking(&dynamo, &eid, meta, "lock", order, |log| {
+        if moderation::locked(log).is_some() == req.locked {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: super::clock::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            kind,
+            note: note.clone(),
+            by: Some(Role::Host),
+        });
+        Ok(true)
+    })
+    .await?;
+    if !changed {
+        return Ok(Json(moderation::status(&log)));
+    }
+    super::hot::forget(&eid);
+    info!(%eid, ?kind, "host changed question list lock");
+    super::bus::tell_hosts(
+        &eid,
+        serde_json::json!({ "kind": "locked", "locked": req.locked }),
+    );
+    Ok(Json(moderation::status(&log)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vote::UpDown;
+    use axum::extract::Query;
+    use http::HeaderMap;
+
+    #[test]
+    fn arranges() {
+        let q = |votes| Question {
+            votes,
+            hidden: false,
+            answered: false,
+            voters: 0,
+            asked: 0,
+            merged_into: None,
+            asker: None,
+            reactions: Default::default(),
+        };
+        let (a, b, c, d) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let mut qs = vec![(c, q(9)), (d, q(5)), (a, q(3)), (b, q(1))];
+        arrange(&mut qs, &[a, b, Uuid::new_v4()]);
+        assert_eq!(
+            qs.iter().map(|(qid, _)| *qid).collect::<Vec<_>>(),
+            [a, b, c, d]
+        );
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let mut qids = Vec::new();
+        for body in ["what about this", "what about that"] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap();
+            qids.push(Uuid::parse_str(q["id"].as_str().unwrap()).unwrap());
+        }
+        let (a, b) = (qids[0], qids[1]);
+        let vote = |qid| {
+            crate::vote::vote(
+                Path((qid, UpDown::Up)),
+                State(backend.clone()),
+                HeaderMap::new(),
+                crate::networks::Client(None),
+            )
+        };
+        let lock = |locked| {
+            super::lock(
+                Path((eid, secret.clone())),
+                State(backend.clone()),
+                Json(Lock { locked }),
+            )
+        };
+        let list = || {
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+        };
+        let order = |qs: serde_json::Value| -> Vec<(String, u64)> {
+            qs.as_array()
+                .unwrap()
+                .iter()
+                .map(|q| {
+                    (
+                        q["qid"].as_str().unwrap().to_string(),
+                        q["votes"].as_u64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        vote(b).await.unwrap();
+        let Json(status) = lock(true).await.unwrap();
+        assert_eq!(status["locked"], true);
+        // the order itself is kept out of the log
+        let meta = crate::get_meta(&backend, &eid).await.unwrap();
+        assert_eq!(
+            moderation::locked(&meta.moderation).unwrap().note,
+            "2 questions"
+        );
+        assert_eq!(snapshot(&meta), Some(vec![b, a]));
+        // votes still count, but don't move anything
+        vote(a).await.unwrap();
+        vote(a).await.unwrap();
+        let (_, tags, listed) = list().await;
+        assert_eq!(
+            order(listed.unwrap().0),
+            [(b.to_string(), 2), (a.to_string(), 3)],
+            "locked list reordered"
+        );
+        // which the list says, so that clients don't sort it by votes themselves
+        let locked = |tags| crate::list::tag(&tags, &crate::list::LOCKED);
+        assert_eq!(locked(tags).as_deref(), Some("true"));
+        // and are served that way from memory too
+        let (_, tags, listed) = list().await;
+        assert_eq!(order(listed.unwrap().0)[0].0, b.to_string());
+        assert_eq!(locked(tags).as_deref(), Some("true"));
+        // hosts see the list as it stands
+        let Json(all) = crate::list::list_all(
+            Path((eid, secret.clone())),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap();
+        assert_eq!(order(all)[0].0, a.to_string());
+
+        let Json(status) = lock(false).await.unwrap();
+        assert_eq!(status["locked"], false);
+        let (_, tags, listed) = list().await;
+        assert_eq!(
+            order(listed.unwrap().0),
+            [(a.to_string(), 3), (b.to_string(), 2)]
+        );
+        assert_eq!(locked(tags), None);
+
+        // locking over and over doesn't make the log grow without end
+        for _ in 0..40 {
+            lock(true).await.unwrap();
+            lock(false).await.unwrap();
+        }
+        let Json(status) = lock(true).await.unwrap();
+        assert!(status["log"].as_array().unwrap().len() <= 64);
+        assert_eq!(
+            snapshot(&crate::get_meta(&backend, &eid).await.unwrap()),
+            Some(vec![a, b])
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
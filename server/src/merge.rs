@@ -0,0 +1,467 @@
+//! Merging questions that ask the same thing.
+//!
+//! Hosts often see the same question asked five different ways. `POST
+//! /api/event/:eid/questions/:secret/:qid/merge/:into` folds the question `qid` into `into`: the
+//! votes `qid` had are added to `into`, and `qid` is hidden as a duplicate of it (which its asker
+//! is told; see [`super::mine`]) with `merged_into` pointing at it. The question list includes
+//! `merged_into` for merged questions, so that clients can send whoever is looking at one to where
+//! it went. `/api/questions` doesn't: texts are cached for good, and a question can be merged long
+//! after its text was first fetched.
+//!
+//! Merges can't be undone, and a question can only be merged once. Questions can't be merged into
+//! one that has itself been merged, so there are never chains to follow.
+
+use super::changes::{Change, BACKOFF, MAX_RETRIES};
+use super::{mine::Reason, Backend, Local};
+use aws_sdk_dynamodb::{
+    error::{ConditionalCheckFailedException, TransactWriteItemsErrorKind, UpdateItemError},
+    model::{AttributeValue, ReturnValue, TransactWriteItem, Update},
+    types::SdkError,
+};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use rand::Rng;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The votes of a question as an update returned it.
+fn votes(attrs: Option<&std::collections::HashMap<String, AttributeValue>>) -> u64 {
+    attrs
+        .and_then(|a| a.get("votes"))
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+        .map_or(0, |v| v.max(0) as u64)
+}
+
+/// The error DynamoDB gives for merging (or merging into) a question that's already been merged.
+fn already_merged(message: &str) -> aws_sdk_dynamodb::Error {
+    aws_sdk_dynamodb::Error::ConditionalCheckFailedException(
+        ConditionalCheckFailedException::builder()
+            .message(message)
+            .build(),
+    )
+}
+
+/// Merge `qid` into `into` without a transaction, and return how many votes that moved.
+///
+/// Like [`super::changes`], this is for alternator, where the hide and the votes can't be written
+/// together. A failure in between leaves `qid` merged without its votes having moved.
+async fn merge_untransacted(
+    dynamo: &aws_sdk_dynamodb::Client,
+    qid: &Uuid,
+    into: &Uuid,
+) -> Result<u64, aws_sdk_dynamodb::Error> {
+    let merged = dynamo
+        .update_item()
+        .table_name("questions")
+        .key("id", AttributeValue::S(qid.to_string()))
+        .update_expression("SET merged_into = :into, #hidden = :yes")
+        .expression_attribute_names("#hidden", "hidden")
+        .expression_attribute_values(":into", AttributeValue::S(into.to_string()))
+        .expression_attribute_values(":yes", AttributeValue::Bool(true))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await?;
+    let moved = votes(merged.attributes());
+    if moved != 0 {
+        dynamo
+            .update_item()
+            .table_name("questions")
+            .key("id", AttributeValue::S(into.to_string()))
+            .update_expression("SET votes = votes + :n")
+            .expression_attribute_values(":n", AttributeValue::N(moved.to_string()))
+            .send()
+            .await?;
+    }
+    Ok(moved)
+}
+
+impl Backend {
+    /// Hide `qid` as merged into `into`, add its votes to `into`, and return how many that was.
+    ///
+    /// DynamoDB (and the in-memory backend) refuse to merge a question that's already been merged,
+    /// or into one that has, with a `ConditionalCheckFailedException`. The other backends rely on
+    /// the check [`merge`] makes first.
+    pub(super) async fn merge(
+        &self,
+        qid: &Uuid,
+        into: &Uuid,
+    ) -> Result<u64, aws_sdk_dynamodb::Error> {
+        let fields = [
+            ("merged_into", AttributeValue::S(into.to_string())),
+            ("hidden", AttributeValue::Bool(true)),
+        ];
+        let moved = match self {
+            Self::Dynamo(dynamo) => {
+                // see Backend::record
+                if super::config::config().alternator {
+                    return merge_untransacted(dynamo, qid, into).await;
+                }
+
+                for retry in 0.. {
+                    // the votes to move have to be known up front to go in both writes, so the
+                    // hide is conditional on nobody having voted for qid in the meantime
+                    let q = dynamo
+                        .get_item()
+                        .table_name("questions")
+                        .key("id", AttributeValue::S(qid.to_string()))
+                        .projection_expression("votes, merged_into")
+                        .consistent_read(true)
+                        .send()
+                        .await?;
+                    let Some(q) = q.item() else {
+                        return Err(aws_sdk_dynamodb::Error::Unhandled(
+                            format!("merging non-existing question {qid}").into(),
+                        ));
+                    };
+                    if q.contains_key("merged_into") {
+                        return Err(already_merged("merging merged question"));
+                    }
+                    let Some(seen) = q.get("votes") else {
+                        unreachable!("no votes for question");
+                    };
+                    let moved = votes(Some(q));
+
+                    let hide = Update::builder()
+                        .table_name("questions")
+                        .key("id", AttributeValue::S(qid.to_string()))
+                        .update_expression("SET merged_into = :into, #hidden = :yes")
+                        .condition_expression("attribute_not_exists(merged_into) AND votes = :seen")
+                        .expression_attribute_names("#hidden", "hidden")
+                        .expression_attribute_values(":into", fields[0].1.clone())
+                        .expression_attribute_values(":yes", AttributeValue::Bool(true))
+                        .expression_attribute_values(":seen", seen.clone());
+                    let add = Update::builder()
+                        .table_name("questions")
+                        .key("id", AttributeValue::S(into.to_string()))
+                        .update_expression("SET votes = votes + :n")
+                        .condition_expression(
+                            "attribute_exists(id) AND attribute_not_exists(merged_into)",
+                        )
+                        .expression_attribute_values(":n", AttributeValue::N(moved.to_string()));
+
+                    // both or neither, so that votes are never lost or counted twice
+                    match dynamo
+                        .transact_write_items()
+                        .transact_items(TransactWriteItem::builder().update(hide.build()).build())
+                        .transact_items(TransactWriteItem::builder().update(add.build()).build())
+                        .send()
+                        .await
+                    {
+                        Ok(_) => return Ok(moved),
+                        Err(SdkError::ServiceError { ref err, .. })
+                            if err.is_transaction_canceled_exception() =>
+                        {
+                            let failed = |i: usize| match &err.kind {
+                                TransactWriteItemsErrorKind::TransactionCanceledException(e) => {
+                                    e.cancellation_reasons()
+                                        .and_then(|r| r.get(i))
+                                        .and_then(|r| r.code())
+                                        == Some("ConditionalCheckFailed")
+                                }
+                                _ => false,
+                            };
+                            if failed(1) {
+                                return Err(already_merged("merging into merged question"));
+                            }
+                            if retry == MAX_RETRIES {
+                                return Err(aws_sdk_dynamodb::Error::Unhandled(
+                                    format!("merging {qid} kept losing races").into(),
+                                ));
+                            }
+                            // someone voted for qid (or merged it), so look again
+                            let wait = rand::thread_rng()
+                                .gen_range(Duration::ZERO..=BACKOFF.saturating_mul(1 << retry));
+                            debug!(%qid, retry, ?wait, "lost race to merge question");
+                            super::metrics::incr("merge.retry");
+                            tokio::time::sleep(wait).await;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                unreachable!("retries until it gives up");
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                if !questions.contains_key(into) {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("merging into unknown question")
+                            .build(),
+                    ))
+                    .into());
+                }
+                if questions[into].contains_key("merged_into") {
+                    return Err(already_merged("merging into merged question"));
+                }
+                let Some(q) = questions.get_mut(qid) else {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("merging unknown question")
+                            .build(),
+                    ))
+                    .into());
+                };
+                if q.contains_key("merged_into") {
+                    return Err(already_merged("merging merged question"));
+                }
+                for (k, v) in fields {
+                    q.insert(k, v);
+                }
+                let moved = q
+                    .get("votes")
+                    .and_then(|v| v.as_n().ok())
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(0);
+                journal.question(qid, q);
+
+                let q = questions.get_mut(into).expect("checked above");
+                if let Some(AttributeValue::N(n)) = q.get_mut("votes") {
+                    let votes = n.parse::<u64>().expect("votes values are numbers");
+                    *n = (votes + moved).to_string();
+                }
+                journal.question(into, q);
+                moved
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let merged = mongo
+                    .update::<UpdateItemError>(
+                        qid,
+                        mongodb::bson::doc! {
+                            "$set": { "merged_into": into.to_string(), "hidden": true }
+                        },
+                    )
+                    .await?;
+                let moved = votes(merged.attributes());
+                mongo
+                    .update::<UpdateItemError>(
+                        into,
+                        mongodb::bson::doc! { "$inc": { "votes": moved as i64 } },
+                    )
+                    .await?;
+                moved
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                redis.set::<UpdateItemError>(qid, &fields).await?;
+                // a vote of nothing changes nothing, but does return the question
+                let merged = redis.vote::<UpdateItemError>(qid, 0).await?;
+                let moved = votes(merged.attributes());
+                redis.vote::<UpdateItemError>(into, moved as i64).await?;
+                moved
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let merged = sled.set::<UpdateItemError>(qid, &fields).await?;
+                let moved = votes(merged.attributes());
+                sled.vote::<UpdateItemError>(into, moved as i64).await?;
+                moved
+            }
+        };
+        Ok(moved)
+    }
+}
+
+pub(super) async fn merge(
+    Path((eid, secret, qid, into)): Path<(Uuid, String, Uuid, Uuid)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    if meta.frozen() {
+        warn!(%eid, "merge in frozen event");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if qid == into {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let found = dynamo.questions(&[qid, into]).await.map_err(|e| {
+        error!(%eid, %qid, %into, error = %e, "dynamodb request for questions to merge failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let found = found
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let find = |id: &Uuid| {
+        found.iter().find(|q| {
+            q.get("id").and_then(|v| v.as_s().ok()) == Some(&id.to_string())
+                && q.get("eid").and_then(|v| v.as_s().ok()) == Some(&eid.to_string())
+        })
+    };
+    let (Some(q), Some(target)) = (find(&qid), find(&into)) else {
+        warn!(%eid, %qid, %into, "merge of questions not in event");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if q.contains_key("merged_into") || target.contains_key("merged_into") {
+        warn!(%eid, %qid, %into, "merge involving merged question");
+        return Err(StatusCode::CONFLICT);
+    }
+
+    // first, so that the asker never sees the question hidden for the wrong reason
+    if let Err(e) = dynamo
+        .give_reason(&qid, Some(Reason::DuplicateOf(into)))
+        .await
+    {
+        error!(%qid, error = %e, "dynamodb request to give reason for merging failed");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match dynamo.merge(&qid, &into).await {
+        Ok(votes) => {
+            debug!(%eid, %qid, %into, votes, "merged question");
+            dynamo
+                .try_record(&eid, Change::QuestionMerged { qid, into, votes })
+                .await;
+            Ok(Json(serde_json::json!({
+                "merged_into": into.to_string(),
+                "votes": votes,
+            })))
+        }
+        Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_)) => {
+            warn!(%eid, %qid, "question was merged concurrently");
+            Err(StatusCode::CONFLICT)
+        }
+        Err(e) => {
+            error!(%eid, %qid, %into, error = %e, "dynamodb request to merge question failed");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vote::UpDown;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let ask = |body: &'static str| {
+            let backend = backend.clone();
+            async move {
+                let q = crate::ask::ask(
+                    Path(eid),
+                    State(backend),
+                    http::HeaderMap::new(),
+                    Json(crate::ask::Question {
+                        body: body.into(),
+                        asker: None,
+                    }),
+                )
+                .await
+                .unwrap();
+                Uuid::parse_str(q["id"].as_str().unwrap()).unwrap()
+            }
+        };
+        let a = ask("what about the bonus freeze?").await;
+        let b = ask("are bonuses frozen this year?").await;
+        for _ in 0..2 {
+            backend.vote(&b, UpDown::Up).await.unwrap();
+        }
+        let merge = |qid: Uuid, into: Uuid| {
+            merge(
+                Path((eid, secret.clone(), qid, into)),
+                State(backend.clone()),
+            )
+        };
+
+        assert_eq!(merge(b, b).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            merge(b, Uuid::new_v4()).await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        let Json(merged) = merge(b, a).await.unwrap();
+        assert_eq!(merged["votes"], 3);
+        assert_eq!(merge(b, a).await.unwrap_err(), StatusCode::CONFLICT);
+        assert_eq!(merge(a, b).await.unwrap_err(), StatusCode::CONFLICT);
+
+        // votes for b don't go anywhere
+        let vote = crate::vote::vote(
+            Path((b, UpDown::Up)),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            crate::networks::Client(None),
+        );
+        assert_eq!(vote.await.unwrap_err(), StatusCode::CONFLICT);
+
+        // the list says where b went, and a has its votes
+        let list = backend.list(&eid, true).await.unwrap();
+        let listed: Vec<_> = list
+            .items()
+            .unwrap()
+            .iter()
+            .filter_map(|doc| crate::list::parse(&eid, doc))
+            .collect();
+        let (_, qa) = listed.iter().find(|(qid, _)| *qid == a).unwrap();
+        let (_, qb) = listed.iter().find(|(qid, _)| *qid == b).unwrap();
+        assert_eq!(qa.votes, 4);
+        assert_eq!(qa.merged_into, None);
+        assert!(qb.hidden);
+        assert_eq!(qb.to_json(&b)["merged_into"], a.to_string());
+
+        // but not its text, which is cached for good
+        let qs = backend.questions(&[b]).await.unwrap();
+        let (_, q) = crate::questions::to_json(&qs.responses().unwrap()["questions"][0]).unwrap();
+        assert!(q.get("merged_into").is_none());
+
+        // and replaying the log comes out the same
+        let replayed = crate::rebuild::replay(backend.all_changes(&eid).await.unwrap());
+        let state = |qid| {
+            replayed
+                .questions
+                .iter()
+                .find(|(q, _)| *q == qid)
+                .unwrap()
+                .1
+        };
+        assert_eq!(state(a).votes, 4);
+        assert!(state(b).hidden);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
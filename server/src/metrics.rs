@@ -0,0 +1,38 @@
+//! Process-wide counters for things operators want to keep an eye on.
+//!
+//! These live in memory, so in Lambda each instance has its own set, and they reset whenever an
+//! instance is recycled. They're meant for spotting trends while debugging, not for billing.
+
+use super::admin::Admin;
+use axum::response::Json;
+use std::{collections::BTreeMap, sync::Mutex};
+
+static COUNTERS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Add `n` to the counter called `name`.
+pub(super) fn add(name: impl Into<String>, n: u64) {
+    *COUNTERS.lock().unwrap().entry(name.into()).or_default() += n;
+}
+
+/// Add one to the counter called `name`.
+pub(super) fn incr(name: impl Into<String>) {
+    add(name, 1);
+}
+
+/// The current value of the counter called `name`.
+#[cfg(test)]
+pub(super) fn get(name: &str) -> u64 {
+    COUNTERS.lock().unwrap().get(name).copied().unwrap_or(0)
+}
+
+pub(super) async fn metrics(_: Admin) -> Json<serde_json::Value> {
+    let counters = COUNTERS.lock().unwrap().clone();
+    let (events, questions) = super::hot::size();
+    let (streams, streamed) = super::sse::open();
+    Json(serde_json::json!({
+        "counters": counters,
+        "hot": { "events": events, "questions": questions },
+        "streams": { "open": streams, "events": streamed },
+        "rejections": super::rejections::top(),
+    }))
+}
@@ -0,0 +1,498 @@
+//! Telling askers why their questions were hidden.
+//!
+//! A question the host hides just disappears from the list, which leaves whoever asked it guessing
+//! (and asking the host some other way). So hosts can say why when they hide a question, with
+//! `?reason=<code>` on `POST /api/event/:eid/questions/:secret/:qid/toggle/hidden`. The codes are
+//! `off-topic`, `duplicate-of:<qid>`, `filtered`, and `moderator-removed`, which is also what
+//! hidden questions without a reason report. Revisions waiting for the host (see
+//! [`super::revise`]) are `pending-review`, and questions that waited too long for an answer (see
+//! [`super::aging`]) are `expired`, and questions their askers took back (see [`super::author`]) are
+//! `retracted`. Reasons only matter while questions are hidden, so
+//! unhiding a question leaves its reason be, and hiding it again replaces it.
+//!
+//! Questions don't know who asked them, so asking also returns a `receipt` for the question,
+//! which is derived from its id and the event's secret and which the client keeps along with the
+//! id. `POST /api/event/:eid/mine` with `{"<qid>": "<receipt>", ..}` then says what became of each
+//! of those questions, as `{"<qid>": {"hidden": true, "reason": "off-topic"}}` (with `revised`
+//! naming the revision, if the asker has sent one). Receipts can't be
+//! made without the secret, so only the asker (and the host) can see why a question was hidden.
+//! Questions whose receipts don't check out, that don't exist, or that belong to another event are
+//! left out.
+//!
+//! Clients that would rather not keep track of every question they asked can also make up a random
+//! token for the asker, and send it in `X-Author` when asking. Questions keep an HMAC of it keyed
+//! with the event's secret, like receipts are (so the token can't be told from the question, and the same
+//! token is a different author in every event). `GET /api/event/:eid/mine` with the same
+//! `X-Author` then lists the asker's (latest `MAX_MINE`) questions like above, along with their
+//! `votes`, whether they're `answered`, and their `receipt`. The hash is in the question list index,
+//! so this takes a query of the list, and then a lookup of just the asker's questions.
+
+use super::{Backend, Local};
+#[cfg(any(feature = "mongo", feature = "redis", feature = "sled"))]
+use aws_sdk_dynamodb::error::UpdateItemError;
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use hmac::{Hmac, Mac};
+use http::{header::HeaderName, HeaderMap, StatusCode};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{collections::HashMap, fmt, str::FromStr};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most questions that can be looked up at once.
+const MAX_MINE: usize = 100;
+/// The longest author token that's taken.
+const MAX_AUTHOR: usize = 128;
+
+/// The asker's own token, which their questions are kept under.
+pub(super) const AUTHOR: HeaderName = HeaderName::from_static("x-author");
+
+/// Why a question was hidden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(super) enum Reason {
+    OffTopic,
+    DuplicateOf(Uuid),
+    Filtered,
+    ModeratorRemoved,
+    /// A revision of a hidden question that the host hasn't looked at yet (see [`super::revise`]).
+    PendingReview,
+    /// An unanswered question that got older than the host lets them get.
+    Expired,
+    /// A question its asker took back (see [`super::author`]).
+    Retracted,
+}
+
+impl FromStr for Reason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off-topic" => Ok(Reason::OffTopic),
+            "filtered" => Ok(Reason::Filtered),
+            "moderator-removed" => Ok(Reason::ModeratorRemoved),
+            "pending-review" => Ok(Reason::PendingReview),
+            "expired" => Ok(Reason::Expired),
+            "retracted" => Ok(Reason::Retracted),
+            _ => s
+                .strip_prefix("duplicate-of:")
+                .and_then(|qid| Uuid::parse_str(qid).ok())
+                .map(Reason::DuplicateOf)
+                .ok_or_else(|| format!("unknown reason {s:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Reason {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::OffTopic => write!(f, "off-topic"),
+            Reason::DuplicateOf(qid) => write!(f, "duplicate-of:{qid}"),
+            Reason::Filtered => write!(f, "filtered"),
+            Reason::ModeratorRemoved => write!(f, "moderator-removed"),
+            Reason::PendingReview => write!(f, "pending-review"),
+            Reason::Expired => write!(f, "expired"),
+            Reason::Retracted => write!(f, "retracted"),
+        }
+    }
+}
+
+/// An HMAC of `parts` keyed with the event's `secret`, in hex, so it can't be made without it.
+fn mac(secret: &str, parts: &[&[u8]]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Whether `a` and `b` are the same, taking as long to say so however much of them is.
+pub(super) fn same(a: &str, b: &str) -> bool {
+    bool::from(a.as_bytes().ct_eq(b.as_bytes()))
+}
+
+/// The receipt for the question `qid` of the event whose secret is `secret`.
+pub(super) fn receipt(secret: &str, qid: &Uuid) -> String {
+    mac(secret, &[qid.as_bytes()])
+}
+
+/// Whether `given` is the receipt for the question `qid` of the event whose secret is `secret`.
+pub(super) fn checks_out(secret: &str, qid: &Uuid, given: &str) -> bool {
+    same(given, &receipt(secret, qid))
+}
+
+/// What questions whose asker sent `headers` are kept under, in the event whose secret is `secret`.
+///
+/// Compare it to what questions have with [`same`].
+pub(super) fn author(headers: &HeaderMap, secret: &str) -> Option<String> {
+    let token = headers
+        .get(AUTHOR)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|t| !t.is_empty() && t.len() <= MAX_AUTHOR)?;
+    // tagged, so that no token makes the receipt of some question
+    Some(mac(secret, &[b"author:", token.as_bytes()]))
+}
+
+/// What the asker gets to know about `q`, an item of the event `eid`, if it's one of its questions.
+fn standing(eid: &Uuid, q: &HashMap<String, AttributeValue>) -> Option<serde_json::Value> {
+    let of = q.get("eid").and_then(|v| v.as_s().ok())?;
+    if *of != eid.to_string() {
+        return None;
+    }
+    let hidden = *q.get("hidden").and_then(|v| v.as_bool().ok())?;
+    if !hidden {
+        return Some(serde_json::json!({ "hidden": false }));
+    }
+    // hiding without a reason blanks out the old one rather than removing it
+    let reason = q
+        .get("reason")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(Reason::ModeratorRemoved);
+    let mut standing = serde_json::json!({ "hidden": true, "reason": reason.to_string() });
+    if let Some(revised) = q.get("revised").and_then(|v| v.as_s().ok()) {
+        standing["revised"] = revised.clone().into();
+    }
+    Some(standing)
+}
+
+impl Backend {
+    /// Say why `qid` is hidden, or blank the reason out if there's none.
+    pub(super) async fn give_reason(
+        &self,
+        qid: &Uuid,
+        reason: Option<Reason>,
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        let reason = reason.map(|r| r.to_string()).unwrap_or_default();
+        match self {
+            Self::Dynamo(dynamo) => {
+                dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression("SET #reason = :reason")
+                    .expression_attribute_names("#reason", "reason")
+                    .expression_attribute_values(":reason", AttributeValue::S(reason))
+                    .send()
+                    .await?;
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                if let Some(q) = questions.get_mut(qid) {
+                    q.insert("reason", AttributeValue::S(reason));
+                    journal.question(qid, q);
+                }
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                mongo
+                    .update::<UpdateItemError>(
+                        qid,
+                        mongodb::bson::doc! { "$set": { "reason": reason } },
+                    )
+                    .await?;
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                redis
+                    .set::<UpdateItemError>(qid, &[("reason", AttributeValue::S(reason))])
+                    .await?;
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                sled.set::<UpdateItemError>(qid, &[("reason", AttributeValue::S(reason))])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(super) async fn mine(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    Json(receipts): Json<HashMap<Uuid, String>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if receipts.len() > MAX_MINE {
+        warn!(%eid, n = receipts.len(), "too many questions to look up");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    let qids: Vec<_> = receipts
+        .iter()
+        .filter(|&(qid, r)| checks_out(&meta.secret, qid, r))
+        .map(|(qid, _)| *qid)
+        .collect();
+    if qids.len() != receipts.len() {
+        warn!(%eid, bad = receipts.len() - qids.len(), "ignoring questions with bad receipts");
+    }
+    if qids.is_empty() {
+        return Ok(Json(serde_json::json!({})));
+    }
+
+    let qs = dynamo.questions(&qids).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for asker's questions failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mine: serde_json::Map<_, _> = qs
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .into_iter()
+        .flatten()
+        .filter_map(|q| {
+            let qid = q.get("id").and_then(|v| v.as_s().ok())?;
+            Some((qid.clone(), standing(&eid, q)?))
+        })
+        .collect();
+    Ok(Json(mine.into()))
+}
+
+/// The questions asked with the `X-Author` in `headers`, and what became of them.
+pub(super) async fn authored(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    let Some(author) = author(&headers, &meta.secret) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let listed = dynamo.list(&eid, true).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for asker's questions failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut asked: Vec<_> = listed
+        .items()
+        .unwrap_or_default()
+        .iter()
+        .filter(|q| {
+            q.get("author")
+                .and_then(|v| v.as_s().ok())
+                .is_some_and(|a| same(a, &author))
+        })
+        .filter_map(|q| super::list::parse(&eid, q))
+        .collect();
+    if asked.is_empty() {
+        return Ok(Json(serde_json::json!({})));
+    }
+    super::experiment::Ordering::New.sort(&mut asked, 0);
+    asked.truncate(MAX_MINE);
+    let qids: Vec<_> = asked.iter().map(|(qid, _)| *qid).collect();
+
+    let qs = dynamo.questions(&qids).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for asker's questions failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mine: serde_json::Map<_, _> = qs
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .into_iter()
+        .flatten()
+        .filter_map(|q| {
+            let qid = Uuid::parse_str(q.get("id").and_then(|v| v.as_s().ok())?).ok()?;
+            let (_, listed) = asked.iter().find(|(id, _)| *id == qid)?;
+            let mut standing = standing(&eid, q)?;
+            standing["votes"] = listed.votes.into();
+            standing["answered"] = listed.answered.into();
+            standing["receipt"] = receipt(&meta.secret, &qid).into();
+            Some((qid.to_string(), standing))
+        })
+        .collect();
+    Ok(Json(mine.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::{Property, Why};
+    use axum::extract::Query;
+
+    #[test]
+    fn receipts() {
+        let qid = Uuid::new_v4();
+        let r = receipt("secret", &qid);
+        assert_eq!(r.len(), 64);
+        assert!(checks_out("secret", &qid, &r));
+        assert!(!checks_out("other", &qid, &r));
+        assert!(!checks_out("secret", &Uuid::new_v4(), &r));
+        assert!(!checks_out("secret", &qid, &r[..40]));
+    }
+
+    #[test]
+    fn authors() {
+        let token = |t: &str| HeaderMap::from_iter([(AUTHOR, t.parse().unwrap())]);
+        let a = author(&token("me"), "secret").unwrap();
+        assert_eq!(a.len(), 64);
+        assert!(same(&a, &author(&token(" me "), "secret").unwrap()));
+        assert!(!same(&a, &author(&token("me"), "other").unwrap()));
+        assert!(!same(&a, &author(&token("you"), "secret").unwrap()));
+        assert_eq!(author(&HeaderMap::new(), "secret"), None);
+    }
+
+    #[test]
+    fn reasons() {
+        let qid = Uuid::new_v4();
+        for reason in [
+            Reason::OffTopic,
+            Reason::DuplicateOf(qid),
+            Reason::Filtered,
+            Reason::ModeratorRemoved,
+            Reason::PendingReview,
+            Reason::Expired,
+            Reason::Retracted,
+        ] {
+            assert_eq!(reason.to_string().parse(), Ok(reason));
+        }
+        assert!("duplicate-of:nope".parse::<Reason>().is_err());
+        assert!("rude".parse::<Reason>().is_err());
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let mut asked = Vec::new();
+        for body in ["hello world", "hello moon", "hello sun"] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap();
+            let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+            asked.push((qid, q["receipt"].as_str().unwrap().to_string()));
+        }
+        let [(q1, r1), (q2, r2), (q3, r3)] = <[_; 3]>::try_from(asked).unwrap();
+        let hide = |qid, reason: Option<&str>, on: &str| {
+            crate::toggle::toggle(
+                Path((eid, secret.to_string(), qid, Property::Hidden)),
+                State(backend.clone()),
+                Query(Why {
+                    reason: reason.map(|r| r.parse().unwrap()),
+                }),
+                String::from(on),
+            )
+        };
+        hide(q1, Some("off-topic"), "on").await.unwrap();
+        hide(q2, None, "on").await.unwrap();
+        let dup = format!("duplicate-of:{q1}");
+        hide(q3, Some(&dup), "on").await.unwrap();
+        hide(q3, None, "off").await.unwrap();
+
+        let Json(mine) = super::mine(
+            Path(eid),
+            State(backend.clone()),
+            Json(HashMap::from_iter([
+                (q1, r1),
+                (q2, r2.clone()),
+                (q3, r3),
+                // someone else's question
+                (Uuid::new_v4(), r2),
+            ])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            mine,
+            serde_json::json!({
+                q1.to_string(): { "hidden": true, "reason": "off-topic" },
+                q2.to_string(): { "hidden": true, "reason": "moderator-removed" },
+                q3.to_string(): { "hidden": false },
+            })
+        );
+
+        // askers who sent a token can find their questions by it
+        let token = |t: &str| HeaderMap::from_iter([(AUTHOR, t.parse().unwrap())]);
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            token("mine-all-mine"),
+            Json(crate::ask::Question {
+                body: "hello stars".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let authored = |headers| super::authored(Path(eid), State(backend.clone()), headers);
+        let Json(mine) = authored(token("mine-all-mine")).await.unwrap();
+        assert_eq!(
+            mine,
+            serde_json::json!({
+                q["id"].as_str().unwrap(): {
+                    "hidden": false,
+                    "votes": 1,
+                    "answered": false,
+                    "receipt": q["receipt"],
+                },
+            })
+        );
+        let Json(none) = authored(token("someone-else")).await.unwrap();
+        assert_eq!(none, serde_json::json!({}));
+        assert_eq!(
+            authored(HeaderMap::new()).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,930 @@
+//! Freezing events that are used for abuse, pending review.
+//!
+//! Public instances occasionally host events that exist to harass someone or to spread spam.
+//! Rather than delete such an event outright, an operator can freeze it: nobody can ask or vote in
+//! it anymore, and its question list, change feed, and question texts are withheld, but nothing is
+//! deleted. The host can see that (and why) the event was frozen and appeal, and the operator can
+//! then unfreeze it, or leave it frozen until it expires like any other event.
+//!
+//! Everything that's done is appended to the event's moderation log, which is stored (as JSON) on
+//! the event's item so it comes along with the reads most requests make anyway, and which doubles
+//! as the audit trail. Legal holds (see [`super::hold`]) and hosts closing their events (see
+//! [`super::archive`]) are recorded there too, as are the networks hosts keep their events to (see
+//! [`super::networks`]), that they lock their question lists (see [`super::lock`]), and the
+//! orderings they pick for them (see [`super::order`]). Once the log is longer than `MAX_LOG`
+//! actions, the oldest changes to those host settings that have been changed again since are
+//! dropped from it, so that toggling a setting can't grow the event's item without end.
+//! Each action is also logged as it happens. The log is only written back if nobody else wrote it
+//! since it was read, and read and amended again otherwise (see [`amend`]), so that a host
+//! changing a setting can't undo a freeze or a hold that went in at the same time.
+//!
+//! Votes and question fetches only know about questions, not their event, so freezing also marks
+//! each of the event's questions as `frozen`. A question that's asked just as the event is frozen
+//! may be missed, but it can't be listed either. Instances that have the event in their hot set
+//! keep serving its list from memory for up to `HOT_REFRESH_MS`.
+
+use super::{admin::Admin, Backend, Local};
+use aws_sdk_dynamodb::{error::UpdateItemError, model::AttributeValue, types::SdkError};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Notes (the reason for a freeze, or an appeal) can't be longer than this.
+pub(super) const MAX_NOTE: usize = 2000;
+/// Hosts can appeal a freeze at most this many times.
+pub(super) const MAX_APPEALS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Kind {
+    /// An operator froze the event.
+    Frozen,
+    /// The host appealed the freeze.
+    Appealed,
+    /// An operator unfroze the event.
+    Unfrozen,
+    /// An operator or the host put the event on legal hold.
+    Held,
+    /// An operator released the event's legal hold.
+    Released,
+    /// The host closed the event to new questions.
+    Closed,
+    /// The host closed the event and published it as a public archive.
+    Archived,
+    /// The host opened the event back up.
+    Reopened,
+    /// The host asked search engines to leave the event out.
+    Unlisted,
+    /// The host let search engines index the event again.
+    Listed,
+    /// The host kept the event to some networks, which are listed in the note.
+    Restricted,
+    /// The host opened the event to every network again.
+    Unrestricted,
+    /// The host locked the order of the question list, which is listed in the note.
+    Locked,
+    /// The host let the question list reorder itself again.
+    Unlocked,
+    /// The host picked the order the question list is in unless asked for another, which is the
+    /// note.
+    Ordered,
+}
+
+/// Who took an action that both operators and hosts can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Role {
+    Operator,
+    Host,
+}
+
+/// An entry in an event's moderation log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Action {
+    /// When it happened, in seconds since the epoch.
+    pub(super) at: u64,
+    pub(super) kind: Kind,
+    /// Why the event was frozen or unfrozen, or what the host had to say.
+    pub(super) note: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) by: Option<Role>,
+}
+
+/// The moderation log stored as `log`.
+pub(super) fn stored(log: Option<&str>) -> Vec<Action> {
+    let Some(log) = log else {
+        return Vec::new();
+    };
+    serde_json::from_str(log).unwrap_or_else(|e| {
+        // failing open would unfreeze the event, so this is worth shouting about
+        error!(error = %e, "found malformed moderation log");
+        Vec::new()
+    })
+}
+
+/// How a moderation log is stored.
+pub(super) fn store(log: &[Action]) -> String {
+    serde_json::to_string(log).expect("logs always serialize")
+}
+
+/// The action that froze the event, if it's frozen.
+pub(super) fn frozen(log: &[Action]) -> Option<&Action> {
+    log.iter()
+        .rev()
+        .find(|a| matches!(a.kind, Kind::Frozen | Kind::Unfrozen))
+        .filter(|a| a.kind == Kind::Frozen)
+}
+
+/// The action that put the event on legal hold, if it's held.
+pub(super) fn held(log: &[Action]) -> Option<&Action> {
+    log.iter()
+        .rev()
+        .find(|a| matches!(a.kind, Kind::Held | Kind::Released))
+        .filter(|a| a.kind == Kind::Held)
+}
+
+/// The action that closed the event, if it's closed.
+pub(super) fn closed(log: &[Action]) -> Option<&Action> {
+    log.iter()
+        .rev()
+        .find(|a| matches!(a.kind, Kind::Closed | Kind::Archived | Kind::Reopened))
+        .filter(|a| a.kind != Kind::Reopened)
+}
+
+/// The action that closed the event and published it as a public archive, if it's archived.
+pub(super) fn archived(log: &[Action]) -> Option<&Action> {
+    closed(log).filter(|a| a.kind == Kind::Archived)
+}
+
+/// The action that asked search engines to leave the event out, if they're to.
+pub(super) fn unlisted(log: &[Action]) -> Option<&Action> {
+    log.iter()
+        .rev()
+        .find(|a| matches!(a.kind, Kind::Unlisted | Kind::Listed))
+        .filter(|a| a.kind == Kind::Unlisted)
+}
+
+/// The action that kept the event to some networks, if it's restricted.
+pub(super) fn restricted(log: &[Action]) -> Option<&Action> {
+    log.iter()
+        .rev()
+        .find(|a| matches!(a.kind, Kind::Restricted | Kind::Unrestricted))
+        .filter(|a| a.kind == Kind::Restricted)
+}
+
+/// The action that locked the order of the question list, if it's locked.
+pub(super) fn locked(log: &[Action]) -> Option<&Action> {
+    log.iter()
+        .rev()
+        .find(|a| matches!(a.kind, Kind::Locked | Kind::Unlocked))
+        .filter(|a| a.kind == Kind::Locked)
+}
+
+/// The action that picked the order of the question list, if the host picked one.
+pub(super) fn ordered(log: &[Action]) -> Option<&Action> {
+    log.iter().rev().find(|a| a.kind == Kind::Ordered)
+}
+
+/// Whether `question` (an item, if there is one) belongs to a frozen event.
+pub(super) fn is_frozen(question: Option<&HashMap<String, AttributeValue>>) -> bool {
+    question
+        .and_then(|q| q.get("frozen"))
+        .and_then(|f| f.as_bool().ok())
+        .copied()
+        .unwrap_or(false)
+}
+
+pub(super) fn status(log: &[Action]) -> serde_json::Value {
+    serde_json::json!({
+        "frozen": frozen(log).is_some(),
+        "held": held(log).is_some(),
+        "closed": closed(log).is_some(),
+        "archived": archived(log).is_some(),
+        "indexed": unlisted(log).is_none(),
+        "networks": restricted(log).map(|a| a.note.split(',').collect::<Vec<_>>()),
+        "locked": locked(log).is_some(),
+        "order": ordered(log).map_or("top", |a| a.note.as_str()),
+        "log": log,
+    })
+}
+
+/// How many times to read, amend, and write back a moderation log that keeps changing under us.
+const MAX_AMENDS: usize = 5;
+/// How many actions a moderation log keeps before host settings that have changed since are
+/// dropped from it (see [`compact`]).
+const MAX_LOG: usize = 64;
+
+/// The host setting an action changes, as the kind of action that turns it on, if it changes one.
+fn setting(kind: Kind) -> Option<Kind> {
+    match kind {
+        Kind::Closed | Kind::Archived | Kind::Reopened => Some(Kind::Closed),
+        Kind::Unlisted | Kind::Listed => Some(Kind::Unlisted),
+        Kind::Restricted | Kind::Unrestricted => Some(Kind::Restricted),
+        Kind::Locked | Kind::Unlocked => Some(Kind::Locked),
+        Kind::Ordered => Some(Kind::Ordered),
+        Kind::Frozen | Kind::Appealed | Kind::Unfrozen | Kind::Held | Kind::Released => None,
+    }
+}
+
+/// Keep `log` to `MAX_LOG` actions by dropping the oldest changes to host settings that have been
+/// changed again since.
+///
+/// Freezes, appeals, and holds are never dropped, and neither is the latest change to each setting,
+/// since that's what the setting is, so the log can still be longer than that.
+fn compact(log: &mut Vec<Action>) {
+    let mut excess = log.len().saturating_sub(MAX_LOG);
+    if excess == 0 {
+        return;
+    }
+    let superseded: Vec<_> = log
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            setting(a.kind).is_some_and(|s| log[i + 1..].iter().any(|b| setting(b.kind) == Some(s)))
+        })
+        .collect();
+    let mut i = 0;
+    log.retain(|_| {
+        let drop = excess != 0 && superseded[i];
+        i += 1;
+        if drop {
+            excess -= 1;
+        }
+        !drop
+    });
+}
+
+/// Amend the event's moderation log with `amend`, and write it back.
+///
+/// `meta` is the event as it was just read. If the log was written by someone else since (say, an
+/// operator freezing the event while the host locks its list), it's read again and amended again,
+/// so that neither write undoes the other. `amend` returns whether it changed the log, and if it
+/// didn't, nothing is written. Either way, the log as it now stands is returned, along with whether
+/// it was changed. `what` is what the amendment is for, for the logs. Logs that get long are
+/// [compacted](compact) as they're written.
+pub(super) async fn amend(
+    dynamo: &Backend,
+    eid: &Uuid,
+    meta: super::Meta,
+    what: &'static str,
+    amend: impl FnMut(&mut Vec<Action>) -> Result<bool, StatusCode>,
+) -> Result<(Vec<Action>, bool), StatusCode> {
+    amend_locking(dynamo, eid, meta, what, None, amend).await
+}
+
+/// [`amend`] the event's moderation log, and if it's changed, make `locked` the order its question
+/// list is locked in along with it (see [`super::lock`]).
+pub(super) async fn amend_locking(
+    dynamo: &Backend,
+    eid: &Uuid,
+    mut meta: super::Meta,
+    what: &'static str,
+    locked: Option<&str>,
+    mut amend: impl FnMut(&mut Vec<Action>) -> Result<bool, StatusCode>,
+) -> Result<(Vec<Action>, bool), StatusCode> {
+    for attempt in 1..=MAX_AMENDS {
+        let mut log = meta.moderation;
+        if !amend(&mut log)? {
+            return Ok((log, false));
+        }
+        compact(&mut log);
+        match dynamo.moderate(eid, meta.moderated, &log, locked).await {
+            Ok(true) => return Ok((log, true)),
+            Ok(false) => {
+                debug!(%eid, what, attempt, "moderation log changed under us");
+                super::metrics::incr("moderation.conflict");
+            }
+            Err(e) => {
+                error!(%eid, what, error = %e, "failed to write moderation log");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        meta = super::get_meta(dynamo, eid).await?;
+    }
+    warn!(%eid, what, "gave up on moderation log that keeps changing");
+    Err(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+impl Backend {
+    /// Make `log` the event's moderation log, and `locked` the order its question list is locked in
+    /// if there's a new one, unless the log has been written since it was written for the
+    /// `moderated`th time (see [`super::Meta`]).
+    ///
+    /// Returns whether `log` was written.
+    pub(super) async fn moderate(
+        &self,
+        eid: &Uuid,
+        moderated: u64,
+        log: &[Action],
+        locked: Option<&str>,
+    ) -> Result<bool, aws_sdk_dynamodb::Error> {
+        let stored = store(log);
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()))
+                    .expression_attribute_values(":log", AttributeValue::S(stored))
+                    .expression_attribute_values(
+                        ":next",
+                        AttributeValue::N((moderated + 1).to_string()),
+                    );
+                let upd = match locked {
+                    Some(locked) => upd
+                        .update_expression(
+                            "SET moderation = :log, moderated = :next, locked = :locked",
+                        )
+                        .expression_attribute_values(":locked", AttributeValue::S(locked.into())),
+                    None => upd.update_expression("SET moderation = :log, moderated = :next"),
+                };
+                // see Backend::record
+                let upd = if super::config::config().alternator {
+                    upd
+                } else if moderated == 0 {
+                    upd.condition_expression(
+                        "attribute_exists(id) AND attribute_not_exists(moderated)",
+                    )
+                } else {
+                    upd.condition_expression("moderated = :moderated")
+                        .expression_attribute_values(
+                            ":moderated",
+                            AttributeValue::N(moderated.to_string()),
+                        )
+                };
+                match upd.send().await {
+                    Ok(_) => Ok(true),
+                    Err(SdkError::ServiceError { err, .. })
+                        if err.is_conditional_check_failed_exception() =>
+                    {
+                        Ok(false)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    events,
+                    moderation,
+                    moderated: written,
+                    locked: orders,
+                    journal,
+                    ..
+                } = &mut *local;
+
+                if !events.contains_key(eid) {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("moderating event that doesn't exist")
+                            .build(),
+                    ))
+                    .into());
+                }
+                let written = written.entry(*eid).or_default();
+                if *written != moderated {
+                    return Ok(false);
+                }
+                *written += 1;
+                journal.moderation(eid, log);
+                moderation.insert(*eid, log.to_vec());
+                if let Some(locked) = locked {
+                    journal.locked(eid, locked);
+                    orders.insert(*eid, locked.to_string());
+                }
+                Ok(true)
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .moderate(eid, moderated, stored, locked)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .moderate(eid, moderated, stored, locked)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .moderate(eid, moderated, stored, locked)
+                .map_err(super::mint_unhandled),
+        }
+    }
+
+    /// Mark all of the event's questions as `frozen` (or not), and return how many there are.
+    pub(super) async fn freeze_questions(
+        &self,
+        eid: &Uuid,
+        frozen: bool,
+    ) -> Result<usize, aws_sdk_dynamodb::Error> {
+        let qs = self.list(eid, true).await?;
+        let qids: Vec<_> = qs
+            .items()
+            .into_iter()
+            .flat_map(|qs| qs.iter().filter_map(|doc| doc["id"].as_s().ok()))
+            .filter_map(|qid| Uuid::parse_str(qid).ok())
+            .collect();
+        for qid in &qids {
+            match self {
+                Self::Dynamo(dynamo) => {
+                    dynamo
+                        .update_item()
+                        .table_name("questions")
+                        .key("id", AttributeValue::S(qid.to_string()))
+                        .update_expression("SET frozen = :frozen")
+                        .expression_attribute_values(":frozen", AttributeValue::Bool(frozen))
+                        .send()
+                        .await?;
+                }
+                Self::Local(local) => {
+                    let mut local = local.write().unwrap();
+                    let Local {
+                        questions, journal, ..
+                    } = &mut *local;
+
+                    if let Some(q) = questions.get_mut(qid) {
+                        q.insert("frozen", AttributeValue::Bool(frozen));
+                        journal.question(qid, q);
+                    }
+                }
+                #[cfg(feature = "mongo")]
+                Self::Mongo(mongo) => {
+                    mongo
+                        .update::<UpdateItemError>(
+                            qid,
+                            mongodb::bson::doc! { "$set": { "frozen": frozen } },
+                        )
+                        .await?;
+                }
+                #[cfg(feature = "redis")]
+                Self::Redis(redis) => {
+                    redis
+                        .set::<UpdateItemError>(qid, &[("frozen", AttributeValue::Bool(frozen))])
+                        .await?;
+                }
+                #[cfg(feature = "sled")]
+                Self::Sled(sled) => {
+                    sled.set::<UpdateItemError>(qid, &[("frozen", AttributeValue::Bool(frozen))])
+                        .await?;
+                }
+            }
+        }
+        Ok(qids.len())
+    }
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// What an operator has decided about an event.
+#[derive(Debug, Deserialize)]
+pub(super) struct Moderate {
+    frozen: bool,
+    /// Why. Hosts get to see this.
+    #[serde(default)]
+    note: String,
+}
+
+/// The event's moderation log, for operators.
+pub(super) async fn review(
+    _: Admin,
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    Ok(Json(status(&meta.moderation)))
+}
+
+/// Freeze or unfreeze the event.
+///
+/// Doing what's already been done doesn't add to the log, but does mark the event's questions
+/// again, in case that didn't finish the first time.
+pub(super) async fn moderate(
+    _: Admin,
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Moderate>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    let note = req.note.trim();
+    if note.chars().count() > MAX_NOTE {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if req.frozen && note.is_empty() {
+        // the host deserves to know why
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let kind = if req.frozen {
+        Kind::Frozen
+    } else {
+        Kind::Unfrozen
+    };
+    let record = |log: &mut Vec<Action>| {
+        if frozen(log).is_some() == req.frozen {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: now(),
+            kind,
+            note: note.to_string(),
+            by: None,
+        });
+        Ok(true)
+    };
+
+    // frozen events should stop taking questions before their questions are marked, and unfrozen
+    // ones shouldn't be listed again until their questions can be voted for
+    let mut amended = None;
+    if req.frozen {
+        amended = Some(amend(&dynamo, &eid, meta.clone(), "freeze", record).await?);
+    }
+    let questions = match dynamo.freeze_questions(&eid, req.frozen).await {
+        Ok(n) => n,
+        Err(e) => {
+            error!(%eid, frozen = req.frozen, error = %e, "failed to mark questions");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if !req.frozen {
+        amended = Some(amend(&dynamo, &eid, meta, "unfreeze", record).await?);
+    }
+    let (log, changed) = amended.expect("either frozen or unfrozen");
+    super::hot::forget(&eid);
+    // the texts of just-frozen questions may be in memory, but which ones isn't known
+    if req.frozen {
+        super::texts::clear();
+    }
+
+    if changed {
+        info!(%eid, ?kind, note, questions, "moderated event");
+        super::metrics::incr(format!("moderation.{kind:?}").to_lowercase());
+    } else {
+        info!(%eid, ?kind, questions, "re-marked questions of moderated event");
+    }
+    Ok(Json(status(&log)))
+}
+
+/// Whether the event is frozen, and why, for its host.
+pub(super) async fn status_for_host(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    Ok(Json(status(&meta.moderation)))
+}
+
+/// What the host has to say about their event being frozen.
+#[derive(Debug, Deserialize)]
+pub(super) struct Appeal {
+    note: String,
+}
+
+/// Appeal the event being frozen.
+pub(super) async fn appeal(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Appeal>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    let note = req.note.trim();
+    let (log, _) = amend(&dynamo, &eid, meta, "appeal", |log| {
+        let Some(freeze) = log.iter().rposition(|a| a.kind == Kind::Frozen) else {
+            return Err(StatusCode::CONFLICT);
+        };
+        if frozen(log).is_none() {
+            return Err(StatusCode::CONFLICT);
+        }
+        if note.is_empty() || note.chars().count() > MAX_NOTE {
+            warn!(%eid, "bad appeal");
+            super::rejections::reject(&eid, "bad_appeal");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if log[freeze..]
+            .iter()
+            .filter(|a| a.kind == Kind::Appealed)
+            .count()
+            >= MAX_APPEALS
+        {
+            warn!(%eid, "too many appeals");
+            super::rejections::reject(&eid, "too_many_appeals");
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        log.push(Action {
+            at: now(),
+            kind: Kind::Appealed,
+            note: note.to_string(),
+            by: None,
+        });
+        Ok(true)
+    })
+    .await?;
+    // operators find out about appeals from the logs (and the metrics)
+    warn!(%eid, note, "host appealed freeze");
+    super::metrics::incr("moderation.appealed");
+    Ok(Json(status(&log)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vote::UpDown;
+    use axum::extract::Query;
+    use http::HeaderMap;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let ask = || {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: "hello world".into(),
+                    asker: None,
+                }),
+            )
+        };
+        let vote = |qid| {
+            crate::vote::vote(
+                Path((qid, UpDown::Up)),
+                State(backend.clone()),
+                HeaderMap::new(),
+                crate::networks::Client(None),
+            )
+        };
+        let list = || {
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                axum::extract::Query(Default::default()),
+                HeaderMap::new(),
+            )
+        };
+        let texts = |qid: Uuid| {
+            crate::questions::questions(
+                Path(qid.to_string()),
+                State(backend.clone()),
+                HeaderMap::new(),
+                crate::networks::Client(None),
+            )
+        };
+        let moderate = |frozen, note: &str| {
+            super::moderate(
+                Admin,
+                Path(eid),
+                State(backend.clone()),
+                Json(Moderate {
+                    frozen,
+                    note: note.to_string(),
+                }),
+            )
+        };
+        let appeal = |note: &str| {
+            super::appeal(
+                Path((eid, secret.to_string())),
+                State(backend.clone()),
+                Json(Appeal {
+                    note: note.to_string(),
+                }),
+            )
+        };
+
+        let q = ask().await.unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        vote(qid).await.unwrap();
+        assert_eq!(appeal("why?").await.unwrap_err(), StatusCode::CONFLICT);
+
+        assert_eq!(
+            moderate(true, "").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let s = moderate(true, "harassment").await.unwrap();
+        assert_eq!(s["frozen"], true);
+        assert_eq!(s["log"][0]["kind"], "frozen");
+        assert_eq!(s["log"][0]["note"], "harassment");
+
+        // nothing goes in, and nothing comes out
+        assert_eq!(ask().await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(vote(qid).await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(list().await.2.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            crate::list::list_all(
+                Path((eid, secret.to_string())),
+                State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new()
+            )
+            .await
+            .2
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        let (_, _, withheld) = texts(qid).await;
+        assert_eq!(withheld.unwrap().0, serde_json::json!({}));
+
+        // but the host can find out why, and appeal
+        let s = status_for_host(Path((eid, secret.to_string())), State(backend.clone()))
+            .await
+            .unwrap();
+        assert_eq!(s["log"][0]["note"], "harassment");
+        assert_eq!(
+            status_for_host(Path((eid, String::from("wrong"))), State(backend.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(appeal("  ").await.unwrap_err(), StatusCode::BAD_REQUEST);
+        for _ in 0..MAX_APPEALS {
+            let s = appeal("it's a roast, they're in on it").await.unwrap();
+            assert_eq!(s["frozen"], true);
+        }
+        assert_eq!(
+            appeal("please").await.unwrap_err(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // freezing again changes nothing
+        let s = moderate(true, "still harassment").await.unwrap();
+        assert_eq!(s["log"].as_array().unwrap().len(), 1 + MAX_APPEALS);
+
+        let s = moderate(false, "appeal accepted").await.unwrap();
+        assert_eq!(s["frozen"], false);
+        assert_eq!(s["log"][1 + MAX_APPEALS]["kind"], "unfrozen");
+        let s = review(Admin, Path(eid), State(backend.clone()))
+            .await
+            .unwrap();
+        assert_eq!(s["log"].as_array().unwrap().len(), 2 + MAX_APPEALS);
+
+        // and everything is as it was
+        let qs = list().await.2.unwrap();
+        assert_eq!(qs[0]["votes"], 2);
+        assert_eq!(vote(qid).await.unwrap()["votes"], 3);
+        let (_, _, texts) = texts(qid).await;
+        assert_eq!(texts.unwrap()[qid.to_string()]["text"], "hello world");
+        ask().await.unwrap();
+
+        // freezes through other instances don't leave the list served from memory here for long
+        list().await.2.unwrap();
+        let meta = crate::get_meta(&backend, &eid).await.unwrap();
+        let mut log = meta.moderation.clone();
+        log.push(Action {
+            at: now(),
+            kind: Kind::Frozen,
+            note: String::from("elsewhere"),
+            by: None,
+        });
+        assert!(backend
+            .moderate(&eid, meta.moderated, &log, None)
+            .await
+            .unwrap());
+        crate::hot::overdue(&eid);
+        assert_eq!(list().await.2.unwrap_err(), StatusCode::FORBIDDEN);
+        assert!(!crate::hot::contains(&eid));
+        moderate(false, "appeal accepted elsewhere").await.unwrap();
+
+        // a write based on a log that's since changed doesn't undo that change
+        let stale = crate::get_meta(&backend, &eid).await.unwrap();
+        let s = moderate(true, "harassment again").await.unwrap();
+        assert_eq!(s["frozen"], true);
+        let mut log = stale.moderation.clone();
+        log.push(Action {
+            at: now(),
+            kind: Kind::Unlisted,
+            note: String::new(),
+            by: Some(Role::Host),
+        });
+        assert!(!backend
+            .moderate(&eid, stale.moderated, &log, None)
+            .await
+            .unwrap());
+        let (log, changed) = amend(&backend, &eid, stale, "test", |log| {
+            log.push(Action {
+                at: now(),
+                kind: Kind::Unlisted,
+                note: String::new(),
+                by: Some(Role::Host),
+            });
+            Ok(true)
+        })
+        .await
+        .unwrap();
+        assert!(changed);
+        assert!(frozen(&log).is_some());
+        assert!(unlisted(&log).is_some());
+        let meta = crate::get_meta(&backend, &eid).await.unwrap();
+        assert_eq!(meta.moderation, log);
+
+        backend.delete(&eid).await;
+    }
+
+    #[test]
+    fn compacts() {
+        let action = |kind, note: &str| Action {
+            at: 0,
+            kind,
+            note: note.to_string(),
+            by: None,
+        };
+        let mut log = vec![action(Kind::Frozen, "spam"), action(Kind::Ordered, "new")];
+        for _ in 0..MAX_LOG {
+            log.push(action(Kind::Locked, ""));
+            log.push(action(Kind::Unlocked, ""));
+        }
+        log.push(action(Kind::Unfrozen, "fine"));
+        let n = log.len();
+        compact(&mut log);
+        assert_eq!(log.len(), MAX_LOG);
+        // the oldest lock changes go first
+        assert_eq!(
+            log[..2],
+            [action(Kind::Frozen, "spam"), action(Kind::Ordered, "new")]
+        );
+        assert_eq!(log[MAX_LOG - 1], action(Kind::Unfrozen, "fine"));
+        assert_eq!(locked(&log), None);
+
+        // and freezes and the latest of each setting stay no matter how many there are
+        let mut log: Vec<_> = (0..n).map(|_| action(Kind::Frozen, "spam")).collect();
+        log.push(action(Kind::Locked, ""));
+        compact(&mut log);
+        assert_eq!(log.len(), n + 1);
+        assert!(locked(&log).is_some());
+    }
+
+    #[test]
+    fn frozen_until_unfrozen() {
+        let action = |kind| Action {
+            at: 0,
+            kind,
+            note: String::new(),
+            by: None,
+        };
+        let mut log = vec![];
+        assert_eq!(frozen(&log), None);
+        log.push(action(Kind::Frozen));
+        assert_eq!(frozen(&log), Some(&log[0]));
+        log.push(action(Kind::Appealed));
+        assert!(frozen(&log).is_some());
+        log.push(action(Kind::Unfrozen));
+        assert_eq!(frozen(&log), None);
+        log.push(action(Kind::Frozen));
+        assert_eq!(frozen(&log), Some(&log[3]));
+
+        // holds come and go independently of freezes
+        assert_eq!(held(&log), None);
+        log.push(action(Kind::Held));
+        assert_eq!(frozen(&log), Some(&log[3]));
+        assert_eq!(held(&log), Some(&log[4]));
+        log.push(action(Kind::Released));
+        assert_eq!(held(&log), None);
+        assert!(frozen(&log).is_some());
+
+        // and so does closing
+        assert_eq!(closed(&log), None);
+        log.push(action(Kind::Archived));
+        assert_eq!(closed(&log), Some(&log[6]));
+        log.push(action(Kind::Reopened));
+        assert_eq!(closed(&log), None);
+        assert!(frozen(&log).is_some());
+        log.push(action(Kind::Unlisted));
+        assert_eq!(unlisted(&log), Some(&log[8]));
+        log.push(action(Kind::Listed));
+        assert_eq!(unlisted(&log), None);
+        log.push(action(Kind::Locked));
+        assert_eq!(locked(&log), Some(&log[10]));
+        log.push(action(Kind::Unlocked));
+        assert_eq!(locked(&log), None);
+        assert_eq!(ordered(&log), None);
+        log.push(action(Kind::Ordered));
+        assert_eq!(ordered(&log), Some(&log[12]));
+
+        assert_eq!(stored(Some(&store(&log))), log);
+        assert_eq!(stored(None), []);
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
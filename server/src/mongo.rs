@@ -0,0 +1,674 @@
+//! A MongoDB backend, for deployments that would rather not depend on AWS.
+//!
+//! Documents mirror the DynamoDB items field for field (except that `id` is stored as `_id`), and
+//! results are handed back shaped like DynamoDB responses, so the handlers don't need to care
+//! which backend they're talking to. `expire` is stored as a date so that TTL indexes can clean up
+//! old events the way DynamoDB's TTL does.
+
+use super::mint_dispatch_failure as failed;
+use super::{
+    announce::{Announcement, Scheduled},
+    hold::Expiry,
+    react::Reaction,
+    Meta,
+};
+use aws_sdk_dynamodb::{
+    model::AttributeValue,
+    output::{BatchGetItemOutput, GetItemOutput, PutItemOutput, QueryOutput, UpdateItemOutput},
+    types::SdkError,
+};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, Bson, DateTime, Document},
+    options::{
+        FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions, ReturnDocument,
+        UpdateModifications,
+    },
+    Collection, Database, IndexModel,
+};
+use std::{collections::HashMap, time::Duration};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Clone, Debug)]
+pub(super) struct Mongo {
+    db: Database,
+}
+
+fn to_bson(key: &str, v: AttributeValue) -> Bson {
+    match v {
+        AttributeValue::S(s) => Bson::String(s),
+        AttributeValue::N(n) if key == "expire" => Bson::DateTime(DateTime::from_millis(
+            n.parse::<i64>().expect("expire is a number") * 1000,
+        )),
+        AttributeValue::N(n) => Bson::Int64(n.parse().expect("all numbers are integers")),
+        AttributeValue::Bool(b) => Bson::Boolean(b),
+        AttributeValue::M(m) => Bson::Document(
+            m.into_iter()
+                .map(|(k, v)| {
+                    let bson = to_bson(&k, v);
+                    (k, bson)
+                })
+                .collect(),
+        ),
+        v => unreachable!("no attributes are of type {v:?}"),
+    }
+}
+
+fn to_document(item: impl IntoIterator<Item = (&'static str, AttributeValue)>) -> Document {
+    item.into_iter()
+        .map(|(k, v)| {
+            let bson = to_bson(k, v);
+            (String::from(if k == "id" { "_id" } else { k }), bson)
+        })
+        .collect()
+}
+
+fn to_item(doc: Document) -> HashMap<String, AttributeValue> {
+    doc.into_iter()
+        .filter_map(|(k, v)| {
+            let v = match v {
+                Bson::String(s) => AttributeValue::S(s),
+                Bson::Int32(n) => AttributeValue::N(n.to_string()),
+                Bson::Int64(n) => AttributeValue::N(n.to_string()),
+                Bson::DateTime(d) => AttributeValue::N((d.timestamp_millis() / 1000).to_string()),
+                Bson::Boolean(b) => AttributeValue::Bool(b),
+                Bson::Document(d) => AttributeValue::M(to_item(d)),
+                v => {
+                    warn!(field = k, value = ?v, "ignoring field of unexpected type");
+                    return None;
+                }
+            };
+            let k = if k == "_id" { String::from("id") } else { k };
+            Some((k, v))
+        })
+        .collect()
+}
+
+impl Mongo {
+    /// Connect to the database named in `uri` (or `wewerewondering`), and set up its indexes.
+    pub(super) async fn connect(uri: &str) -> Result<Self, mongodb::error::Error> {
+        let client = mongodb::Client::with_uri_str(uri).await?;
+        let db = client
+            .default_database()
+            .unwrap_or_else(|| client.database("wewerewondering"));
+        let mongo = Mongo { db };
+        mongo.create_indexes().await?;
+        Ok(mongo)
+    }
+
+    fn collection(&self, name: &str) -> Collection<Document> {
+        self.db.collection(name)
+    }
+
+    async fn create_indexes(&self) -> Result<(), mongodb::error::Error> {
+        let index = |name: &str, keys: Document| {
+            IndexModel::builder()
+                .keys(keys)
+                .options(IndexOptions::builder().name(name.to_string()).build())
+                .build()
+        };
+        let ttl = IndexModel::builder()
+            .keys(doc! { "expire": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name(String::from("expire"))
+                    .expire_after(Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        self.collection("events")
+            .create_indexes([ttl.clone()], None)
+            .await?;
+        // the equivalents of the `top` secondary index, plus one for listing by age
+        self.collection("questions")
+            .create_indexes(
+                [
+                    index("top", doc! { "eid": 1, "votes": -1 }),
+                    index("when", doc! { "eid": 1, "when": 1 }),
+                    ttl.clone(),
+                ],
+                None,
+            )
+            .await?;
+        let seq = IndexModel::builder()
+            .keys(doc! { "eid": 1, "seq": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name(String::from("seq"))
+                    .unique(true)
+                    .build(),
+            )
+            .build();
+        self.collection("changes")
+            .create_indexes([seq, ttl], None)
+            .await?;
+        Ok(())
+    }
+
+    /// Store `item` in `table`.
+    pub(super) async fn put<E>(
+        &self,
+        table: &str,
+        item: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<PutItemOutput, SdkError<E>> {
+        self.collection(table)
+            .insert_one(to_document(item), None)
+            .await
+            .map_err(failed)?;
+        Ok(PutItemOutput::builder().build())
+    }
+
+    pub(super) async fn meta(&self, eid: &Uuid) -> Result<Option<Meta>, mongodb::error::Error> {
+        let event = self
+            .collection("events")
+            .find_one(
+                doc! { "_id": eid.to_string() },
+                FindOneOptions::builder()
+                    .projection(doc! {
+                        "secret": 1,
+                        "seq": 1,
+                        "announcement": 1,
+                        "announced_until": 1,
+                        "scheduled": 1,
+                        "moderation": 1,
+                        "moderated": 1,
+                        "locked": 1,
+                    })
+                    .build(),
+            )
+            .await?;
+        Ok(event.and_then(|e| {
+            Some(Meta {
+                secret: e.get_str("secret").ok()?.to_string(),
+                version: e.get_i64("seq").unwrap_or(0) as u64,
+                announcement: Announcement::stored(
+                    e.get_str("announcement").ok().map(String::from),
+                    e.get_i64("announced_until").ok().map(|u| u as u64),
+                ),
+                scheduled: Scheduled::stored(e.get_str("scheduled").ok()),
+                moderation: super::moderation::stored(e.get_str("moderation").ok()),
+                moderated: e.get_i64("moderated").unwrap_or(0) as u64,
+                locked: e.get_str("locked").ok().map(String::from),
+            })
+        }))
+    }
+
+    pub(super) async fn announce(
+        &self,
+        eid: &Uuid,
+        announcement: Option<&Announcement>,
+    ) -> Result<(), mongodb::error::Error> {
+        let update = match announcement {
+            Some(a) => {
+                doc! { "$set": { "announcement": &a.text, "announced_until": a.until as i64 } }
+            }
+            None => doc! { "$unset": { "announcement": "", "announced_until": "" } },
+        };
+        let updated = self
+            .collection("events")
+            .update_one(doc! { "_id": eid.to_string() }, update, None)
+            .await?;
+        if updated.matched_count == 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "announcing in non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make `scheduled` (as stored) the event's queue of announcements, or remove it if `None`.
+    pub(super) async fn schedule(
+        &self,
+        eid: &Uuid,
+        scheduled: Option<String>,
+    ) -> Result<(), mongodb::error::Error> {
+        let update = match scheduled {
+            Some(s) => doc! { "$set": { "scheduled": s } },
+            None => doc! { "$unset": { "scheduled": "" } },
+        };
+        let updated = self
+            .collection("events")
+            .update_one(doc! { "_id": eid.to_string() }, update, None)
+            .await?;
+        if updated.matched_count == 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "scheduling in non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make `log` (as stored) the event's moderation log, and `locked` the order its question list
+    /// is locked in if there's a new one, unless the log's been written since it was written for
+    /// the `moderated`th time, and return whether it was.
+    pub(super) async fn moderate(
+        &self,
+        eid: &Uuid,
+        moderated: u64,
+        log: String,
+        locked: Option<&str>,
+    ) -> Result<bool, mongodb::error::Error> {
+        let unchanged = if moderated == 0 {
+            doc! { "$exists": false }
+        } else {
+            doc! { "$eq": moderated as i64 }
+        };
+        let mut set = doc! { "moderation": log, "moderated": moderated as i64 + 1 };
+        if let Some(locked) = locked {
+            set.insert("locked", locked);
+        }
+        let updated = self
+            .collection("events")
+            .update_one(
+                doc! { "_id": eid.to_string(), "moderated": unchanged },
+                doc! { "$set": set },
+                None,
+            )
+            .await?;
+        Ok(updated.matched_count != 0)
+    }
+
+    /// Make `policy` (as stored) the event's retention override, or remove it if `None`.
+    pub(super) async fn set_retention(
+        &self,
+        eid: &Uuid,
+        policy: Option<String>,
+    ) -> Result<(), mongodb::error::Error> {
+        let update = match policy {
+            Some(p) => doc! { "$set": { "retention": p } },
+            None => doc! { "$unset": { "retention": "" } },
+        };
+        let updated = self
+            .collection("events")
+            .update_one(doc! { "_id": eid.to_string() }, update, None)
+            .await?;
+        if updated.matched_count == 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "setting retention of non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Note on the event that it was warmed up `at`, and read the note back.
+    pub(super) async fn canary(
+        &self,
+        eid: &Uuid,
+        at: u64,
+    ) -> Result<Option<u64>, mongodb::error::Error> {
+        let events = self.collection("events");
+        let updated = events
+            .update_one(
+                doc! { "_id": eid.to_string() },
+                doc! { "$set": { "warmed": at as i64 } },
+                None,
+            )
+            .await?;
+        if updated.matched_count == 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "warming non-existing event {eid}"
+            )));
+        }
+        let event = events
+            .find_one(
+                doc! { "_id": eid.to_string() },
+                FindOneOptions::builder()
+                    .projection(doc! { "warmed": 1 })
+                    .build(),
+            )
+            .await?;
+        Ok(event
+            .and_then(|e| e.get_i64("warmed").ok())
+            .map(|w| w as u64))
+    }
+
+    /// Every event's id and moderation log (as stored), for the events that have one.
+    pub(super) async fn moderation_logs(
+        &self,
+    ) -> Result<Vec<(Uuid, String)>, mongodb::error::Error> {
+        let events: Vec<_> = self
+            .collection("events")
+            .find(
+                doc! { "moderation": { "$exists": true } },
+                FindOptions::builder()
+                    .projection(doc! { "moderation": 1 })
+                    .build(),
+            )
+            .await?
+            .try_collect()
+            .await?;
+        Ok(events
+            .into_iter()
+            .filter_map(|e| {
+                let eid = Uuid::parse_str(e.get_str("_id").ok()?).ok()?;
+                Some((eid, e.get_str("moderation").ok()?.to_string()))
+            })
+            .collect())
+    }
+
+    /// Every event's id, and its retention override if it has one.
+    pub(super) async fn all_events(
+        &self,
+    ) -> Result<Vec<(Uuid, Option<String>)>, mongodb::error::Error> {
+        let events: Vec<_> = self
+            .collection("events")
+            .find(
+                None,
+                FindOptions::builder()
+                    .projection(doc! { "retention": 1 })
+                    .build(),
+            )
+            .await?
+            .try_collect()
+            .await?;
+        Ok(events
+            .into_iter()
+            .filter_map(|e| {
+                let eid = Uuid::parse_str(e.get_str("_id").ok()?).ok()?;
+                Some((eid, e.get_str("retention").ok().map(String::from)))
+            })
+            .collect())
+    }
+
+    pub(super) async fn delete_question(&self, qid: &Uuid) -> Result<(), mongodb::error::Error> {
+        let deleted = self
+            .collection("questions")
+            .delete_one(doc! { "_id": qid.to_string() }, None)
+            .await?;
+        if deleted.deleted_count == 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "deleting non-existing question {qid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stop the event and everything in it from expiring, or have it expire at `expire` again.
+    pub(super) async fn keep(
+        &self,
+        eid: &Uuid,
+        expire: Option<&Expiry>,
+    ) -> Result<(), mongodb::error::Error> {
+        let at = |secs: u64| DateTime::from_millis(secs as i64 * 1000);
+        let (event, questions) = match expire {
+            Some(e) => (
+                doc! { "$set": { "expire": at(e.event) } },
+                doc! { "$set": { "expire": at(e.questions) } },
+            ),
+            None => (
+                doc! { "$unset": { "expire": "" } },
+                doc! { "$unset": { "expire": "" } },
+            ),
+        };
+        let updated = self
+            .collection("events")
+            .update_one(doc! { "_id": eid.to_string() }, event.clone(), None)
+            .await?;
+        if updated.matched_count == 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "holding non-existing event {eid}"
+            )));
+        }
+        self.collection("questions")
+            .update_many(doc! { "eid": eid.to_string() }, questions, None)
+            .await?;
+        // the change log lives as long as the event does
+        self.collection("changes")
+            .update_many(doc! { "eid": eid.to_string() }, event, None)
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn event<E>(&self, eid: &Uuid) -> Result<GetItemOutput, SdkError<E>> {
+        let event = self
+            .collection("events")
+            .find_one(
+                doc! { "_id": eid.to_string() },
+                FindOneOptions::builder()
+                    .projection(doc! { "_id": 1 })
+                    .build(),
+            )
+            .await
+            .map_err(failed)?;
+        Ok(GetItemOutput::builder()
+            .set_item(event.map(to_item))
+            .build())
+    }
+
+    pub(super) async fn list<E>(
+        &self,
+        eid: &Uuid,
+        has_secret: bool,
+    ) -> Result<QueryOutput, SdkError<E>> {
+        let mut filter = doc! { "eid": eid.to_string() };
+        if !has_secret {
+            filter.insert("hidden", false);
+        }
+        let items: Vec<_> = self
+            .collection("questions")
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "votes": -1 })
+                    .projection(doc! {
+                        "eid": 1,
+                        "votes": 1,
+                        "hidden": 1,
+                        "answered": 1,
+                        "voters": 1,
+                        "when": 1,
+                        "author": 1,
+                        "merged_into": 1,
+                        "reactions": 1,
+                    })
+                    .build(),
+            )
+            .await
+            .map_err(failed)?
+            .map_ok(to_item)
+            .try_collect()
+            .await
+            .map_err(failed)?;
+        Ok(QueryOutput::builder()
+            .set_count(Some(items.len() as i32))
+            .set_items(Some(items))
+            .build())
+    }
+
+    pub(super) async fn questions(
+        &self,
+        qids: &[Uuid],
+    ) -> Result<BatchGetItemOutput, mongodb::error::Error> {
+        let qids: Vec<_> = qids.iter().map(|qid| qid.to_string()).collect();
+        let items: Vec<_> = self
+            .collection("questions")
+            .find(
+                doc! { "_id": { "$in": qids } },
+                FindOptions::builder()
+                    .projection(doc! {
+                        "eid": 1,
+                        "text": 1,
+                        "when": 1,
+                        "who": 1,
+                        "frozen": 1,
+                        "hidden": 1,
+                        "reason": 1,
+                        "revises": 1,
+                        "revised": 1,
+                        "author": 1,
+                        "history": 1,
+                        "source": 1,
+                        "merged_into": 1,
+                    })
+                    .build(),
+            )
+            .await?
+            .map_ok(to_item)
+            .try_collect()
+            .await?;
+        Ok(BatchGetItemOutput::builder()
+            .set_responses(Some(HashMap::from_iter([(
+                String::from("questions"),
+                items,
+            )])))
+            .build())
+    }
+
+    /// The answer published to `qid`, if there is one.
+    pub(super) async fn published(
+        &self,
+        qid: &Uuid,
+    ) -> Result<Option<String>, mongodb::error::Error> {
+        let q = self
+            .collection("questions")
+            .find_one(
+                doc! { "_id": qid.to_string() },
+                FindOneOptions::builder()
+                    .projection(doc! { "answer": 1 })
+                    .build(),
+            )
+            .await?;
+        Ok(q.and_then(|q| q.get_str("answer").ok().map(String::from)))
+    }
+
+    /// Replace the voter sketch of `qid` with `new`, unless it's no longer `old`.
+    pub(super) async fn set_voters(
+        &self,
+        qid: &Uuid,
+        old: Option<&str>,
+        new: &str,
+    ) -> Result<bool, mongodb::error::Error> {
+        let filter = match old {
+            Some(old) => doc! { "_id": qid.to_string(), "voters": old },
+            None => doc! { "_id": qid.to_string(), "voters": { "$exists": false } },
+        };
+        let updated = self
+            .collection("questions")
+            .update_one(filter, doc! { "$set": { "voters": new } }, None)
+            .await?;
+        Ok(updated.matched_count != 0)
+    }
+
+    /// Apply `update` to the question `qid`, and return the question as it is afterwards.
+    pub(super) async fn update<E>(
+        &self,
+        qid: &Uuid,
+        update: impl Into<UpdateModifications>,
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let q = self
+            .collection("questions")
+            .find_one_and_update(
+                doc! { "_id": qid.to_string() },
+                update,
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(failed)?;
+        Ok(UpdateItemOutput::builder()
+            .set_attributes(q.map(to_item))
+            .build())
+    }
+
+    /// Add one to the count of `reaction` on `qid`, if it's a question of `eid`, and return the
+    /// question as it is afterwards.
+    pub(super) async fn react<E>(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        reaction: Reaction,
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let q = self
+            .collection("questions")
+            .find_one_and_update(
+                doc! { "_id": qid.to_string(), "eid": eid.to_string() },
+                doc! { "$inc": { format!("reactions.{}", reaction.emoji()): 1_i64 } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(failed)?;
+        Ok(UpdateItemOutput::builder()
+            .set_attributes(q.map(to_item))
+            .build())
+    }
+
+    /// Append `change` to the log of `eid`, and return its sequence number.
+    pub(super) async fn record(
+        &self,
+        eid: &Uuid,
+        change: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<u64, mongodb::error::Error> {
+        let event = self
+            .collection("events")
+            .find_one_and_update(
+                doc! { "_id": eid.to_string() },
+                doc! { "$inc": { "seq": 1_i64 } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .projection(doc! { "seq": 1 })
+                    .build(),
+            )
+            .await?;
+        let Some(seq) = event.and_then(|e| e.get_i64("seq").ok()) else {
+            return Err(mongodb::error::Error::custom(format!(
+                "recording change for non-existing event {eid}"
+            )));
+        };
+
+        let mut doc = to_document(change);
+        doc.insert("eid", eid.to_string());
+        doc.insert("seq", seq);
+        self.collection("changes").insert_one(doc, None).await?;
+        Ok(seq as u64)
+    }
+
+    pub(super) async fn changes<E>(
+        &self,
+        eid: &Uuid,
+        since: u64,
+        limit: usize,
+    ) -> Result<QueryOutput, SdkError<E>> {
+        let items: Vec<_> = self
+            .collection("changes")
+            .find(
+                doc! { "eid": eid.to_string(), "seq": { "$gt": since as i64 } },
+                FindOptions::builder()
+                    .projection(doc! { "_id": 0 })
+                    .sort(doc! { "seq": 1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(failed)?
+            .map_ok(to_item)
+            .try_collect()
+            .await
+            .map_err(failed)?;
+        Ok(QueryOutput::builder()
+            .set_count(Some(items.len() as i32))
+            .set_items(Some(items))
+            .build())
+    }
+
+    #[cfg(test)]
+    pub(super) async fn delete(&self, eid: &Uuid) {
+        let eid = eid.to_string();
+        for table in ["questions", "changes"] {
+            self.collection(table)
+                .delete_many(doc! { "eid": &eid }, None)
+                .await
+                .unwrap();
+        }
+        self.collection("events")
+            .delete_one(doc! { "_id": &eid }, None)
+            .await
+            .unwrap();
+    }
+}
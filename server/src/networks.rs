@@ -0,0 +1,485 @@
+//! Keeping events to the networks they're meant for.
+//!
+//! Some internal events may only be seen from the office network or the VPN. The host can say so
+//! with `POST /api/event/:eid/networks/:secret` and `{"allow": ["10.0.0.0/8", "192.0.2.7"]}` (up
+//! to `MAX_NETWORKS` addresses or CIDR ranges), and `{"allow": []}` lets everyone back in. Which
+//! networks are allowed is kept in the event's [moderation log](super::moderation), so it comes
+//! along with the event and changes to it are on record.
+//!
+//! Requests for the event that don't carry its secret (so everything attendees do with it) are
+//! then checked against the list before they get to the handler, and requests from anywhere else
+//! are refused with a 403 and `{"error": "network_not_allowed"}`. Requests whose address isn't
+//! known are refused too. The address is the one API Gateway saw as a Lambda, and the one the
+//! connection came from otherwise, so a server run on its own behind a proxy sees only the
+//! proxy's. Hosts can always reach their event with the secret, from anywhere, and so can
+//! operators.
+//!
+//! Routes that name a question rather than its event (votes, permalinks, and question share pages)
+//! are checked against the list of the question's event, and reactions name their event anyway.
+//! Question texts are asked for many at a time, so rather than refusing the whole request, the
+//! texts of questions whose events the request can't reach are left out of the answer, and
+//! answers with any questions of such events in them are only cached by the client. There's no
+//! restricting by country, since that would take a GeoIP database the server doesn't have.
+//!
+//! Each instance keeps the lists of the events it's seen for up to `FRESH`, so that polling the
+//! question list doesn't have to read the event every time, which means a change to the list can
+//! take that long to reach every instance.
+
+use super::{
+    moderation::{self, Action, Kind, Role},
+    Backend,
+};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, Path, State},
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use http::{request::Parts, Extensions, StatusCode};
+use ipnet::IpNet;
+use lambda_http::request::RequestContext;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most networks an event can be kept to.
+const MAX_NETWORKS: usize = 32;
+/// How long an instance goes by the networks it last read for an event.
+const FRESH: Duration = Duration::from_secs(10);
+/// Forget about the networks of events that haven't been checked in a while once there are this
+/// many.
+const MAX_EVENTS: usize = 4096;
+/// How many questions to remember the event of.
+const MAX_QUESTIONS: usize = 16384;
+
+/// The networks in `allow`, if they're all addresses or CIDR ranges.
+fn parse(allow: &[String]) -> Result<Vec<IpNet>, String> {
+    allow
+        .iter()
+        .map(|net| {
+            let net = net.trim();
+            net.parse::<IpNet>()
+                .map(|net| net.trunc())
+                .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("{net:?} is not an address or CIDR range"))
+        })
+        .collect()
+}
+
+/// The networks the event is kept to according to its moderation `log`, if it's restricted.
+fn allowed(log: &[Action]) -> Option<Vec<IpNet>> {
+    let action = moderation::restricted(log)?;
+    Some(
+        action
+            .note
+            .split(',')
+            .filter_map(|net| {
+                net.parse()
+                    .map_err(|_| error!(net, "found malformed network in moderation log"))
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Whether `client` may reach an event kept to `allowed`.
+fn permits(allowed: &[IpNet], client: Option<IpAddr>) -> bool {
+    let Some(client) = client else {
+        return false;
+    };
+    // IPv4 clients of dual-stack listeners show up as IPv6
+    let client = match client {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(client, IpAddr::V4),
+        v4 => v4,
+    };
+    allowed.iter().any(|net| net.contains(&client))
+}
+
+type Networks = Option<Arc<[IpNet]>>;
+
+static CHECKED: Mutex<BTreeMap<Uuid, (Instant, Networks)>> = Mutex::new(BTreeMap::new());
+
+/// The networks `eid` is kept to, if any, as of at most `FRESH` ago.
+async fn networks(dynamo: &Backend, eid: &Uuid) -> Result<Networks, StatusCode> {
+    if let Some((at, networks)) = CHECKED.lock().unwrap().get(eid) {
+        if at.elapsed() < FRESH {
+            return Ok(networks.clone());
+        }
+    }
+    let meta = super::get_meta(dynamo, eid).await?;
+    let networks: Networks = allowed(&meta.moderation).map(Arc::from);
+    let mut checked = CHECKED.lock().unwrap();
+    if checked.len() >= MAX_EVENTS {
+        checked.retain(|_, (at, _)| at.elapsed() < FRESH);
+    }
+    checked.insert(*eid, (Instant::now(), networks.clone()));
+    Ok(networks)
+}
+
+/// Whether a request can reach an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Reach {
+    /// The event isn't kept to any networks.
+    Open,
+    /// The event is kept to networks, and the request came from one of them.
+    Allowed,
+    /// The event is kept to networks the request didn't come from.
+    Refused,
+}
+
+/// Whether a request from `client` can reach `eid`.
+pub(super) async fn reach(
+    dynamo: &Backend,
+    eid: &Uuid,
+    client: Option<IpAddr>,
+) -> Result<Reach, StatusCode> {
+    match networks(dynamo, eid).await {
+        Ok(Some(allowed)) if permits(&allowed, client) => Ok(Reach::Allowed),
+        Ok(Some(_)) => Ok(Reach::Refused),
+        // the handler will have the same to say about events that don't exist
+        Ok(None) | Err(StatusCode::NOT_FOUND) => Ok(Reach::Open),
+        Err(status) => Err(status),
+    }
+}
+
+static OWNERS: Mutex<BTreeMap<Uuid, Uuid>> = Mutex::new(BTreeMap::new());
+
+/// The event question `qid` was asked in, if it exists.
+///
+/// Questions never move between events, so once known, that's remembered.
+async fn event_of(dynamo: &Backend, qid: &Uuid) -> Result<Option<Uuid>, StatusCode> {
+    if let Some(eid) = OWNERS.lock().unwrap().get(qid) {
+        return Ok(Some(*eid));
+    }
+    let q = super::permalink::lookup(dynamo, qid).await.map_err(|e| {
+        error!(%qid, error = %e, "dynamodb request for question's event failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(eid) = q
+        .as_ref()
+        .and_then(|q| q.get("eid"))
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok())
+    else {
+        return Ok(None);
+    };
+    let mut owners = OWNERS.lock().unwrap();
+    if owners.len() >= MAX_QUESTIONS {
+        owners.pop_first();
+    }
+    owners.insert(*qid, eid);
+    Ok(Some(eid))
+}
+
+/// Where the request came from, as far as the server can tell.
+pub(super) fn client<B>(req: &Request<B>) -> Option<IpAddr> {
+    client_of(req.extensions())
+}
+
+fn client_of(extensions: &Extensions) -> Option<IpAddr> {
+    if let Some(ctx) = extensions.get::<RequestContext>() {
+        let RequestContext::ApiGatewayV2(ctx) = ctx;
+        return ctx.http.source_ip.as_deref()?.parse().ok();
+    }
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Where the request came from, for handlers that want to know.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Client(pub(super) Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Client
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Client(client_of(&parts.extensions)))
+    }
+}
+
+/// Refuse requests for events that are kept to networks the request didn't come from.
+///
+/// Only requests that name an event or a question and don't carry a secret are checked, and
+/// operators' requests never are.
+pub(super) async fn enforce<B>(
+    State(dynamo): State<Backend>,
+    route: MatchedPath,
+    params: Option<Path<HashMap<String, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(Path(params)) = params
+        .filter(|Path(params)| !params.contains_key("secret"))
+        .filter(|_| !route.as_str().starts_with("/api/admin/"))
+    else {
+        return next.run(req).await;
+    };
+    let id = |param: &str| params.get(param).and_then(|v| Uuid::parse_str(v).ok());
+    let eid = if let Some(eid) = id("eid") {
+        eid
+    } else if route.as_str().ends_with("/react/:emoji") {
+        // see the comment on the route in super::api
+        let Some(eid) = id("qid") else {
+            return next.run(req).await;
+        };
+        eid
+    } else if let Some(qid) = id("qid") {
+        match event_of(&dynamo, &qid).await {
+            Ok(Some(eid)) => eid,
+            // the handler will have the same to say about questions that don't exist
+            Ok(None) => return next.run(req).await,
+            Err(status) => return status.into_response(),
+        }
+    } else {
+        return next.run(req).await;
+    };
+    let client = client(&req);
+    match reach(&dynamo, &eid, client).await {
+        Ok(Reach::Open | Reach::Allowed) => next.run(req).await,
+        Ok(Reach::Refused) => {
+            warn!(%eid, ?client, route = route.as_str(), "request from outside event's networks");
+            super::metrics::incr("networks.refused");
+            (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({ "error": "network_not_allowed" })),
+            )
+                .into_response()
+        }
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Which networks the host wants to keep the event to, or none to let everyone in.
+#[derive(Debug, Deserialize)]
+pub(super) struct Allow {
+    allow: Vec<String>,
+}
+
+/// Keep the event to some networks, or open it up again, for its host.
+pub(super) async fn restrict(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Allow>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let meta = super::check_secret(&dynamo, &eid, &secret)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    if req.allow.len() > MAX_NETWORKS {
+        warn!(%eid, n = req.allow.len(), "too many networks");
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+    let allow = parse(&req.allow).map_err(|e| {
+        warn!(%eid, error = e, "bad networks");
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "bad_network", "message": e })),
+        )
+            .into_response()
+    })?;
+    let (kind, note) = if allow.is_empty() {
+        (Kind::Unrestricted, String::new())
+    } else {
+        let allow: Vec<_> = allow.iter().map(IpNet::to_string).collect();
+        (Kind::Restricted, allow.join(","))
+    };
+    let (log, changed) = moderation::amend(&dynamo, &eid, meta, "networks", |log| {
+        let unchanged = match moderation::restricted(log) {
+            Some(action) => action.kind == kind && action.note == note,
+            None => kind == Kind::Unrestricted,
+        };
+        if unchanged {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: super::clock::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            kind,
+            note: note.clone(),
+            by: Some(Role::Host),
+        });
+        Ok(true)
+    })
+    .await
+    .map_err(IntoResponse::into_response)?;
+    if !changed {
+        return Ok(Json(moderation::status(&log)));
+    }
+    CHECKED.lock().unwrap().remove(&eid);
+    info!(%eid, ?kind, n = allow.len(), "host changed event's networks");
+    Ok(Json(moderation::status(&log)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, response::AppendHeaders, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn parses() {
+        let nets = parse(&[
+            "10.1.2.3/8".into(),
+            " 192.0.2.7 ".into(),
+            "2001:db8::/32".into(),
+        ])
+        .unwrap();
+        assert_eq!(
+            nets.iter().map(IpNet::to_string).collect::<Vec<_>>(),
+            ["10.0.0.0/8", "192.0.2.7/32", "2001:db8::/32"]
+        );
+        assert!(parse(&["10.0.0.0/33".into()]).is_err());
+        assert!(parse(&["office".into()]).is_err());
+
+        assert!(permits(&nets, Some("10.9.8.7".parse().unwrap())));
+        assert!(permits(&nets, Some("::ffff:192.0.2.7".parse().unwrap())));
+        assert!(!permits(&nets, Some("192.0.2.8".parse().unwrap())));
+        assert!(!permits(&nets, None));
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "hello world".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let app = Router::new()
+            .route("/api/event/:eid", get(|| async {}))
+            .route("/api/event/:eid/:secret", get(|| async {}))
+            .route("/api/q/:qid", get(|| async {}))
+            .route("/api/vote/:qid/:updown/react/:emoji", get(|| async {}))
+            .route_layer(axum::middleware::from_fn_with_state(
+                backend.clone(),
+                enforce,
+            ))
+            .with_state(backend.clone());
+        let from = |path: &str, addr: &str| {
+            let mut req = Request::get(path).body(Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(addr.parse().unwrap(), 4711)));
+            app.clone().oneshot(req)
+        };
+        let guest = format!("/api/event/{eid}");
+        let host = format!("/api/event/{eid}/{secret}");
+        let linked = format!("/api/q/{qid}");
+        let react = format!("/api/vote/{eid}/{qid}/react/%F0%9F%91%8D");
+        let texts = |addr: &str| {
+            crate::questions::questions(
+                Path(qid.to_string()),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Client(Some(addr.parse().unwrap())),
+            )
+        };
+        assert_eq!(
+            from(&guest, "198.51.100.1").await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        let allow = |allow: &[&str]| {
+            restrict(
+                Path((eid, secret.clone())),
+                State(backend.clone()),
+                Json(Allow {
+                    allow: allow.iter().map(|s| s.to_string()).collect(),
+                }),
+            )
+        };
+        let Json(status) = allow(&["192.0.2.0/24"]).await.unwrap();
+        assert_eq!(status["networks"], serde_json::json!(["192.0.2.0/24"]));
+        let res = from(&guest, "198.51.100.1").await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"error":"network_not_allowed"}"#);
+        assert_eq!(
+            from(&guest, "192.0.2.99").await.unwrap().status(),
+            StatusCode::OK
+        );
+        // requests that only name a question are checked against its event's networks
+        for path in [&linked, &react] {
+            let status = from(path, "198.51.100.1").await.unwrap().status();
+            assert_eq!(status, StatusCode::FORBIDDEN, "{path}");
+            let status = from(path, "192.0.2.99").await.unwrap().status();
+            assert_eq!(status, StatusCode::OK, "{path}");
+        }
+        // and texts are left out rather than refused, and kept from shared caches
+        let (AppendHeaders([(_, cache)]), _, res) = texts("198.51.100.1").await;
+        assert_eq!(cache, "private, max-age=60");
+        assert_eq!(res.unwrap().0, serde_json::json!({}));
+        let (AppendHeaders([(_, cache)]), _, res) = texts("192.0.2.99").await;
+        assert_eq!(cache, "private, max-age=60");
+        assert_eq!(res.unwrap().0[qid.to_string()]["text"], "hello world");
+        // hosts get in from anywhere
+        assert_eq!(
+            from(&host, "198.51.100.1").await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        let res = allow(&["not a network"]).await.unwrap_err();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let Json(status) = allow(&[]).await.unwrap();
+        assert_eq!(status["networks"], serde_json::Value::Null);
+        assert_eq!(
+            from(&guest, "198.51.100.1").await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Backend, Local};
+use super::{changes::Change, Backend, Local};
 use aws_sdk_dynamodb::{
     error::PutItemError, model::AttributeValue, output::PutItemOutput, types::SdkError,
 };
@@ -7,71 +7,91 @@ use axum::response::Json;
 use http::StatusCode;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde::Deserialize;
 use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
-const EVENTS_EXPIRE_AFTER_DAYS: u64 = 60;
+pub(super) const EVENTS_EXPIRE_AFTER_DAYS: u64 = 60;
 
 impl Backend {
+    #[allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
     pub(super) async fn new(
         &self,
         eid: &Uuid,
         secret: impl Into<String>,
+        tos: Option<&super::tos::Acceptance>,
     ) -> Result<PutItemOutput, SdkError<PutItemError>> {
+        let secret = secret.into();
+        let tos = tos.map(super::tos::store);
+        let mut attrs = vec![
+            ("id", AttributeValue::S(eid.to_string())),
+            ("secret", AttributeValue::S(secret.clone())),
+            (
+                "when",
+                AttributeValue::N(
+                    super::clock::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs()
+                        .to_string(),
+                ),
+            ),
+            (
+                "expire",
+                AttributeValue::N(
+                    (super::clock::now()
+                        + Duration::from_secs(EVENTS_EXPIRE_AFTER_DAYS * 24 * 60 * 60))
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+                    .to_string(),
+                ),
+            ),
+        ];
+        if let Some(tos) = &tos {
+            attrs.push(("tos", AttributeValue::S(tos.clone())));
+        }
         match self {
             Self::Dynamo(dynamo) => {
-                dynamo
-                    .put_item()
-                    .table_name("events")
-                    .item("id", AttributeValue::S(eid.to_string()))
-                    .item("secret", AttributeValue::S(secret.into()))
-                    .item(
-                        "when",
-                        AttributeValue::N(
-                            SystemTime::now()
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs()
-                                .to_string(),
-                        ),
-                    )
-                    .item(
-                        "expire",
-                        AttributeValue::N(
-                            (SystemTime::now()
-                                + Duration::from_secs(EVENTS_EXPIRE_AFTER_DAYS * 24 * 60 * 60))
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs()
-                            .to_string(),
-                        ),
-                    )
-                    .send()
-                    .await
+                let mut r = dynamo.put_item().table_name("events");
+                for (k, v) in attrs {
+                    r = r.item(k, v);
+                }
+                r.send().await
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
+                let mut local = local.write().unwrap();
                 let Local {
                     events,
                     questions_by_eid,
+                    tos: accepted,
+                    journal,
                     ..
                 } = &mut *local;
 
-                questions_by_eid.insert(eid.clone(), Vec::new());
-                if events.insert(eid.clone(), secret.into()).is_some() {
-                    Ok(PutItemOutput::builder().build())
-                } else {
-                    Ok(PutItemOutput::builder().build())
+                journal.event(eid, &secret, tos.as_deref());
+                questions_by_eid.insert(*eid, Vec::new());
+                events.insert(*eid, secret);
+                if let Some(tos) = tos {
+                    accepted.insert(*eid, tos);
                 }
+                Ok(PutItemOutput::builder().build())
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.put("events", attrs).await,
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.create_event(eid, attrs).await,
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.create_event(eid, attrs).await,
         }
     }
 
     #[cfg(test)]
     pub(super) async fn delete(&self, eid: &Uuid) {
+        super::hot::forget(eid);
         let qs = self.list(eid, false).await.unwrap();
         let qids: Vec<_> = qs
             .items()
@@ -82,6 +102,21 @@ impl Backend {
 
         match self {
             Self::Dynamo(dynamo) => {
+                let log = self.changes(eid, 0).await.unwrap();
+                for seq in log
+                    .items()
+                    .into_iter()
+                    .flat_map(|cs| cs.iter().filter_map(|c| c["seq"].as_n().ok()))
+                {
+                    dynamo
+                        .delete_item()
+                        .table_name("changes")
+                        .key("eid", AttributeValue::S(eid.to_string()))
+                        .key("seq", AttributeValue::N(seq.clone()))
+                        .send()
+                        .await
+                        .unwrap();
+                }
                 for qid in qids {
                     dynamo
                         .delete_item()
@@ -100,36 +135,89 @@ impl Backend {
                     .unwrap();
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
+                let mut local = local.write().unwrap();
                 let Local {
                     events,
                     questions,
                     questions_by_eid,
+                    changes,
+                    announcements,
+                    scheduled,
+                    moderation,
+                    moderated,
+                    locked,
+                    retention,
+                    tos,
+                    warmed,
                     ..
                 } = &mut *local;
 
-                for qid in questions_by_eid.remove(&eid).unwrap() {
+                changes.remove(eid);
+                moderation.remove(eid);
+                moderated.remove(eid);
+                locked.remove(eid);
+                retention.remove(eid);
+                tos.remove(eid);
+                warmed.remove(eid);
+                announcements.remove(eid);
+                scheduled.remove(eid);
+                for qid in questions_by_eid.remove(eid).unwrap() {
                     questions.remove(&qid).unwrap();
                 }
-                events.remove(&eid).unwrap();
+                events.remove(eid).unwrap();
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.delete(eid).await,
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.delete(eid).await,
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.delete(eid),
         }
     }
 }
 
+/// A fresh secret for a new event.
+pub(super) fn secret() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(30)
+        .map(char::from)
+        .collect()
+}
+
+/// What comes with a request to create an event, all of which only matters if hosts have to accept
+/// terms of service (see [`super::tos`]).
+#[derive(Deserialize, Debug, Default)]
+pub(super) struct New {
+    /// The token of the host's browser.
+    #[serde(default)]
+    token: Option<String>,
+    /// The version of the terms of service the host has accepted.
+    #[serde(default)]
+    tos: Option<String>,
+}
+
 pub(super) async fn new(
     State(dynamo): State<Backend>,
+    req: Option<Json<New>>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
+    let req = req.map(|r| r.0).unwrap_or_default();
+    let tos = super::tos::check(
+        super::config::config().tos_version.as_deref(),
+        req.token.as_deref(),
+        req.tos.as_deref(),
+    )?;
+
     // TODO: UUIDv7
     let eid = uuid::Uuid::new_v4();
-    let secret: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(30)
-        .map(char::from)
-        .collect();
-    match dynamo.new(&eid, &secret).await {
+    let secret = secret();
+    match dynamo.new(&eid, &secret, tos.as_ref()).await {
         Ok(_) => {
             debug!(%eid, "created event");
+            if let Some(tos) = &tos {
+                info!(%eid, token = tos.token, version = tos.version, "host accepted terms of service");
+            }
+            dynamo.try_record(&eid, Change::EventCreated).await;
             Ok(Json(
                 serde_json::json!({ "id": eid.to_string(), "secret": secret }),
             ))
@@ -146,7 +234,7 @@ mod tests {
     use super::*;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
         let _secret = e["secret"].as_str().unwrap();
         backend.delete(&eid).await;
@@ -162,4 +250,24 @@ mod tests {
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
 }
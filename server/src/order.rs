@@ -0,0 +1,250 @@
+//! Letting hosts pick the order their question list is in.
+//!
+//! Listing by votes lets the loudest part of the audience decide what gets answered. Hosts who'd
+//! rather take turns (see `fair` in [`super::experiment`]), or go by some other order, can
+//! `POST /api/event/:eid/order/:secret` with `{"order": "fair"}`, and go back with
+//! `{"order": "top"}`. Lists that don't ask for an order themselves (with `?order=` or `?sort=`)
+//! are then in that one, which includes guests who aren't in an experiment. The pick is kept in
+//! the event's [moderation log](super::moderation), and hosts listening with the secret are told
+//! with `{"kind": "ordered", "order": ..}`.
+//!
+//! Like [locking](super::lock), picking an order doesn't change the event's version, so attendees
+//! who already have the list at that version keep the order they have until the next vote or
+//! question comes in. Other instances that have the event [hot](super::hot) keep serving the order
+//! they had for up to `HOT_REFRESH_MS`.
+
+use super::{
+    experiment::Ordering,
+    moderation::{self, Action, Kind, Role},
+    Backend,
+};
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The order the event's question list is in unless asked for another, according to its
+/// moderation `log`.
+pub(super) fn picked(log: &[Action]) -> Ordering {
+    moderation::ordered(log)
+        .and_then(|action| {
+            action
+                .note
+                .parse()
+                .map_err(|e: String| error!(error = e, "found malformed picked order"))
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// The order the host wants the question list in.
+#[derive(Debug, Deserialize)]
+pub(super) struct Pick {
+    order: Ordering,
+}
+
+/// Pick the order of the question list, for the event's host.
+pub(super) async fn order(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Pick>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    let (log, changed) = moderation::amend(&dynamo, &eid, meta, "order", |log| {
+        if picked(log) == req.order {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: super::clock::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            kind: Kind::Ordered,
+            note: req.order.to_string(),
+            by: Some(Role::Host),
+        });
+        Ok(true)
+    })
+    .await?;
+    if !changed {
+        return Ok(Json(moderation::status(&log)));
+    }
+    super::hot::forget(&eid);
+    info!(%eid, order = %req.order, "host picked question list order");
+    super::bus::tell_hosts(
+        &eid,
+        serde_json::json!({ "kind": "ordered", "order": req.order }),
+    );
+    Ok(Json(moderation::status(&log)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::list::Order;
+    use aws_sdk_dynamodb::model::AttributeValue;
+    use axum::extract::Query;
+    use http::HeaderMap;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        // a minute apart, so that it's clear which was asked first
+        let start = SystemTime::now() - std::time::Duration::from_secs(3600);
+        let mut asked = 0;
+        let mut ask = |author: &str| {
+            let qid = Uuid::new_v4();
+            let when = start + std::time::Duration::from_secs(60 * asked);
+            asked += 1;
+            let author = ("author", AttributeValue::S(format!("{author:0<40}")));
+            let backend = backend.clone();
+            async move {
+                backend
+                    .ask_with(
+                        &eid,
+                        &qid,
+                        crate::ask::Question {
+                            body: "what's the plan?".into(),
+                            asker: None,
+                        },
+                        when,
+                        false,
+                        vec![author],
+                    )
+                    .await
+                    .unwrap();
+                backend
+                    .try_record(&eid, crate::changes::Change::QuestionAsked { qid })
+                    .await;
+                qid
+            }
+        };
+        let regular = [ask("a").await, ask("a").await, ask("a").await];
+        let newcomer = ask("b").await;
+        for (qid, votes) in [(regular[0], 1), (regular[1], 3), (regular[2], 2)] {
+            for _ in 0..votes {
+                crate::vote::vote(
+                    Path((qid, crate::vote::UpDown::Up)),
+                    State(backend.clone()),
+                    HeaderMap::new(),
+                    crate::networks::Client(None),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        let list = |order: Option<&str>| {
+            let order: Order = serde_json::from_value(match order {
+                Some(order) => serde_json::json!({ "order": order }),
+                None => serde_json::json!({}),
+            })
+            .unwrap();
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(order),
+                HeaderMap::new(),
+            )
+        };
+        let qids = |(_, _, listed): (_, _, Result<Json<serde_json::Value>, _>)| -> Vec<Uuid> {
+            let Ok(Json(qs)) = listed else {
+                panic!("listing failed");
+            };
+            qs.as_array()
+                .unwrap()
+                .iter()
+                .map(|q| Uuid::parse_str(q["qid"].as_str().unwrap()).unwrap())
+                .collect()
+        };
+        let top = [regular[1], regular[2], regular[0], newcomer];
+        assert_eq!(qids(list(None).await), top);
+
+        let pick = |order: &str| {
+            super::order(
+                Path((eid, secret.clone())),
+                State(backend.clone()),
+                Json(Pick {
+                    order: order.parse().unwrap(),
+                }),
+            )
+        };
+        let Json(status) = pick("fair").await.unwrap();
+        assert_eq!(status["order"], "fair");
+        // the most votes, the longest waiting, the newcomer's first question, and around again
+        let fair = [regular[1], regular[0], newcomer, regular[2]];
+        assert_eq!(qids(list(None).await), fair);
+        // which the list says, so that clients keep it
+        let ordered = |(_, tags, _): (_, _, _)| crate::list::tag(&tags, &crate::list::ORDER);
+        assert_eq!(ordered(list(None).await).as_deref(), Some("fair"));
+        assert_eq!(ordered(list(Some("top")).await).as_deref(), Some("top"));
+        assert_eq!(qids(list(Some("top")).await), top);
+        // hosts get the picked order too
+        let (_, _, Ok(Json(all))) = crate::list::list_all(
+            Path((eid, secret.clone())),
+            State(backend.clone()),
+            Query(serde_json::from_value(serde_json::json!({})).unwrap()),
+            HeaderMap::new(),
+        )
+        .await
+        else {
+            panic!("host listing failed");
+        };
+        assert_eq!(all[2]["qid"], newcomer.to_string());
+
+        let Json(status) = pick("top").await.unwrap();
+        assert_eq!(status["order"], "top");
+        assert_eq!(qids(list(None).await), top);
+        assert_eq!(
+            super::order(
+                Path((eid, String::from("wrong"))),
+                State(backend.clone()),
+                Json(Pick {
+                    order: Ordering::Fair
+                }),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,526 @@
+//! Provider verification of the consumer contracts in `pacts/`.
+//!
+//! Teams with clients of the API (like the frontend in `client/`) put the Pact files their
+//! consumer tests produce in `pacts/`, and [`verify`] replays every interaction in them against the
+//! router with the in-memory backend. A change that breaks what some client relies on then fails
+//! `cargo test`, rather than that client.
+//!
+//! This implements the parts of the [Pact v3 specification] that the contracts so far need:
+//!
+//! - the provider states set up by [`given`];
+//! - request methods, paths, queries, headers, and JSON or plain-text bodies;
+//! - response statuses, headers, and bodies, where objects may have keys the contract doesn't
+//!   mention, but arrays must have exactly the elements it lists;
+//! - the `type` (with `min` and `max`), `regex`, `integer`, `decimal`, `number`, `boolean`,
+//!   `include`, and `equality` body matching rules.
+//!
+//! Anything else in a contract fails verification, so that nothing is silently left unchecked.
+//!
+//! [Pact v3 specification]: https://github.com/pact-foundation/pact-specification/tree/version-3
+
+use super::{ask::Question, changes::Change, toggle::Property, Backend, Local};
+use axum::body::Body;
+use http::{Method, Request};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+struct Pact {
+    consumer: Party,
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Party {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Interaction {
+    description: String,
+    #[serde(default)]
+    provider_states: Vec<ProviderState>,
+    request: PactRequest,
+    response: PactResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderState {
+    name: String,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PactRequest {
+    method: String,
+    path: String,
+    #[serde(default)]
+    query: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    body: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PactResponse {
+    status: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    body: Option<Value>,
+    #[serde(default)]
+    matching_rules: MatchingRules,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MatchingRules {
+    #[serde(default)]
+    body: HashMap<String, Rule>,
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    matchers: Vec<Matcher>,
+    #[serde(default)]
+    combine: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Matcher {
+    #[serde(rename = "match")]
+    kind: String,
+    regex: Option<String>,
+    value: Option<String>,
+    min: Option<usize>,
+    max: Option<usize>,
+}
+
+/// Set up the provider state `state` describes in `backend`.
+///
+/// The states are:
+///
+/// - `an event exists`, with an `eid` and `secret`;
+/// - `a question exists`, with the `eid` and `secret` of the (new or existing) event it's in, its
+///   `qid`, its `body`, and optionally an `asker` and whether it's `hidden` or `answered`.
+async fn given(backend: &Backend, state: &ProviderState) -> Result<(), String> {
+    let param = |name: &str| {
+        state
+            .params
+            .get(name)
+            .ok_or_else(|| format!("provider state {:?} needs {name:?}", state.name))
+    };
+    let string = |name: &str| {
+        param(name)?
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| format!("{name:?} of provider state {:?} isn't a string", state.name))
+    };
+    let uuid = |name: &str| {
+        Uuid::parse_str(&string(name)?)
+            .map_err(|e| format!("{name:?} of provider state {:?}: {e}", state.name))
+    };
+    let flag = |name: &str| state.params.get(name).and_then(Value::as_bool) == Some(true);
+    let failed = |e: &dyn std::fmt::Display| format!("provider state {:?}: {e}", state.name);
+
+    if !matches!(&*state.name, "an event exists" | "a question exists") {
+        return Err(format!("unknown provider state {:?}", state.name));
+    }
+    let eid = uuid("eid")?;
+    // the hot copy is global, and would be for a different backend
+    super::hot::forget(&eid);
+    if super::get_meta(backend, &eid).await.is_err() {
+        backend
+            .new(&eid, string("secret")?, None)
+            .await
+            .map_err(|e| failed(&e))?;
+        backend.try_record(&eid, Change::EventCreated).await;
+    }
+    match &*state.name {
+        "an event exists" => Ok(()),
+        "a question exists" => {
+            let qid = uuid("qid")?;
+            let question = Question {
+                body: string("body")?,
+                asker: state
+                    .params
+                    .contains_key("asker")
+                    .then(|| string("asker"))
+                    .transpose()?,
+            };
+            backend
+                .ask_at(&eid, &qid, question, crate::clock::now(), false)
+                .await
+                .map_err(|e| failed(&e))?;
+            backend
+                .try_record(&eid, Change::QuestionAsked { qid })
+                .await;
+            for (property, name) in [
+                (Property::Hidden, "hidden"),
+                (Property::Answered, "answered"),
+            ] {
+                if flag(name) {
+                    backend
+                        .toggle(&qid, property, true)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                }
+            }
+            Ok(())
+        }
+        _ => unreachable!("checked above"),
+    }
+}
+
+/// The path of a value in a response body, like `$`, `$.changes`, or `$[0].qid`.
+type Path = Vec<String>;
+
+fn display(path: &[String]) -> String {
+    path.iter()
+        .map(|p| {
+            if p == "$" {
+                p.clone()
+            } else if p.parse::<usize>().is_ok() {
+                format!("[{p}]")
+            } else {
+                format!(".{p}")
+            }
+        })
+        .collect()
+}
+
+/// Split a matching rule path like `$.changes[*].seq` or `$['a b']` into its parts.
+fn parse_path(pattern: &str) -> Path {
+    let mut parts = vec![String::from("$")];
+    let mut rest = pattern.strip_prefix('$').unwrap_or(pattern);
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']').unwrap_or(r.len());
+            parts.push(r[..end].trim_matches('\'').to_string());
+            rest = r.get(end + 1..).unwrap_or("");
+        } else {
+            let r = rest.strip_prefix('.').unwrap_or(rest);
+            let end = r.find(['.', '[']).unwrap_or(r.len());
+            parts.push(r[..end].to_string());
+            rest = &r[end..];
+        }
+    }
+    parts
+}
+
+struct Rules(Vec<(Path, Rule)>);
+
+impl Rules {
+    fn new(rules: HashMap<String, Rule>) -> Self {
+        Rules(
+            rules
+                .into_iter()
+                .map(|(p, r)| (parse_path(&p), r))
+                .collect(),
+        )
+    }
+
+    fn at(&self, path: &[String]) -> Option<&Rule> {
+        self.0
+            .iter()
+            .find(|(p, _)| {
+                p.len() == path.len() && p.iter().zip(path).all(|(p, a)| p == "*" || p == a)
+            })
+            .map(|(_, r)| r)
+    }
+}
+
+fn same_type(expected: &Value, actual: &Value) -> bool {
+    matches!(
+        (expected, actual),
+        (Value::Null, Value::Null)
+            | (Value::Bool(_), Value::Bool(_))
+            | (Value::Number(_), Value::Number(_))
+            | (Value::String(_), Value::String(_))
+            | (Value::Array(_), Value::Array(_))
+            | (Value::Object(_), Value::Object(_))
+    )
+}
+
+fn compare(expected: &Value, actual: &Value, path: &mut Path, rules: &Rules) -> Vec<String> {
+    let here = display(path);
+    let Some(rule) = rules.at(path) else {
+        return compare_exactly(expected, actual, path, rules);
+    };
+    if rule.combine.as_deref().is_some_and(|c| c != "AND") {
+        return vec![format!(
+            "{here}: only AND is supported for combining matchers"
+        )];
+    }
+
+    let mut errors = Vec::new();
+    let mut checked_children = false;
+    for m in &rule.matchers {
+        let ok = match &*m.kind {
+            "type" => {
+                if !same_type(expected, actual) {
+                    false
+                } else if let (Value::Array(e), Value::Array(a)) = (expected, actual) {
+                    // every element is like the first one in the contract
+                    if m.min.is_some_and(|min| a.len() < min)
+                        || m.max.is_some_and(|max| a.len() > max)
+                    {
+                        errors.push(format!("{here}: {} elements is out of bounds", a.len()));
+                    }
+                    if let Some(e) = e.first() {
+                        for (i, a) in a.iter().enumerate() {
+                            path.push(i.to_string());
+                            errors.extend(compare(e, a, path, rules));
+                            path.pop();
+                        }
+                    }
+                    checked_children = true;
+                    true
+                } else if expected.is_object() {
+                    errors.extend(compare_exactly(expected, actual, path, rules));
+                    checked_children = true;
+                    true
+                } else {
+                    true
+                }
+            }
+            "regex" => {
+                let Some(re) = m.regex.as_deref() else {
+                    errors.push(format!("{here}: regex matcher without a regex"));
+                    continue;
+                };
+                let Ok(re) = Regex::new(&format!("^(?:{re})$")) else {
+                    errors.push(format!("{here}: invalid regex {re:?}"));
+                    continue;
+                };
+                match actual {
+                    Value::String(s) => re.is_match(s),
+                    Value::Number(n) => re.is_match(&n.to_string()),
+                    _ => false,
+                }
+            }
+            "integer" => actual.is_i64() || actual.is_u64(),
+            "decimal" => actual.is_f64(),
+            "number" => actual.is_number(),
+            "boolean" => actual.is_boolean(),
+            "include" => actual
+                .as_str()
+                .zip(m.value.as_deref())
+                .is_some_and(|(a, v)| a.contains(v)),
+            "equality" => {
+                errors.extend(compare_exactly(expected, actual, path, rules));
+                checked_children = true;
+                true
+            }
+            kind => {
+                errors.push(format!("{here}: unsupported matcher {kind:?}"));
+                continue;
+            }
+        };
+        if !ok {
+            errors.push(format!(
+                "{here}: {actual} doesn't match {:?} like {expected}",
+                m.kind
+            ));
+        }
+    }
+    if !checked_children && (expected.is_array() || expected.is_object()) {
+        errors.extend(compare_exactly(expected, actual, path, rules));
+    }
+    errors
+}
+
+fn compare_exactly(
+    expected: &Value,
+    actual: &Value,
+    path: &mut Path,
+    rules: &Rules,
+) -> Vec<String> {
+    let here = display(path);
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            let mut errors = Vec::new();
+            for (k, e) in e {
+                path.push(k.clone());
+                match a.get(k) {
+                    Some(a) => errors.extend(compare(e, a, path, rules)),
+                    None => errors.push(format!("{}: missing", display(path))),
+                }
+                path.pop();
+            }
+            errors
+        }
+        (Value::Array(e), Value::Array(a)) if e.len() == a.len() => {
+            let mut errors = Vec::new();
+            for (i, (e, a)) in e.iter().zip(a).enumerate() {
+                path.push(i.to_string());
+                errors.extend(compare(e, a, path, rules));
+                path.pop();
+            }
+            errors
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            vec![format!(
+                "{here}: expected {} elements, got {}",
+                e.len(),
+                a.len()
+            )]
+        }
+        (e, a) if e == a => Vec::new(),
+        (e, a) => vec![format!("{here}: expected {e}, got {a}")],
+    }
+}
+
+/// Check `interaction` against a fresh backend, and return everything that's wrong.
+async fn check(interaction: Interaction) -> Vec<String> {
+    let backend = Backend::Local(Arc::new(RwLock::new(Local::default())));
+    for state in &interaction.provider_states {
+        if let Err(e) = given(&backend, state).await {
+            return vec![e];
+        }
+    }
+    let PactRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    } = interaction.request;
+    let mut errors = Vec::new();
+
+    let mut uri = path;
+    let mut query: Vec<_> = query
+        .iter()
+        .flat_map(|(k, vs)| vs.iter().map(move |v| format!("{k}={v}")))
+        .collect();
+    query.sort();
+    if !query.is_empty() {
+        uri = format!("{uri}?{}", query.join("&"));
+    }
+    let json = headers
+        .iter()
+        .any(|(k, v)| k.eq_ignore_ascii_case("content-type") && v.contains("json"));
+    let body = match body {
+        None => Body::empty(),
+        Some(Value::String(s)) if !json => Body::from(s),
+        Some(v) => Body::from(v.to_string()),
+    };
+    let mut req = Request::builder()
+        .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+        .uri(&uri);
+    for (k, v) in &headers {
+        req = req.header(k, v);
+    }
+    let res = super::api()
+        .with_state(backend)
+        .oneshot(req.body(body).unwrap())
+        .await
+        .unwrap();
+
+    let expected = interaction.response;
+    if res.status() != expected.status {
+        errors.push(format!(
+            "status: expected {}, got {}",
+            expected.status,
+            res.status()
+        ));
+    }
+    for (k, v) in &expected.headers {
+        match res.headers().get(k).map(|v| v.to_str()) {
+            Some(Ok(got)) if got == v => {}
+            got => errors.push(format!("header {k}: expected {v:?}, got {got:?}")),
+        }
+    }
+    if let Some(kind) = expected.matching_rules.other.keys().next() {
+        errors.push(format!("unsupported {kind} matching rules"));
+    }
+
+    let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    if let Some(e) = &expected.body {
+        let got = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+        let rules = Rules::new(expected.matching_rules.body);
+        errors.extend(compare(e, &got, &mut vec![String::from("$")], &rules));
+    }
+    errors
+}
+
+#[tokio::test]
+async fn verify() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("pacts");
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|f| f.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    files.sort();
+
+    let mut failures = Vec::new();
+    for file in files {
+        let pact: Pact = serde_json::from_str(&std::fs::read_to_string(&file).unwrap())
+            .unwrap_or_else(|e| panic!("{} isn't a pact file: {e}", file.display()));
+        for interaction in pact.interactions {
+            let what = format!("{} {:?}", pact.consumer.name, interaction.description);
+            for e in check(interaction).await {
+                failures.push(format!("{what}: {e}"));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "the API doesn't satisfy its consumers' contracts:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn matches() {
+    let rules = Rules::new(
+        serde_json::from_value(serde_json::json!({
+            "$.id": { "matchers": [{ "match": "regex", "regex": "[0-9a-f-]{36}" }] },
+            "$.questions": { "matchers": [{ "match": "type", "min": 1 }] },
+            "$.questions[*].votes": { "matchers": [{ "match": "integer" }] },
+        }))
+        .unwrap(),
+    );
+    let expected = serde_json::json!({
+        "id": "00000000-0000-0000-0000-000000000000",
+        "questions": [{ "votes": 1, "hidden": false }],
+    });
+    let check = |actual: Value| compare(&expected, &actual, &mut vec![String::from("$")], &rules);
+
+    let ok = serde_json::json!({
+        "id": Uuid::new_v4().to_string(),
+        "questions": [{ "votes": 7, "hidden": false }, { "votes": 3, "hidden": false }],
+        "extra": true,
+    });
+    assert_eq!(check(ok), Vec::<String>::new());
+
+    let bad = serde_json::json!({
+        "id": "nope",
+        "questions": [{ "votes": 1.5, "hidden": true }],
+    });
+    let errors = check(bad);
+    assert_eq!(errors.len(), 3, "{errors:?}");
+    assert!(errors[0].starts_with("$.id:"), "{errors:?}");
+
+    assert_eq!(
+        check(serde_json::json!({ "id": expected["id"], "questions": [] })),
+        ["$.questions: 0 elements is out of bounds"]
+    );
+}
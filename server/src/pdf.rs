@@ -0,0 +1,315 @@
+//! An event's questions as a PDF, for printing out in case the venue's network gives up.
+//!
+//! `GET /api/event/:eid/export.pdf` has the same questions as the plain-text view (see
+//! [`super::text`]), in the same order: unanswered ones first, each by votes, with how many votes
+//! they have and who asked them. Hosts don't keep a shortlist, notes, or assignees for questions,
+//! so there's nothing else to print.
+//!
+//! The PDF is written out here rather than with a library, since a few pages of text in one of the
+//! fonts every PDF reader has built in is a small part of the format. Those fonts only have Latin
+//! characters, so anything else is printed as `?`. It's cached like the text view.
+
+use super::{
+    text::{Line, Transcript},
+    Backend,
+};
+use axum::extract::{Path, State};
+use axum::response::AppendHeaders;
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A4, in points.
+const WIDTH: u32 = 595;
+const HEIGHT: u32 = 842;
+const MARGIN: u32 = 56;
+/// How many characters fit on a line of body text. Helvetica's characters are about half as wide
+/// as they are tall on average, and this leaves room for the wide ones.
+const COLUMNS: usize = 80;
+
+/// Something to print, in the order it's printed.
+enum Block<'a> {
+    Title(&'a str),
+    Heading(String),
+    Question(usize, &'a Line),
+    Note(&'a str),
+}
+
+/// The text a PDF string shows for `s`, in the standard fonts' (Latin-1-ish) encoding.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+/// Break `text` into lines of at most `columns` characters, at spaces where possible.
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word;
+            // words that don't fit on a line of their own are broken up
+            while word.chars().count() > columns {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let at = word
+                    .char_indices()
+                    .nth(columns)
+                    .map_or(word.len(), |(i, _)| i);
+                lines.push(word[..at].to_string());
+                word = &word[at..];
+            }
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Lay out `blocks` on pages, and give back each page's content stream.
+fn layout(blocks: &[Block<'_>]) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    let mut y = HEIGHT - MARGIN;
+    for block in blocks {
+        // (font, size, indent, lines), for each run of lines in the block
+        let runs: Vec<(&str, u32, u32, Vec<String>)> = match block {
+            Block::Title(t) => vec![("F2", 16, 0, wrap(t, COLUMNS * 11 / 16))],
+            Block::Heading(h) => vec![("F2", 13, 0, vec![h.clone()])],
+            Block::Question(n, line) => {
+                let plural = if line.votes == 1 { "" } else { "s" };
+                let about = match &line.who {
+                    Some(who) => format!("{} vote{plural}, asked by {who}", line.votes),
+                    None => format!("{} vote{plural}", line.votes),
+                };
+                let mut text = wrap(&line.text, COLUMNS - 4);
+                text[0] = format!("{n}. {}", text[0]);
+                vec![("F1", 11, 0, text), ("F1", 9, 16, wrap(&about, COLUMNS))]
+            }
+            Block::Note(n) => vec![("F1", 9, 0, wrap(n, COLUMNS))],
+        };
+        let height: u32 = runs
+            .iter()
+            .map(|(_, size, _, lines)| (size + 4) * lines.len() as u32)
+            .sum::<u32>()
+            + 8;
+        // keep blocks together, unless they wouldn't fit on a page anyway
+        if y < MARGIN + height && y != HEIGHT - MARGIN {
+            pages.push(std::mem::take(&mut page));
+            y = HEIGHT - MARGIN;
+        }
+        for (font, size, indent, lines) in runs {
+            for line in lines {
+                if y < MARGIN + size {
+                    pages.push(std::mem::take(&mut page));
+                    y = HEIGHT - MARGIN;
+                }
+                y -= size + 4;
+                let _ = writeln!(
+                    page,
+                    "BT /{font} {size} Tf {} {y} Td ({}) Tj ET",
+                    MARGIN + indent,
+                    escape(&line)
+                );
+            }
+        }
+        y = y.saturating_sub(8);
+    }
+    pages.push(page);
+    pages
+}
+
+/// Put `pages` together into a PDF file.
+fn document(pages: &[String]) -> Vec<u8> {
+    // the catalog, the page tree, and the two fonts come first, then each page and its contents
+    let mut objects = vec![
+        String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|i| format!("{} 0 R", 5 + 2 * i))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        String::from(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+        ),
+        String::from(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>",
+        ),
+    ];
+    for (i, content) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {WIDTH} {HEIGHT}] \
+             /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            6 + 2 * i
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{content}endstream",
+            content.len()
+        ));
+    }
+
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        let _ = write!(out, "{} 0 obj\n{object}\nendobj\n", i + 1);
+    }
+    let xref = out.len();
+    let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(out, "{offset:010} 00000 n ");
+    }
+    let _ = write!(
+        out,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+        objects.len() + 1
+    );
+    out.into_bytes()
+}
+
+fn render(eid: &Uuid, transcript: &Transcript) -> Vec<u8> {
+    let title = format!("Questions for event {eid}");
+    let mut blocks = vec![Block::Title(&title)];
+    let Transcript {
+        unanswered,
+        answered,
+    } = transcript;
+    if unanswered.is_empty() && answered.is_empty() {
+        blocks.push(Block::Note("No questions have been asked yet."));
+    }
+    for (heading, lines) in [("Unanswered", unanswered), ("Answered", answered)] {
+        if lines.is_empty() {
+            continue;
+        }
+        blocks.push(Block::Heading(format!("{heading} ({})", lines.len())));
+        blocks.extend(
+            lines
+                .iter()
+                .enumerate()
+                .map(|(i, line)| Block::Question(i + 1, line)),
+        );
+    }
+    document(&layout(&blocks))
+}
+
+pub(super) async fn export(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+) -> (
+    AppendHeaders<[(HeaderName, &'static str); 2]>,
+    Result<Vec<u8>, StatusCode>,
+) {
+    let pdf = |cache: &'static str| {
+        AppendHeaders([
+            (header::CACHE_CONTROL, cache),
+            (header::CONTENT_TYPE, "application/pdf"),
+        ])
+    };
+    match super::text::transcript(&dynamo, &eid).await {
+        Ok((cache, transcript)) => (pdf(cache), Ok(render(&eid, &transcript))),
+        Err((cache, status)) => (pdf(cache), Err(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::Json;
+
+    /// Where `object` starts, according to the cross-reference table.
+    fn offset(pdf: &str, object: usize) -> usize {
+        let xref = pdf.rfind("startxref\n").unwrap();
+        let xref: usize = pdf[xref + 10..].lines().next().unwrap().parse().unwrap();
+        pdf[xref..].lines().nth(2 + object).unwrap()[..10]
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn exports() {
+        let backend = Backend::local().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        for body in [
+            "what (exactly) is the answer?",
+            "how do you say café in a PDF?",
+        ] {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: Some(String::from("Alice")),
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let (AppendHeaders(headers), pdf) = super::export(Path(eid), State(backend.clone())).await;
+        assert_eq!(headers[1].1, "application/pdf");
+        let pdf = String::from_utf8(pdf.unwrap()).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.contains("(Unanswered \\(2\\)) Tj"), "{pdf}");
+        assert!(pdf.contains("what \\(exactly\\) is the answer?"), "{pdf}");
+        assert!(pdf.contains("caf\\351 in a PDF?"), "{pdf}");
+        assert!(pdf.contains("(1 vote, asked by Alice) Tj"), "{pdf}");
+        // and readers can find everything
+        for object in 1..=6 {
+            assert!(pdf[offset(&pdf, object)..].starts_with(&format!("{object} 0 obj\n")));
+        }
+
+        backend.delete(&eid).await;
+        let (_, gone) = super::export(Path(eid), State(backend.clone())).await;
+        assert_eq!(gone.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn lays_out() {
+        assert_eq!(wrap("a bb ccc dddd", 6), ["a bb", "ccc", "dddd"]);
+        assert_eq!(wrap("abcdefgh", 3), ["abc", "def", "gh"]);
+        assert_eq!(wrap("one\ntwo", 80), ["one", "two"]);
+        assert_eq!(escape("naïve (π)"), "na\\357ve \\(?\\)");
+
+        // lots of questions take more than one page
+        let line = Line {
+            text: String::from("a question that is asked over and over again"),
+            who: None,
+            votes: 3,
+        };
+        let blocks: Vec<_> = (1..=100).map(|i| Block::Question(i, &line)).collect();
+        let pages = layout(&blocks);
+        assert!(pages.len() > 2);
+        assert!(pages.iter().all(|p| p.contains("3 votes")));
+    }
+}
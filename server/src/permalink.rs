@@ -0,0 +1,332 @@
+//! Linking to a single question.
+//!
+//! People want to point at a particular question from meeting notes or chat, and the event's URL
+//! alone leaves them hunting for it. Every question's text (see [`super::questions`]) therefore
+//! comes with a `permalink`, which is the event's URL with the question id as the fragment, under
+//! `PUBLIC_URL` if it's set and relative otherwise. The client marks each question with its id, so
+//! the browser can scroll to it.
+//!
+//! `GET /api/q/:qid` is the short way to get there when all one has is the question id: it says
+//! which event the question is in (`eid`), where in the event's list attendees see it right now
+//! (`position`, counting from 1 at the top), the `permalink`, and where its [share
+//! page](super::unfurl) is (`share`), along with whether its asker has `edited` it. Hidden questions aren't to be found this way, since attendees
+//! can't see them either.
+//!
+//! Question ids are only made up by the server, so ids that don't exist won't start to, and
+//! looking them up again and again only keeps the database busy. Each instance remembers up to
+//! `MAX_MISSING` ids it didn't find for `MISSING_FOR`, and answers them with a 404 from memory,
+//! which is counted as `permalink.missing`.
+
+use super::{experiment::Ordering, Backend};
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::extract::{Path, State};
+use axum::response::{AppendHeaders, Json};
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How long a question id is known not to exist after it wasn't found.
+const MISSING_FOR: Duration = Duration::from_secs(60);
+/// Remember at most this many question ids that weren't found.
+const MAX_MISSING: usize = 4096;
+
+/// The question ids that weren't found, and when.
+static MISSING: Mutex<BTreeMap<Uuid, Instant>> = Mutex::new(BTreeMap::new());
+
+/// Whether `qid` was recently found not to exist, according to `missing`.
+fn known_missing(missing: &mut BTreeMap<Uuid, Instant>, qid: &Uuid) -> bool {
+    match missing.get(qid) {
+        Some(at) if at.elapsed() < MISSING_FOR => true,
+        Some(_) => {
+            missing.remove(qid);
+            false
+        }
+        None => false,
+    }
+}
+
+/// Remember in `missing` that `qid` doesn't exist.
+fn remember_missing(missing: &mut BTreeMap<Uuid, Instant>, qid: Uuid) {
+    if missing.len() >= MAX_MISSING {
+        missing.retain(|_, at| at.elapsed() < MISSING_FOR);
+        if missing.len() >= MAX_MISSING {
+            // someone's making them up faster than they expire
+            missing.clear();
+        }
+    }
+    missing.insert(qid, Instant::now());
+}
+
+/// The question item of `qid`, if there is one.
+///
+/// Ids that weren't found are remembered for a while, so asking for them again doesn't go to the
+/// database.
+pub(super) async fn lookup(
+    dynamo: &Backend,
+    qid: &Uuid,
+) -> Result<Option<HashMap<String, AttributeValue>>, aws_sdk_dynamodb::Error> {
+    if known_missing(&mut MISSING.lock().unwrap(), qid) {
+        super::metrics::incr("permalink.missing");
+        return Ok(None);
+    }
+    let q = dynamo
+        .questions(&[*qid])
+        .await?
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .and_then(|qs| qs.first())
+        .cloned();
+    if q.is_none() {
+        remember_missing(&mut MISSING.lock().unwrap(), *qid);
+    }
+    Ok(q)
+}
+
+/// Where to see question `qid` of event `eid`.
+pub(super) fn url(eid: &Uuid, qid: &Uuid) -> String {
+    let base = super::config::config().public_url.as_deref().unwrap_or("");
+    format!("{base}/event/{eid}#{qid}")
+}
+
+/// Where `qid` is in `eid`'s list as attendees see it, if it's there at all.
+async fn position(dynamo: &Backend, eid: &Uuid, qid: &Uuid) -> Result<Option<usize>, StatusCode> {
+    let qid = qid.to_string();
+    // live events are in memory, in the order attendees see
+    super::hot::recheck(dynamo, eid).await;
+    if let Some((_, _, _, questions)) = super::hot::get(eid, Some(Ordering::Top)) {
+        return Ok(questions.iter().position(|q| q["qid"] == qid.as_str()));
+    }
+
+    let meta = super::get_meta(dynamo, eid).await?;
+    if meta.frozen() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let qs = dynamo.list(eid, false).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for question list failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut questions: Vec<_> = qs
+        .items()
+        .map(|qs| {
+            qs.iter()
+                .filter_map(|doc| super::list::parse(eid, doc))
+                .collect()
+        })
+        .unwrap_or_default();
+    let now = super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ordering::Top.sort(&mut questions, now);
+    if let Some(locked) = super::lock::snapshot(&meta) {
+        super::lock::arrange(&mut questions, &locked);
+    }
+    Ok(questions.iter().position(|(q, _)| q.to_string() == qid))
+}
+
+pub(super) async fn permalink(
+    Path(qid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+) -> (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Result<Json<serde_json::Value>, StatusCode>,
+) {
+    let cache = |c| AppendHeaders([(header::CACHE_CONTROL, c)]);
+    let q = match lookup(&dynamo, &qid).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!(%qid, error = %e, "dynamodb request for linked question failed");
+            return (cache("no-cache"), Err(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+    let q = q.as_ref();
+    if super::moderation::is_frozen(q) {
+        return (cache("max-age=60"), Err(StatusCode::FORBIDDEN));
+    }
+    let q = q.filter(|q| q.get("hidden").and_then(|v| v.as_bool().ok()) != Some(&true));
+    let eid = q
+        .and_then(|q| q.get("eid"))
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok());
+    let edited = q.is_some_and(|q| q.contains_key("history"));
+    let Some(eid) = eid else {
+        debug!(%qid, "link to unknown or hidden question");
+        return (cache("max-age=60"), Err(StatusCode::NOT_FOUND));
+    };
+
+    match position(&dynamo, &eid, &qid).await {
+        // positions move as votes come in, but a link doesn't need them to the second
+        Ok(Some(i)) => {
+            let mut link = serde_json::json!({
+                "qid": qid.to_string(),
+                "eid": eid.to_string(),
+                "position": i + 1,
+                "permalink": url(&eid, &qid),
+                "share": super::unfurl::url(&qid),
+            });
+            // published from an answer template, and the one place answers are shown
+            match dynamo.published(&qid).await {
+                Ok(Some(answer)) => link["answer"] = answer.into(),
+                Ok(None) => {}
+                Err(e) => {
+                    error!(%qid, error = %e, "dynamodb request for published answer failed");
+                    return (cache("no-cache"), Err(StatusCode::INTERNAL_SERVER_ERROR));
+                }
+            }
+            // by its asker (see super::author)
+            if edited {
+                link["edited"] = true.into();
+            }
+            (cache("max-age=10"), Ok(Json(link)))
+        }
+        // hidden since it was fetched
+        Ok(None) => (cache("max-age=60"), Err(StatusCode::NOT_FOUND)),
+        Err(StatusCode::INTERNAL_SERVER_ERROR) => {
+            (cache("no-cache"), Err(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        Err(e) => (cache("max-age=60"), Err(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::{Property, Why};
+    use axum::extract::Query;
+
+    #[test]
+    fn remembers_missing() {
+        let mut missing = BTreeMap::new();
+        let qid = Uuid::new_v4();
+        assert!(!known_missing(&mut missing, &qid));
+        remember_missing(&mut missing, qid);
+        assert!(known_missing(&mut missing, &qid));
+        missing.insert(qid, Instant::now() - MISSING_FOR);
+        assert!(!known_missing(&mut missing, &qid));
+        assert!(missing.is_empty());
+
+        for _ in 0..MAX_MISSING {
+            remember_missing(&mut missing, Uuid::new_v4());
+        }
+        remember_missing(&mut missing, qid);
+        assert!(missing.len() <= MAX_MISSING);
+        assert!(known_missing(&mut missing, &qid));
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let mut qids = Vec::new();
+        for body in ["what about this", "what about that"] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap();
+            qids.push(Uuid::parse_str(q["id"].as_str().unwrap()).unwrap());
+        }
+        let (a, b) = (qids[0], qids[1]);
+        crate::vote::vote(
+            Path((b, crate::vote::UpDown::Up)),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
+
+        let (_, res) = permalink(Path(a), State(backend.clone())).await;
+        let Json(link) = res.unwrap();
+        assert_eq!(link["eid"], eid.to_string());
+        assert_eq!(link["position"], 2);
+        assert_eq!(link["permalink"], url(&eid, &a));
+        assert!(link["permalink"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("/event/{eid}#{a}")));
+        let (_, res) = permalink(Path(b), State(backend.clone())).await;
+        assert_eq!(res.unwrap().0["position"], 1);
+
+        // the texts link to the question too
+        let Json(texts) = crate::questions::questions(
+            Path(a.to_string()),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .2
+        .unwrap();
+        assert_eq!(texts[a.to_string()]["permalink"], url(&eid, &a));
+
+        crate::toggle::toggle(
+            Path((eid, secret, b, Property::Hidden)),
+            State(backend.clone()),
+            Query(Why::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        let (_, res) = permalink(Path(b), State(backend.clone())).await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_FOUND);
+        let (_, res) = permalink(Path(Uuid::new_v4()), State(backend.clone())).await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_FOUND);
+        // and again, without going to the database
+        let nowhere = Uuid::new_v4();
+        let _ = permalink(Path(nowhere), State(backend.clone())).await;
+        let hits = crate::metrics::get("permalink.missing");
+        let (_, res) = permalink(Path(nowhere), State(backend.clone())).await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_FOUND);
+        assert!(crate::metrics::get("permalink.missing") > hits);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
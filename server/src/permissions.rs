@@ -0,0 +1,317 @@
+use super::infra::{self, Index, Table};
+use super::{admin::Admin, Backend};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, KeysAndAttributes},
+    types::SdkError,
+};
+use aws_smithy_types::retry::ProvideErrorKind;
+use axum::extract::State;
+use axum::response::Json;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Status {
+    Granted,
+    Denied,
+    /// The call failed for some reason other than permissions, so we can't tell.
+    Unknown,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct Check {
+    pub(super) action: &'static str,
+    pub(super) resource: String,
+    pub(super) status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) error: Option<String>,
+}
+
+/// Turn the result of a probe call into a permission check.
+///
+/// `harmless` identifies errors that prove we were allowed to make the call, like the failed
+/// condition we attach to writes so that probing never modifies anything.
+fn classify<T, E>(
+    action: &'static str,
+    resource: String,
+    r: Result<T, SdkError<E>>,
+    harmless: impl Fn(&E) -> bool,
+) -> Check
+where
+    E: ProvideErrorKind + std::error::Error + 'static,
+{
+    let (status, error) = match r {
+        Ok(_) => (Status::Granted, None),
+        Err(SdkError::ServiceError { ref err, .. }) if harmless(err) => (Status::Granted, None),
+        Err(SdkError::ServiceError { ref err, .. })
+            if err.code() == Some("AccessDeniedException") =>
+        {
+            (Status::Denied, Some(err.to_string()))
+        }
+        Err(e) => (Status::Unknown, Some(e.to_string())),
+    };
+    Check {
+        action,
+        resource,
+        status,
+        error,
+    }
+}
+
+/// A key that is vanishingly unlikely to exist, so reads come back empty and conditional writes
+/// are rejected.
+fn probe_key(table: &Table) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    for k in std::iter::once(&table.hash).chain(&table.range) {
+        let v = match k.ty {
+            "N" => AttributeValue::N(0.to_string()),
+            _ => AttributeValue::S(Uuid::new_v4().to_string()),
+        };
+        key.insert(k.name.to_string(), v);
+    }
+    key
+}
+
+impl Backend {
+    async fn probe_table(
+        dynamo: &aws_sdk_dynamodb::Client,
+        table: &Table,
+        action: &'static str,
+    ) -> Check {
+        let key = probe_key(table);
+        let resource = table.name.to_string();
+        match action {
+            "dynamodb:GetItem" => classify(
+                action,
+                resource,
+                dynamo
+                    .get_item()
+                    .table_name(table.name)
+                    .set_key(Some(key))
+                    .send()
+                    .await,
+                |_| false,
+            ),
+            "dynamodb:BatchGetItem" => classify(
+                action,
+                resource,
+                dynamo
+                    .batch_get_item()
+                    .request_items(table.name, KeysAndAttributes::builder().keys(key).build())
+                    .send()
+                    .await,
+                |_| false,
+            ),
+            "dynamodb:PutItem" => classify(
+                action,
+                resource,
+                dynamo
+                    .put_item()
+                    .table_name(table.name)
+                    .set_item(Some(key))
+                    .condition_expression("attribute_exists(#key)")
+                    .expression_attribute_names("#key", table.hash.name)
+                    .send()
+                    .await,
+                |e| e.is_conditional_check_failed_exception(),
+            ),
+            "dynamodb:UpdateItem" => classify(
+                action,
+                resource,
+                dynamo
+                    .update_item()
+                    .table_name(table.name)
+                    .set_key(Some(key))
+                    .update_expression("SET #probe = :probe")
+                    .condition_expression("attribute_exists(#key)")
+                    .expression_attribute_names("#key", table.hash.name)
+                    .expression_attribute_names("#probe", "probe")
+                    .expression_attribute_values(":probe", AttributeValue::Bool(true))
+                    .send()
+                    .await,
+                |e| e.is_conditional_check_failed_exception(),
+            ),
+            "dynamodb:DeleteItem" => classify(
+                action,
+                resource,
+                dynamo
+                    .delete_item()
+                    .table_name(table.name)
+                    .set_key(Some(key))
+                    .condition_expression("attribute_exists(#key)")
+                    .expression_attribute_names("#key", table.hash.name)
+                    .send()
+                    .await,
+                |e| e.is_conditional_check_failed_exception(),
+            ),
+            "dynamodb:Scan" => classify(
+                action,
+                resource,
+                dynamo.scan().table_name(table.name).limit(1).send().await,
+                |_| false,
+            ),
+            "dynamodb:Query" => classify(
+                action,
+                resource,
+                dynamo
+                    .query()
+                    .table_name(table.name)
+                    .key_condition_expression("#key = :key")
+                    .expression_attribute_names("#key", table.hash.name)
+                    .expression_attribute_values(":key", key[table.hash.name].clone())
+                    .limit(1)
+                    .send()
+                    .await,
+                |_| false,
+            ),
+            _ => unreachable!("no probe for {action} on a table"),
+        }
+    }
+
+    async fn probe_index(
+        dynamo: &aws_sdk_dynamodb::Client,
+        table: &Table,
+        index: &Index,
+        action: &'static str,
+    ) -> Check {
+        let resource = format!("{}/index/{}", table.name, index.name);
+        match action {
+            "dynamodb:Query" => classify(
+                action,
+                resource,
+                dynamo
+                    .query()
+                    .table_name(table.name)
+                    .index_name(index.name)
+                    .key_condition_expression("#key = :key")
+                    .expression_attribute_names("#key", index.hash.name)
+                    .expression_attribute_values(
+                        ":key",
+                        AttributeValue::S(Uuid::new_v4().to_string()),
+                    )
+                    .limit(1)
+                    .send()
+                    .await,
+                |_| false,
+            ),
+            _ => unreachable!("no probe for {action} on an index"),
+        }
+    }
+
+    /// Exercise every DynamoDB permission listed in [`infra::TABLES`] without modifying anything.
+    ///
+    /// Only DynamoDB proper has IAM, so there's nothing to check for the other backends.
+    pub(super) async fn check_permissions(&self) -> Vec<Check> {
+        let dynamo = match self {
+            Self::Dynamo(dynamo) if !super::config::config().alternator => Some(dynamo),
+            _ => None,
+        };
+        let granted = |action, resource| Check {
+            action,
+            resource,
+            status: Status::Granted,
+            error: None,
+        };
+        let mut checks = Vec::new();
+        for table in infra::TABLES {
+            for &action in table.actions {
+                checks.push(match dynamo {
+                    Some(dynamo) => Self::probe_table(dynamo, table, action).await,
+                    None => granted(action, table.name.to_string()),
+                });
+            }
+            for index in table.indices {
+                for &action in index.actions {
+                    checks.push(match dynamo {
+                        Some(dynamo) => Self::probe_index(dynamo, table, index, action).await,
+                        None => granted(action, format!("{}/index/{}", table.name, index.name)),
+                    });
+                }
+            }
+        }
+        checks
+    }
+}
+
+/// A report of which permissions the server has, along with the policy it should have.
+pub(super) async fn report(backend: &Backend) -> serde_json::Value {
+    let checks = backend.check_permissions().await;
+    for check in &checks {
+        match check.status {
+            Status::Granted => {}
+            Status::Denied => {
+                warn!(action = check.action, resource = %check.resource, "missing permission")
+            }
+            Status::Unknown => {
+                warn!(action = check.action, resource = %check.resource, error = ?check.error, "permission check failed")
+            }
+        }
+    }
+    let missing: Vec<_> = checks
+        .iter()
+        .filter(|c| c.status == Status::Denied)
+        .map(|c| format!("{} on {}", c.action, c.resource))
+        .collect();
+    let region = std::env::var("AWS_REGION").unwrap_or_else(|_| String::from("*"));
+    serde_json::json!({
+        "ok": checks.iter().all(|c| c.status == Status::Granted),
+        "missing": missing,
+        "checks": checks,
+        "policy": infra::policy_for_account(&region, "*"),
+    })
+}
+
+pub(super) async fn permissions(
+    _: Admin,
+    State(dynamo): State<Backend>,
+) -> Json<serde_json::Value> {
+    Json(report(&dynamo).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let r = super::permissions(Admin, State(backend)).await.0;
+        assert_eq!(r["ok"], true, "missing permissions: {}", r["missing"]);
+        assert_eq!(r["checks"].as_array().unwrap().len(), 13);
+        assert!(r["policy"]["Statement"].is_array());
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
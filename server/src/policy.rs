@@ -0,0 +1,279 @@
+//! Turning routes off, or keeping them for operators, on locked-down deployments.
+//!
+//! Not every deployment wants everything the public instance has. One inside a company may not
+//! want questions leaving the building as public archives or plain text, or webhooks going out,
+//! or anyone but IT creating events. `POLICY` (like `exports=off,new=operator`) says which routes
+//! are `off` entirely and which are only for `operator`s (with the `ADMIN_TOKEN`), by the names
+//! they have in the metrics (like `ask` or `text`) or by group:
+//!
+//!  - `exports`: `text`, `pdf`, `archive`, and `sitemap`
+//!  - `integrations`: `digest`, `email`, and `survey`
+//!
+//! This is checked before anything else happens to a request, so a request for a route that's
+//! off doesn't even find out whether the event exists. It's refused with a 403 and
+//! `{"error": "disabled_by_policy"}` (or `"operators_only"`), so that clients can tell it apart
+//! from the 403s for frozen or closed events, and it's counted in the metrics as
+//! `policy.<route>`. The policy is also in `/api/instance`, so the client can leave out what
+//! won't work.
+//!
+//! A policy that's meant to keep things closed mustn't open them up by accident, so the server
+//! refuses to start with a `POLICY` it can't make sense of, or that names a route or group that
+//! doesn't exist (like `export=off` for `exports=off`). Anything else that reads the configuration
+//! takes a malformed `POLICY` to mean that everything is off.
+
+use super::admin::Admin;
+use axum::{
+    extract::{FromRequestParts, State},
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use http::StatusCode;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::Mutex,
+};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The routes that can be named together.
+const GROUPS: &[(&str, &[&str])] = &[
+    ("exports", &["text", "pdf", "archive", "sitemap"]),
+    ("integrations", &["digest", "email", "survey"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Rule {
+    /// Nobody can use the route.
+    Off,
+    /// Only operators can use the route.
+    Operator,
+}
+
+impl Rule {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Rule::Off => "off",
+            Rule::Operator => "operator",
+        }
+    }
+}
+
+/// What the deployment's policy says about each route it mentions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Policy {
+    rules: BTreeMap<String, Rule>,
+    /// The routes named on their own rather than as part of a group, which have to exist.
+    named: BTreeSet<String>,
+    /// Why the policy couldn't be made sense of, if it couldn't, in which case everything is off.
+    broken: Option<String>,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = BTreeMap::new();
+        let mut named = BTreeSet::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((name, rule)) = part.split_once('=') else {
+                return Err(format!("{part:?} doesn't say what to do"));
+            };
+            let rule = match rule.trim() {
+                "off" => Rule::Off,
+                "operator" => Rule::Operator,
+                rule => return Err(format!("{rule:?} isn't off or operator")),
+            };
+            let name = name.trim();
+            match GROUPS.iter().find(|(group, _)| *group == name) {
+                Some((_, routes)) => {
+                    rules.extend(routes.iter().map(|route| (route.to_string(), rule)));
+                }
+                None => {
+                    rules.insert(name.to_string(), rule);
+                    named.insert(name.to_string());
+                }
+            }
+        }
+        Ok(Policy {
+            rules,
+            named,
+            broken: None,
+        })
+    }
+}
+
+impl Policy {
+    /// The policy to go by when the configured one is malformed (as `error` says), which turns
+    /// everything off.
+    pub(super) fn broken(error: String) -> Self {
+        Policy {
+            broken: Some(error),
+            ..Default::default()
+        }
+    }
+
+    /// What the policy says about the route named `route`, if anything.
+    pub(super) fn rule(&self, route: &str) -> Option<Rule> {
+        if self.broken.is_some() {
+            return Some(Rule::Off);
+        }
+        self.rules.get(route).copied()
+    }
+
+    /// The names the policy gives that aren't among `routes` or the groups.
+    fn unknown(&self, routes: &BTreeSet<&str>) -> Vec<&str> {
+        self.named
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !routes.contains(name))
+            .collect()
+    }
+
+    pub(super) fn to_json(&self) -> serde_json::Value {
+        self.rules
+            .iter()
+            .map(|(route, rule)| (route.clone(), rule.as_str().into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Check {
+    route: &'static str,
+    rule: Option<Rule>,
+}
+
+/// Every route that's been given a [`check`], which are all the routes once the API is set up.
+static ROUTES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// The check for the route named `route`, with the rule from the configuration.
+pub(super) fn check(route: &'static str) -> Check {
+    ROUTES.lock().unwrap().insert(route);
+    Check {
+        route,
+        rule: super::config::config().policy.rule(route),
+    }
+}
+
+/// Make sure the configured policy made sense, and only names routes that exist.
+///
+/// Only meaningful once every route has been set up with a [`check`].
+pub(super) fn verify() -> Result<(), String> {
+    let policy = &super::config::config().policy;
+    if let Some(error) = &policy.broken {
+        return Err(format!("malformed POLICY: {error}"));
+    }
+    let unknown = policy.unknown(&ROUTES.lock().unwrap());
+    if !unknown.is_empty() {
+        return Err(format!(
+            "POLICY names unknown routes: {}",
+            unknown.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn refuse(route: &'static str, error: &'static str) -> Response {
+    super::metrics::incr(format!("policy.{route}"));
+    debug!(route, error, "refused request by policy");
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::json!({ "error": error })),
+    )
+        .into_response()
+}
+
+pub(super) async fn enforce<B>(
+    State(check): State<Check>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match check.rule {
+        None => next.run(req).await,
+        Some(Rule::Off) => refuse(check.route, "disabled_by_policy"),
+        Some(Rule::Operator) => {
+            let (mut parts, body) = req.into_parts();
+            if Admin::from_request_parts(&mut parts, &()).await.is_err() {
+                return refuse(check.route, "operators_only");
+            }
+            next.run(Request::from_parts(parts, body)).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn parses() {
+        let p: Policy = "exports=off, new = operator,,".parse().unwrap();
+        assert_eq!(p.rule("text"), Some(Rule::Off));
+        assert_eq!(p.rule("sitemap"), Some(Rule::Off));
+        assert_eq!(p.rule("new"), Some(Rule::Operator));
+        assert_eq!(p.rule("ask"), None);
+        assert_eq!(p.to_json()["archive"], serde_json::Value::from("off"));
+
+        // later rules win
+        let p: Policy = "exports=off,text=operator".parse().unwrap();
+        assert_eq!(p.rule("text"), Some(Rule::Operator));
+        assert_eq!(p.rule("archive"), Some(Rule::Off));
+
+        assert!("text".parse::<Policy>().is_err());
+        assert!("text=maybe".parse::<Policy>().is_err());
+        assert_eq!("".parse(), Ok(Policy::default()));
+
+        // one that can't be made sense of turns everything off
+        let p = Policy::broken(String::from("\"opertor\" isn't off or operator"));
+        assert_eq!(p.rule("ask"), Some(Rule::Off));
+    }
+
+    #[test]
+    fn knows_routes() {
+        // setting up the API gives every route its check
+        let _ = crate::api::<Body>();
+        let routes = ROUTES.lock().unwrap();
+        let p: Policy = "exports=off,ask=operator,new=off".parse().unwrap();
+        assert!(p.unknown(&routes).is_empty());
+        let p: Policy = "export=off,ask=operator".parse().unwrap();
+        assert_eq!(p.unknown(&routes), ["export"]);
+    }
+
+    #[tokio::test]
+    async fn enforces() {
+        let route = |route, rule| {
+            get(|| async {}).layer(axum::middleware::from_fn_with_state(
+                Check { route, rule },
+                enforce,
+            ))
+        };
+        let app = Router::new()
+            .route("/on", route("test_on", None))
+            .route("/off", route("test_off", Some(Rule::Off)))
+            .route("/operator", route("test_operator", Some(Rule::Operator)));
+        let get = |uri| {
+            app.clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(get("/on").await.unwrap().status(), StatusCode::OK);
+        for (uri, error) in [
+            ("/off", "disabled_by_policy"),
+            // there's no admin token in tests, so nobody is an operator
+            ("/operator", "operators_only"),
+        ] {
+            let res = get(uri).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], error);
+        }
+        assert_eq!(super::super::metrics::get("policy.test_off"), 1);
+    }
+}
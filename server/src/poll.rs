@@ -0,0 +1,157 @@
+//! Hints for how long clients should wait before polling an event again.
+//!
+//! Polling a busy event every second keeps its audience up to date, but an event where nothing
+//! has happened for minutes can be polled far less often without anyone noticing. So list and
+//! change responses say how long to wait, based on how often the event has changed lately, and
+//! the load on the server follows how much is going on rather than how many people are watching.
+//!
+//! The rate of change is estimated from the event's version (the sequence number of its latest
+//! change) every time this instance learns it, so changes made through other instances count too.
+//! Recent changes count the most: each one's weight halves every `HALF_LIFE`.
+
+use http::header::HeaderName;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The header list responses carry the hint in, since their body is a bare array.
+pub(super) const POLL_AFTER: HeaderName = HeaderName::from_static("x-poll-after-ms");
+
+/// Busy events shouldn't be polled more often than this.
+const BUSY: Duration = Duration::from_secs(1);
+/// Idle events should still be polled this often, so that they don't seem dead.
+const IDLE: Duration = Duration::from_secs(30);
+/// How quickly the weight of past changes fades.
+const HALF_LIFE: Duration = Duration::from_secs(60);
+/// How long an event has to be watched before its rate of change is trusted.
+const WARMUP: Duration = Duration::from_secs(5);
+/// Stop tracking events that haven't been seen for a while once there are this many.
+const MAX_EVENTS: usize = 4096;
+
+#[derive(Debug)]
+struct Activity {
+    /// When the event was first seen.
+    first: Instant,
+    /// When the event was last seen.
+    at: Instant,
+    /// The event's version when it was last seen.
+    seq: u64,
+    /// The number of changes seen, each weighted by how recent it is as of `at`.
+    weight: f64,
+}
+
+static ACTIVITY: Mutex<BTreeMap<Uuid, Activity>> = Mutex::new(BTreeMap::new());
+
+/// How much of a change's weight is left after `elapsed`.
+fn decay(elapsed: Duration) -> f64 {
+    0.5f64.powf(elapsed.as_secs_f64() / HALF_LIFE.as_secs_f64())
+}
+
+/// Note that `eid` was at version `seq` just now.
+pub(super) fn observe(eid: &Uuid, seq: u64) {
+    observe_at(&mut ACTIVITY.lock().unwrap(), eid, seq, Instant::now());
+}
+
+fn observe_at(activity: &mut BTreeMap<Uuid, Activity>, eid: &Uuid, seq: u64, now: Instant) {
+    if activity.len() >= MAX_EVENTS && !activity.contains_key(eid) {
+        activity.retain(|_, a| now.saturating_duration_since(a.at) < 10 * HALF_LIFE);
+    }
+    let a = activity.entry(*eid).or_insert(Activity {
+        first: now,
+        at: now,
+        seq,
+        weight: 0.0,
+    });
+    // observations can arrive out of order, and the clock may not have moved
+    if now > a.at {
+        a.weight *= decay(now - a.at);
+        a.at = now;
+    }
+    if seq > a.seq {
+        a.weight += (seq - a.seq) as f64;
+        a.seq = seq;
+    }
+}
+
+/// How long clients (hosts if `host`) should wait before they poll `eid` again.
+pub(super) fn hint(eid: &Uuid, host: bool) -> Duration {
+    hint_at(&ACTIVITY.lock().unwrap(), eid, host, Instant::now())
+}
+
+fn hint_at(activity: &BTreeMap<Uuid, Activity>, eid: &Uuid, host: bool, now: Instant) -> Duration {
+    let wait = match activity.get(eid) {
+        Some(a) if now.saturating_duration_since(a.first) >= WARMUP => {
+            let weight = a.weight * decay(now.saturating_duration_since(a.at));
+            // the total weight that one change per second over the time watched would have had
+            let half_life = HALF_LIFE.as_secs_f64();
+            let span = half_life / std::f64::consts::LN_2
+                * (1.0 - decay(now.saturating_duration_since(a.first)));
+            let per_second = weight / span;
+            if per_second > 0.0 {
+                Duration::try_from_secs_f64(1.0 / per_second).unwrap_or(IDLE)
+            } else {
+                IDLE
+            }
+        }
+        // don't know yet, so go with what the client did before there were hints
+        _ => Duration::from_secs(10),
+    };
+    // hosts should be allowed to see more up-to-date views
+    let wait = if host { wait / 3 } else { wait };
+    wait.clamp(BUSY, IDLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_activity() {
+        let mut activity = BTreeMap::new();
+        let (busy, idle) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // nothing is known about new events
+        observe_at(&mut activity, &busy, 40, start);
+        observe_at(&mut activity, &idle, 3, start);
+        assert_eq!(
+            hint_at(&activity, &busy, false, at(1)),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            hint_at(&activity, &busy, true, at(1)),
+            Duration::from_secs(10) / 3
+        );
+
+        // two changes a second
+        for s in 1..=120 {
+            observe_at(&mut activity, &busy, 40 + 2 * s, at(s));
+        }
+        observe_at(&mut activity, &idle, 3, at(120));
+        assert_eq!(hint_at(&activity, &busy, false, at(120)), BUSY);
+        assert_eq!(hint_at(&activity, &idle, false, at(120)), IDLE);
+        assert_eq!(hint_at(&activity, &idle, true, at(120)), IDLE / 3);
+
+        // one change every ten seconds
+        for s in 1..=30 {
+            observe_at(&mut activity, &idle, 3 + s, at(120 + 10 * s));
+        }
+        let wait = hint_at(&activity, &idle, false, at(420));
+        assert!(
+            (Duration::from_secs(9)..=Duration::from_secs(11)).contains(&wait),
+            "{wait:?}"
+        );
+
+        // and then nothing for a while
+        let wait = hint_at(&activity, &busy, false, at(240));
+        assert!(wait > BUSY && wait < IDLE, "{wait:?}");
+        assert_eq!(hint_at(&activity, &busy, false, at(600)), IDLE);
+    }
+}
@@ -0,0 +1,160 @@
+//! Roughly how many people are following an event right now.
+//!
+//! Question lists are cached (by browsers and the CDN), so polling them says little about who's
+//! there. Instead, open pages ping the event with a random token of their own every so often
+//! (every `PING` or so while they're visible). Each event keeps a HyperLogLog sketch of the tokens
+//! that pinged it in the current `WINDOW`, and one for the window before, so the memory it needs
+//! doesn't grow with the audience. An attendee counts as present if they pinged in either.
+//!
+//! Like the other process-wide state, every instance only knows about the pings it served, so with
+//! several Lambda instances the count is a share of the audience rather than all of it.
+
+use super::hll::Sketch;
+use axum::extract::Path;
+use axum::response::Json;
+use http::{header::HeaderName, StatusCode};
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Mutex, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The header host question lists carry the count in.
+pub(super) const ATTENDEES: HeaderName = HeaderName::from_static("x-attendees");
+
+/// How often clients should ping, in seconds.
+pub(super) const PING: u64 = 30;
+/// How long a ping counts for, in seconds. At least two pings fit into one.
+const WINDOW: u64 = 60;
+/// Stop tracking events that haven't been pinged for a while once there are this many.
+const MAX_EVENTS: usize = 4096;
+
+#[derive(Debug, Default)]
+struct Presence {
+    /// The window that `current` is for.
+    window: u64,
+    current: Sketch,
+    previous: Sketch,
+}
+
+static PRESENCE: Mutex<BTreeMap<Uuid, Presence>> = Mutex::new(BTreeMap::new());
+
+fn window() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / WINDOW
+}
+
+/// Note that whoever holds `token` is following `eid`.
+fn ping_at(presence: &mut BTreeMap<Uuid, Presence>, eid: &Uuid, token: &str, window: u64) {
+    if presence.len() >= MAX_EVENTS && !presence.contains_key(eid) {
+        presence.retain(|_, p| p.window + 1 >= window);
+    }
+    let p = presence.entry(*eid).or_insert_with(|| Presence {
+        window,
+        ..Default::default()
+    });
+    if window == p.window + 1 {
+        p.previous = std::mem::take(&mut p.current);
+    } else if window > p.window + 1 {
+        p.previous = Sketch::default();
+        p.current = Sketch::default();
+    }
+    p.window = p.window.max(window);
+    p.current.insert(token);
+}
+
+fn count_at(presence: &BTreeMap<Uuid, Presence>, eid: &Uuid, window: u64) -> u64 {
+    match presence.get(eid) {
+        Some(p) if p.window == window => {
+            let mut both = p.current.clone();
+            both.merge(&p.previous);
+            both.estimate()
+        }
+        Some(p) if p.window + 1 == window => p.current.estimate(),
+        _ => 0,
+    }
+}
+
+/// Roughly how many people have pinged `eid` lately.
+pub(super) fn count(eid: &Uuid) -> u64 {
+    count_at(&PRESENCE.lock().unwrap(), eid, window())
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Ping {
+    /// Random, and the same for as long as the attendee has the event open.
+    token: String,
+}
+
+pub(super) async fn ping(
+    Path(eid): Path<Uuid>,
+    Json(ping): Json<Ping>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // tokens are just hashed, but there's no reason to accept anything big
+    if ping.token.is_empty() || ping.token.len() > 64 {
+        warn!(%eid, "ping with bad token");
+        super::rejections::reject(&eid, "bad_token");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    super::experiment::outcome(&ping.token, "pings");
+    let mut presence = PRESENCE.lock().unwrap();
+    let window = window();
+    ping_at(&mut presence, &eid, &ping.token, window);
+    Ok(Json(serde_json::json!({
+        "attendees": count_at(&presence, &eid, window),
+        "ping_after_ms": PING * 1000,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_recent_pings() {
+        let mut presence = BTreeMap::new();
+        let (eid, other) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(count_at(&presence, &eid, 10), 0);
+
+        for token in ["a", "b", "c", "a"] {
+            ping_at(&mut presence, &eid, token, 10);
+        }
+        ping_at(&mut presence, &other, "a", 10);
+        assert_eq!(count_at(&presence, &eid, 10), 3);
+        assert_eq!(count_at(&presence, &other, 10), 1);
+
+        // people who pinged in the last window still count
+        ping_at(&mut presence, &eid, "d", 11);
+        assert_eq!(count_at(&presence, &eid, 11), 4);
+        // until they've missed a whole window
+        assert_eq!(count_at(&presence, &eid, 12), 1);
+        ping_at(&mut presence, &eid, "a", 13);
+        assert_eq!(count_at(&presence, &eid, 13), 1);
+        assert_eq!(count_at(&presence, &eid, 20), 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_tokens() {
+        let eid = Uuid::new_v4();
+        let ping = |token: &str| {
+            super::ping(
+                Path(eid),
+                Json(Ping {
+                    token: token.to_string(),
+                }),
+            )
+        };
+        assert_eq!(ping("").await.unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            ping(&"x".repeat(65)).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let res = ping("t").await.unwrap();
+        assert_eq!(res["attendees"], 1);
+        assert_eq!(count(&eid), 1);
+    }
+}
@@ -1,60 +1,229 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, net::IpAddr, time::Duration};
 
-use super::{Backend, Local};
+use super::{networks::Reach, Backend, Local};
 use aws_sdk_dynamodb::{
-    error::BatchGetItemError,
     model::{AttributeValue, KeysAndAttributes},
     output::BatchGetItemOutput,
-    types::SdkError,
 };
 use axum::{
     extract::{Path, State},
     response::AppendHeaders,
     Json,
 };
+use futures_util::stream::{self, StreamExt, TryStreamExt};
 use http::{
     header::{self, HeaderName},
-    StatusCode,
+    HeaderMap, StatusCode,
 };
+use rand::Rng;
 use serde_json::Value;
 use uuid::Uuid;
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
+/// How to look up a set of questions in DynamoDB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Fetch {
+    /// With a single `BatchGetItem`.
+    Batch,
+    /// With one `GetItem` per question, running this many at a time.
+    ///
+    /// Some DynamoDB-compatible stores don't support `BatchGetItem` (well).
+    Each(usize),
+}
+
+/// The most keys a single `BatchGetItem` may ask for.
+const MAX_BATCH: usize = 100;
+/// How many `BatchGetItem`s for the same request may run at once.
+const MAX_BATCHES: usize = 4;
+
+/// Put the responses to several `BatchGetItem`s together as if they were one.
+fn merge(batches: Vec<BatchGetItemOutput>) -> BatchGetItemOutput {
+    let mut responses: HashMap<String, Vec<_>> = HashMap::new();
+    let mut unprocessed: HashMap<String, Vec<_>> = HashMap::new();
+    for batch in batches {
+        for (table, items) in batch.responses.unwrap_or_default() {
+            responses.entry(table).or_default().extend(items);
+        }
+        for (table, keys) in batch.unprocessed_keys.unwrap_or_default() {
+            unprocessed
+                .entry(table)
+                .or_default()
+                .extend(keys.keys.unwrap_or_default());
+        }
+    }
+    let unprocessed: HashMap<_, _> = unprocessed
+        .into_iter()
+        .filter(|(_, keys)| !keys.is_empty())
+        .map(|(table, keys)| {
+            (
+                table,
+                KeysAndAttributes::builder().set_keys(Some(keys)).build(),
+            )
+        })
+        .collect();
+    BatchGetItemOutput::builder()
+        .set_responses(Some(responses))
+        .set_unprocessed_keys(if unprocessed.is_empty() {
+            None
+        } else {
+            Some(unprocessed)
+        })
+        .build()
+}
+
+/// How many times a `BatchGetItem` is sent again for the keys DynamoDB didn't get to.
+const MAX_RETRIES: u32 = 5;
+/// How long to wait before the first retry, at most.
+const BACKOFF: Duration = Duration::from_millis(25);
+/// The longest to wait before any retry.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The most to wait before retry number `retry` (counting from 0).
+fn backoff(retry: u32) -> Duration {
+    BACKOFF.saturating_mul(1 << retry.min(16)).min(MAX_BACKOFF)
+}
+
+/// Fetch the questions in `qids` with `BatchGetItem`, for as long as it takes to get them all.
+///
+/// When DynamoDB is throttled, it answers with whatever it got to and lists the rest as
+/// unprocessed. Those are asked for again after a random wait that grows with every attempt, and
+/// if they're still unprocessed after `MAX_RETRIES` attempts, that's treated as throttling, since
+/// leaving them out would look like the questions don't exist.
+async fn batch_get(
+    dynamo: &aws_sdk_dynamodb::Client,
+    qids: Vec<Uuid>,
+) -> Result<BatchGetItemOutput, aws_sdk_dynamodb::Error> {
+    let keys = qids
+        .iter()
+        .map(|qid| HashMap::from_iter([(String::from("id"), AttributeValue::S(qid.to_string()))]))
+        .collect();
+    let mut request = HashMap::from_iter([(
+        String::from("questions"),
+        KeysAndAttributes::builder()
+            .set_keys(Some(keys))
+            .projection_expression(
+                "id,eid,#text,#when,who,frozen,#hidden,#reason,revises,revised,author,#history,#source,merged_into",
+            )
+            .expression_attribute_names("#text", "text")
+            .expression_attribute_names("#when", "when")
+            .expression_attribute_names("#hidden", "hidden")
+            .expression_attribute_names("#reason", "reason")
+                        .expression_attribute_names("#history", "history")
+            .expression_attribute_names("#source", "source")
+            .build(),
+    )]);
+    let mut batches = Vec::new();
+    for retry in 0.. {
+        let mut batch = dynamo
+            .batch_get_item()
+            .set_request_items(Some(request))
+            .send()
+            .await?;
+        let unprocessed = batch
+            .unprocessed_keys
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, keys)| keys.keys().is_some_and(|keys| !keys.is_empty()))
+            .collect::<HashMap<_, _>>();
+        batches.push(batch);
+        if unprocessed.is_empty() {
+            break;
+        }
+        let left: usize = unprocessed
+            .values()
+            .map(|keys| keys.keys().map_or(0, |keys| keys.len()))
+            .sum();
+        if retry == MAX_RETRIES {
+            warn!(left, "gave up on unprocessed question keys");
+            super::metrics::incr("questions.unprocessed");
+            return Err(
+                aws_sdk_dynamodb::Error::ProvisionedThroughputExceededException(
+                    aws_sdk_dynamodb::error::ProvisionedThroughputExceededException::builder()
+                        .message(format!("{left} question keys left unprocessed"))
+                        .build(),
+                ),
+            );
+        }
+        // the unprocessed keys come back with the projection they were asked for with
+        request = unprocessed;
+        let wait = rand::thread_rng().gen_range(Duration::ZERO..=backoff(retry));
+        debug!(left, retry, ?wait, "retrying unprocessed question keys");
+        super::metrics::incr("questions.retry");
+        tokio::time::sleep(wait).await;
+    }
+    Ok(merge(batches))
+}
+
 impl Backend {
     pub(super) async fn questions(
         &self,
         qids: &[Uuid],
-    ) -> Result<BatchGetItemOutput, SdkError<BatchGetItemError>> {
+    ) -> Result<BatchGetItemOutput, aws_sdk_dynamodb::Error> {
+        let config = super::config::config();
+        let fetch = match config.get_item_concurrency {
+            Some(n) => Fetch::Each(n),
+            None => Fetch::Batch,
+        };
+        self.questions_with(qids, fetch).await
+    }
+
+    pub(super) async fn questions_with(
+        &self,
+        qids: &[Uuid],
+        fetch: Fetch,
+    ) -> Result<BatchGetItemOutput, aws_sdk_dynamodb::Error> {
         match self {
+            Self::Dynamo(dynamo) if fetch == Fetch::Batch => {
+                // a BatchGetItem can only ask for so many keys, so bigger sets take several
+                let chunks: Vec<Vec<Uuid>> = qids.chunks(MAX_BATCH).map(Vec::from).collect();
+                let batches: Vec<_> = stream::iter(chunks)
+                    .map(|qids| batch_get(dynamo, qids))
+                    .buffer_unordered(MAX_BATCHES)
+                    .try_collect()
+                    .await?;
+                Ok(merge(batches))
+            }
             Self::Dynamo(dynamo) => {
-                let keys = qids
-                    .iter()
+                let Fetch::Each(n) = fetch else {
+                    unreachable!("batch fetches are handled above");
+                };
+                let items: Vec<_> = stream::iter(qids.iter().copied())
                     .map(|qid| {
-                        HashMap::from_iter([(
-                            String::from("id"),
-                            AttributeValue::S(qid.to_string()),
-                        )])
-                    })
-                    .collect();
-                dynamo
-                    .batch_get_item()
-                    .request_items(
-                        "questions",
-                        KeysAndAttributes::builder()
-                            .set_keys(Some(keys))
-                            .projection_expression("id,#text,#when,who")
+                        dynamo
+                            .get_item()
+                            .table_name("questions")
+                            .key("id", AttributeValue::S(qid.to_string()))
+                            .projection_expression(
+                                "id,eid,#text,#when,who,frozen,#hidden,#reason,revises,revised,author,#history,#source,merged_into",
+                            )
                             .expression_attribute_names("#text", "text")
                             .expression_attribute_names("#when", "when")
-                            .build(),
-                    )
-                    .send()
-                    .await
+                            .expression_attribute_names("#hidden", "hidden")
+                            .expression_attribute_names("#reason", "reason")
+                                                        .expression_attribute_names("#history", "history")
+                            .expression_attribute_names("#source", "source")
+                            .send()
+                    })
+                    .buffered(n.max(1))
+                    .try_collect()
+                    .await?;
+                // shaped like a BatchGetItem response so callers don't need to care
+                Ok(BatchGetItemOutput::builder()
+                    .set_responses(Some(HashMap::from_iter([(
+                        String::from("questions"),
+                        items
+                            .into_iter()
+                            .filter_map(|item| item.item().cloned())
+                            .collect(),
+                    )])))
+                    .build())
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { questions, .. } = &mut *local;
+                let local = local.read().unwrap();
+                let Local { questions, .. } = &*local;
 
                 let unprocessed: Vec<_> = qids
                     .iter()
@@ -89,7 +258,22 @@ impl Backend {
                                         .get(qid)?
                                         .iter()
                                         .filter(|&(k, _)| {
-                                            matches!(*k, "id" | "text" | "when" | "who")
+                                            matches!(
+                                                *k,
+                                                "id" | "eid"
+                                                    | "text"
+                                                    | "when"
+                                                    | "who"
+                                                    | "frozen"
+                                                    | "hidden"
+                                                    | "reason"
+                                                    | "revises"
+                                                    | "revised"
+                                                    | "author"
+                                                    | "history"
+                                                    | "source"
+                                                    | "merged_into"
+                                            )
                                         })
                                         .map(|(k, v)| (k.to_string(), v.clone()))
                                         .collect(),
@@ -99,17 +283,108 @@ impl Backend {
                     )])))
                     .build())
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.questions(qids).await.map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.questions(qids).await.map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.questions(qids).map_err(super::mint_unhandled),
         }
     }
 }
 
+/// The API's view of a question item, keyed by its id.
+pub(super) fn to_json(q: &HashMap<String, AttributeValue>) -> Option<(String, Value)> {
+    let qid = q
+        .get("id")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok())?;
+    let text = q.get("text").and_then(|v| v.as_s().ok())?;
+    // the retention policy blanks out signatures rather than removing them
+    let who = q
+        .get("who")
+        .and_then(|v| v.as_s().ok())
+        .filter(|v| !v.is_empty());
+    let when = q
+        .get("when")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|v| v.parse::<usize>().ok())?;
+    let mut v = serde_json::json!({
+        "text": text,
+        "when": when,
+    });
+    if let Some(who) = who {
+        v["who"] = who.clone().into();
+    }
+    if let Some(eid) = q
+        .get("eid")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok())
+    {
+        v["permalink"] = super::permalink::url(&eid, &qid).into();
+    }
+    // set when the question is asked, so it's as unchanging as the rest
+    if let Some(revises) = q.get("revises").and_then(|v| v.as_s().ok()) {
+        v["revises"] = revises.clone().into();
+    }
+    // like a survey (see super::survey), if it wasn't asked on the site
+    if let Some(source) = q.get("source").and_then(|v| v.as_s().ok()) {
+        v["source"] = source.clone().into();
+    }
+    // edits only happen before the text is cached for good, so this doesn't change after
+    if q.contains_key("history") {
+        v["edited"] = true.into();
+    }
+    Some((qid.to_string(), v))
+}
+
+/// How long complete answers can be cached, which is as long as anything will keep them.
+const FOREVER: &str = "max-age=864001, immutable";
+
+/// The strong ETag of the questions in `qids`.
+///
+/// What's shown of a question never changes once it can't be edited (and only then are answers
+/// tagged), so the set of questions says it all.
+fn etag(qids: &[Uuid]) -> String {
+    let mut qids = qids.to_vec();
+    qids.sort_unstable();
+    qids.dedup();
+    let mut sha1 = sha1_smol::Sha1::new();
+    for qid in qids {
+        sha1.update(qid.as_bytes());
+    }
+    format!("\"{}\"", &sha1.digest().to_string()[..32])
+}
+
+/// Whether the client already has the questions tagged `etag`, according to `If-None-Match`.
+fn fresh(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        // If-None-Match always compares weakly
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+type Texts = (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Option<AppendHeaders<[(HeaderName, String); 1]>>,
+    Result<Json<Value>, StatusCode>,
+);
+
+/// The most questions `POST /api/questions` can ask for at once.
+const MAX_QIDS: usize = 1000;
+/// How big the body of `POST /api/questions` can be, which is enough for `MAX_QIDS` ids.
+pub(super) const MAX_BODY: usize = 64 * 1024;
+
 pub(super) async fn questions(
     Path(qids): Path<String>,
     State(dynamo): State<Backend>,
-) -> (
-    AppendHeaders<[(HeaderName, &'static str); 1]>,
-    Result<Json<Value>, StatusCode>,
-) {
+    headers: HeaderMap,
+    super::networks::Client(client): super::networks::Client,
+) -> Texts {
     let qids: Vec<_> = match qids.split(',').map(Uuid::parse_str).collect() {
         Ok(v) => v,
         Err(e) => {
@@ -117,81 +392,174 @@ pub(super) async fn questions(
             return (
                 // a bad request will never become good
                 AppendHeaders([(header::CACHE_CONTROL, "max-age=864001")]),
+                None,
                 Err(http::StatusCode::BAD_REQUEST),
             );
         }
     };
-    match dynamo.questions(&qids).await {
-        Ok(v) => {
-            if v.responses().map_or(true, |r| r.is_empty()) {
-                warn!(?qids, "no valid qids");
+    texts(&dynamo, qids, &headers, client).await
+}
+
+/// The same as [`questions`], but with the ids as a JSON array in the body.
+///
+/// Events with lots of questions can ask for more at once than fit in a URL this way.
+pub(super) async fn questions_in_body(
+    State(dynamo): State<Backend>,
+    headers: HeaderMap,
+    super::networks::Client(client): super::networks::Client,
+    Json(qids): Json<Vec<Uuid>>,
+) -> Texts {
+    if qids.is_empty() || qids.len() > MAX_QIDS {
+        warn!(n = qids.len(), "got bad number of qids");
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+            None,
+            Err(http::StatusCode::BAD_REQUEST),
+        );
+    }
+    texts(&dynamo, qids, &headers, client).await
+}
+
+async fn texts(
+    dynamo: &Backend,
+    qids: Vec<Uuid>,
+    headers: &HeaderMap,
+    client: Option<IpAddr>,
+) -> Texts {
+    // only complete answers are tagged, and those stay the same, so there's no need to query
+    let etag = etag(&qids);
+    if fresh(headers, &etag) {
+        trace!(?qids, "questions not modified");
+        return (
+            AppendHeaders([(header::CACHE_CONTROL, FOREVER)]),
+            Some(AppendHeaders([(header::ETAG, etag)])),
+            Err(StatusCode::NOT_MODIFIED),
+        );
+    }
+
+    // only what isn't in memory needs fetching
+    let (mut t, missing) = super::texts::get(&qids);
+    if !missing.is_empty() {
+        let v = match dynamo.questions(&missing).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!(?qids, error = %e, "dynamodb question request failed");
                 return (
-                    // it should be unlikely that someone fetches a question that hasn't been asked
-                    // it's _possible_ that it happens and _then_ a question is assigned that uuid,
-                    // but it too seems rare.
-                    AppendHeaders([(header::CACHE_CONTROL, "max-age=600")]),
-                    Err(http::StatusCode::NOT_FOUND),
+                    AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                    None,
+                    Err(http::StatusCode::INTERNAL_SERVER_ERROR),
                 );
             }
-            let r = v.responses().unwrap();
-            let t = if let Some(t) = r.get("questions") {
-                t
-            } else {
-                error!(?qids, ?v, "got non-empty non-questions response");
+        };
+        match v.responses() {
+            Some(r) if r.values().any(|t| !t.is_empty()) => {
+                let Some(fetched) = r.get("questions") else {
+                    error!(?qids, ?v, "got non-empty non-questions response");
+                    return (
+                        AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+                        None,
+                        Err(http::StatusCode::INTERNAL_SERVER_ERROR),
+                    );
+                };
+                super::texts::put(fetched);
+                t.extend(fetched.iter().cloned());
+            }
+            _ => {}
+        }
+    }
+    if t.is_empty() {
+        warn!(?qids, "no valid qids");
+        return (
+            // it should be unlikely that someone fetches a question that hasn't been asked
+            // it's _possible_ that it happens and _then_ a question is assigned that uuid,
+            // but it too seems rare.
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=600")]),
+            None,
+            Err(http::StatusCode::NOT_FOUND),
+        );
+    }
+
+    // and those of events kept to some networks are only for those networks (see super::networks)
+    let mut reach = HashMap::new();
+    for eid in t.iter().filter_map(|q| {
+        q.get("eid")
+            .and_then(|v| v.as_s().ok())
+            .and_then(|v| Uuid::parse_str(v).ok())
+    }) {
+        if reach.contains_key(&eid) {
+            continue;
+        }
+        match super::networks::reach(dynamo, &eid, client).await {
+            Ok(r) => {
+                reach.insert(eid, r);
+            }
+            Err(status) => {
                 return (
                     AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
-                    Err(http::StatusCode::INTERNAL_SERVER_ERROR),
+                    None,
+                    Err(status),
                 );
-            };
-
-            let r = t
-                .iter()
-                .map(|q| {
-                    let qid = q
-                        .get("id")
-                        .and_then(|v| v.as_s().ok())
-                        .and_then(|v| Uuid::parse_str(v).ok());
-                    let text = q.get("text").and_then(|v| v.as_s().ok());
-                    let who = q.get("who").and_then(|v| v.as_s().ok());
-                    let when = q
-                        .get("when")
-                        .and_then(|v| v.as_n().ok())
-                        .and_then(|v| v.parse::<usize>().ok());
-                    match (qid, text, when) {
-                        (Some(qid), Some(text), Some(when)) => {
-                            let mut v = serde_json::json!({
-                                "text": text,
-                                "when": when,
-                            });
-                            if let Some(who) = who {
-                                v["who"] = who.clone().into();
-                            }
-                            Ok((qid.to_string(), v))
-                        }
-                        _ => {
-                            error!(?qids, ?q, "bad data types for id/text/when");
-                            Err(StatusCode::INTERNAL_SERVER_ERROR)
-                        }
-                    }
-                })
-                .collect::<Result<_, _>>()
-                .map(Json);
-            if r.is_ok() {
-                (
-                    AppendHeaders([(header::CACHE_CONTROL, "max-age=864001")]),
-                    r,
-                )
-            } else {
-                (AppendHeaders([(header::CACHE_CONTROL, "no-cache")]), r)
             }
         }
-        Err(e) => {
-            error!(?qids, error = %e, "dynamodb question request failed");
-            (
-                AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
-                Err(http::StatusCode::INTERNAL_SERVER_ERROR),
-            )
-        }
+    }
+    let kept = reach.values().any(|r| *r != Reach::Open);
+    t.retain(|q| {
+        let eid = q
+            .get("eid")
+            .and_then(|v| v.as_s().ok())
+            .and_then(|v| Uuid::parse_str(v).ok());
+        eid.and_then(|eid| reach.get(&eid)) != Some(&Reach::Refused)
+    });
+    // the questions of frozen events are hidden until they've been reviewed
+    let frozen = t
+        .iter()
+        .filter(|q| super::moderation::is_frozen(Some(q)))
+        .count();
+    let r = t
+        .iter()
+        .filter(|q| !super::moderation::is_frozen(Some(q)))
+        .map(|q| {
+            to_json(q).ok_or_else(|| {
+                error!(?qids, ?q, "bad data types for id/text/when");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Json);
+    if r.is_ok() && kept {
+        debug!(?qids, ?client, "questions of events kept to some networks");
+        // so that shared caches don't hand them to everyone else
+        (
+            AppendHeaders([(header::CACHE_CONTROL, "private, max-age=60")]),
+            None,
+            r,
+        )
+    } else if r.is_ok() && frozen != 0 {
+        warn!(?qids, frozen, "withheld questions of frozen event");
+        (
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=60")]),
+            None,
+            r,
+        )
+    } else if r.is_ok() && t.iter().any(super::author::editable) {
+        // askers can still change some of them, so they're not final yet
+        (
+            AppendHeaders([(header::CACHE_CONTROL, "max-age=10")]),
+            None,
+            r,
+        )
+    } else if r.is_ok() {
+        (
+            AppendHeaders([(header::CACHE_CONTROL, FOREVER)]),
+            Some(AppendHeaders([(header::ETAG, etag)])),
+            r,
+        )
+    } else {
+        (
+            AppendHeaders([(header::CACHE_CONTROL, "no-cache")]),
+            None,
+            r,
+        )
     }
 }
 
@@ -199,37 +567,39 @@ pub(super) async fn questions(
 mod tests {
     use super::*;
 
+    /// Ask a question long enough ago that its asker can't edit it any more.
+    async fn ask_settled(backend: &Backend, eid: &Uuid, body: &str, asker: Option<&str>) -> Uuid {
+        let qid = Uuid::new_v4();
+        let when = crate::clock::now() - Duration::from_secs(crate::author::EDIT_WITHIN);
+        let q = crate::ask::Question {
+            body: body.into(),
+            asker: asker.map(String::from),
+        };
+        backend.ask_at(eid, &qid, q, when, false).await.unwrap();
+        qid
+    }
+
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
         let _secret = e["secret"].as_str().unwrap();
-        let q1 = crate::ask::ask(
-            Path(eid.clone()),
-            State(backend.clone()),
-            Json(crate::ask::Question {
-                body: "hello world".into(),
-                asker: None,
-            }),
-        )
-        .await
-        .unwrap();
-        let qid1 = q1["id"].as_str().unwrap();
-        let q2 = crate::ask::ask(
-            Path(eid.clone()),
+        let qid1 = &ask_settled(&backend, &eid, "hello world", None)
+            .await
+            .to_string();
+        let qid2 = &ask_settled(&backend, &eid, "hello moon", Some("person"))
+            .await
+            .to_string();
+
+        let (cache, etag, qids) = super::questions(
+            Path(format!("{qid1},{qid2}")),
             State(backend.clone()),
-            Json(crate::ask::Question {
-                body: "hello moon".into(),
-                asker: Some("person".into()),
-            }),
+            HeaderMap::new(),
+            crate::networks::Client(None),
         )
-        .await
-        .unwrap();
-        let qid2 = q2["id"].as_str().unwrap();
-
-        let qids = super::questions(Path(format!("{qid1},{qid2}")), State(backend.clone()))
-            .await
-            .1
-            .unwrap();
+        .await;
+        let AppendHeaders([(_, cache)]) = cache;
+        assert_eq!(cache, FOREVER);
+        let qids = qids.unwrap();
 
         let qids = qids.as_object().unwrap();
         let q1 = &qids[qid1];
@@ -241,6 +611,115 @@ mod tests {
         assert_eq!(q2["who"], "person");
         assert!(q2["when"].is_u64());
 
+        // clients that already have the same questions, in any order, get a 304
+        let AppendHeaders([(_, etag)]) = etag.unwrap();
+        let (_, again, res) = super::questions(
+            Path(format!("{qid2},{qid1}")),
+            State(backend.clone()),
+            HeaderMap::from_iter([(header::IF_NONE_MATCH, etag.parse().unwrap())]),
+            crate::networks::Client(None),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_MODIFIED);
+        assert_eq!(again.unwrap().0[0].1, etag);
+        let (_, _, res) = super::questions(
+            Path(qid1.to_string()),
+            State(backend.clone()),
+            HeaderMap::from_iter([(header::IF_NONE_MATCH, etag.parse().unwrap())]),
+            crate::networks::Client(None),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        // and the second time around, they're already in memory
+        let hits = crate::metrics::get("texts.hit");
+        let (_, _, again) = super::questions(
+            Path(format!("{qid1},{qid2}")),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await;
+        assert_eq!(again.unwrap().0, serde_json::Value::from(qids.clone()));
+        assert!(crate::metrics::get("texts.hit") >= hits + 2);
+
+        // questions their askers can still edit aren't final
+        let q3 = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "hello sun".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid3 = q3["id"].as_str().unwrap();
+        let (AppendHeaders([(_, cache)]), etag, res) = super::questions(
+            Path(format!("{qid1},{qid3}")),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await;
+        assert_eq!(cache, "max-age=10");
+        assert!(etag.is_none());
+        assert_eq!(res.unwrap().0[qid3]["text"], "hello sun");
+        assert!(!crate::texts::get(&[Uuid::parse_str(qid3).unwrap()])
+            .1
+            .is_empty());
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn in_body() {
+        let backend = Backend::local().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let mut qids = Vec::new();
+        for body in ["hello world", "hello moon"] {
+            qids.push(ask_settled(&backend, &eid, body, None).await);
+        }
+
+        let (_, etag, posted) = questions_in_body(
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+            Json(qids.clone()),
+        )
+        .await;
+        let (_, _, got) = super::questions(
+            Path(format!("{},{}", qids[0], qids[1])),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await;
+        assert_eq!(posted.unwrap().0, got.unwrap().0);
+        assert!(etag.is_some());
+
+        let (_, _, none) = questions_in_body(
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+            Json(Vec::new()),
+        )
+        .await;
+        assert_eq!(none.unwrap_err(), StatusCode::BAD_REQUEST);
+        let too_many = vec![qids[0]; MAX_QIDS + 1];
+        let (_, _, res) = questions_in_body(
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+            Json(too_many),
+        )
+        .await;
+        assert_eq!(res.unwrap_err(), StatusCode::BAD_REQUEST);
+        // and the body limit leaves room for as many as are allowed
+        assert!(serde_json::to_vec(&vec![qids[0]; MAX_QIDS]).unwrap().len() <= MAX_BODY);
+
         backend.delete(&eid).await;
     }
 
@@ -254,4 +733,152 @@ mod tests {
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[tokio::test]
+    async fn golden() {
+        let qids = "6f2e9d14-8a7b-4c3e-b1d0-5a9f8e7c6b21,a3c5e7f9-1b2d-4f6a-8c0e-2d4f6a8c0e13";
+        let (backend, replay) = crate::golden::replay("questions");
+        replay.check(
+            super::questions(
+                Path(qids.to_string()),
+                State(backend),
+                HeaderMap::new(),
+                crate::networks::Client(None),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb_get_item() {
+        let backend = Backend::dynamo().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let mut qids = Vec::new();
+        for body in ["hello world", "hello moon", "hello sun"] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: Some("person".into()),
+                }),
+            )
+            .await
+            .unwrap();
+            qids.push(Uuid::parse_str(q["id"].as_str().unwrap()).unwrap());
+        }
+        // one that doesn't exist
+        qids.push(Uuid::new_v4());
+
+        let items = |v: BatchGetItemOutput| {
+            let mut qs = v.responses().unwrap()["questions"].clone();
+            qs.sort_by_key(|q| q["id"].as_s().unwrap().clone());
+            qs
+        };
+        let batch = items(backend.questions_with(&qids, Fetch::Batch).await.unwrap());
+        let each = items(backend.questions_with(&qids, Fetch::Each(2)).await.unwrap());
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch, each);
+
+        // more than fit in one BatchGetItem
+        qids.extend((0..2 * MAX_BATCH).map(|_| Uuid::new_v4()));
+        let many = items(backend.questions_with(&qids, Fetch::Batch).await.unwrap());
+        assert_eq!(many, batch);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn policy_grants_fetches() {
+        let qids = [Uuid::new_v4(), Uuid::new_v4()];
+        for (fetch, calls) in [(Fetch::Batch, 1), (Fetch::Each(2), 2)] {
+            let (backend, conn) = crate::golden::answering(calls);
+            backend.questions_with(&qids, fetch).await.unwrap();
+            let requests = conn.requests();
+            assert_eq!(requests.len(), calls);
+            for req in requests.iter() {
+                let target = req.actual.headers()["x-amz-target"].to_str().unwrap();
+                let action = target.replace("DynamoDB_20120810.", "dynamodb:");
+                let body: serde_json::Value =
+                    serde_json::from_slice(req.actual.body().bytes().unwrap()).unwrap();
+                let table = match body["RequestItems"].as_object() {
+                    Some(tables) => tables.keys().next().unwrap().as_str(),
+                    None => body["TableName"].as_str().unwrap(),
+                };
+                assert!(
+                    crate::infra::grants(table, &action),
+                    "{fetch:?} needs {action} on {table}, which the policy doesn't grant"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn merges_batches() {
+        let item =
+            |qid: &str| HashMap::from_iter([(String::from("id"), AttributeValue::S(qid.into()))]);
+        let batch = |qids: &[&str], unprocessed: &[&str]| {
+            let unprocessed = (!unprocessed.is_empty()).then(|| {
+                HashMap::from_iter([(
+                    String::from("questions"),
+                    KeysAndAttributes::builder()
+                        .set_keys(Some(unprocessed.iter().map(|qid| item(qid)).collect()))
+                        .build(),
+                )])
+            });
+            BatchGetItemOutput::builder()
+                .set_responses(Some(HashMap::from_iter([(
+                    String::from("questions"),
+                    qids.iter().map(|qid| item(qid)).collect(),
+                )])))
+                .set_unprocessed_keys(unprocessed)
+                .build()
+        };
+
+        let merged = merge(vec![batch(&["a", "b"], &[]), batch(&["c"], &["d"])]);
+        assert_eq!(
+            merged.responses().unwrap()["questions"],
+            vec![item("a"), item("b"), item("c")]
+        );
+        assert_eq!(
+            merged.unprocessed_keys().unwrap()["questions"].keys(),
+            Some(&[item("d")][..])
+        );
+        assert!(merge(vec![batch(&["a"], &[])]).unprocessed_keys().is_none());
+    }
+
+    #[test]
+    fn backs_off() {
+        assert_eq!(backoff(0), BACKOFF);
+        assert_eq!(backoff(1), 2 * BACKOFF);
+        assert_eq!(backoff(2), 4 * BACKOFF);
+        assert_eq!(backoff(MAX_RETRIES + 10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
 }
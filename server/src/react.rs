@@ -0,0 +1,366 @@
+//! Reacting to questions with an emoji, for when a vote doesn't say enough.
+//!
+//! Guests can `POST /api/vote/:eid/:qid/react/:emoji` with one of a handful of emoji, which adds
+//! one to that emoji's count in the question's `reactions` map. Reactions don't count as votes,
+//! and don't move the question in the list, but the list shows their counts as `reactions`
+//! (leaving out emoji nobody used, and the whole map if nobody reacted). Like votes, reactions are
+//! in the change log (as `reacted`), and can't be taken back.
+
+use super::{changes::Change, Backend, Local};
+use aws_sdk_dynamodb::{
+    error::{UpdateItemError, UpdateItemErrorKind},
+    model::{AttributeValue, ReturnValue},
+    output::UpdateItemOutput,
+    types::SdkError,
+};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, str::FromStr};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum Reaction {
+    ThumbsUp,
+    Heart,
+    Laugh,
+    Party,
+    Thinking,
+}
+
+impl Reaction {
+    pub(super) const ALL: [Reaction; 5] = [
+        Reaction::ThumbsUp,
+        Reaction::Heart,
+        Reaction::Laugh,
+        Reaction::Party,
+        Reaction::Thinking,
+    ];
+
+    /// The emoji, which is also its key in the `reactions` map.
+    pub(super) fn emoji(self) -> &'static str {
+        match self {
+            Reaction::ThumbsUp => "👍",
+            Reaction::Heart => "❤️",
+            Reaction::Laugh => "😂",
+            Reaction::Party => "🎉",
+            Reaction::Thinking => "🤔",
+        }
+    }
+}
+
+impl fmt::Display for Reaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.emoji())
+    }
+}
+
+impl FromStr for Reaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // plenty of keyboards leave out the variation selector
+        let bare = |r: &Reaction| r.emoji().trim_end_matches('\u{fe0f}');
+        Self::ALL
+            .into_iter()
+            .find(|r| bare(r) == s.trim_end_matches('\u{fe0f}'))
+            .ok_or_else(|| format!("no reaction {s:?}"))
+    }
+}
+
+/// How many times a question was reacted to with each [`Reaction`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Reactions([u64; Reaction::ALL.len()]);
+
+impl Reactions {
+    pub(super) fn add(&mut self, reaction: Reaction, n: u64) {
+        let i = Reaction::ALL.iter().position(|r| *r == reaction).unwrap();
+        self.0[i] += n;
+    }
+
+    /// The counts in a question's `reactions` map, ignoring emoji that aren't reactions (anymore).
+    pub(super) fn from_item(reactions: Option<&AttributeValue>) -> Self {
+        let mut counts = Self::default();
+        let Some(AttributeValue::M(m)) = reactions else {
+            return counts;
+        };
+        for (emoji, n) in m {
+            let n = n.as_n().ok().and_then(|n| n.parse().ok());
+            if let (Ok(reaction), Some(n)) = (emoji.parse(), n) {
+                counts.add(reaction, n);
+            }
+        }
+        counts
+    }
+
+    /// The reactions as they appear in list responses, if there were any.
+    pub(super) fn to_json(self) -> Option<serde_json::Value> {
+        let counts: serde_json::Map<_, _> = Reaction::ALL
+            .into_iter()
+            .zip(self.0)
+            .filter(|&(_, n)| n != 0)
+            .map(|(r, n)| (r.emoji().to_string(), n.into()))
+            .collect();
+        (!counts.is_empty()).then(|| counts.into())
+    }
+}
+
+/// Add one to the count of `reaction` in a question's `reactions` map.
+pub(super) fn bump(reactions: &mut AttributeValue, reaction: Reaction) {
+    let AttributeValue::M(m) = reactions else {
+        return;
+    };
+    let n = m
+        .entry(reaction.emoji().to_string())
+        .or_insert_with(|| AttributeValue::N(0.to_string()));
+    if let AttributeValue::N(n) = n {
+        *n = (n.parse::<u64>().unwrap_or(0) + 1).to_string();
+    }
+}
+
+impl Backend {
+    /// Add one to the count of `reaction` on `qid`, if it's a question of `eid`.
+    ///
+    /// DynamoDB (and the in-memory backend) refuse to react to questions of other events with a
+    /// `ConditionalCheckFailedException`. The other backends leave the question as it is, and
+    /// return it as it is, if at all.
+    pub(super) async fn react(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        reaction: Reaction,
+    ) -> Result<UpdateItemOutput, SdkError<UpdateItemError>> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let add = || {
+                    dynamo
+                        .update_item()
+                        .table_name("questions")
+                        .key("id", AttributeValue::S(qid.to_string()))
+                        .update_expression(
+                            "SET reactions.#emoji = if_not_exists(reactions.#emoji, :zero) + :one",
+                        )
+                        .condition_expression("eid = :eid")
+                        .expression_attribute_names("#emoji", reaction.emoji())
+                        .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()))
+                        .expression_attribute_values(":zero", AttributeValue::N(0.to_string()))
+                        .expression_attribute_values(":one", AttributeValue::N(1.to_string()))
+                        .return_values(ReturnValue::AllNew)
+                        .send()
+                };
+                match add().await {
+                    // questions nobody has reacted to yet have no map to add to
+                    Err(SdkError::ServiceError { ref err, .. })
+                        if err.code() == Some("ValidationException") =>
+                    {
+                        dynamo
+                            .update_item()
+                            .table_name("questions")
+                            .key("id", AttributeValue::S(qid.to_string()))
+                            .update_expression("SET reactions = if_not_exists(reactions, :empty)")
+                            .condition_expression("eid = :eid")
+                            .expression_attribute_values(":eid", AttributeValue::S(eid.to_string()))
+                            .expression_attribute_values(
+                                ":empty",
+                                AttributeValue::M(HashMap::new()),
+                            )
+                            .send()
+                            .await?;
+                        add().await
+                    }
+                    r => r,
+                }
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                let q = questions
+                    .get_mut(qid)
+                    .filter(|q| q["eid"] == AttributeValue::S(eid.to_string()));
+                let Some(q) = q else {
+                    return Err(super::mint_service_error(UpdateItemError::new(
+                        UpdateItemErrorKind::ConditionalCheckFailedException(
+                            aws_sdk_dynamodb::error::ConditionalCheckFailedException::builder()
+                                .message("reacting to question of another event")
+                                .build(),
+                        ),
+                        Error::builder()
+                            .code("ConditionalCheckFailedException")
+                            .build(),
+                    )));
+                };
+                bump(
+                    q.entry("reactions")
+                        .or_insert_with(|| AttributeValue::M(HashMap::new())),
+                    reaction,
+                );
+                journal.question(qid, q);
+                Ok(UpdateItemOutput::builder()
+                    .set_attributes(Some(
+                        q.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+                    ))
+                    .build())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.react(eid, qid, reaction).await,
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.react(eid, qid, reaction).await,
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.react(eid, qid, reaction).await,
+        }
+    }
+}
+
+pub(super) async fn react(
+    Path((eid, qid, emoji)): Path<(Uuid, Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let Ok(reaction) = emoji.parse::<Reaction>() else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    if super::get_meta(&dynamo, &eid).await?.frozen() {
+        warn!(%eid, %qid, "reaction to question of frozen event");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match dynamo.react(&eid, &qid, reaction).await {
+        Ok(v) => {
+            let attributes = v.attributes();
+            let of = attributes
+                .and_then(|a| a.get("eid"))
+                .and_then(|v| v.as_s().ok());
+            if of != Some(&eid.to_string()) {
+                warn!(%eid, %qid, "reaction to question of another event");
+                return Err(StatusCode::NOT_FOUND);
+            }
+            debug!(%eid, %qid, %reaction, "reacted to question");
+            dynamo
+                .try_record(&eid, Change::Reacted { qid, reaction })
+                .await;
+            super::budget::spend(&eid, super::budget::cost("react"));
+            let reactions = Reactions::from_item(attributes.and_then(|a| a.get("reactions")));
+            Ok(Json(serde_json::json!({
+                "reactions": reactions.to_json().unwrap_or_else(|| serde_json::json!({})),
+            })))
+        }
+        Err(SdkError::ServiceError { ref err, .. })
+            if err.is_conditional_check_failed_exception() =>
+        {
+            warn!(%eid, %qid, "reaction to question of another event");
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!(%eid, %qid, error = %e, "dynamodb request to react to question failed");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses() {
+        for r in Reaction::ALL {
+            assert_eq!(r.emoji().parse::<Reaction>(), Ok(r));
+        }
+        assert_eq!("\u{2764}".parse::<Reaction>(), Ok(Reaction::Heart));
+        assert!("🦀".parse::<Reaction>().is_err());
+        assert!("".parse::<Reaction>().is_err());
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "can we have cake on fridays?".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let react =
+            |eid, emoji: &str| react(Path((eid, qid, emoji.to_string())), State(backend.clone()));
+        let list = || async {
+            let list = backend.list(&eid, false).await.unwrap();
+            let (qid, q) = crate::list::parse(&eid, &list.items().unwrap()[0]).unwrap();
+            q.to_json(&qid)
+        };
+
+        assert_eq!(list().await.get("reactions"), None);
+        react(eid, "🎉").await.unwrap();
+        react(eid, "👍").await.unwrap();
+        let Json(r) = react(eid, "🎉").await.unwrap();
+        assert_eq!(r["reactions"], serde_json::json!({ "👍": 1, "🎉": 2 }));
+        assert_eq!(
+            list().await["reactions"],
+            serde_json::json!({ "👍": 1, "🎉": 2 })
+        );
+        assert_eq!(list().await["votes"], 1, "reactions aren't votes");
+
+        assert_eq!(react(eid, "🦀").await.unwrap_err(), StatusCode::BAD_REQUEST);
+        let other = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let other = Uuid::parse_str(other["id"].as_str().unwrap()).unwrap();
+        assert_eq!(react(other, "👍").await.unwrap_err(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            list().await["reactions"],
+            serde_json::json!({ "👍": 1, "🎉": 2 })
+        );
+
+        let changes = backend.all_changes(&eid).await.unwrap();
+        assert_eq!(
+            changes.last(),
+            Some(&Change::Reacted {
+                qid,
+                reaction: Reaction::Party
+            })
+        );
+
+        backend.delete(&eid).await;
+        backend.delete(&other).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,521 @@
+//! Reconstruct an event's derived state by replaying its change log.
+//!
+//! Vote counts and the answered/hidden flags on questions are really just a summary of the
+//! event's log in `changes`. If they ever go wrong (or you want to check that they haven't), the
+//! `rebuild` command replays the log, compares the result with what's stored, and can optionally
+//! write the replayed state back.
+//!
+//! Changes are recorded after the fact, and a change that fails to record doesn't fail what caused
+//! it (see [`Backend::try_record`]), so the log can come up short. Writing back a log that's short
+//! would throw away real votes, so `--apply` refuses to when the log is missing changes, when it has
+//! fewer votes for a question than are stored, or when it takes a question below zero votes (which
+//! the stored count never goes).
+
+use super::{changes::Change, vote::UpDown, Backend, Local};
+use aws_sdk_dynamodb::{
+    error::UpdateItemError, model::AttributeValue, output::UpdateItemOutput, types::SdkError,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct State {
+    pub(super) votes: i64,
+    pub(super) answered: bool,
+    pub(super) hidden: bool,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub(super) struct Stats {
+    pub(super) changes: usize,
+    pub(super) questions: usize,
+    pub(super) upvotes: usize,
+    pub(super) downvotes: usize,
+    pub(super) answered: usize,
+    pub(super) hidden: usize,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Replayed {
+    /// Questions in the order they were asked.
+    pub(super) questions: Vec<(Uuid, State)>,
+    pub(super) stats: Stats,
+}
+
+impl Replayed {
+    /// Question ids ordered the same way the `top` index orders them.
+    pub(super) fn ranking(&self) -> Vec<Uuid> {
+        let mut qs: Vec<_> = self.questions.iter().collect();
+        // stable, so ties stay in the order they were asked
+        qs.sort_by_key(|(_, s)| std::cmp::Reverse(s.votes));
+        qs.into_iter().map(|(qid, _)| *qid).collect()
+    }
+}
+
+/// Apply changes, in sequence order, to an empty event.
+pub(super) fn replay(changes: impl IntoIterator<Item = Change>) -> Replayed {
+    let mut r = Replayed::default();
+    let mut index = HashMap::new();
+    for change in changes {
+        r.stats.changes += 1;
+        match change {
+            // the state of the question list doesn't include texts (or reactions)
+            Change::EventCreated
+            | Change::Announced
+            | Change::QuestionEdited { .. }
+            | Change::Reacted { .. } => {}
+            Change::QuestionAsked { qid } => {
+                index.insert(qid, r.questions.len());
+                r.questions.push((
+                    qid,
+                    State {
+                        // asking a question counts as voting for it (see `ask`)
+                        votes: 1,
+                        answered: false,
+                        hidden: false,
+                    },
+                ));
+            }
+            Change::VoteCast { qid, direction } => {
+                let Some(&i) = index.get(&qid) else {
+                    warn!(%qid, "vote for question that was never asked");
+                    continue;
+                };
+                match direction {
+                    UpDown::Up => {
+                        r.questions[i].1.votes += 1;
+                        r.stats.upvotes += 1;
+                    }
+                    UpDown::Down => {
+                        r.questions[i].1.votes -= 1;
+                        r.stats.downvotes += 1;
+                    }
+                }
+            }
+            Change::QuestionAnswered { qid, set } | Change::QuestionHidden { qid, set } => {
+                let Some(&i) = index.get(&qid) else {
+                    warn!(%qid, "toggle for question that was never asked");
+                    continue;
+                };
+                if let Change::QuestionAnswered { .. } = change {
+                    r.questions[i].1.answered = set;
+                } else {
+                    r.questions[i].1.hidden = set;
+                }
+            }
+            Change::QuestionMerged { qid, into, votes } => {
+                let (Some(&i), Some(&j)) = (index.get(&qid), index.get(&into)) else {
+                    warn!(%qid, %into, "merge of questions that were never asked");
+                    continue;
+                };
+                r.questions[i].1.hidden = true;
+                r.questions[j].1.votes += votes as i64;
+            }
+            Change::VotesHeld { qid, votes } | Change::VotesReleased { qid, votes } => {
+                let Some(&i) = index.get(&qid) else {
+                    warn!(%qid, "held votes for question that was never asked");
+                    continue;
+                };
+                if let Change::VotesHeld { .. } = change {
+                    r.questions[i].1.votes -= votes as i64;
+                } else {
+                    r.questions[i].1.votes += votes as i64;
+                }
+            }
+        }
+    }
+    r.stats.questions = r.questions.len();
+    r.stats.answered = r.questions.iter().filter(|(_, s)| s.answered).count();
+    r.stats.hidden = r.questions.iter().filter(|(_, s)| s.hidden).count();
+    r
+}
+
+/// Why the `replayed` state can't be written over the `stored` one, if it can't.
+///
+/// `logged` are the sequence numbers of the changes that were replayed, and `version` is the last
+/// sequence number the event handed out.
+fn distrust(
+    version: u64,
+    logged: &[u64],
+    replayed: &Replayed,
+    stored: &HashMap<Uuid, State>,
+) -> Option<String> {
+    let missing =
+        version.saturating_sub(logged.iter().filter(|&&seq| seq <= version).count() as u64);
+    if missing != 0 {
+        return Some(format!("the log is missing {missing} of {version} changes"));
+    }
+    for (qid, state) in &replayed.questions {
+        if state.votes < 0 {
+            return Some(format!("the log takes {qid} below zero votes"));
+        }
+        if stored.get(qid).is_some_and(|s| s.votes > state.votes) {
+            return Some(format!("{qid} has more votes stored than the log has"));
+        }
+    }
+    None
+}
+
+impl Backend {
+    /// The event's entire change log.
+    #[cfg(test)]
+    pub(super) async fn all_changes(
+        &self,
+        eid: &Uuid,
+    ) -> Result<Vec<Change>, aws_sdk_dynamodb::Error> {
+        Ok(self
+            .numbered_changes(eid)
+            .await?
+            .into_iter()
+            .map(|(_, change)| change)
+            .collect())
+    }
+
+    /// The event's entire change log, with each change's sequence number.
+    ///
+    /// Malformed changes are left out.
+    async fn numbered_changes(
+        &self,
+        eid: &Uuid,
+    ) -> Result<Vec<(u64, Change)>, aws_sdk_dynamodb::Error> {
+        let mut all = Vec::new();
+        let mut since = 0;
+        loop {
+            let page = self.changes(eid, since).await?;
+            let items = page.items().unwrap_or_default();
+            if items.is_empty() {
+                break;
+            }
+            for item in items {
+                since = item
+                    .get("seq")
+                    .and_then(|v| v.as_n().ok())
+                    .and_then(|v| v.parse().ok())
+                    .expect("seq is always a number");
+                match Change::from_item(item) {
+                    Some(c) => all.push((since, c)),
+                    None => error!(%eid, ?item, "skipping malformed change"),
+                }
+            }
+        }
+        Ok(all)
+    }
+
+    /// Overwrite the derived state of a question.
+    pub(super) async fn set_state(
+        &self,
+        qid: &Uuid,
+        state: State,
+    ) -> Result<UpdateItemOutput, SdkError<UpdateItemError>> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression(
+                        "SET votes = :votes, answered = :answered, hidden = :hidden",
+                    );
+                // the questions being rebuilt were just read, so the condition is only a safeguard
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.expression_attribute_values(
+                    ":votes",
+                    AttributeValue::N(state.votes.to_string()),
+                )
+                .expression_attribute_values(":answered", AttributeValue::Bool(state.answered))
+                .expression_attribute_values(":hidden", AttributeValue::Bool(state.hidden))
+                .send()
+                .await
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                let q = questions
+                    .get_mut(qid)
+                    .expect("rebuilding question that doesn't exist");
+                q.insert("votes", AttributeValue::N(state.votes.to_string()));
+                q.insert("answered", AttributeValue::Bool(state.answered));
+                q.insert("hidden", AttributeValue::Bool(state.hidden));
+                journal.question(qid, q);
+                Ok(UpdateItemOutput::builder().build())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let set = mongodb::bson::doc! {
+                    "votes": state.votes,
+                    "answered": state.answered,
+                    "hidden": state.hidden,
+                };
+                mongo.update(qid, mongodb::bson::doc! { "$set": set }).await
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                let fields = [
+                    ("votes", AttributeValue::N(state.votes.to_string())),
+                    ("answered", AttributeValue::Bool(state.answered)),
+                    ("hidden", AttributeValue::Bool(state.hidden)),
+                ];
+                redis.set(qid, &fields).await
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let fields = [
+                    ("votes", AttributeValue::N(state.votes.to_string())),
+                    ("answered", AttributeValue::Bool(state.answered)),
+                    ("hidden", AttributeValue::Bool(state.hidden)),
+                ];
+                sled.set(qid, &fields).await
+            }
+        }
+    }
+
+    /// Replay the event's log, compare it with the stored state, and (if `apply`, and the log can
+    /// be trusted) fix any differences.
+    pub(super) async fn rebuild(
+        &self,
+        eid: &Uuid,
+        apply: bool,
+    ) -> Result<serde_json::Value, aws_sdk_dynamodb::Error> {
+        let version = super::get_meta(self, eid)
+            .await
+            .map_err(|status| {
+                aws_sdk_dynamodb::Error::Unhandled(
+                    format!("failed to read event {eid}: {status}").into(),
+                )
+            })?
+            .version;
+        let (logged, changes): (Vec<_>, Vec<_>) =
+            self.numbered_changes(eid).await?.into_iter().unzip();
+        let replayed = replay(changes);
+
+        let stored = self.list(eid, true).await?;
+        let stored: HashMap<_, _> = stored
+            .items()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|doc| {
+                let qid = Uuid::parse_str(doc.get("id")?.as_s().ok()?).ok()?;
+                let state = State {
+                    votes: doc.get("votes")?.as_n().ok()?.parse().ok()?,
+                    answered: *doc.get("answered")?.as_bool().ok()?,
+                    hidden: *doc.get("hidden")?.as_bool().ok()?,
+                };
+                Some((qid, state))
+            })
+            .collect();
+
+        let refused = distrust(version, &logged, &replayed, &stored);
+        if let Some(ref reason) = refused {
+            warn!(%eid, reason, "not trusting the log over the stored state");
+        }
+        let apply = apply && refused.is_none();
+
+        let mut mismatched = Vec::new();
+        for (qid, state) in &replayed.questions {
+            match stored.get(qid) {
+                Some(s) if s == state => {}
+                Some(s) => {
+                    warn!(%eid, %qid, stored = ?s, replayed = ?state, "stored state differs from log");
+                    mismatched.push(serde_json::json!({
+                        "qid": qid.to_string(),
+                        "stored": s,
+                        "replayed": state,
+                    }));
+                    if apply {
+                        self.set_state(qid, *state).await?;
+                    }
+                }
+                None => {
+                    // most likely expired, which takes the question but not the log with it
+                    debug!(%eid, %qid, "question in log is no longer stored");
+                }
+            }
+        }
+        let unlogged: Vec<_> = stored
+            .keys()
+            .filter(|qid| !replayed.questions.iter().any(|(q, _)| q == *qid))
+            .map(|qid| qid.to_string())
+            .collect();
+
+        let states: HashMap<_, _> = replayed.questions.iter().copied().collect();
+        let ranking: Vec<_> = replayed
+            .ranking()
+            .into_iter()
+            .map(|qid| {
+                let s = states[&qid];
+                serde_json::json!({
+                    "qid": qid.to_string(),
+                    "votes": s.votes,
+                    "answered": s.answered,
+                    "hidden": s.hidden,
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "eid": eid.to_string(),
+            "stats": replayed.stats,
+            "ranking": ranking,
+            "mismatched": mismatched,
+            "unlogged": unlogged,
+            "refused": refused,
+            "applied": apply && !mismatched.is_empty(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::Json;
+    use http::HeaderMap;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let ask = |body: &'static str| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+        };
+        let q1 = ask("hello world").await.unwrap();
+        let qid1 = Uuid::parse_str(q1["id"].as_str().unwrap()).unwrap();
+        let q2 = ask("hello moon").await.unwrap();
+        let qid2 = Uuid::parse_str(q2["id"].as_str().unwrap()).unwrap();
+        crate::vote::vote(
+            Path((qid2, UpDown::Up)),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
+
+        // nothing to fix yet
+        let r = backend.rebuild(&eid, false).await.unwrap();
+        assert_eq!(r["mismatched"].as_array().unwrap().len(), 0);
+        assert_eq!(r["ranking"][0]["qid"], qid2.to_string());
+        assert_eq!(r["ranking"][0]["votes"], 2);
+        assert_eq!(r["stats"]["questions"], 2);
+        assert_eq!(r["stats"]["upvotes"], 1);
+
+        // votes that bypass the log corrupt the counter
+        backend.vote(&qid2, UpDown::Down).await.unwrap();
+        let r = backend.rebuild(&eid, false).await.unwrap();
+        assert_eq!(r["mismatched"][0]["qid"], qid2.to_string());
+        assert_eq!(r["mismatched"][0]["stored"]["votes"], 1);
+        assert_eq!(r["mismatched"][0]["replayed"]["votes"], 2);
+        assert_eq!(r["refused"], serde_json::Value::Null);
+        assert_eq!(r["applied"], false);
+
+        let r = backend.rebuild(&eid, true).await.unwrap();
+        assert_eq!(r["applied"], true);
+        let r = backend.rebuild(&eid, false).await.unwrap();
+        assert_eq!(r["mismatched"].as_array().unwrap().len(), 0);
+
+        // but votes the log doesn't have are more likely missing from the log than made up
+        backend.vote(&qid1, UpDown::Up).await.unwrap();
+        backend.vote(&qid1, UpDown::Up).await.unwrap();
+        let r = backend.rebuild(&eid, true).await.unwrap();
+        assert_eq!(r["mismatched"][0]["qid"], qid1.to_string());
+        assert_eq!(r["mismatched"][0]["stored"]["votes"], 3);
+        assert_eq!(r["mismatched"][0]["replayed"]["votes"], 1);
+        assert!(r["refused"].is_string());
+        assert_eq!(r["applied"], false);
+        let r = backend.rebuild(&eid, false).await.unwrap();
+        assert_eq!(r["mismatched"][0]["stored"]["votes"], 3);
+
+        backend.delete(&eid).await;
+    }
+
+    #[test]
+    fn distrusts() {
+        let qid = Uuid::new_v4();
+        let replayed = replay([
+            Change::EventCreated,
+            Change::QuestionAsked { qid },
+            Change::VoteCast {
+                qid,
+                direction: UpDown::Down,
+            },
+        ]);
+        let stored = |votes| {
+            HashMap::from([(
+                qid,
+                super::State {
+                    votes,
+                    answered: false,
+                    hidden: false,
+                },
+            )])
+        };
+        assert_eq!(distrust(3, &[1, 2, 3], &replayed, &stored(0)), None);
+        // a change that never made it into the log
+        assert!(distrust(4, &[1, 2, 3], &replayed, &stored(0)).is_some());
+        assert!(distrust(4, &[1, 3, 4], &replayed, &stored(0)).is_some());
+        // a vote that never made it into the log
+        assert!(distrust(3, &[1, 2, 3], &replayed, &stored(1)).is_some());
+
+        let replayed = replay([
+            Change::QuestionAsked { qid },
+            Change::VoteCast {
+                qid,
+                direction: UpDown::Down,
+            },
+            Change::VoteCast {
+                qid,
+                direction: UpDown::Down,
+            },
+        ]);
+        assert!(distrust(3, &[1, 2, 3], &replayed, &stored(0)).is_some());
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,738 @@
+//! A Redis backend, for events whose questions should vanish once they're over.
+//!
+//! Every key belonging to an event expires at the same moment as the event itself, which is
+//! `REDIS_TTL_HOURS` after it's created, so nothing outlives it. Events on legal hold have no
+//! `expire`, and none of their keys expire until the hold is released. Each event keeps its questions in
+//! a sorted set ranked by votes, which makes listing them in order about as cheap as it gets.
+//!
+//! The layout is:
+//!
+//! - `event:<eid>`: a hash with the event's `secret`, `when`, `expire`, and `seq`.
+//! - `event:<eid>:top`: a sorted set of the event's question ids, scored by votes.
+//! - `event:<eid>:changes`: a sorted set of the event's change log as JSON, scored by `seq`.
+//! - `question:<qid>`: a hash with the same fields as a DynamoDB question item, except that the
+//!   counts in the `reactions` map are fields of their own, like `reactions.👍`.
+//!
+//! Like the other non-DynamoDB backends, results are shaped like DynamoDB responses.
+
+use super::{
+    announce::{Announcement, Scheduled},
+    react::Reaction,
+    Meta,
+};
+use aws_sdk_dynamodb::{
+    model::AttributeValue,
+    output::{BatchGetItemOutput, GetItemOutput, PutItemOutput, QueryOutput, UpdateItemOutput},
+    types::SdkError,
+};
+use redis::{aio::ConnectionManager, AsyncCommands, RedisError};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Clone)]
+pub(super) struct Redis {
+    conn: ConnectionManager,
+}
+
+impl std::fmt::Debug for Redis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redis").finish_non_exhaustive()
+    }
+}
+
+fn event_key(eid: &Uuid) -> String {
+    format!("event:{eid}")
+}
+
+fn top_key(eid: &Uuid) -> String {
+    format!("event:{eid}:top")
+}
+
+fn changes_key(eid: &Uuid) -> String {
+    format!("event:{eid}:changes")
+}
+
+fn question_key(qid: &Uuid) -> String {
+    format!("question:{qid}")
+}
+
+/// Turn a hash field back into the attribute it was stored from.
+fn attribute(field: &str, v: String) -> AttributeValue {
+    match field {
+        "votes" | "when" | "expire" | "seq" | "at" => AttributeValue::N(v),
+        "hidden" | "answered" | "set" | "frozen" => AttributeValue::Bool(v == "true"),
+        _ => AttributeValue::S(v),
+    }
+}
+
+fn to_field(v: AttributeValue) -> String {
+    match v {
+        AttributeValue::S(s) | AttributeValue::N(s) => s,
+        AttributeValue::Bool(b) => b.to_string(),
+        v => unreachable!("no attributes are of type {v:?}"),
+    }
+}
+
+fn to_item(fields: impl IntoIterator<Item = (String, String)>) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::new();
+    let mut reactions = HashMap::new();
+    for (k, v) in fields {
+        if let Some(emoji) = k.strip_prefix("reactions.") {
+            reactions.insert(emoji.to_string(), AttributeValue::N(v));
+        } else {
+            let v = attribute(&k, v);
+            item.insert(k, v);
+        }
+    }
+    if !reactions.is_empty() {
+        item.insert(String::from("reactions"), AttributeValue::M(reactions));
+    }
+    item
+}
+
+/// Look up `fields` of each of `qids`, skipping questions that don't exist (anymore).
+async fn questions(
+    conn: &mut ConnectionManager,
+    qids: &[String],
+    fields: &[&str],
+) -> Result<Vec<HashMap<String, AttributeValue>>, RedisError> {
+    let mut pipe = redis::pipe();
+    for qid in qids {
+        pipe.cmd("HMGET").arg(format!("question:{qid}")).arg(fields);
+    }
+    let found: Vec<Vec<Option<String>>> = pipe.query_async(conn).await?;
+    Ok(qids
+        .iter()
+        .zip(found)
+        .filter(|(_, values)| values.iter().any(Option::is_some))
+        .map(|(qid, values)| {
+            let mut item = to_item(
+                fields
+                    .iter()
+                    .zip(values)
+                    .filter_map(|(k, v)| Some((k.to_string(), v?))),
+            );
+            item.insert(String::from("id"), AttributeValue::S(qid.clone()));
+            item
+        })
+        .collect())
+}
+
+impl Redis {
+    pub(super) async fn connect(url: &str) -> Result<Self, RedisError> {
+        let client = redis::Client::open(url)?;
+        Ok(Redis {
+            conn: ConnectionManager::new(client).await?,
+        })
+    }
+
+    /// When everything belonging to `eid` expires (`Some(None)` if it's on legal hold, and doesn't),
+    /// or `None` if the event doesn't exist.
+    async fn expiry(&self, eid: &Uuid) -> Result<Option<Option<usize>>, RedisError> {
+        let (secret, expire): (Option<String>, Option<String>) = self
+            .conn
+            .clone()
+            .hget(event_key(eid), &["secret", "expire"])
+            .await?;
+        Ok(secret.map(|_| expire.and_then(|e| e.parse().ok())))
+    }
+
+    /// Store a new event, which will vanish `REDIS_TTL_HOURS` from now.
+    pub(super) async fn create_event<E>(
+        &self,
+        eid: &Uuid,
+        item: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<PutItemOutput, SdkError<E>> {
+        let ttl = super::config::config().redis_ttl;
+        let now = std::time::SystemTime::now();
+        let expire = (now + ttl)
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as usize;
+        let fields: Vec<_> = item
+            .into_iter()
+            .filter(|(k, _)| !matches!(*k, "id" | "expire"))
+            .map(|(k, v)| (k, to_field(v)))
+            .chain([("expire", expire.to_string())])
+            .collect();
+        redis::pipe()
+            .atomic()
+            .hset_multiple(event_key(eid), &fields)
+            .expire_at(event_key(eid), expire)
+            .query_async::<_, ()>(&mut self.conn.clone())
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(PutItemOutput::builder().build())
+    }
+
+    pub(super) async fn meta(&self, eid: &Uuid) -> Result<Option<Meta>, RedisError> {
+        let fields: Vec<Option<String>> = self
+            .conn
+            .clone()
+            .hget(
+                event_key(eid),
+                &[
+                    "secret",
+                    "seq",
+                    "announcement",
+                    "announced_until",
+                    "scheduled",
+                    "moderation",
+                    "moderated",
+                    "locked",
+                ],
+            )
+            .await?;
+        let [secret, seq, announcement, until, scheduled, moderation, moderated, locked] =
+            <[_; 8]>::try_from(fields).expect("one value per field");
+        let number = |n: Option<String>| n.and_then(|n| n.parse().ok());
+        Ok(secret.map(|secret| Meta {
+            secret,
+            version: number(seq).unwrap_or(0),
+            announcement: Announcement::stored(announcement, number(until)),
+            scheduled: Scheduled::stored(scheduled.as_deref()),
+            moderation: super::moderation::stored(moderation.as_deref()),
+            moderated: number(moderated).unwrap_or(0),
+            locked,
+        }))
+    }
+
+    pub(super) async fn announce(
+        &self,
+        eid: &Uuid,
+        announcement: Option<&Announcement>,
+    ) -> Result<(), RedisError> {
+        if self.expiry(eid).await?.is_none() {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "announcing in non-existing event",
+            )));
+        }
+        let mut conn = self.conn.clone();
+        match announcement {
+            Some(a) => {
+                conn.hset_multiple(
+                    event_key(eid),
+                    &[
+                        ("announcement", a.text.clone()),
+                        ("announced_until", a.until.to_string()),
+                    ],
+                )
+                .await
+            }
+            None => {
+                conn.hdel(event_key(eid), &["announcement", "announced_until"])
+                    .await
+            }
+        }
+    }
+
+    /// Make `scheduled` (as stored) the event's queue of announcements, or remove it if `None`.
+    pub(super) async fn schedule(
+        &self,
+        eid: &Uuid,
+        scheduled: Option<String>,
+    ) -> Result<(), RedisError> {
+        if self.expiry(eid).await?.is_none() {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "scheduling in non-existing event",
+            )));
+        }
+        let mut conn = self.conn.clone();
+        match scheduled {
+            Some(s) => conn.hset(event_key(eid), "scheduled", s).await,
+            None => conn.hdel(event_key(eid), "scheduled").await,
+        }
+    }
+
+    /// Make `log` (as stored) the event's moderation log, and `locked` the order its question list
+    /// is locked in if there's a new one, unless the log's been written since it was written for
+    /// the `moderated`th time, and return whether it was.
+    pub(super) async fn moderate(
+        &self,
+        eid: &Uuid,
+        moderated: u64,
+        log: String,
+        locked: Option<&str>,
+    ) -> Result<bool, RedisError> {
+        // checked and written in one go, so that nothing else gets in between
+        let script = redis::Script::new(
+            r"
+            if redis.call('EXISTS', KEYS[1]) == 0 then
+                return redis.error_reply('moderating non-existing event')
+            end
+            if (redis.call('HGET', KEYS[1], 'moderated') or '0') ~= ARGV[1] then
+                return 0
+            end
+            redis.call('HSET', KEYS[1], 'moderation', ARGV[2], 'moderated', ARGV[3])
+            if ARGV[4] then
+                redis.call('HSET', KEYS[1], 'locked', ARGV[4])
+            end
+            return 1
+            ",
+        );
+        let written: i64 = script
+            .key(event_key(eid))
+            .arg(moderated.to_string())
+            .arg(log)
+            .arg((moderated + 1).to_string())
+            .arg(locked)
+            .invoke_async(&mut self.conn.clone())
+            .await?;
+        Ok(written == 1)
+    }
+
+    /// Make `policy` (as stored) the event's retention override, or remove it if `None`.
+    pub(super) async fn set_retention(
+        &self,
+        eid: &Uuid,
+        policy: Option<String>,
+    ) -> Result<(), RedisError> {
+        if self.expiry(eid).await?.is_none() {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "setting retention of non-existing event",
+            )));
+        }
+        let mut conn = self.conn.clone();
+        match policy {
+            Some(p) => conn.hset(event_key(eid), "retention", p).await,
+            None => conn.hdel(event_key(eid), "retention").await,
+        }
+    }
+
+    /// Note on the event that it was warmed up `at`, and read the note back.
+    pub(super) async fn canary(&self, eid: &Uuid, at: u64) -> Result<Option<u64>, RedisError> {
+        if self.expiry(eid).await?.is_none() {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "warming non-existing event",
+            )));
+        }
+        let mut conn = self.conn.clone();
+        conn.hset::<_, _, _, ()>(event_key(eid), "warmed", at)
+            .await?;
+        conn.hget(event_key(eid), "warmed").await
+    }
+
+    /// Every event's id and moderation log (as stored), for the events that have one.
+    pub(super) async fn moderation_logs(&self) -> Result<Vec<(Uuid, String)>, RedisError> {
+        let eids: Vec<_> = self
+            .all_events()
+            .await?
+            .into_iter()
+            .map(|(eid, _)| eid)
+            .collect();
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for eid in &eids {
+            pipe.hget(event_key(eid), "moderation");
+        }
+        let logs: Vec<Option<String>> = pipe.query_async(&mut conn).await?;
+        Ok(eids
+            .into_iter()
+            .zip(logs)
+            .filter_map(|(eid, log)| Some((eid, log?)))
+            .collect())
+    }
+
+    /// Every event's id, and its retention override if it has one.
+    pub(super) async fn all_events(&self) -> Result<Vec<(Uuid, Option<String>)>, RedisError> {
+        let mut conn = self.conn.clone();
+        let mut eids = Vec::new();
+        {
+            let mut keys = conn.scan_match::<_, String>("event:*").await?;
+            while let Some(key) = keys.next_item().await {
+                // the pattern also matches the events' other keys, whose names don't parse
+                if let Some(eid) = key
+                    .strip_prefix("event:")
+                    .and_then(|eid| Uuid::parse_str(eid).ok())
+                {
+                    eids.push(eid);
+                }
+            }
+        }
+        let mut pipe = redis::pipe();
+        for eid in &eids {
+            pipe.hget(event_key(eid), "retention");
+        }
+        let policies: Vec<Option<String>> = pipe.query_async(&mut conn).await?;
+        Ok(eids.into_iter().zip(policies).collect())
+    }
+
+    pub(super) async fn delete_question(&self, eid: &Uuid, qid: &Uuid) -> Result<(), RedisError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .del(question_key(qid))
+            .zrem(top_key(eid), qid.to_string());
+        let (deleted, _): (usize, usize) = pipe.query_async(&mut self.conn.clone()).await?;
+        if deleted == 0 {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "deleting non-existing question",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stop everything belonging to `eid` from expiring, or have it all expire at `expire` again.
+    pub(super) async fn keep(&self, eid: &Uuid, expire: Option<usize>) -> Result<(), RedisError> {
+        if self.expiry(eid).await?.is_none() {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "holding non-existing event",
+            )));
+        }
+        let mut conn = self.conn.clone();
+        let qids: Vec<String> = conn.zrange(top_key(eid), 0, -1).await?;
+        let hashes: Vec<_> = std::iter::once(event_key(eid))
+            .chain(qids.iter().map(|qid| format!("question:{qid}")))
+            .collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in hashes.iter().chain([&top_key(eid), &changes_key(eid)]) {
+            match expire {
+                Some(expire) => pipe.expire_at(key, expire).ignore(),
+                None => pipe.persist(key).ignore(),
+            };
+        }
+        for key in &hashes {
+            match expire {
+                Some(expire) => pipe.hset(key, "expire", expire).ignore(),
+                None => pipe.hdel(key, "expire").ignore(),
+            };
+        }
+        pipe.query_async(&mut conn).await
+    }
+
+    pub(super) async fn event<E>(&self, eid: &Uuid) -> Result<GetItemOutput, SdkError<E>> {
+        let exists: bool = self
+            .conn
+            .clone()
+            .exists(event_key(eid))
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(GetItemOutput::builder()
+            .set_item(exists.then(|| {
+                HashMap::from_iter([(String::from("id"), AttributeValue::S(eid.to_string()))])
+            }))
+            .build())
+    }
+
+    pub(super) async fn ask<E>(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        item: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<PutItemOutput, SdkError<E>> {
+        let Some(expire) = self
+            .expiry(eid)
+            .await
+            .map_err(super::mint_dispatch_failure)?
+        else {
+            return Err(super::mint_dispatch_failure(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "asking question for non-existing event",
+            ))));
+        };
+        let fields: Vec<_> = item
+            .into_iter()
+            .filter(|(k, _)| !matches!(*k, "id" | "expire"))
+            .map(|(k, v)| (k, to_field(v)))
+            .chain(expire.map(|expire| ("expire", expire.to_string())))
+            .collect();
+        let votes = fields
+            .iter()
+            .find(|(k, _)| *k == "votes")
+            .and_then(|(_, v)| v.parse::<i64>().ok())
+            .unwrap_or(0);
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset_multiple(question_key(qid), &fields)
+            .zadd(top_key(eid), qid.to_string(), votes);
+        if let Some(expire) = expire {
+            pipe.expire_at(question_key(qid), expire)
+                .expire_at(top_key(eid), expire);
+        }
+        pipe.query_async::<_, ()>(&mut self.conn.clone())
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(PutItemOutput::builder().build())
+    }
+
+    pub(super) async fn list<E>(
+        &self,
+        eid: &Uuid,
+        has_secret: bool,
+    ) -> Result<QueryOutput, SdkError<E>> {
+        let mut conn = self.conn.clone();
+        let qids: Vec<String> = conn
+            .zrevrange(top_key(eid), 0, -1)
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        let reactions: Vec<_> = Reaction::ALL
+            .iter()
+            .map(|r| format!("reactions.{r}"))
+            .collect();
+        let fields: Vec<_> = [
+            "eid",
+            "votes",
+            "hidden",
+            "answered",
+            "voters",
+            "when",
+            "author",
+            "merged_into",
+        ]
+        .into_iter()
+        .chain(reactions.iter().map(String::as_str))
+        .collect();
+        let items: Vec<_> = questions(&mut conn, &qids, &fields)
+            .await
+            .map_err(super::mint_dispatch_failure)?
+            .into_iter()
+            .filter(|q| has_secret || q.get("hidden") != Some(&AttributeValue::Bool(true)))
+            .collect();
+        Ok(QueryOutput::builder()
+            .set_count(Some(items.len() as i32))
+            .set_items(Some(items))
+            .build())
+    }
+
+    pub(super) async fn questions(&self, qids: &[Uuid]) -> Result<BatchGetItemOutput, RedisError> {
+        let qids: Vec<_> = qids.iter().map(|qid| qid.to_string()).collect();
+        let items = questions(
+            &mut self.conn.clone(),
+            &qids,
+            &[
+                "eid",
+                "text",
+                "when",
+                "who",
+                "frozen",
+                "hidden",
+                "reason",
+                "revises",
+                "revised",
+                "author",
+                "history",
+                "source",
+                "merged_into",
+            ],
+        )
+        .await?;
+        Ok(BatchGetItemOutput::builder()
+            .set_responses(Some(HashMap::from_iter([(
+                String::from("questions"),
+                items,
+            )])))
+            .build())
+    }
+
+    /// The answer published to `qid`, if there is one.
+    pub(super) async fn published(&self, qid: &Uuid) -> Result<Option<String>, RedisError> {
+        self.conn.clone().hget(question_key(qid), "answer").await
+    }
+
+    /// Replace the voter sketch of `qid` with `new`, unless it's no longer `old`.
+    pub(super) async fn set_voters(
+        &self,
+        qid: &Uuid,
+        old: Option<&str>,
+        new: &str,
+    ) -> Result<bool, RedisError> {
+        // a missing field is `false` in Lua, and a missing sketch is passed as ""
+        let script = redis::Script::new(
+            r"
+            if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+            if (redis.call('HGET', KEYS[1], 'voters') or '') ~= ARGV[1] then return 0 end
+            redis.call('HSET', KEYS[1], 'voters', ARGV[2])
+            return 1
+            ",
+        );
+        let replaced: i64 = script
+            .key(question_key(qid))
+            .arg(old.unwrap_or(""))
+            .arg(new)
+            .invoke_async(&mut self.conn.clone())
+            .await?;
+        Ok(replaced == 1)
+    }
+
+    /// Change the votes of `qid` by `by`, and return the question as it is afterwards.
+    pub(super) async fn vote<E>(
+        &self,
+        qid: &Uuid,
+        by: i64,
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let mut conn = self.conn.clone();
+        let (eid, frozen, merged_into): (Option<String>, Option<String>, Option<String>) = conn
+            .hget(question_key(qid), &["eid", "frozen", "merged_into"])
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        let Some(eid) = eid.and_then(|eid| Uuid::parse_str(&eid).ok()) else {
+            return Ok(UpdateItemOutput::builder().build());
+        };
+        // see Backend::vote
+        let by = if frozen.as_deref() == Some("true") || merged_into.is_some() {
+            0
+        } else {
+            by
+        };
+        let (q,): (HashMap<String, String>,) = redis::pipe()
+            .atomic()
+            .hincr(question_key(qid), "votes", by)
+            .ignore()
+            .zincr(top_key(&eid), qid.to_string(), by)
+            .ignore()
+            .hgetall(question_key(qid))
+            .query_async(&mut conn)
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        let mut item = to_item(q);
+        item.insert(String::from("id"), AttributeValue::S(qid.to_string()));
+        Ok(UpdateItemOutput::builder()
+            .set_attributes(Some(item))
+            .build())
+    }
+
+    /// Add one to the count of `reaction` on `qid`, if it's a question of `eid`, and return the
+    /// question as it is afterwards.
+    pub(super) async fn react<E>(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        reaction: Reaction,
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let mut conn = self.conn.clone();
+        let of: Option<String> = conn
+            .hget(question_key(qid), "eid")
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        if of != Some(eid.to_string()) {
+            return Ok(UpdateItemOutput::builder().build());
+        }
+        let (q,): (HashMap<String, String>,) = redis::pipe()
+            .atomic()
+            .hincr(question_key(qid), format!("reactions.{reaction}"), 1)
+            .ignore()
+            .hgetall(question_key(qid))
+            .query_async(&mut conn)
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        let mut item = to_item(q);
+        item.insert(String::from("id"), AttributeValue::S(qid.to_string()));
+        Ok(UpdateItemOutput::builder()
+            .set_attributes(Some(item))
+            .build())
+    }
+
+    /// Overwrite `fields` of the question `qid`, keeping its rank up to date if `votes` changes.
+    pub(super) async fn set<E>(
+        &self,
+        qid: &Uuid,
+        fields: &[(&'static str, AttributeValue)],
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let mut conn = self.conn.clone();
+        let eid: Option<String> = conn
+            .hget(question_key(qid), "eid")
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        let Some(eid) = eid.and_then(|eid| Uuid::parse_str(&eid).ok()) else {
+            return Ok(UpdateItemOutput::builder().build());
+        };
+        let values: Vec<_> = fields
+            .iter()
+            .map(|(k, v)| (*k, to_field(v.clone())))
+            .collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset_multiple(question_key(qid), &values)
+            .ignore();
+        if let Some((_, votes)) = values.iter().find(|(k, _)| *k == "votes") {
+            pipe.zadd(top_key(&eid), qid.to_string(), votes).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut conn)
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(UpdateItemOutput::builder().build())
+    }
+
+    /// Append `change` to the log of `eid`, and return its sequence number.
+    pub(super) async fn record(
+        &self,
+        eid: &Uuid,
+        change: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<u64, RedisError> {
+        let Some(expire) = self.expiry(eid).await? else {
+            return Err(RedisError::from((
+                redis::ErrorKind::ResponseError,
+                "recording change for non-existing event",
+            )));
+        };
+        let mut conn = self.conn.clone();
+        let seq: u64 = conn.hincr(event_key(eid), "seq", 1).await?;
+
+        let mut item: serde_json::Map<_, _> = change
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), serde_json::Value::from(to_field(v))))
+            .collect();
+        item.insert(String::from("eid"), eid.to_string().into());
+        item.insert(String::from("seq"), seq.to_string().into());
+        let mut pipe = redis::pipe();
+        pipe.atomic().zadd(
+            changes_key(eid),
+            serde_json::Value::from(item).to_string(),
+            seq,
+        );
+        if let Some(expire) = expire {
+            pipe.expire_at(changes_key(eid), expire);
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+        Ok(seq)
+    }
+
+    pub(super) async fn changes<E>(
+        &self,
+        eid: &Uuid,
+        since: u64,
+        limit: usize,
+    ) -> Result<QueryOutput, SdkError<E>> {
+        let changes: Vec<String> = self
+            .conn
+            .clone()
+            .zrangebyscore_limit(
+                changes_key(eid),
+                format!("({since}"),
+                "+inf",
+                0,
+                limit as isize,
+            )
+            .await
+            .map_err(super::mint_dispatch_failure)?;
+        let items: Vec<_> = changes
+            .iter()
+            .filter_map(|c| {
+                let fields: HashMap<String, String> = serde_json::from_str(c).ok()?;
+                Some(to_item(fields))
+            })
+            .collect();
+        Ok(QueryOutput::builder()
+            .set_count(Some(items.len() as i32))
+            .set_items(Some(items))
+            .build())
+    }
+
+    #[cfg(test)]
+    pub(super) async fn delete(&self, eid: &Uuid) {
+        let mut conn = self.conn.clone();
+        let qids: Vec<String> = conn.zrange(top_key(eid), 0, -1).await.unwrap();
+        let mut keys: Vec<_> = qids.iter().map(|qid| format!("question:{qid}")).collect();
+        keys.extend([event_key(eid), top_key(eid), changes_key(eid)]);
+        conn.del::<_, ()>(keys).await.unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! Moving to a second region when DynamoDB in the first stops answering.
+//!
+//! With `DYNAMODB_FAILOVER_REGION` set, the tables are expected to be [Global Tables] replicated
+//! to that region. Every call to the primary region counts towards its health: a call that
+//! doesn't get an answer, or gets a 5xx, is a failure, and anything else (including throttling and
+//! failed conditions, which are 4xx) means the region is up. After `FAILOVER_AFTER` failures in a
+//! row, reads and writes go to the secondary region instead. After `FAILOVER_FOR_MS` the primary
+//! gets another chance: if the next call to it fails too, it's straight back to the secondary,
+//! and otherwise everything stays in the primary. There's no separate probe, since a Lambda
+//! instance has nothing to run it on between requests; the traffic is the health check.
+//!
+//! Each instance decides on its own, so for a while some instances may write to one region and
+//! some to the other. Global Tables settle concurrent writes to an item by keeping whichever came
+//! last, whole, and replicate with a lag of about a second, so what that means for counters is:
+//!
+//!  - Votes are `votes + 1` in whichever region takes them. Votes that hadn't replicated when a
+//!    question was voted on in the other region are lost once that vote replicates back, so
+//!    counts can come out low after a failover, but never higher than the votes actually cast.
+//!  - The same goes for the sketch of who voted (see [`super::hll`]), which only ever makes the
+//!    estimate a little lower.
+//!  - An event's change sequence (see [`super::changes`]) can hand out the same number in both
+//!    regions. Clients that see a number twice, or one go missing, resync from the change feed,
+//!    and since question lists are always built from the questions table, nothing shown goes
+//!    wrong for longer than the replication lag.
+//!
+//! Failing over is counted as `region.failover` and failing back as `region.failback` in the
+//! [metrics](super::metrics). It's ignored with `DYNAMODB_ENDPOINT`, which has no regions.
+//!
+//! [Global Tables]: https://docs.aws.amazon.com/amazondynamodb/latest/developerguide/GlobalTables.html
+
+use aws_sdk_dynamodb::Client;
+use aws_smithy_http::{body::SdkBody, result::ConnectorError};
+use std::{
+    future::Future,
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::Service;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug, Default)]
+struct State {
+    /// How many calls to the primary have failed in a row.
+    failures: u32,
+    /// When calls moved to the secondary, if they have.
+    since: Option<Instant>,
+    /// Whether the primary is getting another chance after calls moved away from it.
+    probing: bool,
+}
+
+/// How the primary region has been doing.
+#[derive(Debug)]
+pub(super) struct Health {
+    after: u32,
+    hold: Duration,
+    state: Mutex<State>,
+}
+
+impl Health {
+    /// Fail over after `after` failures in a row, and try the primary again after `hold`.
+    pub(super) fn new(after: u32, hold: Duration) -> Arc<Self> {
+        Arc::new(Health {
+            after: after.max(1),
+            hold,
+            state: Mutex::default(),
+        })
+    }
+
+    pub(super) fn from_config() -> Arc<Self> {
+        let config = super::config::config();
+        Self::new(config.failover_after, config.failover_for)
+    }
+
+    /// Whether calls should go to the secondary region.
+    fn failed_over(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.since {
+            Some(at) if at.elapsed() < self.hold => true,
+            Some(_) => {
+                // give the primary another chance, but only one
+                state.since = None;
+                state.failures = self.after - 1;
+                state.probing = true;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn record(&self, ok: bool) {
+        let mut state = self.state.lock().unwrap();
+        if ok {
+            if std::mem::take(&mut state.probing) {
+                info!("failing back to primary region");
+                super::metrics::incr("region.failback");
+            }
+            state.failures = 0;
+            return;
+        }
+        state.probing = false;
+        state.failures += 1;
+        if state.failures >= self.after && state.since.is_none() {
+            warn!(
+                failures = state.failures,
+                "failing over to secondary region"
+            );
+            super::metrics::incr("region.failover");
+            state.since = Some(Instant::now());
+        }
+    }
+}
+
+/// A DynamoDB connector that tells `health` how each call went.
+#[derive(Clone, Debug)]
+pub(super) struct Watched<C> {
+    inner: C,
+    health: Arc<Health>,
+}
+
+impl<C> Watched<C> {
+    pub(super) fn new(inner: C, health: Arc<Health>) -> Self {
+        Self { inner, health }
+    }
+}
+
+impl<C> Service<http::Request<SdkBody>> for Watched<C>
+where
+    C: Service<http::Request<SdkBody>, Response = http::Response<SdkBody>, Error = ConnectorError>,
+    C::Future: Send + 'static,
+{
+    type Response = http::Response<SdkBody>;
+    type Error = ConnectorError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<SdkBody>) -> Self::Future {
+        let fut = self.inner.call(req);
+        let health = Arc::clone(&self.health);
+        Box::pin(async move {
+            let res = fut.await;
+            health.record(matches!(&res, Ok(r) if !r.status().is_server_error()));
+            res
+        })
+    }
+}
+
+/// A DynamoDB client that moves to the secondary region while the primary is down.
+///
+/// It dereferences to the client for whichever region is in use at the time.
+#[derive(Clone, Debug)]
+pub(super) struct Regional {
+    primary: Client,
+    secondary: Option<(Client, Arc<Health>)>,
+}
+
+impl Regional {
+    /// Use `primary`, whose connector reports to `health`, and `secondary` while it's down.
+    pub(super) fn new(primary: Client, secondary: Client, health: Arc<Health>) -> Self {
+        Regional {
+            primary,
+            secondary: Some((secondary, health)),
+        }
+    }
+}
+
+impl From<Client> for Regional {
+    fn from(primary: Client) -> Self {
+        Regional {
+            primary,
+            secondary: None,
+        }
+    }
+}
+
+impl Deref for Regional {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        match &self.secondary {
+            Some((secondary, health)) if health.failed_over() => secondary,
+            _ => &self.primary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::model::AttributeValue;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A region that answers every `GetItem` with its own name, unless it's `down`.
+    #[derive(Clone)]
+    struct Faulty {
+        name: &'static str,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Service<http::Request<SdkBody>> for Faulty {
+        type Response = http::Response<SdkBody>;
+        type Error = ConnectorError;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: http::Request<SdkBody>) -> Self::Future {
+            let res = if self.down.load(Ordering::SeqCst) {
+                http::Response::builder()
+                    .status(500)
+                    .header("content-type", "application/x-amz-json-1.0")
+                    .body(SdkBody::from(
+                        r#"{"__type":"com.amazonaws.dynamodb.v20120810#InternalServerError"}"#,
+                    ))
+            } else {
+                http::Response::builder()
+                    .status(200)
+                    .header("content-type", "application/x-amz-json-1.0")
+                    .body(SdkBody::from(format!(
+                        r#"{{"Item":{{"id":{{"S":"{}"}}}}}}"#,
+                        self.name
+                    )))
+            };
+            std::future::ready(Ok(res.unwrap()))
+        }
+    }
+
+    async fn answer(dynamo: &Regional) -> Option<String> {
+        let item = dynamo
+            .get_item()
+            .table_name("events")
+            .key("id", AttributeValue::S(String::from("e")))
+            .send()
+            .await
+            .ok()?
+            .item?;
+        Some(item["id"].as_s().unwrap().clone())
+    }
+
+    #[tokio::test]
+    async fn fails_over() {
+        let down = Arc::new(AtomicBool::new(false));
+        let health = Health::new(3, Duration::from_millis(50));
+        let primary = Faulty {
+            name: "primary",
+            down: Arc::clone(&down),
+        };
+        let secondary = Faulty {
+            name: "secondary",
+            down: Arc::new(AtomicBool::new(false)),
+        };
+        let dynamo = Regional::new(
+            crate::golden::client(Watched::new(primary, Arc::clone(&health))),
+            crate::golden::client(secondary),
+            Arc::clone(&health),
+        );
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("primary"));
+
+        // the primary goes down, and a few calls fail before moving on
+        down.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            assert_eq!(answer(&dynamo).await, None);
+        }
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("secondary"));
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("secondary"));
+
+        // still down once the hold is over, so one more failure sends us straight back
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(answer(&dynamo).await, None);
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("secondary"));
+
+        // and once it's back up, it's used again
+        down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("primary"));
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("primary"));
+
+        // with the count starting over
+        down.store(true, Ordering::SeqCst);
+        assert_eq!(answer(&dynamo).await, None);
+        assert_eq!(answer(&dynamo).await, None);
+        down.store(false, Ordering::SeqCst);
+        assert_eq!(answer(&dynamo).await.as_deref(), Some("primary"));
+    }
+}
@@ -0,0 +1,93 @@
+//! Per-event tallies of submissions that were turned away, and why.
+//!
+//! Whenever a submission (a question, an announcement, a ping) is rejected, the reason is counted
+//! both in the process-wide metrics (as `rejected.<reason>`) and for the event it was for. The
+//! admin metrics endpoint lists the events with the most rejections, so operators can see whether
+//! the checks catch abuse or mostly get in the way of regular attendees before they change them.
+//!
+//! Like the metrics, these live in memory, so each Lambda instance has its own tallies.
+
+use std::{collections::BTreeMap, sync::Mutex};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Keep tallies for at most this many events.
+const MAX_EVENTS: usize = 1024;
+/// Show at most this many events in the admin metrics.
+const TOP: usize = 100;
+
+static REJECTIONS: Mutex<BTreeMap<Uuid, BTreeMap<&'static str, u64>>> = Mutex::new(BTreeMap::new());
+
+/// Note that a submission for `eid` was rejected because of `reason`.
+pub(super) fn reject(eid: &Uuid, reason: &'static str) {
+    super::metrics::incr(format!("rejected.{reason}"));
+    let mut rejections = REJECTIONS.lock().unwrap();
+    if rejections.len() >= MAX_EVENTS && !rejections.contains_key(eid) {
+        // make room by forgetting about the event that has caused the least trouble
+        let quietest = rejections
+            .iter()
+            .min_by_key(|(_, reasons)| reasons.values().sum::<u64>())
+            .map(|(eid, _)| *eid)
+            .expect("more than zero events");
+        rejections.remove(&quietest);
+    }
+    *rejections
+        .entry(*eid)
+        .or_default()
+        .entry(reason)
+        .or_default() += 1;
+}
+
+/// The events with the most rejections, most first, with their rejections by reason.
+pub(super) fn top() -> serde_json::Value {
+    let rejections = REJECTIONS.lock().unwrap();
+    let mut events: Vec<_> = rejections
+        .iter()
+        .map(|(eid, reasons)| (reasons.values().sum::<u64>(), eid, reasons))
+        .collect();
+    events.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    events
+        .into_iter()
+        .take(TOP)
+        .map(|(total, eid, reasons)| {
+            serde_json::json!({
+                "eid": eid.to_string(),
+                "total": total,
+                "reasons": reasons,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tallies() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        reject(&a, "single_word");
+        reject(&b, "single_word");
+        reject(&b, "empty");
+        reject(&b, "single_word");
+
+        let top = top();
+        let find = |eid: &Uuid| {
+            let eid = eid.to_string();
+            top.as_array()
+                .unwrap()
+                .iter()
+                .position(|e| e["eid"] == eid)
+                .unwrap()
+        };
+        let (a, b) = (find(&a), find(&b));
+        assert!(b < a, "events with more rejections come first");
+        assert_eq!(top[b]["total"], 3);
+        assert_eq!(top[b]["reasons"]["single_word"], 2);
+        assert_eq!(top[b]["reasons"]["empty"], 1);
+        assert_eq!(top[a]["reasons"]["single_word"], 1);
+        assert!(super::super::metrics::get("rejected.single_word") >= 3);
+    }
+}
@@ -0,0 +1,688 @@
+//! Scrubbing and deleting old questions according to the deployment's retention policy.
+//!
+//! Events expire `EVENTS_EXPIRE_AFTER_DAYS` after they're created, and questions
+//! `QUESTIONS_EXPIRE_AFTER_DAYS` after they're asked, which is the longest anything is kept unless
+//! the event is on legal hold (see [`super::hold`]). Within that, a deployment can set a retention policy
+//! (`RETENTION`, like `text=30,who=7,question=90`) that says after how many days questions lose
+//! their text, lose their asker's signature, or are deleted outright. Operators can override the
+//! policy for individual events, including with `never`, through
+//! `POST /api/admin/event/:eid/retention`. The override is stored on the event's item.
+//!
+//! Scrubbed fields are set to the empty string rather than removed, so scrubbed questions keep
+//! their votes and flags (and the event its counts), and scrubbing is easy to tell apart from
+//! never having had a signature. Deleted questions leave their entries in the change log behind,
+//! which `rebuild` already copes with since that's what expiry does too.
+//!
+//! Nothing runs in the background (the API runs as a Lambda), so the policy is applied by the
+//! `retention` command, which is meant to be run on a schedule (daily, say) with `--apply`.
+//! Without `--apply` it changes nothing, and only reports what it would do.
+//!
+//! Events on legal hold are left alone. Applying the policy instead clears the expiry of anything
+//! that's been recorded in them since they were put on hold.
+
+use super::{Backend, Local};
+use aws_sdk_dynamodb::{
+    error::{DeleteItemError, UpdateItemError},
+    model::AttributeValue,
+};
+use aws_smithy_types::Error;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, str::FromStr, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// `BatchGetItem` takes at most this many keys.
+const BATCH: usize = 100;
+
+/// What a retention rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Target {
+    /// The text of questions.
+    Text,
+    /// The signature of whoever asked.
+    Who,
+    /// Entire questions.
+    Question,
+}
+
+impl Target {
+    fn as_str(self) -> &'static str {
+        match self {
+            Target::Text => "text",
+            Target::Who => "who",
+            Target::Question => "question",
+        }
+    }
+}
+
+/// After how many days each target goes away, if ever.
+///
+/// A target without a rule follows the deployment's policy, one with `None` (`never`) is kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Policy {
+    rules: BTreeMap<Target, Option<u64>>,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = BTreeMap::new();
+        for rule in s.split(',').filter(|r| !r.trim().is_empty()) {
+            let Some((target, days)) = rule.split_once('=') else {
+                return Err(format!("`{rule}` isn't of the form target=days"));
+            };
+            let target = match target.trim() {
+                "text" => Target::Text,
+                "who" => Target::Who,
+                "question" => Target::Question,
+                t => return Err(format!("unknown retention target `{t}`")),
+            };
+            let days = match days.trim() {
+                "never" => None,
+                d => Some(
+                    d.parse()
+                        .map_err(|_| format!("`{d}` isn't a number of days"))?,
+                ),
+            };
+            rules.insert(target, days);
+        }
+        Ok(Policy { rules })
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (target, days)) in self.rules.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            match days {
+                Some(days) => write!(f, "{}={days}", target.as_str())?,
+                None => write!(f, "{}=never", target.as_str())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Policy {
+    /// This policy, except where `over` says otherwise.
+    fn with(&self, over: &Policy) -> Policy {
+        let mut rules = self.rules.clone();
+        rules.extend(over.rules.iter().map(|(&t, &d)| (t, d)));
+        Policy { rules }
+    }
+
+    /// Whether `target` is due to go away once it's `age` seconds old.
+    fn due(&self, target: Target, age: u64) -> bool {
+        matches!(self.rules.get(&target), Some(Some(days)) if age >= days * 24 * 60 * 60)
+    }
+}
+
+/// What the retention policy calls for (or called for) across all events.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub(super) struct Report {
+    /// Whether anything was actually changed, as opposed to a dry run.
+    pub(super) applied: bool,
+    /// The deployment's policy.
+    pub(super) policy: String,
+    pub(super) events: usize,
+    pub(super) questions: usize,
+    pub(super) scrubbed_text: usize,
+    pub(super) scrubbed_who: usize,
+    pub(super) deleted: usize,
+    /// Events that were left alone because they're on legal hold.
+    pub(super) held: usize,
+    /// The policy of each event that overrides the deployment's.
+    pub(super) overrides: BTreeMap<String, String>,
+    /// Audit records that are older than `AUDIT_DAYS` (see [`super::audit`]).
+    pub(super) audit_expired: usize,
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Backend {
+    /// Every event's id, along with the retention policy it overrides the deployment's with.
+    pub(super) async fn all_events(
+        &self,
+    ) -> Result<Vec<(Uuid, Option<String>)>, aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let mut events = Vec::new();
+                let mut start = None;
+                loop {
+                    let page = dynamo
+                        .scan()
+                        .table_name("events")
+                        .projection_expression("id, retention")
+                        .set_exclusive_start_key(start)
+                        .send()
+                        .await?;
+                    for item in page.items().unwrap_or_default() {
+                        let Some(eid) = item
+                            .get("id")
+                            .and_then(|id| id.as_s().ok())
+                            .and_then(|id| Uuid::parse_str(id).ok())
+                        else {
+                            continue;
+                        };
+                        let retention = item.get("retention").and_then(|r| r.as_s().ok());
+                        events.push((eid, retention.cloned()));
+                    }
+                    start = page.last_evaluated_key().cloned();
+                    if start.is_none() {
+                        return Ok(events);
+                    }
+                }
+            }
+            Self::Local(local) => {
+                let local = local.read().unwrap();
+                Ok(local
+                    .events
+                    .keys()
+                    .map(|eid| (*eid, local.retention.get(eid).cloned()))
+                    .collect())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.all_events().await.map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.all_events().await.map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.all_events().map_err(super::mint_unhandled),
+        }
+    }
+
+    /// Make `policy` the event's retention policy, or go back to the deployment's if it's `None`.
+    pub(super) async fn set_retention(
+        &self,
+        eid: &Uuid,
+        policy: Option<&Policy>,
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        let stored = policy.map(Policy::to_string);
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()));
+                let upd = if let Some(stored) = stored {
+                    upd.update_expression("SET retention = :retention")
+                        .expression_attribute_values(":retention", AttributeValue::S(stored))
+                } else {
+                    upd.update_expression("REMOVE retention")
+                };
+                // see Backend::record
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.send().await?;
+                Ok(())
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    events,
+                    retention,
+                    journal,
+                    ..
+                } = &mut *local;
+
+                if !events.contains_key(eid) {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("setting retention of event that doesn't exist")
+                            .build(),
+                    ))
+                    .into());
+                }
+                journal.retention(eid, stored.as_deref());
+                match stored {
+                    Some(s) => retention.insert(*eid, s),
+                    None => retention.remove(eid),
+                };
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .set_retention(eid, stored)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .set_retention(eid, stored)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .set_retention(eid, stored)
+                .map_err(super::mint_unhandled),
+        }
+    }
+
+    /// Blank out `fields` of the question `qid`.
+    pub(super) async fn scrub(
+        &self,
+        qid: &Uuid,
+        fields: &[&'static str],
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let sets: Vec<_> = (0..fields.len())
+                    .map(|i| format!("#f{i} = :empty"))
+                    .collect();
+                let mut upd = dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression(format!("SET {}", sets.join(", ")))
+                    .expression_attribute_values(":empty", AttributeValue::S(String::new()));
+                for (i, field) in fields.iter().enumerate() {
+                    upd = upd.expression_attribute_names(format!("#f{i}"), *field);
+                }
+                // the questions being scrubbed were just read, so the condition is only a safeguard
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.send().await?;
+                Ok(())
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                if let Some(q) = questions.get_mut(qid) {
+                    for field in fields {
+                        q.insert(field, AttributeValue::S(String::new()));
+                    }
+                    journal.question(qid, q);
+                }
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let mut set = mongodb::bson::Document::new();
+                for field in fields {
+                    set.insert(*field, "");
+                }
+                mongo
+                    .update::<UpdateItemError>(qid, mongodb::bson::doc! { "$set": set })
+                    .await?;
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|f| (*f, AttributeValue::S(String::new())))
+                    .collect();
+                redis.set::<UpdateItemError>(qid, &fields).await?;
+                Ok(())
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|f| (*f, AttributeValue::S(String::new())))
+                    .collect();
+                sled.set::<UpdateItemError>(qid, &fields).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Delete the question `qid` of the event `eid`.
+    pub(super) async fn delete_question(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                dynamo
+                    .delete_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .send()
+                    .await?;
+                Ok(())
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions,
+                    questions_by_eid,
+                    journal,
+                    ..
+                } = &mut *local;
+
+                if questions.remove(qid).is_none() {
+                    return Err(super::mint_service_error(DeleteItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("deleting question that doesn't exist")
+                            .build(),
+                    ))
+                    .into());
+                }
+                if let Some(qids) = questions_by_eid.get_mut(eid) {
+                    qids.retain(|q| q != qid);
+                }
+                journal.deleted(eid, qid);
+                Ok(())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .delete_question(qid)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .delete_question(eid, qid)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .delete_question(eid, qid)
+                .map_err(super::mint_unhandled),
+        }
+    }
+
+    /// Work out what `policy` calls for across all events, and do it if `apply` is set.
+    pub(super) async fn retention(
+        &self,
+        policy: &Policy,
+        apply: bool,
+    ) -> Result<Report, aws_sdk_dynamodb::Error> {
+        let mut report = Report {
+            applied: apply,
+            policy: policy.to_string(),
+            ..Default::default()
+        };
+        let now = now();
+        for (eid, over) in self.all_events().await? {
+            report.events += 1;
+            match super::get_meta(self, &eid).await {
+                Ok(meta) if meta.held() => {
+                    report.held += 1;
+                    if apply {
+                        self.keep(&eid, true).await?;
+                    }
+                    continue;
+                }
+                Ok(_) => {}
+                // expired (or was deleted) since it was listed
+                Err(StatusCode::NOT_FOUND) => continue,
+                Err(status) => {
+                    error!(%eid, %status, "skipping event whose hold couldn't be checked");
+                    continue;
+                }
+            }
+            let policy = match over.as_deref().map(Policy::from_str) {
+                None => policy.clone(),
+                Some(Ok(over)) => {
+                    report.overrides.insert(eid.to_string(), over.to_string());
+                    policy.with(&over)
+                }
+                Some(Err(e)) => {
+                    // better to keep too much than to delete what someone meant to keep
+                    error!(%eid, error = e, "skipping event with malformed retention override");
+                    continue;
+                }
+            };
+            if policy.rules.values().all(Option::is_none) {
+                continue;
+            }
+
+            let qids: Vec<_> = self
+                .list(&eid, true)
+                .await?
+                .items()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|q| q.get("id")?.as_s().ok())
+                .filter_map(|qid| Uuid::parse_str(qid).ok())
+                .collect();
+            for qids in qids.chunks(BATCH) {
+                let qs = self.questions(qids).await?;
+                for q in qs
+                    .responses()
+                    .and_then(|r| r.get("questions"))
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                {
+                    let Some(qid) = q
+                        .get("id")
+                        .and_then(|id| id.as_s().ok())
+                        .and_then(|id| Uuid::parse_str(id).ok())
+                    else {
+                        continue;
+                    };
+                    let Some(when) = q
+                        .get("when")
+                        .and_then(|w| w.as_n().ok())
+                        .and_then(|w| w.parse::<u64>().ok())
+                    else {
+                        continue;
+                    };
+                    report.questions += 1;
+                    let age = now.saturating_sub(when);
+
+                    if policy.due(Target::Question, age) {
+                        report.deleted += 1;
+                        if apply {
+                            self.delete_question(&eid, &qid).await?;
+                            super::texts::forget(&qid);
+                        }
+                        continue;
+                    }
+                    let present = |field| {
+                        q.get(field)
+                            .and_then(|v| v.as_s().ok())
+                            .is_some_and(|v| !v.is_empty())
+                    };
+                    let mut scrub = Vec::new();
+                    if policy.due(Target::Text, age) && present("text") {
+                        report.scrubbed_text += 1;
+                        scrub.push("text");
+                    }
+                    if policy.due(Target::Who, age) && present("who") {
+                        report.scrubbed_who += 1;
+                        scrub.push("who");
+                    }
+                    if apply && !scrub.is_empty() {
+                        self.scrub(&qid, &scrub).await?;
+                        super::texts::forget(&qid);
+                    }
+                }
+            }
+        }
+        info!(?report, "applied retention policy");
+        Ok(report)
+    }
+}
+
+/// Override the deployment's retention policy for an event, or go back to it with an empty body.
+pub(super) async fn retention(
+    _: super::admin::Admin,
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    body: String,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let over: Policy = body.parse().map_err(|e| {
+        warn!(%eid, error = e, "bad retention override");
+        StatusCode::BAD_REQUEST
+    })?;
+    super::get_meta(&dynamo, &eid).await?;
+
+    let over = (!over.rules.is_empty()).then_some(over);
+    if let Err(e) = dynamo.set_retention(&eid, over.as_ref()).await {
+        error!(%eid, error = %e, "failed to set retention override");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    info!(%eid, retention = ?over.as_ref().map(Policy::to_string), "set retention override");
+    let policy = &super::config::config().retention;
+    let effective = match &over {
+        Some(over) => policy.with(over),
+        None => policy.clone(),
+    };
+    Ok(Json(serde_json::json!({
+        "retention": over.as_ref().map(Policy::to_string),
+        "effective": effective.to_string(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let ask = |body: &str, asker: Option<&str>| {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: asker.map(String::from),
+                }),
+            )
+        };
+        let q1 = ask("old question", Some("someone")).await.unwrap();
+        let qid1 = Uuid::parse_str(q1["id"].as_str().unwrap()).unwrap();
+        let texts = || {
+            crate::questions::questions(
+                Path(qid1.to_string()),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                crate::networks::Client(None),
+            )
+        };
+
+        // only this event can be affected, however many others the backend has
+        let over = |policy: &str| {
+            retention(
+                crate::admin::Admin,
+                Path(eid),
+                State(backend.clone()),
+                policy.to_string(),
+            )
+        };
+        assert_eq!(
+            over("text=soon").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let r = over("text=0,who=0").await.unwrap();
+        assert_eq!(r["retention"], "text=0,who=0");
+        let none = Policy::from_str("text=never,who=never,question=never").unwrap();
+
+        let report = backend.retention(&none, false).await.unwrap();
+        assert_eq!(report.overrides[&eid.to_string()], "text=0,who=0");
+        assert!(report.scrubbed_text >= 1);
+        assert!(report.scrubbed_who >= 1);
+        assert_eq!(report.deleted, 0);
+        // a dry run doesn't change anything
+        let q = texts().await.2.unwrap();
+        assert_eq!(q[qid1.to_string()]["text"], "old question");
+
+        backend.retention(&none, true).await.unwrap();
+        let q = texts().await.2.unwrap();
+        assert_eq!(q[qid1.to_string()]["text"], "");
+        assert_eq!(q[qid1.to_string()].get("who"), None);
+        // scrubbed questions still count
+        let list = crate::list::list(
+            Path(eid),
+            State(backend.clone()),
+            axum::extract::Query(Default::default()),
+            http::HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap();
+        assert_eq!(list[0]["votes"], 1);
+
+        // there's nothing left to scrub in this event
+        let before = backend.retention(&none, false).await.unwrap();
+        over("text=0,who=never").await.unwrap();
+        let after = backend.retention(&none, false).await.unwrap();
+        assert_eq!(after.scrubbed_text, before.scrubbed_text);
+        assert_eq!(after.overrides[&eid.to_string()], "text=0,who=never");
+
+        over("question=0").await.unwrap();
+        backend.retention(&none, true).await.unwrap();
+        assert_eq!(texts().await.2.unwrap_err(), StatusCode::NOT_FOUND);
+
+        over("").await.unwrap();
+        backend.delete(&eid).await;
+    }
+
+    #[test]
+    fn policies() {
+        let p: Policy = "text=30, who=7,question=never".parse().unwrap();
+        assert_eq!(p.to_string(), "text=30,who=7,question=never");
+        assert!("text=30,votes=7".parse::<Policy>().is_err());
+        assert!("text".parse::<Policy>().is_err());
+        assert_eq!("".parse::<Policy>().unwrap(), Policy::default());
+
+        let day = 24 * 60 * 60;
+        assert!(!p.due(Target::Text, 29 * day));
+        assert!(p.due(Target::Text, 30 * day));
+        assert!(!p.due(Target::Question, 1000 * day));
+
+        let over: Policy = "text=never,question=90".parse().unwrap();
+        let p = p.with(&over);
+        assert_eq!(p.to_string(), "text=never,who=7,question=90");
+        assert!(!p.due(Target::Text, 1000 * day));
+        assert!(p.due(Target::Question, 90 * day));
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
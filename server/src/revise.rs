@@ -0,0 +1,361 @@
+//! Letting askers have another go at a question the host hid.
+//!
+//! Askers who are told their question was hidden (see [`super::mine`]) often have a point, and
+//! without a way to make it, they take it to the host some other way. So an asker can send one
+//! revised version of a hidden question, with `POST /api/event/:eid/revise/:qid` and
+//! `{"receipt": "<receipt>", "body": "..", "asker": ..}`. The revision is asked like any other
+//! question, but hidden from the start as `pending-review`, and marked as revising the original,
+//! so both are in the host's list (as hidden questions) and the revision's text says which
+//! question it `revises`. Hosts listening with the secret are also told, with `{"kind":
+//! "revised", "qid": .., "revises": ..}`. Unhiding the revision is how the host accepts it.
+//!
+//! Only hidden questions can be revised, and only once: the original remembers its revision, and
+//! revising it again is a 409. Two revisions sent at the very same moment can both get through,
+//! though. Each event also takes at most `PER_EVENT` revisions an hour, which like the other
+//! process-wide limits is counted by each instance on its own.
+
+use super::{changes::Change, mine::Reason, Backend, Local};
+#[cfg(any(feature = "mongo", feature = "redis", feature = "sled"))]
+use aws_sdk_dynamodb::error::UpdateItemError;
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Mutex, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How many revisions a single event takes in an hour.
+const PER_EVENT: usize = 30;
+/// Forget about this many events at most.
+const MAX_EVENTS: usize = 4096;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Revision {
+    receipt: String,
+    body: String,
+    #[serde(default)]
+    asker: Option<String>,
+}
+
+/// How many revisions each event took in the current hour.
+static REVISED: Mutex<(u64, BTreeMap<Uuid, usize>)> = Mutex::new((0, BTreeMap::new()));
+
+/// Whether `eid` may take another revision in `hour`.
+fn admit(revised: &mut (u64, BTreeMap<Uuid, usize>), eid: Uuid, hour: u64) -> bool {
+    let (current, by_event) = revised;
+    if *current != hour || (by_event.len() >= MAX_EVENTS && !by_event.contains_key(&eid)) {
+        *current = hour;
+        by_event.clear();
+    }
+    let n = by_event.entry(eid).or_default();
+    if *n >= PER_EVENT {
+        return false;
+    }
+    *n += 1;
+    true
+}
+
+fn hour() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / (60 * 60)
+}
+
+impl Backend {
+    /// Remember that `qid` has been revised as `revision`.
+    pub(super) async fn revised(
+        &self,
+        qid: &Uuid,
+        revision: &Uuid,
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        let revision = revision.to_string();
+        match self {
+            Self::Dynamo(dynamo) => {
+                dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression("SET revised = :revised")
+                    .expression_attribute_values(":revised", AttributeValue::S(revision))
+                    .send()
+                    .await?;
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                if let Some(q) = questions.get_mut(qid) {
+                    q.insert("revised", AttributeValue::S(revision));
+                    journal.question(qid, q);
+                }
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                mongo
+                    .update::<UpdateItemError>(
+                        qid,
+                        mongodb::bson::doc! { "$set": { "revised": revision } },
+                    )
+                    .await?;
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                redis
+                    .set::<UpdateItemError>(qid, &[("revised", AttributeValue::S(revision))])
+                    .await?;
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                sled.set::<UpdateItemError>(qid, &[("revised", AttributeValue::S(revision))])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(super) async fn revise(
+    Path((eid, qid)): Path<(Uuid, Uuid)>,
+    State(dynamo): State<Backend>,
+    Json(revision): Json<Revision>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::ask::screen(&eid, &revision.body)?;
+    let meta = super::get_meta(&dynamo, &eid).await?;
+    if !super::mine::checks_out(&meta.secret, &qid, &revision.receipt) {
+        warn!(%eid, %qid, "attempted to revise question with incorrect receipt");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    super::ask::taking(&eid, &meta)?;
+
+    let original = dynamo.questions(&[qid]).await.map_err(|e| {
+        error!(%eid, %qid, error = %e, "dynamodb request for revised question failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(original) = original
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .and_then(|qs| qs.first())
+        .filter(|q| q.get("eid").and_then(|v| v.as_s().ok()) == Some(&eid.to_string()))
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if original.get("hidden").and_then(|v| v.as_bool().ok()) != Some(&true) {
+        debug!(%eid, %qid, "attempted to revise question that isn't hidden");
+        return Err(StatusCode::CONFLICT);
+    }
+    if original.contains_key("revised") {
+        debug!(%eid, %qid, "attempted to revise question again");
+        return Err(StatusCode::CONFLICT);
+    }
+    if !admit(&mut REVISED.lock().unwrap(), eid, hour()) {
+        warn!(%eid, "too many revisions for event");
+        super::rejections::reject(&eid, "revisions");
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let rid = Uuid::new_v4();
+    let q = super::ask::Question {
+        body: revision.body,
+        asker: revision.asker,
+    };
+    let extra = vec![
+        ("hidden", AttributeValue::Bool(true)),
+        (
+            "reason",
+            AttributeValue::S(Reason::PendingReview.to_string()),
+        ),
+        ("revises", AttributeValue::S(qid.to_string())),
+    ];
+    if let Err(e) = dynamo
+        .ask_with(&eid, &rid, q, super::clock::now(), meta.held(), extra)
+        .await
+    {
+        error!(%eid, %qid, error = %e, "dynamodb request to create revision failed");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(e) = dynamo.revised(&qid, &rid).await {
+        // the revision is there for the host either way
+        error!(%eid, %qid, %rid, error = %e, "dynamodb request to link revision failed");
+    }
+    debug!(%eid, %qid, %rid, "created revision");
+    dynamo
+        .try_record(&eid, Change::QuestionAsked { qid: rid })
+        .await;
+    super::bus::tell_hosts(
+        &eid,
+        serde_json::json!({ "kind": "revised", "qid": rid, "revises": qid }),
+    );
+    Ok(Json(serde_json::json!({
+        "id": rid.to_string(),
+        "receipt": super::mine::receipt(&meta.secret, &rid),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::{Property, Why};
+    use axum::extract::Query;
+    use std::collections::HashMap;
+
+    #[test]
+    fn limits_events() {
+        let mut revised = (0, BTreeMap::new());
+        let (e1, e2) = (Uuid::new_v4(), Uuid::new_v4());
+        for _ in 0..PER_EVENT {
+            assert!(admit(&mut revised, e1, 1));
+        }
+        assert!(!admit(&mut revised, e1, 1));
+        assert!(admit(&mut revised, e2, 1));
+        // a new hour starts over
+        assert!(admit(&mut revised, e1, 2));
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "what about the other thing".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let receipt = q["receipt"].as_str().unwrap().to_string();
+        let revise = |receipt: &str, body: &str| {
+            super::revise(
+                Path((eid, qid)),
+                State(backend.clone()),
+                Json(Revision {
+                    receipt: receipt.to_string(),
+                    body: body.to_string(),
+                    asker: None,
+                }),
+            )
+        };
+
+        // only hidden questions
+        assert_eq!(
+            revise(&receipt, "what about the thing").await.unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        crate::toggle::toggle(
+            Path((eid, secret.to_string(), qid, Property::Hidden)),
+            State(backend.clone()),
+            Query(Why {
+                reason: Some(Reason::OffTopic),
+            }),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        // and only by the asker
+        assert_eq!(
+            revise("nope", "what about the thing").await.unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let Json(r) = revise(&receipt, "what about the thing").await.unwrap();
+        let rid = Uuid::parse_str(r["id"].as_str().unwrap()).unwrap();
+        // but only once
+        assert_eq!(
+            revise(&receipt, "what about the thing then")
+                .await
+                .unwrap_err(),
+            StatusCode::CONFLICT
+        );
+
+        // the host sees both, hidden
+        let Json(all) = crate::list::list_all(
+            Path((eid, secret.to_string())),
+            State(backend.clone()),
+            Query(Default::default()),
+            http::HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap();
+        let all = all.as_array().unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all.iter().all(|q| q["hidden"] == true));
+        let Json(texts) = crate::questions::questions(
+            Path(rid.to_string()),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .2
+        .unwrap();
+        assert_eq!(texts[rid.to_string()]["revises"], qid.to_string());
+
+        let Json(mine) = crate::mine::mine(
+            Path(eid),
+            State(backend.clone()),
+            Json(HashMap::from_iter([
+                (qid, receipt),
+                (rid, r["receipt"].as_str().unwrap().to_string()),
+            ])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            mine,
+            serde_json::json!({
+                qid.to_string(): {
+                    "hidden": true,
+                    "reason": "off-topic",
+                    "revised": rid.to_string(),
+                },
+                rid.to_string(): { "hidden": true, "reason": "pending-review" },
+            })
+        );
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
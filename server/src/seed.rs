@@ -0,0 +1,243 @@
+//! Generated events that look like the real thing, for performance work and for trying out UI
+//! states.
+//!
+//! Question texts come from the seed data. Questions arrive in bursts (like in the breaks of a
+//! talk) over the event's `minutes`, votes are spread over them following a Zipf distribution (so
+//! a few questions get most of them), and some of them are hidden or answered. Everything goes
+//! through the same backend calls and change log entries as real traffic does.
+
+use super::{ask::Question, changes::Change, toggle::Property, vote::UpDown, Backend};
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(clap::Args, Debug, Clone)]
+pub(super) struct Params {
+    /// How many events to create.
+    #[arg(long, default_value_t = Params::default().events)]
+    pub(super) events: usize,
+    /// How many questions each event gets.
+    #[arg(long, default_value_t = Params::default().questions)]
+    pub(super) questions: usize,
+    /// How many votes each event gets, on top of the askers' own.
+    #[arg(long, default_value_t = Params::default().votes)]
+    pub(super) votes: usize,
+    /// The exponent of the Zipf distribution of votes. Higher is more lopsided.
+    #[arg(long, default_value_t = Params::default().zipf)]
+    pub(super) zipf: f64,
+    /// How long ago each event started, in minutes.
+    #[arg(long, default_value_t = Params::default().minutes)]
+    pub(super) minutes: u64,
+    /// How many bursts questions arrive in.
+    #[arg(long, default_value_t = Params::default().bursts)]
+    pub(super) bursts: usize,
+    /// The fraction of questions that are hidden.
+    #[arg(long, default_value_t = Params::default().hidden)]
+    pub(super) hidden: f64,
+    /// The fraction of questions that are answered.
+    #[arg(long, default_value_t = Params::default().answered)]
+    pub(super) answered: f64,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            events: 1,
+            questions: 50,
+            votes: 500,
+            zipf: 1.0,
+            minutes: 60,
+            bursts: 3,
+            hidden: 0.05,
+            answered: 0.3,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Planned {
+    body: String,
+    when: SystemTime,
+    hidden: bool,
+    answered: bool,
+}
+
+/// Decide on everything about an event up front, so the generator isn't held across awaits.
+///
+/// Returns the questions in the order they were asked, and for each extra vote the index of the
+/// question it goes to.
+fn plan(params: &Params, now: SystemTime) -> (Vec<Planned>, Vec<usize>) {
+    #[derive(Deserialize)]
+    struct Text {
+        text: String,
+    }
+    let texts: Vec<Text> = serde_json::from_str(super::SEED).expect("seed data is valid");
+    let texts: Vec<_> = texts
+        .into_iter()
+        .map(|t| t.text)
+        // the ask endpoint wouldn't let these through
+        .filter(|t| t.trim().contains(' '))
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    let length = Duration::from_secs(params.minutes * 60);
+    let start = now - length;
+    let centers: Vec<_> = (0..params.bursts.max(1))
+        .map(|_| length.mul_f64(rng.gen::<f64>()))
+        .collect();
+    // most of a burst arrives within a couple of minutes of it starting
+    let spread = Duration::from_secs(120).as_secs_f64();
+
+    let mut questions: Vec<_> = (0..params.questions)
+        .map(|_| {
+            let center = *centers.choose(&mut rng).expect("at least one burst");
+            let after = -spread * (1.0 - rng.gen::<f64>()).ln();
+            let when = (center + Duration::from_secs_f64(after)).min(length);
+            Planned {
+                body: texts.choose(&mut rng).expect("seed data has texts").clone(),
+                when: start + when,
+                hidden: rng.gen_bool(params.hidden.clamp(0.0, 1.0)),
+                answered: rng.gen_bool(params.answered.clamp(0.0, 1.0)),
+            }
+        })
+        .collect();
+    questions.sort_by_key(|q| q.when);
+
+    // which question gets to be the most popular shouldn't depend on when it was asked
+    let mut ranked: Vec<_> = (0..questions.len()).collect();
+    ranked.shuffle(&mut rng);
+    let votes = match WeightedIndex::new((1..=ranked.len()).map(|k| (k as f64).powf(-params.zipf)))
+    {
+        Ok(zipf) => (0..params.votes)
+            .map(|_| ranked[zipf.sample(&mut rng)])
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    (questions, votes)
+}
+
+/// Generate an event as described by `params`, and return its id and secret.
+pub(super) async fn event(
+    backend: &Backend,
+    params: &Params,
+) -> Result<(Uuid, String), aws_sdk_dynamodb::Error> {
+    let (questions, votes) = plan(params, super::clock::now());
+
+    let eid = Uuid::new_v4();
+    let secret = super::new::secret();
+    backend.new(&eid, &secret, None).await?;
+    backend.try_record(&eid, Change::EventCreated).await;
+
+    let mut qids = Vec::with_capacity(questions.len());
+    for q in &questions {
+        let qid = Uuid::new_v4();
+        let question = Question {
+            body: q.body.clone(),
+            asker: None,
+        };
+        backend.ask_at(&eid, &qid, question, q.when, false).await?;
+        backend
+            .try_record(&eid, Change::QuestionAsked { qid })
+            .await;
+        qids.push(qid);
+    }
+    for i in votes {
+        let qid = qids[i];
+        backend.vote(&qid, UpDown::Up).await?;
+        let change = Change::VoteCast {
+            qid,
+            direction: UpDown::Up,
+        };
+        backend.try_record(&eid, change).await;
+    }
+    for (q, &qid) in questions.iter().zip(&qids) {
+        if q.hidden {
+            backend.toggle(&qid, Property::Hidden, true).await?;
+            let change = Change::QuestionHidden { qid, set: true };
+            backend.try_record(&eid, change).await;
+        }
+        if q.answered {
+            backend.toggle(&qid, Property::Answered, true).await?;
+            let change = Change::QuestionAnswered { qid, set: true };
+            backend.try_record(&eid, change).await;
+        }
+    }
+
+    debug!(%eid, questions = qids.len(), "generated event");
+    Ok((eid, secret))
+}
+
+/// Generate all the events described by `params`.
+pub(super) async fn run(
+    backend: &Backend,
+    params: &Params,
+) -> Result<serde_json::Value, aws_sdk_dynamodb::Error> {
+    let mut events = Vec::new();
+    for _ in 0..params.events {
+        let (eid, secret) = event(backend, params).await?;
+        events.push(serde_json::json!({ "id": eid.to_string(), "secret": secret }));
+    }
+    Ok(events.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn plans() {
+        let params = Params {
+            questions: 40,
+            votes: 1000,
+            bursts: 2,
+            hidden: 0.0,
+            answered: 1.0,
+            ..Params::default()
+        };
+        let now = SystemTime::now();
+        let (questions, votes) = plan(&params, now);
+        assert_eq!(questions.len(), 40);
+        assert!(questions.windows(2).all(|w| w[0].when <= w[1].when));
+        assert!(questions
+            .iter()
+            .all(|q| q.when <= now && q.when >= now - Duration::from_secs(60 * 60)));
+        assert!(questions.iter().all(|q| !q.hidden && q.answered));
+
+        assert_eq!(votes.len(), 1000);
+        let mut tally = vec![0; questions.len()];
+        for i in votes {
+            tally[i] += 1;
+        }
+        tally.sort_unstable_by(|a, b| b.cmp(a));
+        // with s = 1 and 40 questions, the favorite expects ~23% of the votes and the median ~1%
+        assert!(tally[0] > 100, "{tally:?}");
+        assert!(tally[20] < 40, "{tally:?}");
+    }
+
+    #[tokio::test]
+    async fn generates() {
+        let backend = Backend::local().await;
+        let params = Params {
+            questions: 10,
+            votes: 30,
+            ..Params::default()
+        };
+        let (eid, _) = event(&backend, &params).await.unwrap();
+        let qs = backend.list(&eid, true).await.unwrap();
+        let qs = qs.items().unwrap();
+        assert_eq!(qs.len(), 10);
+        let votes: usize = qs
+            .iter()
+            .map(|q| q["votes"].as_n().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(votes, 10 + 30);
+        let qids: HashSet<_> = qs.iter().map(|q| q["id"].as_s().unwrap()).collect();
+        assert_eq!(qids.len(), 10);
+        backend.delete(&eid).await;
+    }
+}
@@ -0,0 +1,179 @@
+//! Question lists shared between instances, in Redis.
+//!
+//! Each instance keeps the lists of live events in memory (see [`super::hot`]), but every instance
+//! has to load them itself, and reload them whenever they change or get `HOT_REFRESH_MS` old. For
+//! a big event served by many Lambda instances at once, that still adds up to a lot of DynamoDB
+//! queries. With `LIST_CACHE_URL` pointing at a Redis server (and the `redis` feature built in),
+//! whichever instance lists an event first also leaves the whole list (with vote counts) in Redis,
+//! and the other instances take it from there rather than query DynamoDB again.
+//!
+//! Lists are kept by the event's version, which every change (a question asked, a vote, a host
+//! toggling something) moves on, so a list is only ever used for the version it was listed at, and
+//! writes invalidate it without having to say so. Lists expire after `LIST_CACHE_TTL_MS` anyway,
+//! both to keep Redis small and in case a change that couldn't be recorded left the version where
+//! it was. Redis trouble only ever means going to DynamoDB, and is logged and counted as
+//! `shared.failed`; hits and misses are counted as `shared.hit` and `shared.miss`.
+
+use super::hot::Question;
+#[cfg(feature = "redis")]
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "redis")]
+use std::sync::OnceLock;
+#[cfg(feature = "redis")]
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A question list, as it's kept in Redis.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+struct Listed {
+    version: u64,
+    questions: Vec<(Uuid, Question)>,
+}
+
+#[cfg(feature = "redis")]
+fn key(eid: &Uuid) -> String {
+    format!("list:{eid}")
+}
+
+/// The list kept as `stored`, if it's for `version`.
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+fn current(stored: &str, version: u64) -> Option<Vec<(Uuid, Question)>> {
+    let listed: Listed = serde_json::from_str(stored)
+        .map_err(|e| error!(error = %e, "found malformed shared question list"))
+        .ok()?;
+    (listed.version == version).then_some(listed.questions)
+}
+
+/// The connection to the configured Redis, if there is one.
+#[cfg(feature = "redis")]
+async fn conn() -> Option<ConnectionManager> {
+    // tokio's cell can't be made in a const without parking_lot, but it can be opened async
+    static CONN: OnceLock<OnceCell<Option<ConnectionManager>>> = OnceLock::new();
+    CONN.get_or_init(OnceCell::new)
+        .get_or_init(|| async {
+            let url = super::config::config().list_cache_url.as_deref()?;
+            let client = redis::Client::open(url)
+                .map_err(|e| error!(error = %e, "bad LIST_CACHE_URL"))
+                .ok()?;
+            ConnectionManager::new(client)
+                .await
+                .map_err(|e| error!(error = %e, "failed to connect to list cache"))
+                .ok()
+        })
+        .await
+        .clone()
+}
+
+/// `eid`'s entire question list at `version`, if another instance left it.
+pub(super) async fn get(eid: &Uuid, version: u64) -> Option<Vec<(Uuid, Question)>> {
+    #[cfg(feature = "redis")]
+    {
+        let mut conn = conn().await?;
+        let stored: Option<String> = match conn.get(key(eid)).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!(%eid, error = %e, "failed to read shared question list");
+                super::metrics::incr("shared.failed");
+                return None;
+            }
+        };
+        let questions = stored.and_then(|stored| current(&stored, version));
+        super::metrics::incr(if questions.is_some() {
+            "shared.hit"
+        } else {
+            "shared.miss"
+        });
+        questions
+    }
+    #[cfg(not(feature = "redis"))]
+    {
+        let _ = (eid, version);
+        None
+    }
+}
+
+/// Leave `eid`'s entire question list at `version` for the other instances.
+pub(super) async fn put(eid: &Uuid, version: u64, questions: &[(Uuid, Question)]) {
+    #[cfg(feature = "redis")]
+    {
+        let Some(mut conn) = conn().await else {
+            return;
+        };
+        let listed = Listed {
+            version,
+            questions: questions.to_vec(),
+        };
+        let stored = serde_json::to_string(&listed).expect("lists always serialize");
+        let ttl = super::config::config().list_cache_ttl.as_millis() as usize;
+        if let Err(e) = conn.pset_ex::<_, _, ()>(key(eid), stored, ttl).await {
+            warn!(%eid, error = %e, "failed to share question list");
+            super::metrics::incr("shared.failed");
+        }
+    }
+    #[cfg(not(feature = "redis"))]
+    {
+        let _ = (eid, version, questions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_current() {
+        let qid = Uuid::new_v4();
+        let question = Question {
+            votes: 3,
+            hidden: false,
+            answered: true,
+            voters: 2,
+            asked: 1674659874,
+            merged_into: None,
+            asker: None,
+            reactions: Default::default(),
+        };
+        let stored = serde_json::to_string(&Listed {
+            version: 7,
+            questions: vec![(qid, question)],
+        })
+        .unwrap();
+        assert_eq!(current(&stored, 7), Some(vec![(qid, question)]));
+        // anything that's changed since is a miss
+        assert_eq!(current(&stored, 8), None);
+        assert_eq!(current("nonsense", 7), None);
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        assert!(
+            super::super::config::config().list_cache_url.is_some(),
+            "needs LIST_CACHE_URL"
+        );
+        let eid = Uuid::new_v4();
+        let questions = vec![(
+            Uuid::new_v4(),
+            Question {
+                votes: 1,
+                hidden: false,
+                answered: false,
+                voters: 1,
+                asked: 1674659874,
+                merged_into: None,
+                asker: None,
+                reactions: Default::default(),
+            },
+        )];
+        assert_eq!(get(&eid, 1).await, None);
+        put(&eid, 1, &questions).await;
+        assert_eq!(get(&eid, 1).await, Some(questions));
+        assert_eq!(get(&eid, 2).await, None);
+    }
+}
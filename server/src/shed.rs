@@ -0,0 +1,259 @@
+//! Concurrency limits and load-shedding.
+//!
+//! There's a global cap on how many attendee requests we handle at once (`MAX_CONCURRENCY`), plus
+//! optional caps for individual routes (`ROUTE_CONCURRENCY`). When a cap is hit, reads are turned
+//! away right away with a 503 (clients will just poll again), while writes wait their turn, since
+//! dropping someone's question is far worse than making them wait a bit. Health checks never count
+//! against any cap.
+//!
+//! Host actions (the ones authenticated with the event secret) get a lane of their own with a
+//! separate cap (`HOST_CONCURRENCY`). They queue for it rather than being shed, and never compete
+//! with the audience for permits, so the host stays in control even when the audience is
+//! stampeding.
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use http::{header, StatusCode};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Class {
+    /// Attendee reads, which are cheap to retry and so are shed first.
+    Read,
+    /// Attendee writes, which queue rather than get shed.
+    Write,
+    /// Host actions, which have their own lane and queue rather than get shed.
+    Host,
+    /// Health checks and admin endpoints, which are never limited.
+    Exempt,
+}
+
+impl Class {
+    pub(super) fn of(route: &str) -> Self {
+        match route {
+            "event" | "list" | "text" | "pdf" | "archive" | "questions" | "changes" | "ping"
+            | "experiment" | "ws" | "stream" | "mine" | "permalink" | "unfurl" | "board" => {
+                Class::Read
+            }
+            "new" | "ask" | "vote" | "react" | "email" | "survey" | "revise" | "edit"
+            | "retract" => Class::Write,
+            "list_all" | "toggle" | "actions" | "lease" | "leases" | "announce" | "appeal"
+            | "hold" | "close" | "robots" | "networks" | "digest" | "lock" | "templates"
+            | "answer" | "aging" | "history" | "summary" | "merge" | "anomalies" | "order" => {
+                Class::Host
+            }
+            _ => Class::Exempt,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Gate {
+    route: &'static str,
+    class: Class,
+    /// The cap shared by every route in the same lane.
+    lane: Option<Arc<Semaphore>>,
+    local: Option<Arc<Semaphore>>,
+}
+
+fn attendees() -> Option<Arc<Semaphore>> {
+    static ATTENDEES: OnceLock<Option<Arc<Semaphore>>> = OnceLock::new();
+    ATTENDEES
+        .get_or_init(|| {
+            super::config::config()
+                .max_concurrency
+                .map(|n| Arc::new(Semaphore::new(n)))
+        })
+        .clone()
+}
+
+fn hosts() -> Option<Arc<Semaphore>> {
+    static HOSTS: OnceLock<Option<Arc<Semaphore>>> = OnceLock::new();
+    HOSTS
+        .get_or_init(|| {
+            super::config::config()
+                .host_concurrency
+                .map(|n| Arc::new(Semaphore::new(n)))
+        })
+        .clone()
+}
+
+/// The gate for the route named `route`, with limits from the configuration.
+pub(super) fn gate(route: &'static str) -> Gate {
+    let class = Class::of(route);
+    let lane = match class {
+        Class::Read | Class::Write => attendees(),
+        Class::Host => hosts(),
+        Class::Exempt => {
+            return Gate {
+                route,
+                class,
+                lane: None,
+                local: None,
+            }
+        }
+    };
+    Gate {
+        route,
+        class,
+        lane,
+        local: super::config::config()
+            .route_concurrency
+            .get(route)
+            .map(|&n| Arc::new(Semaphore::new(n))),
+    }
+}
+
+impl Gate {
+    #[cfg(test)]
+    pub(super) fn new(route: &'static str, class: Class, lane: usize, local: usize) -> Self {
+        Gate {
+            route,
+            class,
+            lane: Some(Arc::new(Semaphore::new(lane))),
+            local: Some(Arc::new(Semaphore::new(local))),
+        }
+    }
+
+    /// Get a permit from `sem` if there is one, or `Err` if the request should be shed.
+    async fn permit(
+        &self,
+        sem: &Option<Arc<Semaphore>>,
+    ) -> Result<Option<OwnedSemaphorePermit>, ()> {
+        let Some(sem) = sem else {
+            return Ok(None);
+        };
+        match self.class {
+            Class::Read => sem.clone().try_acquire_owned().map(Some).map_err(|_| ()),
+            // the semaphores are never closed, and the timeout layer bounds how long we wait
+            Class::Write | Class::Host => Ok(Some(
+                sem.clone().acquire_owned().await.expect("never closed"),
+            )),
+            Class::Exempt => Ok(None),
+        }
+    }
+}
+
+pub(super) async fn admit<B>(State(gate): State<Gate>, req: Request<B>, next: Next<B>) -> Response {
+    // take the route permit first so that one hot route can't hog all of the lane's permits
+    let Ok(_local) = gate.permit(&gate.local).await else {
+        return shed(&gate);
+    };
+    let Ok(_lane) = gate.permit(&gate.lane).await else {
+        return shed(&gate);
+    };
+    next.run(req).await
+}
+
+fn shed(gate: &Gate) -> Response {
+    super::metrics::incr(format!("shed.{}", gate.route));
+    warn!(route = gate.route, "shedding load");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        AppendHeaders([(header::RETRY_AFTER, "1")]),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn sheds_reads() {
+        let go = Arc::new(tokio::sync::Notify::new());
+        let go2 = go.clone();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| async move { go2.notified().await }).layer(
+                    axum::middleware::from_fn_with_state(
+                        Gate::new("test_shed", Class::Read, 10, 1),
+                        admit,
+                    ),
+                ),
+            )
+            .route("/health", get(|| async {}));
+
+        let first = tokio::spawn(
+            app.clone()
+                .oneshot(Request::get("/slow").body(Body::empty()).unwrap()),
+        );
+        // give the first request a chance to grab the only permit
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let res = app
+            .clone()
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(super::super::metrics::get("shed.test_shed"), 1);
+
+        // unlimited routes are unaffected
+        let res = app
+            .clone()
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        go.notify_one();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn queues_hosts() {
+        let go = Arc::new(tokio::sync::Notify::new());
+        let go2 = go.clone();
+        let app = Router::new().route(
+            "/toggle",
+            get(|| async move { go2.notified().await }).layer(
+                axum::middleware::from_fn_with_state(
+                    Gate::new("test_host", Class::Host, 1, 1),
+                    admit,
+                ),
+            ),
+        );
+
+        let first = tokio::spawn(
+            app.clone()
+                .oneshot(Request::get("/toggle").body(Body::empty()).unwrap()),
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // the second host request waits for the first rather than being turned away
+        let second = tokio::spawn(
+            app.clone()
+                .oneshot(Request::get("/toggle").body(Body::empty()).unwrap()),
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+
+        go.notify_one();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        go.notify_one();
+        assert_eq!(second.await.unwrap().unwrap().status(), StatusCode::OK);
+        assert_eq!(super::super::metrics::get("shed.test_host"), 0);
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(Class::of("list"), Class::Read);
+        assert_eq!(Class::of("ask"), Class::Write);
+        assert_eq!(Class::of("toggle"), Class::Host);
+        assert_eq!(Class::of("list_all"), Class::Host);
+        assert_eq!(Class::of("health"), Class::Exempt);
+    }
+}
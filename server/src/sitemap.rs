@@ -0,0 +1,316 @@
+//! Telling search engines about public archives, and letting hosts keep theirs out of them.
+//!
+//! `GET /sitemap.xml` lists the [archive](super::archive) page of every event that's archived,
+//! isn't frozen, and whose host hasn't asked to keep it out of search engines with
+//! `POST /api/event/:eid/robots/:secret` (and `{"index": false}`). Archives that are left out are
+//! still there for anyone with the link, but their pages also tell crawlers not to index them. What
+//! the host decided goes in the event's moderation log, like closing it does.
+//!
+//! A sitemap has to have full URLs, so there's only one if `PUBLIC_URL` is set. Finding the
+//! archived events means going through all of them, so it's cached for an hour.
+
+use super::{
+    moderation::{self, Action, Kind, Role},
+    Backend,
+};
+use axum::extract::{Path, State};
+use axum::response::{AppendHeaders, Json};
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use serde::Deserialize;
+use std::{fmt::Write, time::SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Sitemaps can't list more URLs than this.
+const MAX_URLS: usize = 50_000;
+
+impl Backend {
+    /// The moderation log of every event that has one.
+    pub(super) async fn moderation_logs(
+        &self,
+    ) -> Result<Vec<(Uuid, Vec<Action>)>, aws_sdk_dynamodb::Error> {
+        let stored = |logs: Vec<(Uuid, String)>| {
+            logs.into_iter()
+                .map(|(eid, log)| (eid, moderation::stored(Some(&log))))
+                .collect()
+        };
+        match self {
+            Self::Dynamo(dynamo) => {
+                let mut logs = Vec::new();
+                let mut start = None;
+                loop {
+                    let page = dynamo
+                        .scan()
+                        .table_name("events")
+                        .projection_expression("id, moderation")
+                        .filter_expression("attribute_exists(moderation)")
+                        .set_exclusive_start_key(start)
+                        .send()
+                        .await?;
+                    for item in page.items().unwrap_or_default() {
+                        let eid = item
+                            .get("id")
+                            .and_then(|id| id.as_s().ok())
+                            .and_then(|id| Uuid::parse_str(id).ok());
+                        let log = item.get("moderation").and_then(|l| l.as_s().ok());
+                        if let (Some(eid), Some(log)) = (eid, log) {
+                            logs.push((eid, log.clone()));
+                        }
+                    }
+                    start = page.last_evaluated_key().cloned();
+                    if start.is_none() {
+                        return Ok(stored(logs));
+                    }
+                }
+            }
+            Self::Local(local) => {
+                let local = local.read().unwrap();
+                Ok(local
+                    .moderation
+                    .iter()
+                    .map(|(eid, log)| (*eid, log.clone()))
+                    .collect())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .moderation_logs()
+                .await
+                .map(stored)
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .moderation_logs()
+                .await
+                .map(stored)
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .moderation_logs()
+                .map(stored)
+                .map_err(super::mint_unhandled),
+        }
+    }
+}
+
+/// The events whose archives belong in the sitemap, and when they were archived, newest first.
+fn listed(logs: &[(Uuid, Vec<Action>)]) -> Vec<(Uuid, u64)> {
+    let mut listed: Vec<_> = logs
+        .iter()
+        .filter(|(_, log)| moderation::frozen(log).is_none() && moderation::unlisted(log).is_none())
+        .filter_map(|(eid, log)| Some((*eid, moderation::archived(log)?.at)))
+        .collect();
+    listed.sort_unstable_by(|(aid, a), (bid, b)| b.cmp(a).then(aid.cmp(bid)));
+    listed.truncate(MAX_URLS);
+    listed
+}
+
+/// The date (as `YYYY-MM-DD`) of `at` seconds since the epoch.
+fn date(at: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (at / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+fn render(base: &str, listed: &[(Uuid, u64)]) -> String {
+    let base = super::archive::escape(base);
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (eid, at) in listed {
+        let _ = writeln!(
+            out,
+            "<url><loc>{base}/archive/{eid}</loc><lastmod>{}</lastmod></url>",
+            date(*at)
+        );
+    }
+    out.push_str("</urlset>\n");
+    out
+}
+
+pub(super) async fn sitemap(
+    State(dynamo): State<Backend>,
+) -> (
+    AppendHeaders<[(HeaderName, &'static str); 2]>,
+    Result<String, StatusCode>,
+) {
+    let xml = |cache| {
+        AppendHeaders([
+            (header::CACHE_CONTROL, cache),
+            (header::CONTENT_TYPE, "application/xml"),
+        ])
+    };
+    let Some(base) = &super::config::config().public_url else {
+        // that's not going to change without a deploy
+        return (xml("max-age=3600"), Err(StatusCode::NOT_FOUND));
+    };
+    match dynamo.moderation_logs().await {
+        Ok(logs) => {
+            let listed = listed(&logs);
+            debug!(n = listed.len(), "listed public archives");
+            (xml("max-age=3600"), Ok(render(base, &listed)))
+        }
+        Err(e) => {
+            error!(error = %e, "failed to find public archives");
+            (xml("no-cache"), Err(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+/// Whether the host wants search engines to index the event.
+#[derive(Debug, Deserialize)]
+pub(super) struct Robots {
+    index: bool,
+}
+
+/// Let search engines index the event or not, for its host.
+pub(super) async fn robots(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+    Json(req): Json<Robots>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    let kind = if req.index {
+        Kind::Listed
+    } else {
+        Kind::Unlisted
+    };
+    let (log, changed) = moderation::amend(&dynamo, &eid, meta, "robots", |log| {
+        if moderation::unlisted(log).is_none() == req.index {
+            return Ok(false);
+        }
+        log.push(Action {
+            at: super::clock::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            kind,
+            note: String::new(),
+            by: Some(Role::Host),
+        });
+        Ok(true)
+    })
+    .await?;
+    if !changed {
+        return Ok(Json(moderation::status(&log)));
+    }
+    info!(%eid, ?kind, "host changed whether event is indexed");
+    Ok(Json(moderation::status(&log)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{Close, Stage};
+
+    async fn inner(backend: Backend) {
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+            let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+            let secret = e["secret"].as_str().unwrap().to_string();
+            events.push((eid, secret));
+        }
+        let [(archived, a), (unlisted, u), (open, _)] = &events[..] else {
+            unreachable!();
+        };
+        for (eid, secret) in [(archived, a), (unlisted, u)] {
+            crate::archive::close(
+                Path((*eid, secret.clone())),
+                State(backend.clone()),
+                Json(Close {
+                    state: Stage::Archived,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+        let Json(s) = super::robots(
+            Path((*unlisted, u.clone())),
+            State(backend.clone()),
+            Json(Robots { index: false }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(s["indexed"], false);
+
+        let listed: Vec<_> = listed(&backend.moderation_logs().await.unwrap())
+            .into_iter()
+            .map(|(eid, _)| eid)
+            .collect();
+        assert!(listed.contains(archived));
+        assert!(!listed.contains(unlisted));
+        assert!(!listed.contains(open));
+
+        // unlisted archives are still there, but tell crawlers to stay away
+        let (AppendHeaders(headers), page) =
+            crate::archive::archive(Path(*unlisted), State(backend.clone())).await;
+        assert!(headers.contains(&(HeaderName::from_static("x-robots-tag"), "noindex")));
+        assert!(page.unwrap().0.contains("noindex"));
+        let (AppendHeaders(headers), page) =
+            crate::archive::archive(Path(*archived), State(backend.clone())).await;
+        assert!(headers.iter().all(|(h, _)| h != "x-robots-tag"));
+        assert!(!page.unwrap().0.contains("noindex"));
+
+        for (eid, _) in &events {
+            backend.delete(eid).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[test]
+    fn renders() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_700_000_000), "2023-11-14");
+
+        let eid = Uuid::new_v4();
+        let xml = render("https://example.com", &[(eid, 0)]);
+        assert!(xml.contains(&format!(
+            "<url><loc>https://example.com/archive/{eid}</loc><lastmod>1970-01-01</lastmod></url>"
+        )));
+    }
+}
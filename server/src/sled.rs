@@ -0,0 +1,736 @@
+//! An embedded backend on top of [sled], so that a single binary and a data directory are all a
+//! deployment needs.
+//!
+//! Items are stored in DynamoDB's JSON format (`{"votes": {"N": "3"}}`), and results are handed
+//! back shaped like DynamoDB responses, just like with the other non-DynamoDB backends. The trees
+//! are:
+//!
+//! - `events`: event id to event item, which also holds the event's last change `seq`.
+//! - `questions`: question id to question item.
+//! - `event_questions`: event id followed by question id, to nothing. It's the index for listing.
+//! - `changes`: event id followed by the big-endian `seq`, to change item.
+//!
+//! There's no TTL in sled, so expired events (and everything that belongs to them) are swept out
+//! whenever the database is opened. Events on legal hold have no `expire`, so they never are.
+
+use super::{
+    announce::{Announcement, Scheduled},
+    react::Reaction,
+    Meta,
+};
+use aws_sdk_dynamodb::{
+    model::AttributeValue,
+    output::{BatchGetItemOutput, GetItemOutput, PutItemOutput, QueryOutput, UpdateItemOutput},
+    types::SdkError,
+};
+use sled::{transaction::TransactionError, Db, Transactional, Tree};
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+type Item = HashMap<String, AttributeValue>;
+
+#[derive(Clone, Debug)]
+pub(super) struct Sled {
+    events: Tree,
+    questions: Tree,
+    event_questions: Tree,
+    changes: Tree,
+}
+
+fn encode<K: Into<String>>(item: impl IntoIterator<Item = (K, AttributeValue)>) -> Vec<u8> {
+    serde_json::to_vec(&to_json(item)).expect("items always serialize")
+}
+
+fn to_json<K: Into<String>>(
+    item: impl IntoIterator<Item = (K, AttributeValue)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    item.into_iter()
+        .map(|(k, v)| {
+            let v = match v {
+                AttributeValue::S(s) => serde_json::json!({ "S": s }),
+                AttributeValue::N(n) => serde_json::json!({ "N": n }),
+                AttributeValue::Bool(b) => serde_json::json!({ "BOOL": b }),
+                AttributeValue::M(m) => serde_json::json!({ "M": to_json(m) }),
+                v => unreachable!("no attributes are of type {v:?}"),
+            };
+            (k.into(), v)
+        })
+        .collect()
+}
+
+fn decode(bytes: &[u8]) -> Item {
+    let item: HashMap<String, HashMap<String, serde_json::Value>> =
+        serde_json::from_slice(bytes).expect("only valid items are stored");
+    from_json(item)
+}
+
+fn from_json(item: HashMap<String, HashMap<String, serde_json::Value>>) -> Item {
+    item.into_iter()
+        .filter_map(|(k, v)| {
+            let v = match v.into_iter().next()? {
+                (t, serde_json::Value::String(s)) if t == "S" => AttributeValue::S(s),
+                (t, serde_json::Value::String(n)) if t == "N" => AttributeValue::N(n),
+                (t, serde_json::Value::Bool(b)) if t == "BOOL" => AttributeValue::Bool(b),
+                (t, m @ serde_json::Value::Object(_)) if t == "M" => {
+                    AttributeValue::M(from_json(serde_json::from_value(m).ok()?))
+                }
+                v => {
+                    warn!(field = k, value = ?v, "ignoring field of unexpected type");
+                    return None;
+                }
+            };
+            Some((k, v))
+        })
+        .collect()
+}
+
+/// Keep only the `fields` of `item`.
+fn project(mut item: Item, fields: &[&str]) -> Item {
+    item.retain(|k, _| fields.contains(&&**k));
+    item
+}
+
+fn number(item: &Item, field: &str) -> Option<u64> {
+    item.get(field)?.as_n().ok()?.parse().ok()
+}
+
+fn changes_key(eid: &Uuid, seq: u64) -> Vec<u8> {
+    [&eid.as_bytes()[..], &seq.to_be_bytes()].concat()
+}
+
+fn query(items: Vec<Item>) -> QueryOutput {
+    QueryOutput::builder()
+        .set_count(Some(items.len() as i32))
+        .set_items(Some(items))
+        .build()
+}
+
+impl Sled {
+    /// Open (or create) the database in `dir`, and sweep out everything that has expired.
+    pub(super) fn open(dir: impl AsRef<Path>) -> Result<Self, sled::Error> {
+        Self::from_db(sled::open(dir)?)
+    }
+
+    /// A database that's deleted again when it's dropped.
+    #[cfg(test)]
+    pub(super) fn temporary() -> Result<Self, sled::Error> {
+        Self::from_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    fn from_db(db: Db) -> Result<Self, sled::Error> {
+        let sled = Sled {
+            events: db.open_tree("events")?,
+            questions: db.open_tree("questions")?,
+            event_questions: db.open_tree("event_questions")?,
+            changes: db.open_tree("changes")?,
+        };
+        sled.sweep()?;
+        Ok(sled)
+    }
+
+    /// Remove every event that has expired, along with its questions and changes.
+    fn sweep(&self) -> Result<(), sled::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut swept = 0;
+        for event in self.events.iter() {
+            let (eid, event) = event?;
+            if number(&decode(&event), "expire").is_some_and(|expire| expire < now) {
+                let eid = Uuid::from_slice(&eid).expect("event keys are ids");
+                self.remove(&eid)?;
+                swept += 1;
+            }
+        }
+        if swept != 0 {
+            info!(swept, "removed expired events");
+        }
+        Ok(())
+    }
+
+    fn remove(&self, eid: &Uuid) -> Result<(), sled::Error> {
+        for key in self.event_questions.scan_prefix(eid.as_bytes()).keys() {
+            let key = key?;
+            self.questions.remove(&key[16..])?;
+            self.event_questions.remove(key)?;
+        }
+        for key in self.changes.scan_prefix(eid.as_bytes()).keys() {
+            self.changes.remove(key?)?;
+        }
+        self.events.remove(eid.as_bytes())?;
+        Ok(())
+    }
+
+    pub(super) async fn create_event<E>(
+        &self,
+        eid: &Uuid,
+        item: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<PutItemOutput, SdkError<E>> {
+        self.events
+            .insert(eid.as_bytes(), encode(item))
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(PutItemOutput::builder().build())
+    }
+
+    pub(super) fn meta(&self, eid: &Uuid) -> Result<Option<Meta>, sled::Error> {
+        Ok(self.events.get(eid.as_bytes())?.and_then(|event| {
+            let event = decode(&event);
+            Some(Meta {
+                secret: event.get("secret")?.as_s().ok()?.clone(),
+                version: number(&event, "seq").unwrap_or(0),
+                announcement: Announcement::stored(
+                    event
+                        .get("announcement")
+                        .and_then(|a| a.as_s().ok())
+                        .cloned(),
+                    number(&event, "announced_until"),
+                ),
+                scheduled: Scheduled::stored(
+                    event
+                        .get("scheduled")
+                        .and_then(|s| s.as_s().ok())
+                        .map(String::as_str),
+                ),
+                moderation: super::moderation::stored(
+                    event
+                        .get("moderation")
+                        .and_then(|m| m.as_s().ok())
+                        .map(String::as_str),
+                ),
+                moderated: number(&event, "moderated").unwrap_or(0),
+                locked: event.get("locked").and_then(|l| l.as_s().ok()).cloned(),
+            })
+        }))
+    }
+
+    pub(super) fn announce(
+        &self,
+        eid: &Uuid,
+        announcement: Option<&Announcement>,
+    ) -> Result<(), sled::Error> {
+        let event = self.events.update_and_fetch(eid.as_bytes(), |event| {
+            let mut event = decode(event?);
+            if let Some(a) = announcement {
+                event.insert(
+                    String::from("announcement"),
+                    AttributeValue::S(a.text.clone()),
+                );
+                event.insert(
+                    String::from("announced_until"),
+                    AttributeValue::N(a.until.to_string()),
+                );
+            } else {
+                event.remove("announcement");
+                event.remove("announced_until");
+            }
+            Some(encode(event))
+        })?;
+        if event.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "announcing in non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make `scheduled` (as stored) the event's queue of announcements, or remove it if `None`.
+    pub(super) fn schedule(
+        &self,
+        eid: &Uuid,
+        scheduled: Option<String>,
+    ) -> Result<(), sled::Error> {
+        let event = self.events.update_and_fetch(eid.as_bytes(), |event| {
+            let mut event = decode(event?);
+            if let Some(s) = &scheduled {
+                event.insert(String::from("scheduled"), AttributeValue::S(s.clone()));
+            } else {
+                event.remove("scheduled");
+            }
+            Some(encode(event))
+        })?;
+        if event.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "scheduling in non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make `log` (as stored) the event's moderation log, and `locked` the order its question list
+    /// is locked in if there's a new one, unless the log's been written since it was written for
+    /// the `moderated`th time, and return whether it was.
+    pub(super) fn moderate(
+        &self,
+        eid: &Uuid,
+        moderated: u64,
+        log: String,
+        locked: Option<&str>,
+    ) -> Result<bool, sled::Error> {
+        let mut written = false;
+        let event = self.events.update_and_fetch(eid.as_bytes(), |event| {
+            let mut event = decode(event?);
+            // this may run more than once if the event changes under it
+            written = number(&event, "moderated").unwrap_or(0) == moderated;
+            if written {
+                event.insert(String::from("moderation"), AttributeValue::S(log.clone()));
+                event.insert(
+                    String::from("moderated"),
+                    AttributeValue::N((moderated + 1).to_string()),
+                );
+                if let Some(locked) = locked {
+                    event.insert(
+                        String::from("locked"),
+                        AttributeValue::S(locked.to_string()),
+                    );
+                }
+            }
+            Some(encode(event))
+        })?;
+        if event.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "moderating non-existing event {eid}"
+            )));
+        }
+        Ok(written)
+    }
+
+    /// Make `policy` (as stored) the event's retention override, or remove it if `None`.
+    pub(super) fn set_retention(
+        &self,
+        eid: &Uuid,
+        policy: Option<String>,
+    ) -> Result<(), sled::Error> {
+        let event = self.events.update_and_fetch(eid.as_bytes(), |event| {
+            let mut event = decode(event?);
+            if let Some(p) = &policy {
+                event.insert(String::from("retention"), AttributeValue::S(p.clone()));
+            } else {
+                event.remove("retention");
+            }
+            Some(encode(event))
+        })?;
+        if event.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "setting retention of non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Note on the event that it was warmed up `at`, and read the note back.
+    pub(super) fn canary(&self, eid: &Uuid, at: u64) -> Result<Option<u64>, sled::Error> {
+        let event = self.events.update_and_fetch(eid.as_bytes(), |event| {
+            let mut event = decode(event?);
+            event.insert(String::from("warmed"), AttributeValue::N(at.to_string()));
+            Some(encode(event))
+        })?;
+        if event.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "warming non-existing event {eid}"
+            )));
+        }
+        Ok(self
+            .events
+            .get(eid.as_bytes())?
+            .and_then(|event| number(&decode(&event), "warmed")))
+    }
+
+    /// Every event's id and moderation log (as stored), for the events that have one.
+    pub(super) fn moderation_logs(&self) -> Result<Vec<(Uuid, String)>, sled::Error> {
+        let mut logs = Vec::new();
+        for event in self.events.iter() {
+            let (eid, event) = event?;
+            let eid = Uuid::from_slice(&eid).expect("event keys are ids");
+            if let Some(AttributeValue::S(log)) = decode(&event).remove("moderation") {
+                logs.push((eid, log));
+            }
+        }
+        Ok(logs)
+    }
+
+    /// Every event's id, and its retention override if it has one.
+    pub(super) fn all_events(&self) -> Result<Vec<(Uuid, Option<String>)>, sled::Error> {
+        self.events
+            .iter()
+            .map(|event| {
+                let (eid, event) = event?;
+                let eid = Uuid::from_slice(&eid).expect("event keys are ids");
+                let retention = match decode(&event).remove("retention") {
+                    Some(AttributeValue::S(p)) => Some(p),
+                    _ => None,
+                };
+                Ok((eid, retention))
+            })
+            .collect()
+    }
+
+    pub(super) fn delete_question(&self, eid: &Uuid, qid: &Uuid) -> Result<(), sled::Error> {
+        if self.questions.remove(qid.as_bytes())?.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "deleting non-existing question {qid}"
+            )));
+        }
+        self.event_questions
+            .remove([&eid.as_bytes()[..], qid.as_bytes()].concat())?;
+        Ok(())
+    }
+
+    /// Stop the event from being swept, or have it swept once it's past `expire` again.
+    ///
+    /// Its questions and changes only ever go when the event does, so they don't need touching.
+    pub(super) fn keep(&self, eid: &Uuid, expire: Option<u64>) -> Result<(), sled::Error> {
+        let event = self.events.update_and_fetch(eid.as_bytes(), |event| {
+            let mut event = decode(event?);
+            if let Some(expire) = expire {
+                event.insert(
+                    String::from("expire"),
+                    AttributeValue::N(expire.to_string()),
+                );
+            } else {
+                event.remove("expire");
+            }
+            Some(encode(event))
+        })?;
+        if event.is_none() {
+            return Err(sled::Error::Unsupported(format!(
+                "holding non-existing event {eid}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub(super) async fn event<E>(&self, eid: &Uuid) -> Result<GetItemOutput, SdkError<E>> {
+        let exists = self
+            .events
+            .contains_key(eid.as_bytes())
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(GetItemOutput::builder()
+            .set_item(exists.then(|| {
+                HashMap::from_iter([(String::from("id"), AttributeValue::S(eid.to_string()))])
+            }))
+            .build())
+    }
+
+    pub(super) async fn ask<E>(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        item: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<PutItemOutput, SdkError<E>> {
+        self.questions
+            .insert(qid.as_bytes(), encode(item))
+            .map_err(super::mint_dispatch_failure)?;
+        self.event_questions
+            .insert([&eid.as_bytes()[..], qid.as_bytes()].concat(), &[])
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(PutItemOutput::builder().build())
+    }
+
+    /// The questions of `eid`, most votes first.
+    pub(super) async fn list<E>(
+        &self,
+        eid: &Uuid,
+        has_secret: bool,
+    ) -> Result<QueryOutput, SdkError<E>> {
+        let mut items = Vec::new();
+        for key in self.event_questions.scan_prefix(eid.as_bytes()).keys() {
+            let key = key.map_err(super::mint_dispatch_failure)?;
+            let Some(q) = self
+                .questions
+                .get(&key[16..])
+                .map_err(super::mint_dispatch_failure)?
+            else {
+                continue;
+            };
+            let q = decode(&q);
+            if has_secret || q.get("hidden") == Some(&AttributeValue::Bool(false)) {
+                items.push(project(
+                    q,
+                    &[
+                        "id",
+                        "eid",
+                        "votes",
+                        "hidden",
+                        "answered",
+                        "voters",
+                        "when",
+                        "author",
+                        "merged_into",
+                        "reactions",
+                    ],
+                ));
+            }
+        }
+        items.sort_by_key(|q| std::cmp::Reverse(number(q, "votes")));
+        Ok(query(items))
+    }
+
+    pub(super) fn questions(&self, qids: &[Uuid]) -> Result<BatchGetItemOutput, sled::Error> {
+        let mut items = Vec::new();
+        for qid in qids {
+            if let Some(q) = self.questions.get(qid.as_bytes())? {
+                items.push(project(
+                    decode(&q),
+                    &[
+                        "id",
+                        "eid",
+                        "text",
+                        "when",
+                        "who",
+                        "frozen",
+                        "hidden",
+                        "reason",
+                        "revises",
+                        "revised",
+                        "author",
+                        "history",
+                        "source",
+                        "merged_into",
+                    ],
+                ));
+            }
+        }
+        Ok(BatchGetItemOutput::builder()
+            .set_responses(Some(HashMap::from_iter([(
+                String::from("questions"),
+                items,
+            )])))
+            .build())
+    }
+
+    /// The answer published to `qid`, if there is one.
+    pub(super) fn published(&self, qid: &Uuid) -> Result<Option<String>, sled::Error> {
+        let Some(q) = self.questions.get(qid.as_bytes())? else {
+            return Ok(None);
+        };
+        Ok(decode(&q)
+            .get("answer")
+            .and_then(|v| v.as_s().ok())
+            .cloned())
+    }
+
+    /// Apply `f` to the question `qid`, and return the question as it is afterwards.
+    async fn update<E>(
+        &self,
+        qid: &Uuid,
+        f: impl Fn(&mut Item),
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let q = self
+            .questions
+            .update_and_fetch(qid.as_bytes(), |q| {
+                let mut q = decode(q?);
+                f(&mut q);
+                Some(encode(q))
+            })
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(UpdateItemOutput::builder()
+            .set_attributes(q.map(|q| decode(&q)))
+            .build())
+    }
+
+    /// Change the votes of `qid` by `by`, and return the question as it is afterwards.
+    pub(super) async fn vote<E>(
+        &self,
+        qid: &Uuid,
+        by: i64,
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        self.update(qid, |q| {
+            // see Backend::vote
+            if q.get("frozen").and_then(|f| f.as_bool().ok()) == Some(&true)
+                || q.contains_key("merged_into")
+            {
+                return;
+            }
+            if let Some(AttributeValue::N(n)) = q.get_mut("votes") {
+                let votes = n.parse::<i64>().expect("votes values are numbers");
+                *n = (votes + by).to_string();
+            }
+        })
+        .await
+    }
+
+    /// Add one to the count of `reaction` on `qid`, and return the question as it is afterwards.
+    pub(super) async fn react<E>(
+        &self,
+        eid: &Uuid,
+        qid: &Uuid,
+        reaction: Reaction,
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        let eid = AttributeValue::S(eid.to_string());
+        self.update(qid, |q| {
+            // see Backend::react
+            if q.get("eid") != Some(&eid) {
+                return;
+            }
+            super::react::bump(
+                q.entry(String::from("reactions"))
+                    .or_insert_with(|| AttributeValue::M(HashMap::new())),
+                reaction,
+            );
+        })
+        .await
+    }
+
+    /// Replace the voter sketch of `qid` with `new`, unless it's no longer `old`.
+    pub(super) fn set_voters(
+        &self,
+        qid: &Uuid,
+        old: Option<&str>,
+        new: &str,
+    ) -> Result<bool, sled::Error> {
+        let mut replaced = false;
+        self.questions.fetch_and_update(qid.as_bytes(), |q| {
+            replaced = false;
+            let mut q = decode(q?);
+            if q.get("voters")
+                .and_then(|v| v.as_s().ok())
+                .map(String::as_str)
+                == old
+            {
+                q.insert(String::from("voters"), AttributeValue::S(new.to_string()));
+                replaced = true;
+            }
+            Some(encode(q))
+        })?;
+        Ok(replaced)
+    }
+
+    /// Overwrite `fields` of the question `qid`.
+    pub(super) async fn set<E>(
+        &self,
+        qid: &Uuid,
+        fields: &[(&'static str, AttributeValue)],
+    ) -> Result<UpdateItemOutput, SdkError<E>> {
+        self.update(qid, |q| {
+            for (k, v) in fields {
+                q.insert(k.to_string(), v.clone());
+            }
+        })
+        .await
+    }
+
+    /// Append `change` to the log of `eid`, and return its sequence number.
+    pub(super) fn record(
+        &self,
+        eid: &Uuid,
+        change: impl IntoIterator<Item = (&'static str, AttributeValue)>,
+    ) -> Result<u64, sled::Error> {
+        let change: Vec<_> = change.into_iter().collect();
+        // both or neither, so that a failed write never leaves a gap in the log
+        let seq = (&self.events, &self.changes)
+            .transaction(|(events, changes)| {
+                let Some(event) = events.get(eid.as_bytes())? else {
+                    return Ok(None);
+                };
+                let mut event = decode(&event);
+                let seq = number(&event, "seq").unwrap_or(0) + 1;
+                event.insert(String::from("seq"), AttributeValue::N(seq.to_string()));
+                events.insert(eid.as_bytes(), encode(event))?;
+
+                let item = change.iter().cloned().chain([
+                    ("eid", AttributeValue::S(eid.to_string())),
+                    ("seq", AttributeValue::N(seq.to_string())),
+                ]);
+                changes.insert(changes_key(eid, seq), encode(item))?;
+                Ok(Some(seq))
+            })
+            .map_err(|e: TransactionError<()>| match e {
+                TransactionError::Abort(()) => unreachable!("recording never aborts"),
+                TransactionError::Storage(e) => e,
+            })?;
+        seq.ok_or_else(|| {
+            sled::Error::Unsupported(format!("recording change for non-existing event {eid}"))
+        })
+    }
+
+    pub(super) async fn changes<E>(
+        &self,
+        eid: &Uuid,
+        since: u64,
+        limit: usize,
+    ) -> Result<QueryOutput, SdkError<E>> {
+        let items = self
+            .changes
+            .range(changes_key(eid, since + 1)..=changes_key(eid, u64::MAX))
+            .values()
+            .take(limit)
+            .map(|c| c.map(|c| decode(&c)))
+            .collect::<Result<_, _>>()
+            .map_err(super::mint_dispatch_failure)?;
+        Ok(query(items))
+    }
+
+    #[cfg(test)]
+    pub(super) fn delete(&self, eid: &Uuid) {
+        self.remove(eid).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let item = [
+            ("id", AttributeValue::S(String::from("x"))),
+            ("votes", AttributeValue::N(String::from("3"))),
+            ("hidden", AttributeValue::Bool(true)),
+        ];
+        let decoded = decode(&encode(item.clone()));
+        assert_eq!(decoded.len(), 3);
+        for (k, v) in item {
+            assert_eq!(decoded[k], v);
+        }
+    }
+
+    #[tokio::test]
+    async fn sweeps_expired() {
+        let sled = Sled::temporary().unwrap();
+        let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+        for (eid, expire) in [(old, 1), (new, u64::MAX / 2)] {
+            sled.create_event::<()>(
+                &eid,
+                [
+                    ("id", AttributeValue::S(eid.to_string())),
+                    ("secret", AttributeValue::S(String::from("s"))),
+                    ("expire", AttributeValue::N(expire.to_string())),
+                ],
+            )
+            .await
+            .unwrap();
+            sled.ask::<()>(
+                &eid,
+                &Uuid::new_v4(),
+                [("eid", AttributeValue::S(eid.to_string()))],
+            )
+            .await
+            .unwrap();
+        }
+
+        sled.sweep().unwrap();
+        assert_eq!(sled.meta(&old).unwrap(), None);
+        assert_eq!(
+            sled.meta(&new).unwrap(),
+            Some(Meta {
+                secret: String::from("s"),
+                version: 0,
+                announcement: None,
+                scheduled: Vec::new(),
+                moderation: Vec::new(),
+                moderated: 0,
+                locked: None,
+            })
+        );
+        assert_eq!(sled.questions.len(), 1);
+        assert_eq!(sled.event_questions.len(), 1);
+    }
+}
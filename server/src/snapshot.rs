@@ -0,0 +1,635 @@
+//! Exporting every table at once, and loading such an export back, for disaster recovery drills.
+//!
+//! `snapshot --out DIR` scans each table in `--segments` parallel segments, and writes every item
+//! it finds to `DIR/<table>/<segment>.jsonl`, in DynamoDB's JSON format. After each page, where
+//! the segment got to is written to `DIR/<table>/<segment>.checkpoint`, so a snapshot that was
+//! cut off picks up where it left off when it's run again with the same `DIR`, and the items of a
+//! page that was written but not checkpointed are dropped rather than written twice.
+//! `DIR/manifest.json` says when the snapshot started and finished, and how many items each table
+//! had.
+//!
+//! Scans read consistently, but they aren't a point in time: items written while a snapshot runs
+//! may or may not be in it. The tables are exported children first, questions and change logs
+//! before the events they belong to, so that whatever made it in has its event along with it.
+//! Only events that expire in the meantime can go missing, which `restore --verify` points out.
+//!
+//! `restore --from DIR` writes a finished snapshot into the tables, events first, overwriting any
+//! items with the same keys. With `--verify`, it then checks that every question and change log in
+//! the tables belongs to an event that's there too, and fails if not.
+//!
+//! Both need `dynamodb:Scan` and `dynamodb:BatchWriteItem` on all three tables, which the server's
+//! own role doesn't get (see [`super::infra`]), so run them with an operator's credentials. Only
+//! DynamoDB is supported; the other backends have their own tools for this, like copying the data
+//! directory of sled or the in-memory backend.
+
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, PutRequest, WriteRequest},
+    Client,
+};
+use futures_util::future::try_join_all;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+type Item = HashMap<String, AttributeValue>;
+
+/// The tables, children first.
+const TABLES: [&str; 3] = ["questions", "changes", "events"];
+
+/// How many items DynamoDB takes in one `BatchWriteItem`.
+const BATCH: usize = 25;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Manifest {
+    segments: u32,
+    started: u64,
+    finished: Option<u64>,
+    /// How many items each table had.
+    tables: BTreeMap<String, usize>,
+}
+
+/// How far a segment got.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    /// Where to continue the scan from, unless it's done.
+    next: Option<serde_json::Value>,
+    done: bool,
+    /// How many items, and bytes of them, have been written so far.
+    items: usize,
+    bytes: u64,
+}
+
+fn to_json(v: &AttributeValue) -> serde_json::Value {
+    use serde_json::json;
+    match v {
+        AttributeValue::S(s) => json!({ "S": s }),
+        AttributeValue::N(n) => json!({ "N": n }),
+        AttributeValue::Bool(b) => json!({ "BOOL": b }),
+        AttributeValue::Null(n) => json!({ "NULL": n }),
+        AttributeValue::Ss(ss) => json!({ "SS": ss }),
+        AttributeValue::Ns(ns) => json!({ "NS": ns }),
+        AttributeValue::L(l) => json!({ "L": l.iter().map(to_json).collect::<Vec<_>>() }),
+        AttributeValue::M(m) => json!({ "M": item_to_json(m) }),
+        v => unreachable!("no attributes are of type {v:?}"),
+    }
+}
+
+fn from_json(v: &serde_json::Value) -> Option<AttributeValue> {
+    let strings = |v: &serde_json::Value| -> Option<Vec<String>> {
+        v.as_array()?
+            .iter()
+            .map(|s| s.as_str().map(String::from))
+            .collect()
+    };
+    let (ty, v) = v.as_object()?.iter().next()?;
+    Some(match ty.as_str() {
+        "S" => AttributeValue::S(v.as_str()?.to_string()),
+        "N" => AttributeValue::N(v.as_str()?.to_string()),
+        "BOOL" => AttributeValue::Bool(v.as_bool()?),
+        "NULL" => AttributeValue::Null(v.as_bool()?),
+        "SS" => AttributeValue::Ss(strings(v)?),
+        "NS" => AttributeValue::Ns(strings(v)?),
+        "L" => AttributeValue::L(v.as_array()?.iter().map(from_json).collect::<Option<_>>()?),
+        "M" => AttributeValue::M(item_from_json(v)?),
+        _ => return None,
+    })
+}
+
+fn item_to_json(item: &Item) -> serde_json::Value {
+    item.iter()
+        .map(|(k, v)| (k.clone(), to_json(v)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn item_from_json(item: &serde_json::Value) -> Option<Item> {
+    item.as_object()?
+        .iter()
+        .map(|(k, v)| Some((k.clone(), from_json(v)?)))
+        .collect()
+}
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Write `value` to `path` as JSON, all at once or not at all.
+fn write_json(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    fs::rename(&tmp, path)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn segment_path(dir: &Path, table: &str, segment: u32, ext: &str) -> PathBuf {
+    dir.join(table).join(format!("{segment}.{ext}"))
+}
+
+/// Scan `segment` of `table` into `dir`, from wherever it got to last time. Returns how many
+/// items it has.
+async fn scan_segment(
+    dynamo: &Client,
+    dir: &Path,
+    table: &'static str,
+    segment: u32,
+    segments: u32,
+) -> Result<usize, Error> {
+    let checkpoint = segment_path(dir, table, segment, "checkpoint");
+    let mut at: Checkpoint = read_json(&checkpoint)?.unwrap_or_default();
+    if at.done {
+        return Ok(at.items);
+    }
+    let mut out = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(segment_path(dir, table, segment, "jsonl"))?;
+    // anything past the checkpoint is from a page that'll be scanned again
+    out.set_len(at.bytes)?;
+    out.seek(SeekFrom::End(0))?;
+    loop {
+        let start = match &at.next {
+            Some(next) => Some(item_from_json(next).ok_or("malformed checkpoint")?),
+            None => None,
+        };
+        let page = dynamo
+            .scan()
+            .table_name(table)
+            .segment(segment as i32)
+            .total_segments(segments as i32)
+            .consistent_read(true)
+            .set_exclusive_start_key(start)
+            .send()
+            .await?;
+        let mut buf = Vec::new();
+        let items = page.items().unwrap_or_default();
+        for item in items {
+            serde_json::to_writer(&mut buf, &item_to_json(item))?;
+            buf.push(b'\n');
+        }
+        out.write_all(&buf)?;
+        out.sync_data()?;
+        at.items += items.len();
+        at.bytes += buf.len() as u64;
+        at.next = page.last_evaluated_key().map(item_to_json);
+        at.done = at.next.is_none();
+        write_json(&checkpoint, &at)?;
+        if at.done {
+            return Ok(at.items);
+        }
+    }
+}
+
+/// Export every table into `dir`, or finish doing so.
+pub(super) async fn snapshot(
+    dynamo: &Client,
+    dir: &Path,
+    segments: u32,
+) -> Result<Manifest, Error> {
+    let segments = segments.max(1);
+    fs::create_dir_all(dir)?;
+    let path = dir.join("manifest.json");
+    let mut manifest = match read_json::<Manifest>(&path)? {
+        Some(m) if m.segments != segments => {
+            return Err(format!(
+                "the snapshot in {} was started with {} segments",
+                dir.display(),
+                m.segments
+            )
+            .into());
+        }
+        Some(m) => m,
+        None => Manifest {
+            segments,
+            started: now(),
+            finished: None,
+            tables: BTreeMap::new(),
+        },
+    };
+    write_json(&path, &manifest)?;
+
+    for table in TABLES {
+        fs::create_dir_all(dir.join(table))?;
+        let counts = try_join_all(
+            (0..segments).map(|segment| scan_segment(dynamo, dir, table, segment, segments)),
+        )
+        .await?;
+        let items = counts.into_iter().sum();
+        info!(table, items, "exported table");
+        manifest.tables.insert(table.to_string(), items);
+    }
+    manifest.finished.get_or_insert_with(now);
+    write_json(&path, &manifest)?;
+    Ok(manifest)
+}
+
+/// Put all of `items` into `table`, retrying whatever DynamoDB doesn't get to.
+async fn put_all(dynamo: &Client, table: &str, items: Vec<Item>) -> Result<(), Error> {
+    let mut pending: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            WriteRequest::builder()
+                .put_request(PutRequest::builder().set_item(Some(item)).build())
+                .build()
+        })
+        .collect();
+    let mut backoff = Duration::from_millis(50);
+    while !pending.is_empty() {
+        let out = dynamo
+            .batch_write_item()
+            .request_items(table, pending)
+            .send()
+            .await?;
+        pending = out
+            .unprocessed_items()
+            .and_then(|u| u.get(table))
+            .cloned()
+            .unwrap_or_default();
+        if !pending.is_empty() {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(5));
+        }
+    }
+    Ok(())
+}
+
+/// Questions and change logs that belong to events that aren't there.
+#[derive(Debug, Default, Serialize)]
+pub(super) struct Orphans {
+    /// The ids of the questions.
+    questions: Vec<String>,
+    /// The events the change logs are for.
+    changes: Vec<String>,
+}
+
+impl Orphans {
+    pub(super) fn is_empty(&self) -> bool {
+        self.questions.is_empty() && self.changes.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(super) struct Report {
+    /// How many items were written to each table.
+    restored: BTreeMap<String, usize>,
+    /// Only with `--verify`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) orphans: Option<Orphans>,
+}
+
+/// The ids (`id`) of `refs` whose events (`eid`) aren't among `events`.
+fn orphaned<'a>(
+    events: &HashSet<&str>,
+    refs: impl IntoIterator<Item = &'a Item>,
+    id: &str,
+) -> Vec<String> {
+    let orphans: BTreeSet<_> = refs
+        .into_iter()
+        .filter(|item| {
+            item.get("eid")
+                .and_then(|eid| eid.as_s().ok())
+                .is_none_or(|eid| !events.contains(eid.as_str()))
+        })
+        .filter_map(|item| item.get(id).and_then(|id| id.as_s().ok()).cloned())
+        .collect();
+    orphans.into_iter().collect()
+}
+
+/// Every item in `table`, with only the attributes in `projection`.
+async fn scan_all(dynamo: &Client, table: &str, projection: &str) -> Result<Vec<Item>, Error> {
+    let mut all = Vec::new();
+    let mut start = None;
+    loop {
+        let page = dynamo
+            .scan()
+            .table_name(table)
+            .projection_expression(projection)
+            .set_exclusive_start_key(start)
+            .send()
+            .await?;
+        all.extend(page.items().unwrap_or_default().iter().cloned());
+        start = page.last_evaluated_key().cloned();
+        if start.is_none() {
+            return Ok(all);
+        }
+    }
+}
+
+async fn verify(dynamo: &Client) -> Result<Orphans, Error> {
+    let events = scan_all(dynamo, "events", "id").await?;
+    let events: HashSet<_> = events
+        .iter()
+        .filter_map(|e| e.get("id").and_then(|id| id.as_s().ok()))
+        .map(String::as_str)
+        .collect();
+    let questions = scan_all(dynamo, "questions", "id, eid").await?;
+    let changes = scan_all(dynamo, "changes", "eid").await?;
+    Ok(Orphans {
+        questions: orphaned(&events, &questions, "id"),
+        changes: orphaned(&events, &changes, "eid"),
+    })
+}
+
+/// Load the snapshot in `dir` into the tables, and check what's there after if `check`.
+pub(super) async fn restore(dynamo: &Client, dir: &Path, check: bool) -> Result<Report, Error> {
+    let manifest: Manifest = read_json(&dir.join("manifest.json"))?
+        .ok_or_else(|| format!("there's no snapshot in {}", dir.display()))?;
+    if manifest.finished.is_none() {
+        return Err(format!(
+            "the snapshot in {} isn't finished; run `snapshot` again to finish it",
+            dir.display()
+        )
+        .into());
+    }
+
+    let mut report = Report::default();
+    // events first, so nothing's ever there without its event
+    for table in TABLES.into_iter().rev() {
+        let mut n = 0;
+        for segment in 0..manifest.segments {
+            let file = File::open(segment_path(dir, table, segment, "jsonl"))?;
+            let mut batch = Vec::with_capacity(BATCH);
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let item = item_from_json(&serde_json::from_str(&line)?)
+                    .ok_or_else(|| format!("malformed item in {table}: {line}"))?;
+                batch.push(item);
+                if batch.len() == BATCH {
+                    n += batch.len();
+                    put_all(dynamo, table, std::mem::take(&mut batch)).await?;
+                }
+            }
+            if !batch.is_empty() {
+                n += batch.len();
+                put_all(dynamo, table, batch).await?;
+            }
+        }
+        info!(table, items = n, "restored table");
+        report.restored.insert(table.to_string(), n);
+    }
+
+    if check {
+        report.orphans = Some(verify(dynamo).await?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_smithy_http::{body::SdkBody, result::ConnectorError};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    #[test]
+    fn attributes() {
+        let item: Item = HashMap::from_iter([
+            (String::from("id"), AttributeValue::S(String::from("q"))),
+            (String::from("votes"), AttributeValue::N(String::from("3"))),
+            (String::from("hidden"), AttributeValue::Bool(false)),
+            (
+                String::from("log"),
+                AttributeValue::L(vec![AttributeValue::M(HashMap::from_iter([(
+                    String::from("tags"),
+                    AttributeValue::Ss(vec![String::from("a"), String::from("b")]),
+                )]))]),
+            ),
+        ]);
+        let json = item_to_json(&item);
+        assert_eq!(json["votes"], serde_json::json!({ "N": "3" }));
+        assert_eq!(item_from_json(&json), Some(item));
+        assert_eq!(
+            item_from_json(&serde_json::json!({ "id": { "X": 1 } })),
+            None
+        );
+    }
+
+    #[test]
+    fn orphans() {
+        let item = |id: &str, eid: Option<&str>| -> Item {
+            let mut item = HashMap::from_iter([(String::from("id"), AttributeValue::S(id.into()))]);
+            if let Some(eid) = eid {
+                item.insert(String::from("eid"), AttributeValue::S(eid.into()));
+            }
+            item
+        };
+        let events = HashSet::from_iter(["e1", "e2"]);
+        let questions = [
+            item("q1", Some("e1")),
+            item("q2", Some("gone")),
+            item("q3", Some("e2")),
+            item("q4", None),
+        ];
+        assert_eq!(orphaned(&events, &questions, "id"), ["q2", "q4"]);
+        // change logs have many entries per event, but each event is only listed once
+        let changes = [item("x", Some("gone")), item("y", Some("gone"))];
+        assert_eq!(orphaned(&events, &changes, "eid"), ["gone"]);
+    }
+
+    /// A table of two items that come back a page at a time, where call `fail` fails.
+    #[derive(Clone)]
+    struct Pages {
+        calls: Arc<AtomicUsize>,
+        fail: usize,
+    }
+
+    impl tower::Service<http::Request<SdkBody>> for Pages {
+        type Response = http::Response<SdkBody>;
+        type Error = ConnectorError;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<SdkBody>) -> Self::Future {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let second = req
+                .body()
+                .bytes()
+                .and_then(|b| serde_json::from_slice::<serde_json::Value>(b).ok())
+                .is_some_and(|b| b.get("ExclusiveStartKey").is_some());
+            let (status, body) = if call == self.fail {
+                (
+                    500,
+                    r#"{"__type":"com.amazonaws.dynamodb.v20120810#InternalServerError"}"#,
+                )
+            } else if second {
+                (200, r#"{"Items":[{"id":{"S":"b"}}]}"#)
+            } else {
+                (
+                    200,
+                    r#"{"Items":[{"id":{"S":"a"}}],"LastEvaluatedKey":{"id":{"S":"a"}}}"#,
+                )
+            };
+            let res = http::Response::builder()
+                .status(status)
+                .header("content-type", "application/x-amz-json-1.0")
+                .body(SdkBody::from(body))
+                .unwrap();
+            std::future::ready(Ok(res))
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes() {
+        let dir = std::env::temp_dir().join(format!("wewerewondering-{}", uuid::Uuid::new_v4()));
+        // the second page of the questions fails the first time around
+        let calls = Arc::new(AtomicUsize::new(0));
+        let dynamo = crate::golden::client(Pages {
+            calls: Arc::clone(&calls),
+            fail: 1,
+        });
+        assert!(snapshot(&dynamo, &dir, 1).await.is_err());
+        let manifest: Manifest = read_json(&dir.join("manifest.json")).unwrap().unwrap();
+        assert_eq!(manifest.finished, None);
+        let at: Checkpoint = read_json(&segment_path(&dir, "questions", 0, "checkpoint"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(at.items, 1);
+        assert!(!at.done);
+
+        // pretend the process died after writing a page but before checkpointing it
+        let mut out = OpenOptions::new()
+            .append(true)
+            .open(segment_path(&dir, "questions", 0, "jsonl"))
+            .unwrap();
+        out.write_all(b"{\"id\":{\"S\":\"b\"}}\n").unwrap();
+        drop(out);
+
+        let manifest = snapshot(&dynamo, &dir, 1).await.unwrap();
+        assert!(manifest.finished.is_some());
+        assert_eq!(manifest.tables["questions"], 2);
+        assert_eq!(manifest.tables["events"], 2);
+        let questions = fs::read_to_string(segment_path(&dir, "questions", 0, "jsonl")).unwrap();
+        assert_eq!(
+            questions.lines().collect::<Vec<_>>(),
+            [r#"{"id":{"S":"a"}}"#, r#"{"id":{"S":"b"}}"#]
+        );
+
+        // a finished snapshot doesn't scan anything again, but can't change shape either
+        let before = calls.load(Ordering::SeqCst);
+        assert!(snapshot(&dynamo, &dir, 1).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), before);
+        assert!(snapshot(&dynamo, &dir, 2).await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tables that keep whatever is written to them, and give all of it back when scanned.
+    #[derive(Clone, Default)]
+    struct Tables(Arc<std::sync::Mutex<HashMap<String, Vec<serde_json::Value>>>>);
+
+    impl tower::Service<http::Request<SdkBody>> for Tables {
+        type Response = http::Response<SdkBody>;
+        type Error = ConnectorError;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<SdkBody>) -> Self::Future {
+            let operation = req.headers()["x-amz-target"].to_str().unwrap().to_string();
+            let body: serde_json::Value =
+                serde_json::from_slice(req.body().bytes().unwrap()).unwrap();
+            let mut tables = self.0.lock().unwrap();
+            let res = if operation.ends_with(".BatchWriteItem") {
+                for (table, writes) in body["RequestItems"].as_object().unwrap() {
+                    let items = tables.entry(table.clone()).or_default();
+                    for write in writes.as_array().unwrap() {
+                        items.push(write["PutRequest"]["Item"].clone());
+                    }
+                }
+                serde_json::json!({})
+            } else {
+                let table = body["TableName"].as_str().unwrap();
+                serde_json::json!({ "Items": tables.get(table).cloned().unwrap_or_default() })
+            };
+            let res = http::Response::builder()
+                .status(200)
+                .header("content-type", "application/x-amz-json-1.0")
+                .body(SdkBody::from(res.to_string()))
+                .unwrap();
+            std::future::ready(Ok(res))
+        }
+    }
+
+    #[tokio::test]
+    async fn restores() {
+        let dir = std::env::temp_dir().join(format!("wewerewondering-{}", uuid::Uuid::new_v4()));
+        for table in TABLES {
+            fs::create_dir_all(dir.join(table)).unwrap();
+        }
+        let mut manifest = Manifest {
+            segments: 2,
+            started: 1,
+            finished: None,
+            tables: BTreeMap::new(),
+        };
+        write_json(&dir.join("manifest.json"), &manifest).unwrap();
+        let tables = Tables::default();
+        let dynamo = crate::golden::client(tables.clone());
+        assert!(restore(&dynamo, &dir, true).await.is_err());
+
+        manifest.finished = Some(2);
+        write_json(&dir.join("manifest.json"), &manifest).unwrap();
+        let write = |table: &str, segment: u32, items: &[&str]| {
+            let lines: String = items.iter().map(|i| format!("{i}\n")).collect();
+            fs::write(segment_path(&dir, table, segment, "jsonl"), lines).unwrap();
+        };
+        write("events", 0, &[r#"{"id":{"S":"e1"}}"#]);
+        write("events", 1, &[]);
+        // more than fits in one batch
+        let questions: Vec<_> = (0..30)
+            .map(|i| format!(r#"{{"id":{{"S":"q{i:02}"}},"eid":{{"S":"e1"}}}}"#))
+            .collect();
+        let questions: Vec<_> = questions.iter().map(String::as_str).collect();
+        write("questions", 0, &questions);
+        write(
+            "questions",
+            1,
+            &[r#"{"id":{"S":"lost"},"eid":{"S":"gone"}}"#],
+        );
+        write(
+            "changes",
+            0,
+            &[r#"{"eid":{"S":"e1"},"seq":{"N":"1"},"kind":{"S":"question_asked"}}"#],
+        );
+        write("changes", 1, &[]);
+
+        let report = restore(&dynamo, &dir, true).await.unwrap();
+        assert_eq!(report.restored["events"], 1);
+        assert_eq!(report.restored["questions"], 31);
+        assert_eq!(report.restored["changes"], 1);
+        let orphans = report.orphans.unwrap();
+        assert_eq!(orphans.questions, ["lost"]);
+        assert!(orphans.changes.is_empty());
+        assert_eq!(tables.0.lock().unwrap()["questions"].len(), 31);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
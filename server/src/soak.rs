@@ -0,0 +1,400 @@
+//! Long-running load against a single instance, to catch leaks.
+//!
+//! The soak command sends a steady mix of API traffic (mostly attendees polling, with some asking,
+//! voting, and hosts toggling) to the instance at `--target` for `--minutes`. Every
+//! `--sample-secs`, it samples the instance's resident memory and open file descriptors from
+//! `/proc`, and its hot cache size from `/api/admin/metrics` (if it has an admin token). After a
+//! warm-up, it fits a line through each series, and fails if memory or descriptors trend upwards
+//! by more than the tolerance over the run.
+//!
+//! Every question asked makes the stored state a little bigger, so with the in-memory backend
+//! memory grows along with the data. Either keep `--writes` low there, or soak an instance with a
+//! backend that stores data elsewhere.
+
+use hyper::{client::HttpConnector, Body, Client, Method, Request, StatusCode};
+use rand::{distributions::WeightedIndex, prelude::*};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(clap::Args, Debug, Clone)]
+pub(super) struct Params {
+    /// The instance to send traffic to.
+    #[arg(long, default_value = "http://127.0.0.1:3000")]
+    target: hyper::Uri,
+    /// The process id of the instance, to sample its memory and descriptors.
+    #[arg(long)]
+    pid: u32,
+    /// How long to keep it up, in minutes.
+    #[arg(long, default_value_t = 120)]
+    minutes: u64,
+    /// How many requests to keep in flight.
+    #[arg(long, default_value_t = 16)]
+    concurrency: usize,
+    /// How many events to spread the traffic over.
+    #[arg(long, default_value_t = 20)]
+    events: usize,
+    /// Stop asking questions in an event once it has this many.
+    #[arg(long, default_value_t = 200)]
+    questions: usize,
+    /// The fraction of requests that change something.
+    #[arg(long, default_value_t = 0.05)]
+    writes: f64,
+    /// How often to sample the instance, in seconds.
+    #[arg(long, default_value_t = 30)]
+    sample_secs: u64,
+    /// The fraction of the run that's warm-up, and doesn't count towards trends.
+    #[arg(long, default_value_t = 0.2)]
+    warmup: f64,
+    /// How much memory may grow over the run (after warm-up), relative to its average.
+    #[arg(long, default_value_t = 0.1)]
+    tolerance: f64,
+    /// How many file descriptors may be opened (and kept) over the run (after warm-up).
+    #[arg(long, default_value_t = 32)]
+    fd_tolerance: u64,
+    /// The instance's `ADMIN_TOKEN`, to sample its hot cache. Defaults to this process's.
+    #[arg(long)]
+    admin_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+struct Sample {
+    secs: u64,
+    rss_kb: u64,
+    fds: u64,
+    sockets: u64,
+    hot_events: Option<u64>,
+    hot_questions: Option<u64>,
+}
+
+/// Resident memory, open file descriptors, and open sockets of `pid`.
+fn proc_stats(pid: u32) -> std::io::Result<(u64, u64, u64)> {
+    let dir = PathBuf::from(format!("/proc/{pid}"));
+    let status = fs::read_to_string(dir.join("status"))?;
+    let rss_kb = status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+        .unwrap_or(0);
+    let (mut fds, mut sockets) = (0, 0);
+    for fd in fs::read_dir(dir.join("fd"))? {
+        fds += 1;
+        // the descriptor may be closed by the time we look at it
+        if let Ok(link) = fs::read_link(fd?.path()) {
+            if link.to_string_lossy().starts_with("socket:") {
+                sockets += 1;
+            }
+        }
+    }
+    Ok((rss_kb, fds, sockets))
+}
+
+/// The slope of the least-squares line through `points`, if there are enough of them.
+fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    if points.len() < 3 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let (mx, my) = points
+        .iter()
+        .fold((0.0, 0.0), |(x, y), &(px, py)| (x + px / n, y + py / n));
+    let (cov, var) = points.iter().fold((0.0, 0.0), |(c, v), &(x, y)| {
+        (c + (x - mx) * (y - my), v + (x - mx) * (x - mx))
+    });
+    (var > 0.0).then(|| cov / var)
+}
+
+/// How much `value` grew over the samples after warm-up, going by its trend, and its average.
+fn growth(samples: &[Sample], warmup: f64, value: impl Fn(&Sample) -> u64) -> Option<(f64, f64)> {
+    let skip = (samples.len() as f64 * warmup.clamp(0.0, 1.0)) as usize;
+    let points: Vec<_> = samples[skip..]
+        .iter()
+        .map(|s| (s.secs as f64, value(s) as f64))
+        .collect();
+    let slope = slope(&points)?;
+    let span = points.last()?.0 - points.first()?.0;
+    let mean = points.iter().map(|p| p.1).sum::<f64>() / points.len() as f64;
+    Some((slope * span, mean))
+}
+
+struct Traffic {
+    client: Client<HttpConnector>,
+    target: hyper::Uri,
+    events: Vec<(Uuid, String)>,
+    questions: Mutex<Vec<Vec<Uuid>>>,
+    statuses: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Traffic {
+    async fn send(&self, method: Method, path: &str, body: impl Into<Body>) -> Option<Vec<u8>> {
+        let uri = format!("{}{}", self.target.to_string().trim_end_matches('/'), path);
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .expect("requests are valid");
+        let (status, body) = match self.client.request(req).await {
+            Ok(res) => {
+                let status = res.status();
+                let body = hyper::body::to_bytes(res.into_body()).await.ok();
+                (
+                    status.as_u16().to_string(),
+                    body.filter(|_| status == StatusCode::OK),
+                )
+            }
+            Err(e) => {
+                debug!(error = %e, "soak request failed");
+                (String::from("error"), None)
+            }
+        };
+        *self.statuses.lock().unwrap().entry(status).or_default() += 1;
+        body.map(|b| b.to_vec())
+    }
+
+    /// Send one request, picked from the mix.
+    async fn one(&self, writes: f64, max_questions: usize) {
+        let (i, (eid, secret), qid) = {
+            let mut rng = thread_rng();
+            let i = rng.gen_range(0..self.events.len());
+            let qid = self.questions.lock().unwrap()[i].choose(&mut rng).copied();
+            (i, &self.events[i], qid)
+        };
+        let write = thread_rng().gen_bool(writes.clamp(0.0, 1.0));
+        if write {
+            let asked = self.questions.lock().unwrap()[i].len();
+            let pick = WeightedIndex::new([
+                // ask while there's room, otherwise vote and toggle
+                if asked < max_questions { 3 } else { 0 },
+                6,
+                1,
+            ])
+            .expect("weights are valid")
+            .sample(&mut thread_rng());
+            match (pick, qid) {
+                (0, _) | (_, None) if asked < max_questions => {
+                    let body = serde_json::json!({
+                        "body": format!("soak question {}", Uuid::new_v4()),
+                        "asker": null,
+                    });
+                    let path = format!("/api/event/{eid}");
+                    if let Some(res) = self.send(Method::POST, &path, body.to_string()).await {
+                        let qid = serde_json::from_slice::<serde_json::Value>(&res)
+                            .ok()
+                            .and_then(|v| v["id"].as_str().and_then(|id| id.parse().ok()));
+                        if let Some(qid) = qid {
+                            self.questions.lock().unwrap()[i].push(qid);
+                        }
+                    }
+                }
+                (1, Some(qid)) => {
+                    let dir = if thread_rng().gen_bool(0.9) {
+                        "up"
+                    } else {
+                        "down"
+                    };
+                    self.send(Method::POST, &format!("/api/vote/{qid}/{dir}"), "")
+                        .await;
+                }
+                (_, Some(qid)) => {
+                    let on = if thread_rng().gen_bool(0.5) {
+                        "on"
+                    } else {
+                        "off"
+                    };
+                    let path = format!("/api/event/{eid}/questions/{secret}/{qid}/toggle/answered");
+                    self.send(Method::POST, &path, on).await;
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        let pick = WeightedIndex::new([50, 15, 10, 10, 5])
+            .expect("weights are valid")
+            .sample(&mut thread_rng());
+        let path = match pick {
+            0 => format!("/api/event/{eid}/questions"),
+            1 => {
+                let qids: Vec<_> = {
+                    let questions = self.questions.lock().unwrap();
+                    questions[i]
+                        .choose_multiple(&mut thread_rng(), 25)
+                        .map(|q| q.to_string())
+                        .collect()
+                };
+                if qids.is_empty() {
+                    return;
+                }
+                format!("/api/questions/{}", qids.join(","))
+            }
+            2 => format!("/api/event/{eid}/questions/{secret}"),
+            3 => format!("/api/event/{eid}/changes"),
+            _ => format!("/api/event/{eid}"),
+        };
+        self.send(Method::GET, &path, Body::empty()).await;
+    }
+
+    /// The instance's hot cache size, if we're allowed to see it.
+    async fn hot(&self, token: Option<&str>) -> Option<(u64, u64)> {
+        let token = token?;
+        let uri = format!(
+            "{}/api/admin/metrics",
+            self.target.to_string().trim_end_matches('/')
+        );
+        let req = Request::get(uri)
+            .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .ok()?;
+        let res = self.client.request(req).await.ok()?;
+        let bytes = hyper::body::to_bytes(res.into_body()).await.ok()?;
+        let metrics: serde_json::Value = serde_json::from_slice(&bytes).ok()?;
+        Some((
+            metrics["hot"]["events"].as_u64()?,
+            metrics["hot"]["questions"].as_u64()?,
+        ))
+    }
+}
+
+/// Soak the instance described by `params`, and report how it held up.
+pub(super) async fn run(params: &Params) -> serde_json::Value {
+    let client = Client::new();
+    let mut traffic = Traffic {
+        client,
+        target: params.target.clone(),
+        events: Vec::new(),
+        questions: Mutex::new(Vec::new()),
+        statuses: Mutex::new(BTreeMap::new()),
+    };
+    for _ in 0..params.events.max(1) {
+        let Some(res) = traffic
+            .send(Method::POST, "/api/event", Body::empty())
+            .await
+        else {
+            return serde_json::json!({ "ok": false, "error": "failed to create events" });
+        };
+        let e: serde_json::Value = serde_json::from_slice(&res).unwrap_or_default();
+        let (Some(eid), Some(secret)) = (
+            e["id"].as_str().and_then(|id| id.parse().ok()),
+            e["secret"].as_str(),
+        ) else {
+            return serde_json::json!({ "ok": false, "error": "unexpected event response" });
+        };
+        traffic.events.push((eid, secret.to_string()));
+        traffic.questions.lock().unwrap().push(Vec::new());
+    }
+    info!(events = traffic.events.len(), "created soak events");
+
+    let traffic = Arc::new(traffic);
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(params.minutes * 60);
+    let workers: Vec<_> = (0..params.concurrency.max(1))
+        .map(|_| {
+            let traffic = Arc::clone(&traffic);
+            let (writes, questions) = (params.writes, params.questions);
+            tokio::spawn(async move {
+                while Instant::now() < deadline {
+                    traffic.one(writes, questions).await;
+                }
+            })
+        })
+        .collect();
+
+    let token = params
+        .admin_token
+        .clone()
+        .or_else(|| super::config::config().admin_token.clone());
+    let mut samples = Vec::new();
+    let mut error = None;
+    loop {
+        let (rss_kb, fds, sockets) = match proc_stats(params.pid) {
+            Ok(s) => s,
+            Err(e) => {
+                error = Some(format!("failed to sample process {}: {e}", params.pid));
+                break;
+            }
+        };
+        let hot = traffic.hot(token.as_deref()).await;
+        let sample = Sample {
+            secs: start.elapsed().as_secs(),
+            rss_kb,
+            fds,
+            sockets,
+            hot_events: hot.map(|h| h.0),
+            hot_questions: hot.map(|h| h.1),
+        };
+        info!(?sample, "soak sample");
+        samples.push(sample);
+        if Instant::now() >= deadline {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(params.sample_secs.max(1))).await;
+    }
+    for w in workers {
+        w.abort();
+    }
+
+    let rss = growth(&samples, params.warmup, |s| s.rss_kb);
+    let fds = growth(&samples, params.warmup, |s| s.fds);
+    let rss_ok = rss.is_none_or(|(g, mean)| g <= mean * params.tolerance);
+    let fds_ok = fds.is_none_or(|(g, _)| g <= params.fd_tolerance as f64);
+    let statuses = traffic.statuses.lock().unwrap().clone();
+    serde_json::json!({
+        "ok": error.is_none() && rss_ok && fds_ok,
+        "error": error,
+        "requests": statuses,
+        "rss_kb": { "growth": rss.map(|r| r.0.round()), "mean": rss.map(|r| r.1.round()), "ok": rss_ok },
+        "fds": { "growth": fds.map(|f| f.0.round()), "mean": fds.map(|f| f.1.round()), "ok": fds_ok },
+        "samples": samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(rss: impl Fn(u64) -> u64) -> Vec<Sample> {
+        (0..20)
+            .map(|i| Sample {
+                secs: i * 30,
+                rss_kb: rss(i),
+                fds: 40 + i % 3,
+                sockets: 16,
+                hot_events: None,
+                hot_questions: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trends() {
+        // noisy but flat, after growing during warm-up
+        let flat = samples(|i| {
+            if i < 4 {
+                10_000 + i * 5_000
+            } else {
+                30_000 + (i * 7919) % 500
+            }
+        });
+        let (g, mean) = growth(&flat, 0.2, |s| s.rss_kb).unwrap();
+        assert!(g.abs() < mean * 0.1, "{g} {mean}");
+        let (g, _) = growth(&flat, 0.2, |s| s.fds).unwrap();
+        assert!(g.abs() < 2.0, "{g}");
+
+        // a steady leak
+        let leak = samples(|i| 30_000 + i * 1_000);
+        let (g, mean) = growth(&leak, 0.2, |s| s.rss_kb).unwrap();
+        assert!(g > mean * 0.1, "{g} {mean}");
+        assert!((g - 15_000.0).abs() < 1.0, "{g}");
+
+        assert_eq!(growth(&leak[..2], 0.0, |s| s.rss_kb), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Pushing an event's changes to clients as server-sent events, for where WebSockets don't work.
+//!
+//! Some proxies break WebSockets but are fine with a response that just takes a long time, so
+//! `GET /api/event/:eid/stream` sends the same messages as [`super::ws`] as an `EventSource`
+//! stream instead: first `{"seq": <n>}`, then each change from [`super::bus`] in the change feed's
+//! JSON, and `{"resync": true}` whenever changes couldn't be passed on, plus who's moderating
+//! which question and which are [surging](super::surge) for hosts who add `?secret=<secret>`. A
+//! comment is sent every `PING` seconds so that nothing in between decides the stream is idle.
+//!
+//! Like sockets, streams need a connection that stays open. As a Lambda, the whole response would
+//! have to be put together before any of it is sent, so streams are refused there with a 501,
+//! which also tells `EventSource` not to reconnect, and clients should keep polling. Streams of
+//! frozen events aren't opened either.
+//!
+//! Changes are only written out as fast as each client takes them, so a slow client doesn't get a
+//! queue of its own, it just falls behind in the event's [channel](super::bus). What happens when
+//! it falls too far behind is up to `STREAM_SLOW`: by default it skips what it missed and is told
+//! to resync (`sse.lagged`), but with `disconnect` its stream is ended instead (`sse.dropped`),
+//! and the client can reconnect or go back to polling. Deployments can also cap how many streams
+//! each event has open on an instance with `STREAM_MAX_PER_EVENT`, past which streams are refused
+//! with a 503 (`sse.refused`) so that those clients poll. How many streams are open right now is
+//! in the admin metrics, along with `sse.opened` and `sse.closed`.
+
+use super::{bus::Listen, Backend};
+use axum::{
+    extract::{Path, Query, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
+use futures_util::stream::{self, StreamExt};
+use http::StatusCode;
+use lambda_http::request::RequestContext;
+use std::{
+    collections::BTreeMap, convert::Infallible, fmt, str::FromStr, sync::Mutex, time::Duration,
+};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How often open streams get a comment, in seconds.
+const PING: u64 = 30;
+
+/// What to do with a stream that's fallen too far behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(super) enum Slow {
+    /// Skip what it missed, and tell the client to resync.
+    #[default]
+    Resync,
+    /// End the stream.
+    Disconnect,
+}
+
+impl FromStr for Slow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "resync" => Ok(Slow::Resync),
+            "disconnect" => Ok(Slow::Disconnect),
+            s => Err(format!("`{s}` isn't resync or disconnect")),
+        }
+    }
+}
+
+impl fmt::Display for Slow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Slow::Resync => "resync",
+            Slow::Disconnect => "disconnect",
+        })
+    }
+}
+
+/// How many streams each event has open.
+static OPEN: Mutex<BTreeMap<Uuid, usize>> = Mutex::new(BTreeMap::new());
+
+/// An open stream of an event, which stops counting as open when it's dropped.
+#[derive(Debug)]
+struct Slot(Uuid);
+
+impl Slot {
+    /// Open another stream of `eid`, unless it already has `max` open.
+    fn claim(eid: &Uuid, max: Option<usize>) -> Option<Self> {
+        let mut open = OPEN.lock().unwrap();
+        let n = open.entry(*eid).or_default();
+        if max.is_some_and(|max| *n >= max) {
+            if *n == 0 {
+                open.remove(eid);
+            }
+            return None;
+        }
+        *n += 1;
+        Some(Slot(*eid))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut open = OPEN.lock().unwrap();
+        if let Some(n) = open.get_mut(&self.0) {
+            *n -= 1;
+            if *n == 0 {
+                open.remove(&self.0);
+            }
+        }
+        super::metrics::incr("sse.closed");
+    }
+}
+
+/// How many streams are open, and of how many events.
+pub(super) fn open() -> (usize, usize) {
+    let open = OPEN.lock().unwrap();
+    (open.values().sum(), open.len())
+}
+
+pub(super) async fn stream(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    Query(listen): Query<Listen>,
+    lambda: Option<Extension<RequestContext>>,
+) -> Response {
+    if lambda.is_some() {
+        return StatusCode::NOT_IMPLEMENTED.into_response();
+    }
+
+    // listen before looking, so that nothing falls in between
+    let changes = super::bus::subscribe(&eid);
+    let (meta, host) = match super::bus::admit(&dynamo, &eid, &listen).await {
+        Ok(admitted) => admitted,
+        Err(status) => return status.into_response(),
+    };
+    let config = super::config::config();
+    let Some(slot) = Slot::claim(&eid, config.stream_max_per_event) else {
+        warn!(%eid, "refused event stream past the limit");
+        super::metrics::incr("sse.refused");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    super::metrics::incr("sse.opened");
+
+    let slow = config.stream_slow;
+    let hello = Event::default().data(serde_json::json!({ "seq": meta.version }).to_string());
+    // the slot goes along with the stream, so it's given back whenever the stream goes away
+    let notices = stream::unfold((changes, slot), move |(mut changes, slot)| async move {
+        let notice = loop {
+            match changes.recv().await {
+                Ok(notice) if notice.hosts_only && !host => continue,
+                Ok(notice) => break Event::default().data(&*notice.text),
+                Err(RecvError::Lagged(n)) if slow == Slow::Disconnect => {
+                    debug!(%eid, n, "dropping event stream that fell behind");
+                    super::metrics::incr("sse.dropped");
+                    return None;
+                }
+                Err(RecvError::Lagged(n)) => {
+                    debug!(%eid, n, "event stream fell behind");
+                    super::metrics::incr("sse.lagged");
+                    break Event::default().data(super::bus::RESYNC);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        };
+        Some((notice, (changes, slot)))
+    });
+    Sse::new(
+        stream::once(async { hello })
+            .chain(notices)
+            .map(Ok::<_, Infallible>),
+    )
+    .keep_alive(KeepAlive::new().interval(Duration::from_secs(PING)))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::Json;
+    use hyper::body::HttpBody;
+
+    /// The data of the next event in `body`.
+    async fn next(body: &mut axum::body::BoxBody) -> serde_json::Value {
+        let chunk = body.data().await.unwrap().unwrap();
+        let chunk = std::str::from_utf8(&chunk).unwrap();
+        let data = chunk.strip_prefix("data:").expect(chunk);
+        serde_json::from_str(data.trim()).unwrap()
+    }
+
+    #[test]
+    fn slots() {
+        let eid = Uuid::new_v4();
+        let a = Slot::claim(&eid, Some(2)).unwrap();
+        let b = Slot::claim(&eid, Some(2)).unwrap();
+        assert!(Slot::claim(&eid, Some(2)).is_none());
+        assert_eq!(OPEN.lock().unwrap()[&eid], 2);
+        drop(a);
+        let c = Slot::claim(&eid, Some(2)).unwrap();
+        drop((b, c));
+        assert!(!OPEN.lock().unwrap().contains_key(&eid));
+        assert!(Slot::claim(&eid, Some(0)).is_none());
+        assert!(!OPEN.lock().unwrap().contains_key(&eid));
+    }
+
+    #[test]
+    fn slow() {
+        for slow in [Slow::Resync, Slow::Disconnect] {
+            assert_eq!(slow.to_string().parse(), Ok(slow));
+        }
+        assert!("wait".parse::<Slow>().is_err());
+    }
+
+    #[tokio::test]
+    async fn streams() {
+        let backend = Backend::local().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+
+        let res = stream(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            None,
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[http::header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut body = res.into_body();
+        assert_eq!(next(&mut body).await["seq"], 1);
+        assert_eq!(OPEN.lock().unwrap()[&eid], 1);
+
+        // guests aren't told what only hosts are
+        crate::bus::tell_hosts(&eid, serde_json::json!({ "kind": "lease" }));
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "is this thing on?".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let notice = next(&mut body).await;
+        assert_eq!(notice["seq"], 2);
+        assert_eq!(notice["kind"], "question_asked");
+        assert_eq!(notice["qid"], q["id"]);
+        // once the client goes, so does the stream
+        drop(body);
+        assert!(!OPEN.lock().unwrap().contains_key(&eid));
+
+        backend.delete(&eid).await;
+        let gone = stream(
+            Path(eid),
+            State(backend.clone()),
+            Query(Default::default()),
+            None,
+        )
+        .await;
+        assert_eq!(gone.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,539 @@
+//! A summary of what an event's questions are about, for hosts.
+//!
+//! With hundreds of questions in, it's hard for a host to tell what's on people's minds. With a
+//! summarizer configured, `GET /api/event/:eid/summary/:secret` gives hosts a few sentences on the
+//! themes that come up most, like "most questions are about the reorg and the bonus freeze", made
+//! from the event's visible questions (the most voted for first, if there are more than
+//! `MAX_QUESTIONS`). It's off unless `SUMMARIZER` says which one to use:
+//!
+//!  - `bedrock`, with the `bedrock` feature, uses the Bedrock Converse API in the region and with
+//!    the credentials the rest of the AWS configuration uses.
+//!  - `openai`, with the `openai` feature, uses OpenAI's chat completions with `OPENAI_API_KEY`.
+//!
+//! `SUMMARIZER_MODEL` picks the model, if the default isn't the one. Either way, summaries are
+//! slow and cost money, so each instance keeps the last one it made for each event, and only makes
+//! another when the event has changed (which is to say, its version has moved on; see
+//! [`super::changes`]). Summaries made are counted as `summary.made` in the
+//! [metrics](super::metrics), ones that came from the cache as `summary.cached`, and ones that
+//! couldn't be made as `summary.failed`.
+
+use super::Backend;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::StatusCode;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most questions that go into a summary.
+const MAX_QUESTIONS: usize = 300;
+
+/// The most characters of each question that go into a summary.
+const MAX_CHARS: usize = 500;
+
+/// How many events each instance keeps a summary for.
+const MAX_CACHED: usize = 1000;
+
+/// What the summarizer is asked to do with the questions.
+#[cfg_attr(not(any(feature = "bedrock", feature = "openai")), allow(dead_code))]
+const INSTRUCTIONS: &str = "The following are questions attendees have submitted for a Q&A \
+session, one per line, the most voted for first. In two or three sentences, tell the host which \
+themes come up most, like \"most questions are about the reorg and the bonus freeze\". Don't \
+answer the questions, don't quote them at length, and don't mention anyone by name.";
+
+/// Something that can summarize questions.
+pub(super) trait Summarizer: Send + Sync {
+    /// A few sentences about what `questions` are mostly about.
+    fn summarize<'a>(
+        &'a self,
+        questions: &'a [String],
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>>;
+}
+
+/// Which summarizer to use, as configured by `SUMMARIZER`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) enum Provider {
+    #[default]
+    Off,
+    #[cfg(feature = "bedrock")]
+    Bedrock,
+    #[cfg(feature = "openai")]
+    OpenAi,
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Provider::Off),
+            #[cfg(feature = "bedrock")]
+            "bedrock" => Ok(Provider::Bedrock),
+            #[cfg(feature = "openai")]
+            "openai" => Ok(Provider::OpenAi),
+            #[cfg(not(feature = "bedrock"))]
+            "bedrock" => Err(String::from("built without the `bedrock` feature")),
+            #[cfg(not(feature = "openai"))]
+            "openai" => Err(String::from("built without the `openai` feature")),
+            _ => Err(format!("`{s}` isn't off, bedrock, or openai")),
+        }
+    }
+}
+
+/// The questions as the summarizer is given them.
+#[cfg_attr(not(any(feature = "bedrock", feature = "openai")), allow(dead_code))]
+fn prompt(questions: &[String]) -> String {
+    questions
+        .iter()
+        .map(|q| q.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Send `req`, and give back the JSON it's answered with.
+#[cfg(any(feature = "bedrock", feature = "openai"))]
+async fn send(req: http::Request<hyper::Body>) -> Result<serde_json::Value, String> {
+    let client =
+        hyper::Client::builder().build::<_, hyper::Body>(aws_smithy_client::conns::https());
+    let res = client.request(req).await.map_err(|e| e.to_string())?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("{status}: {}", String::from_utf8_lossy(&body)));
+    }
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "bedrock")]
+mod bedrock {
+    use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningParams};
+    use aws_types::credentials::{ProvideCredentials, SharedCredentialsProvider};
+    use http::{header, Request};
+    use std::{future::Future, pin::Pin, time::SystemTime};
+
+    /// Summaries from a model on Amazon Bedrock.
+    pub(in super::super) struct Bedrock {
+        region: String,
+        model: String,
+        credentials: SharedCredentialsProvider,
+    }
+
+    impl Bedrock {
+        pub(super) async fn from_env(model: Option<&str>) -> Self {
+            let aws = aws_config::load_from_env().await;
+            Bedrock {
+                region: aws
+                    .region()
+                    .map_or_else(|| String::from("us-east-1"), |r| r.to_string()),
+                model: model.unwrap_or("amazon.nova-lite-v1:0").to_string(),
+                credentials: aws
+                    .credentials_provider()
+                    .expect("AWS credentials are configured")
+                    .clone(),
+            }
+        }
+
+        async fn ask(&self, questions: &[String]) -> Result<String, String> {
+            let body = serde_json::json!({
+                "system": [{ "text": super::INSTRUCTIONS }],
+                "messages": [{
+                    "role": "user",
+                    "content": [{ "text": super::prompt(questions) }],
+                }],
+                "inferenceConfig": { "maxTokens": 400 },
+            })
+            .to_string();
+            // model ids have colons in them, which have to be escaped in the path
+            let url = format!(
+                "https://bedrock-runtime.{}.amazonaws.com/model/{}/converse",
+                self.region,
+                self.model.replace(':', "%3A")
+            );
+            let mut req = Request::post(url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(hyper::Body::from(body.clone()))
+                .map_err(|e| e.to_string())?;
+
+            let credentials = self
+                .credentials
+                .provide_credentials()
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut params = SigningParams::builder()
+                .access_key(credentials.access_key_id())
+                .secret_key(credentials.secret_access_key())
+                .region(&self.region)
+                .service_name("bedrock")
+                .time(SystemTime::now())
+                .settings(Default::default());
+            params.set_security_token(credentials.session_token());
+            let params = params.build().expect("all required parameters are set");
+            let signable = SignableRequest::new(
+                req.method(),
+                req.uri(),
+                req.headers(),
+                SignableBody::Bytes(body.as_bytes()),
+            );
+            let (signing, _) = sign(signable, &params)
+                .map_err(|e| e.to_string())?
+                .into_parts();
+            signing.apply_to_request(&mut req);
+
+            let res = super::send(req).await?;
+            text(&res).ok_or_else(|| format!("unexpected response: {res}"))
+        }
+    }
+
+    /// The text of a Converse response.
+    pub(super) fn text(res: &serde_json::Value) -> Option<String> {
+        res.pointer("/output/message/content/0/text")?
+            .as_str()
+            .map(|t| t.trim().to_string())
+    }
+
+    impl super::Summarizer for Bedrock {
+        fn summarize<'a>(
+            &'a self,
+            questions: &'a [String],
+        ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>> {
+            Box::pin(self.ask(questions))
+        }
+    }
+}
+
+#[cfg(feature = "openai")]
+mod openai {
+    use http::{header, Request};
+    use std::{future::Future, pin::Pin};
+
+    /// Summaries from a model on OpenAI.
+    pub(in super::super) struct OpenAi {
+        pub(super) key: String,
+        pub(super) model: String,
+    }
+
+    impl OpenAi {
+        async fn ask(&self, questions: &[String]) -> Result<String, String> {
+            let body = serde_json::json!({
+                "model": self.model,
+                "messages": [
+                    { "role": "system", "content": super::INSTRUCTIONS },
+                    { "role": "user", "content": super::prompt(questions) },
+                ],
+                "max_tokens": 400,
+            });
+            let req = Request::post("https://api.openai.com/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("Bearer {}", self.key))
+                .body(hyper::Body::from(body.to_string()))
+                .map_err(|e| e.to_string())?;
+            let res = super::send(req).await?;
+            text(&res).ok_or_else(|| format!("unexpected response: {res}"))
+        }
+    }
+
+    /// The text of a chat completion.
+    pub(super) fn text(res: &serde_json::Value) -> Option<String> {
+        res.pointer("/choices/0/message/content")?
+            .as_str()
+            .map(|t| t.trim().to_string())
+    }
+
+    impl super::Summarizer for OpenAi {
+        fn summarize<'a>(
+            &'a self,
+            questions: &'a [String],
+        ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>> {
+            Box::pin(self.ask(questions))
+        }
+    }
+}
+
+/// The configured summarizer, if there is one.
+async fn summarizer() -> Option<&'static dyn Summarizer> {
+    // tokio's cell can't be made in a const without parking_lot, but it can be opened async
+    static SUMMARIZER: OnceLock<tokio::sync::OnceCell<Option<Box<dyn Summarizer>>>> =
+        OnceLock::new();
+    SUMMARIZER
+        .get_or_init(tokio::sync::OnceCell::new)
+        .get_or_init(|| async {
+            let config = super::config::config();
+            #[allow(unused_variables)]
+            let model = config.summarizer_model.as_deref();
+            match config.summarizer {
+                Provider::Off => None,
+                #[cfg(feature = "bedrock")]
+                Provider::Bedrock => {
+                    Some(Box::new(bedrock::Bedrock::from_env(model).await) as Box<dyn Summarizer>)
+                }
+                #[cfg(feature = "openai")]
+                Provider::OpenAi => {
+                    let Some(key) = config.openai_api_key.clone() else {
+                        error!("SUMMARIZER is openai, but OPENAI_API_KEY isn't set");
+                        return None;
+                    };
+                    Some(Box::new(openai::OpenAi {
+                        key,
+                        model: model.unwrap_or("gpt-4o-mini").to_string(),
+                    }) as Box<dyn Summarizer>)
+                }
+            }
+        })
+        .await
+        .as_deref()
+}
+
+/// The last summary made of each event, and the version it was made at.
+fn cache() -> &'static Mutex<HashMap<Uuid, (u64, serde_json::Value)>> {
+    static CACHE: OnceLock<Mutex<HashMap<Uuid, (u64, serde_json::Value)>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// The texts of `eid`'s visible questions, the most voted for first.
+async fn texts(dynamo: &Backend, eid: &Uuid) -> Result<Vec<String>, aws_sdk_dynamodb::Error> {
+    let list = dynamo.list(eid, false).await?;
+    let mut listed: Vec<_> = list
+        .items()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|doc| super::list::parse(eid, doc))
+        .filter(|(_, q)| !q.hidden)
+        .collect();
+    listed.sort_by_key(|(_, q)| std::cmp::Reverse(q.votes));
+    listed.truncate(MAX_QUESTIONS);
+    if listed.is_empty() {
+        return Ok(Vec::new());
+    }
+    let qids: Vec<_> = listed.iter().map(|(qid, _)| *qid).collect();
+    let found = dynamo.questions(&qids).await?;
+    let mut texts: HashMap<_, _> = found
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .map(|qs| {
+            qs.iter()
+                .filter_map(|q| {
+                    let qid = q.get("id")?.as_s().ok()?;
+                    let text = q.get("text")?.as_s().ok()?;
+                    Some((Uuid::parse_str(qid).ok()?, text.clone()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(qids
+        .iter()
+        .filter_map(|qid| texts.remove(qid))
+        .map(|text| text.chars().take(MAX_CHARS).collect())
+        .collect())
+}
+
+/// The summary of `eid` at `version`, made by `summarizer` if there isn't one already.
+async fn summarize(
+    dynamo: &Backend,
+    eid: &Uuid,
+    version: u64,
+    summarizer: &dyn Summarizer,
+) -> Result<serde_json::Value, StatusCode> {
+    if let Some((at, summary)) = cache().lock().unwrap().get(eid) {
+        if *at == version {
+            super::metrics::incr("summary.cached");
+            return Ok(summary.clone());
+        }
+    }
+
+    let texts = texts(dynamo, eid).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for questions to summarize failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let summary = if texts.is_empty() {
+        // no need to ask anyone about that
+        None
+    } else {
+        match summarizer.summarize(&texts).await {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                error!(%eid, error = %e, "failed to summarize questions");
+                super::metrics::incr("summary.failed");
+                return Err(StatusCode::BAD_GATEWAY);
+            }
+        }
+    };
+    debug!(%eid, version, n = texts.len(), "summarized questions");
+    super::metrics::incr("summary.made");
+    let summary = serde_json::json!({
+        "summary": summary,
+        "questions": texts.len(),
+        "version": version,
+    });
+
+    let mut cache = cache().lock().unwrap();
+    if cache.len() >= MAX_CACHED && !cache.contains_key(eid) {
+        // events that are summarized at all are few, so starting over now and then is fine
+        cache.clear();
+    }
+    cache.insert(*eid, (version, summary.clone()));
+    Ok(summary)
+}
+
+pub(super) async fn summary(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let Some(summarizer) = summarizer().await else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let meta = super::check_secret(&dynamo, &eid, &secret).await?;
+    summarize(&dynamo, &eid, meta.version, summarizer)
+        .await
+        .map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A summarizer that counts the questions it's given, and how often it's asked.
+    #[derive(Default)]
+    struct Counting {
+        calls: AtomicUsize,
+    }
+
+    impl Summarizer for Counting {
+        fn summarize<'a>(
+            &'a self,
+            questions: &'a [String],
+        ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Ok(format!("{} questions, {}", questions.len(), questions[0])) })
+        }
+    }
+
+    #[test]
+    fn provider() {
+        assert_eq!("off".parse::<Provider>(), Ok(Provider::Off));
+        assert!("gpt".parse::<Provider>().is_err());
+        #[cfg(not(feature = "openai"))]
+        assert!("openai".parse::<Provider>().is_err());
+        #[cfg(feature = "openai")]
+        assert_eq!("openai".parse::<Provider>(), Ok(Provider::OpenAi));
+    }
+
+    #[test]
+    fn prompts() {
+        let questions = [
+            String::from("why  the\nreorg?"),
+            String::from("what about bonuses"),
+        ];
+        assert_eq!(prompt(&questions), "why the reorg?\nwhat about bonuses");
+    }
+
+    #[cfg(feature = "bedrock")]
+    #[test]
+    fn bedrock_text() {
+        let res = serde_json::json!({
+            "output": { "message": { "role": "assistant", "content": [{ "text": " Reorg. " }] } },
+            "stopReason": "end_turn",
+        });
+        assert_eq!(bedrock::text(&res).as_deref(), Some("Reorg."));
+        assert_eq!(bedrock::text(&serde_json::json!({})), None);
+    }
+
+    #[cfg(feature = "openai")]
+    #[test]
+    fn openai_text() {
+        let res = serde_json::json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Reorg.\n" } }],
+        });
+        assert_eq!(openai::text(&res).as_deref(), Some("Reorg."));
+        assert_eq!(openai::text(&serde_json::json!({ "choices": [] })), None);
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+        let summarizer = Counting::default();
+        let version = || async {
+            crate::check_secret(&backend, &eid, secret)
+                .await
+                .unwrap()
+                .version
+        };
+
+        // nothing to summarize yet
+        let s = summarize(&backend, &eid, version().await, &summarizer)
+            .await
+            .unwrap();
+        assert_eq!(s["summary"], serde_json::Value::Null);
+        assert_eq!(s["questions"], 0);
+        assert_eq!(summarizer.calls.load(Ordering::SeqCst), 0);
+
+        for body in ["what's happening with the reorg?", "is the bonus frozen?"] {
+            crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: None,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+        let v = version().await;
+        let s = summarize(&backend, &eid, v, &summarizer).await.unwrap();
+        assert_eq!(s["questions"], 2);
+        assert!(s["summary"].as_str().unwrap().starts_with("2 questions"));
+        assert_eq!(s["version"], v);
+        assert_eq!(summarizer.calls.load(Ordering::SeqCst), 1);
+
+        // the same version isn't summarized again
+        let again = summarize(&backend, &eid, v, &summarizer).await.unwrap();
+        assert_eq!(again, s);
+        assert_eq!(summarizer.calls.load(Ordering::SeqCst), 1);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,166 @@
+//! Telling hosts when a question suddenly gets a lot of votes.
+//!
+//! A question that gains votes much faster than the rest is either what everyone wants to hear
+//! about right now, or someone rallying people (or scripts) to push it up, and either way the host
+//! wants to know. Every upvote through this instance is counted, and once a question has had
+//! `VOTE_SURGE` of them within `VOTE_SURGE_WINDOW_MS`, hosts listening with the event's secret (see
+//! [`super::ws`] and [`super::sse`]) are told with `{"kind": "surge", "qid": .., "votes": ..,
+//! "within": <seconds>}`. If the host subscribed to [digests](super::digest), the same alert goes
+//! to the digest webhook too, with a plain-text `text` like the digests have. A question is only
+//! alerted about once per window, however fast it keeps going.
+//!
+//! Like the other process-wide state, votes are only counted by the instance they went through, so
+//! this is only reliable where the server runs on its own. As a Lambda, each instance only sees
+//! some of the votes, so it takes a bigger surge before anyone hears about it.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Forget about questions that haven't had votes in a while once this many are tracked.
+const MAX_TRACKED: usize = 4096;
+
+#[derive(Debug, Default)]
+struct Track {
+    /// When each of the question's recent upvotes came in, in milliseconds since the epoch.
+    recent: VecDeque<u64>,
+    /// When hosts were last told about the question.
+    alerted: Option<u64>,
+}
+
+impl Track {
+    /// Count an upvote at `now`, and say how many there have been within `window` if that's
+    /// `threshold` or more and hosts haven't been told about it in the meantime.
+    fn vote(&mut self, now: u64, threshold: usize, window: u64) -> Option<usize> {
+        self.recent.push_back(now);
+        while self.recent.front().is_some_and(|&at| at + window <= now) {
+            self.recent.pop_front();
+        }
+        // there's no need to remember any more votes than it takes
+        while self.recent.len() > threshold {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < threshold || self.alerted.is_some_and(|at| at + window > now) {
+            return None;
+        }
+        self.alerted = Some(now);
+        Some(self.recent.len())
+    }
+
+    fn quiet(&self, now: u64, window: u64) -> bool {
+        self.recent.back().is_none_or(|&at| at + window <= now)
+            && self.alerted.is_none_or(|at| at + window <= now)
+    }
+}
+
+static TRACKED: Mutex<BTreeMap<Uuid, Track>> = Mutex::new(BTreeMap::new());
+
+/// Count an upvote for `qid` at `now`, and say how many there were if it's a surge.
+fn vote_at(
+    tracked: &mut BTreeMap<Uuid, Track>,
+    qid: Uuid,
+    now: u64,
+    threshold: usize,
+    window: u64,
+) -> Option<usize> {
+    if tracked.len() >= MAX_TRACKED && !tracked.contains_key(&qid) {
+        tracked.retain(|_, t| !t.quiet(now, window));
+        if tracked.len() >= MAX_TRACKED {
+            // still busy everywhere, so this one will just have to wait its turn
+            return None;
+        }
+    }
+    tracked.entry(qid).or_default().vote(now, threshold, window)
+}
+
+/// Count an upvote for `qid` in `eid`, and tell the host if it's part of a surge.
+pub(super) fn upvoted(eid: &Uuid, qid: &Uuid) {
+    let config = super::config::config();
+    if config.vote_surge == 0 {
+        return;
+    }
+    let now = super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let window = config.vote_surge_window.as_millis() as u64;
+    let Some(votes) = vote_at(
+        &mut TRACKED.lock().unwrap(),
+        *qid,
+        now,
+        config.vote_surge,
+        window,
+    ) else {
+        return;
+    };
+
+    let within = config.vote_surge_window.as_secs();
+    info!(%eid, %qid, votes, within, "question is surging");
+    super::metrics::incr("surge.alerts");
+    let alert = serde_json::json!({
+        "kind": "surge",
+        "qid": qid,
+        "votes": votes,
+        "within": within,
+    });
+    super::bus::tell_hosts(eid, alert.clone());
+    let text = text(eid, qid, votes, config.vote_surge_window);
+    let eid = *eid;
+    tokio::spawn(async move { super::digest::alert(&eid, alert, text).await });
+}
+
+fn text(eid: &Uuid, qid: &Uuid, votes: usize, within: Duration) -> String {
+    format!(
+        "Question {qid} in event {eid} got {votes} votes in the last {} seconds",
+        within.as_secs()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_once_per_window() {
+        let mut tracked = BTreeMap::new();
+        let (q1, q2) = (Uuid::new_v4(), Uuid::new_v4());
+        // three votes within 10s is a surge
+        assert_eq!(vote_at(&mut tracked, q1, 0, 3, 10_000), None);
+        assert_eq!(vote_at(&mut tracked, q1, 4_000, 3, 10_000), None);
+        assert_eq!(vote_at(&mut tracked, q2, 5_000, 3, 10_000), None);
+        // but not spread out over longer
+        assert_eq!(vote_at(&mut tracked, q1, 11_000, 3, 10_000), None);
+        assert_eq!(vote_at(&mut tracked, q1, 12_000, 3, 10_000), Some(3));
+        // hosts already know
+        assert_eq!(vote_at(&mut tracked, q1, 13_000, 3, 10_000), None);
+        assert_eq!(vote_at(&mut tracked, q1, 21_000, 3, 10_000), None);
+        // until the window's passed
+        assert_eq!(vote_at(&mut tracked, q1, 22_000, 3, 10_000), Some(3));
+        assert!(tracked[&q1].recent.len() <= 3);
+
+        assert!(!tracked[&q2].quiet(14_000, 10_000));
+        assert!(tracked[&q2].quiet(15_000, 10_000));
+    }
+
+    #[tokio::test]
+    async fn tells_hosts() {
+        let (eid, qid) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut rx = crate::bus::subscribe(&eid);
+        for _ in 1..crate::config::config().vote_surge {
+            upvoted(&eid, &qid);
+        }
+        assert!(rx.try_recv().is_err());
+        upvoted(&eid, &qid);
+        let notice = rx.try_recv().unwrap();
+        assert!(notice.hosts_only);
+        let notice: serde_json::Value = serde_json::from_str(&notice.text).unwrap();
+        assert_eq!(notice["kind"], "surge");
+        assert_eq!(notice["qid"], qid.to_string());
+    }
+}
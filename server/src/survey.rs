@@ -0,0 +1,339 @@
+//! Bringing in questions collected before the event, like with a survey form.
+//!
+//! Teams often ask for questions ahead of a meeting with whatever survey tool they use. With
+//! `SURVEY_KEY` set, the tool (or whatever glue passes its responses on) can post them to
+//! `POST /api/event/:eid/survey` with `Authorization: Bearer <SURVEY_KEY>`, as a JSON object per
+//! response, or an array of them. Which field holds the question, and which the asker's name, is
+//! up to `SURVEY_FIELDS`, like `question=What would you like to ask?,name=/respondent/name`: a
+//! field is either the name of a top-level key or, starting with `/`, a JSON pointer. By default,
+//! they're `question` and `name`. Responses without a question are skipped.
+//!
+//! Questions from surveys go through the same checks as the ones asked on the site, and are marked
+//! with `"source": "survey"` (see [`super::questions`]). Posting the same responses again doesn't
+//! ask them twice: a question that's already in the event (or earlier in the same post), ignoring
+//! case, spacing, and punctuation at the end, is counted as a duplicate instead. The answer says
+//! which questions were `asked`, and how many responses were `duplicates` or `rejected`.
+
+use super::{ask::Question, changes::Change, Backend};
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::extract::{Path, State};
+use axum::response::Json;
+use http::{header, HeaderMap, StatusCode};
+use serde_json::Value;
+use std::{collections::HashSet, fmt, str::FromStr};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most responses that are taken in one post.
+const MAX_RESPONSES: usize = 1000;
+
+/// Where in a survey response its fields are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Fields {
+    question: String,
+    name: Option<String>,
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Fields {
+            question: String::from("question"),
+            name: Some(String::from("name")),
+        }
+    }
+}
+
+impl FromStr for Fields {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Fields {
+            question: String::new(),
+            name: None,
+        };
+        for mapping in s.split(',').filter(|s| !s.trim().is_empty()) {
+            let Some((ours, theirs)) = mapping.split_once('=') else {
+                return Err(format!("`{mapping}` isn't of the form field=name"));
+            };
+            let theirs = theirs.trim().to_string();
+            match ours.trim() {
+                "question" => fields.question = theirs,
+                "name" => fields.name = Some(theirs),
+                other => return Err(format!("`{other}` isn't a survey field")),
+            }
+        }
+        if fields.question.is_empty() {
+            return Err(String::from("there's no field for the question"));
+        }
+        Ok(fields)
+    }
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "question={}", self.question)?;
+        if let Some(name) = &self.name {
+            write!(f, ",name={name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The text of `field` in `response`, if it has any.
+fn pick(response: &Value, field: &str) -> Option<String> {
+    let v = if field.starts_with('/') {
+        response.pointer(field)?
+    } else {
+        response.get(field)?
+    };
+    let v = v.as_str()?.trim();
+    (!v.is_empty()).then(|| v.to_string())
+}
+
+/// What's left of `text` for telling whether two questions are the same.
+fn normalize(text: &str) -> String {
+    let text = text.trim_end_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace());
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The texts of all of `eid`'s questions, normalized.
+async fn existing(dynamo: &Backend, eid: &Uuid) -> Result<HashSet<String>, StatusCode> {
+    let list = dynamo.list(eid, true).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for questions to deduplicate against failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let qids: Vec<_> = list
+        .items()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|q| q.get("id").and_then(|v| v.as_s().ok()))
+        .filter_map(|qid| Uuid::parse_str(qid).ok())
+        .collect();
+    if qids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let texts = dynamo.questions(&qids).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for texts to deduplicate against failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(texts
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .map(|qs| {
+            qs.iter()
+                .filter_map(|q| q.get("text").and_then(|v| v.as_s().ok()))
+                .map(|t| normalize(t))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+pub(super) async fn survey(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    headers: HeaderMap,
+    Json(responses): Json<Value>,
+) -> Result<Json<Value>, StatusCode> {
+    let config = super::config::config();
+    let Some(ref key) = config.survey_key else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if given != Some(key.as_str()) {
+        warn!(%eid, "survey responses with incorrect key");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let responses = match responses {
+        Value::Array(responses) => responses,
+        response @ Value::Object(_) => vec![response],
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    if responses.len() > MAX_RESPONSES {
+        warn!(%eid, n = responses.len(), "too many survey responses at once");
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    ingest(&dynamo, &eid, &config.survey_fields, &responses)
+        .await
+        .map(Json)
+}
+
+async fn ingest(
+    dynamo: &Backend,
+    eid: &Uuid,
+    fields: &Fields,
+    responses: &[Value],
+) -> Result<Value, StatusCode> {
+    let meta = super::get_meta(dynamo, eid).await?;
+    super::ask::taking(eid, &meta)?;
+    let mut seen = existing(dynamo, eid).await?;
+
+    let mut asked = Vec::new();
+    let (mut duplicates, mut rejected) = (0, 0);
+    for response in responses {
+        let Some(body) = pick(response, &fields.question) else {
+            rejected += 1;
+            continue;
+        };
+        if super::ask::screen(eid, &body).is_err() {
+            rejected += 1;
+            continue;
+        }
+        if !seen.insert(normalize(&body)) {
+            duplicates += 1;
+            continue;
+        }
+        let asker = fields.name.as_deref().and_then(|f| pick(response, f));
+        let qid = Uuid::new_v4();
+        let q = Question { body, asker };
+        let extra = vec![("source", AttributeValue::S(String::from("survey")))];
+        if let Err(e) = dynamo
+            .ask_with(eid, &qid, q, super::clock::now(), meta.held(), extra)
+            .await
+        {
+            error!(%eid, %qid, error = %e, "dynamodb request to create survey question failed");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        dynamo.try_record(eid, Change::QuestionAsked { qid }).await;
+        asked.push(qid.to_string());
+    }
+    info!(
+        %eid,
+        asked = asked.len(),
+        duplicates,
+        rejected,
+        "took questions from survey"
+    );
+    super::metrics::add("survey.asked", asked.len() as u64);
+    Ok(serde_json::json!({
+        "asked": asked,
+        "duplicates": duplicates,
+        "rejected": rejected,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let fields: Fields = "question=What would you like to ask?, name=/who/name"
+            .parse()
+            .unwrap();
+        assert_eq!(fields.question, "What would you like to ask?");
+        assert_eq!(fields.name.as_deref(), Some("/who/name"));
+        assert_eq!(fields.to_string().parse::<Fields>().unwrap(), fields);
+        assert!("name=who".parse::<Fields>().is_err());
+        assert!("topic=x,question=q".parse::<Fields>().is_err());
+        assert!("question".parse::<Fields>().is_err());
+
+        let response = serde_json::json!({
+            "What would you like to ask?": " why is the sky blue? ",
+            "who": { "name": "Ada" },
+            "empty": "",
+        });
+        assert_eq!(
+            pick(&response, &fields.question).as_deref(),
+            Some("why is the sky blue?")
+        );
+        assert_eq!(pick(&response, "/who/name").as_deref(), Some("Ada"));
+        assert_eq!(pick(&response, "empty"), None);
+        assert_eq!(pick(&response, "who"), None);
+    }
+
+    #[test]
+    fn normalizes() {
+        assert_eq!(
+            normalize("  Why is  the Sky blue?! "),
+            normalize("why is the sky blue")
+        );
+        assert_ne!(
+            normalize("why is the sky blue"),
+            normalize("why is the sea blue")
+        );
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            HeaderMap::new(),
+            Json(Question {
+                body: String::from("what's the plan for next year?"),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let responses = [
+            serde_json::json!({ "question": "will there be a bonus this year?", "name": "Sam" }),
+            // already asked
+            serde_json::json!({ "question": "What's the plan for next year" }),
+            // in the same post
+            serde_json::json!({ "question": "will there be a bonus  this year" }),
+            serde_json::json!({ "question": "bonus?" }),
+            serde_json::json!({ "name": "Kim" }),
+        ];
+        let fields = Fields::default();
+        let res = ingest(&backend, &eid, &fields, &responses).await.unwrap();
+        assert_eq!(res["duplicates"], 2);
+        assert_eq!(res["rejected"], 2);
+        let asked = res["asked"].as_array().unwrap();
+        assert_eq!(asked.len(), 1);
+        let qid = Uuid::parse_str(asked[0].as_str().unwrap()).unwrap();
+        let qs = backend.questions(&[qid]).await.unwrap();
+        let (_, q) = crate::questions::to_json(&qs.responses().unwrap()["questions"][0]).unwrap();
+        assert_eq!(q["text"], "will there be a bonus this year?");
+        assert_eq!(q["who"], "Sam");
+        assert_eq!(q["source"], "survey");
+
+        // and posting it all again asks nothing new
+        let res = ingest(&backend, &eid, &fields, &responses).await.unwrap();
+        assert_eq!(res["asked"], serde_json::json!([]));
+        assert_eq!(res["duplicates"], 3);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,242 @@
+//! Errors that browsers run into, reported back so operators get to see them.
+//!
+//! The server's logs only show what the server saw. When the client breaks, or gives up on a
+//! request, it can `POST /api/telemetry/client-error` with what went wrong: a `message`, and
+//! optionally where (`context`), for which event (`eid`), the HTTP `status` it got, and the
+//! `request_ids` of the responses involved (from their `X-Request-Id`), which is what ties a report
+//! to the server's logs.
+//!
+//! Request bodies are capped at a kilobyte anyway, and the fields are held to limits of their own.
+//! Each browser token gets `PER_TOKEN` reports a minute, and everyone together `PER_MINUTE`, so a
+//! broken release can't turn into a flood. Accepted reports are logged, counted as
+//! `client_errors.reported` (and refused ones as `client_errors.dropped`), and the last `KEEP` of
+//! them from the past `MAX_AGE` seconds are kept in memory for `GET /api/admin/client-errors`. Like
+//! the metrics, each Lambda instance only has the reports it received.
+
+use super::admin::Admin;
+use axum::response::Json;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::SystemTime,
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How many reports a single browser token may send a minute.
+const PER_TOKEN: usize = 5;
+/// How many reports are accepted a minute, from everyone.
+const PER_MINUTE: usize = 120;
+/// How many reports are kept.
+const KEEP: usize = 200;
+/// How long reports are kept for, in seconds.
+const MAX_AGE: u64 = 60 * 60;
+
+const MAX_MESSAGE: usize = 500;
+const MAX_CONTEXT: usize = 100;
+const MAX_REQUEST_IDS: usize = 5;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(super) struct Report {
+    /// The token the browser pings and votes with, which is only used for rate limiting.
+    #[serde(skip_serializing)]
+    token: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eid: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    request_ids: Vec<String>,
+}
+
+impl Report {
+    /// What's wrong with the report itself, if anything.
+    fn problem(&self) -> Option<&'static str> {
+        let short = |s: &str, max| !s.is_empty() && s.len() <= max;
+        if !short(&self.token, 64) {
+            Some("bad token")
+        } else if !short(&self.message, MAX_MESSAGE) {
+            Some("bad message")
+        } else if self
+            .context
+            .as_deref()
+            .is_some_and(|c| !short(c, MAX_CONTEXT))
+        {
+            Some("bad context")
+        } else if self.request_ids.len() > MAX_REQUEST_IDS
+            || !self.request_ids.iter().all(|id| short(id, 64))
+        {
+            Some("bad request ids")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Kept {
+    /// When the report was received, in seconds since the epoch.
+    at: u64,
+    #[serde(flatten)]
+    report: Report,
+}
+
+/// How many reports were accepted in the current minute, in total and per token.
+struct Budget {
+    minute: u64,
+    total: usize,
+    by_token: BTreeMap<String, usize>,
+}
+
+static BUDGET: Mutex<Budget> = Mutex::new(Budget {
+    minute: 0,
+    total: 0,
+    by_token: BTreeMap::new(),
+});
+static KEPT: Mutex<VecDeque<Kept>> = Mutex::new(VecDeque::new());
+
+fn now() -> u64 {
+    super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Whether another report from `token` fits in the budget for `minute`.
+fn admit(budget: &mut Budget, token: &str, minute: u64) -> bool {
+    if budget.minute != minute {
+        budget.minute = minute;
+        budget.total = 0;
+        budget.by_token.clear();
+    }
+    if budget.total >= PER_MINUTE {
+        return false;
+    }
+    let sent = budget.by_token.entry(token.to_string()).or_default();
+    if *sent >= PER_TOKEN {
+        return false;
+    }
+    *sent += 1;
+    budget.total += 1;
+    true
+}
+
+/// Keep `report`, received `at`, forgetting the oldest ones if there are too many.
+fn keep(kept: &mut VecDeque<Kept>, report: Report, at: u64) {
+    kept.push_back(Kept { at, report });
+    while kept.len() > KEEP {
+        kept.pop_front();
+    }
+}
+
+/// The reports that are still recent as of `now`, newest first.
+fn recent(kept: &VecDeque<Kept>, now: u64) -> Vec<&Kept> {
+    kept.iter()
+        .rev()
+        .take_while(|k| k.at + MAX_AGE >= now)
+        .collect()
+}
+
+pub(super) async fn client_error(Json(report): Json<Report>) -> StatusCode {
+    if let Some(problem) = report.problem() {
+        debug!(problem, "malformed client error report");
+        return StatusCode::BAD_REQUEST;
+    }
+    let now = now();
+    if !admit(&mut BUDGET.lock().unwrap(), &report.token, now / 60) {
+        super::metrics::incr("client_errors.dropped");
+        return StatusCode::TOO_MANY_REQUESTS;
+    }
+    super::metrics::incr("client_errors.reported");
+    warn!(
+        error = %report.message,
+        context = ?report.context,
+        eid = ?report.eid,
+        status = ?report.status,
+        request_ids = ?report.request_ids,
+        "client reported an error"
+    );
+    keep(&mut KEPT.lock().unwrap(), report, now);
+    StatusCode::NO_CONTENT
+}
+
+pub(super) async fn client_errors(_: Admin) -> Json<serde_json::Value> {
+    let kept = KEPT.lock().unwrap();
+    Json(serde_json::json!(recent(&kept, now())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(token: &str, message: &str) -> Report {
+        Report {
+            token: token.to_string(),
+            message: message.to_string(),
+            context: None,
+            eid: None,
+            status: None,
+            request_ids: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn checks_reports() {
+        assert_eq!(report("a", "oops").problem(), None);
+        assert!(report("", "oops").problem().is_some());
+        assert!(report("a", "").problem().is_some());
+        assert!(report("a", &"x".repeat(MAX_MESSAGE + 1))
+            .problem()
+            .is_some());
+        let mut r = report("a", "oops");
+        r.request_ids = vec![String::from("id"); MAX_REQUEST_IDS + 1];
+        assert!(r.problem().is_some());
+    }
+
+    #[test]
+    fn limits_reports() {
+        let mut budget = Budget {
+            minute: 0,
+            total: 0,
+            by_token: BTreeMap::new(),
+        };
+        assert!((0..PER_TOKEN).all(|_| admit(&mut budget, "a", 1)));
+        assert!(!admit(&mut budget, "a", 1), "one token can't send too many");
+        assert!(admit(&mut budget, "b", 1));
+        assert!(admit(&mut budget, "a", 2), "until the next minute");
+
+        let mut n = 0;
+        while admit(&mut budget, &n.to_string(), 3) {
+            n += 1;
+        }
+        assert_eq!(n, PER_MINUTE, "nor can everyone together");
+    }
+
+    #[test]
+    fn keeps_recent_reports() {
+        let mut kept = VecDeque::new();
+        keep(&mut kept, report("a", "old"), 10);
+        for i in 0..KEEP {
+            keep(&mut kept, report("a", &i.to_string()), 20);
+        }
+        assert_eq!(kept.len(), KEEP);
+        assert!(kept.iter().all(|k| k.report.message != "old"));
+
+        keep(&mut kept, report("a", "new"), 30 + MAX_AGE);
+        let recent = recent(&kept, 30 + MAX_AGE);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].report.message, "new");
+        // tokens are only for rate limiting
+        assert!(serde_json::to_value(recent[0])
+            .unwrap()
+            .get("token")
+            .is_none());
+    }
+}
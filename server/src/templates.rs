@@ -0,0 +1,540 @@
+//! Canned answers, for hosts who get asked the same kind of thing over and over.
+//!
+//! A host can keep answer templates for an event, by name: `PUT
+//! /api/event/:eid/templates/:secret/:name` with `{"text": "This is on our roadmap for {quarter}"}`
+//! adds or replaces one, `DELETE` on the same path removes it, and `GET
+//! /api/event/:eid/templates/:secret` lists them all. Each event can have `MAX_TEMPLATES` of them.
+//! Like digest subscriptions, they're kept in the [blob store](super::blobs).
+//!
+//! `POST /api/event/:eid/questions/:secret/:qid/answer` with `{"template": "roadmap", "params":
+//! {"quarter": "Q3"}}` then fills in the template's `{placeholders}` and publishes the result as
+//! the question's answer: the answer is kept on the question, which is marked answered as if the
+//! host toggled it, and `/api/q/:qid` includes it as `answer`. A template with a placeholder that
+//! isn't given a value isn't used. `{{` and `}}` stand for plain braces. Hosts can also write an
+//! answer out themselves, for questions that weren't addressed live, by posting `{"body": "..."}`
+//! instead. Answering a question again replaces its answer.
+
+use super::{blobs::BlobStore, toggle::Property, Backend, Local};
+#[cfg(any(feature = "mongo", feature = "redis", feature = "sled"))]
+use aws_sdk_dynamodb::error::UpdateItemError;
+use aws_sdk_dynamodb::model::AttributeValue;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json, Response};
+use bytes::Bytes;
+use http::StatusCode;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The most templates an event can have.
+const MAX_TEMPLATES: usize = 50;
+/// Template names can't be longer than this.
+const MAX_NAME: usize = 64;
+/// Nor can templates, or the answers they're filled into.
+const MAX_TEXT: usize = 2000;
+
+/// An event's templates, by name.
+type Templates = BTreeMap<String, String>;
+
+fn key(eid: &Uuid) -> String {
+    format!("templates/{eid}.json")
+}
+
+async fn load(store: &BlobStore, eid: &Uuid) -> Result<Templates, StatusCode> {
+    match store.get(&key(eid)).await {
+        Ok(blob) => Ok(blob
+            .and_then(|blob| {
+                serde_json::from_slice(&blob.data)
+                    .map_err(|e| error!(%eid, error = %e, "found malformed answer templates"))
+                    .ok()
+            })
+            .unwrap_or_default()),
+        Err(e) => {
+            error!(%eid, error = %e, "failed to read answer templates");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn save(store: &BlobStore, eid: &Uuid, templates: &Templates) -> Result<(), StatusCode> {
+    let result = if templates.is_empty() {
+        store.delete(&key(eid)).await
+    } else {
+        let data = Bytes::from(serde_json::to_vec(templates).expect("templates always serialize"));
+        store.put_bytes(&key(eid), "application/json", data).await
+    };
+    result.map_err(|e| {
+        error!(%eid, error = %e, "failed to store answer templates");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// `template` with its placeholders replaced by their `value`s.
+fn fill<'a>(template: &str, value: impl Fn(&str) -> Option<&'a str>) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let (brace, after) = rest[i..].split_at(1);
+        if let Some(after) = after.strip_prefix(brace) {
+            // doubled up, so just a brace
+            out.push_str(brace);
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            return Err(String::from("unmatched \"}\""));
+        }
+        let Some(end) = after.find('}') else {
+            return Err(String::from("unmatched \"{\""));
+        };
+        let name = &after[..end];
+        let Some(value) = value(name) else {
+            return Err(format!("no value for {{{name}}}"));
+        };
+        out.push_str(value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn bad(error: &'static str, message: String) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": error, "message": message })),
+    )
+        .into_response()
+}
+
+impl Backend {
+    /// Make `answer` the published answer to `qid`.
+    pub(super) async fn answer(
+        &self,
+        qid: &Uuid,
+        answer: &str,
+    ) -> Result<(), aws_sdk_dynamodb::Error> {
+        let answer = answer.to_string();
+        match self {
+            Self::Dynamo(dynamo) => {
+                dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression("SET #answer = :answer")
+                    .expression_attribute_names("#answer", "answer")
+                    .expression_attribute_values(":answer", AttributeValue::S(answer))
+                    .send()
+                    .await?;
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                if let Some(q) = questions.get_mut(qid) {
+                    q.insert("answer", AttributeValue::S(answer));
+                    journal.question(qid, q);
+                }
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                mongo
+                    .update::<UpdateItemError>(
+                        qid,
+                        mongodb::bson::doc! { "$set": { "answer": answer } },
+                    )
+                    .await?;
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                redis
+                    .set::<UpdateItemError>(qid, &[("answer", AttributeValue::S(answer))])
+                    .await?;
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                sled.set::<UpdateItemError>(qid, &[("answer", AttributeValue::S(answer))])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The answer published to `qid`, if there is one.
+    ///
+    /// Only `/api/q/:qid` shows answers, so they're left out of [`Backend::questions`], which
+    /// `/api/questions` caches for good.
+    pub(super) async fn published(
+        &self,
+        qid: &Uuid,
+    ) -> Result<Option<String>, aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let q = dynamo
+                    .get_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .projection_expression("#answer")
+                    .expression_attribute_names("#answer", "answer")
+                    .send()
+                    .await?;
+                Ok(q.item()
+                    .and_then(|q| q.get("answer"))
+                    .and_then(|v| v.as_s().ok())
+                    .cloned())
+            }
+            Self::Local(local) => {
+                let local = local.read().unwrap();
+                Ok(local
+                    .questions
+                    .get(qid)
+                    .and_then(|q| q.get("answer"))
+                    .and_then(|v| v.as_s().ok())
+                    .cloned())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.published(qid).await.map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.published(qid).await.map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.published(qid).map_err(super::mint_unhandled),
+        }
+    }
+}
+
+/// List the event's templates, for its host.
+pub(super) async fn templates(
+    Path((eid, secret)): Path<(Uuid, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(&dynamo, &eid, &secret).await?;
+    let templates = load(super::blobs::store().await, &eid).await?;
+    Ok(Json(serde_json::json!({ "templates": templates })))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Template {
+    text: String,
+}
+
+/// Add or replace one of the event's templates, for its host.
+pub(super) async fn put(
+    Path((eid, secret, name)): Path<(Uuid, String, String)>,
+    State(dynamo): State<Backend>,
+    Json(template): Json<Template>,
+) -> Result<Json<serde_json::Value>, Response> {
+    super::check_secret(&dynamo, &eid, &secret)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let name = name.trim();
+    let text = template.text.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME {
+        warn!(%eid, "bad answer template name");
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+    if text.is_empty() || text.chars().count() > MAX_TEXT {
+        warn!(%eid, name, "bad answer template");
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+    // with every placeholder filled in, only the braces can be wrong
+    fill(text, |_| Some("")).map_err(|e| bad("bad_template", e))?;
+
+    let store = super::blobs::store().await;
+    let mut templates = load(store, &eid)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    if templates.len() >= MAX_TEMPLATES && !templates.contains_key(name) {
+        warn!(%eid, "too many answer templates");
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+    templates.insert(name.to_string(), text.to_string());
+    save(store, &eid, &templates)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    debug!(%eid, name, "host saved answer template");
+    Ok(Json(serde_json::json!({ "templates": templates })))
+}
+
+/// Remove one of the event's templates, for its host.
+pub(super) async fn delete(
+    Path((eid, secret, name)): Path<(Uuid, String, String)>,
+    State(dynamo): State<Backend>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    super::check_secret(&dynamo, &eid, &secret).await?;
+    let store = super::blobs::store().await;
+    let mut templates = load(store, &eid).await?;
+    if templates.remove(name.trim()).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    save(store, &eid, &templates).await?;
+    debug!(%eid, name, "host removed answer template");
+    Ok(Json(serde_json::json!({ "templates": templates })))
+}
+
+/// Which template to answer with, and what to fill into it, or the answer itself.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Apply {
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    params: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Answer a question, from one of the event's templates or in so many words, for its host.
+pub(super) async fn answer(
+    Path((eid, secret, qid)): Path<(Uuid, String, Uuid)>,
+    State(dynamo): State<Backend>,
+    Json(apply): Json<Apply>,
+) -> Result<Json<serde_json::Value>, Response> {
+    super::check_secret(&dynamo, &eid, &secret)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let answer = match (&apply.template, apply.body) {
+        (Some(name), None) => {
+            let templates = load(super::blobs::store().await, &eid)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            let Some(template) = templates.get(name.trim()) else {
+                debug!(%eid, template = name, "answer with unknown template");
+                return Err(StatusCode::NOT_FOUND.into_response());
+            };
+            fill(template, |name| apply.params.get(name).map(String::as_str))
+                .map_err(|e| bad("missing_parameter", e))?
+        }
+        (None, Some(body)) => body,
+        _ => {
+            return Err(bad(
+                "template_or_body",
+                String::from("answers need either a template or a body"),
+            ))
+        }
+    };
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Err(bad("empty_answer", String::from("answers can't be empty")));
+    }
+    if answer.chars().count() > MAX_TEXT {
+        warn!(%eid, %qid, "answer filled in from template is too long");
+        return Err(bad(
+            "answer_too_long",
+            format!("answers can't be longer than {MAX_TEXT} characters"),
+        ));
+    }
+
+    let q = dynamo.questions(&[qid]).await.map_err(|e| {
+        error!(%eid, %qid, error = %e, "dynamodb request for question to answer failed");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+    let theirs = q
+        .responses()
+        .and_then(|r| r.get("questions"))
+        .and_then(|qs| qs.first())
+        .is_some_and(|q| q.get("eid").and_then(|v| v.as_s().ok()) == Some(&eid.to_string()));
+    if !theirs {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+
+    if let Err(e) = dynamo.answer(&qid, answer).await {
+        error!(%eid, %qid, error = %e, "dynamodb request to publish answer failed");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+    super::toggle::apply(&dynamo, &eid, qid, Property::Answered, true, None)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    debug!(%eid, %qid, template = apply.template, "answered question");
+    Ok(Json(serde_json::json!({
+        "qid": qid.to_string(),
+        "answer": answer,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+
+    #[test]
+    fn fills() {
+        let params = |name: &str| (name == "quarter").then_some("Q3");
+        assert_eq!(
+            fill("on the roadmap for {quarter}", params).unwrap(),
+            "on the roadmap for Q3"
+        );
+        assert_eq!(
+            fill("{{quarter}} is {quarter}", params).unwrap(),
+            "{quarter} is Q3"
+        );
+        assert!(fill("for {year}", params).is_err());
+        assert!(fill("for {quarter", params).is_err());
+        assert!(fill("for quarter}", params).is_err());
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "when is dark mode coming".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+        let put = |name: &str, text: &str| {
+            super::put(
+                Path((eid, secret.clone(), name.to_string())),
+                State(backend.clone()),
+                Json(Template {
+                    text: text.to_string(),
+                }),
+            )
+        };
+        let answer = |template: &str, params: &[(&str, &str)]| {
+            super::answer(
+                Path((eid, secret.clone(), qid)),
+                State(backend.clone()),
+                Json(Apply {
+                    template: Some(template.to_string()),
+                    params: params
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                    body: None,
+                }),
+            )
+        };
+        let write = |apply: Apply| {
+            super::answer(
+                Path((eid, secret.clone(), qid)),
+                State(backend.clone()),
+                Json(apply),
+            )
+        };
+
+        let Json(saved) = put("roadmap", "This is on our roadmap for {quarter}.")
+            .await
+            .unwrap();
+        assert_eq!(
+            saved["templates"]["roadmap"],
+            "This is on our roadmap for {quarter}."
+        );
+        assert_eq!(
+            put("broken", "for {quarter").await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            answer("nope", &[]).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            answer("roadmap", &[]).await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let Json(answered) = answer("roadmap", &[("quarter", "Q3")]).await.unwrap();
+        assert_eq!(answered["answer"], "This is on our roadmap for Q3.");
+        let Json(all) = crate::list::list_all(
+            Path((eid, secret.clone())),
+            State(backend.clone()),
+            Query(Default::default()),
+            http::HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap();
+        assert_eq!(all[0]["answered"], true);
+        let (_, link) = crate::permalink::permalink(Path(qid), State(backend.clone())).await;
+        assert_eq!(link.unwrap().0["answer"], "This is on our roadmap for Q3.");
+        // and only there, since texts are cached for good
+        let qs = backend.questions(&[qid]).await.unwrap();
+        assert!(!qs.responses().unwrap()["questions"][0].contains_key("answer"));
+
+        // or in so many words, which replaces the answer
+        for (apply, error) in [
+            (Apply::default(), "template_or_body"),
+            (
+                Apply {
+                    template: Some(String::from("roadmap")),
+                    body: Some(String::from("soon")),
+                    ..Default::default()
+                },
+                "template_or_body",
+            ),
+            (
+                Apply {
+                    body: Some(String::from("  ")),
+                    ..Default::default()
+                },
+                "empty_answer",
+            ),
+        ] {
+            let res = write(apply).await.unwrap_err();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], error);
+        }
+        let Json(answered) = write(Apply {
+            body: Some(String::from(" Dark mode ships next week. ")),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(answered["answer"], "Dark mode ships next week.");
+        let (_, link) = crate::permalink::permalink(Path(qid), State(backend.clone())).await;
+        assert_eq!(link.unwrap().0["answer"], "Dark mode ships next week.");
+
+        let Json(left) = delete(
+            Path((eid, secret.clone(), String::from("roadmap"))),
+            State(backend.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(left["templates"], serde_json::json!({}));
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -0,0 +1,277 @@
+//! An event's questions as plain text, for when the client isn't an option.
+//!
+//! `GET /event/:eid.txt` (or `GET /api/event/:eid/text`) renders the questions guests can see,
+//! unanswered ones first and each in order of votes, along with who asked them if they said. It
+//! needs neither JavaScript nor a second request for the question texts, so it works with screen
+//! readers, with `curl`, and on connections that can barely load the client.
+//!
+//! It's built from the same list guests poll, so it's cached (and goes stale when the database is
+//! having trouble) the same way, and is refused for frozen events.
+
+use super::Backend;
+use axum::extract::{Path, Query, State};
+use axum::response::{AppendHeaders, Json};
+use http::{
+    header::{self, HeaderName},
+    HeaderMap, Request, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Write};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Send `/event/:eid.txt` to the text route, since the router can't match on a suffix.
+///
+/// Everything else under `/event/` is the client's, so that can't be a route of its own either.
+pub(super) fn rewrite<B>(mut req: Request<B>) -> Request<B> {
+    let eid = req
+        .uri()
+        .path()
+        .strip_prefix("/event/")
+        .and_then(|p| p.strip_suffix(".txt"))
+        .filter(|eid| !eid.contains('/'));
+    if let Some(uri) = eid.and_then(|eid| format!("/api/event/{eid}/text").parse().ok()) {
+        *req.uri_mut() = uri;
+    }
+    req
+}
+
+/// A question as it's shown in a transcript.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Line {
+    pub(super) text: String,
+    pub(super) who: Option<String>,
+    pub(super) votes: u64,
+}
+
+/// The questions guests can see in an event, each in order of votes.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Transcript {
+    pub(super) unanswered: Vec<Line>,
+    pub(super) answered: Vec<Line>,
+}
+
+/// Put together the transcript of `eid`, and how long it may be cached for.
+///
+/// If that doesn't work out, this says how long the failure may be cached for instead.
+pub(super) async fn transcript(
+    dynamo: &Backend,
+    eid: &Uuid,
+) -> Result<(&'static str, Transcript), (&'static str, StatusCode)> {
+    // the list guests see has already been checked for existence, freezing, and the like
+    let (AppendHeaders([(_, cache)]), _, listed) = super::list::list(
+        Path(*eid),
+        State(dynamo.clone()),
+        Query(Default::default()),
+        HeaderMap::new(),
+    )
+    .await;
+    let Json(listed) = listed.map_err(|status| (cache, status))?;
+    let listed = listed.as_array().map(Vec::as_slice).unwrap_or_default();
+
+    let qids: Vec<_> = listed
+        .iter()
+        .filter_map(|q| q["qid"].as_str())
+        .filter_map(|qid| Uuid::parse_str(qid).ok())
+        .collect();
+    let mut texts = HashMap::new();
+    // BatchGetItem takes at most 100 keys
+    for qids in qids.chunks(100) {
+        match dynamo.questions(qids).await {
+            Ok(v) => texts.extend(
+                v.responses()
+                    .and_then(|r| r.get("questions"))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(super::questions::to_json),
+            ),
+            Err(e) => {
+                error!(%eid, error = %e, "dynamodb request for question texts failed");
+                return Err(("no-cache", StatusCode::INTERNAL_SERVER_ERROR));
+            }
+        }
+    }
+
+    let mut transcript = Transcript {
+        unanswered: Vec::new(),
+        answered: Vec::new(),
+    };
+    for q in listed {
+        let Some(t) = q["qid"].as_str().and_then(|qid| texts.remove(qid)) else {
+            // asked after the list was made, or already gone again
+            continue;
+        };
+        let line = Line {
+            text: t["text"].as_str().unwrap_or_default().trim().to_string(),
+            who: t["who"].as_str().map(String::from),
+            votes: q["votes"].as_u64().unwrap_or_default(),
+        };
+        if q["answered"] == true {
+            transcript.answered.push(line);
+        } else {
+            transcript.unanswered.push(line);
+        }
+    }
+    Ok((cache, transcript))
+}
+
+fn render(eid: &Uuid, transcript: &Transcript) -> String {
+    let mut out = format!("Questions for event {eid}\n");
+    let Transcript {
+        unanswered,
+        answered,
+    } = transcript;
+    if unanswered.is_empty() && answered.is_empty() {
+        out.push_str("\nNo questions have been asked yet.\n");
+    }
+    for (heading, lines) in [("Unanswered", unanswered), ("Answered", answered)] {
+        if lines.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "\n{heading} ({})\n", lines.len());
+        for line in lines {
+            // keep multi-line questions under their bullet
+            let _ = write!(out, "\n* {}\n", line.text.replace('\n', "\n  "));
+            let plural = if line.votes == 1 { "" } else { "s" };
+            let _ = match &line.who {
+                Some(who) => writeln!(out, "  {} vote{plural}, asked by {who}", line.votes),
+                None => writeln!(out, "  {} vote{plural}", line.votes),
+            };
+        }
+    }
+    out
+}
+
+pub(super) async fn text(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+) -> (
+    AppendHeaders<[(HeaderName, &'static str); 2]>,
+    Result<String, StatusCode>,
+) {
+    let plain = |cache: &'static str| {
+        AppendHeaders([
+            (header::CACHE_CONTROL, cache),
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+        ])
+    };
+    match transcript(&dynamo, &eid).await {
+        Ok((cache, transcript)) => (plain(cache), Ok(render(&eid, &transcript))),
+        Err((cache, status)) => (plain(cache), Err(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap();
+
+        let (_, empty) = super::text(Path(eid), State(backend.clone())).await;
+        assert!(empty.unwrap().contains("No questions"));
+
+        let mut qids = Vec::new();
+        for (body, asker) in [
+            ("what is the answer?", None),
+            ("who asked?\nand why?", Some("Alice")),
+            ("can you see me?", None),
+        ] {
+            let q = crate::ask::ask(
+                Path(eid),
+                State(backend.clone()),
+                http::HeaderMap::new(),
+                Json(crate::ask::Question {
+                    body: body.into(),
+                    asker: asker.map(String::from),
+                }),
+            )
+            .await
+            .unwrap();
+            qids.push(Uuid::parse_str(q["id"].as_str().unwrap()).unwrap());
+        }
+        for (qid, property) in [
+            (qids[0], crate::toggle::Property::Answered),
+            (qids[2], crate::toggle::Property::Hidden),
+        ] {
+            crate::toggle::toggle(
+                Path((eid, secret.to_string(), qid, property)),
+                State(backend.clone()),
+                axum::extract::Query(Default::default()),
+                String::from("on"),
+            )
+            .await
+            .unwrap();
+        }
+        crate::hot::forget(&eid);
+
+        let (AppendHeaders(headers), text) = super::text(Path(eid), State(backend.clone())).await;
+        assert_eq!(headers[1].1, "text/plain; charset=utf-8");
+        let text = text.unwrap();
+        assert!(!text.contains("can you see me?"), "{text}");
+        let unanswered = text.find("Unanswered (1)").expect(&text);
+        let answered = text.find("Answered (1)").expect(&text);
+        let asked = text.find("* who asked?\n  and why?\n").expect(&text);
+        assert!(unanswered < asked && asked < answered, "{text}");
+        assert!(text.contains("1 vote, asked by Alice"), "{text}");
+        assert!(text[answered..].contains("* what is the answer?"), "{text}");
+
+        backend.delete(&eid).await;
+        let (_, gone) = super::text(Path(eid), State(backend.clone())).await;
+        assert_eq!(gone.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[test]
+    fn rewrites() {
+        let path = |uri: &str| {
+            let req = Request::get(uri).body(()).unwrap();
+            rewrite(req).uri().to_string()
+        };
+        let eid = Uuid::new_v4();
+        assert_eq!(
+            path(&format!("/event/{eid}.txt")),
+            format!("/api/event/{eid}/text")
+        );
+        // the client's own routes are left alone
+        assert_eq!(path(&format!("/event/{eid}")), format!("/event/{eid}"));
+        assert_eq!(
+            path(&format!("/event/{eid}/secret.txt")),
+            format!("/event/{eid}/secret.txt")
+        );
+    }
+}
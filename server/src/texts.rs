@@ -0,0 +1,177 @@
+//! In-memory copies of question texts, so that fetching them again doesn't go to the database.
+//!
+//! What `/api/questions/:qids` shows of a question never changes once its asker can no longer
+//! [edit](super::author) it, so from then on responses are cached by browsers and the CDN for as
+//! long as they'll keep them (with `immutable`, so browsers don't even revalidate on reload). Every new viewer of a busy event still asks for them, though,
+//! and the CDN doesn't keep everything, so each instance also keeps the `TEXT_CACHE` question
+//! items it fetched most recently, and only fetches the rest.
+//!
+//! Texts do go away eventually: the retention policy blanks or deletes old questions, and the
+//! questions of frozen events are withheld until they've been reviewed. When that happens through
+//! this instance, the copies are dropped right away. Nothing tells an instance about what other
+//! instances (or the `retention` command) did, so copies are also dropped after `TTL`, and
+//! questions of frozen events aren't kept at all. Nor are questions whose askers can still
+//! [edit](super::author) them, since other instances wouldn't hear about the edit.
+
+use aws_sdk_dynamodb::model::AttributeValue;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How long a copy is kept before it's fetched again.
+const TTL: Duration = Duration::from_secs(10 * 60);
+
+type Item = HashMap<String, AttributeValue>;
+
+#[derive(Debug)]
+struct Entry {
+    item: Item,
+    loaded: Instant,
+    /// When the copy was last used, as a position in `Cache::by_use`.
+    used: u64,
+}
+
+#[derive(Debug)]
+struct Cache {
+    entries: BTreeMap<Uuid, Entry>,
+    /// Which question was used at each point, oldest first.
+    by_use: BTreeMap<u64, Uuid>,
+    uses: u64,
+}
+
+impl Cache {
+    const fn new() -> Self {
+        Cache {
+            entries: BTreeMap::new(),
+            by_use: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    fn remove(&mut self, qid: &Uuid) {
+        if let Some(entry) = self.entries.remove(qid) {
+            self.by_use.remove(&entry.used);
+        }
+    }
+
+    /// The copy of `qid`, if there's a fresh one.
+    fn get(&mut self, qid: &Uuid) -> Option<&Item> {
+        let entry = self.entries.get(qid)?;
+        if entry.loaded.elapsed() > TTL {
+            self.remove(qid);
+            return None;
+        }
+        self.uses += 1;
+        let entry = self.entries.get_mut(qid).expect("just looked");
+        self.by_use.remove(&entry.used);
+        entry.used = self.uses;
+        self.by_use.insert(entry.used, *qid);
+        Some(&entry.item)
+    }
+
+    /// Keep `item` as the copy of `qid`, and make room for it if there are `limit` already.
+    fn put(&mut self, qid: Uuid, item: Item, limit: usize) {
+        self.remove(&qid);
+        while self.entries.len() >= limit {
+            let Some((_, coldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            self.entries.remove(&coldest);
+        }
+        self.uses += 1;
+        self.by_use.insert(self.uses, qid);
+        self.entries.insert(
+            qid,
+            Entry {
+                item,
+                loaded: Instant::now(),
+                used: self.uses,
+            },
+        );
+    }
+}
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache::new());
+
+/// The copies there are of `qids`, and which of them there aren't copies of.
+pub(super) fn get(qids: &[Uuid]) -> (Vec<Item>, Vec<Uuid>) {
+    let mut cache = CACHE.lock().unwrap();
+    let mut hits = Vec::new();
+    let mut misses = Vec::new();
+    for qid in qids {
+        match cache.get(qid) {
+            Some(item) => hits.push(item.clone()),
+            None => misses.push(*qid),
+        }
+    }
+    super::metrics::add("texts.hit", hits.len() as u64);
+    super::metrics::add("texts.miss", misses.len() as u64);
+    (hits, misses)
+}
+
+/// Keep copies of the question `items` that were just fetched.
+pub(super) fn put(items: &[Item]) {
+    let limit = super::config::config().text_cache;
+    if limit == 0 {
+        return;
+    }
+    let mut cache = CACHE.lock().unwrap();
+    for item in items {
+        if super::moderation::is_frozen(Some(item)) || super::author::editable(item) {
+            continue;
+        }
+        let Some(qid) = item
+            .get("id")
+            .and_then(|v| v.as_s().ok())
+            .and_then(|v| Uuid::parse_str(v).ok())
+        else {
+            continue;
+        };
+        cache.put(qid, item.clone(), limit);
+    }
+}
+
+/// Drop the copy of `qid`, since it's changed.
+pub(super) fn forget(qid: &Uuid) {
+    CACHE.lock().unwrap().remove(qid);
+}
+
+/// Drop every copy, for when questions changed but it's not known which.
+pub(super) fn clear() {
+    *CACHE.lock().unwrap() = Cache::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(qid: &Uuid) -> Item {
+        HashMap::from_iter([(String::from("id"), AttributeValue::S(qid.to_string()))])
+    }
+
+    #[test]
+    fn keeps_recently_used() {
+        let mut cache = Cache::new();
+        let qids: Vec<_> = (0..3).map(|_| Uuid::new_v4()).collect();
+        cache.put(qids[0], item(&qids[0]), 2);
+        cache.put(qids[1], item(&qids[1]), 2);
+        // using the first makes the second the coldest
+        assert!(cache.get(&qids[0]).is_some());
+        cache.put(qids[2], item(&qids[2]), 2);
+        assert!(cache.get(&qids[1]).is_none());
+        assert!(cache.get(&qids[0]).is_some());
+        assert!(cache.get(&qids[2]).is_some());
+        assert_eq!(cache.by_use.len(), 2);
+
+        cache.remove(&qids[0]);
+        assert!(cache.get(&qids[0]).is_none());
+        assert_eq!(cache.entries.len(), 1);
+        assert_eq!(cache.by_use.len(), 1);
+    }
+}
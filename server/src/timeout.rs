@@ -0,0 +1,114 @@
+//! Per-route request timeouts.
+//!
+//! If a handler hasn't produced a response within its route's limit, its future is dropped, which
+//! also cancels any DynamoDB calls it has in flight, and the client gets a 504 along with the
+//! request id so the failure can be found in the logs.
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use http::StatusCode;
+use std::time::Duration;
+use tower_http::request_id::RequestId;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Limit {
+    pub(super) route: &'static str,
+    pub(super) after: Duration,
+}
+
+/// The configured limit for the route named `route`.
+pub(super) fn limit(route: &'static str) -> Limit {
+    let config = super::config::config();
+    Limit {
+        route,
+        after: config
+            .route_timeouts
+            .get(route)
+            .copied()
+            .unwrap_or(config.timeout),
+    }
+}
+
+pub(super) async fn enforce<B>(
+    State(limit): State<Limit>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(String::from);
+
+    match tokio::time::timeout(limit.after, next.run(req)).await {
+        Ok(res) => res,
+        Err(_) => {
+            super::metrics::incr(format!("timeout.{}", limit.route));
+            error!(
+                route = limit.route,
+                after = ?limit.after,
+                request_id,
+                "request timed out"
+            );
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                Json(serde_json::json!({
+                    "error": "timeout",
+                    "request_id": request_id,
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, SetRequestIdLayer};
+
+    #[tokio::test]
+    async fn times_out() {
+        let slow = Limit {
+            route: "test_slow",
+            after: Duration::from_millis(10),
+        };
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| tokio::time::sleep(Duration::from_secs(5)))
+                    .layer(axum::middleware::from_fn_with_state(slow, enforce)),
+            )
+            .route(
+                "/fast",
+                get(|| async {}).layer(axum::middleware::from_fn_with_state(slow, enforce)),
+            )
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+
+        let res = app
+            .clone()
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["request_id"].is_string());
+        assert_eq!(super::super::metrics::get("timeout.test_slow"), 1);
+
+        let res = app
+            .oneshot(Request::get("/fast").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -1,11 +1,11 @@
-use super::{Backend, Local};
+use super::{changes::Change, mine::Reason, Backend, Local};
 use aws_sdk_dynamodb::{
     error::UpdateItemError, model::AttributeValue, output::UpdateItemOutput, types::SdkError,
 };
-use axum::extract::{Path, State};
+use aws_smithy_types::Error;
+use axum::extract::{Path, Query, State};
 use http::StatusCode;
 use serde::Deserialize;
-use std::collections::HashMap;
 use uuid::Uuid;
 
 #[allow(unused_imports)]
@@ -42,34 +42,68 @@ impl Backend {
                 q.send().await
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { questions, .. } = &mut *local;
-
-                fn invert(q: &mut HashMap<&'static str, AttributeValue>, key: &'static str) {
-                    if let AttributeValue::Bool(b) = q[key] {
-                        q.insert(key, AttributeValue::Bool(!b));
-                    } else {
-                        unreachable!("all properties are bools");
-                    }
-                }
-
-                let q = questions
-                    .get_mut(qid)
-                    .expect("toggle property on unknown question ");
-                match property {
-                    Property::Hidden => invert(q, "hidden"),
-                    Property::Answered => invert(q, "answered"),
-                }
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                let Some(q) = questions.get_mut(qid) else {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("toggle property on unknown question")
+                            .build(),
+                    )));
+                };
+                let field = match property {
+                    Property::Hidden => "hidden",
+                    Property::Answered => "answered",
+                };
+                q.insert(field, AttributeValue::Bool(set));
+                journal.question(qid, q);
 
                 Ok(UpdateItemOutput::builder().build())
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let field = match property {
+                    Property::Hidden => "hidden",
+                    Property::Answered => "answered",
+                };
+                mongo
+                    .update(qid, mongodb::bson::doc! { "$set": { field: set } })
+                    .await
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                let field = match property {
+                    Property::Hidden => "hidden",
+                    Property::Answered => "answered",
+                };
+                redis.set(qid, &[(field, AttributeValue::Bool(set))]).await
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let field = match property {
+                    Property::Hidden => "hidden",
+                    Property::Answered => "answered",
+                };
+                sled.set(qid, &[(field, AttributeValue::Bool(set))]).await
+            }
         }
     }
 }
 
+/// Why a question is being hidden, for its asker (see [`super::mine`]).
+#[derive(Deserialize, Debug, Default)]
+pub(super) struct Why {
+    pub(super) reason: Option<Reason>,
+}
+
 pub(super) async fn toggle(
     Path((eid, secret, qid, property)): Path<(Uuid, String, Uuid, Property)>,
     State(dynamo): State<Backend>,
+    Query(why): Query<Why>,
     body: String,
 ) -> Result<(), StatusCode> {
     super::check_secret(&dynamo, &eid, &secret).await?;
@@ -83,9 +117,35 @@ pub(super) async fn toggle(
         }
     };
 
+    apply(&dynamo, &eid, qid, property, set, why.reason).await
+}
+
+/// Set `property` of `qid` in `eid`, and record that it changed.
+///
+/// Hiding a question also says why (or blanks out why it was hidden before, if there's no reason).
+pub(super) async fn apply(
+    dynamo: &Backend,
+    eid: &Uuid,
+    qid: Uuid,
+    property: Property,
+    set: bool,
+    reason: Option<Reason>,
+) -> Result<(), StatusCode> {
+    if let (Property::Hidden, true) = (property, set) {
+        // first, so that the asker never sees the question hidden for the wrong reason
+        if let Err(e) = dynamo.give_reason(&qid, reason).await {
+            error!(%qid, error = %e, "dynamodb request to give reason for hiding failed");
+            return Err(http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
     match dynamo.toggle(&qid, property, set).await {
         Ok(_) => {
             debug!(%eid, %qid, p = ?property, "toggled question property");
+            let change = match property {
+                Property::Hidden => Change::QuestionHidden { qid, set },
+                Property::Answered => Change::QuestionAnswered { qid, set },
+            };
+            dynamo.try_record(eid, change).await;
             Ok(())
         }
         Err(e) => {
@@ -99,14 +159,16 @@ pub(super) async fn toggle(
 mod tests {
     use super::*;
     use axum::Json;
+    use http::HeaderMap;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
         let secret = e["secret"].as_str().unwrap();
         let q = crate::ask::ask(
-            Path(eid.clone()),
+            Path(eid),
             State(backend.clone()),
+            http::HeaderMap::new(),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
@@ -140,80 +202,91 @@ mod tests {
 
         // only admin should see hidden
         super::toggle(
-            Path((
-                eid.clone(),
-                secret.to_string(),
-                qid_u.clone(),
-                Property::Hidden,
-            )),
+            Path((eid, secret.to_string(), qid_u, Property::Hidden)),
             State(backend.clone()),
+            Query(Default::default()),
             String::from("on"),
         )
         .await
         .unwrap();
         check(
             crate::list::list_all(
-                Path((eid.clone(), secret.to_string())),
+                Path((eid, secret.to_string())),
                 State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
             )
             .await
-            .1
+            .2
             .unwrap()
             .0,
             Some((true, false, 1)),
         );
         check(
-            crate::list::list(Path(eid.clone()), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
             None,
         );
 
         // should toggle back
         super::toggle(
-            Path((
-                eid.clone(),
-                secret.to_string(),
-                qid_u.clone(),
-                Property::Hidden,
-            )),
+            Path((eid, secret.to_string(), qid_u, Property::Hidden)),
             State(backend.clone()),
+            Query(Default::default()),
             String::from("off"),
         )
         .await
         .unwrap();
         // and should now show up as answered
         super::toggle(
-            Path((
-                eid.clone(),
-                secret.to_string(),
-                qid_u.clone(),
-                Property::Answered,
-            )),
+            Path((eid, secret.to_string(), qid_u, Property::Answered)),
             State(backend.clone()),
+            Query(Default::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        // setting it again leaves it set
+        super::toggle(
+            Path((eid, secret.to_string(), qid_u, Property::Answered)),
+            State(backend.clone()),
+            Query(Default::default()),
             String::from("on"),
         )
         .await
         .unwrap();
         check(
             crate::list::list_all(
-                Path((eid.clone(), secret.to_string())),
+                Path((eid, secret.to_string())),
                 State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
             )
             .await
-            .1
+            .2
             .unwrap()
             .0,
             Some((false, true, 1)),
         );
         check(
-            crate::list::list(Path(eid.clone()), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
             Some((false, true, 1)),
         );
 
@@ -230,4 +303,24 @@ mod tests {
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
 }
@@ -0,0 +1,122 @@
+//! Making hosts accept the instance's terms of service before they create events.
+//!
+//! Public instances may need hosts to agree to terms before they use them. If `TOS_VERSION` is
+//! set, creating an event takes the browser's token (the same one it pings and votes with) and
+//! the version of the terms its host accepted, and anything but the current version is turned away
+//! with a 428. The client then fetches the current terms from `GET /api/tos`, asks the host to
+//! accept them, and tries again. Changing `TOS_VERSION` therefore has every host accept the new
+//! terms the next time they create an event.
+//!
+//! There are no accounts, so the token is as close to who the host is as it gets. Who accepted
+//! which version when is stored (as JSON in `tos`) on the item of the event they created, and is
+//! logged.
+
+use axum::response::{AppendHeaders, Json};
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A host accepting a version of the terms of service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Acceptance {
+    /// The token of the host's browser.
+    pub(super) token: String,
+    pub(super) version: String,
+    /// When they created the event, in seconds since the epoch.
+    pub(super) at: u64,
+}
+
+/// How an acceptance is stored.
+pub(super) fn store(acceptance: &Acceptance) -> String {
+    serde_json::to_string(acceptance).expect("acceptances always serialize")
+}
+
+/// What creating an event with `token` and the `accepted` version amounts to, given the `current`
+/// version (if there are terms to accept at all).
+pub(super) fn check(
+    current: Option<&str>,
+    token: Option<&str>,
+    accepted: Option<&str>,
+) -> Result<Option<Acceptance>, StatusCode> {
+    let Some(current) = current else {
+        return Ok(None);
+    };
+    // tokens are random uuids, so there's no reason to accept anything big
+    let Some(token) = token.filter(|t| !t.is_empty() && t.len() <= 64) else {
+        warn!("event creation without a (valid) token");
+        super::metrics::incr("tos.rejected");
+        return Err(StatusCode::PRECONDITION_REQUIRED);
+    };
+    if accepted != Some(current) {
+        info!(
+            token,
+            accepted, current, "terms of service not (yet) accepted"
+        );
+        super::metrics::incr("tos.rejected");
+        return Err(StatusCode::PRECONDITION_REQUIRED);
+    }
+    Ok(Some(Acceptance {
+        token: token.to_string(),
+        version: current.to_string(),
+        at: super::clock::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    }))
+}
+
+/// The terms of service hosts have to accept, if any.
+pub(super) async fn tos() -> (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Result<Json<serde_json::Value>, StatusCode>,
+) {
+    let config = super::config::config();
+    // the version only changes with a deploy, but hosts shouldn't be stuck with the old one
+    let cache = AppendHeaders([(header::CACHE_CONTROL, "max-age=300")]);
+    match &config.tos_version {
+        Some(version) => (
+            cache,
+            Ok(Json(serde_json::json!({
+                "version": version,
+                "url": config.tos_url,
+            }))),
+        ),
+        None => (cache, Err(StatusCode::NOT_FOUND)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate() {
+        assert_eq!(check(None, None, None), Ok(None));
+        assert_eq!(
+            check(Some("2"), None, Some("2")),
+            Err(StatusCode::PRECONDITION_REQUIRED)
+        );
+        assert_eq!(
+            check(Some("2"), Some(&"x".repeat(65)), Some("2")),
+            Err(StatusCode::PRECONDITION_REQUIRED)
+        );
+        // accepting an earlier version doesn't count
+        for accepted in [None, Some("1")] {
+            assert_eq!(
+                check(Some("2"), Some("host"), accepted),
+                Err(StatusCode::PRECONDITION_REQUIRED)
+            );
+        }
+        let a = check(Some("2"), Some("host"), Some("2")).unwrap().unwrap();
+        assert_eq!(a.token, "host");
+        assert_eq!(a.version, "2");
+        assert!(a.at > 0);
+        assert!(super::super::metrics::get("tos.rejected") >= 4);
+    }
+}
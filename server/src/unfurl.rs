@@ -0,0 +1,328 @@
+//! Link previews for events and questions.
+//!
+//! Chat apps and social sites show a card for the links people paste, made from the Open Graph and
+//! Twitter meta tags of the page behind the link. The client's pages are all the same `index.html`
+//! until its script runs, which the apps don't do, and question permalinks only differ in their
+//! fragment, which never reaches the server. So links meant for sharing go to small pages the
+//! server renders itself: `GET /share/event/:eid` says how many questions the event has and how
+//! many votes they've had, and `GET /share/q/:qid` quotes (the start of) the question and says how
+//! many votes it has. Both send browsers straight on to the event or the question's
+//! [permalink](super::permalink), and `/api/q/:qid` says where a question's share page is.
+//!
+//! Hidden questions don't have share pages, and neither do frozen events or their questions. The
+//! share pages of events kept to some networks (see [`super::networks`]), and of their questions,
+//! are refused to everyone else like any other request for the event, and since previews get
+//! passed around, question pages of those events say nothing about the question even then. Pages are cached for a few minutes,
+//! and are never indexed.
+
+use super::{archive::escape, hot::Question, Backend};
+use axum::extract::{Path, State};
+use axum::response::{AppendHeaders, Html};
+use http::{
+    header::{self, HeaderName},
+    StatusCode,
+};
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Quote at most this many characters of a question.
+const SNIPPET: usize = 200;
+
+/// Where question `qid`'s share page is.
+pub(super) fn url(qid: &Uuid) -> String {
+    let base = super::config::config().public_url.as_deref().unwrap_or("");
+    format!("{base}/share/q/{qid}")
+}
+
+/// What a link preview shows.
+struct Card {
+    title: String,
+    description: String,
+    /// Where browsers go from the share page.
+    to: String,
+}
+
+/// The start of `text`, on one line.
+fn snippet(text: &str) -> String {
+    let words: Vec<_> = text.split_whitespace().collect();
+    let text = words.join(" ");
+    if text.chars().count() <= SNIPPET {
+        return text;
+    }
+    let mut cut: String = text.chars().take(SNIPPET).collect();
+    cut.truncate(cut.trim_end().len());
+    cut.push('…');
+    cut
+}
+
+fn plural(n: usize, what: &str) -> String {
+    if n == 1 {
+        format!("{n} {what}")
+    } else {
+        format!("{n} {what}s")
+    }
+}
+
+fn render(card: &Card) -> String {
+    let config = super::config::config();
+    let title = escape(&card.title);
+    let description = escape(&card.description);
+    let to = escape(&card.to);
+    let mut out = String::from(
+        "<!DOCTYPE html>\n\
+         <html lang=\"en\">\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n",
+    );
+    let _ = write!(
+        out,
+        "<meta http-equiv=\"refresh\" content=\"0; url={to}\">\n\
+         <meta property=\"og:type\" content=\"website\">\n\
+         <meta property=\"og:title\" content=\"{title}\">\n\
+         <meta property=\"og:description\" content=\"{description}\">\n"
+    );
+    // previews need full URLs, which only the configured one gives
+    if config.public_url.is_some() {
+        let _ = writeln!(out, "<meta property=\"og:url\" content=\"{to}\">");
+    }
+    if let Some(name) = &config.instance_name {
+        let _ = writeln!(
+            out,
+            "<meta property=\"og:site_name\" content=\"{}\">",
+            escape(name)
+        );
+    }
+    let _ = write!(
+        out,
+        "<meta name=\"twitter:card\" content=\"summary\">\n\
+         <meta name=\"twitter:title\" content=\"{title}\">\n\
+         <meta name=\"twitter:description\" content=\"{description}\">\n\
+         <title>{title}</title>\n\
+         </head>\n\
+         <body>\n\
+         <p><a href=\"{to}\">{description}</a></p>\n\
+         </body>\n\
+         </html>\n"
+    );
+    out
+}
+
+/// The questions attendees of `eid` can see.
+async fn visible(dynamo: &Backend, eid: &Uuid) -> Result<Vec<(Uuid, Question)>, StatusCode> {
+    let qs = dynamo.list(eid, false).await.map_err(|e| {
+        error!(%eid, error = %e, "dynamodb request for questions to preview failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(qs
+        .items()
+        .map(|qs| {
+            qs.iter()
+                .filter_map(|doc| super::list::parse(eid, doc))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn title(what: &str) -> String {
+    match &super::config::config().instance_name {
+        Some(name) => format!("{what} on {name}"),
+        None => what.to_string(),
+    }
+}
+
+type Page = (
+    AppendHeaders<[(HeaderName, &'static str); 1]>,
+    Result<Html<String>, StatusCode>,
+);
+
+fn page(card: Result<Card, StatusCode>) -> Page {
+    let cache = |c| AppendHeaders([(header::CACHE_CONTROL, c)]);
+    match card {
+        Ok(card) => (cache("max-age=300"), Ok(Html(render(&card)))),
+        // events are unlikely to re-appear with the same uuid
+        Err(StatusCode::NOT_FOUND) => (cache("max-age=3600"), Err(StatusCode::NOT_FOUND)),
+        // the event may well be unfrozen after review
+        Err(StatusCode::FORBIDDEN) => (cache("max-age=60"), Err(StatusCode::FORBIDDEN)),
+        Err(status) => (cache("no-cache"), Err(status)),
+    }
+}
+
+async fn event_card(dynamo: &Backend, eid: &Uuid) -> Result<Card, StatusCode> {
+    let meta = super::get_meta(dynamo, eid).await?;
+    if meta.frozen() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let base = super::config::config().public_url.as_deref().unwrap_or("");
+    let to = format!("{base}/event/{eid}");
+    let questions = visible(dynamo, eid).await?;
+    let votes: usize = questions.iter().map(|(_, q)| q.votes).sum();
+    let answered = questions.iter().filter(|(_, q)| q.answered).count();
+    Ok(Card {
+        title: title("Q&A"),
+        description: format!(
+            "{} with {} so far, {answered} answered.",
+            plural(questions.len(), "question"),
+            plural(votes, "vote"),
+        ),
+        to,
+    })
+}
+
+pub(super) async fn event(Path(eid): Path<Uuid>, State(dynamo): State<Backend>) -> Page {
+    page(event_card(&dynamo, &eid).await)
+}
+
+async fn question_card(dynamo: &Backend, qid: &Uuid) -> Result<Card, StatusCode> {
+    let q = super::permalink::lookup(dynamo, qid).await.map_err(|e| {
+        error!(%qid, error = %e, "dynamodb request for question to preview failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let q = q.as_ref();
+    if super::moderation::is_frozen(q) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let q = q
+        .filter(|q| q.get("hidden").and_then(|v| v.as_bool().ok()) != Some(&true))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let eid = q
+        .get("eid")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|v| Uuid::parse_str(v).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let text = q
+        .get("text")
+        .and_then(|v| v.as_s().ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let meta = super::get_meta(dynamo, &eid).await?;
+    if meta.frozen() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let to = super::permalink::url(&eid, qid);
+    if super::moderation::restricted(&meta.moderation).is_some() {
+        return Ok(Card {
+            title: title("A question"),
+            description: String::from("A question asked in a Q&A."),
+            to,
+        });
+    }
+    // hidden since it was fetched
+    let votes = visible(dynamo, &eid)
+        .await?
+        .into_iter()
+        .find(|(id, _)| id == qid)
+        .map(|(_, q)| q.votes)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Card {
+        title: title("A question"),
+        description: format!("{} ({})", snippet(text), plural(votes, "vote")),
+        to,
+    })
+}
+
+pub(super) async fn question(Path(qid): Path<Uuid>, State(dynamo): State<Backend>) -> Page {
+    page(question_card(&dynamo, &qid).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::{Property, Why};
+    use axum::extract::Query;
+    use axum::Json;
+
+    #[test]
+    fn snippets() {
+        assert_eq!(snippet("what\nabout  this?"), "what about this?");
+        let long = "a ".repeat(SNIPPET);
+        let cut = snippet(&long);
+        assert!(cut.ends_with("a…"));
+        assert_eq!(cut.chars().count(), SNIPPET);
+    }
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        let secret = e["secret"].as_str().unwrap().to_string();
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "is <b>this</b> \"on\"?".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let qid = Uuid::parse_str(q["id"].as_str().unwrap()).unwrap();
+
+        let (AppendHeaders(headers), res) = question(Path(qid), State(backend.clone())).await;
+        assert_eq!(headers[0].1, "max-age=300");
+        let Html(html) = res.unwrap();
+        assert!(html.contains(
+            "<meta property=\"og:description\" \
+             content=\"is &lt;b&gt;this&lt;/b&gt; &quot;on&quot;? (1 vote)\">"
+        ));
+        assert!(html.contains("<meta name=\"twitter:card\" content=\"summary\">"));
+        assert!(html.contains(&format!(
+            "url={}\"",
+            escape(&crate::permalink::url(&eid, &qid))
+        )));
+
+        let Html(html) = event(Path(eid), State(backend.clone())).await.1.unwrap();
+        assert!(html.contains("1 question with 1 vote so far, 0 answered."));
+
+        crate::toggle::toggle(
+            Path((eid, secret, qid, Property::Hidden)),
+            State(backend.clone()),
+            Query(Why::default()),
+            String::from("on"),
+        )
+        .await
+        .unwrap();
+        let (_, res) = question(Path(qid), State(backend.clone())).await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_FOUND);
+        let Html(html) = event(Path(eid), State(backend.clone())).await.1.unwrap();
+        assert!(html.contains("0 questions with 0 votes so far"));
+        let (_, res) = event(Path(Uuid::new_v4()), State(backend.clone())).await;
+        assert_eq!(res.unwrap_err(), StatusCode::NOT_FOUND);
+
+        backend.delete(&eid).await;
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+}
@@ -1,20 +1,30 @@
-use super::{Backend, Local};
+use super::{changes::Change, hll::Sketch, Backend, Local};
 use aws_sdk_dynamodb::{
-    error::UpdateItemError,
+    error::{ConditionalCheckFailedException, UpdateItemError, UpdateItemErrorKind},
     model::{AttributeValue, ReturnValue},
     output::UpdateItemOutput,
     types::SdkError,
 };
+use aws_smithy_types::Error;
 use axum::extract::{Path, State};
 use axum::response::Json;
-use http::StatusCode;
+use http::{header::HeaderName, HeaderMap, StatusCode};
 use serde::Deserialize;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, trace, warn};
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+/// The header that says who's voting, as the random token the client also pings with.
+///
+/// Votes aren't deduplicated by it, but each question keeps a HyperLogLog sketch of the tokens it
+/// was upvoted with (as `voters`), so that hosts can see roughly how many different people voted
+/// for it. The sketch is read back along with the vote, and only written (conditionally, so
+/// concurrent voters don't overwrite each other) if the new voter changed it.
+pub(super) const VOTER: HeaderName = HeaderName::from_static("x-voter");
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub(super) enum UpDown {
     Up,
@@ -22,6 +32,11 @@ pub(super) enum UpDown {
 }
 
 impl Backend {
+    /// Vote for `qid`, unless its event has been frozen or it has been merged into another question.
+    ///
+    /// DynamoDB (and the in-memory backend) refuse to vote for frozen or merged questions with a
+    /// `ConditionalCheckFailedException`. The other backends leave the votes as they are, and
+    /// return the question with `frozen` or `merged_into` set.
     pub(super) async fn vote(
         &self,
         qid: &Uuid,
@@ -39,43 +54,271 @@ impl Backend {
                     UpDown::Down => upd.update_expression("SET votes = votes - :one"),
                 };
                 let upd = upd.expression_attribute_values(":one", AttributeValue::N(1.to_string()));
+                // see Backend::record (so with alternator, frozen and merged questions can still be
+                // voted for)
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression(
+                        "(attribute_not_exists(frozen) OR frozen = :no) \
+                         AND attribute_not_exists(merged_into)",
+                    )
+                    .expression_attribute_values(":no", AttributeValue::Bool(false))
+                };
 
                 upd.return_values(ReturnValue::AllNew).send().await
             }
             Self::Local(local) => {
-                let mut local = local.lock().unwrap();
-                let Local { questions, .. } = &mut *local;
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
 
                 let ret = UpdateItemOutput::builder();
-                let q = questions
-                    .get_mut(qid)
-                    .expect("voting for non-existing question");
+                let Some(q) = questions.get_mut(qid) else {
+                    // like DynamoDB, which has no votes to add to
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("voting for non-existing question")
+                            .build(),
+                    )));
+                };
+                let refused = if q.get("frozen").and_then(|f| f.as_bool().ok()) == Some(&true) {
+                    Some("voting for frozen question")
+                } else if q.contains_key("merged_into") {
+                    Some("voting for merged question")
+                } else {
+                    None
+                };
+                if let Some(refused) = refused {
+                    return Err(super::mint_service_error(UpdateItemError::new(
+                        UpdateItemErrorKind::ConditionalCheckFailedException(
+                            ConditionalCheckFailedException::builder()
+                                .message(refused)
+                                .build(),
+                        ),
+                        Error::builder()
+                            .code("ConditionalCheckFailedException")
+                            .build(),
+                    )));
+                }
                 if let Some(AttributeValue::N(n)) = q.get_mut("votes") {
                     let real_n = n.parse::<usize>().expect("votes values are numbers");
                     let new_n = match direction {
                         UpDown::Up => real_n + 1,
-                        UpDown::Down => real_n - 1,
+                        UpDown::Down => real_n.saturating_sub(1),
                     };
                     *n = new_n.to_string();
                 } else {
                     unreachable!("no votes for question");
                 }
+                journal.question(qid, q);
                 let ret = ret.set_attributes(Some(
                     q.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
                 ));
                 Ok(ret.build())
             }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => {
+                let by: i64 = match direction {
+                    UpDown::Up => 1,
+                    UpDown::Down => -1,
+                };
+                // an update pipeline, so that frozen and merged questions can be left as they are
+                // (a missing field sorts before null, so only merged questions are "greater")
+                let refused = mongodb::bson::doc! {
+                    "$or": [{ "$eq": ["$frozen", true] }, { "$gt": ["$merged_into", null] }]
+                };
+                let votes = mongodb::bson::doc! {
+                    "$cond": [refused, "$votes", { "$add": ["$votes", by] }]
+                };
+                mongo
+                    .update(
+                        qid,
+                        vec![mongodb::bson::doc! { "$set": { "votes": votes } }],
+                    )
+                    .await
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => {
+                let by = match direction {
+                    UpDown::Up => 1,
+                    UpDown::Down => -1,
+                };
+                redis.vote(qid, by).await
+            }
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => {
+                let by = match direction {
+                    UpDown::Up => 1,
+                    UpDown::Down => -1,
+                };
+                sled.vote(qid, by).await
+            }
         }
     }
 }
 
+impl Backend {
+    /// Replace the question's voter sketch with `new`, unless it's no longer `old`.
+    ///
+    /// Returns whether the sketch was replaced.
+    pub(super) async fn set_voters(
+        &self,
+        qid: &Uuid,
+        old: Option<&str>,
+        new: &str,
+    ) -> Result<bool, aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("questions")
+                    .key("id", AttributeValue::S(qid.to_string()))
+                    .update_expression("SET voters = :new")
+                    .expression_attribute_values(":new", AttributeValue::S(new.to_string()));
+                // see Backend::record; a lost voter just makes the estimate a little lower
+                let upd = if super::config::config().alternator {
+                    upd
+                } else if let Some(old) = old {
+                    upd.condition_expression("voters = :old")
+                        .expression_attribute_values(":old", AttributeValue::S(old.to_string()))
+                } else {
+                    upd.condition_expression(
+                        "attribute_exists(id) AND attribute_not_exists(voters)",
+                    )
+                };
+                match upd.send().await {
+                    Ok(_) => Ok(true),
+                    Err(SdkError::ServiceError { err, .. })
+                        if err.is_conditional_check_failed_exception() =>
+                    {
+                        Ok(false)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local {
+                    questions, journal, ..
+                } = &mut *local;
+
+                let Some(q) = questions.get_mut(qid) else {
+                    return Ok(false);
+                };
+                if q.get("voters")
+                    .and_then(|v| v.as_s().ok())
+                    .map(String::as_str)
+                    != old
+                {
+                    return Ok(false);
+                }
+                q.insert("voters", AttributeValue::S(new.to_string()));
+                journal.question(qid, q);
+                Ok(true)
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo
+                .set_voters(qid, old, new)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis
+                .set_voters(qid, old, new)
+                .await
+                .map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled
+                .set_voters(qid, old, new)
+                .map_err(super::mint_unhandled),
+        }
+    }
+}
+
+/// Count the holder of `token` among the voters of `qid`, whose stored sketch is `stored`.
+async fn count_voter(dynamo: &Backend, qid: &Uuid, stored: Option<&str>, token: &str) {
+    let mut sketch = stored.and_then(Sketch::stored).unwrap_or_default();
+    if !sketch.insert(token) {
+        return;
+    }
+    match dynamo.set_voters(qid, stored, &sketch.to_stored()).await {
+        Ok(true) => {}
+        Ok(false) => debug!(%qid, "voter sketch changed concurrently"),
+        Err(e) => warn!(%qid, error = %e, "failed to update voter sketch"),
+    }
+}
+
+/// Whether the question `q` has been merged into another one (see [`super::merge`]).
+fn is_merged(q: Option<&HashMap<String, AttributeValue>>) -> bool {
+    q.is_some_and(|q| q.contains_key("merged_into"))
+}
+
 pub(super) async fn vote(
     Path((qid, direction)): Path<(Uuid, UpDown)>,
     State(dynamo): State<Backend>,
+    headers: HeaderMap,
+    super::networks::Client(client): super::networks::Client,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     match dynamo.vote(&qid, direction).await {
+        Ok(v) if super::moderation::is_frozen(v.attributes()) => {
+            warn!(%qid, "vote for frozen question");
+            Err(StatusCode::FORBIDDEN)
+        }
+        Ok(v) if is_merged(v.attributes()) => {
+            warn!(%qid, "vote for merged question");
+            Err(StatusCode::CONFLICT)
+        }
+        Err(SdkError::ServiceError { ref err, .. })
+            if err.is_conditional_check_failed_exception() =>
+        {
+            // the condition doesn't say which of the two it was
+            match super::permalink::lookup(&dynamo, &qid).await {
+                Ok(q) if is_merged(q.as_ref()) => {
+                    warn!(%qid, "vote for merged question");
+                    Err(StatusCode::CONFLICT)
+                }
+                _ => {
+                    warn!(%qid, "vote for frozen question");
+                    Err(StatusCode::FORBIDDEN)
+                }
+            }
+        }
         Ok(v) => {
             debug!(%qid, "voted for question");
+            let voter = headers
+                .get(VOTER)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty() && v.len() <= 64);
+            if let Some(voter) = voter {
+                super::experiment::outcome(voter, "votes");
+            }
+            if let (UpDown::Up, Some(voter)) = (direction, voter) {
+                let stored = v
+                    .attributes()
+                    .and_then(|a| a.get("voters"))
+                    .and_then(|v| v.as_s().ok());
+                count_voter(&dynamo, &qid, stored.map(String::as_str), voter).await;
+            }
+            let eid = v
+                .attributes()
+                .and_then(|a| a.get("eid"))
+                .and_then(|v| v.as_s().ok())
+                .and_then(|v| Uuid::parse_str(v).ok());
+            if let Some(eid) = eid {
+                let marks = super::anomaly::marks(&eid, client, voter);
+                dynamo
+                    .try_record_with(&eid, Change::VoteCast { qid, direction }, marks)
+                    .await;
+                if direction == UpDown::Up {
+                    super::surge::upvoted(&eid, &qid);
+                }
+                // votes don't say which event they're for until they've been made
+                super::budget::spend(&eid, super::budget::cost("vote"));
+            } else {
+                error!(%qid, "voted-for question has no event");
+            }
             let new_count = v
                 .attributes()
                 .and_then(|a| a.get("votes"))
@@ -93,14 +336,17 @@ pub(super) async fn vote(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::extract::Query;
+    use http::HeaderMap;
 
     async fn inner(backend: Backend) {
-        let e = crate::new::new(State(backend.clone())).await.unwrap();
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
         let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
-        let _secret = e["secret"].as_str().unwrap();
+        let secret = e["secret"].as_str().unwrap();
         let q1 = crate::ask::ask(
-            Path(eid.clone()),
+            Path(eid),
             State(backend.clone()),
+            http::HeaderMap::new(),
             Json(crate::ask::Question {
                 body: "hello world".into(),
                 asker: None,
@@ -110,8 +356,9 @@ mod tests {
         .unwrap();
         let qid1 = Uuid::parse_str(q1["id"].as_str().unwrap()).unwrap();
         let q2 = crate::ask::ask(
-            Path(eid.clone()),
+            Path(eid),
             State(backend.clone()),
+            http::HeaderMap::new(),
             Json(crate::ask::Question {
                 body: "hello moon".into(),
                 asker: Some("person".into()),
@@ -129,33 +376,95 @@ mod tests {
             }
         };
 
-        super::vote(Path((qid2.clone(), UpDown::Up)), State(backend.clone()))
-            .await
-            .unwrap();
+        super::vote(
+            Path((qid2, UpDown::Up)),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
         check(
-            crate::list::list(Path(eid.clone()), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                axum::extract::Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
             &[(&qid2, 2), (&qid1, 1)],
         );
 
-        super::vote(Path((qid1.clone(), UpDown::Up)), State(backend.clone()))
-            .await
-            .unwrap();
-        super::vote(Path((qid2.clone(), UpDown::Down)), State(backend.clone()))
-            .await
-            .unwrap();
+        super::vote(
+            Path((qid1, UpDown::Up)),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
+        super::vote(
+            Path((qid2, UpDown::Down)),
+            State(backend.clone()),
+            HeaderMap::new(),
+            crate::networks::Client(None),
+        )
+        .await
+        .unwrap();
         check(
-            crate::list::list(Path(eid.clone()), State(backend.clone()))
-                .await
-                .1
-                .unwrap()
-                .0,
+            crate::list::list(
+                Path(eid),
+                State(backend.clone()),
+                axum::extract::Query(Default::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .2
+            .unwrap()
+            .0,
             &[(&qid1, 2), (&qid2, 1)],
         );
 
+        // hosts see roughly how many different people voted
+        for voter in ["a", "b", "a", "c"] {
+            super::vote(
+                Path((qid2, UpDown::Up)),
+                State(backend.clone()),
+                HeaderMap::from_iter([(VOTER, voter.parse().unwrap())]),
+                crate::networks::Client(None),
+            )
+            .await
+            .unwrap();
+        }
+        let qs = crate::list::list_all(
+            Path((eid, secret.to_string())),
+            State(backend.clone()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap()
+        .0;
+        assert_eq!(qs[0]["qid"], qid2.to_string());
+        assert_eq!(qs[0]["votes"], 5);
+        assert_eq!(qs[0]["voters"], 3);
+        assert_eq!(qs[1]["voters"], 0);
+        let qs = crate::list::list(
+            Path(eid),
+            State(backend.clone()),
+            axum::extract::Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .2
+        .unwrap()
+        .0;
+        assert_eq!(qs[0].get("voters"), None, "guests don't see voter counts");
+
         backend.delete(&eid).await;
     }
 
@@ -169,4 +478,24 @@ mod tests {
     async fn dynamodb() {
         inner(Backend::dynamo().await).await;
     }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
 }
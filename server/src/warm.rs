@@ -0,0 +1,278 @@
+//! Getting an event ready for a crowd before it shows up.
+//!
+//! Before a big talk, operators (or their deployment automation) can `POST /api/admin/warm/:eid`.
+//! That reads the event, loads its question list into the [hot](super::hot) set, and makes a
+//! canary write (`warmed` on the event's item) that it then reads back, timing each step. The
+//! answer says how that went, and is a 503 unless every step worked and the event isn't frozen, so
+//! a script can simply fail on it.
+//!
+//! Only the instance that served the request ends up with the event hot. On Lambda, other
+//! instances load it the first time they're asked for it, like they always would, so warming is
+//! mostly about finding out that the database and the event are in order while there's still time
+//! to do something about it.
+
+use super::{admin::Admin, Backend, Local};
+use aws_sdk_dynamodb::{error::UpdateItemError, model::AttributeValue};
+use aws_smithy_types::Error;
+use axum::extract::{Path, Query, State};
+use axum::response::Json;
+use http::{HeaderMap, StatusCode};
+use serde::Serialize;
+use std::time::{Instant, SystemTime};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+impl Backend {
+    /// Note on `eid`'s item that it was warmed up `at`, and read the note back.
+    pub(super) async fn canary(
+        &self,
+        eid: &Uuid,
+        at: u64,
+    ) -> Result<Option<u64>, aws_sdk_dynamodb::Error> {
+        match self {
+            Self::Dynamo(dynamo) => {
+                let upd = dynamo
+                    .update_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()))
+                    .update_expression("SET warmed = :at")
+                    .expression_attribute_values(":at", AttributeValue::N(at.to_string()));
+                // see Backend::record
+                let upd = if super::config::config().alternator {
+                    upd
+                } else {
+                    upd.condition_expression("attribute_exists(id)")
+                };
+                upd.send().await?;
+                let item = dynamo
+                    .get_item()
+                    .table_name("events")
+                    .key("id", AttributeValue::S(eid.to_string()))
+                    .projection_expression("warmed")
+                    .consistent_read(true)
+                    .send()
+                    .await?;
+                Ok(item
+                    .item()
+                    .and_then(|e| e.get("warmed"))
+                    .and_then(|w| w.as_n().ok())
+                    .and_then(|w| w.parse().ok()))
+            }
+            Self::Local(local) => {
+                let mut local = local.write().unwrap();
+                let Local { events, warmed, .. } = &mut *local;
+
+                if !events.contains_key(eid) {
+                    return Err(super::mint_service_error(UpdateItemError::generic(
+                        Error::builder()
+                            .code("ValidationException")
+                            .message("warming event that doesn't exist")
+                            .build(),
+                    ))
+                    .into());
+                }
+                warmed.insert(*eid, at);
+                Ok(warmed.get(eid).copied())
+            }
+            #[cfg(feature = "mongo")]
+            Self::Mongo(mongo) => mongo.canary(eid, at).await.map_err(super::mint_unhandled),
+            #[cfg(feature = "redis")]
+            Self::Redis(redis) => redis.canary(eid, at).await.map_err(super::mint_unhandled),
+            #[cfg(feature = "sled")]
+            Self::Sled(sled) => sled.canary(eid, at).map_err(super::mint_unhandled),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Step {
+    step: &'static str,
+    ok: bool,
+    ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Step {
+    fn timed(step: &'static str, start: Instant, error: Option<String>) -> Self {
+        if let Some(error) = &error {
+            warn!(step, error, "warm-up step failed");
+        }
+        Self {
+            step,
+            ok: error.is_none(),
+            ms: start.elapsed().as_millis() as u64,
+            error,
+        }
+    }
+}
+
+pub(super) async fn warm(
+    _: Admin,
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let mut steps = Vec::new();
+    let report = |steps: Vec<Step>, event: serde_json::Value| {
+        let ready = steps.iter().all(|s| s.ok) && event["frozen"] != true;
+        info!(%eid, ready, "warmed up event");
+        let status = if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Ok((
+            status,
+            Json(serde_json::json!({
+                "ready": ready,
+                "event": event,
+                "hot": super::hot::contains(&eid),
+                "steps": steps,
+            })),
+        ))
+    };
+
+    let start = Instant::now();
+    let meta = match super::get_meta(&dynamo, &eid).await {
+        Ok(meta) => meta,
+        Err(StatusCode::NOT_FOUND) => return Err(StatusCode::NOT_FOUND),
+        Err(status) => {
+            steps.push(Step::timed("read", start, Some(status.to_string())));
+            return report(steps, serde_json::Value::Null);
+        }
+    };
+    steps.push(Step::timed("read", start, None));
+
+    // listing it the way its host would puts it in the hot set
+    let start = Instant::now();
+    let (_, _, listed) = super::list::list_all(
+        Path((eid, meta.secret.clone())),
+        State(dynamo.clone()),
+        Query(Default::default()),
+        HeaderMap::new(),
+    )
+    .await;
+    let questions = listed
+        .as_ref()
+        .ok()
+        .and_then(|Json(qs)| qs.as_array())
+        .map(Vec::len);
+    steps.push(Step::timed(
+        "list",
+        start,
+        listed.err().map(|status| status.to_string()),
+    ));
+
+    let start = Instant::now();
+    let at = super::clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let canary = match dynamo.canary(&eid, at).await {
+        Ok(Some(read)) if read == at => None,
+        Ok(read) => Some(format!("wrote {at}, but read back {read:?}")),
+        Err(e) => Some(e.to_string()),
+    };
+    steps.push(Step::timed("canary", start, canary));
+
+    report(
+        steps,
+        serde_json::json!({
+            "version": meta.version,
+            "questions": questions,
+            "frozen": meta.frozen(),
+            "held": meta.held(),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inner(backend: Backend) {
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+        crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "hello world".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        crate::hot::forget(&eid);
+
+        let (status, Json(r)) = super::warm(Admin, Path(eid), State(backend.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK, "{r}");
+        assert_eq!(r["ready"], true);
+        assert_eq!(r["event"]["questions"], 1);
+        assert_eq!(r["event"]["frozen"], false);
+        let steps: Vec<_> = r["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["step"].as_str().unwrap(), s["ok"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(steps, [("read", true), ("list", true), ("canary", true)]);
+        assert_eq!(r["hot"], true);
+
+        backend.delete(&eid).await;
+        assert_eq!(
+            super::warm(Admin, Path(eid), State(backend.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn local() {
+        inner(Backend::local().await).await;
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn dynamodb() {
+        inner(Backend::dynamo().await).await;
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    #[ignore]
+    async fn mongodb() {
+        inner(Backend::mongo().await).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore]
+    async fn redis() {
+        inner(Backend::redis().await).await;
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn sled() {
+        inner(Backend::sled().await).await;
+    }
+
+    #[tokio::test]
+    async fn unreachable() {
+        let eid = Uuid::new_v4();
+        let (status, Json(r)) = super::warm(Admin, Path(eid), State(crate::golden::unreachable()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(r["ready"], false);
+        assert_eq!(r["steps"][0]["step"], "read");
+        assert_eq!(r["steps"][0]["ok"], false);
+    }
+}
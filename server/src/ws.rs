@@ -0,0 +1,338 @@
+//! Pushing an event's changes to clients over a WebSocket, instead of making them poll.
+//!
+//! `GET /api/event/:eid/ws` upgrades to a WebSocket that gets every change to the event as a text
+//! message, as it happens, in the same JSON the change feed has for it: new questions, votes, and
+//! questions being answered or hidden (see [`super::bus`]). The first message is just
+//! `{"seq": <n>}`, the sequence number of the latest change before the socket was opened, so
+//! clients know where to fetch anything they missed from `/api/event/:eid/changes`. Messages that
+//! say `{"resync": true}` mean some changes couldn't be passed on, and it's time to fetch the
+//! change feed again. Hosts who add `?secret=<secret>` are also told who's moderating which
+//! question, and which questions are [suddenly popular](super::surge). Clients don't have anything to say over the socket, so anything they send other than
+//! pings and closes is ignored.
+//!
+//! The handshake and framing are done here rather than with a WebSocket library, since pushing
+//! small text messages takes only a small part of the protocol. Sockets need a connection that
+//! stays open, so they only work where the server is run directly rather than as a Lambda, where
+//! the upgrade is refused with a 426 and clients should keep polling. Sockets to frozen events
+//! aren't opened either, and ones left open are pinged every `PING` seconds so that proxies don't
+//! think they're idle.
+
+use super::{
+    bus::{Listen, Notice},
+    Backend,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use http::{header, request::Parts, HeaderMap, StatusCode};
+use hyper::upgrade::OnUpgrade;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{broadcast::error::RecvError, mpsc},
+};
+use uuid::Uuid;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How often open sockets are pinged, in seconds.
+const PING: u64 = 30;
+/// The largest message clients may send, in bytes.
+const MAX_INCOMING: u64 = 1024;
+/// What the handshake appends to the client's key (from RFC 6455).
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING_FRAME: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// The connection's upgrade, if it can be upgraded.
+pub(super) struct Upgrade(Option<OnUpgrade>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Upgrade
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Upgrade(parts.extensions.remove::<OnUpgrade>()))
+    }
+}
+
+/// The `Sec-WebSocket-Accept` for the client's `Sec-WebSocket-Key`.
+fn accept(key: &[u8]) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key);
+    sha1.update(GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(sha1.digest().bytes())
+}
+
+/// A frame with `payload`, as the server sends it (unmasked, and never fragmented).
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 10);
+    out.push(0x80 | opcode);
+    match payload.len() {
+        n if n < 126 => out.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            out.push(126);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            out.push(127);
+            out.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Read the next frame a client sends, and give back its opcode and (unmasked) payload.
+async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    r.read_exact(&mut head).await?;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7F {
+        126 => u64::from(r.read_u16().await?),
+        127 => r.read_u64().await?,
+        n => u64::from(n),
+    };
+    if len > MAX_INCOMING {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        r.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0; len as usize];
+    r.read_exact(&mut payload).await?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+/// Push `eid`'s changes down `socket` until either side goes away.
+async fn push<S>(
+    eid: Uuid,
+    socket: S,
+    seq: u64,
+    host: bool,
+    mut changes: tokio::sync::broadcast::Receiver<Notice>,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut r, mut w) = tokio::io::split(socket);
+    // frames are read on their own, since reads can't be cancelled halfway through one
+    let (control, mut controls) = mpsc::channel(4);
+    tokio::spawn(async move {
+        loop {
+            match read_frame(&mut r).await {
+                Ok((PING_FRAME, payload)) => {
+                    if control.send(frame(PONG, &payload)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok((CLOSE, _)) | Err(_) => {
+                    let _ = control.send(frame(CLOSE, &[])).await;
+                    return;
+                }
+                Ok(_) => {}
+            }
+        }
+    });
+
+    let hello = serde_json::json!({ "seq": seq }).to_string();
+    if w.write_all(&frame(TEXT, hello.as_bytes())).await.is_err() {
+        return;
+    }
+    let mut ping = tokio::time::interval(Duration::from_secs(PING));
+    ping.tick().await;
+    loop {
+        let out = tokio::select! {
+            notice = changes.recv() => match notice {
+                Ok(notice) if notice.hosts_only && !host => continue,
+                Ok(notice) => frame(TEXT, notice.text.as_bytes()),
+                Err(RecvError::Lagged(n)) => {
+                    debug!(%eid, n, "websocket fell behind");
+                    frame(TEXT, super::bus::RESYNC.as_bytes())
+                }
+                Err(RecvError::Closed) => return,
+            },
+            Some(reply) = controls.recv() => {
+                let closing = reply[0] & 0x0F == CLOSE;
+                let _ = w.write_all(&reply).await;
+                if closing {
+                    return;
+                }
+                continue;
+            }
+            _ = ping.tick() => frame(PING_FRAME, &[]),
+        };
+        if w.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+pub(super) async fn ws(
+    Path(eid): Path<Uuid>,
+    State(dynamo): State<Backend>,
+    Query(listen): Query<Listen>,
+    Upgrade(upgrade): Upgrade,
+    headers: HeaderMap,
+) -> Response {
+    let wants = |name, value: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.split(',').any(|v| v.trim().eq_ignore_ascii_case(value)))
+    };
+    let key = headers.get(header::SEC_WEBSOCKET_KEY);
+    let (Some(key), true, true) = (
+        key,
+        wants(header::UPGRADE, "websocket"),
+        wants(header::SEC_WEBSOCKET_VERSION, "13"),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some(upgrade) = upgrade else {
+        // running as a Lambda, where connections don't stay open
+        return StatusCode::UPGRADE_REQUIRED.into_response();
+    };
+
+    // listen before looking, so that nothing falls in between
+    let changes = super::bus::subscribe(&eid);
+    let (meta, host) = match super::bus::admit(&dynamo, &eid, &listen).await {
+        Ok(admitted) => admitted,
+        Err(status) => return status.into_response(),
+    };
+    let accept = accept(key.as_bytes());
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(socket) => {
+                super::metrics::incr("ws.opened");
+                push(eid, socket, meta.version, host, changes).await;
+                debug!(%eid, "websocket closed");
+            }
+            Err(e) => warn!(%eid, error = %e, "websocket upgrade failed"),
+        }
+    });
+    (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::CONNECTION, "upgrade"),
+            (header::UPGRADE, "websocket"),
+        ],
+        [(header::SEC_WEBSOCKET_ACCEPT, accept)],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::Json;
+
+    #[test]
+    fn handshakes() {
+        // the example from RFC 6455
+        assert_eq!(
+            accept(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn frames() {
+        for len in [0, 5, 125, 126, 1000] {
+            let payload = vec![b'x'; len];
+            let mut framed = &frame(TEXT, &payload)[..];
+            assert_eq!(read_frame(&mut framed).await.unwrap(), (TEXT, payload));
+        }
+
+        // what a client sends is masked
+        let mut masked: &[u8] = &[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            read_frame(&mut masked).await.unwrap(),
+            (TEXT, b"Hello".to_vec())
+        );
+    }
+
+    async fn next(socket: &mut tokio::net::TcpStream) -> serde_json::Value {
+        let (opcode, payload) = read_frame(socket).await.unwrap();
+        assert_eq!(opcode, TEXT);
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pushes() {
+        let backend = Backend::local().await;
+        let e = crate::new::new(State(backend.clone()), None).await.unwrap();
+        let eid = Uuid::parse_str(e["id"].as_str().unwrap()).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = crate::api().with_state(backend.clone());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(
+                format!(
+                    "GET /api/event/{eid}/ws HTTP/1.1\r\n\
+                     Host: {addr}\r\n\
+                     Connection: Upgrade\r\n\
+                     Upgrade: websocket\r\n\
+                     Sec-WebSocket-Version: 13\r\n\
+                     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(socket.read_u8().await.unwrap());
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"), "{response}");
+        assert!(
+            response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+            "{response}"
+        );
+
+        assert_eq!(next(&mut socket).await["seq"], 1);
+        let q = crate::ask::ask(
+            Path(eid),
+            State(backend.clone()),
+            http::HeaderMap::new(),
+            Json(crate::ask::Question {
+                body: "can you hear me now?".into(),
+                asker: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let notice = next(&mut socket).await;
+        assert_eq!(notice["seq"], 2);
+        assert_eq!(notice["kind"], "question_asked");
+        assert_eq!(notice["qid"], q["id"]);
+    }
+}
@@ -0,0 +1,316 @@
+//! AWS X-Ray correlation.
+//!
+//! Every request gets an X-Ray trace context, taken from the `X-Amzn-Trace-Id` header (or, in
+//! Lambda, from the invocation). The trace id is attached to the request's log span, and the
+//! DynamoDB client's connector forwards the context on every call it makes, so a slow request can
+//! be matched up with the DynamoDB calls that made it slow.
+//!
+//! If `AWS_XRAY_DAEMON_ADDRESS` is set (which Lambda does when active tracing is on), we also
+//! emit a subsegment to the daemon for each DynamoDB call, and, outside of Lambda, a segment for
+//! each request. Inside Lambda, the runtime already emits the request segment for us.
+
+use aws_smithy_http::{body::SdkBody, result::ConnectorError};
+use axum::{http::Request, middleware::Next, response::Response};
+use rand::Rng;
+use std::{
+    future::Future,
+    net::{SocketAddr, UdpSocket},
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+use tower_service::Service;
+use tracing::Instrument;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const HEADER: &str = "x-amzn-trace-id";
+
+/// The fraction of requests without an upstream trace that we start a sampled trace for.
+const SAMPLE_RATE: f64 = 0.05;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct TraceHeader {
+    pub(super) root: String,
+    pub(super) parent: Option<String>,
+    pub(super) sampled: bool,
+}
+
+impl TraceHeader {
+    pub(super) fn parse(s: &str) -> Option<Self> {
+        let mut root = None;
+        let mut parent = None;
+        let mut sampled = false;
+        for part in s.split(';') {
+            match part.trim().split_once('=') {
+                Some(("Root", v)) => root = Some(v.to_string()),
+                Some(("Parent", v)) => parent = Some(v.to_string()),
+                Some(("Sampled", v)) => sampled = v == "1",
+                _ => {}
+            }
+        }
+        Some(TraceHeader {
+            root: root?,
+            parent,
+            sampled,
+        })
+    }
+
+    fn new_root() -> Self {
+        let mut rng = rand::thread_rng();
+        TraceHeader {
+            root: format!("1-{:08x}-{:024x}", epoch() as u32, rng.gen::<u128>() >> 32),
+            parent: None,
+            sampled: rng.gen_bool(SAMPLE_RATE),
+        }
+    }
+
+    fn with_parent(&self, parent: &str) -> String {
+        format!(
+            "Root={};Parent={};Sampled={}",
+            self.root,
+            parent,
+            if self.sampled { 1 } else { 0 }
+        )
+    }
+}
+
+/// The X-Ray context of the request currently being handled.
+#[derive(Clone, Debug)]
+struct Current {
+    trace: TraceHeader,
+    /// The segment that calls made while handling this request should hang off of.
+    segment: String,
+}
+
+tokio::task_local! {
+    static CURRENT: Current;
+}
+
+fn epoch() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+fn new_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+fn in_lambda() -> bool {
+    std::env::var_os("AWS_LAMBDA_FUNCTION_NAME").is_some()
+}
+
+fn daemon() -> Option<&'static (UdpSocket, SocketAddr)> {
+    static DAEMON: OnceLock<Option<(UdpSocket, SocketAddr)>> = OnceLock::new();
+    DAEMON
+        .get_or_init(|| {
+            let addr = std::env::var("AWS_XRAY_DAEMON_ADDRESS").ok()?;
+            // the variable may hold separate udp and tcp addresses
+            let addr = addr
+                .split(' ')
+                .find_map(|a| a.strip_prefix("udp:"))
+                .unwrap_or(&addr)
+                .parse()
+                .map_err(|e| warn!(%addr, error = %e, "invalid x-ray daemon address"))
+                .ok()?;
+            let socket = UdpSocket::bind("0.0.0.0:0")
+                .and_then(|s| s.set_nonblocking(true).map(|_| s))
+                .map_err(|e| warn!(error = %e, "could not create socket for x-ray daemon"))
+                .ok()?;
+            Some((socket, addr))
+        })
+        .as_ref()
+}
+
+fn emit(segment: serde_json::Value) {
+    let Some((socket, addr)) = daemon() else {
+        return;
+    };
+    let doc = format!("{{\"format\":\"json\",\"version\":1}}\n{segment}");
+    if let Err(e) = socket.send_to(doc.as_bytes(), addr) {
+        // tracing must never break the request
+        debug!(error = %e, "failed to send segment to x-ray daemon");
+    }
+}
+
+/// Middleware that establishes the X-Ray context for each request.
+pub(super) async fn trace<B>(req: Request<B>, next: Next<B>) -> Response {
+    // in lambda, the runtime has already made a segment for this invocation, and tells us its id
+    let (trace, own_segment) = if in_lambda() {
+        let env = std::env::var("_X_AMZN_TRACE_ID").ok();
+        match env.as_deref().and_then(TraceHeader::parse) {
+            Some(trace) => (trace, false),
+            None => (TraceHeader::new_root(), true),
+        }
+    } else {
+        let upstream = req
+            .headers()
+            .get(HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(TraceHeader::parse);
+        (upstream.unwrap_or_else(TraceHeader::new_root), true)
+    };
+
+    let segment = if own_segment {
+        new_id()
+    } else {
+        trace.parent.clone().unwrap_or_else(new_id)
+    };
+    let span = tracing::info_span!("xray", trace_id = %trace.root);
+    let start = epoch();
+    let method = req.method().to_string();
+    let url = req.uri().path().to_string();
+    let current = Current {
+        trace: trace.clone(),
+        segment: segment.clone(),
+    };
+    let res = CURRENT.scope(current, next.run(req).instrument(span)).await;
+
+    if own_segment && trace.sampled {
+        let status = res.status();
+        let mut doc = serde_json::json!({
+            "name": "wewerewondering-api",
+            "id": segment,
+            "trace_id": trace.root,
+            "start_time": start,
+            "end_time": epoch(),
+            "http": {
+                "request": { "method": method, "url": url },
+                "response": { "status": status.as_u16() },
+            },
+        });
+        if let Some(ref parent) = trace.parent {
+            doc["parent_id"] = parent.clone().into();
+        }
+        if status.is_client_error() {
+            doc["error"] = true.into();
+        } else if status.is_server_error() {
+            doc["fault"] = true.into();
+        }
+        emit(doc);
+    }
+    res
+}
+
+/// A DynamoDB connector that propagates the current X-Ray context and records each call as a
+/// subsegment.
+#[derive(Clone, Debug)]
+pub(super) struct Traced<C> {
+    inner: C,
+}
+
+impl<C> Traced<C> {
+    pub(super) fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+impl<C> Service<http::Request<SdkBody>> for Traced<C>
+where
+    C: Service<http::Request<SdkBody>, Response = http::Response<SdkBody>, Error = ConnectorError>,
+    C::Future: Send + 'static,
+{
+    type Response = http::Response<SdkBody>;
+    type Error = ConnectorError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<SdkBody>) -> Self::Future {
+        let Ok(current) = CURRENT.try_with(Current::clone) else {
+            // not called while handling a request (e.g., from a cli command)
+            return Box::pin(self.inner.call(req));
+        };
+
+        let id = new_id();
+        if let Ok(v) = current.trace.with_parent(&id).parse() {
+            req.headers_mut().insert(HEADER, v);
+        }
+        // DynamoDB_20120810.GetItem
+        let operation = req
+            .headers()
+            .get("x-amz-target")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split_once('.'))
+            .map(|(_, op)| op.to_string());
+        let table = req
+            .body()
+            .bytes()
+            .and_then(|b| serde_json::from_slice::<serde_json::Value>(b).ok())
+            .and_then(|b| {
+                b.get("TableName")
+                    .and_then(|t| t.as_str())
+                    .map(String::from)
+            });
+
+        let start = epoch();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            let end = epoch();
+            trace!(trace_id = %current.trace.root, ?operation, ?table, took = end - start, "dynamodb call");
+            if current.trace.sampled {
+                let mut doc = serde_json::json!({
+                    "type": "subsegment",
+                    "name": "DynamoDB",
+                    "namespace": "aws",
+                    "id": id,
+                    "trace_id": current.trace.root,
+                    "parent_id": current.segment,
+                    "start_time": start,
+                    "end_time": end,
+                    "aws": { "operation": operation, "table_name": table },
+                });
+                match res {
+                    Ok(ref r) => {
+                        doc["http"] = serde_json::json!({
+                            "response": { "status": r.status().as_u16() }
+                        });
+                        if r.status().is_client_error() {
+                            doc["error"] = true.into();
+                        } else if r.status().is_server_error() {
+                            doc["fault"] = true.into();
+                        }
+                    }
+                    Err(_) => doc["fault"] = true.into(),
+                }
+                emit(doc);
+            }
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let h = TraceHeader::parse(
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1",
+        )
+        .unwrap();
+        assert_eq!(h.root, "1-5759e988-bd862e3fe1be46a994272793");
+        assert_eq!(h.parent.as_deref(), Some("53995c3f42cd8ad8"));
+        assert!(h.sampled);
+        assert_eq!(
+            h.with_parent("0000000000000001"),
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=0000000000000001;Sampled=1"
+        );
+
+        assert_eq!(TraceHeader::parse("Sampled=1"), None);
+        let root = TraceHeader::new_root();
+        assert_eq!(root.root.len(), 35);
+        assert_eq!(
+            TraceHeader::parse(&root.with_parent("x")).unwrap().root,
+            root.root
+        );
+    }
+}